@@ -20,6 +20,7 @@ use std::{fmt, fs};
 use ccore::Spec;
 use clap;
 use cnetwork::{NetworkConfig, SocketAddr};
+use crpc::HealthConfig;
 use ctypes::{Address, Secret};
 use rpc::{HttpConfiguration as RpcHttpConfig, IpcConfiguration as RpcIpcConfig};
 use toml;
@@ -103,16 +104,38 @@ pub struct Operating {
     pub quiet: bool,
     pub instance_id: Option<usize>,
     pub db_path: String,
+    pub db_cache_size: Option<usize>,
+    pub db_compaction: Option<String>,
+    pub db_wal: Option<bool>,
+    pub db_columns: Option<Vec<DbColumn>>,
+    pub db_recovery: Option<String>,
+    pub parcel_recovery_threads: Option<usize>,
+    pub max_future_blocks: Option<usize>,
+    pub allowed_future_drift: Option<u64>,
+    pub recent_states: Option<usize>,
     pub snapshot_path: String,
+    pub snapshot_period: Option<u64>,
+    pub max_snapshots: Option<usize>,
     pub chain: ChainType,
     pub secret_key: Secret,
 }
 
+/// RocksDB options of a column overriding the database-wide ones.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DbColumn {
+    pub column: u32,
+    pub cache_size: Option<usize>,
+    pub compaction: Option<String>,
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Mining {
     pub author: Option<Address>,
     pub engine_signer: Option<Address>,
+    pub local_parcels_journal: Option<bool>,
+    pub mem_pool_journal: Option<bool>,
 }
 
 #[derive(Deserialize)]
@@ -124,7 +147,9 @@ pub struct Network {
     pub min_peers: usize,
     pub max_peers: usize,
     pub sync: bool,
+    pub snapshot_sync: Option<bool>,
     pub parcel_relay: bool,
+    pub parcel_relay_policy: Option<String>,
     pub discovery: bool,
     pub discovery_type: String,
     pub discovery_refresh: u32,
@@ -136,6 +161,8 @@ pub struct Network {
 pub struct Rpc {
     pub disable: bool,
     pub port: u16,
+    pub health_min_peers: Option<usize>,
+    pub health_max_sync_lag: Option<u64>,
 }
 
 impl<'a> Into<RpcIpcConfig> for &'a Ipc {
@@ -171,6 +198,16 @@ impl<'a> Into<RpcHttpConfig> for &'a Rpc {
     }
 }
 
+impl<'a> Into<HealthConfig> for &'a Rpc {
+    fn into(self) -> HealthConfig {
+        let default = HealthConfig::default();
+        HealthConfig {
+            min_peers: self.health_min_peers.unwrap_or(default.min_peers),
+            max_sync_lag: self.health_max_sync_lag.unwrap_or(default.max_sync_lag),
+        }
+    }
+}
+
 pub fn load(config_path: &str) -> Result<Config, String> {
     let toml_string = fs::read_to_string(config_path).map_err(|e| format!("Fail to read file: {:?}", e))?;
     toml::from_str(toml_string.as_ref()).map_err(|e| format!("Error while parse TOML: {:?}", e))
@@ -199,9 +236,33 @@ impl Operating {
         if let Some(db_path) = matches.value_of("db-path") {
             self.db_path = db_path.to_string();
         }
+        if let Some(db_recovery) = matches.value_of("db-recovery") {
+            self.db_recovery = Some(db_recovery.to_string());
+        }
+        if let Some(values) = matches.values_of("db-column-cache-size") {
+            for value in values {
+                let (column, cache_size) = parse_column_option(value)?;
+                self.db_column(column).cache_size = Some(cache_size.parse().map_err(|e| format!("{}", e))?);
+            }
+        }
+        if let Some(values) = matches.values_of("db-column-compaction") {
+            for value in values {
+                let (column, compaction) = parse_column_option(value)?;
+                self.db_column(column).compaction = Some(compaction.to_string());
+            }
+        }
+        if let Some(recent_states) = matches.value_of("recent-states") {
+            self.recent_states = Some(recent_states.parse().map_err(|e| format!("{}", e))?);
+        }
         if let Some(snapshot_path) = matches.value_of("snapshot-path") {
             self.snapshot_path = snapshot_path.to_string();
         }
+        if let Some(snapshot_period) = matches.value_of("snapshot-period") {
+            self.snapshot_period = Some(snapshot_period.parse().map_err(|e| format!("{}", e))?);
+        }
+        if let Some(max_snapshots) = matches.value_of("max-snapshots") {
+            self.max_snapshots = Some(max_snapshots.parse().map_err(|e| format!("{}", e))?);
+        }
         if let Some(chain) = matches.value_of("chain") {
             self.chain = chain.parse()?;
         }
@@ -212,6 +273,34 @@ impl Operating {
     }
 }
 
+impl Operating {
+    /// Returns the options of the column, adding them if the column has none yet.
+    fn db_column(&mut self, column: u32) -> &mut DbColumn {
+        let columns = self.db_columns.get_or_insert_with(Vec::new);
+        let index = match columns.iter().position(|c| c.column == column) {
+            Some(index) => index,
+            None => {
+                columns.push(DbColumn {
+                    column,
+                    cache_size: None,
+                    compaction: None,
+                });
+                columns.len() - 1
+            }
+        };
+        &mut columns[index]
+    }
+}
+
+/// Parses a column option given as `COLUMN=VALUE`.
+fn parse_column_option(option: &str) -> Result<(u32, &str), String> {
+    let mut parts = option.splitn(2, '=');
+    let column = parts.next().unwrap_or_default();
+    let value = parts.next().ok_or_else(|| format!("Invalid column option {}: expected COLUMN=VALUE", option))?;
+    let column = column.parse().map_err(|_| format!("Invalid column in {}", option))?;
+    Ok((column, value))
+}
+
 impl Mining {
     pub fn overwrite_with(&mut self, matches: &clap::ArgMatches) -> Result<(), String> {
         if let Some(author) = matches.value_of("author") {
@@ -220,6 +309,12 @@ impl Mining {
         if let Some(engine_signer) = matches.value_of("engine-signer") {
             self.engine_signer = Some(Address::from_str(engine_signer).map_err(|_| "Invalid address")?);
         }
+        if matches.is_present("local-parcels-journal") {
+            self.local_parcels_journal = Some(true);
+        }
+        if matches.is_present("mem-pool-journal") {
+            self.mem_pool_journal = Some(true);
+        }
         Ok(())
     }
 }
@@ -251,9 +346,15 @@ impl Network {
         if matches.is_present("no-sync") {
             self.sync = false;
         }
+        if matches.is_present("snapshot-sync") {
+            self.snapshot_sync = Some(true);
+        }
         if matches.is_present("no-parcel-relay") {
             self.parcel_relay = false;
         }
+        if let Some(parcel_relay_policy) = matches.value_of("parcel-relay-policy") {
+            self.parcel_relay_policy = Some(parcel_relay_policy.to_string());
+        }
 
         if matches.is_present("no-discovery") {
             self.discovery = false;
@@ -57,7 +57,9 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use account_command::run_account_command;
 use app_dirs::AppInfo;
-use ccore::{AccountProvider, ClientService, Miner, MinerOptions, MinerService, Spec};
+use ccore::{
+    AccountProvider, ClientConfig, ClientService, DatabaseColumnConfig, Miner, MinerOptions, MinerService, Spec,
+};
 use cdiscovery::{KademliaConfig, KademliaExtension, UnstructuredConfig, UnstructuredExtension};
 use ckeystore::accounts_dir::RootDiskDirectory;
 use ckeystore::KeyStore;
@@ -65,23 +67,29 @@ use clap::ArgMatches;
 use clogger::LoggerConfig;
 use cnetwork::{NetworkConfig, NetworkService, SocketAddr};
 use creactor::EventLoop;
-use crpc::{HttpServer, IpcServer};
-use csync::{BlockSyncExtension, ParcelSyncExtension, SnapshotService};
+use crpc::{Health, HttpServer, IpcServer};
+use csync::{BlockSyncCapabilities, BlockSyncExtension, ParcelRelayPolicy, ParcelSyncExtension, SnapshotService};
 use ctrlc::CtrlC;
 use fdlimit::raise_fd_limit;
 use parking_lot::{Condvar, Mutex};
 use rpc::{HttpConfiguration as RpcHttpConfig, IpcConfiguration as RpcIpcConfig};
 
 const DEFAULT_CONFIG_PATH: &'static str = "codechain/config/presets/config.dev.toml";
+const DEFAULT_SNAPSHOT_PERIOD: u64 = 1 << 14;
+const DEFAULT_MAX_SNAPSHOTS: usize = 2;
 
 pub const APP_INFO: AppInfo = AppInfo {
     name: "codechain",
     author: "Kodebox",
 };
 
-pub fn rpc_start(cfg: RpcHttpConfig, deps: Arc<rpc_apis::ApiDependencies>) -> Result<HttpServer, String> {
+pub fn rpc_start(
+    cfg: RpcHttpConfig,
+    deps: Arc<rpc_apis::ApiDependencies>,
+    health: Arc<Health>,
+) -> Result<HttpServer, String> {
     info!("RPC Listening on {}", cfg.port);
-    rpc::new_http(cfg, deps)
+    rpc::new_http(cfg, deps, health)
 }
 
 pub fn rpc_ipc_start(cfg: RpcIpcConfig, deps: Arc<rpc_apis::ApiDependencies>) -> Result<IpcServer, String> {
@@ -128,7 +136,42 @@ pub fn discovery_start(service: &NetworkService, cfg: &config::Network) -> Resul
 pub fn client_start(cfg: &config::Config, spec: &Spec, miner: Arc<Miner>) -> Result<ClientService, String> {
     info!("Starting client");
     let client_path = Path::new(&cfg.operating.db_path);
-    let client_config = Default::default();
+    let mut client_config = ClientConfig::default();
+    if let Some(db_cache_size) = cfg.operating.db_cache_size {
+        client_config.db_cache_size = Some(db_cache_size);
+    }
+    if let Some(ref db_compaction) = cfg.operating.db_compaction {
+        client_config.db_compaction = db_compaction.parse()?;
+    }
+    if let Some(db_wal) = cfg.operating.db_wal {
+        client_config.db_wal = db_wal;
+    }
+    for column in cfg.operating.db_columns.iter().flat_map(|columns| columns.iter()) {
+        let compaction = match column.compaction {
+            Some(ref compaction) => Some(compaction.parse()?),
+            None => None,
+        };
+        let column_config = DatabaseColumnConfig {
+            cache_size: column.cache_size,
+            compaction,
+        };
+        client_config.db_column_configs.insert(column.column, column_config);
+    }
+    if let Some(ref db_recovery) = cfg.operating.db_recovery {
+        client_config.db_recovery = db_recovery.parse()?;
+    }
+    if let Some(parcel_recovery_threads) = cfg.operating.parcel_recovery_threads {
+        client_config.queue.parcel_recovery_threads = parcel_recovery_threads;
+    }
+    if let Some(max_future_blocks) = cfg.operating.max_future_blocks {
+        client_config.queue.max_future_items = max_future_blocks;
+    }
+    if let Some(allowed_future_drift) = cfg.operating.allowed_future_drift {
+        client_config.queue.allowed_future_drift = allowed_future_drift;
+    }
+    if let Some(recent_states) = cfg.operating.recent_states {
+        client_config.recent_states = recent_states;
+    }
     let service = ClientService::start(client_config, &spec, &client_path, miner)
         .map_err(|e| format!("Client service error: {:?}", e))?;
 
@@ -137,8 +180,6 @@ pub fn client_start(cfg: &config::Config, spec: &Spec, miner: Arc<Miner>) -> Res
 
 #[cfg(all(unix, target_arch = "x86_64"))]
 fn main() -> Result<(), String> {
-    panic_hook::set();
-
     // Always print backtrace on panic.
     ::std::env::set_var("RUST_BACKTRACE", "1");
 
@@ -156,6 +197,8 @@ fn run() -> Result<(), String> {
 }
 
 fn run_subcommand(matches: ArgMatches) -> Result<(), String> {
+    panic_hook::set();
+
     let subcommand = matches.subcommand.unwrap();
     if subcommand.name == "account" {
         run_account_command(subcommand.matches)
@@ -177,6 +220,8 @@ fn run_node(matches: ArgMatches) -> Result<(), String> {
     config.mining.overwrite_with(&matches)?;
     config.network.overwrite_with(&matches)?;
     config.rpc.overwrite_with(&matches)?;
+    // The hook is set once, when the directory for the crash reports is known.
+    panic_hook::set_with_report_dir(Path::new(&config.operating.db_path).join("crashes"));
     let spec = config.operating.chain.spec()?;
 
     let instance_id = config.operating.instance_id.unwrap_or(SystemTime::now()
@@ -198,39 +243,46 @@ fn run_node(matches: ArgMatches) -> Result<(), String> {
             .map_err(|e| format!("Invalid secret key: {:?}", e))?
     };
 
-    let miner = Miner::new(MinerOptions::default(), &spec, Some(ap.clone()));
+    let local_parcels_journal = match config.mining.local_parcels_journal {
+        Some(true) => Some(Path::new(&config.operating.db_path).join("local_parcels.rlp")),
+        _ => None,
+    };
+    let miner_options = MinerOptions {
+        local_parcels_journal,
+        mem_pool_journal: config.mining.mem_pool_journal.unwrap_or(false),
+        ..Default::default()
+    };
+    let miner = Miner::new(miner_options, &spec, Some(ap.clone()));
     let author = config.mining.author.unwrap_or(address);
     miner.set_author(author);
     let enginer_signer = config.mining.engine_signer.unwrap_or(address);
     // FIXME: Don't hardcode password.
     miner.set_engine_signer(enginer_signer, "password".to_string()).map_err(|err| format!("{:?}", err))?;
 
+    let health = Arc::new(Health::new((&config.rpc).into()));
     let client = client_start(&config, &spec, miner.clone())?;
-
-    let rpc_apis_deps = Arc::new(rpc_apis::ApiDependencies {
-        client: client.client(),
-        miner: miner.clone(),
-    });
-
-    let _rpc_server = {
-        if !config.rpc.disable {
-            let rpc_config = (&config.rpc).into();
-            Some(rpc_start(rpc_config, rpc_apis_deps.clone())?)
-        } else {
-            None
-        }
-    };
-
-    let _ipc_server = {
-        if !config.ipc.disable {
-            let ipc_config = (&config.ipc).into();
-            Some(rpc_ipc_start(ipc_config, rpc_apis_deps.clone())?)
-        } else {
-            None
-        }
+    health.set_db_opened();
+    miner.recover_local_parcels(&*client.client());
+    miner.recover_mem_pool(&*client.client());
+
+    let parcel_relay_policy = match config.network.parcel_relay_policy {
+        _ if config.network.disable || !config.network.parcel_relay => ParcelRelayPolicy::None,
+        Some(ref policy) => policy.parse()?,
+        None => ParcelRelayPolicy::default(),
     };
+    let snapshot_period = config.operating.snapshot_period.unwrap_or(DEFAULT_SNAPSHOT_PERIOD);
+    if snapshot_period == 0 {
+        return Err("Invalid snapshot period given. Expected a positive number.".to_string())
+    }
+    let snapshot_service = SnapshotService::new(
+        client.client(),
+        config.operating.snapshot_path.clone(),
+        snapshot_period,
+        config.operating.max_snapshots.unwrap_or(DEFAULT_MAX_SNAPSHOTS),
+    );
+    client.client().add_notify(snapshot_service.clone());
 
-    let _network_service = {
+    let network_service = {
         if !config.network.disable {
             let network_config = (&config.network).into();
             let service = network_start(&network_config)?;
@@ -242,12 +294,32 @@ fn run_node(matches: ArgMatches) -> Result<(), String> {
             }
 
             if config.network.sync {
-                let sync = BlockSyncExtension::new(client.client());
+                let mut capabilities = BlockSyncCapabilities::default();
+                if Path::new(&config.operating.snapshot_path).is_dir() {
+                    capabilities.insert(BlockSyncCapabilities::SERVE_SNAPSHOT_MANIFESTS);
+                    capabilities.insert(BlockSyncCapabilities::SERVE_STATE_CHUNKS);
+                }
+                let latest_manifest = snapshot_service.latest_manifest();
+                let sync = if config.network.snapshot_sync.unwrap_or(false) {
+                    let progress_path = Path::new(&config.operating.db_path).join("restoration.rlp");
+                    let state_db = client.client().database();
+                    BlockSyncExtension::with_snapshot_restoration(
+                        client.client(),
+                        capabilities,
+                        Some(latest_manifest),
+                        state_db,
+                        progress_path,
+                    )
+                } else {
+                    BlockSyncExtension::with_snapshot_manifest(client.client(), capabilities, latest_manifest)
+                };
+                health.set_sync_status(sync.status());
                 service.register_extension(sync.clone())?;
                 client.client().add_notify(sync.clone());
             }
             if config.network.parcel_relay {
-                service.register_extension(ParcelSyncExtension::new(client.client()))?;
+                let parcel_sync = ParcelSyncExtension::with_relay_policy(client.client(), parcel_relay_policy);
+                service.register_extension(parcel_sync)?;
             }
             if let Some(consensus_extension) = spec.engine.network_extension() {
                 service.register_extension(consensus_extension)?;
@@ -256,15 +328,37 @@ fn run_node(matches: ArgMatches) -> Result<(), String> {
             for address in network_config.bootstrap_addresses {
                 service.connect_to(address)?;
             }
-            Some(service)
+            Some(Arc::new(service))
         } else {
             None
         }
     };
 
-    // FIXME: Get snapshot period from genesis block
-    let snapshot_service = SnapshotService::new(client.client(), config.operating.snapshot_path, 1 << 14);
-    client.client().add_notify(snapshot_service.clone());
+    let rpc_apis_deps = Arc::new(rpc_apis::ApiDependencies {
+        client: client.client(),
+        miner: miner.clone(),
+        network_control: network_service.clone(),
+        parcel_relay_policy,
+        snapshot_service: snapshot_service.clone(),
+    });
+
+    let _rpc_server = {
+        if !config.rpc.disable {
+            let rpc_config = (&config.rpc).into();
+            Some(rpc_start(rpc_config, rpc_apis_deps.clone(), health.clone())?)
+        } else {
+            None
+        }
+    };
+
+    let _ipc_server = {
+        if !config.ipc.disable {
+            let ipc_config = (&config.ipc).into();
+            Some(rpc_ipc_start(ipc_config, rpc_apis_deps.clone())?)
+        } else {
+            None
+        }
+    };
 
     // drop the spec to free up genesis state.
     drop(spec);
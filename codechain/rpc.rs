@@ -18,7 +18,7 @@ use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use crpc::{start_http, start_ipc, HttpServer, IpcServer};
+use crpc::{start_http, start_ipc, Health, HttpServer, IpcServer};
 use crpc::{Compatibility, MetaIoHandler};
 use rpc_apis;
 
@@ -41,10 +41,14 @@ impl HttpConfiguration {
     }
 }
 
-pub fn new_http(cfg: HttpConfiguration, deps: Arc<rpc_apis::ApiDependencies>) -> Result<HttpServer, String> {
+pub fn new_http(
+    cfg: HttpConfiguration,
+    deps: Arc<rpc_apis::ApiDependencies>,
+    health: Arc<Health>,
+) -> Result<HttpServer, String> {
     let url = format!("{}:{}", cfg.interface, cfg.port);
     let addr = url.parse().map_err(|_| format!("Invalid JSONRPC listen host/port given: {}", url))?;
-    let server = setup_http_rpc_server(&addr, cfg.cors, cfg.hosts, deps)?;
+    let server = setup_http_rpc_server(&addr, cfg.cors, cfg.hosts, deps, health)?;
     Ok(server)
 }
 
@@ -53,9 +57,10 @@ pub fn setup_http_rpc_server(
     cors_domains: Option<Vec<String>>,
     allowed_hosts: Option<Vec<String>>,
     deps: Arc<rpc_apis::ApiDependencies>,
+    health: Arc<Health>,
 ) -> Result<HttpServer, String> {
     let server = setup_rpc_server(deps);
-    let start_result = start_http(url, cors_domains, allowed_hosts, server);
+    let start_result = start_http(url, cors_domains, allowed_hosts, health, server);
     match start_result {
         Err(ref err) if err.kind() == io::ErrorKind::AddrInUse => {
             Err(format!("RPC address {} is already in use, make sure that another instance of a Bitcoin node is not running or change the address using the --jsonrpc-port and --jsonrpc-interface options.", url))
@@ -17,19 +17,27 @@
 use std::sync::Arc;
 
 use ccore::{Client, Miner};
+use cnetwork::NetworkService;
+use csync::{ParcelRelayPolicy, SnapshotService};
 use crpc::{MetaIoHandler, Params, Value};
 
 pub struct ApiDependencies {
     pub client: Arc<Client>,
     pub miner: Arc<Miner>,
+    pub network_control: Option<Arc<NetworkService>>,
+    pub parcel_relay_policy: ParcelRelayPolicy,
+    pub snapshot_service: Arc<SnapshotService>,
 }
 
 impl ApiDependencies {
     pub fn extend_api(&self, handler: &mut MetaIoHandler<()>) {
         use crpc::v1::*;
         handler.extend_with(ChainClient::new(&self.client, &self.miner).to_delegate());
-        handler.extend_with(DevelClient::new(&self.client).to_delegate());
-        handler.extend_with(MinerClient::new(&self.miner).to_delegate());
+        handler.extend_with(DevelClient::new(&self.client, &self.snapshot_service).to_delegate());
+        handler.extend_with(EngineClient::new(&self.client).to_delegate());
+        handler.extend_with(MempoolClient::new(&self.client, &self.miner).to_delegate());
+        handler.extend_with(MinerClient::new(&self.client, &self.miner).to_delegate());
+        handler.extend_with(NetClient::new(&self.network_control, self.parcel_relay_policy).to_delegate());
     }
 }
 
@@ -0,0 +1,61 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Benchmarks for importing a batch of external parcels into the mem pool. Run with:
+//! ```bash
+//! rustup run nightly cargo bench -p codechain-core --bench parcel_import
+//! ```
+
+#![feature(test)]
+
+extern crate codechain_core as ccore;
+extern crate codechain_keys as ckeys;
+extern crate codechain_types as ctypes;
+extern crate test;
+
+use ccore::{Action, Miner, MinerOptions, MinerService, Parcel, TestBlockChainClient, UnverifiedParcel};
+use ckeys::{Generator, Random};
+use ctypes::U256;
+use test::{black_box, Bencher};
+
+const PARCELS: usize = 500;
+
+#[bench]
+fn import_external_parcels(b: &mut Bencher) {
+    let mut client = TestBlockChainClient::new();
+    let keypair = Random.generate().unwrap();
+    client.set_balance(keypair.address(), U256::max_value());
+    let network_id = client.spec.params().network_id;
+    let parcels: Vec<UnverifiedParcel> = (0..PARCELS as u64)
+        .map(|nonce| {
+            Parcel {
+                nonce: nonce.into(),
+                fee: 10.into(),
+                network_id,
+                action: Action::ChangeShardState {
+                    transactions: vec![],
+                },
+            }.sign(keypair.private())
+                .into()
+        })
+        .collect();
+
+    b.iter(|| {
+        // Every iteration imports the batch into an empty mem pool.
+        client.miner = Miner::new(MinerOptions::default(), &client.spec, None);
+        black_box(client.miner.import_external_parcels(&client, parcels.clone()))
+    });
+}
@@ -0,0 +1,59 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Benchmarks for the duplicate parcel check. Run with:
+//! ```bash
+//! rustup run nightly cargo bench -p codechain-core --bench parcel_lookup
+//! ```
+
+#![feature(test)]
+
+extern crate codechain_core as ccore;
+extern crate codechain_io as cio;
+extern crate codechain_types as ctypes;
+extern crate kvdb_memorydb;
+extern crate test;
+
+use std::sync::Arc;
+
+use ccore::{Client, ClientConfig, Miner, ParcelId, ParcelInfo, Spec, NUM_COLUMNS};
+use cio::IoChannel;
+use ctypes::H256;
+use test::{black_box, Bencher};
+
+const LOOKUPS: usize = 10_000;
+
+fn new_client() -> Arc<Client> {
+    let spec = Spec::new_test();
+    let db = Arc::new(kvdb_memorydb::create(NUM_COLUMNS.unwrap_or(0)));
+    let miner = Arc::new(Miner::with_spec(&spec));
+    Client::new(ClientConfig::default(), &spec, db, miner, IoChannel::disconnected()).unwrap()
+}
+
+fn unknown_hashes() -> Vec<H256> {
+    (0..LOOKUPS).map(|_| H256::random()).collect()
+}
+
+#[bench]
+fn lookup_unknown_parcels_in_index(b: &mut Bencher) {
+    let client = new_client();
+    let hashes = unknown_hashes();
+    b.iter(|| {
+        for hash in &hashes {
+            black_box(client.parcel_block(ParcelId::Hash(*hash)).is_some());
+        }
+    });
+}
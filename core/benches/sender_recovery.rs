@@ -0,0 +1,104 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Benchmarks for sender recovery. Run with:
+//! ```bash
+//! rustup run nightly cargo bench -p codechain-core
+//! ```
+
+#![feature(test)]
+
+extern crate codechain_core as ccore;
+extern crate codechain_keys as ckeys;
+extern crate rlp;
+extern crate test;
+
+use ccore::{clear_sender_cache, recover_senders, Parcel, SignedParcel, UnverifiedParcel};
+use ckeys::{recover_ecdsa, Generator, Random};
+use rlp::Encodable;
+use test::{black_box, Bencher};
+
+const PARCELS: usize = 1_000;
+const BLOCK_PARCELS: usize = 2_000;
+
+fn encoded_parcels() -> Vec<Vec<u8>> {
+    encode_parcels(PARCELS)
+}
+
+fn encode_parcels(count: usize) -> Vec<Vec<u8>> {
+    let keypair = Random.generate().unwrap();
+    (0..count)
+        .map(|nonce| {
+            let parcel = Parcel {
+                nonce: nonce.into(),
+                ..Parcel::default()
+            };
+            parcel.sign(keypair.private()).rlp_bytes().into_vec()
+        })
+        .collect()
+}
+
+#[bench]
+fn recover_unknown_senders(b: &mut Bencher) {
+    let parcels: Vec<UnverifiedParcel> = encoded_parcels().iter().map(|bytes| rlp::decode(bytes)).collect();
+    b.iter(|| {
+        for parcel in &parcels {
+            black_box(recover_ecdsa(&parcel.signature(), &parcel.as_unsigned().hash()).unwrap());
+        }
+    });
+}
+
+#[bench]
+fn recover_known_senders(b: &mut Bencher) {
+    // Every parcel was recovered once when it was signed, as if it had been received via gossip.
+    let encoded = encoded_parcels();
+    b.iter(|| {
+        for bytes in &encoded {
+            let parcel: UnverifiedParcel = rlp::decode(bytes);
+            black_box(SignedParcel::new(parcel).unwrap());
+        }
+    });
+}
+
+fn recover_block_senders(b: &mut Bencher, threads: usize) {
+    let encoded = encode_parcels(BLOCK_PARCELS);
+    b.iter(|| {
+        clear_sender_cache();
+        let parcels: Vec<UnverifiedParcel> = encoded.iter().map(|bytes| rlp::decode(bytes)).collect();
+        recover_senders(&parcels, threads).unwrap();
+        black_box(parcels);
+    });
+}
+
+#[bench]
+fn recover_block_senders_1_thread(b: &mut Bencher) {
+    recover_block_senders(b, 1);
+}
+
+#[bench]
+fn recover_block_senders_2_threads(b: &mut Bencher) {
+    recover_block_senders(b, 2);
+}
+
+#[bench]
+fn recover_block_senders_4_threads(b: &mut Bencher) {
+    recover_block_senders(b, 4);
+}
+
+#[bench]
+fn recover_block_senders_8_threads(b: &mut Bencher) {
+    recover_block_senders(b, 8);
+}
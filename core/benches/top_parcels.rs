@@ -0,0 +1,80 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Benchmarks for collecting the parcels of a block from a large mem pool. Run with:
+//! ```bash
+//! rustup run nightly cargo bench -p codechain-core --bench top_parcels
+//! ```
+
+#![feature(test)]
+
+extern crate codechain_core as ccore;
+extern crate codechain_keys as ckeys;
+extern crate codechain_types as ctypes;
+extern crate test;
+
+use ccore::{Action, Miner, MinerOptions, MinerService, Parcel, TestBlockChainClient};
+use ckeys::{Generator, Random};
+use ctypes::U256;
+use test::{black_box, Bencher};
+
+const SENDERS: usize = 500;
+const PARCELS_PER_SENDER: usize = 100;
+
+/// Returns a client whose mem pool holds 50k ready parcels.
+fn client_with_full_mem_pool() -> TestBlockChainClient {
+    let mut client = TestBlockChainClient::new();
+    let options = MinerOptions {
+        mem_pool_size: SENDERS * PARCELS_PER_SENDER,
+        mem_pool_memory_limit: None,
+        ..Default::default()
+    };
+    client.miner = Miner::new(options, &client.spec, None);
+
+    let network_id = client.spec.params().network_id;
+    let mut parcels = Vec::with_capacity(SENDERS * PARCELS_PER_SENDER);
+    for _ in 0..SENDERS {
+        let keypair = Random.generate().unwrap();
+        client.set_balance(keypair.address(), U256::max_value());
+        for nonce in 0..PARCELS_PER_SENDER as u64 {
+            let parcel = Parcel {
+                nonce: nonce.into(),
+                fee: 10.into(),
+                network_id,
+                action: Action::ChangeShardState {
+                    transactions: vec![],
+                },
+            };
+            parcels.push(parcel.sign(keypair.private()).into());
+        }
+    }
+    client.miner.import_external_parcels(&client, parcels);
+    assert_eq!(SENDERS * PARCELS_PER_SENDER, client.miner.ready_parcels().len());
+    client
+}
+
+#[bench]
+fn collect_every_ready_parcel(b: &mut Bencher) {
+    let client = client_with_full_mem_pool();
+    b.iter(|| black_box(client.miner.ready_parcels()));
+}
+
+#[bench]
+fn collect_ready_parcels_for_a_block(b: &mut Bencher) {
+    let client = client_with_full_mem_pool();
+    let params = client.spec.params();
+    b.iter(|| black_box(client.miner.ready_parcels_with_limit(params.max_parcels_per_block, params.max_body_size)));
+}
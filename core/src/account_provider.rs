@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::error;
 use std::fmt;
 use std::sync::Arc;
 
@@ -21,7 +22,7 @@ use ckeys::{
     public_to_address, ECDSASignature, Error as KeysError, Generator, KeyPair, Message, Private, Public, Random,
 };
 use ckeystore::accounts_dir::MemoryDirectory;
-use ckeystore::{Error as KeystoreError, KeyStore, SimpleSecretStore};
+use ckeystore::{Error as KeystoreError, KeyStore, SecretStore, SimpleSecretStore};
 use ctypes::Address;
 use parking_lot::RwLock;
 
@@ -38,6 +39,8 @@ pub enum SignError {
     KeystoreError(KeystoreError),
     /// Inappropriate chain
     InappropriateChain,
+    /// The password doesn't decrypt the key of the account.
+    InvalidPassword,
 }
 
 impl From<KeysError> for SignError {
@@ -60,6 +63,21 @@ impl fmt::Display for SignError {
             SignError::KeysError(e) => write!(f, "{}", e),
             SignError::KeystoreError(e) => write!(f, "{}", e),
             SignError::InappropriateChain => write!(f, "Inappropriate chain"),
+            SignError::InvalidPassword => write!(f, "Invalid password"),
+        }
+    }
+}
+
+impl error::Error for SignError {
+    fn description(&self) -> &str {
+        "Sign error"
+    }
+
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self {
+            SignError::KeysError(err) => Some(err),
+            SignError::KeystoreError(err) => Some(err),
+            _ => None,
         }
     }
 }
@@ -115,6 +133,12 @@ impl AccountProvider {
         }
     }
 
+    /// Checks whether `password` decrypts the key of the account. The key is not kept decrypted.
+    pub fn test_password(&self, address: Address, password: &str) -> Result<bool, SignError> {
+        let is_valid = self.keystore.read().test_password(&address, password)?;
+        Ok(is_valid)
+    }
+
     pub fn has_account(&self, address: Address) -> Result<bool, SignError> {
         let has = self.keystore.read().has_account(&address)?;
         Ok(has)
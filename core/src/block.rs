@@ -19,6 +19,7 @@ use std::collections::HashSet;
 use ccrypto::BLAKE_NULL_RLP;
 use cmerkle::skewed_merkle_root;
 use ctypes::{Address, Bytes, H256};
+use panic_hook;
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 use trie::TrieFactory;
 use unexpected::Mismatch;
@@ -155,6 +156,7 @@ impl<'x> OpenBlock<'x> {
             return Err(ParcelError::AlreadyImported.into())
         }
 
+        let _guard = panic_hook::with_context("parcel", parcel.hash());
         let outcomes = self.block.state.apply(&parcel)?;
 
         self.block.parcels_set.insert(h.unwrap_or_else(|| parcel.hash()));
@@ -208,6 +210,7 @@ impl<'x> OpenBlock<'x> {
             self.block.parcels.iter().map(|e| e.rlp_bytes()),
         ));
         self.block.header.set_state_root(self.block.state.root().clone());
+        self.set_shard_roots();
         self.block.header.set_invoices_root(skewed_merkle_root(
             parent_invoices_root,
             self.block.invoices.iter().flat_map(|invoices| invoices.iter().map(|invoice| invoice.rlp_bytes())),
@@ -241,12 +244,20 @@ impl<'x> OpenBlock<'x> {
             ));
         }
         self.block.header.set_state_root(self.block.state.root().clone());
+        self.set_shard_roots();
 
         LockedBlock {
             block: self.block,
         }
     }
 
+    fn set_shard_roots(&mut self) {
+        match self.block.state.shard_roots(self.engine.params().shard_count) {
+            Ok(shard_roots) => self.block.header.set_shard_roots(shard_roots),
+            Err(e) => warn!("Encountered error on reading the shard roots: {}", e),
+        }
+    }
+
     /// Alter the timestamp of the block.
     pub fn set_timestamp(&mut self, timestamp: u64) {
         self.block.header.set_timestamp(timestamp);
@@ -445,11 +456,16 @@ pub fn enact(
 
 #[cfg(test)]
 mod tests {
-    use ctypes::Address;
+    use ccrypto::BLAKE_NULL_RLP;
+    use ckeys::{Generator, Random};
+    use ctypes::{Address, H256};
 
+    use super::super::parcel::{Action, Parcel};
     use super::super::spec::Spec;
+    use super::super::state::{ShardAddress, TopState, TopStateInfo};
     use super::super::tests::helpers::get_temp_state_db;
-    use super::OpenBlock;
+    use super::super::transaction::Transaction;
+    use super::{IsBlock, OpenBlock};
 
     #[test]
     fn open_block() {
@@ -463,4 +479,41 @@ mod tests {
         let b = b.close_and_lock(parent_parcels_root, parent_invoices_root);
         let _ = b.seal(&*spec.engine, vec![]);
     }
+
+    #[test]
+    fn header_commits_to_the_shard_roots() {
+        let spec = Spec::new_test();
+        let genesis_header = spec.genesis_header();
+        assert_eq!(vec![BLAKE_NULL_RLP; spec.params().shard_count as usize], genesis_header.shard_roots());
+
+        let db = spec.ensure_db_good(get_temp_state_db(), &Default::default()).unwrap();
+        let mut b =
+            OpenBlock::new(&*spec.engine, Default::default(), db, &genesis_header, Address::zero(), vec![], false)
+                .unwrap();
+        let keypair = Random.generate().unwrap();
+        b.block.state_mut().add_balance(&keypair.address(), &100.into()).unwrap();
+        let parcel = Parcel {
+            fee: 10.into(),
+            network_id: spec.params().network_id,
+            action: Action::ChangeShardState {
+                transactions: vec![Transaction::AssetMint {
+                    metadata: "metadata".to_string(),
+                    lock_script_hash: H256::random(),
+                    parameters: vec![],
+                    amount: Some(30),
+                    registrar: None,
+                    nonce: 0,
+                }],
+            },
+            ..Parcel::default()
+        }.sign(keypair.private());
+        b.push_parcel(parcel, None).unwrap();
+
+        let parent_parcels_root = genesis_header.parcels_root().clone();
+        let parent_invoices_root = genesis_header.invoices_root().clone();
+        let b = b.close_and_lock(parent_parcels_root, parent_invoices_root);
+        let shard_root = b.block().state.shard_root(&ShardAddress::new(0)).unwrap().unwrap();
+        assert_ne!(BLAKE_NULL_RLP, shard_root);
+        assert_eq!(&[shard_root], b.header().shard_roots());
+    }
 }
@@ -35,10 +35,11 @@ use super::extras::{
     BlockDetails, BlockInvoices, EpochTransitions, ParcelAddress, ParcelInvoice, TransactionAddress, EPOCH_KEY_PREFIX,
 };
 use super::headerchain::{HeaderChain, HeaderProvider};
+use super::indexer::{ChainIndexer, IndexDivergence};
 use super::invoice_db::{InvoiceDB, InvoiceProvider};
 use super::route::{tree_route, ImportRoute};
 
-const BEST_BLOCK_KEY: &[u8] = b"best-block";
+pub const BEST_BLOCK_KEY: &[u8] = b"best-block";
 
 /// Structure providing fast access to blockchain data.
 ///
@@ -49,6 +50,7 @@ pub struct BlockChain {
     headerchain: HeaderChain,
     body_db: BodyDB,
     invoice_db: InvoiceDB,
+    indexer: ChainIndexer,
 
     db: Arc<KeyValueDB>,
 
@@ -79,6 +81,7 @@ impl BlockChain {
             headerchain: HeaderChain::new(&genesis_block.header_view(), db.clone()),
             body_db: BodyDB::new(&genesis_block, db.clone()),
             invoice_db: InvoiceDB::new(db.clone()),
+            indexer: ChainIndexer::new(db.clone()),
 
             db,
 
@@ -111,7 +114,8 @@ impl BlockChain {
         let location = self.block_location(&block);
 
         self.headerchain.insert_header(batch, &header);
-        self.body_db.insert_body(batch, &block, &location);
+        self.body_db.insert_body(batch, &block);
+        self.indexer.insert(batch, &self.body_db, &block, &location);
         self.invoice_db.insert_invoice(batch, &hash, invoices);
 
         if location != BlockLocation::Branch {
@@ -123,10 +127,30 @@ impl BlockChain {
         ImportRoute::new(&hash, &location)
     }
 
+    /// Inserts the block whose state is restored from a snapshot as the best block.
+    /// Expects the block to be valid and its header to be in the canonical header chain.
+    /// The ancestors of the block may have no bodies, and the invoices of the block are left empty.
+    pub fn insert_restored_block(&self, batch: &mut DBTransaction, bytes: &[u8]) -> ImportRoute {
+        let block = BlockView::new(bytes);
+        let hash = block.header_view().hash();
+
+        assert!(self.pending_best_block_hash.read().is_none());
+
+        let location = BlockLocation::CanonChain;
+        self.body_db.insert_body(batch, &block);
+        self.indexer.insert(batch, &self.body_db, &block, &location);
+        self.invoice_db.insert_invoice(batch, &hash, Vec::new());
+
+        batch.put(db::COL_EXTRA, BEST_BLOCK_KEY, &hash);
+        *self.pending_best_block_hash.write() = Some(hash);
+
+        ImportRoute::new(&hash, &location)
+    }
+
     /// Apply pending insertion updates
     pub fn commit(&self) {
         self.headerchain.commit();
-        self.body_db.commit();
+        self.indexer.commit();
         // NOTE: There are no commit for InvoiceDB
 
         let mut best_block_hash = self.best_block_hash.write();
@@ -187,6 +211,22 @@ impl BlockChain {
         self.block_header_data(&self.best_block_hash()).expect("Best block always exists")
     }
 
+    /// Approximate heap size used by the header, body and invoice caches.
+    pub fn mem_used(&self) -> usize {
+        self.headerchain.mem_used() + self.body_db.mem_used() + self.invoice_db.mem_used() + self.indexer.mem_used()
+    }
+
+    /// Compares the indexes of the `depth` most recent canonical blocks with the ones derived from their bodies.
+    pub fn verify_indexes(&self, depth: BlockNumber) -> Result<(), Vec<IndexDivergence>> {
+        self.indexer.verify(self, &self.body_db, self.best_block_hash(), depth)
+    }
+
+    /// Compares the indexes of the parcels in the blocks a reorganization retracted and enacted
+    /// with the ones derived from their bodies.
+    pub fn verify_route_indexes(&self, retracted: &[H256], enacted: &[H256]) -> Result<(), Vec<IndexDivergence>> {
+        self.indexer.verify_route(self, &self.body_db, retracted, enacted)
+    }
+
     /// Insert an epoch transition. Provide an epoch number being transitioned to
     /// and epoch transition object.
     ///
@@ -403,11 +443,11 @@ impl BodyProvider for BlockChain {
     }
 
     fn parcel_address(&self, hash: &H256) -> Option<ParcelAddress> {
-        self.body_db.parcel_address(hash)
+        self.indexer.parcel_address(hash)
     }
 
     fn transaction_address(&self, hash: &H256) -> Option<TransactionAddress> {
-        self.body_db.transaction_address(hash)
+        self.indexer.transaction_address(hash)
     }
 
     fn block_body(&self, hash: &H256) -> Option<encoded::Body> {
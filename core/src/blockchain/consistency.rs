@@ -0,0 +1,599 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Startup check of the chain stored in the database.
+//!
+//! A crash in the middle of a write may leave the best block pointing to data
+//! which can't be read back. `check_consistency` finds such a state before the
+//! node starts importing on top of it, and `rewind_to_consistent_block` moves
+//! the best block back to the most recent ancestor which is fully readable.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use ccrypto::blake256;
+use ctypes::H256;
+use kvdb::{DBTransaction, KeyValueDB};
+use rlp::{Decodable, UntrustedRlp};
+use rlp_compress::{blocks_swapper, decompress};
+
+use super::super::db::{self, Key, Writable};
+use super::super::header::Header;
+use super::super::parcel::{Action, UnverifiedParcel};
+use super::super::types::BlockNumber;
+use super::blockchain::BEST_BLOCK_KEY;
+use super::extras::{BlockDetails, BlockInvoices, ParcelAddress, TransactionAddress};
+use super::headerchain::BEST_HEADER_KEY;
+
+/// The number of the most recent canonical blocks whose hash index is checked.
+pub const CHECKED_HASH_INDEXES: BlockNumber = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inconsistency {
+    /// The header of the block is not stored.
+    MissingHeader(H256),
+    /// The stored header can't be decoded or doesn't hash to the block.
+    CorruptedHeader(H256),
+    /// The details of the block are not stored or can't be decoded.
+    MissingDetails(H256),
+    MissingBody(H256),
+    /// The state root of the block is not in the state database.
+    MissingState {
+        block: H256,
+        state_root: H256,
+    },
+    /// The hash index of the canonical chain doesn't point to the block at the number.
+    HashIndexMismatch {
+        number: BlockNumber,
+        expected: H256,
+        found: Option<H256>,
+    },
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inconsistency::MissingHeader(hash) => write!(f, "The header of block {} is missing", hash),
+            Inconsistency::CorruptedHeader(hash) => write!(f, "The header of block {} is corrupted", hash),
+            Inconsistency::MissingDetails(hash) => write!(f, "The details of block {} are missing", hash),
+            Inconsistency::MissingBody(hash) => write!(f, "The body of block {} is missing", hash),
+            Inconsistency::MissingState {
+                block,
+                state_root,
+            } => write!(f, "The state {} of block {} is missing", state_root, block),
+            Inconsistency::HashIndexMismatch {
+                number,
+                expected,
+                found: Some(found),
+            } => write!(f, "Block #{} is indexed as {}, expected {}", number, found, expected),
+            Inconsistency::HashIndexMismatch {
+                number,
+                expected,
+                found: None,
+            } => write!(f, "Block #{} is not indexed, expected {}", number, expected),
+        }
+    }
+}
+
+/// The best block after a rewind.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rewound {
+    pub best_block_hash: H256,
+    pub best_block_number: BlockNumber,
+    /// The number of blocks removed from the database.
+    pub removed: usize,
+}
+
+/// Checks that the best block can be read back with its state, and that the
+/// hash index agrees with the most recent canonical blocks.
+///
+/// A database which has no best block yet is consistent.
+pub fn check_consistency<F>(db: &KeyValueDB, has_state: F) -> Result<(), Inconsistency>
+where
+    F: Fn(&H256) -> bool, {
+    let best_block_hash = match read_hash(db, BEST_BLOCK_KEY) {
+        Some(hash) => hash,
+        None => return Ok(()),
+    };
+    check_block(db, &best_block_hash, &has_state)?;
+
+    // The hash index follows the best header, which may be ahead of the best block.
+    let best_header_hash = read_hash(db, BEST_HEADER_KEY).unwrap_or(best_block_hash);
+    check_hash_index(db, &best_header_hash)
+}
+
+/// Moves the best block and the best header back to the most recent ancestor
+/// of the best block which passes `check_consistency`.
+///
+/// The blocks above the ancestor are removed with their descendants, and so are
+/// the entries of the hash index, the parcel and transaction addresses and the
+/// invoices which refer to them. Returns `None` if there is no such ancestor.
+pub fn rewind_to_consistent_block<F>(db: &KeyValueDB, has_state: F) -> Option<Rewound>
+where
+    F: Fn(&H256) -> bool, {
+    let best_block_hash = read_hash(db, BEST_BLOCK_KEY)?;
+    let mut removed = Vec::new();
+
+    let mut current = if parent_of(db, &best_block_hash).is_some() {
+        best_block_hash
+    } else {
+        // The best block can't lead to its ancestors; start from the top of the hash index.
+        removed.push(best_block_hash);
+        read_extra::<H256, _>(db, &highest_indexed_number(db)?)?
+    };
+    let (ancestor_hash, ancestor_details) = loop {
+        if check_block(db, &current, &has_state).is_ok() && check_hash_index(db, &current).is_ok() {
+            let details = read_extra::<BlockDetails, _>(db, &current).expect("Checked above");
+            break (current, details)
+        }
+        removed.push(current);
+        current = parent_of(db, &current)?;
+    };
+
+    if let Some(mut current) = read_hash(db, BEST_HEADER_KEY) {
+        while let Some(details) = read_extra::<BlockDetails, _>(db, &current) {
+            if details.number <= ancestor_details.number {
+                break
+            }
+            removed.push(current);
+            current = details.parent;
+        }
+    }
+
+    let removed = with_descendants(db, removed, &ancestor_hash);
+    let mut batch = DBTransaction::new();
+    let highest_number = highest_indexed_number(db).unwrap_or(0);
+    for number in (ancestor_details.number + 1)..(highest_number + 1) {
+        delete_extra::<H256, _>(&mut batch, &number);
+    }
+
+    let mut parents = HashMap::new();
+    for hash in &removed {
+        remove_block(db, &mut batch, hash);
+        if let Some(parent) = parent_of(db, hash) {
+            if !removed.contains(&parent) {
+                if let Some(details) = read_extra::<BlockDetails, _>(db, &parent) {
+                    parents.entry(parent).or_insert(details).children.retain(|child| !removed.contains(child));
+                }
+            }
+        }
+    }
+    for (hash, details) in parents {
+        batch.write(db::COL_EXTRA, &hash, &details);
+    }
+
+    batch.put(db::COL_EXTRA, BEST_BLOCK_KEY, &ancestor_hash);
+    batch.put(db::COL_EXTRA, BEST_HEADER_KEY, &ancestor_hash);
+    db.write(batch).expect("Low level database error. Some issue with disk?");
+
+    Some(Rewound {
+        best_block_hash: ancestor_hash,
+        best_block_number: ancestor_details.number,
+        removed: removed.len(),
+    })
+}
+
+fn check_block<F>(db: &KeyValueDB, hash: &H256, has_state: &F) -> Result<(), Inconsistency>
+where
+    F: Fn(&H256) -> bool, {
+    let header = read_header(db, hash)?;
+    if read_extra::<BlockDetails, _>(db, hash).is_none() {
+        return Err(Inconsistency::MissingDetails(*hash))
+    }
+    if read_body(db, hash).is_none() {
+        return Err(Inconsistency::MissingBody(*hash))
+    }
+    if !has_state(header.state_root()) {
+        return Err(Inconsistency::MissingState {
+            block: *hash,
+            state_root: *header.state_root(),
+        })
+    }
+    Ok(())
+}
+
+/// Checks the hash index of `CHECKED_HASH_INDEXES` blocks from `hash` down.
+fn check_hash_index(db: &KeyValueDB, hash: &H256) -> Result<(), Inconsistency> {
+    let mut current = *hash;
+    for _ in 0..CHECKED_HASH_INDEXES {
+        let details =
+            read_extra::<BlockDetails, _>(db, &current).ok_or_else(|| Inconsistency::MissingDetails(current))?;
+        let found = read_extra::<H256, _>(db, &details.number);
+        if found != Some(current) {
+            return Err(Inconsistency::HashIndexMismatch {
+                number: details.number,
+                expected: current,
+                found,
+            })
+        }
+        if details.number == 0 {
+            break
+        }
+        current = details.parent;
+    }
+    Ok(())
+}
+
+fn read_hash(db: &KeyValueDB, key: &[u8]) -> Option<H256> {
+    db.get(db::COL_EXTRA, key)
+        .expect("Low level database error. Some issue with disk?")
+        .map(|hash| H256::from_slice(&hash))
+}
+
+/// Reads a value of the extra column, treating an undecodable value as missing.
+fn read_extra<T, K>(db: &KeyValueDB, key: &K) -> Option<T>
+where
+    T: Decodable,
+    K: Key<T>, {
+    let bytes = db.get(db::COL_EXTRA, &key.key()).expect("Low level database error. Some issue with disk?")?;
+    UntrustedRlp::new(&bytes).as_val().ok()
+}
+
+fn read_header(db: &KeyValueDB, hash: &H256) -> Result<Header, Inconsistency> {
+    let compressed = db.get(db::COL_HEADERS, hash)
+        .expect("Low level database error. Some issue with disk?")
+        .ok_or_else(|| Inconsistency::MissingHeader(*hash))?;
+    let bytes = decompress(&compressed, blocks_swapper());
+    match UntrustedRlp::new(&bytes).as_val::<Header>() {
+        Ok(ref header) if header.hash() == *hash => Ok(header.clone()),
+        _ => Err(Inconsistency::CorruptedHeader(*hash)),
+    }
+}
+
+fn read_body(db: &KeyValueDB, hash: &H256) -> Option<Vec<u8>> {
+    let compressed = db.get(db::COL_BODIES, hash).expect("Low level database error. Some issue with disk?")?;
+    Some(decompress(&compressed, blocks_swapper()).into_vec())
+}
+
+fn parent_of(db: &KeyValueDB, hash: &H256) -> Option<H256> {
+    match read_extra::<BlockDetails, _>(db, hash) {
+        Some(details) => Some(details.parent),
+        None => read_header(db, hash).ok().map(|header| *header.parent_hash()),
+    }
+}
+
+/// Returns the highest number in the hash index, assuming the numbers have no gap.
+fn highest_indexed_number(db: &KeyValueDB) -> Option<BlockNumber> {
+    let is_indexed = |number: BlockNumber| read_extra::<H256, _>(db, &number).is_some();
+    if !is_indexed(0) {
+        return None
+    }
+    let mut upper = 1;
+    while is_indexed(upper) {
+        upper *= 2;
+    }
+    // is_indexed(lower) && !is_indexed(upper)
+    let mut lower = upper / 2;
+    while lower + 1 < upper {
+        let middle = lower + (upper - lower) / 2;
+        if is_indexed(middle) {
+            lower = middle;
+        } else {
+            upper = middle;
+        }
+    }
+    Some(lower)
+}
+
+fn with_descendants(db: &KeyValueDB, blocks: Vec<H256>, ancestor: &H256) -> HashSet<H256> {
+    let mut result = HashSet::new();
+    let mut queue: VecDeque<H256> = blocks.into_iter().collect();
+    while let Some(hash) = queue.pop_front() {
+        if hash == *ancestor || !result.insert(hash) {
+            continue
+        }
+        if let Some(details) = read_extra::<BlockDetails, _>(db, &hash) {
+            queue.extend(details.children);
+        }
+    }
+    result
+}
+
+/// Removes the block and the indexes which refer to it.
+fn remove_block(db: &KeyValueDB, batch: &mut DBTransaction, hash: &H256) {
+    if let Some(body) = read_body(db, hash) {
+        let body = UntrustedRlp::new(&body);
+        if let Ok(parcels) = body.at(0) {
+            for parcel in parcels.iter() {
+                let parcel_hash = blake256(parcel.as_raw());
+                if let Some(address) = read_extra::<ParcelAddress, _>(db, &parcel_hash) {
+                    if address.block_hash == *hash {
+                        delete_extra::<ParcelAddress, _>(batch, &parcel_hash);
+                    }
+                }
+
+                let transactions: Vec<H256> = match parcel.as_val::<UnverifiedParcel>() {
+                    Ok(parcel) => match parcel.action {
+                        Action::ChangeShardState {
+                            ref transactions,
+                        } => transactions.iter().map(|transaction| transaction.hash()).collect(),
+                        _ => vec![],
+                    },
+                    Err(_) => vec![],
+                };
+                for transaction_hash in transactions {
+                    if let Some(address) = read_extra::<TransactionAddress, _>(db, &transaction_hash) {
+                        if address.parcel_address.block_hash == *hash {
+                            delete_extra::<TransactionAddress, _>(batch, &transaction_hash);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    batch.delete(db::COL_HEADERS, hash);
+    batch.delete(db::COL_BODIES, hash);
+    delete_extra::<BlockDetails, _>(batch, hash);
+    delete_extra::<BlockInvoices, _>(batch, hash);
+}
+
+fn delete_extra<T, K>(batch: &mut DBTransaction, key: &K)
+where
+    K: Key<T>, {
+    batch.delete(db::COL_EXTRA, &key.key());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ckeys::{Generator, Random};
+    use ctypes::{Bytes, H256};
+    use rlp::{self, RlpStream};
+
+    use super::super::super::header::Header;
+    use super::super::super::parcel::{Action, Parcel};
+    use super::super::super::tests::helpers::new_db;
+    use super::super::super::transaction::Transaction;
+    use super::super::{BlockChain, BlockProvider, BodyProvider, HeaderProvider, InvoiceProvider};
+    use super::*;
+
+    struct TestChain {
+        db: Arc<KeyValueDB>,
+        genesis: Bytes,
+        /// The hashes of the blocks from the genesis.
+        hashes: Vec<H256>,
+        state_roots: HashSet<H256>,
+        parcels: Vec<H256>,
+        transactions: Vec<H256>,
+    }
+
+    impl TestChain {
+        /// Creates a chain of `length` blocks after the genesis, each of which has a parcel minting an asset.
+        fn new(length: BlockNumber) -> Self {
+            let db = new_db();
+            let mut genesis_header = Header::new();
+            genesis_header.set_state_root(H256::random());
+            let genesis = encode_block(&genesis_header, &[]);
+            let chain = BlockChain::new(&genesis, db.clone());
+
+            let keypair = Random.generate().unwrap();
+            let mut test_chain = TestChain {
+                db: db.clone(),
+                genesis,
+                hashes: vec![genesis_header.hash()],
+                state_roots: vec![*genesis_header.state_root()].into_iter().collect(),
+                parcels: vec![],
+                transactions: vec![],
+            };
+            let mut parent = genesis_header;
+            for number in 1..(length + 1) {
+                let transaction = Transaction::AssetMint {
+                    metadata: format!("asset {}", number),
+                    lock_script_hash: H256::random(),
+                    parameters: vec![],
+                    amount: Some(number),
+                    registrar: None,
+                    nonce: number,
+                };
+                let parcel = Parcel {
+                    nonce: number.into(),
+                    action: Action::ChangeShardState {
+                        transactions: vec![transaction.clone()],
+                    },
+                    ..Parcel::default()
+                }.sign(keypair.private());
+
+                let mut header = Header::new();
+                header.set_parent_hash(parent.hash());
+                header.set_number(number);
+                header.set_score(1.into());
+                header.set_state_root(H256::random());
+                let block = encode_block(&header, &[rlp::encode(&parcel).into_vec()]);
+
+                let mut batch = DBTransaction::new();
+                chain.insert_block(&mut batch, &block, vec![]);
+                db.write(batch).unwrap();
+                chain.commit();
+
+                test_chain.hashes.push(header.hash());
+                test_chain.state_roots.insert(*header.state_root());
+                test_chain.parcels.push(parcel.hash());
+                test_chain.transactions.push(transaction.hash());
+                parent = header;
+            }
+            test_chain
+        }
+
+        fn check(&self) -> Result<(), Inconsistency> {
+            check_consistency(&*self.db, |root| self.state_roots.contains(root))
+        }
+
+        fn rewind(&self) -> Option<Rewound> {
+            rewind_to_consistent_block(&*self.db, |root| self.state_roots.contains(root))
+        }
+
+        fn reopen(&self) -> BlockChain {
+            BlockChain::new(&self.genesis, self.db.clone())
+        }
+
+        fn corrupt<F: FnOnce(&mut DBTransaction)>(&self, f: F) {
+            let mut batch = DBTransaction::new();
+            f(&mut batch);
+            self.db.write(batch).unwrap();
+        }
+    }
+
+    fn encode_block(header: &Header, parcels: &[Bytes]) -> Bytes {
+        let mut block = RlpStream::new_list(2);
+        block.append(header);
+        block.begin_list(parcels.len());
+        for parcel in parcels {
+            block.append_raw(parcel, 1);
+        }
+        block.out()
+    }
+
+    /// Asserts that the blocks after `number` are gone with their indexes.
+    fn assert_rewound_to(test_chain: &TestChain, number: usize) {
+        let chain = test_chain.reopen();
+        assert_eq!(test_chain.hashes[number], chain.best_block_hash());
+        assert_eq!(number as BlockNumber, chain.best_block_detail().number);
+        assert_eq!(test_chain.hashes[number], read_hash(&*test_chain.db, BEST_HEADER_KEY).unwrap());
+        assert_eq!(Vec::<H256>::new(), chain.best_block_detail().children);
+        assert_eq!(Ok(()), test_chain.check());
+
+        for removed in (number + 1)..test_chain.hashes.len() {
+            let hash = test_chain.hashes[removed];
+            assert!(!chain.is_known(&hash));
+            assert_eq!(None, chain.block_hash(removed as BlockNumber));
+            assert!(!chain.is_known_invoice(&hash));
+            assert_eq!(None, chain.parcel_address(&test_chain.parcels[removed - 1]));
+            assert_eq!(None, chain.transaction_address(&test_chain.transactions[removed - 1]));
+        }
+        for kept in 1..(number + 1) {
+            assert!(chain.parcel_address(&test_chain.parcels[kept - 1]).is_some());
+            assert!(chain.transaction_address(&test_chain.transactions[kept - 1]).is_some());
+        }
+    }
+
+    #[test]
+    fn intact_chain_is_consistent() {
+        let test_chain = TestChain::new(5);
+        assert_eq!(Ok(()), test_chain.check());
+        assert_eq!(Ok(()), check_consistency(&*new_db(), |_| false));
+    }
+
+    #[test]
+    fn dangling_best_block_is_rewound_to_the_top_of_the_index() {
+        let test_chain = TestChain::new(5);
+        let dangling = H256::random();
+        test_chain.corrupt(|batch| batch.put(db::COL_EXTRA, BEST_BLOCK_KEY, &dangling));
+
+        assert_eq!(Err(Inconsistency::MissingHeader(dangling)), test_chain.check());
+        assert_eq!(
+            format!("The header of block {} is missing", dangling),
+            test_chain.check().unwrap_err().to_string()
+        );
+
+        assert_eq!(
+            Some(Rewound {
+                best_block_hash: test_chain.hashes[5],
+                best_block_number: 5,
+                removed: 1,
+            }),
+            test_chain.rewind()
+        );
+        assert_rewound_to(&test_chain, 5);
+    }
+
+    #[test]
+    fn corrupted_best_header_is_rewound() {
+        let test_chain = TestChain::new(5);
+        let best = test_chain.hashes[5];
+        test_chain.corrupt(|batch| batch.put(db::COL_HEADERS, &best, b"garbage"));
+
+        assert_eq!(
+            format!("The header of block {} is corrupted", best),
+            test_chain.check().unwrap_err().to_string()
+        );
+
+        assert_eq!(4, test_chain.rewind().unwrap().best_block_number);
+        assert_rewound_to(&test_chain, 4);
+    }
+
+    #[test]
+    fn missing_state_is_rewound() {
+        let mut test_chain = TestChain::new(5);
+        let best = test_chain.hashes[5];
+        let state_root = *test_chain.reopen().best_block_header().decode().state_root();
+        let parent_state_root = *test_chain.reopen().block_header(&test_chain.hashes[4]).unwrap().state_root();
+        test_chain.state_roots.remove(&state_root);
+        test_chain.state_roots.remove(&parent_state_root);
+
+        assert_eq!(
+            Err(Inconsistency::MissingState {
+                block: best,
+                state_root,
+            }),
+            test_chain.check()
+        );
+        assert_eq!(
+            format!("The state {} of block {} is missing", state_root, best),
+            test_chain.check().unwrap_err().to_string()
+        );
+
+        assert_eq!(3, test_chain.rewind().unwrap().best_block_number);
+        assert_rewound_to(&test_chain, 3);
+    }
+
+    #[test]
+    fn mismatched_hash_index_is_rewound_below_the_mismatch() {
+        let test_chain = TestChain::new(5);
+        let wrong = H256::random();
+        test_chain.corrupt(|batch| batch.write(db::COL_EXTRA, &3u64, &wrong));
+
+        assert_eq!(
+            Err(Inconsistency::HashIndexMismatch {
+                number: 3,
+                expected: test_chain.hashes[3],
+                found: Some(wrong),
+            }),
+            test_chain.check()
+        );
+        assert_eq!(
+            format!("Block #3 is indexed as {}, expected {}", wrong, test_chain.hashes[3]),
+            test_chain.check().unwrap_err().to_string()
+        );
+
+        assert_eq!(2, test_chain.rewind().unwrap().best_block_number);
+        assert_rewound_to(&test_chain, 2);
+    }
+
+    #[test]
+    fn missing_body_is_reported() {
+        let test_chain = TestChain::new(5);
+        let best = test_chain.hashes[5];
+        test_chain.corrupt(|batch| batch.delete(db::COL_BODIES, &best));
+
+        assert_eq!(Err(Inconsistency::MissingBody(best)), test_chain.check());
+        assert_eq!(format!("The body of block {} is missing", best), test_chain.check().unwrap_err().to_string());
+    }
+
+    #[test]
+    fn chain_without_a_consistent_block_is_not_rewound() {
+        let test_chain = TestChain::new(2);
+        test_chain.corrupt(|batch| {
+            for hash in &test_chain.hashes {
+                batch.delete(db::COL_BODIES, hash);
+            }
+        });
+
+        assert!(test_chain.check().is_err());
+        assert_eq!(None, test_chain.rewind());
+    }
+}
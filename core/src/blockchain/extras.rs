@@ -46,6 +46,11 @@ enum ExtrasIndex {
     PendingEpochTransition = 6,
 }
 
+/// The first byte of the keys of the parcel addresses.
+pub const PARCEL_ADDRESS_PREFIX: &[u8] = &[ExtrasIndex::ParcelAddress as u8];
+/// The first byte of the keys of the transaction addresses.
+pub const TRANSACTION_ADDRESS_PREFIX: &[u8] = &[ExtrasIndex::TransactionAddress as u8];
+
 fn with_index(hash: &H256, i: ExtrasIndex) -> H264 {
     let mut result = H264::default();
     result[0] = i as u8;
@@ -194,8 +199,7 @@ pub struct TransactionAddress {
 }
 
 
-#[derive(Clone, Debug, PartialEq, Serialize)]
-#[serde(untagged)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ParcelInvoice {
     Single(Invoice),
     Multiple(Vec<Invoice>),
@@ -16,6 +16,7 @@
 
 use std::collections::HashMap;
 use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::Arc;
 
 use ctypes::{Bytes, H256};
@@ -32,7 +33,7 @@ use super::block_info::BlockLocation;
 use super::extras::BlockDetails;
 use super::route::tree_route;
 
-const BEST_HEADER_KEY: &[u8] = b"best-header";
+pub const BEST_HEADER_KEY: &[u8] = b"best-header";
 
 /// Structure providing fast access to blockchain data.
 ///
@@ -43,6 +44,7 @@ pub struct HeaderChain {
 
     // cache
     header_cache: RwLock<HashMap<H256, Bytes>>,
+    header_cache_size: AtomicUsize,
     detail_cache: RwLock<HashMap<H256, BlockDetails>>,
     hash_cache: RwLock<HashMap<BlockNumber, H256>>,
 
@@ -87,6 +89,7 @@ impl HeaderChain {
             best_header_hash: RwLock::new(best_header_hash),
 
             header_cache: RwLock::new(HashMap::new()),
+            header_cache_size: AtomicUsize::new(0),
             detail_cache: RwLock::new(HashMap::new()),
             hash_cache: RwLock::new(HashMap::new()),
 
@@ -237,6 +240,14 @@ impl HeaderChain {
     pub fn best_header_detail(&self) -> BlockDetails {
         self.block_details(&self.best_header_hash()).expect("Best header always exists")
     }
+
+    /// Approximate heap size used by the caches.
+    pub fn mem_used(&self) -> usize {
+        self.header_cache_size.load(AtomicOrdering::Relaxed)
+            + self.header_cache.read().len() * mem::size_of::<(H256, Bytes)>()
+            + self.detail_cache.read().len() * mem::size_of::<(H256, BlockDetails)>()
+            + self.hash_cache.read().len() * mem::size_of::<(BlockNumber, H256)>()
+    }
 }
 
 /// Interface for querying blocks by hash and by number.
@@ -295,7 +306,10 @@ impl HeaderProvider for HeaderChain {
 
         let bytes = decompress(&b, blocks_swapper()).into_vec();
         let mut write = self.header_cache.write();
-        write.insert(*hash, bytes.clone());
+        self.header_cache_size.fetch_add(bytes.len(), AtomicOrdering::Relaxed);
+        if let Some(old) = write.insert(*hash, bytes.clone()) {
+            self.header_cache_size.fetch_sub(old.len(), AtomicOrdering::Relaxed);
+        }
 
         Some(encoded::Header::new(bytes))
     }
@@ -0,0 +1,653 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The indexes of the canonical chain.
+//!
+//! Parcel addresses and transaction addresses only describe
+//! the canonical chain, so a reorganization has to remove the entries of the
+//! retracted blocks and add the ones of the enacted blocks. `ChainIndexer`
+//! derives both from the tree route of each import, and `verify` re-derives
+//! them from the stored bodies to find the entries which diverged.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+use ctypes::H256;
+use kvdb::{DBTransaction, KeyValueDB};
+use parking_lot::RwLock;
+
+use super::super::db::{self, CacheUpdatePolicy, Readable, Writable};
+use super::super::parcel::{Action, UnverifiedParcel};
+use super::super::types::BlockNumber;
+use super::super::views::BlockView;
+use super::block_info::BlockLocation;
+use super::body_db::BodyDB;
+use super::extras::{ParcelAddress, TransactionAddress, PARCEL_ADDRESS_PREFIX, TRANSACTION_ADDRESS_PREFIX};
+use super::headerchain::HeaderProvider;
+
+/// An index entry which doesn't agree with the canonical chain.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IndexDivergence {
+    /// The hash index doesn't point to the canonical block at the number.
+    BlockHash {
+        number: BlockNumber,
+        expected: H256,
+        found: Option<H256>,
+    },
+    /// The body of a canonical block is not stored.
+    MissingBody(H256),
+    ParcelAddress {
+        parcel: H256,
+        expected: Option<ParcelAddress>,
+        found: Option<ParcelAddress>,
+    },
+    TransactionAddress {
+        transaction: H256,
+        expected: Option<TransactionAddress>,
+        found: Option<TransactionAddress>,
+    },
+}
+
+impl fmt::Display for IndexDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexDivergence::BlockHash {
+                number,
+                expected,
+                found,
+            } => write!(f, "Block #{} is indexed as {:?}, expected {}", number, found, expected),
+            IndexDivergence::MissingBody(hash) => write!(f, "The body of block {} is missing", hash),
+            IndexDivergence::ParcelAddress {
+                parcel,
+                expected,
+                found,
+            } => write!(f, "Parcel {} is indexed at {:?}, expected {:?}", parcel, found, expected),
+            IndexDivergence::TransactionAddress {
+                transaction,
+                expected,
+                found,
+            } => write!(f, "Transaction {} is indexed at {:?}, expected {:?}", transaction, found, expected),
+        }
+    }
+}
+
+/// The parcels of a block, with the hash of the block.
+type BlockParcels = (H256, Vec<UnverifiedParcel>);
+
+/// Maintains the parcel addresses and the transaction addresses.
+pub struct ChainIndexer {
+    parcel_address_cache: RwLock<HashMap<H256, ParcelAddress>>,
+    pending_parcel_addresses: RwLock<HashMap<H256, Option<ParcelAddress>>>,
+
+    transaction_address_cache: RwLock<HashMap<H256, TransactionAddress>>,
+    pending_transaction_addresses: RwLock<HashMap<H256, Option<TransactionAddress>>>,
+
+    db: Arc<KeyValueDB>,
+}
+
+impl ChainIndexer {
+    pub fn new(db: Arc<KeyValueDB>) -> Self {
+        Self {
+            parcel_address_cache: RwLock::new(HashMap::new()),
+            pending_parcel_addresses: RwLock::new(HashMap::new()),
+
+            transaction_address_cache: RwLock::new(HashMap::new()),
+            pending_transaction_addresses: RwLock::new(HashMap::new()),
+
+            db,
+        }
+    }
+
+    /// Approximate heap size used by the caches.
+    pub fn mem_used(&self) -> usize {
+        self.parcel_address_cache.read().len() * mem::size_of::<(H256, ParcelAddress)>()
+            + self.transaction_address_cache.read().len() * mem::size_of::<(H256, TransactionAddress)>()
+    }
+
+    /// Writes the index changes made by inserting `block` at `location`.
+    ///
+    /// The bodies of the other blocks on the route must be in `bodies` already.
+    pub fn insert(&self, batch: &mut DBTransaction, bodies: &BodyDB, block: &BlockView, location: &BlockLocation) {
+        let current = (block.hash(), block.parcels());
+        let (retracted, enacted) = match location {
+            BlockLocation::CanonChain => (vec![], vec![current]),
+            BlockLocation::BranchBecomingCanonChain(ref route) => {
+                let parcels_of = |hash: &H256| {
+                    let body = bodies.block_body(hash).expect("Blocks on the route must be in database.");
+                    (*hash, body.parcels())
+                };
+                let retracted: Vec<_> = route.retracted.iter().map(&parcels_of).collect();
+                let mut enacted: Vec<_> = route.enacted.iter().map(&parcels_of).collect();
+                enacted.push(current);
+                (retracted, enacted)
+            }
+            BlockLocation::Branch => return,
+        };
+
+        let (parcel_addresses, transaction_addresses) = index_entries(&retracted, &enacted);
+        batch.extend_with_option_cache(
+            db::COL_EXTRA,
+            &mut *self.pending_parcel_addresses.write(),
+            parcel_addresses,
+            CacheUpdatePolicy::Overwrite,
+        );
+        batch.extend_with_option_cache(
+            db::COL_EXTRA,
+            &mut *self.pending_transaction_addresses.write(),
+            transaction_addresses,
+            CacheUpdatePolicy::Overwrite,
+        );
+    }
+
+    /// Apply pending insertion updates
+    pub fn commit(&self) {
+        commit_entries(&mut *self.parcel_address_cache.write(), &mut *self.pending_parcel_addresses.write());
+        commit_entries(&mut *self.transaction_address_cache.write(), &mut *self.pending_transaction_addresses.write());
+    }
+
+    pub fn parcel_address(&self, hash: &H256) -> Option<ParcelAddress> {
+        self.db.read_with_cache(db::COL_EXTRA, &self.parcel_address_cache, hash)
+    }
+
+    pub fn transaction_address(&self, hash: &H256) -> Option<TransactionAddress> {
+        self.db.read_with_cache(db::COL_EXTRA, &self.transaction_address_cache, hash)
+    }
+
+    /// Re-derives the indexes of the `depth` most recent canonical blocks from their bodies
+    /// and compares them with the stored ones.
+    ///
+    /// Every stored address is read, so an address left behind by a retracted block is found
+    /// however old the block is. This is meant for debugging; it is slow on a long chain.
+    pub fn verify<C: HeaderProvider>(
+        &self,
+        headers: &C,
+        bodies: &BodyDB,
+        best_block_hash: H256,
+        depth: BlockNumber,
+    ) -> Result<(), Vec<IndexDivergence>> {
+        let mut divergences = Vec::new();
+
+        let mut canonical = Vec::new();
+        let mut hash = best_block_hash;
+        while (canonical.len() as BlockNumber) < depth {
+            let details = match headers.block_details(&hash) {
+                Some(details) => details,
+                None => break,
+            };
+            let found = headers.block_hash(details.number);
+            if found != Some(hash) {
+                divergences.push(IndexDivergence::BlockHash {
+                    number: details.number,
+                    expected: hash,
+                    found,
+                });
+            }
+            match bodies.block_body(&hash) {
+                Some(body) => canonical.push((hash, body.parcels())),
+                None => divergences.push(IndexDivergence::MissingBody(hash)),
+            }
+            if details.number == 0 {
+                break
+            }
+            hash = details.parent;
+        }
+        canonical.reverse();
+        let lowest_checked = canonical.first().and_then(|(hash, _)| headers.block_details(hash)).map(|d| d.number);
+
+        let (parcel_addresses, transaction_addresses) = index_entries(&[], &canonical);
+        let is_checked = |address: &ParcelAddress| match headers.block_details(&address.block_hash) {
+            // An address into a canonical block below the checked blocks is not derived again.
+            Some(details) => {
+                headers.block_hash(details.number) != Some(address.block_hash)
+                    || lowest_checked.map_or(false, |lowest| details.number >= lowest)
+            }
+            None => true,
+        };
+
+        let stored_parcels =
+            stored_entries(&*self.db, PARCEL_ADDRESS_PREFIX, &parcel_addresses, |hash| self.parcel_address(hash));
+        for (parcel, found) in stored_parcels {
+            let expected = parcel_addresses.get(&parcel).cloned().and_then(|address| address);
+            let is_divergent = match (&expected, &found) {
+                (Some(_), _) => expected != found,
+                (None, Some(found)) => is_checked(found),
+                (None, None) => false,
+            };
+            if is_divergent {
+                divergences.push(IndexDivergence::ParcelAddress {
+                    parcel,
+                    expected,
+                    found,
+                });
+            }
+        }
+        let stored_transactions = stored_entries(&*self.db, TRANSACTION_ADDRESS_PREFIX, &transaction_addresses, |hash| {
+            self.transaction_address(hash)
+        });
+        for (transaction, found) in stored_transactions {
+            let expected = transaction_addresses.get(&transaction).cloned().and_then(|address| address);
+            let is_divergent = match (&expected, &found) {
+                (Some(_), _) => expected != found,
+                (None, Some(found)) => is_checked(&found.parcel_address),
+                (None, None) => false,
+            };
+            if is_divergent {
+                divergences.push(IndexDivergence::TransactionAddress {
+                    transaction,
+                    expected,
+                    found,
+                });
+            }
+        }
+
+        if divergences.is_empty() {
+            Ok(())
+        } else {
+            Err(divergences)
+        }
+    }
+
+    /// Compares the indexes of the parcels in the `retracted` and `enacted` blocks of a reorganization
+    /// with the ones derived from their bodies.
+    ///
+    /// Unlike `verify`, only the entries of those parcels are read, so it is cheap enough to run
+    /// after every reorganization.
+    pub fn verify_route<C: HeaderProvider>(
+        &self,
+        headers: &C,
+        bodies: &BodyDB,
+        retracted: &[H256],
+        enacted: &[H256],
+    ) -> Result<(), Vec<IndexDivergence>> {
+        let mut divergences = Vec::new();
+
+        let mut retracted_parcels = Vec::new();
+        for hash in retracted {
+            match bodies.block_body(hash) {
+                Some(body) => retracted_parcels.push((*hash, body.parcels())),
+                None => divergences.push(IndexDivergence::MissingBody(*hash)),
+            }
+        }
+        let mut enacted_blocks = Vec::new();
+        for hash in enacted {
+            let number = match headers.block_number(hash) {
+                Some(number) => number,
+                None => continue,
+            };
+            let found = headers.block_hash(number);
+            if found != Some(*hash) {
+                divergences.push(IndexDivergence::BlockHash {
+                    number,
+                    expected: *hash,
+                    found,
+                });
+            }
+            match bodies.block_body(hash) {
+                Some(body) => enacted_blocks.push((number, (*hash, body.parcels()))),
+                None => divergences.push(IndexDivergence::MissingBody(*hash)),
+            }
+        }
+        enacted_blocks.sort_by_key(|(number, _)| *number);
+        let enacted_parcels: Vec<BlockParcels> = enacted_blocks.into_iter().map(|(_, parcels)| parcels).collect();
+
+        let (parcel_addresses, transaction_addresses) = index_entries(&retracted_parcels, &enacted_parcels);
+        for (parcel, expected, found) in divergent_entries(&parcel_addresses, |hash| self.parcel_address(hash)) {
+            divergences.push(IndexDivergence::ParcelAddress {
+                parcel,
+                expected,
+                found,
+            });
+        }
+        for (transaction, expected, found) in
+            divergent_entries(&transaction_addresses, |hash| self.transaction_address(hash))
+        {
+            divergences.push(IndexDivergence::TransactionAddress {
+                transaction,
+                expected,
+                found,
+            });
+        }
+
+        if divergences.is_empty() {
+            Ok(())
+        } else {
+            Err(divergences)
+        }
+    }
+}
+
+/// Returns the address entries removed by the retracted blocks and added by the enacted blocks.
+fn index_entries(
+    retracted: &[BlockParcels],
+    enacted: &[BlockParcels],
+) -> (HashMap<H256, Option<ParcelAddress>>, HashMap<H256, Option<TransactionAddress>>) {
+    let mut parcel_addresses = HashMap::new();
+    let mut transaction_addresses = HashMap::new();
+
+    for (_, parcels) in retracted {
+        for parcel in parcels {
+            parcel_addresses.insert(parcel.hash(), None);
+            for transaction in transactions(parcel) {
+                transaction_addresses.insert(transaction, None);
+            }
+        }
+    }
+    // The order here is important! Don't remove an entry if it is a part of the enacted blocks as well.
+    for (block_hash, parcels) in enacted {
+        for (parcel_index, parcel) in parcels.iter().enumerate() {
+            let parcel_address = ParcelAddress {
+                block_hash: *block_hash,
+                index: parcel_index,
+            };
+            for (index, transaction) in transactions(parcel).into_iter().enumerate() {
+                transaction_addresses.insert(
+                    transaction,
+                    Some(TransactionAddress {
+                        parcel_address: parcel_address.clone(),
+                        index,
+                    }),
+                );
+            }
+            parcel_addresses.insert(parcel.hash(), Some(parcel_address));
+        }
+    }
+    (parcel_addresses, transaction_addresses)
+}
+
+fn transactions(parcel: &UnverifiedParcel) -> Vec<H256> {
+    match &parcel.action {
+        Action::ChangeShardState {
+            transactions,
+        } => transactions.iter().map(|transaction| transaction.hash()).collect(),
+        _ => vec![],
+    }
+}
+
+fn commit_entries<T>(cache: &mut HashMap<H256, T>, pending: &mut HashMap<H256, Option<T>>) {
+    for (hash, entry) in pending.drain() {
+        match entry {
+            Some(entry) => {
+                cache.insert(hash, entry);
+            }
+            None => {
+                cache.remove(&hash);
+            }
+        }
+    }
+}
+
+/// Returns the expected entries which `read` finds otherwise, with the expected and the found ones.
+fn divergent_entries<T, F>(expected: &HashMap<H256, Option<T>>, read: F) -> Vec<(H256, Option<T>, Option<T>)>
+where
+    T: Clone + PartialEq,
+    F: Fn(&H256) -> Option<T>, {
+    let mut hashes: Vec<H256> = expected.keys().cloned().collect();
+    hashes.sort();
+    hashes
+        .into_iter()
+        .filter_map(|hash| {
+            let expected = expected[&hash].clone();
+            let found = read(&hash);
+            if expected == found {
+                None
+            } else {
+                Some((hash, expected, found))
+            }
+        })
+        .collect()
+}
+
+/// Returns the stored entries with the given key prefix and the expected entries, as `read` finds them.
+fn stored_entries<T, F>(
+    db: &KeyValueDB,
+    prefix: &[u8],
+    expected: &HashMap<H256, Option<T>>,
+    read: F,
+) -> Vec<(H256, Option<T>)>
+where
+    F: Fn(&H256) -> Option<T>, {
+    let mut hashes: Vec<H256> = db
+        .iter_from_prefix(db::COL_EXTRA, prefix)
+        .take_while(|(key, _)| key.starts_with(prefix))
+        .filter(|(key, _)| key.len() == 33)
+        .map(|(key, _)| H256::from(&key[1..]))
+        .collect();
+    hashes.extend(expected.keys().cloned());
+    hashes.sort();
+    hashes.dedup();
+    hashes.into_iter().map(|hash| (hash, read(&hash))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ckeys::{Generator, Random};
+    use ctypes::Bytes;
+    use rlp::{self, RlpStream};
+
+    use super::super::super::header::Header;
+    use super::super::super::parcel::{Parcel, SignedParcel};
+    use super::super::super::tests::helpers::new_db;
+    use super::super::super::transaction::Transaction;
+    use super::super::{BlockChain, BlockProvider, BodyProvider};
+    use super::*;
+
+    /// Returns parcels which mint an asset each.
+    fn parcels(count: usize) -> Vec<(SignedParcel, H256)> {
+        let keypair = Random.generate().unwrap();
+        (0..count)
+            .map(|nonce| {
+                let transaction = Transaction::AssetMint {
+                    metadata: format!("asset {}", nonce),
+                    lock_script_hash: H256::random(),
+                    parameters: vec![],
+                    amount: Some(1),
+                    registrar: None,
+                    nonce: nonce as u64,
+                };
+                let transaction_hash = transaction.hash();
+                let parcel = Parcel {
+                    nonce: (nonce as u64).into(),
+                    action: Action::ChangeShardState {
+                        transactions: vec![transaction],
+                    },
+                    ..Parcel::default()
+                }.sign(keypair.private());
+                (parcel, transaction_hash)
+            })
+            .collect()
+    }
+
+    fn insert_block(db: &Arc<KeyValueDB>, chain: &BlockChain, parent: &Header, parcels: &[&SignedParcel]) -> Header {
+        let mut header = Header::new();
+        header.set_parent_hash(parent.hash());
+        header.set_number(parent.number() + 1);
+        header.set_score(1.into());
+        header.set_state_root(H256::random());
+
+        let mut block = RlpStream::new_list(2);
+        block.append(&header);
+        block.begin_list(parcels.len());
+        for parcel in parcels {
+            block.append_raw(&rlp::encode(*parcel), 1);
+        }
+        let block: Bytes = block.out();
+
+        let mut batch = DBTransaction::new();
+        chain.insert_block(&mut batch, &block, vec![]);
+        db.write(batch).unwrap();
+        chain.commit();
+        header
+    }
+
+    fn parcel_address(block: &Header, index: usize) -> Option<ParcelAddress> {
+        Some(ParcelAddress {
+            block_hash: block.hash(),
+            index,
+        })
+    }
+
+    fn transaction_address(block: &Header, parcel_index: usize) -> Option<TransactionAddress> {
+        Some(TransactionAddress {
+            parcel_address: parcel_address(block, parcel_index).unwrap(),
+            index: 0,
+        })
+    }
+
+    struct ReorganizedChain {
+        db: Arc<KeyValueDB>,
+        chain: BlockChain,
+        /// The parcels with the hashes of their transactions.
+        parcels: Vec<(SignedParcel, H256)>,
+        retracted: Vec<Header>,
+        enacted: Vec<Header>,
+    }
+
+    impl ReorganizedChain {
+        fn reopen(&self) -> BlockChain {
+            let genesis = self.chain.block(&self.chain.genesis_hash()).unwrap().into_inner();
+            BlockChain::new(&genesis, self.db.clone())
+        }
+    }
+
+    /// Builds `a1[p0] -> a2[p1, p2] -> a3[p3]` and reorganizes it to `b1[p0] -> b2[p2] -> b3[p1] -> b4[]`.
+    fn reorganized_chain() -> ReorganizedChain {
+        let db = new_db();
+        let genesis = Header::new();
+        let mut genesis_block = RlpStream::new_list(2);
+        genesis_block.append(&genesis);
+        genesis_block.begin_list(0);
+        let chain = BlockChain::new(&genesis_block.out(), db.clone());
+
+        let parcels = parcels(4);
+        let (retracted, enacted) = {
+            let p = |i: usize| &parcels[i].0;
+
+            let a1 = insert_block(&db, &chain, &genesis, &[p(0)]);
+            let a2 = insert_block(&db, &chain, &a1, &[p(1), p(2)]);
+            let a3 = insert_block(&db, &chain, &a2, &[p(3)]);
+            assert_eq!(Ok(()), chain.verify_indexes(10));
+
+            let b1 = insert_block(&db, &chain, &genesis, &[p(0)]);
+            let b2 = insert_block(&db, &chain, &b1, &[p(2)]);
+            let b3 = insert_block(&db, &chain, &b2, &[p(1)]);
+            assert_eq!(a3.hash(), chain.best_block_hash());
+            assert_eq!(Ok(()), chain.verify_indexes(10));
+
+            let b4 = insert_block(&db, &chain, &b3, &[]);
+            assert_eq!(b4.hash(), chain.best_block_hash());
+            (vec![a1, a2, a3], vec![b1, b2, b3, b4])
+        };
+
+        ReorganizedChain {
+            db,
+            chain,
+            parcels,
+            retracted,
+            enacted,
+        }
+    }
+
+    #[test]
+    fn indexes_follow_a_multi_block_reorganization() {
+        let reorganized = reorganized_chain();
+        let chain = &reorganized.chain;
+        let fork = &reorganized.enacted;
+        assert_eq!(Ok(()), chain.verify_indexes(10));
+
+        let parcel = |i: usize| reorganized.parcels[i].0.hash();
+        let transaction = |i: usize| reorganized.parcels[i].1;
+        assert_eq!(parcel_address(&fork[0], 0), chain.parcel_address(&parcel(0)));
+        assert_eq!(parcel_address(&fork[2], 0), chain.parcel_address(&parcel(1)));
+        assert_eq!(parcel_address(&fork[1], 0), chain.parcel_address(&parcel(2)));
+        assert_eq!(None, chain.parcel_address(&parcel(3)));
+
+        assert_eq!(transaction_address(&fork[0], 0), chain.transaction_address(&transaction(0)));
+        assert_eq!(transaction_address(&fork[2], 0), chain.transaction_address(&transaction(1)));
+        assert_eq!(transaction_address(&fork[1], 0), chain.transaction_address(&transaction(2)));
+        assert_eq!(None, chain.transaction_address(&transaction(3)));
+
+        let retracted: Vec<H256> = reorganized.retracted.iter().map(Header::hash).collect();
+        let enacted: Vec<H256> = fork.iter().map(Header::hash).collect();
+        assert_eq!(Ok(()), chain.verify_route_indexes(&retracted, &enacted));
+    }
+
+    #[test]
+    fn indexes_are_the_same_after_reopening() {
+        let reorganized = reorganized_chain();
+        let reopened = reorganized.reopen();
+
+        assert_eq!(Ok(()), reopened.verify_indexes(10));
+        for (parcel, transaction) in &reorganized.parcels {
+            assert_eq!(reorganized.chain.parcel_address(&parcel.hash()), reopened.parcel_address(&parcel.hash()));
+            assert_eq!(reorganized.chain.transaction_address(transaction), reopened.transaction_address(transaction));
+        }
+    }
+
+    #[test]
+    fn an_address_left_by_a_retracted_block_is_found() {
+        let reorganized = reorganized_chain();
+        let retracted = &reorganized.retracted;
+        let parcel = reorganized.parcels[3].0.hash();
+
+        let mut batch = DBTransaction::new();
+        batch.write(db::COL_EXTRA, &parcel, &parcel_address(&retracted[2], 0).unwrap());
+        reorganized.db.write(batch).unwrap();
+
+        assert_eq!(
+            Err(vec![IndexDivergence::ParcelAddress {
+                parcel,
+                expected: None,
+                found: parcel_address(&retracted[2], 0),
+            }]),
+            reorganized.chain.verify_indexes(10)
+        );
+
+        let retracted: Vec<H256> = retracted.iter().map(Header::hash).collect();
+        let enacted: Vec<H256> = reorganized.enacted.iter().map(Header::hash).collect();
+        assert_eq!(
+            Err(vec![IndexDivergence::ParcelAddress {
+                parcel,
+                expected: None,
+                found: parcel_address(&reorganized.retracted[2], 0),
+            }]),
+            reorganized.chain.verify_route_indexes(&retracted, &enacted)
+        );
+    }
+
+    #[test]
+    fn a_wrong_address_of_a_canonical_parcel_is_found() {
+        let reorganized = reorganized_chain();
+        let fork = &reorganized.enacted;
+        let transaction = reorganized.parcels[1].1;
+
+        let mut batch = DBTransaction::new();
+        batch.write(db::COL_EXTRA, &transaction, &transaction_address(&fork[3], 0).unwrap());
+        reorganized.db.write(batch).unwrap();
+        // The cache of the chain is not updated by the write above.
+        let reopened = reorganized.reopen();
+
+        assert_eq!(
+            Err(vec![IndexDivergence::TransactionAddress {
+                transaction,
+                expected: transaction_address(&fork[2], 0),
+                found: transaction_address(&fork[3], 0),
+            }]),
+            reopened.verify_indexes(10)
+        );
+    }
+}
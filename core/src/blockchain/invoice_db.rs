@@ -15,6 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::collections::HashMap;
+use std::mem;
 use std::sync::Arc;
 
 use ctypes::H256;
@@ -57,6 +58,11 @@ impl InvoiceDB {
         let mut invoice_cache = self.invoice_cache.write();
         batch.extend_with_cache(db::COL_EXTRA, &mut *invoice_cache, invoice_map, CacheUpdatePolicy::Remove);
     }
+
+    /// Approximate heap size used by the caches.
+    pub fn mem_used(&self) -> usize {
+        self.invoice_cache.read().len() * mem::size_of::<(H256, BlockInvoices)>()
+    }
 }
 
 /// Interface for querying invoices.
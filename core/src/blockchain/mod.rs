@@ -17,14 +17,18 @@
 mod block_info;
 mod blockchain;
 mod body_db;
+mod consistency;
 mod extras;
 mod headerchain;
+mod indexer;
 mod invoice_db;
 mod route;
 
 pub use self::blockchain::{BlockChain, BlockProvider};
 pub use self::body_db::BodyProvider;
+pub use self::consistency::{check_consistency, rewind_to_consistent_block, Inconsistency, Rewound};
 pub use self::extras::{BlockDetails, ParcelAddress, ParcelInvoice, TransactionAddress};
 pub use self::headerchain::HeaderProvider;
+pub use self::indexer::IndexDivergence;
 pub use self::invoice_db::InvoiceProvider;
 pub use self::route::ImportRoute;
@@ -22,39 +22,43 @@ use std::time::Instant;
 use cio::IoChannel;
 use cnetwork::NodeId;
 use ctypes::{Address, Bytes, H256, Public, U256};
+use hashdb::HashDB;
 use journaldb;
 use kvdb::{DBTransaction, KeyValueDB};
 use parking_lot::{Mutex, RwLock};
-use rlp::{Encodable, UntrustedRlp};
-use trie::{TrieFactory, TrieSpec};
+use rlp::{DecoderError, Encodable, UntrustedRlp};
+use trie::{TrieError, TrieFactory, TrieSpec};
 
 use super::super::block::{enact, ClosedBlock, Drain, IsBlock, LockedBlock, OpenBlock, SealedBlock};
 use super::super::blockchain::{
-    BlockChain, BlockProvider, BodyProvider, HeaderProvider, ImportRoute, InvoiceProvider, ParcelAddress,
-    ParcelInvoice, TransactionAddress,
+    check_consistency, rewind_to_consistent_block, BlockChain, BlockProvider, BodyProvider, HeaderProvider,
+    ImportRoute, IndexDivergence, InvoiceProvider, ParcelAddress, ParcelInvoice, TransactionAddress,
 };
 use super::super::consensus::epoch::Transition as EpochTransition;
 use super::super::consensus::CodeChainEngine;
+use super::super::decode_limits::DecodeLimits;
 use super::super::encoded;
-use super::super::error::{BlockImportError, Error, ImportError};
+use super::super::error::{BlockError, BlockImportError, Error, ImportError};
 use super::super::header::Header;
 use super::super::miner::{Miner, MinerService};
 use super::super::parcel::{LocalizedParcel, SignedParcel, UnverifiedParcel};
 use super::super::service::ClientIoMessage;
 use super::super::spec::Spec;
-use super::super::state::{TopLevelState, TopStateInfo};
+use super::super::state::{Account, TopLevelState, TopStateInfo};
 use super::super::state_db::StateDB;
 use super::super::types::{
-    BlockId, BlockNumber, BlockStatus, ParcelId, TransactionId, VerificationQueueInfo as BlockQueueInfo,
+    BlockId, BlockNumber, BlockStatus, MemoryInfo, ParcelId, TransactionId,
+    VerificationQueueInfo as BlockQueueInfo,
 };
 use super::super::verification::queue::{BlockQueue, HeaderQueue};
 use super::super::verification::{self, PreverifiedBlock, Verifier};
 use super::super::views::{BlockView, HeaderView};
+use super::recent_states::RecentStates;
 use super::{
-    AccountData, Balance, BlockChain as BlockChainTrait, BlockChainClient, BlockChainInfo, BlockInfo, BlockProducer,
-    ChainInfo, ChainNotify, ClientConfig, EngineClient, Error as ClientError, ImportBlock, ImportResult,
-    ImportSealedBlock, Invoice, MiningBlockChainClient, Nonce, ParcelInfo, PrepareOpenBlock, RegularKey, ReopenBlock,
-    StateOrBlock,
+    AccountData, Balance, BlockChain as BlockChainTrait, BlockChainClient, BlockChainInfo, BlockImportOutcome,
+    BlockInfo, BlockProducer, ChainInfo, ChainNotify, ClientConfig, DatabaseRecovery, EngineClient,
+    Error as ClientError, ImportBlock, ImportResult, ImportSealedBlock, Invoice, MiningBlockChainClient, Nonce,
+    ParcelInfo, PrepareOpenBlock, RegularKey, ReopenBlock, StateOrBlock,
 };
 
 const MAX_MEM_POOL_SIZE: usize = 4096;
@@ -71,6 +75,9 @@ pub struct Client {
 
     state_db: RwLock<StateDB>,
 
+    /// States of the recently committed blocks, for at-block queries.
+    recent_states: Mutex<RecentStates>,
+
     /// List of actors to be notified on certain chain events
     notify: RwLock<Vec<Weak<ChainNotify>>>,
 
@@ -106,11 +113,32 @@ impl Client {
             db.write(batch).map_err(ClientError::Database)?;
         }
 
+        {
+            let has_state = |root: &H256| state_db.journal_db().contains(root);
+            if let Err(inconsistency) = check_consistency(&*db, &has_state) {
+                cwarn!(CLIENT, "The database is inconsistent: {}", inconsistency);
+                if config.db_recovery == DatabaseRecovery::Refuse {
+                    return Err(ClientError::Inconsistent(inconsistency).into())
+                }
+                match rewind_to_consistent_block(&*db, &has_state) {
+                    Some(rewound) => cwarn!(
+                        CLIENT,
+                        "The best block is rewound to #{} {}, removing {} blocks",
+                        rewound.best_block_number,
+                        rewound.best_block_hash,
+                        rewound.removed
+                    ),
+                    None => return Err(ClientError::Inconsistent(inconsistency).into()),
+                }
+            }
+        }
+
         let gb = spec.genesis_block();
         let chain = Arc::new(BlockChain::new(&gb, db.clone()));
 
         let engine = spec.engine.clone();
 
+        miner.open_mem_pool_journal(db.clone());
         let importer = Importer::new(&config, engine.clone(), message_channel.clone(), miner)?;
 
         let client = Arc::new(Client {
@@ -119,6 +147,7 @@ impl Client {
             chain: RwLock::new(chain),
             db: RwLock::new(db),
             state_db: RwLock::new(state_db),
+            recent_states: Mutex::new(RecentStates::new(config.recent_states)),
             notify: RwLock::new(Vec::new()),
             queue_parcels: AtomicUsize::new(0),
             trie_factory,
@@ -160,9 +189,79 @@ impl Client {
         self.importer.import_verified_blocks(self)
     }
 
+    /// Moves the blocks and headers held for their future timestamps into the queues once they are due.
+    pub fn release_future_blocks(&self) -> usize {
+        self.importer.block_queue.release_future() + self.importer.header_queue.release_future()
+    }
+
+    /// Reseals the work the miner postponed, if it has waited long enough.
+    pub fn reseal_pending_work(&self) {
+        self.importer.miner.reseal_pending_work(self)
+    }
+
+    /// Compares the indexes of the `depth` most recent canonical blocks with the ones derived from their bodies.
+    pub fn verify_chain_indexes(&self, depth: BlockNumber) -> Result<(), Vec<IndexDivergence>> {
+        self.chain.read().verify_indexes(depth)
+    }
+
+    /// Imports an RLP encoded block and waits until the queue is done with it.
+    ///
+    /// Unlike `import_block`, bytes which can't be decoded are rejected before they reach the queue.
+    /// `Queued` is returned if the block is valid but can't be imported yet, e.g. its parent is queued.
+    pub fn import_block_rlp(&self, bytes: Bytes) -> Result<BlockImportOutcome, BlockImportError> {
+        use super::super::verification::queue::kind::blocks::Unverified;
+
+        let hash = {
+            let rlp = UntrustedRlp::new(&bytes);
+            self.decode_limits().check_block(&rlp).map_err(Error::from)?;
+            if rlp.item_count().map_err(Error::from)? != 2 {
+                return Err(Error::from(DecoderError::RlpIncorrectListLen).into())
+            }
+            let header: Header = rlp.val_at(0).map_err(Error::from)?;
+            rlp.list_at::<UnverifiedParcel>(1).map_err(Error::from)?;
+            header.hash()
+        };
+        if self.chain.read().is_known(&hash) {
+            return Ok(BlockImportOutcome::AlreadyInChain(hash))
+        }
+
+        let queue = &self.importer.block_queue;
+        match queue.import(Unverified::new(bytes)) {
+            Ok(_) | Err(Error::Import(ImportError::AlreadyQueued)) => {}
+            Err(err) => return Err(err.into()),
+        }
+        queue.flush();
+        while queue.queue_info().verified_queue_size > 0 {
+            self.import_verified_blocks();
+        }
+        // Wait for the import running on the IO thread, if any.
+        drop(self.importer.import_lock.lock());
+
+        if self.chain.read().is_known(&hash) {
+            return Ok(BlockImportOutcome::Imported(hash))
+        }
+        if let Some(err) = queue.take_rejection(&hash) {
+            return Err(err.into())
+        }
+        match queue.status(&hash) {
+            BlockStatus::Bad => Err(BlockImportError::Import(ImportError::KnownBad)),
+            _ => Ok(BlockImportOutcome::Queued(hash)),
+        }
+    }
+
+    /// Resolves `id` to the hash of a block in the database.
+    ///
+    /// A hash resolves to itself if the block is known, whether or not it is canonical.
+    /// Numbers, `Earliest` and `Latest` always resolve through the canonical chain.
     fn block_hash(chain: &BlockChain, id: BlockId) -> Option<H256> {
         match id {
-            BlockId::Hash(hash) => Some(hash),
+            BlockId::Hash(hash) => {
+                if chain.is_known(&hash) {
+                    Some(hash)
+                } else {
+                    None
+                }
+            }
             BlockId::Number(number) => chain.block_hash(number),
             BlockId::Earliest => chain.block_hash(0),
             BlockId::Latest => Some(chain.best_block_hash()),
@@ -193,8 +292,13 @@ impl Client {
     pub fn import_queued_parcels(&self, parcels: &[Bytes], peer_id: NodeId) -> usize {
         ctrace!(EXTERNAL_PARCEL, "Importing queued");
         self.queue_parcels.fetch_sub(parcels.len(), AtomicOrdering::SeqCst);
-        let parcels: Vec<UnverifiedParcel> =
-            parcels.iter().filter_map(|bytes| UntrustedRlp::new(bytes).as_val().ok()).collect();
+        let limits = self.decode_limits();
+        let parcels: Vec<UnverifiedParcel> = parcels
+            .iter()
+            .map(|bytes| UntrustedRlp::new(bytes))
+            .filter(|rlp| limits.check_parcel(rlp).is_ok())
+            .filter_map(|rlp| rlp.as_val().ok())
+            .collect();
         let hashes: Vec<_> = parcels.iter().map(|parcel| parcel.hash()).collect();
         self.notify(|notify| {
             notify.parcels_received(hashes.clone(), peer_id);
@@ -204,11 +308,13 @@ impl Client {
     }
 
     fn block_number_ref(&self, id: &BlockId) -> Option<BlockNumber> {
+        let chain = self.chain.read();
         match id {
-            BlockId::Number(number) => Some(number.clone()),
-            BlockId::Hash(hash) => self.chain.read().block_number(hash),
+            BlockId::Number(number) if *number <= chain.best_block_detail().number => Some(*number),
+            BlockId::Number(_) => None,
+            BlockId::Hash(hash) => chain.block_number(hash),
             BlockId::Earliest => Some(0),
-            BlockId::Latest => Some(self.chain.read().best_block_detail().number),
+            BlockId::Latest => Some(chain.best_block_detail().number),
         }
     }
 
@@ -234,17 +340,47 @@ impl Client {
             _ => {}
         }
 
-        self.block_header(id).and_then(|header| {
-            let db = self.state_db.read().clone();
+        let hash = Self::block_hash(&self.chain.read(), id)?;
+        let recent_root = self.recent_states.lock().root(&hash);
+        let root = match recent_root {
+            Some(root) => root,
+            None => self.block_header(BlockId::Hash(hash))?.state_root(),
+        };
+        let db = self.state_db.read().clone();
+        TopLevelState::from_existing(db, root, self.trie_factory.clone()).ok()
+    }
 
-            let root = header.state_root();
-            TopLevelState::from_existing(db, root, self.trie_factory.clone()).ok()
-        })
+    /// Applies `f` to the account at the given block.
+    /// The account is served from the recent states if the block is one of them.
+    fn account_with<F, U>(&self, address: &Address, id: BlockId, f: F) -> Option<U>
+    where
+        F: FnOnce(Option<&Account>) -> U, {
+        let hash = Self::block_hash(&self.chain.read(), id)?;
+        let recent = self.recent_states.lock().account(&hash, address, |root| {
+            let db = self.state_db.read().clone();
+            TopLevelState::from_existing(db, *root, self.trie_factory.clone())?.account(address)
+        });
+        match recent {
+            Some(account) => account.ok().map(|account| f(account.as_ref().as_ref())),
+            None => self.state_at(BlockId::Hash(hash))?.account(address).ok().map(|account| f(account.as_ref())),
+        }
     }
 
     pub fn database(&self) -> Arc<KeyValueDB> {
         Arc::clone(&self.db.read())
     }
+
+    /// Get a snapshot of the heap memory used by the caches and queues.
+    /// The node has no metrics endpoint, so the snapshot is served by `devel_getMemoryInfo` only.
+    pub fn memory_info(&self) -> MemoryInfo {
+        MemoryInfo {
+            mem_pool: self.importer.miner.mem_pool_mem_usage(),
+            state_cache: self.state_db.read().mem_used(),
+            chain_cache: self.chain.read().mem_used(),
+            block_queue: self.importer.block_queue.queue_info().mem_used,
+            header_queue: self.importer.header_queue.queue_info().mem_used,
+        }
+    }
 }
 
 impl ChainInfo for Client {
@@ -298,6 +434,7 @@ impl ImportBlock for Client {
         use super::super::verification::queue::kind::blocks::Unverified;
         use super::super::verification::queue::kind::BlockLike;
 
+        self.decode_limits().check_block(&UntrustedRlp::new(&bytes)).map_err(Error::from)?;
         let unverified = Unverified::new(bytes);
         {
             if self.chain.read().is_known(&unverified.hash()) {
@@ -308,6 +445,7 @@ impl ImportBlock for Client {
     }
 
     fn import_header(&self, bytes: Bytes) -> Result<H256, BlockImportError> {
+        self.decode_limits().check_header(&UntrustedRlp::new(&bytes)).map_err(Error::from)?;
         let unverified = ::encoded::Header::new(bytes).decode();
         {
             if self.chain.read().is_known_header(&unverified.hash()) {
@@ -316,6 +454,57 @@ impl ImportBlock for Client {
         }
         Ok(self.importer.header_queue.import(unverified)?)
     }
+
+    fn import_restored_block(&self, bytes: Bytes) -> Result<H256, BlockImportError> {
+        let header = {
+            let rlp = UntrustedRlp::new(&bytes);
+            self.decode_limits().check_block(&rlp).map_err(Error::from)?;
+            if rlp.item_count().map_err(Error::from)? != 2 {
+                return Err(Error::from(DecoderError::RlpIncorrectListLen).into())
+            }
+            rlp.list_at::<UnverifiedParcel>(1).map_err(Error::from)?;
+            rlp.val_at::<Header>(0).map_err(Error::from)?
+        };
+        let hash = header.hash();
+        let start = Instant::now();
+
+        let route = {
+            let _import_lock = self.importer.import_lock.lock();
+            let chain = self.chain.read();
+            if chain.is_known(&hash) {
+                return Err(BlockImportError::Import(ImportError::AlreadyInChain))
+            }
+            if chain.block_hash(header.number()) != Some(hash) {
+                return Err(BlockImportError::Import(ImportError::UnknownHeader))
+            }
+            let parent = chain.block_header(header.parent_hash()).expect("The parent of a known header exists");
+
+            let engine = &*self.engine;
+            verification::verify_block_basic(&header, &bytes, engine)?;
+            engine.verify_block_unordered(&header)?;
+            self.importer.verifier.verify_block_family(&bytes, &header, &parent, engine, None)?;
+            if !self.state_db.read().journal_db().contains(header.state_root()) {
+                return Err(Error::Trie(TrieError::InvalidStateRoot(*header.state_root())).into())
+            }
+
+            let mut batch = DBTransaction::new();
+            let route = chain.insert_restored_block(&mut batch, &bytes);
+            self.db.read().write_buffered(batch);
+            chain.commit();
+            route
+        };
+        cinfo!(CLIENT, "Restored block #{} ({}) is the best block", header.number(), hash);
+
+        self.importer.miner.chain_new_blocks(self, &[hash], &[], &route.enacted, &route.retracted);
+        self.notify(|notify| {
+            notify.new_blocks(vec![hash], vec![], route.enacted.clone(), route.retracted.clone(), vec![], {
+                let elapsed = start.elapsed();
+                elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64
+            });
+        });
+        self.db.read().flush().expect("DB flush failed.");
+        Ok(hash)
+    }
 }
 
 impl BlockChainTrait for Client {}
@@ -347,6 +536,14 @@ impl BlockChainClient for Client {
         self.importer.miner.ready_parcels()
     }
 
+    fn ready_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel> {
+        self.importer.miner.ready_parcels_with_limit(max_count, max_bytes)
+    }
+
+    fn ready_local_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel> {
+        self.importer.miner.ready_local_parcels_with_limit(max_count, max_bytes)
+    }
+
     fn block_number(&self, id: BlockId) -> Option<BlockNumber> {
         self.block_number_ref(&id)
     }
@@ -359,10 +556,12 @@ impl BlockChainClient for Client {
 
     fn block_status(&self, id: BlockId) -> BlockStatus {
         let chain = self.chain.read();
-        match Self::block_hash(&chain, id) {
-            Some(ref hash) if chain.is_known(hash) => BlockStatus::InChain,
-            Some(hash) => self.importer.block_queue.status(&hash).into(),
-            None => BlockStatus::Unknown,
+        match id {
+            BlockId::Hash(ref hash) if !chain.is_known(hash) => self.importer.block_queue.status(hash).into(),
+            _ => match Self::block_hash(&chain, id) {
+                Some(_) => BlockStatus::InChain,
+                None => BlockStatus::Unknown,
+            },
         }
     }
 
@@ -398,6 +597,10 @@ impl BlockChainClient for Client {
             })
         })
     }
+
+    fn decode_limits(&self) -> DecodeLimits {
+        DecodeLimits::from(self.engine.params())
+    }
 }
 
 pub struct Importer {
@@ -473,17 +676,21 @@ impl Importer {
                     invalid_blocks.insert(header.hash());
                     continue
                 }
-                if let Ok(closed_block) = self.check_and_close_block(&block, client) {
-                    if self.engine.is_proposal(&block.header) {
-                        self.block_queue.mark_as_good(&[header.hash()]);
-                    } else {
-                        imported_blocks.push(header.hash());
-
-                        let route = self.commit_block(closed_block, &header, &block.bytes, client);
-                        import_results.push(route);
+                match self.check_and_close_block(&block, client) {
+                    Ok(closed_block) => {
+                        if self.engine.is_proposal(&block.header) {
+                            self.block_queue.mark_as_good(&[header.hash()]);
+                        } else {
+                            imported_blocks.push(header.hash());
+
+                            let route = self.commit_block(closed_block, &header, &block.bytes, client);
+                            import_results.push(route);
+                        }
+                    }
+                    Err(err) => {
+                        invalid_blocks.insert(header.hash());
+                        self.block_queue.reject(header.hash(), err);
                     }
-                } else {
-                    invalid_blocks.insert(header.hash());
                 }
             }
 
@@ -582,11 +789,26 @@ impl Importer {
         let route = chain.insert_block(&mut batch, block_data, invoices.clone());
 
         let is_canon = route.enacted.last().map_or(false, |h| h == hash);
+        {
+            let mut recent_states = client.recent_states.lock();
+            recent_states.rewind(&route.retracted);
+            recent_states.push(*hash, *header.parent_hash(), number, *header.state_root(), state.local_accounts());
+        }
         state.sync_cache(&route.enacted, &route.retracted, is_canon);
         // Final commit to the DB
         client.db.read().write_buffered(batch);
         chain.commit();
 
+        if !route.retracted.is_empty() {
+            self.block_queue.retract_future(&route.retracted);
+        }
+
+        if cfg!(debug_assertions) && !route.retracted.is_empty() {
+            if let Err(divergences) = chain.verify_route_indexes(&route.retracted, &route.enacted) {
+                panic!("The indexes diverged after the reorganization to #{} ({}): {:?}", number, hash, divergences);
+            }
+        }
+
         self.check_epoch_end(&header, &chain, client);
 
         route
@@ -648,7 +870,7 @@ impl Importer {
         }
     }
 
-    fn check_and_close_block(&self, block: &PreverifiedBlock, client: &Client) -> Result<LockedBlock, ()> {
+    fn check_and_close_block(&self, block: &PreverifiedBlock, client: &Client) -> Result<LockedBlock, Error> {
         let engine = &*self.engine;
         let header = &block.header;
 
@@ -665,7 +887,7 @@ impl Importer {
                     header.hash(),
                     header.parent_hash()
                 );
-                return Err(())
+                return Err(BlockError::UnknownParent(*header.parent_hash()).into())
             }
         };
 
@@ -691,7 +913,7 @@ impl Importer {
                 header.hash(),
                 e
             );
-            return Err(())
+            return Err(e)
         };
 
         let verify_external_result = self.verifier.verify_block_external(header, engine);
@@ -703,7 +925,7 @@ impl Importer {
                 header.hash(),
                 e
             );
-            return Err(())
+            return Err(e)
         };
 
         // Enact Verified Block
@@ -714,6 +936,7 @@ impl Importer {
             enact(&block.header, &block.parcels, engine, db, &parent, client.trie_factory.clone(), is_epoch_begin);
         let locked_block = enact_result.map_err(|e| {
             cwarn!(CLIENT, "Block import failed for #{} ({})\nError: {:?}", header.number(), header.hash(), e);
+            e
         })?;
 
         // Final Verification
@@ -725,7 +948,7 @@ impl Importer {
                 header.hash(),
                 e
             );
-            return Err(())
+            return Err(e)
         }
 
         Ok(locked_block)
@@ -836,7 +1059,7 @@ impl AccountData for Client {}
 
 impl Nonce for Client {
     fn nonce(&self, address: &Address, id: BlockId) -> Option<U256> {
-        self.state_at(id).and_then(|s| s.nonce(address).ok())
+        self.account_with(address, id, |account| account.map_or_else(U256::zero, |account| *account.nonce()))
     }
 }
 
@@ -844,7 +1067,9 @@ impl Balance for Client {
     fn balance(&self, address: &Address, state: StateOrBlock) -> Option<U256> {
         match state {
             StateOrBlock::State(s) => s.balance(address).ok(),
-            StateOrBlock::Block(id) => self.state_at(id).and_then(|s| s.balance(address).ok()),
+            StateOrBlock::Block(id) => {
+                self.account_with(address, id, |account| account.map_or_else(U256::zero, |account| *account.balance()))
+            }
         }
     }
 }
@@ -853,7 +1078,7 @@ impl RegularKey for Client {
     fn regular_key(&self, address: &Address, state: StateOrBlock) -> Option<Public> {
         match state {
             StateOrBlock::State(s) => s.regular_key(address).ok()?,
-            StateOrBlock::Block(id) => self.state_at(id)?.regular_key(address).ok()?,
+            StateOrBlock::Block(id) => self.account_with(address, id, |account| account?.regular_key())?,
         }
     }
 }
@@ -918,3 +1143,167 @@ impl ImportSealedBlock for Client {
 }
 
 impl MiningBlockChainClient for Client {}
+
+#[cfg(test)]
+mod tests {
+    use rlp::RlpStream;
+
+    use super::super::super::spec::Spec;
+    use super::super::super::tests::helpers::{generate_dummy_client, generate_dummy_client_with_db, new_db};
+    use super::*;
+
+    fn seal_block(client: &Client) {
+        let parent = client.best_block_header();
+        let block = client
+            .prepare_open_block(Address::default(), vec![])
+            .close(parent.parcels_root(), parent.invoices_root());
+        let sealed = block.lock().seal(client.engine(), vec![]).unwrap();
+        client.import_sealed_block(sealed).unwrap();
+    }
+
+    #[test]
+    fn client_starts_on_in_memory_db() {
+        let spec = Spec::new_test();
+        let client = generate_dummy_client(&spec);
+
+        let genesis_hash = spec.genesis_header().hash();
+        assert_eq!(0, client.chain_info().best_block_number);
+        assert_eq!(genesis_hash, client.best_block_header().hash());
+        assert_eq!(Some(genesis_hash), client.block_header(BlockId::Number(0)).map(|h| h.hash()));
+        assert!(client.state_at(BlockId::Latest).is_some());
+    }
+
+    #[test]
+    fn client_reopens_in_memory_db() {
+        let spec = Spec::new_test();
+        let db = new_db();
+        let genesis_hash = {
+            let client = generate_dummy_client_with_db(&spec, Arc::clone(&db));
+            client.best_block_header().hash()
+        };
+
+        let client = generate_dummy_client_with_db(&spec, db);
+        assert_eq!(genesis_hash, client.best_block_header().hash());
+        assert!(client.state_at(BlockId::Latest).is_some());
+    }
+
+    #[test]
+    fn block_ids_resolve_against_the_chain() {
+        let spec = Spec::new_test();
+        let client = generate_dummy_client(&spec);
+        let genesis_hash = spec.genesis_header().hash();
+        let unknown = BlockId::Hash(H256::from(42));
+
+        for id in &[BlockId::Earliest, BlockId::Latest, BlockId::Number(0), BlockId::Hash(genesis_hash)] {
+            assert_eq!(Some(genesis_hash), client.block_hash(*id));
+            assert_eq!(Some(0), client.block_number(*id));
+            assert_eq!(BlockStatus::InChain, client.block_status(*id));
+            assert!(client.block_body(*id).is_some());
+            assert!(client.state_at(*id).is_some());
+        }
+
+        for id in &[BlockId::Number(1), unknown] {
+            assert_eq!(None, client.block_hash(*id));
+            assert_eq!(None, client.block_number(*id));
+            assert_eq!(BlockStatus::Unknown, client.block_status(*id));
+            assert!(client.block_header(*id).is_none());
+            assert!(client.block_total_score(*id).is_none());
+            assert!(client.state_at(*id).is_none());
+        }
+    }
+
+    #[test]
+    fn blocks_exported_from_a_client_are_imported_into_another() {
+        let spec = Spec::new_test();
+        let source = generate_dummy_client(&spec);
+        for _ in 0..5 {
+            seal_block(&source);
+        }
+        assert_eq!(5, source.chain_info().best_block_number);
+
+        let target = generate_dummy_client(&spec);
+        for number in 1..6 {
+            let block = source.block(BlockId::Number(number)).unwrap();
+            let hash = block.hash();
+            assert_eq!(BlockImportOutcome::Imported(hash), target.import_block_rlp(block.into_inner()).unwrap());
+        }
+        assert_eq!(source.chain_info().best_block_hash, target.chain_info().best_block_hash);
+
+        let block = source.block(BlockId::Number(3)).unwrap();
+        let hash = block.hash();
+        assert_eq!(BlockImportOutcome::AlreadyInChain(hash), target.import_block_rlp(block.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn blocks_are_imported_on_top_of_a_restored_block() {
+        let spec = Spec::new_test();
+        let source = generate_dummy_client(&spec);
+        for _ in 0..5 {
+            seal_block(&source);
+        }
+
+        let db = new_db();
+        let target = generate_dummy_client_with_db(&spec, Arc::clone(&db));
+        for number in 1..4 {
+            let header = source.block_header(BlockId::Number(number)).unwrap();
+            target.import_header(header.into_inner()).unwrap();
+        }
+        target.importer.header_queue.flush();
+        target.import_verified_headers();
+
+        let block = |number| source.block(BlockId::Number(number)).unwrap().into_inner();
+        match target.import_restored_block(block(4)) {
+            Err(BlockImportError::Import(ImportError::UnknownHeader)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        let mut batch = DBTransaction::new();
+        for (key, value) in source.database().iter(::db::COL_STATE) {
+            batch.put(::db::COL_STATE, &key, &value);
+        }
+        db.write(batch).unwrap();
+
+        let restored = source.block_hash(BlockId::Number(3)).unwrap();
+        assert_eq!(restored, target.import_restored_block(block(3)).unwrap());
+        assert_eq!(restored, target.chain_info().best_block_hash);
+        assert!(target.block_body(BlockId::Number(2)).is_none());
+        assert!(target.state_at(BlockId::Latest).is_some());
+
+        for number in 4..6 {
+            let hash = source.block_hash(BlockId::Number(number)).unwrap();
+            assert_eq!(BlockImportOutcome::Imported(hash), target.import_block_rlp(block(number)).unwrap());
+        }
+        assert_eq!(source.chain_info().best_block_hash, target.chain_info().best_block_hash);
+    }
+
+    #[test]
+    fn undecodable_blocks_do_not_poison_the_queue() {
+        let spec = Spec::new_test();
+        let source = generate_dummy_client(&spec);
+        seal_block(&source);
+        let block = source.block(BlockId::Number(1)).unwrap();
+        let hash = block.hash();
+
+        let broken = {
+            let mut stream = RlpStream::new_list(2);
+            stream.append_raw(&block.header().into_inner(), 1);
+            stream.begin_list(1).append(&1u8);
+            stream.out()
+        };
+
+        let target = generate_dummy_client(&spec);
+        match target.import_block_rlp(vec![0x01, 0x02]) {
+            Err(BlockImportError::Other(_)) => {}
+            result => panic!("Garbage must not be imported: {:?}", result),
+        }
+        match target.import_block_rlp(broken.clone()) {
+            Err(BlockImportError::Other(_)) => {}
+            result => panic!("A broken body must not be imported: {:?}", result),
+        }
+        assert!(target.import_block(broken).is_err());
+        assert_eq!(BlockStatus::Unknown, target.block_status(BlockId::Hash(hash)));
+
+        assert_eq!(BlockImportOutcome::Imported(hash), target.import_block_rlp(block.into_inner()).unwrap());
+        assert_eq!(source.chain_info().best_block_hash, target.chain_info().best_block_hash);
+    }
+}
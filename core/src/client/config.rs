@@ -14,10 +14,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
 use std::path::Path;
 use std::str::FromStr;
 
-use kvdb_rocksdb::CompactionProfile;
+use kvdb_rocksdb::{ColumnConfig, CompactionProfile};
 
 use super::super::verification::{QueueConfig, VerifierType};
 
@@ -62,6 +63,52 @@ impl FromStr for DatabaseCompactionProfile {
     }
 }
 
+/// RocksDB options of a column overriding the database-wide ones
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct DatabaseColumnConfig {
+    /// Cache size of the column in MiB
+    pub cache_size: Option<usize>,
+    /// Compaction profile of the column
+    pub compaction: Option<DatabaseCompactionProfile>,
+}
+
+impl DatabaseColumnConfig {
+    /// Returns corresponding column configuration.
+    pub fn column_config(&self, db_path: &Path) -> ColumnConfig {
+        ColumnConfig {
+            memory_budget: self.cache_size,
+            compaction: self.compaction.as_ref().map(|compaction| compaction.compaction_profile(db_path)),
+        }
+    }
+}
+
+/// What to do when the stored chain is found inconsistent at startup
+#[derive(Debug, PartialEq, Clone)]
+pub enum DatabaseRecovery {
+    /// Rewind the best block to the most recent consistent ancestor
+    Rewind,
+    /// Refuse to start, reporting the inconsistency
+    Refuse,
+}
+
+impl Default for DatabaseRecovery {
+    fn default() -> Self {
+        DatabaseRecovery::Rewind
+    }
+}
+
+impl FromStr for DatabaseRecovery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rewind" => Ok(DatabaseRecovery::Rewind),
+            "refuse" => Ok(DatabaseRecovery::Refuse),
+            _ => Err("Invalid database recovery given. Expected rewind/refuse.".into()),
+        }
+    }
+}
+
 /// Client configuration. Includes configs for all sub-systems.
 #[derive(Debug, PartialEq)]
 pub struct ClientConfig {
@@ -75,8 +122,14 @@ pub struct ClientConfig {
     pub db_compaction: DatabaseCompactionProfile,
     /// Should db have WAL enabled?
     pub db_wal: bool,
+    /// What to do with an inconsistent database
+    pub db_recovery: DatabaseRecovery,
+    /// RocksDB options overriding the above ones for specific columns
+    pub db_column_configs: HashMap<u32, DatabaseColumnConfig>,
     /// State db cache-size.
     pub state_cache_size: usize,
+    /// The number of recent blocks whose states are kept for at-block queries.
+    pub recent_states: usize,
     /// Type of block verifier used by client.
     pub verifier_type: VerifierType,
 }
@@ -85,13 +138,17 @@ impl Default for ClientConfig {
     fn default() -> Self {
         let mb = 1024 * 1024;
         const DEFAULT_STATE_CACHE_SIZE: u32 = 25;
+        const DEFAULT_RECENT_STATES: usize = 8;
         Self {
             queue: Default::default(),
             fat_db: false,
             db_cache_size: Default::default(),
             db_compaction: Default::default(),
             db_wal: true,
+            db_recovery: Default::default(),
+            db_column_configs: HashMap::new(),
             state_cache_size: DEFAULT_STATE_CACHE_SIZE as usize * mb,
+            recent_states: DEFAULT_RECENT_STATES,
             verifier_type: Default::default(),
         }
     }
@@ -15,9 +15,12 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use kvdb;
+use std::error;
 use std::fmt::{Display, Error as FmtError, Formatter};
 use util_error::UtilError;
 
+use super::super::blockchain::Inconsistency;
+
 /// Client configuration errors.
 #[derive(Debug)]
 pub enum Error {
@@ -25,6 +28,8 @@ pub enum Error {
     Database(kvdb::Error),
     /// Util error
     Util(UtilError),
+    /// The stored chain can't be read back
+    Inconsistent(Inconsistency),
 }
 
 impl From<UtilError> for Error {
@@ -47,6 +52,21 @@ impl Display for Error {
         match self {
             Error::Util(err) => write!(f, "{}", err),
             Error::Database(s) => write!(f, "Database error: {}", s),
+            Error::Inconsistent(inconsistency) => write!(f, "The database is inconsistent: {}", inconsistency),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Client configuration error"
+    }
+
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self {
+            Error::Database(err) => Some(err),
+            Error::Util(err) => Some(err),
+            Error::Inconsistent(_) => None,
         }
     }
 }
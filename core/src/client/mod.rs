@@ -18,14 +18,15 @@ mod chain_notify;
 mod client;
 mod config;
 mod error;
+mod recent_states;
 mod test_client;
 
 pub use self::chain_notify::ChainNotify;
 
 pub use self::client::Client;
-pub use self::config::ClientConfig;
+pub use self::config::{ClientConfig, DatabaseColumnConfig, DatabaseCompactionProfile, DatabaseRecovery};
 pub use self::error::Error;
-pub use self::test_client::TestBlockChainClient;
+pub use self::test_client::{TestBlock, TestBlockChainClient, TestClientCall, TestClientFailure};
 
 use cnetwork::NodeId;
 use ctypes::{Address, Bytes, H256, Public, U256};
@@ -33,6 +34,7 @@ use ctypes::{Address, Bytes, H256, Public, U256};
 use super::block::{ClosedBlock, OpenBlock, SealedBlock};
 use super::blockchain::ParcelInvoice;
 use super::blockchain_info::BlockChainInfo;
+use super::decode_limits::DecodeLimits;
 use super::encoded;
 use super::error::BlockImportError;
 use super::parcel::{LocalizedParcel, SignedParcel};
@@ -141,6 +143,17 @@ pub trait RegularKey {
 /// Provides methods to access account info
 pub trait AccountData: Nonce + Balance {}
 
+/// The result of a block import which waits for the verification.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockImportOutcome {
+    /// The block is verified and inserted into the chain.
+    Imported(H256),
+    /// The block is in the chain already.
+    AlreadyInChain(H256),
+    /// The block is verified but not inserted yet.
+    Queued(H256),
+}
+
 /// Provides methods to import block into blockchain
 pub trait ImportBlock {
     /// Import a block into the blockchain.
@@ -148,6 +161,13 @@ pub trait ImportBlock {
 
     /// Import a header into the blockchain
     fn import_header(&self, bytes: Bytes) -> Result<H256, BlockImportError>;
+
+    /// Makes the block whose state is restored from a snapshot the best block without executing it.
+    ///
+    /// The header of the block must be in the canonical header chain and its state must be in the state DB.
+    /// The blocks are imported on top of it as usual, while its ancestors are left without bodies.
+    /// It is meant for a node which has imported no blocks yet.
+    fn import_restored_block(&self, bytes: Bytes) -> Result<H256, BlockImportError>;
 }
 
 /// Provides various blockchain information, like block header, chain state etc.
@@ -164,6 +184,13 @@ pub trait BlockChainClient: Sync + Send + AccountData + BlockChain + ImportBlock
     /// List all parcels that are allowed into the next block.
     fn ready_parcels(&self) -> Vec<SignedParcel>;
 
+    /// List the parcels with the highest priority that are allowed into the next block,
+    /// at most `max_count` of them and `max_bytes` in total.
+    fn ready_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel>;
+
+    /// Same as `ready_parcels_with_limit`, but only the parcels which originated from this node are listed.
+    fn ready_local_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel>;
+
     /// Look up the block number for the given block ID.
     fn block_number(&self, id: BlockId) -> Option<BlockNumber>;
 
@@ -187,6 +214,9 @@ pub trait BlockChainClient: Sync + Send + AccountData + BlockChain + ImportBlock
     fn parcel_invoice(&self, id: ParcelId) -> Option<ParcelInvoice>;
 
     fn transaction_invoice(&self, id: TransactionId) -> Option<Invoice>;
+
+    /// Get the bounds that untrusted blocks, headers and parcels must satisfy before being decoded.
+    fn decode_limits(&self) -> DecodeLimits;
 }
 
 /// Result of import block operation.
@@ -0,0 +1,256 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use ctypes::{Address, H256};
+use trie::Result as TrieResult;
+
+use super::super::state::Account;
+use super::super::types::BlockNumber;
+
+/// The accounts known at a recently committed block.
+struct RecentState {
+    parent: H256,
+    number: BlockNumber,
+    root: H256,
+    /// Accounts changed by the block itself.
+    modified: HashSet<Address>,
+    /// Accounts as they are after the block. `None` if the account doesn't exist.
+    accounts: HashMap<Address, Arc<Option<Account>>>,
+}
+
+/// The states of the most recently committed blocks, keyed by block hash.
+///
+/// An account which is not modified by a block is shared with the state of
+/// its parent, so looking up an account at any block in the window reads the
+/// trie at most once.
+pub struct RecentStates {
+    /// The number of block heights kept.
+    capacity: usize,
+    states: HashMap<H256, RecentState>,
+}
+
+impl RecentStates {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            states: HashMap::new(),
+        }
+    }
+
+    /// Records the state of a committed block.
+    ///
+    /// `accounts` are the accounts loaded while the block was enacted, with
+    /// a flag which is set if the block modified the account.
+    pub fn push(
+        &mut self,
+        hash: H256,
+        parent: H256,
+        number: BlockNumber,
+        root: H256,
+        accounts: Vec<(Address, Option<Account>, bool)>,
+    ) {
+        if self.capacity == 0 {
+            return
+        }
+
+        let mut modified = HashSet::new();
+        let accounts = accounts
+            .into_iter()
+            .map(|(address, account, is_modified)| {
+                if is_modified {
+                    modified.insert(address);
+                }
+                (address, Arc::new(account))
+            })
+            .collect();
+        self.states.insert(
+            hash,
+            RecentState {
+                parent,
+                number,
+                root,
+                modified,
+                accounts,
+            },
+        );
+
+        let capacity = self.capacity as BlockNumber;
+        self.states.retain(|_, state| state.number + capacity > number);
+    }
+
+    /// Drops the states of the blocks retracted by a reorganization.
+    pub fn rewind(&mut self, retracted: &[H256]) {
+        for hash in retracted {
+            self.states.remove(hash);
+        }
+    }
+
+    /// Returns the state root of the block if it is one of the recent states.
+    pub fn root(&self, hash: &H256) -> Option<H256> {
+        self.states.get(hash).map(|state| state.root)
+    }
+
+    /// Returns the account at the block `hash`, or `None` if the block is not one of the recent states.
+    ///
+    /// The account is shared from the nearest ancestor that knows it; `read` is
+    /// called with the state root of the block only when none of them does.
+    pub fn account<F>(&mut self, hash: &H256, address: &Address, read: F) -> Option<TrieResult<Arc<Option<Account>>>>
+    where
+        F: FnOnce(&H256) -> TrieResult<Option<Account>>, {
+        let root = self.root(hash)?;
+
+        let known = {
+            let mut current = self.states.get(hash);
+            let mut found = None;
+            while let Some(state) = current {
+                if let Some(account) = state.accounts.get(address) {
+                    found = Some(Arc::clone(account));
+                    break
+                }
+                debug_assert!(!state.modified.contains(address), "Modified accounts are always kept");
+                current = self.states.get(&state.parent);
+            }
+            found
+        };
+
+        let account = match known {
+            Some(account) => account,
+            None => match read(&root) {
+                Ok(account) => Arc::new(account),
+                Err(err) => return Some(Err(err)),
+            },
+        };
+        let state = self.states.get_mut(hash).expect("The root of the state is found above");
+        state.accounts.insert(*address, Arc::clone(&account));
+        Some(Ok(account))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use ctypes::U256;
+
+    use super::*;
+
+    fn hash(number: BlockNumber) -> H256 {
+        H256::from(number + 1)
+    }
+
+    fn fork_hash(number: BlockNumber) -> H256 {
+        H256::from(number + 1_000)
+    }
+
+    fn balance(states: &mut RecentStates, hash: &H256, address: &Address, reads: &Cell<usize>) -> U256 {
+        let account = states
+            .account(hash, address, |_| {
+                reads.set(reads.get() + 1);
+                Ok(Some(Account::new(100.into(), 0.into())))
+            })
+            .expect("The block is one of the recent states")
+            .unwrap();
+        account.as_ref().as_ref().map_or_else(U256::zero, |account| *account.balance())
+    }
+
+    #[test]
+    fn unchanged_accounts_are_read_once() {
+        let address = Address::random();
+        let mut states = RecentStates::new(8);
+        for number in 1..11 {
+            states.push(hash(number), hash(number - 1), number, H256::random(), vec![]);
+        }
+
+        let reads = Cell::new(0);
+        for number in 3..11 {
+            assert_eq!(U256::from(100), balance(&mut states, &hash(number), &address, &reads));
+        }
+        let reads_to_populate = reads.get();
+        assert_eq!(1, reads_to_populate);
+
+        for _ in 0..3 {
+            for number in 3..11 {
+                assert_eq!(U256::from(100), balance(&mut states, &hash(number), &address, &reads));
+            }
+        }
+        assert_eq!(reads_to_populate, reads.get(), "Queries after the population must not read the trie");
+
+        assert!(states.account(&hash(2), &address, |_| unreachable!()).is_none());
+    }
+
+    #[test]
+    fn modified_accounts_are_served_at_their_blocks() {
+        let address = Address::random();
+        let mut states = RecentStates::new(8);
+        for number in 1..9 {
+            let accounts = vec![(address, Some(Account::new((number * 10).into(), 0.into())), true)];
+            states.push(hash(number), hash(number - 1), number, H256::random(), accounts);
+        }
+
+        let reads = Cell::new(0);
+        for number in 1..9 {
+            assert_eq!(U256::from(number * 10), balance(&mut states, &hash(number), &address, &reads));
+        }
+        assert_eq!(0, reads.get());
+    }
+
+    #[test]
+    fn results_stay_correct_across_a_reorg() {
+        let address = Address::random();
+        let mut states = RecentStates::new(8);
+        for number in 1..9 {
+            states.push(hash(number), hash(number - 1), number, H256::random(), vec![]);
+        }
+
+        let reads = Cell::new(0);
+        for number in 1..9 {
+            assert_eq!(U256::from(100), balance(&mut states, &hash(number), &address, &reads));
+        }
+        assert_eq!(1, reads.get());
+
+        // Blocks 7 and 8 are replaced by a fork which changes the account at block 7.
+        let fork = vec![(address, Some(Account::new(7.into(), 0.into())), true)];
+        states.push(fork_hash(7), hash(6), 7, H256::random(), fork);
+        states.push(fork_hash(8), fork_hash(7), 8, H256::random(), vec![]);
+        states.rewind(&[hash(7), hash(8)]);
+
+        assert!(states.root(&hash(7)).is_none());
+        assert!(states.root(&hash(8)).is_none());
+        for number in 1..7 {
+            assert_eq!(U256::from(100), balance(&mut states, &hash(number), &address, &reads));
+        }
+        assert_eq!(U256::from(7), balance(&mut states, &fork_hash(7), &address, &reads));
+        assert_eq!(U256::from(7), balance(&mut states, &fork_hash(8), &address, &reads));
+        assert_eq!(1, reads.get());
+    }
+
+    #[test]
+    fn old_states_are_dropped() {
+        let mut states = RecentStates::new(8);
+        for number in 1..20 {
+            states.push(hash(number), hash(number - 1), number, H256::random(), vec![]);
+        }
+        for number in 1..12 {
+            assert!(states.root(&hash(number)).is_none());
+        }
+        for number in 12..20 {
+            assert!(states.root(&hash(number)).is_some());
+        }
+    }
+}
@@ -48,14 +48,16 @@ use trie;
 use super::super::block::{ClosedBlock, OpenBlock, SealedBlock};
 use super::super::blockchain::ParcelInvoice;
 use super::super::blockchain_info::BlockChainInfo;
+use super::super::client::Error as ClientError;
 use super::super::client::ImportResult;
 use super::super::client::{
     AccountData, Balance, BlockChain, BlockChainClient, BlockInfo, BlockProducer, BlockStatus, ChainInfo, ImportBlock,
     ImportSealedBlock, Invoice, MiningBlockChainClient, Nonce, ParcelInfo, PrepareOpenBlock, ReopenBlock, StateOrBlock,
 };
 use super::super::db::{COL_STATE, NUM_COLUMNS};
+use super::super::decode_limits::DecodeLimits;
 use super::super::encoded;
-use super::super::error::BlockImportError;
+use super::super::error::{BlockImportError, Error, ImportError};
 use super::super::header::Header as BlockHeader;
 use super::super::miner::{Miner, MinerService, ParcelImportResult};
 use super::super::parcel::{Action, LocalizedParcel, Parcel, SignedParcel};
@@ -66,6 +68,60 @@ use super::super::state::{
 use super::super::state_db::StateDB;
 use super::super::types::{BlockId, BlockNumber, ParcelId, TransactionId, VerificationQueueInfo as QueueInfo};
 
+/// A call to the test client which can be made to fail.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TestClientCall {
+    Nonce,
+    Balance,
+    ImportBlock,
+    BlockHeader,
+    BlockBody,
+    BlockStatus,
+}
+
+/// A failure injected into a call to the test client.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TestClientFailure {
+    /// The states of the past blocks are pruned. Only the latest state is served.
+    StatePruned,
+    /// The database fails. Queries answer nothing and imports are rejected.
+    DatabaseError,
+}
+
+/// A block to be generated by the test client.
+#[derive(Clone, Debug, Default)]
+pub struct TestBlock {
+    parcels: usize,
+    author: Address,
+    score: Option<U256>,
+    extra_data: Option<Bytes>,
+}
+
+impl TestBlock {
+    /// The number of parcels in the block. Each parcel is signed by a new account.
+    pub fn parcels(mut self, count: usize) -> Self {
+        self.parcels = count;
+        self
+    }
+
+    pub fn author(mut self, author: Address) -> Self {
+        self.author = author;
+        self
+    }
+
+    /// The score of the block. The block number is used if it is not set.
+    pub fn score(mut self, score: U256) -> Self {
+        self.score = Some(score);
+        self
+    }
+
+    /// The extra data of the block. The extra data of the client is used if it is not set.
+    pub fn extra_data(mut self, extra_data: Bytes) -> Self {
+        self.extra_data = Some(extra_data);
+        self
+    }
+}
+
 /// Test client.
 pub struct TestBlockChainClient {
     /// Blocks.
@@ -80,12 +136,16 @@ pub struct TestBlockChainClient {
     pub last_parcels_root: RwLock<H256>,
     /// Extra data do set for each block
     pub extra_data: Bytes,
-    /// Score.
+    /// Total score of the best block.
     pub score: RwLock<U256>,
+    /// Total scores of every block.
+    pub total_scores: RwLock<HashMap<H256, U256>>,
     /// Balances.
     pub balances: RwLock<HashMap<Address, U256>>,
     /// Nonces.
     pub nonces: RwLock<HashMap<Address, U256>>,
+    /// The number of the latest nonces and balances looked up.
+    pub account_lookups: AtomicUsize,
     /// Storage.
     pub storage: RwLock<HashMap<(Address, H256), H256>>,
     /// Block queue size.
@@ -98,6 +158,8 @@ pub struct TestBlockChainClient {
     pub latest_block_timestamp: RwLock<u64>,
     /// Pruning history size to report.
     pub history: RwLock<Option<u64>>,
+    /// Failures injected into the calls.
+    pub failures: RwLock<HashMap<TestClientCall, TestClientFailure>>,
 }
 
 impl Default for TestBlockChainClient {
@@ -139,18 +201,22 @@ impl TestBlockChainClient {
             last_hash: RwLock::new(genesis_hash),
             last_parcels_root: RwLock::new(genesis_parcels_root),
             score: RwLock::new(genesis_score),
+            total_scores: RwLock::new(HashMap::new()),
             balances: RwLock::new(HashMap::new()),
             nonces: RwLock::new(HashMap::new()),
+            account_lookups: AtomicUsize::new(0),
             storage: RwLock::new(HashMap::new()),
             queue_size: AtomicUsize::new(0),
             miner: Arc::new(Miner::with_spec(&spec)),
             spec,
             latest_block_timestamp: RwLock::new(10_000_000),
             history: RwLock::new(None),
+            failures: RwLock::new(HashMap::new()),
         };
 
         // insert genesis hash.
         client.blocks.get_mut().insert(genesis_hash, genesis_block);
+        client.total_scores.get_mut().insert(genesis_hash, genesis_score);
         client.numbers.get_mut().insert(0, genesis_hash);
         client
     }
@@ -182,37 +248,85 @@ impl TestBlockChainClient {
 
     /// Add blocks to test client.
     pub fn add_blocks(&self, count: usize, parcel_length: usize) {
-        let len = self.numbers.read().len();
-        for n in len..(len + count) {
-            let mut header = BlockHeader::new();
-            header.set_score(From::from(n));
-            header.set_parent_hash(self.last_hash.read().clone());
-            header.set_number(n as BlockNumber);
-            header.set_extra_data(self.extra_data.clone());
-            let mut parcels = Vec::new();
-            for _ in 0..parcel_length {
-                let keypair = Random.generate().unwrap();
-                // Update nonces value
-                self.nonces.write().insert(keypair.address(), U256::zero());
-                let parcel = Parcel {
-                    nonce: U256::zero(),
-                    fee: U256::from(10),
-                    network_id: 0u64,
-                    action: Action::ChangeShardState {
-                        transactions: vec![],
-                    },
-                };
-                let signed_parcel = parcel.sign(keypair.private());
-                parcels.push(signed_parcel);
-            }
-            header.set_parcels_root(skewed_merkle_root(
-                self.last_parcels_root.read().clone(),
-                parcels.iter().map(Encodable::rlp_bytes),
-            ));
-            let mut rlp = RlpStream::new_list(2);
-            rlp.append(&header);
-            rlp.append_list(&parcels);
-            self.import_block(rlp.as_raw().to_vec()).unwrap();
+        for _ in 0..count {
+            self.add_block_on(BlockId::Latest, TestBlock::default().parcels(parcel_length));
+        }
+    }
+
+    /// Adds a chain of `blocks` on top of `parent`, which can be any known block to make a fork.
+    /// Returns the hashes of the added blocks.
+    pub fn add_chain(&self, parent: BlockId, blocks: Vec<TestBlock>) -> Vec<H256> {
+        let mut parent = parent;
+        blocks
+            .into_iter()
+            .map(|block| {
+                let hash = self.add_block_on(parent, block);
+                parent = BlockId::Hash(hash);
+                hash
+            })
+            .collect()
+    }
+
+    /// Adds a block on top of `parent` and returns its hash.
+    /// The block becomes the best block if its total score is the highest.
+    pub fn add_block_on(&self, parent: BlockId, block: TestBlock) -> H256 {
+        let parent: BlockHeader = self.block_header(parent).expect("The parent block must exist").decode();
+        let number = parent.number() + 1;
+
+        let mut header = BlockHeader::new();
+        header.set_score(block.score.unwrap_or_else(|| U256::from(number)));
+        header.set_parent_hash(parent.hash());
+        header.set_number(number);
+        header.set_author(block.author);
+        header.set_extra_data(block.extra_data.unwrap_or_else(|| self.extra_data.clone()));
+        // The parcels are not executed, so the state stays the same as the genesis state.
+        header.set_state_root(self.spec.state_root());
+        let mut parcels = Vec::new();
+        for _ in 0..block.parcels {
+            let keypair = Random.generate().unwrap();
+            // Update nonces value
+            self.nonces.write().insert(keypair.address(), U256::zero());
+            let parcel = Parcel {
+                nonce: U256::zero(),
+                fee: U256::from(10),
+                network_id: 0u64,
+                action: Action::ChangeShardState {
+                    transactions: vec![],
+                },
+            };
+            let signed_parcel = parcel.sign(keypair.private());
+            parcels.push(signed_parcel);
+        }
+        header.set_parcels_root(skewed_merkle_root(*parent.parcels_root(), parcels.iter().map(Encodable::rlp_bytes)));
+        let mut rlp = RlpStream::new_list(2);
+        rlp.append(&header);
+        rlp.append_list(&parcels);
+        self.import_block(rlp.as_raw().to_vec()).unwrap()
+    }
+
+    /// Makes `call` fail with `failure` until it is recovered.
+    pub fn fail(&self, call: TestClientCall, failure: TestClientFailure) {
+        self.failures.write().insert(call, failure);
+    }
+
+    pub fn recover(&self, call: TestClientCall) {
+        self.failures.write().remove(&call);
+    }
+
+    fn failure(&self, call: TestClientCall) -> Option<TestClientFailure> {
+        self.failures.read().get(&call).cloned()
+    }
+
+    /// Whether the state at `id` can be read under the failures injected into `call`.
+    fn has_state(&self, call: TestClientCall, id: BlockId) -> bool {
+        let is_latest = match id {
+            BlockId::Latest => true,
+            id => self.block_hash(id) == Some(*self.last_hash.read()),
+        };
+        match self.failure(call) {
+            Some(TestClientFailure::DatabaseError) => false,
+            Some(TestClientFailure::StatePruned) => is_latest,
+            None => is_latest || self.block_hash(id).is_some(),
         }
     }
 
@@ -249,7 +363,13 @@ impl TestBlockChainClient {
 
     fn block_hash(&self, id: BlockId) -> Option<H256> {
         match id {
-            BlockId::Hash(hash) => Some(hash),
+            BlockId::Hash(hash) => {
+                if self.blocks.read().contains_key(&hash) {
+                    Some(hash)
+                } else {
+                    None
+                }
+            }
             BlockId::Number(n) => self.numbers.read().get(&(n as usize)).cloned(),
             BlockId::Earliest => self.numbers.read().get(&0).cloned(),
             BlockId::Latest => self.numbers.read().get(&(self.numbers.read().len() - 1)).cloned(),
@@ -258,23 +378,38 @@ impl TestBlockChainClient {
 
     /// Inserts a parcel to miners mem pool.
     pub fn insert_parcel_to_pool(&self) -> H256 {
+        let signed_parcel = self.funded_parcel();
+        let hash = signed_parcel.hash();
+        let res = self.miner.import_external_parcels(self, vec![signed_parcel.into()]);
+        let res = res.into_iter().next().unwrap().expect("Successful import");
+        assert_eq!(res, ParcelImportResult::Current);
+        hash
+    }
+
+    /// Inserts a parcel to miners mem pool as if it were sent through the local RPC.
+    pub fn insert_own_parcel_to_pool(&self) -> H256 {
+        let signed_parcel = self.funded_parcel();
+        let hash = signed_parcel.hash();
+        let res = self.miner.import_own_parcel(self, signed_parcel).expect("Successful import");
+        assert_eq!(res, ParcelImportResult::Current);
+        hash
+    }
+
+    /// Creates a parcel whose sender has enough balance to pay for it.
+    pub fn funded_parcel(&self) -> SignedParcel {
         let keypair = Random.generate().unwrap();
         let transactions = vec![];
         let parcel = Parcel {
             nonce: U256::zero(),
             fee: U256::from(10),
-            network_id: 0u64,
+            network_id: self.spec.params().network_id,
             action: Action::ChangeShardState {
                 transactions,
             },
         };
         let signed_parcel = parcel.sign(keypair.private());
         self.set_balance(signed_parcel.sender(), 10_000_000_000_000_000_000u64.into());
-        let hash = signed_parcel.hash();
-        let res = self.miner.import_external_parcels(self, vec![signed_parcel.into()]);
-        let res = res.into_iter().next().unwrap().expect("Successful import");
-        assert_eq!(res, ParcelImportResult::Current);
-        hash
+        signed_parcel
     }
 
     /// Set reported history size.
@@ -298,10 +433,10 @@ impl ReopenBlock for TestBlockChainClient {
 impl PrepareOpenBlock for TestBlockChainClient {
     fn prepare_open_block(&self, author: Address, extra_data: Bytes) -> OpenBlock {
         let engine = &*self.spec.engine;
-        let genesis_header = self.spec.genesis_header();
-        let db = get_temp_state_db();
+        let best_header = self.block_header(BlockId::Latest).expect("Best block always exists").decode();
+        let db = self.spec.ensure_db_good(get_temp_state_db(), &Default::default()).expect("Genesis state is valid");
 
-        let mut open_block = OpenBlock::new(engine, Default::default(), db, &genesis_header, author, extra_data, false)
+        let mut open_block = OpenBlock::new(engine, Default::default(), db, &best_header, author, extra_data, false)
             .expect("Opening block for tests will not fail.");
         // TODO [todr] Override timestamp for predictability (set_timestamp_now kind of sucks)
         open_block.set_timestamp(*self.latest_block_timestamp.read());
@@ -321,29 +456,35 @@ impl MiningBlockChainClient for TestBlockChainClient {}
 
 impl Nonce for TestBlockChainClient {
     fn nonce(&self, address: &Address, id: BlockId) -> Option<U256> {
-        match id {
-            BlockId::Latest => Some(self.nonces.read().get(address).cloned().unwrap_or_else(U256::zero)),
-            _ => None,
+        if self.has_state(TestClientCall::Nonce, id) {
+            Some(self.latest_nonce(address))
+        } else {
+            None
         }
     }
 
     fn latest_nonce(&self, address: &Address) -> U256 {
-        self.nonce(address, BlockId::Latest).unwrap()
+        self.account_lookups.fetch_add(1, AtomicOrder::Relaxed);
+        self.nonces.read().get(address).cloned().unwrap_or_else(U256::zero)
     }
 }
 
 impl Balance for TestBlockChainClient {
     fn balance(&self, address: &Address, state: StateOrBlock) -> Option<U256> {
-        match state {
-            StateOrBlock::Block(BlockId::Latest) | StateOrBlock::State(_) => {
-                Some(self.balances.read().get(address).cloned().unwrap_or_else(U256::zero))
-            }
-            _ => None,
+        let has_state = match state {
+            StateOrBlock::State(_) => true,
+            StateOrBlock::Block(id) => self.has_state(TestClientCall::Balance, id),
+        };
+        if has_state {
+            Some(self.latest_balance(address))
+        } else {
+            None
         }
     }
 
     fn latest_balance(&self, address: &Address) -> U256 {
-        self.balance(address, BlockId::Latest.into()).unwrap()
+        self.account_lookups.fetch_add(1, AtomicOrder::Relaxed);
+        self.balances.read().get(address).cloned().unwrap_or_else(U256::zero)
     }
 }
 
@@ -351,7 +492,7 @@ impl AccountData for TestBlockChainClient {}
 
 impl ChainInfo for TestBlockChainClient {
     fn chain_info(&self) -> BlockChainInfo {
-        let number = self.blocks.read().len() as BlockNumber - 1;
+        let number = self.numbers.read().len() as BlockNumber - 1;
         BlockChainInfo {
             total_score: *self.score.read(),
             pending_total_score: *self.score.read(),
@@ -365,6 +506,9 @@ impl ChainInfo for TestBlockChainClient {
 
 impl BlockInfo for TestBlockChainClient {
     fn block_header(&self, id: BlockId) -> Option<encoded::Header> {
+        if self.failure(TestClientCall::BlockHeader).is_some() {
+            return None
+        }
         self.block_hash(id)
             .and_then(|hash| self.blocks.read().get(&hash).map(|r| Rlp::new(r).at(0).as_raw().to_vec()))
             .map(encoded::Header::new)
@@ -389,44 +533,36 @@ impl BlockChain for TestBlockChainClient {}
 
 impl ImportBlock for TestBlockChainClient {
     fn import_block(&self, b: Bytes) -> Result<H256, BlockImportError> {
+        if self.failure(TestClientCall::ImportBlock).is_some() {
+            let error = ClientError::Database("Injected failure".into());
+            return Err(BlockImportError::Other(Box::new(Error::Client(error))))
+        }
+
         let header = Rlp::new(&b).val_at::<BlockHeader>(0);
         let h = header.hash();
-        let number: usize = header.number() as usize;
-        if number > self.blocks.read().len() {
-            panic!("Unexpected block number. Expected {}, got {}", self.blocks.read().len(), number);
+        if header.number() == 0 {
+            self.blocks.write().insert(h, b);
+            return Ok(h)
         }
-        if number > 0 {
+        let parent_total_score = {
             let blocks = self.blocks.read();
             let parent = blocks.get(header.parent_hash()).expect(&format!(
                 "Unknown block parent {:?} for block {}",
                 header.parent_hash(),
-                number
+                header.number()
             ));
             let parent = Rlp::new(parent).val_at::<BlockHeader>(0);
-            assert_eq!(parent.number(), header.number() - 1, "Unexpected block parent");
-        }
-        let len = self.numbers.read().len();
-        if number == len {
-            {
-                let mut score = self.score.write();
-                *score = *score + header.score().clone();
-            }
-            mem::replace(&mut *self.last_hash.write(), h.clone());
-            mem::replace(&mut *self.last_parcels_root.write(), h.clone());
-            self.blocks.write().insert(h.clone(), b);
-            self.numbers.write().insert(number, h.clone());
-            let mut parent_hash = header.parent_hash().clone();
-            if number > 0 {
-                let mut n = number - 1;
-                while n > 0 && self.numbers.read()[&n] != parent_hash {
-                    *self.numbers.write().get_mut(&n).unwrap() = parent_hash.clone();
-                    n -= 1;
-                    parent_hash =
-                        Rlp::new(&self.blocks.read()[&parent_hash]).val_at::<BlockHeader>(0).parent_hash().clone();
-                }
-            }
-        } else {
-            self.blocks.write().insert(h.clone(), b.to_vec());
+            assert_eq!(parent.number() + 1, header.number(), "Unexpected block parent");
+            self.total_scores.read()[header.parent_hash()]
+        };
+        let total_score = parent_total_score + *header.score();
+        self.blocks.write().insert(h, b);
+        self.total_scores.write().insert(h, total_score);
+
+        let best_score = *self.score.read();
+        let extends_best = *header.parent_hash() == *self.last_hash.read();
+        if total_score > best_score || (total_score == best_score && extends_best) {
+            self.set_best_block(&header, total_score);
         }
         Ok(h)
     }
@@ -434,6 +570,45 @@ impl ImportBlock for TestBlockChainClient {
     fn import_header(&self, _bytes: Bytes) -> Result<H256, BlockImportError> {
         unimplemented!()
     }
+
+    fn import_restored_block(&self, b: Bytes) -> Result<H256, BlockImportError> {
+        let header = Rlp::new(&b).val_at::<BlockHeader>(0);
+        let h = header.hash();
+        if self.blocks.read().contains_key(&h) {
+            return Err(BlockImportError::Import(ImportError::AlreadyInChain))
+        }
+        // The test client has no header chain, so the parent must be imported already.
+        let total_score = self.total_scores.read()[header.parent_hash()] + *header.score();
+        self.blocks.write().insert(h, b);
+        self.total_scores.write().insert(h, total_score);
+        self.set_best_block(&header, total_score);
+        Ok(h)
+    }
+}
+
+impl TestBlockChainClient {
+    /// Makes `header` the best block, rewriting the canonical numbers down to the fork point.
+    fn set_best_block(&self, header: &BlockHeader, total_score: U256) {
+        *self.score.write() = total_score;
+        *self.last_hash.write() = header.hash();
+        *self.last_parcels_root.write() = *header.parcels_root();
+
+        let blocks = self.blocks.read();
+        let mut numbers = self.numbers.write();
+        let best_number = header.number() as usize;
+        numbers.retain(|number, _| *number <= best_number);
+
+        let mut number = best_number;
+        let mut hash = header.hash();
+        while numbers.get(&number) != Some(&hash) {
+            numbers.insert(number, hash);
+            if number == 0 {
+                break
+            }
+            number -= 1;
+            hash = *Rlp::new(&blocks[&hash]).val_at::<BlockHeader>(0).parent_hash();
+        }
+    }
 }
 
 impl TopStateInfo for () {
@@ -470,19 +645,22 @@ impl ShardStateInfo for () {
 }
 
 impl BlockChainClient for TestBlockChainClient {
-    fn block_total_score(&self, _id: BlockId) -> Option<U256> {
-        Some(U256::zero())
+    fn block_total_score(&self, id: BlockId) -> Option<U256> {
+        self.block_hash(id).and_then(|hash| self.total_scores.read().get(&hash).cloned())
     }
 
     fn block_hash(&self, id: BlockId) -> Option<H256> {
         Self::block_hash(self, id)
     }
 
-    fn block_number(&self, _id: BlockId) -> Option<BlockNumber> {
-        unimplemented!()
+    fn block_number(&self, id: BlockId) -> Option<BlockNumber> {
+        self.block_header(id).map(|header| header.number())
     }
 
     fn block_body(&self, id: BlockId) -> Option<encoded::Body> {
+        if self.failure(TestClientCall::BlockBody).is_some() {
+            return None
+        }
         self.block_hash(id).and_then(|hash| {
             self.blocks.read().get(&hash).map(|r| {
                 let mut stream = RlpStream::new_list(1);
@@ -493,8 +671,11 @@ impl BlockChainClient for TestBlockChainClient {
     }
 
     fn block_status(&self, id: BlockId) -> BlockStatus {
+        if self.failure(TestClientCall::BlockStatus).is_some() {
+            return BlockStatus::Unknown
+        }
         match id {
-            BlockId::Number(number) if (number as usize) < self.blocks.read().len() => BlockStatus::InChain,
+            BlockId::Number(number) if (number as usize) < self.numbers.read().len() => BlockStatus::InChain,
             BlockId::Hash(ref hash) if self.blocks.read().get(hash).is_some() => BlockStatus::InChain,
             BlockId::Latest | BlockId::Earliest => BlockStatus::InChain,
             _ => BlockStatus::Unknown,
@@ -522,6 +703,14 @@ impl BlockChainClient for TestBlockChainClient {
         self.miner.ready_parcels()
     }
 
+    fn ready_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel> {
+        self.miner.ready_parcels_with_limit(max_count, max_bytes)
+    }
+
+    fn ready_local_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel> {
+        self.miner.ready_local_parcels_with_limit(max_count, max_bytes)
+    }
+
     fn parcel(&self, _id: ParcelId) -> Option<LocalizedParcel> {
         unimplemented!();
     }
@@ -533,6 +722,10 @@ impl BlockChainClient for TestBlockChainClient {
     fn transaction_invoice(&self, _id: TransactionId) -> Option<Invoice> {
         unimplemented!()
     }
+
+    fn decode_limits(&self) -> DecodeLimits {
+        DecodeLimits::from(self.spec.params())
+    }
 }
 
 impl super::EngineClient for TestBlockChainClient {
@@ -546,3 +739,123 @@ impl super::EngineClient for TestBlockChainClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a chain of three blocks on top of genesis and a non-canonical sibling of block #1.
+    fn client_with_fork() -> (TestBlockChainClient, H256) {
+        let client = TestBlockChainClient::new();
+        client.add_blocks(3, 0);
+
+        let mut header = BlockHeader::new();
+        header.set_parent_hash(client.block_hash(BlockId::Number(0)).unwrap());
+        header.set_number(1);
+        header.set_extra_data(b"fork".to_vec());
+        let mut rlp = RlpStream::new_list(2);
+        rlp.append(&header);
+        rlp.begin_list(0);
+        let fork = client.import_block(rlp.out()).unwrap();
+        (client, fork)
+    }
+
+    #[test]
+    fn resolves_every_block_id_variant() {
+        let (client, fork) = client_with_fork();
+        let genesis = client.block_hash(BlockId::Number(0)).unwrap();
+        let best = client.chain_info().best_block_hash;
+
+        assert_eq!(Some(genesis), client.block_hash(BlockId::Earliest));
+        assert_eq!(Some(best), client.block_hash(BlockId::Latest));
+        assert_eq!(Some(best), client.block_hash(BlockId::Number(3)));
+        assert_eq!(None, client.block_hash(BlockId::Number(4)));
+        assert_eq!(Some(best), client.block_hash(BlockId::Hash(best)));
+        assert_eq!(Some(fork), client.block_hash(BlockId::Hash(fork)));
+        assert_eq!(None, client.block_hash(BlockId::Hash(H256::from(42))));
+
+        assert_eq!(Some(0), client.block_number(BlockId::Earliest));
+        assert_eq!(Some(3), client.block_number(BlockId::Latest));
+        assert_eq!(Some(2), client.block_number(BlockId::Number(2)));
+        assert_eq!(None, client.block_number(BlockId::Number(4)));
+        assert_eq!(None, client.block_number(BlockId::Hash(H256::from(42))));
+    }
+
+    #[test]
+    fn resolves_non_canonical_hash() {
+        let (client, fork) = client_with_fork();
+
+        assert_ne!(Some(fork), client.block_hash(BlockId::Number(1)));
+        assert_eq!(Some(1), client.block_number(BlockId::Hash(fork)));
+        assert_eq!(Some(fork), client.block_header(BlockId::Hash(fork)).map(|header| header.hash()));
+        assert!(client.block_body(BlockId::Hash(fork)).is_some());
+        assert_eq!(BlockStatus::InChain, client.block_status(BlockId::Hash(fork)));
+    }
+
+    #[test]
+    fn unresolvable_ids_have_no_data() {
+        let (client, _) = client_with_fork();
+        let unknown = BlockId::Hash(H256::from(42));
+
+        assert!(client.block_header(unknown).is_none());
+        assert!(client.block_body(unknown).is_none());
+        assert!(client.block(BlockId::Number(4)).is_none());
+        assert_eq!(BlockStatus::Unknown, client.block_status(unknown));
+        assert_eq!(BlockStatus::Unknown, client.block_status(BlockId::Number(4)));
+    }
+
+    #[test]
+    fn equally_long_fork_with_more_score_becomes_the_best_chain() {
+        let client = TestBlockChainClient::new();
+        client.add_blocks(2, 0);
+        let fork_point = client.block_hash(BlockId::Number(2)).unwrap();
+        let weak = client.add_chain(BlockId::Latest, vec![TestBlock::default().score(1.into()); 3]);
+        assert_eq!(Some(weak[2]), client.block_hash(BlockId::Latest));
+
+        let author = Address::random();
+        let strong = client.add_chain(
+            BlockId::Hash(fork_point),
+            vec![TestBlock::default().score(2.into()).author(author).parcels(1); 3],
+        );
+        assert_eq!(Some(strong[2]), client.block_hash(BlockId::Latest));
+        assert_eq!(5, client.chain_info().best_block_number);
+        for (number, hash) in (3..6).zip(&strong) {
+            assert_eq!(Some(*hash), client.block_hash(BlockId::Number(number)));
+            assert_eq!(author, client.block_header(BlockId::Number(number)).unwrap().author());
+        }
+        assert_eq!(Some(fork_point), client.block_hash(BlockId::Number(2)));
+
+        let weak_score = client.block_total_score(BlockId::Hash(weak[2])).unwrap();
+        let strong_score = client.block_total_score(BlockId::Hash(strong[2])).unwrap();
+        assert_eq!(weak_score + 3.into(), strong_score);
+        assert_eq!(strong_score, client.chain_info().total_score);
+    }
+
+    #[test]
+    fn injected_failures_last_until_recovered() {
+        let client = TestBlockChainClient::new();
+        client.add_blocks(3, 0);
+        let address = Address::random();
+        client.set_nonce(address, 7.into());
+
+        assert_eq!(Some(7.into()), client.nonce(&address, BlockId::Number(1)));
+        client.fail(TestClientCall::Nonce, TestClientFailure::StatePruned);
+        assert_eq!(None, client.nonce(&address, BlockId::Number(1)));
+        assert_eq!(Some(7.into()), client.nonce(&address, BlockId::Number(3)));
+        assert_eq!(7, client.latest_nonce(&address).low_u64());
+
+        client.fail(TestClientCall::Nonce, TestClientFailure::DatabaseError);
+        assert_eq!(None, client.nonce(&address, BlockId::Latest));
+        client.recover(TestClientCall::Nonce);
+        assert_eq!(Some(7.into()), client.nonce(&address, BlockId::Latest));
+
+        client.fail(TestClientCall::ImportBlock, TestClientFailure::DatabaseError);
+        let source = TestBlockChainClient::new();
+        source.add_blocks(4, 0);
+        match client.import_block(source.block(BlockId::Number(4)).unwrap().into_inner()) {
+            Err(BlockImportError::Other(_)) => {}
+            result => panic!("The import must fail: {:?}", result),
+        }
+        assert_eq!(3, client.chain_info().best_block_number);
+    }
+}
@@ -48,10 +48,11 @@ impl CodeChainMachine {
     }
 
     /// Does basic verification of the parcel.
-    pub fn verify_parcel_basic(&self, t: &UnverifiedParcel, _header: &Header) -> Result<(), Error> {
-        if t.fee < self.params.min_parcel_cost {
+    pub fn verify_parcel_basic(&self, t: &UnverifiedParcel, header: &Header) -> Result<(), Error> {
+        let min_parcel_cost = self.params.at(header.number()).min_parcel_cost;
+        if t.fee < min_parcel_cost {
             return Err(ParcelError::InsufficientFee {
-                minimal: self.params.min_parcel_cost,
+                minimal: min_parcel_cost,
                 got: t.fee,
             }.into())
         }
@@ -35,7 +35,7 @@ use std::sync::{Arc, Weak};
 
 use ckeys::ECDSASignature;
 use cnetwork::NetworkExtension;
-use ctypes::{Address, Bytes, H256};
+use ctypes::{Address, Bytes, H256, U256};
 use unexpected::{Mismatch, OutOfBounds};
 
 use self::epoch::{EpochVerifier, NoOp, PendingTransition};
@@ -79,6 +79,21 @@ pub trait ConsensusEngine<M: Machine>: Sync + Send {
         None
     }
 
+    /// The reward for the author of the block `block_number`.
+    fn block_reward(&self, _block_number: u64) -> U256 {
+        U256::zero()
+    }
+
+    /// The number of validators for the child of `parent`. None if the engine has no validators.
+    fn validator_count(&self, _parent: &H256) -> Option<usize> {
+        None
+    }
+
+    /// The timeouts of the consensus steps in milliseconds, in the order of the steps.
+    fn step_timeouts(&self) -> Vec<(&'static str, u64)> {
+        Vec::new()
+    }
+
     /// Attempt to seal the block internally.
     ///
     /// If `Some` is returned, then you get a valid seal.
@@ -258,6 +273,8 @@ pub enum EngineError {
     BadSealFieldSize(OutOfBounds<usize>),
     /// Malformed consensus message.
     MalformedMessage(String),
+    /// The signers of the commit seal do not hold enough weight.
+    InsufficientCommit(OutOfBounds<usize>),
 }
 
 impl fmt::Display for EngineError {
@@ -270,12 +287,19 @@ impl fmt::Display for EngineError {
             UnexpectedMessage => "This Engine should not be fed messages.".into(),
             BadSealFieldSize(oob) => format!("Seal field has an unexpected length: {}", oob),
             MalformedMessage(msg) => format!("Received malformed consensus message: {}", msg),
+            InsufficientCommit(oob) => format!("Commit seal has insufficient weight: {}", oob),
         };
 
         f.write_fmt(format_args!("Engine error ({})", msg))
     }
 }
 
+impl ::std::error::Error for EngineError {
+    fn description(&self) -> &str {
+        "Engine error"
+    }
+}
+
 /// Common type alias for an engine coupled with an CodeChain-like state machine.
 pub trait CodeChainEngine: ConsensusEngine<CodeChainMachine> {
     /// Get the general parameters of the chain.
@@ -69,6 +69,10 @@ impl<M: Machine> ConsensusEngine<M> for NullEngine<M> {
         &self.machine
     }
 
+    fn block_reward(&self, _block_number: u64) -> U256 {
+        self.params.block_reward
+    }
+
     fn on_close_block(&self, block: &mut M::LiveBlock) -> Result<(), M::Error> {
         let author = *LiveBlock::header(&*block).author();
         self.machine.add_balance(block, &author, &self.params.block_reward)
@@ -70,12 +70,13 @@ where
         Some(true)
     }
 
-    fn generate_seal(&self, block: &M::LiveBlock, _parent: &M::Header) -> Seal {
-        if block.parcels().is_empty() {
-            Seal::None
-        } else {
-            Seal::Regular(Vec::new())
-        }
+    fn block_reward(&self, _block_number: u64) -> U256 {
+        self.params.block_reward
+    }
+
+    /// Every block is sealed, even an empty one. The miner decides whether an empty block is sealed.
+    fn generate_seal(&self, _block: &M::LiveBlock, _parent: &M::Header) -> Seal {
+        Seal::Regular(Vec::new())
     }
 
     fn verify_local_seal(&self, _header: &M::Header) -> Result<(), M::Error> {
@@ -94,13 +95,22 @@ where
 
 #[cfg(test)]
 mod tests {
-    use ctypes::H520;
+    use std::sync::Arc;
+
+    use ctypes::{Address, Signature, U256};
 
     use super::super::super::block::{IsBlock, OpenBlock};
+    use super::super::super::client::{
+        Balance, BlockImportOutcome, BlockInfo, ChainInfo, ImportSealedBlock, PrepareOpenBlock,
+    };
+    use super::super::super::codechain_machine::CodeChainMachine;
     use super::super::super::header::Header;
     use super::super::super::spec::Spec;
-    use super::super::super::tests::helpers::get_temp_state_db;
+    use super::super::super::state::TopStateInfo;
+    use super::super::super::tests::helpers::{generate_dummy_client, get_temp_state_db};
+    use super::super::super::types::BlockId;
     use super::super::Seal;
+    use super::{Solo, SoloParams};
 
     #[test]
     fn solo_can_seal() {
@@ -118,6 +128,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn author_of_a_closed_block_is_rewarded() {
+        let mut spec = Spec::new_test_solo();
+        spec.engine = Arc::new(Solo::new(
+            SoloParams {
+                block_reward: U256::from(50),
+            },
+            CodeChainMachine::new(spec.params().clone()),
+        ));
+        let author = Address::random();
+
+        let source = generate_dummy_client(&spec);
+        let parent = source.best_block_header();
+        let open_block = source.prepare_open_block(author, vec![]);
+        let block = open_block.close_and_lock(parent.parcels_root(), parent.invoices_root());
+        let sealed = block.seal(&*spec.engine, vec![]).unwrap();
+        assert_eq!(U256::from(50), sealed.state().balance(&author).unwrap());
+        source.import_sealed_block(sealed).unwrap();
+
+        // Another client importing the block rewards the author as well, or the state root would differ.
+        let target = generate_dummy_client(&spec);
+        let block = source.block(BlockId::Number(1)).unwrap();
+        let hash = block.hash();
+        assert_eq!(BlockImportOutcome::Imported(hash), target.import_block_rlp(block.into_inner()).unwrap());
+        assert_eq!(hash, target.chain_info().best_block_hash);
+        assert_eq!(Some(U256::from(50)), target.balance(&author, BlockId::Latest.into()));
+        assert_eq!(source.best_block_header().state_root(), target.best_block_header().state_root());
+    }
+
     #[test]
     fn solo_cant_verify() {
         let engine = Spec::new_test_solo().engine;
@@ -125,7 +164,7 @@ mod tests {
 
         assert!(engine.verify_block_basic(&header).is_ok());
 
-        header.set_seal(vec![::rlp::encode(&H520::default()).into_vec()]);
+        header.set_seal(vec![::rlp::encode(&Signature::default()).into_vec()]);
 
         assert!(engine.verify_block_unordered(&header).is_ok());
     }
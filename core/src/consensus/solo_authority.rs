@@ -19,7 +19,7 @@ use std::sync::{Arc, Weak};
 use cjson;
 use ckeys::{public_to_address, recover_ecdsa, ECDSASignature};
 use cnetwork::NetworkExtension;
-use ctypes::{Address, H256, H520, U256};
+use ctypes::{Address, Signature, H256, U256};
 use parking_lot::RwLock;
 
 use super::super::account_provider::AccountProvider;
@@ -82,11 +82,9 @@ impl super::epoch::EpochVerifier<CodeChainMachine> for EpochVerifier {
 }
 
 fn verify_external(header: &Header, validators: &ValidatorSet) -> Result<(), Error> {
-    use rlp::UntrustedRlp;
-
     // Check if the signature belongs to a validator, can depend on parent state.
-    let sig = UntrustedRlp::new(&header.seal()[0]).as_val::<H520>()?;
-    let signer = public_to_address(&recover_ecdsa(&sig.into(), &header.bare_hash())?);
+    let sig: Signature = header.seal_at(0).ok_or(BlockError::InvalidSeal)?;
+    let signer = public_to_address(&recover_ecdsa(&sig, &header.bare_hash())?);
 
     if *header.author() != signer {
         return Err(EngineError::NotAuthorized(header.author().clone()).into())
@@ -116,6 +114,14 @@ impl ConsensusEngine<CodeChainMachine> for SoloAuthority {
         Some(self.signer.read().is_some())
     }
 
+    fn block_reward(&self, _block_number: u64) -> U256 {
+        self.block_reward
+    }
+
+    fn validator_count(&self, parent: &H256) -> Option<usize> {
+        Some(self.validators.count(parent))
+    }
+
     /// Attempt to seal the block internally.
     fn generate_seal(&self, block: &ExecutedBlock, _parent: &Header) -> Seal {
         let header = block.header();
@@ -123,7 +129,7 @@ impl ConsensusEngine<CodeChainMachine> for SoloAuthority {
         if self.validators.contains(header.parent_hash(), author) {
             // account should be permanently unlocked, otherwise sealing will fail
             if let Ok(signature) = self.sign(header.bare_hash()) {
-                return Seal::Regular(vec![::rlp::encode(&(&H520::from(signature) as &[u8])).into_vec()])
+                return Seal::Regular(vec![::rlp::encode(&signature).into_vec()])
             } else {
                 ctrace!(SOLO_AUTHORITY, "generate_seal: FAIL: accounts secret key unavailable");
             }
@@ -212,7 +218,7 @@ impl ConsensusEngine<CodeChainMachine> for SoloAuthority {
 
 #[cfg(test)]
 mod tests {
-    use ctypes::H520;
+    use ctypes::Signature;
 
     use super::super::super::block::{IsBlock, OpenBlock};
     use super::super::super::header::Header;
@@ -230,7 +236,7 @@ mod tests {
     fn can_do_signature_verification_fail() {
         let engine = Spec::new_test_solo_authority().engine;
         let mut header: Header = Header::default();
-        header.set_seal(vec![::rlp::encode(&H520::default()).into_vec()]);
+        header.set_seal(vec![::rlp::encode(&Signature::default()).into_vec()]);
 
         let verify_result = engine.verify_block_external(&header);
         assert!(verify_result.is_err());
@@ -0,0 +1,300 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use ccrypto::blake256;
+use cnetwork::NodeId;
+use ctypes::{Address, H256};
+use rlp;
+
+use super::message::{ConsensusMessage, VoteStep};
+use super::{Height, Step, View};
+
+/// Messages are accepted for heights at most this far from the current height.
+const HEIGHT_WINDOW: Height = 2;
+/// Messages are accepted for views at most this far ahead of the current view.
+const VIEW_WINDOW: View = 8;
+/// The number of new messages a peer can send in a rate limit period.
+const MAX_MESSAGES_PER_PERIOD: usize = 256;
+const RATE_LIMIT_PERIOD_IN_SECONDS: u64 = 1;
+/// A peer whose messages are ignored once its penalty reaches this amount.
+const MAX_PENALTY: u32 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Violation {
+    /// The height or the view of the message is too far from ours.
+    OutOfWindow,
+    /// The peer sent too many messages in a period.
+    RateLimited,
+    /// The message is malformed, its signature is invalid, or it is not signed by a validator.
+    InvalidSignature,
+}
+
+impl Violation {
+    fn penalty(&self) -> u32 {
+        match self {
+            Violation::OutOfWindow => 1,
+            Violation::RateLimited => 1,
+            Violation::InvalidSignature => 25,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    /// The message should be verified and handled.
+    Admitted,
+    /// The message is seen already.
+    Duplicate,
+    Dropped(Violation),
+    /// The peer is penalized too much. Its messages are ignored.
+    Banned,
+}
+
+struct PeerRecord {
+    penalty: u32,
+    period_start: Instant,
+    messages_in_period: usize,
+}
+
+/// Screens the consensus messages from peers before their signatures are verified.
+///
+/// The validator of a message is only known after its signature is recovered, so the messages
+/// are recorded once they are verified, keeping one message for each validator at each step.
+/// The unverified messages can't grow the cache, since they are not recorded.
+/// The penalties of a peer are kept until the peer is disconnected.
+pub struct MessageAdmission {
+    /// The hashes of the verified messages by their steps and their validators.
+    seen: BTreeMap<VoteStep, HashMap<Address, H256>>,
+    peers: HashMap<NodeId, PeerRecord>,
+}
+
+impl MessageAdmission {
+    pub fn new() -> Self {
+        Self {
+            seen: BTreeMap::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Decides whether `message` from `peer` is worth verifying,
+    /// while our consensus is at `height` and `view`.
+    pub fn admit(
+        &mut self,
+        peer: &NodeId,
+        message: &ConsensusMessage,
+        height: Height,
+        view: View,
+        now: Instant,
+    ) -> Verdict {
+        if self.is_banned(peer) {
+            return Verdict::Banned
+        }
+        let hash = blake256(&rlp::encode(message));
+        if self.seen.get(&message.vote_step).map_or(false, |votes| votes.values().any(|seen| *seen == hash)) {
+            return Verdict::Duplicate
+        }
+
+        let is_rate_limited = {
+            let record = self.peers.entry(*peer).or_insert_with(|| PeerRecord {
+                penalty: 0,
+                period_start: now,
+                messages_in_period: 0,
+            });
+            if now.duration_since(record.period_start) >= Duration::from_secs(RATE_LIMIT_PERIOD_IN_SECONDS) {
+                record.period_start = now;
+                record.messages_in_period = 0;
+            }
+            record.messages_in_period += 1;
+            record.messages_in_period > MAX_MESSAGES_PER_PERIOD
+        };
+        if is_rate_limited {
+            return self.report(peer, Violation::RateLimited)
+        }
+        if !is_in_window(&message.vote_step, height, view) {
+            return self.report(peer, Violation::OutOfWindow)
+        }
+        Verdict::Admitted
+    }
+
+    /// Records `message` signed by `validator`, so that it is not verified again.
+    /// The first message of a validator at a step is kept, since a different one is a double vote to be verified.
+    pub fn record(&mut self, validator: Address, message: &ConsensusMessage, height: Height) {
+        self.prune(height);
+        let hash = blake256(&rlp::encode(message));
+        self.seen.entry(message.vote_step.clone()).or_insert_with(HashMap::new).entry(validator).or_insert(hash);
+    }
+
+    /// Adds the penalty of `violation` to `peer`.
+    pub fn report(&mut self, peer: &NodeId, violation: Violation) -> Verdict {
+        if let Some(record) = self.peers.get_mut(peer) {
+            record.penalty = record.penalty.saturating_add(violation.penalty());
+            cdebug!(ENGINE, "{:?} from {}, penalty {}", violation, peer, record.penalty);
+        }
+        Verdict::Dropped(violation)
+    }
+
+    pub fn is_banned(&self, peer: &NodeId) -> bool {
+        self.peers.get(peer).map_or(false, |record| record.penalty >= MAX_PENALTY)
+    }
+
+    pub fn remove_peer(&mut self, peer: &NodeId) {
+        self.peers.remove(peer);
+    }
+
+    /// Forgets the messages for the heights below the window.
+    fn prune(&mut self, height: Height) {
+        let lowest = VoteStep::new(height.saturating_sub(HEIGHT_WINDOW), 0, Step::Propose);
+        self.seen = self.seen.split_off(&lowest);
+    }
+}
+
+fn is_in_window(vote_step: &VoteStep, height: Height, view: View) -> bool {
+    let is_too_low = vote_step.height.saturating_add(HEIGHT_WINDOW) < height;
+    let is_too_high = vote_step.height > height.saturating_add(HEIGHT_WINDOW);
+    if is_too_low || is_too_high {
+        return false
+    }
+    let base_view = if vote_step.height == height {
+        view
+    } else {
+        0
+    };
+    vote_step.height < height || vote_step.view <= base_view.saturating_add(VIEW_WINDOW)
+}
+
+#[cfg(test)]
+mod tests {
+    use cnetwork::SocketAddr;
+
+    use super::*;
+
+    fn peer(port: u16) -> NodeId {
+        SocketAddr::v4(127, 0, 0, 1, port).into()
+    }
+
+    fn vote(height: Height, view: View) -> ConsensusMessage {
+        ConsensusMessage::new(Default::default(), height, view, Step::Prevote, Some(H256::from(1)))
+    }
+
+    #[test]
+    fn a_replayed_vote_is_admitted_once() {
+        let mut admission = MessageAdmission::new();
+        let now = Instant::now();
+        let message = vote(10, 0);
+        let validator = Address::random();
+
+        let mut admitted = 0;
+        for i in 0..1000 {
+            if admission.admit(&peer(i % 5), &message, 10, 0, now) == Verdict::Admitted {
+                admitted += 1;
+                admission.record(validator, &message, 10);
+            }
+        }
+        assert_eq!(1, admitted);
+        assert_eq!(Verdict::Duplicate, admission.admit(&peer(0), &message, 10, 0, now));
+        assert!(!admission.is_banned(&peer(0)));
+    }
+
+    #[test]
+    fn only_the_verified_votes_are_cached_for_each_validator() {
+        let mut admission = MessageAdmission::new();
+        let now = Instant::now();
+        let first = vote(10, 0);
+        let double = ConsensusMessage::new(Default::default(), 10, 0, Step::Prevote, Some(H256::from(2)));
+
+        // The messages are not cached until they are verified.
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &first, 10, 0, now));
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &first, 10, 0, now));
+
+        let validator = Address::random();
+        admission.record(validator, &first, 10);
+        admission.record(validator, &double, 10);
+        assert_eq!(1, admission.seen[&first.vote_step].len());
+        assert_eq!(Verdict::Duplicate, admission.admit(&peer(0), &first, 10, 0, now));
+        // The double vote is verified to be reported.
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &double, 10, 0, now));
+
+        let other = Address::random();
+        admission.record(other, &double, 10);
+        assert_eq!(2, admission.seen[&first.vote_step].len());
+        assert_eq!(Verdict::Duplicate, admission.admit(&peer(0), &double, 10, 0, now));
+    }
+
+    #[test]
+    fn out_of_window_messages_are_dropped_with_a_penalty() {
+        let mut admission = MessageAdmission::new();
+        let now = Instant::now();
+        let dropped = Verdict::Dropped(Violation::OutOfWindow);
+
+        assert_eq!(dropped, admission.admit(&peer(0), &vote(10 + HEIGHT_WINDOW + 1, 0), 10, 0, now));
+        assert_eq!(dropped, admission.admit(&peer(0), &vote(10 - HEIGHT_WINDOW - 1, 0), 10, 0, now));
+        assert_eq!(dropped, admission.admit(&peer(0), &vote(10, 3 + VIEW_WINDOW + 1), 10, 3, now));
+        assert_eq!(3, admission.peers[&peer(0)].penalty);
+
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &vote(10 + HEIGHT_WINDOW, 0), 10, 0, now));
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &vote(10 - HEIGHT_WINDOW, 100), 10, 0, now));
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &vote(10, 3 + VIEW_WINDOW), 10, 3, now));
+    }
+
+    #[test]
+    fn the_highest_heights_and_views_do_not_overflow_the_window() {
+        let mut admission = MessageAdmission::new();
+        let now = Instant::now();
+        let dropped = Verdict::Dropped(Violation::OutOfWindow);
+
+        assert_eq!(dropped, admission.admit(&peer(0), &vote(Height::max_value(), 0), 10, 0, now));
+        assert_eq!(dropped, admission.admit(&peer(0), &vote(10, View::max_value()), 10, 3, now));
+
+        let (height, view) = (Height::max_value(), View::max_value());
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(1), &vote(height, view), height, view, now));
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(1), &vote(height - HEIGHT_WINDOW, 0), height, 0, now));
+    }
+
+    #[test]
+    fn a_flooding_peer_is_rate_limited() {
+        let mut admission = MessageAdmission::new();
+        let now = Instant::now();
+        for view in 0..MAX_MESSAGES_PER_PERIOD {
+            assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &vote(10, view), 10, view, now));
+        }
+        let message = vote(10, MAX_MESSAGES_PER_PERIOD);
+        let view = MAX_MESSAGES_PER_PERIOD;
+        assert_eq!(Verdict::Dropped(Violation::RateLimited), admission.admit(&peer(0), &message, 10, view, now));
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(1), &message, 10, view, now));
+
+        let later = now + Duration::from_secs(RATE_LIMIT_PERIOD_IN_SECONDS);
+        let message = vote(10, view + 1);
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &message, 10, view, later));
+    }
+
+    #[test]
+    fn invalid_signatures_ban_the_peer() {
+        let mut admission = MessageAdmission::new();
+        let now = Instant::now();
+        for view in 0..4 {
+            assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &vote(10, view), 10, 0, now));
+            admission.report(&peer(0), Violation::InvalidSignature);
+        }
+        assert!(admission.is_banned(&peer(0)));
+        assert_eq!(Verdict::Banned, admission.admit(&peer(0), &vote(10, 5), 10, 0, now));
+
+        admission.remove_peer(&peer(0));
+        assert_eq!(Verdict::Admitted, admission.admit(&peer(0), &vote(10, 5), 10, 0, now));
+    }
+}
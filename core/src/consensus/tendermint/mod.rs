@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod admission;
 mod message;
 mod params;
 
@@ -21,18 +22,20 @@ use std::cmp;
 use std::collections::HashSet;
 use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::{Arc, Weak};
+use std::time::Instant;
 
 use ccrypto::blake256;
 use ckeys::{public_to_address, recover_ecdsa};
 use ckeys::{ECDSASignature, Message};
 use cnetwork::{Api, NetworkExtension, NodeId, TimerToken};
-use ctypes::{Address, Bytes, H256, H520, U128, U256};
+use ctypes::{Address, Bytes, Signature, H256, U128, U256};
 use parking_lot::{Mutex, RwLock};
 use rand::{thread_rng, Rng};
 use rlp::{self, Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 use time::Duration;
 use unexpected::{Mismatch, OutOfBounds};
 
+use self::admission::{MessageAdmission, Verdict, Violation};
 use self::message::*;
 pub use self::params::{TendermintParams, TendermintTimeouts};
 use super::super::account_provider::AccountProvider;
@@ -332,7 +335,7 @@ impl Tendermint {
         let r = self.view.load(AtomicOrdering::SeqCst);
         let s = *self.step.read();
         let vote_info = message_info_rlp(&VoteStep::new(h, r, s), block_hash);
-        match (self.signer.read().address(), self.sign(blake256(&vote_info)).map(Into::into)) {
+        match (self.signer.read().address(), self.sign(blake256(&vote_info))) {
             (Some(validator), Ok(signature)) => {
                 let message_rlp = message_full_rlp(&signature, &vote_info);
                 let message = ConsensusMessage::new(signature, h, r, s, block_hash);
@@ -353,6 +356,42 @@ impl Tendermint {
         }
     }
 
+    /// Verifies that the precommits in the seal commit the block.
+    ///
+    /// Every precommit must be signed over the vote of the block's height and view by a distinct
+    /// validator of the parent's validator set, and the signers must hold more than two-thirds of the weight.
+    fn verify_commit_seal(&self, header: &Header) -> Result<(), Error> {
+        let vote_step = VoteStep::new(header.number() as Height, consensus_view(header)?, Step::Precommit);
+        let precommit_hash = message_hash(vote_step.clone(), header.bare_hash());
+        let ref signatures_field = header
+            .seal()
+            .get(2)
+            .expect("block went through verify_block_basic; block has .seal_fields() fields; qed");
+        let signatures: Vec<Signature> = UntrustedRlp::new(signatures_field).as_list()?;
+        let mut origins = HashSet::new();
+        for signature in signatures {
+            let precommit = ConsensusMessage {
+                signature,
+                block_hash: Some(header.bare_hash()),
+                vote_step: vote_step.clone(),
+            };
+            let address = match self.votes.get(&precommit) {
+                Some(a) => a,
+                None => public_to_address(&recover_ecdsa(&precommit.signature, &precommit_hash)?),
+            };
+            if !self.validators.contains(header.parent_hash(), &address) {
+                return Err(EngineError::NotAuthorized(address.to_owned()).into())
+            }
+
+            if !origins.insert(address) {
+                cwarn!(ENGINE, "verify_commit_seal: Duplicate signature from {} on the seal.", address);
+                return Err(BlockError::InvalidSeal.into())
+            }
+        }
+
+        check_commit_weight(origins.len(), self.validators.count(header.parent_hash())).map_err(Into::into)
+    }
+
     fn handle_valid_message(&self, message: &ConsensusMessage) {
         let ref vote_step = message.vote_step;
         let is_newer_than_lock = match &*self.lock_change.read() {
@@ -410,6 +449,35 @@ impl Tendermint {
             }
         }
     }
+
+    /// Handles a consensus message, returning its sender if the signature was verified.
+    /// The old or known messages are not verified again.
+    fn handle_consensus_message(&self, rlp: &[u8]) -> Result<Option<Address>, EngineError> {
+        fn fmt_err<T: ::std::fmt::Debug>(x: T) -> EngineError {
+            EngineError::MalformedMessage(format!("{:?}", x))
+        }
+
+        let rlp = UntrustedRlp::new(rlp);
+        let message: ConsensusMessage = rlp.as_val().map_err(fmt_err)?;
+        if self.votes.is_old_or_known(&message) {
+            return Ok(None)
+        }
+        let msg_hash = blake256(rlp.at(1).map_err(fmt_err)?.as_raw());
+        let sender = public_to_address(&recover_ecdsa(&message.signature, &msg_hash).map_err(fmt_err)?);
+
+        if !self.is_authority(&sender) {
+            return Err(EngineError::NotAuthorized(sender))
+        }
+        self.broadcast_message(rlp.as_raw().to_vec());
+        if let Some(double) = self.votes.vote(message.clone(), sender) {
+            let height = message.vote_step.height as BlockNumber;
+            self.validators.report_malicious(&sender, height, height, ::rlp::encode(&double).into_vec());
+            return Err(EngineError::DoubleVote(sender))
+        }
+        ctrace!(ENGINE, "Handling a valid {:?} from {}.", message, sender);
+        self.handle_valid_message(&message);
+        Ok(Some(sender))
+    }
 }
 
 impl ConsensusEngine<CodeChainMachine> for Tendermint {
@@ -431,6 +499,24 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
         Some(self.signer.read().is_some())
     }
 
+    fn block_reward(&self, _block_number: u64) -> U256 {
+        self.block_reward
+    }
+
+    fn validator_count(&self, parent: &H256) -> Option<usize> {
+        Some(self.validators.count(parent))
+    }
+
+    fn step_timeouts(&self) -> Vec<(&'static str, u64)> {
+        let timeouts = &self.extension.timeouts;
+        vec![
+            ("propose", timeouts.propose.num_milliseconds() as u64),
+            ("prevote", timeouts.prevote.num_milliseconds() as u64),
+            ("precommit", timeouts.precommit.num_milliseconds() as u64),
+            ("commit", timeouts.commit.num_milliseconds() as u64),
+        ]
+    }
+
     /// Attempt to seal generate a proposal seal.
     ///
     /// This operation is synchronous and may (quite reasonably) not be available, in which case
@@ -446,7 +532,7 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
         let view = self.view.load(AtomicOrdering::SeqCst);
         let bh = Some(header.bare_hash());
         let vote_info = message_info_rlp(&VoteStep::new(height, view, Step::Propose), bh.clone());
-        if let Ok(signature) = self.sign(blake256(&vote_info)).map(Into::into) {
+        if let Ok(signature) = self.sign(blake256(&vote_info)) {
             // Insert Propose vote.
             cdebug!(ENGINE, "Submitting proposal {} at height {} view {}.", header.bare_hash(), height, view);
             let sender = self.signer.read().address().expect("seals_internally already returned true");
@@ -503,34 +589,7 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
                 &proposer,
             ).map_err(Into::into)
         } else {
-            let vote_step = VoteStep::new(header.number() as usize, consensus_view(header)?, Step::Precommit);
-            let precommit_hash = message_hash(vote_step.clone(), header.bare_hash());
-            let ref signatures_field = header
-                .seal()
-                .get(2)
-                .expect("block went through verify_block_basic; block has .seal_fields() fields; qed");
-            let mut origins = HashSet::new();
-            for rlp in UntrustedRlp::new(signatures_field).iter() {
-                let precommit = ConsensusMessage {
-                    signature: rlp.as_val()?,
-                    block_hash: Some(header.bare_hash()),
-                    vote_step: vote_step.clone(),
-                };
-                let address = match self.votes.get(&precommit) {
-                    Some(a) => a,
-                    None => public_to_address(&recover_ecdsa(&precommit.signature.into(), &precommit_hash)?),
-                };
-                if !self.validators.contains(header.parent_hash(), &address) {
-                    return Err(EngineError::NotAuthorized(address.to_owned()).into())
-                }
-
-                if !origins.insert(address) {
-                    cwarn!(ENGINE, "verify_block_unordered: Duplicate signature from {} on the seal.", address);
-                    return Err(BlockError::InvalidSeal.into())
-                }
-            }
-
-            self.check_above_threshold(origins.len()).map_err(Into::into)
+            self.verify_commit_seal(header)
         }
     }
 
@@ -552,29 +611,7 @@ impl ConsensusEngine<CodeChainMachine> for Tendermint {
     }
 
     fn handle_message(&self, rlp: &[u8]) -> Result<(), EngineError> {
-        fn fmt_err<T: ::std::fmt::Debug>(x: T) -> EngineError {
-            EngineError::MalformedMessage(format!("{:?}", x))
-        }
-
-        let rlp = UntrustedRlp::new(rlp);
-        let message: ConsensusMessage = rlp.as_val().map_err(fmt_err)?;
-        if !self.votes.is_old_or_known(&message) {
-            let msg_hash = blake256(rlp.at(1).map_err(fmt_err)?.as_raw());
-            let sender = public_to_address(&recover_ecdsa(&message.signature.into(), &msg_hash).map_err(fmt_err)?);
-
-            if !self.is_authority(&sender) {
-                return Err(EngineError::NotAuthorized(sender))
-            }
-            self.broadcast_message(rlp.as_raw().to_vec());
-            if let Some(double) = self.votes.vote(message.clone(), sender) {
-                let height = message.vote_step.height as BlockNumber;
-                self.validators.report_malicious(&sender, height, height, ::rlp::encode(&double).into_vec());
-                return Err(EngineError::DoubleVote(sender))
-            }
-            ctrace!(ENGINE, "Handling a valid {:?} from {}.", message, sender);
-            self.handle_valid_message(&message);
-        }
-        Ok(())
+        self.handle_consensus_message(rlp).map(|_| ())
     }
 
     /// Equivalent to a timeout: to be used for tests.
@@ -750,8 +787,8 @@ where
         let mut addresses = HashSet::new();
         let ref header_signatures_field = header.seal().get(2).ok_or(BlockError::InvalidSeal)?;
         for rlp in UntrustedRlp::new(header_signatures_field).iter() {
-            let signature: H520 = rlp.as_val()?;
-            let address = (self.recover)(&signature.into(), &message)?;
+            let signature: Signature = rlp.as_val()?;
+            let address = (self.recover)(&signature, &message)?;
 
             if !self.subchain_validators.contains(header.parent_hash(), &address) {
                 return Err(EngineError::NotAuthorized(address.to_owned()).into())
@@ -759,17 +796,7 @@ where
             addresses.insert(address);
         }
 
-        let n = addresses.len();
-        let threshold = self.subchain_validators.len() * 2 / 3;
-        if n > threshold {
-            Ok(())
-        } else {
-            Err(EngineError::BadSealFieldSize(OutOfBounds {
-                min: Some(threshold),
-                max: None,
-                found: n,
-            }).into())
-        }
+        check_commit_weight(addresses.len(), self.subchain_validators.len()).map_err(Into::into)
     }
 
     fn check_finality_proof(&self, proof: &[u8]) -> Option<Vec<H256>> {
@@ -778,6 +805,21 @@ where
     }
 }
 
+/// Every validator has the same weight, so the weight of a commit is the number of its distinct signers.
+/// A commit must hold more than two-thirds of the total weight.
+fn check_commit_weight(achieved: usize, total: usize) -> Result<(), EngineError> {
+    let required = total * 2 / 3 + 1;
+    if achieved >= required {
+        Ok(())
+    } else {
+        Err(EngineError::InsufficientCommit(OutOfBounds {
+            min: Some(required),
+            max: None,
+            found: achieved,
+        }))
+    }
+}
+
 fn combine_proofs(signal_number: BlockNumber, set_proof: &[u8], finality_proof: &[u8]) -> Vec<u8> {
     let mut stream = ::rlp::RlpStream::new_list(3);
     stream.append(&signal_number).append(&set_proof).append(&finality_proof);
@@ -804,6 +846,7 @@ struct TendermintExtension {
     peers: RwLock<HashSet<NodeId>>,
     api: Mutex<Option<Arc<Api>>>,
     timeouts: TendermintTimeouts,
+    admission: Mutex<MessageAdmission>,
 }
 
 const MIN_PEERS_PROPAGATION: usize = 4;
@@ -817,6 +860,7 @@ impl TendermintExtension {
             peers: RwLock::new(HashSet::new()),
             api: Mutex::new(None),
             timeouts,
+            admission: Mutex::new(MessageAdmission::new()),
         }
     }
 
@@ -890,16 +934,44 @@ impl NetworkExtension for TendermintExtension {
 
     fn on_node_removed(&self, token: &NodeId) {
         self.peers.write().remove(token);
+        self.admission.lock().remove_peer(token);
     }
 
     fn on_message(&self, token: &NodeId, data: &[u8]) {
+        if self.admission.lock().is_banned(token) {
+            ctrace!(ENGINE, "Ignoring a message from the banned peer {}", token);
+            return
+        }
         let m = UntrustedRlp::new(data);
         match m.as_val() {
             Ok(TendermintMessage::ConsensusMessage(ref bytes)) => {
                 if let Some(ref weak) = *self.tendermint.read() {
                     if let Some(c) = weak.upgrade() {
-                        if let Err(e) = c.handle_message(bytes) {
-                            cinfo!(ENGINE, "Failed to handle message {:?}", e);
+                        let message: ConsensusMessage = match UntrustedRlp::new(bytes).as_val() {
+                            Ok(message) => message,
+                            Err(e) => {
+                                cinfo!(ENGINE, "Invalid consensus message from peer {}: {:?}", token, e);
+                                self.admission.lock().report(token, Violation::InvalidSignature);
+                                return
+                            }
+                        };
+                        let height = c.height.load(AtomicOrdering::SeqCst);
+                        let view = c.view.load(AtomicOrdering::SeqCst);
+                        match self.admission.lock().admit(token, &message, height, view, Instant::now()) {
+                            Verdict::Admitted => {}
+                            verdict => {
+                                ctrace!(ENGINE, "{:?} is not admitted: {:?}", message, verdict);
+                                return
+                            }
+                        }
+                        match c.handle_consensus_message(bytes) {
+                            Err(e @ EngineError::MalformedMessage(_)) | Err(e @ EngineError::NotAuthorized(_)) => {
+                                cinfo!(ENGINE, "Failed to handle message {:?}", e);
+                                self.admission.lock().report(token, Violation::InvalidSignature);
+                            }
+                            Err(e) => cinfo!(ENGINE, "Failed to handle message {:?}", e),
+                            Ok(Some(sender)) => self.admission.lock().record(sender, &message, height),
+                            Ok(None) => {}
                         }
                     }
                 }
@@ -941,9 +1013,48 @@ impl NetworkExtension for TendermintExtension {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use ckeys::{sign_ecdsa, Generator, KeyPair, Random};
+    use ctypes::U256;
+    use unexpected::OutOfBounds;
+
+    use super::super::super::codechain_machine::CodeChainMachine;
     use super::super::super::error::{BlockError, Error};
     use super::super::super::header::Header;
-    use super::super::super::spec::Spec;
+    use super::super::super::spec::{CommonParams, Spec};
+    use super::super::validator_set::new_validator_set;
+    use super::super::{ConsensusEngine, EngineError};
+    use super::message::{message_hash, VoteStep};
+    use super::{Step, Tendermint, TendermintParams, TendermintTimeouts};
+
+    fn engine_with_validators(validators: &[KeyPair]) -> Arc<Tendermint> {
+        let params = TendermintParams {
+            validators: new_validator_set(validators.iter().map(|keypair| keypair.address()).collect()),
+            timeouts: TendermintTimeouts::default(),
+            block_reward: U256::zero(),
+        };
+        Tendermint::new(params, CodeChainMachine::new(CommonParams::default())).unwrap()
+    }
+
+    fn committed_header(signers: &[&KeyPair]) -> Header {
+        let mut header = Header::default();
+        header.set_number(1);
+        let view = 0;
+        let precommit_hash = message_hash(VoteStep::new(1, view, Step::Precommit), header.bare_hash());
+        let precommits: Vec<_> =
+            signers.iter().map(|signer| sign_ecdsa(signer.private(), &precommit_hash).unwrap()).collect();
+        header.set_seal(vec![
+            ::rlp::encode(&view).into_vec(),
+            ::rlp::NULL_RLP.to_vec(),
+            ::rlp::encode_list(&precommits).into_vec(),
+        ]);
+        header
+    }
+
+    fn random_keypairs(n: usize) -> Vec<KeyPair> {
+        (0..n).map(|_| Random.generate().unwrap()).collect()
+    }
 
     #[test]
     fn has_valid_metadata() {
@@ -968,4 +1079,68 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn commit_with_exactly_the_threshold_weight_is_accepted() {
+        let validators = random_keypairs(4);
+        let engine = engine_with_validators(&validators);
+
+        let header = committed_header(&[&validators[0], &validators[1], &validators[2]]);
+        assert!(engine.verify_block_basic(&header).is_ok());
+        assert!(engine.verify_block_external(&header).is_ok());
+
+        let header = committed_header(&[&validators[3], &validators[1], &validators[0], &validators[2]]);
+        assert!(engine.verify_block_external(&header).is_ok());
+    }
+
+    #[test]
+    fn commit_below_the_threshold_weight_is_rejected() {
+        let validators = random_keypairs(4);
+        let engine = engine_with_validators(&validators);
+        let header = committed_header(&[&validators[0], &validators[1]]);
+        match engine.verify_block_external(&header) {
+            Err(Error::Engine(EngineError::InsufficientCommit(OutOfBounds {
+                min: Some(3),
+                max: None,
+                found: 2,
+            }))) => {}
+            result => panic!("should be insufficient commit error (got {:?})", result),
+        }
+
+        // Exactly two-thirds does not exceed two-thirds.
+        let validators = random_keypairs(3);
+        let engine = engine_with_validators(&validators);
+        let header = committed_header(&[&validators[0], &validators[1]]);
+        match engine.verify_block_external(&header) {
+            Err(Error::Engine(EngineError::InsufficientCommit(OutOfBounds {
+                min: Some(3),
+                max: None,
+                found: 2,
+            }))) => {}
+            result => panic!("should be insufficient commit error (got {:?})", result),
+        }
+    }
+
+    #[test]
+    fn commit_with_a_duplicate_signer_is_rejected() {
+        let validators = random_keypairs(4);
+        let engine = engine_with_validators(&validators);
+        let header = committed_header(&[&validators[0], &validators[1], &validators[1], &validators[2]]);
+        match engine.verify_block_external(&header) {
+            Err(Error::Block(BlockError::InvalidSeal)) => {}
+            result => panic!("should be invalid seal error (got {:?})", result),
+        }
+    }
+
+    #[test]
+    fn commit_with_a_non_validator_signer_is_rejected() {
+        let validators = random_keypairs(4);
+        let engine = engine_with_validators(&validators);
+        let outsider = Random.generate().unwrap();
+        let header = committed_header(&[&validators[0], &validators[1], &validators[2], &outsider]);
+        match engine.verify_block_external(&header) {
+            Err(Error::Engine(EngineError::NotAuthorized(address))) => assert_eq!(outsider.address(), address),
+            result => panic!("should be not authorized error (got {:?})", result),
+        }
+    }
 }
@@ -18,14 +18,14 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
 use std::hash::Hash;
 
-use ctypes::{Address, Bytes, H256, H520};
+use ctypes::{Address, Bytes, Signature, H256};
 use parking_lot::RwLock;
 use rlp::{Encodable, RlpStream};
 
 pub trait Message: Clone + PartialEq + Eq + Hash + Encodable + Debug {
     type Round: Clone + PartialEq + Eq + Hash + Default + Debug + Ord;
 
-    fn signature(&self) -> H520;
+    fn signature(&self) -> Signature;
 
     fn block_hash(&self) -> Option<H256>;
 
@@ -43,7 +43,7 @@ pub struct VoteCollector<M: Message> {
 #[derive(Debug, Default)]
 struct StepCollector<M: Message> {
     voted: HashMap<Address, M>,
-    block_votes: HashMap<Option<H256>, HashMap<H520, Address>>,
+    block_votes: HashMap<Option<H256>, HashMap<Signature, Address>>,
     messages: HashSet<M>,
 }
 
@@ -136,7 +136,7 @@ impl<M: Message + Default + Encodable + Debug> VoteCollector<M> {
     }
 
     /// Collects the signatures for a given round and hash.
-    pub fn round_signatures(&self, round: &M::Round, block_hash: &H256) -> Vec<H520> {
+    pub fn round_signatures(&self, round: &M::Round, block_hash: &H256) -> Vec<Signature> {
         let guard = self.votes.read();
         guard
             .get(round)
@@ -0,0 +1,274 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use rlp::{DecoderError, UntrustedRlp};
+
+use super::spec::CommonParams;
+
+/// Maximum number of seal fields in a header.
+const MAX_SEAL_FIELDS: usize = 16;
+/// Maximum size of the seal fields of a header in bytes.
+const MAX_SEAL_SIZE: usize = 64 * 1024;
+/// Maximum size of the header fields other than the extra data and the seal in bytes.
+const MAX_PLAIN_HEADER_SIZE: usize = 512;
+/// The number of header fields other than the seal.
+const HEADER_FIELDS: usize = 10;
+/// The index of the extra data in a header.
+const EXTRA_DATA_INDEX: usize = 8;
+/// The index of the shard roots in a header.
+const SHARD_ROOTS_INDEX: usize = 9;
+/// Size of an encoded shard root in bytes.
+const SHARD_ROOT_SIZE: usize = 33;
+/// The index of the number in a header.
+const NUMBER_INDEX: usize = 6;
+/// Minimum size of an encoded parcel in bytes.
+const MIN_PARCEL_SIZE: usize = 8;
+
+/// Bounds checked on untrusted RLP before it's decoded.
+///
+/// The checks only walk the RLP, so an oversized item is rejected without being materialized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodeLimits {
+    /// Maximum size of the extra data of a header.
+    pub max_extra_data_size: usize,
+    /// Maximum size of a block body.
+    pub max_body_size: usize,
+    /// Number of shards, i.e. the number of shard roots in a header.
+    pub shard_count: usize,
+}
+
+impl<'a> From<&'a CommonParams> for DecodeLimits {
+    fn from(params: &'a CommonParams) -> Self {
+        DecodeLimits {
+            max_extra_data_size: params.maximum_extra_data_size,
+            max_body_size: params.largest_max_body_size(),
+            shard_count: params.shard_count as usize,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Maximum size of an encoded header.
+    pub fn max_header_size(&self) -> usize {
+        MAX_PLAIN_HEADER_SIZE + self.max_extra_data_size + self.shard_count * SHARD_ROOT_SIZE + MAX_SEAL_SIZE
+    }
+
+    /// Maximum number of parcels in a block body.
+    pub fn max_parcels(&self) -> usize {
+        self.max_body_size / MIN_PARCEL_SIZE
+    }
+
+    /// Checks an encoded header.
+    pub fn check_header(&self, header: &UntrustedRlp) -> Result<(), DecoderError> {
+        if header.as_raw().len() > self.max_header_size() {
+            return Err(DecoderError::Custom("Header exceeds the maximum header size"))
+        }
+        if header.item_count()? > HEADER_FIELDS + MAX_SEAL_FIELDS {
+            return Err(DecoderError::Custom("Header exceeds the maximum number of seal fields"))
+        }
+        // The genesis header is not bound by the extra data limit.
+        let is_genesis = header.val_at::<u64>(NUMBER_INDEX)? == 0;
+        if !is_genesis && header.at(EXTRA_DATA_INDEX)?.size() > self.max_extra_data_size {
+            return Err(DecoderError::Custom("Header exceeds the maximum extra data size"))
+        }
+        if header.at(SHARD_ROOTS_INDEX)?.item_count()? > self.shard_count {
+            return Err(DecoderError::Custom("Header exceeds the number of shard roots"))
+        }
+        Ok(())
+    }
+
+    /// Checks an encoded list of headers.
+    pub fn check_headers(&self, headers: &UntrustedRlp) -> Result<(), DecoderError> {
+        for header in headers.iter() {
+            self.check_header(&header)?;
+        }
+        Ok(())
+    }
+
+    /// Checks an encoded parcel.
+    pub fn check_parcel(&self, parcel: &UntrustedRlp) -> Result<(), DecoderError> {
+        if parcel.as_raw().len() > self.max_body_size {
+            return Err(DecoderError::Custom("Parcel exceeds the maximum body size"))
+        }
+        Ok(())
+    }
+
+    /// Checks an encoded block body, i.e. a list of parcels.
+    pub fn check_body(&self, body: &UntrustedRlp) -> Result<(), DecoderError> {
+        if body.as_raw().len() > self.max_body_size {
+            return Err(DecoderError::Custom("Body exceeds the maximum body size"))
+        }
+        if body.item_count()? > self.max_parcels() {
+            return Err(DecoderError::Custom("Body exceeds the maximum number of parcels"))
+        }
+        Ok(())
+    }
+
+    /// Checks an encoded block, i.e. a header followed by a list of parcels.
+    pub fn check_block(&self, block: &UntrustedRlp) -> Result<(), DecoderError> {
+        if block.item_count()? != 2 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        self.check_header(&block.at(0)?)?;
+        self.check_body(&block.at(1)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ckeys::{Generator, Random};
+    use ctypes::H256;
+    use rlp::{self, RlpStream};
+
+    use super::super::header::Header;
+    use super::super::parcel::Parcel;
+    use super::*;
+
+    fn limits() -> DecodeLimits {
+        DecodeLimits {
+            max_extra_data_size: 32,
+            max_body_size: 1024,
+            shard_count: 1,
+        }
+    }
+
+    fn header(number: u64, extra_data: Vec<u8>) -> Vec<u8> {
+        let mut header = Header::new();
+        header.set_number(number);
+        header.set_extra_data(extra_data);
+        rlp::encode(&header).into_vec()
+    }
+
+    #[test]
+    fn accepts_headers_within_limits() {
+        let limits = limits();
+        assert_eq!(Ok(()), limits.check_header(&UntrustedRlp::new(&header(1, vec![0; 32]))));
+        // The genesis header may have a larger extra data.
+        assert_eq!(Ok(()), limits.check_header(&UntrustedRlp::new(&header(0, vec![0; 64]))));
+    }
+
+    #[test]
+    fn rejects_header_with_large_extra_data() {
+        let limits = limits();
+        let bytes = header(1, vec![0; 33]);
+        assert_eq!(
+            Err(DecoderError::Custom("Header exceeds the maximum extra data size")),
+            limits.check_header(&UntrustedRlp::new(&bytes))
+        );
+    }
+
+    #[test]
+    fn rejects_header_larger_than_the_header_size() {
+        let limits = limits();
+        let bytes = header(0, vec![0; limits.max_header_size()]);
+        assert_eq!(
+            Err(DecoderError::Custom("Header exceeds the maximum header size")),
+            limits.check_header(&UntrustedRlp::new(&bytes))
+        );
+    }
+
+    #[test]
+    fn rejects_header_with_too_many_seal_fields() {
+        let limits = limits();
+        let mut header = Header::new();
+        header.set_number(1);
+        header.set_seal(vec![rlp::NULL_RLP.to_vec(); MAX_SEAL_FIELDS + 1]);
+        let bytes = rlp::encode(&header).into_vec();
+        assert_eq!(
+            Err(DecoderError::Custom("Header exceeds the maximum number of seal fields")),
+            limits.check_header(&UntrustedRlp::new(&bytes))
+        );
+    }
+
+    #[test]
+    fn rejects_header_with_too_many_shard_roots() {
+        let limits = limits();
+        let mut header = Header::new();
+        header.set_number(1);
+        header.set_shard_roots(vec![H256::zero(); 1]);
+        assert_eq!(Ok(()), limits.check_header(&UntrustedRlp::new(&rlp::encode(&header))));
+
+        header.set_shard_roots(vec![H256::zero(); 2]);
+        assert_eq!(
+            Err(DecoderError::Custom("Header exceeds the number of shard roots")),
+            limits.check_header(&UntrustedRlp::new(&rlp::encode(&header)))
+        );
+    }
+
+    #[test]
+    fn rejects_body_with_too_many_empty_parcels() {
+        let limits = limits();
+        // Each empty list is one byte, so the body fits in the size limit but not in the count limit.
+        let count = limits.max_parcels() + 1;
+        let mut s = RlpStream::new_list(count);
+        for _ in 0..count {
+            s.begin_list(0);
+        }
+        let bytes = s.out();
+        assert!(bytes.len() <= limits.max_body_size);
+        assert_eq!(
+            Err(DecoderError::Custom("Body exceeds the maximum number of parcels")),
+            limits.check_body(&UntrustedRlp::new(&bytes))
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_body_before_decoding() {
+        let limits = limits();
+        // A list header claiming a huge payload is rejected by its size alone.
+        let mut s = RlpStream::new_list(1);
+        s.append(&vec![0u8; limits.max_body_size]);
+        let bytes = s.out();
+        assert_eq!(
+            Err(DecoderError::Custom("Body exceeds the maximum body size")),
+            limits.check_body(&UntrustedRlp::new(&bytes))
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_parcel() {
+        let limits = DecodeLimits {
+            max_extra_data_size: 32,
+            max_body_size: 16,
+            shard_count: 1,
+        };
+        let parcel = Parcel::default().sign(Random.generate().unwrap().private());
+        let bytes = rlp::encode(&parcel).into_vec();
+        assert!(bytes.len() > 16);
+        assert_eq!(
+            Err(DecoderError::Custom("Parcel exceeds the maximum body size")),
+            limits.check_parcel(&UntrustedRlp::new(&bytes))
+        );
+        assert_eq!(Ok(()), self::limits().check_parcel(&UntrustedRlp::new(&bytes)));
+    }
+
+    #[test]
+    fn checks_both_parts_of_a_block() {
+        let limits = limits();
+        let mut s = RlpStream::new_list(2);
+        s.append_raw(&header(1, vec![0; 33]), 1);
+        s.begin_list(0);
+        assert_eq!(
+            Err(DecoderError::Custom("Header exceeds the maximum extra data size")),
+            limits.check_block(&UntrustedRlp::new(&s.out()))
+        );
+
+        let mut s = RlpStream::new_list(2);
+        s.append_raw(&header(1, vec![]), 1);
+        s.begin_list(0);
+        assert_eq!(Ok(()), limits.check_block(&UntrustedRlp::new(&s.out())));
+    }
+}
@@ -107,6 +107,11 @@ impl Header {
         self.view().invoices_root()
     }
 
+    /// Returns the state roots of the shards.
+    pub fn shard_roots(&self) -> Vec<H256> {
+        self.view().shard_roots()
+    }
+
     /// Score of this block
     pub fn score(&self) -> U256 {
         self.view().score()
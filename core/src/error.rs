@@ -14,11 +14,13 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::error;
 use std::fmt;
 
 use cio::IoError;
 use ckeys::Error as KeyError;
 use ctypes::{Address, H256, U256};
+use rlp::DecoderError;
 use trie::TrieError;
 use unexpected::{Mismatch, OutOfBounds};
 use util_error::UtilError;
@@ -39,6 +41,8 @@ pub enum ImportError {
     AlreadyQueued,
     /// Already marked as bad from a previous import (could mean parent is bad).
     KnownBad,
+    /// The header is not in the canonical header chain.
+    UnknownHeader,
 }
 
 impl fmt::Display for ImportError {
@@ -47,12 +51,19 @@ impl fmt::Display for ImportError {
             ImportError::AlreadyInChain => "block already in chain",
             ImportError::AlreadyQueued => "block already in the block queue",
             ImportError::KnownBad => "block known to be bad",
+            ImportError::UnknownHeader => "block header not in the canonical header chain",
         };
 
         f.write_fmt(format_args!("Block import error ({})", msg))
     }
 }
 
+impl error::Error for ImportError {
+    fn description(&self) -> &str {
+        "Block import error"
+    }
+}
+
 /// Error dedicated to import block function
 #[derive(Debug)]
 pub enum BlockImportError {
@@ -61,7 +72,7 @@ pub enum BlockImportError {
     /// Block error
     Block(BlockError),
     /// Other error
-    Other(String),
+    Other(Box<Error>),
 }
 
 impl From<Error> for BlockImportError {
@@ -69,7 +80,31 @@ impl From<Error> for BlockImportError {
         match e {
             Error::Block(block_error) => BlockImportError::Block(block_error),
             Error::Import(import_error) => BlockImportError::Import(import_error),
-            _ => BlockImportError::Other(format!("other block import error: {:?}", e)),
+            _ => BlockImportError::Other(Box::new(e)),
+        }
+    }
+}
+
+impl fmt::Display for BlockImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockImportError::Import(err) => err.fmt(f),
+            BlockImportError::Block(err) => err.fmt(f),
+            BlockImportError::Other(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for BlockImportError {
+    fn description(&self) -> &str {
+        "Block import error"
+    }
+
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self {
+            BlockImportError::Import(err) => Some(err),
+            BlockImportError::Block(err) => Some(err),
+            BlockImportError::Other(err) => Some(&**err),
         }
     }
 }
@@ -98,6 +133,10 @@ pub enum BlockError {
     InvalidSeal,
     /// Invoices trie root header field is invalid.
     InvalidInvoicesRoot(Mismatch<H256>),
+    /// Number of shard roots in the header is invalid.
+    InvalidShardRootCount(Mismatch<usize>),
+    /// A shard root header field is invalid.
+    InvalidShardRoot(Mismatch<H256>),
     /// Timestamp header field is invalid.
     InvalidTimestamp(OutOfBounds<u64>),
     /// Timestamp header field is too far in future.
@@ -111,6 +150,10 @@ pub enum BlockError {
     RidiculousNumber(OutOfBounds<BlockNumber>),
     /// Too many parcels from a particular address.
     TooManyParcels(Address),
+    /// Block body is too large.
+    BodySizeOutOfBounds(OutOfBounds<usize>),
+    /// Block has too many parcels.
+    ParcelCountOutOfBounds(OutOfBounds<usize>),
     /// Parent given is unknown.
     UnknownParent(H256),
 }
@@ -129,6 +172,8 @@ impl fmt::Display for BlockError {
             MismatchedH256SealElement(mis) => format!("Seal element out of bounds: {}", mis),
             InvalidSeal => "Block has invalid seal.".into(),
             InvalidInvoicesRoot(mis) => format!("Invalid invoices trie root in header: {}", mis),
+            InvalidShardRootCount(mis) => format!("Invalid number of shard roots in header: {}", mis),
+            InvalidShardRoot(mis) => format!("Invalid shard root in header: {}", mis),
             InvalidTimestamp(oob) => format!("Invalid timestamp in header: {}", oob),
             TemporarilyInvalid(oob) => format!("Future timestamp in header: {}", oob),
             InvalidParentHash(mis) => format!("Invalid parent hash: {}", mis),
@@ -136,12 +181,20 @@ impl fmt::Display for BlockError {
             RidiculousNumber(oob) => format!("Implausible block number. {}", oob),
             UnknownParent(hash) => format!("Unknown parent: {}", hash),
             TooManyParcels(address) => format!("Too many parcels from: {}", address),
+            BodySizeOutOfBounds(oob) => format!("Block body too large. {}", oob),
+            ParcelCountOutOfBounds(oob) => format!("Too many parcels in block. {}", oob),
         };
 
         f.write_fmt(format_args!("Block error ({})", msg))
     }
 }
 
+impl error::Error for BlockError {
+    fn description(&self) -> &str {
+        "Block error"
+    }
+}
+
 #[derive(Debug)]
 /// General error type which should be capable of representing all errors in codechain
 pub enum Error {
@@ -149,6 +202,8 @@ pub enum Error {
     Client(ClientError),
     /// Error concerning a utility.
     Util(UtilError),
+    /// RLP decoding error.
+    Decoder(DecoderError),
     /// Error concerning block processing.
     Block(BlockError),
     /// Error concerning parcel processing.
@@ -177,6 +232,7 @@ impl fmt::Display for Error {
         match self {
             Error::Client(err) => err.fmt(f),
             Error::Util(err) => err.fmt(f),
+            Error::Decoder(err) => err.fmt(f),
             Error::Io(err) => err.fmt(f),
             Error::Block(err) => err.fmt(f),
             Error::Parcel(err) => err.fmt(f),
@@ -192,6 +248,34 @@ impl fmt::Display for Error {
     }
 }
 
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            Error::PowHashInvalid => "Invalid or out of date PoW hash",
+            Error::PowInvalid => "Invalid nonce or mishash",
+            _ => "CodeChain error",
+        }
+    }
+
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match self {
+            Error::Client(err) => Some(err),
+            Error::Util(err) => Some(err),
+            Error::Decoder(err) => Some(err),
+            Error::Block(err) => Some(err),
+            Error::Parcel(err) => Some(err),
+            Error::Import(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Engine(err) => Some(err),
+            Error::Key(err) => Some(err),
+            Error::Trie(err) => Some(err),
+            Error::PowHashInvalid | Error::PowInvalid => None,
+            Error::AccountProvider(err) => Some(err),
+            Error::Transaction(err) => Some(err),
+        }
+    }
+}
+
 impl From<ClientError> for Error {
     fn from(err: ClientError) -> Error {
         Error::Client(err)
@@ -228,9 +312,9 @@ impl From<KeyError> for Error {
     }
 }
 
-impl From<::rlp::DecoderError> for Error {
-    fn from(err: ::rlp::DecoderError) -> Error {
-        Error::Util(UtilError::from(err))
+impl From<DecoderError> for Error {
+    fn from(err: DecoderError) -> Error {
+        Error::Decoder(err)
     }
 }
 
@@ -251,7 +335,7 @@ impl From<BlockImportError> for Error {
         match err {
             BlockImportError::Block(e) => Error::Block(e),
             BlockImportError::Import(e) => Error::Import(e),
-            BlockImportError::Other(s) => Error::Util(UtilError::from(s)),
+            BlockImportError::Other(e) => *e,
         }
     }
 }
@@ -282,3 +366,52 @@ impl From<TransactionError> for Error {
         Error::Transaction(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as StdError;
+
+    use super::*;
+
+    #[test]
+    fn decoder_error_is_kept_as_the_cause() {
+        let err = Error::from(DecoderError::RlpIsTooShort);
+        match &err {
+            Error::Decoder(DecoderError::RlpIsTooShort) => {}
+            _ => panic!("The decoder error must not be flattened: {:?}", err),
+        }
+        let source = err.source().expect("Decoder error is the source");
+        assert_eq!(Some(&DecoderError::RlpIsTooShort), source.downcast_ref::<DecoderError>());
+        assert!(source.source().is_none());
+    }
+
+    #[test]
+    fn block_import_error_keeps_the_whole_error() {
+        let err = BlockImportError::from(Error::from(DecoderError::RlpExpectedToBeList));
+        {
+            let source = err.source().expect("The wrapped error is the source");
+            match source.downcast_ref::<Error>() {
+                Some(Error::Decoder(DecoderError::RlpExpectedToBeList)) => {}
+                _ => panic!("The whole error must be the source: {:?}", source),
+            }
+            let root = source.source().expect("The decoder error is the root source");
+            assert_eq!(Some(&DecoderError::RlpExpectedToBeList), root.downcast_ref::<DecoderError>());
+            assert!(root.source().is_none());
+        }
+        match Error::from(err) {
+            Error::Decoder(DecoderError::RlpExpectedToBeList) => {}
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn block_error_is_the_cause() {
+        let block_error = BlockError::UnknownParent(H256::zero());
+        let err = Error::from(block_error);
+        assert_eq!(Some(&block_error), err.source().unwrap().downcast_ref::<BlockError>());
+        match BlockImportError::from(err) {
+            BlockImportError::Block(BlockError::UnknownParent(hash)) => assert_eq!(H256::zero(), hash),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+}
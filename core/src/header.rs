@@ -34,7 +34,7 @@ pub enum Seal {
 }
 
 /// A block header.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Header {
     /// Parent hash.
     parent_hash: H256,
@@ -54,6 +54,8 @@ pub struct Header {
     state_root: H256,
     /// Block invoices root.
     invoices_root: H256,
+    /// State roots of the shards, indexed by shard id.
+    shard_roots: Vec<H256>,
 
     /// Block score.
     score: U256,
@@ -66,6 +68,23 @@ pub struct Header {
     bare_hash: RefCell<Option<H256>>,
 }
 
+impl PartialEq for Header {
+    /// Compare the header fields, ignoring the memoized hashes.
+    fn eq(&self, other: &Self) -> bool {
+        self.parent_hash == other.parent_hash
+            && self.timestamp == other.timestamp
+            && self.number == other.number
+            && self.author == other.author
+            && self.extra_data == other.extra_data
+            && self.parcels_root == other.parcels_root
+            && self.state_root == other.state_root
+            && self.invoices_root == other.invoices_root
+            && self.shard_roots == other.shard_roots
+            && self.score == other.score
+            && self.seal == other.seal
+    }
+}
+
 impl Default for Header {
     /// Create a new, default-valued, header.
     fn default() -> Self {
@@ -79,6 +98,7 @@ impl Default for Header {
             parcels_root: BLAKE_NULL_RLP,
             state_root: BLAKE_NULL_RLP,
             invoices_root: BLAKE_NULL_RLP,
+            shard_roots: vec![],
 
             score: U256::default(),
             seal: vec![],
@@ -133,6 +153,10 @@ impl Header {
     pub fn parcels_root(&self) -> &H256 {
         &self.parcels_root
     }
+    /// Get the shard roots field of the header.
+    pub fn shard_roots(&self) -> &[H256] {
+        &self.shard_roots
+    }
 
     /// Get the score field of the header.
     pub fn score(&self) -> &U256 {
@@ -142,6 +166,10 @@ impl Header {
     pub fn seal(&self) -> &[Bytes] {
         &self.seal
     }
+    /// Decode the seal field at `index`. Returns `None` if the field is missing or malformed.
+    pub fn seal_at<T: Decodable>(&self, index: usize) -> Option<T> {
+        self.seal.get(index).and_then(|field| UntrustedRlp::new(field).as_val().ok())
+    }
 
     /// Set the number field of the header.
     pub fn set_parent_hash(&mut self, a: H256) {
@@ -193,6 +221,11 @@ impl Header {
         self.invoices_root = a;
         self.note_dirty()
     }
+    /// Set the shard roots field of the header.
+    pub fn set_shard_roots(&mut self, a: Vec<H256>) {
+        self.shard_roots = a;
+        self.note_dirty()
+    }
 
     /// Set the score field of the header.
     pub fn set_score(&mut self, a: U256) {
@@ -234,7 +267,7 @@ impl Header {
     /// Place this header into an RLP stream `s`, optionally `with_seal`.
     pub fn stream_rlp(&self, s: &mut RlpStream, with_seal: Seal) {
         s.begin_list(
-            9 + match with_seal {
+            10 + match with_seal {
                 Seal::With => self.seal.len(),
                 _ => 0,
             },
@@ -248,6 +281,7 @@ impl Header {
         s.append(&self.number);
         s.append(&self.timestamp);
         s.append(&self.extra_data);
+        s.append_list(&self.shard_roots);
         if let Seal::With = with_seal {
             for b in &self.seal {
                 s.append_raw(b, 1);
@@ -276,7 +310,9 @@ impl Header {
 
 impl HeapSizeOf for Header {
     fn heap_size_of_children(&self) -> usize {
-        self.extra_data.heap_size_of_children() + self.seal.heap_size_of_children()
+        self.extra_data.heap_size_of_children()
+            + self.shard_roots.heap_size_of_children()
+            + self.seal.heap_size_of_children()
     }
 }
 
@@ -292,12 +328,13 @@ impl Decodable for Header {
             number: r.val_at(6)?,
             timestamp: cmp::min(r.val_at::<U256>(7)?, u64::max_value().into()).as_u64(),
             extra_data: r.val_at(8)?,
+            shard_roots: r.list_at(9)?,
             seal: vec![],
             hash: RefCell::new(Some(blake256(r.as_raw()))),
             bare_hash: RefCell::new(None),
         };
 
-        for i in 9..r.item_count()? {
+        for i in 10..r.item_count()? {
             blockheader.seal.push(r.at(i)?.as_raw().to_vec())
         }
 
@@ -332,3 +369,89 @@ impl ::machine::Header for Header {
         Header::number(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_with_seal(seal: Vec<Bytes>) -> Header {
+        let mut header = Header::new();
+        header.set_number(3);
+        header.set_timestamp(1_000);
+        header.set_extra_data(b"extra".to_vec());
+        header.set_shard_roots(vec![H256::from(7), BLAKE_NULL_RLP]);
+        header.set_seal(seal);
+        header
+    }
+
+    #[test]
+    fn bare_hash_excludes_exactly_the_seal_fields() {
+        let seal = vec![::rlp::encode(&7u64).into_vec(), ::rlp::encode(&H256::random()).into_vec()];
+        let header = header_with_seal(seal.clone());
+
+        let with_seal = header.rlp(Seal::With);
+        let without_seal = header.rlp(Seal::Without);
+        let full = UntrustedRlp::new(&with_seal);
+        let bare = UntrustedRlp::new(&without_seal);
+        assert_eq!(full.item_count().unwrap(), 10 + seal.len());
+        assert_eq!(bare.item_count().unwrap(), 10);
+        for i in 0..10 {
+            assert_eq!(full.at(i).unwrap().as_raw(), bare.at(i).unwrap().as_raw());
+        }
+        for (i, field) in seal.iter().enumerate() {
+            assert_eq!(full.at(10 + i).unwrap().as_raw(), &field[..]);
+        }
+
+        assert_eq!(header.hash(), blake256(&with_seal));
+        assert_eq!(header.bare_hash(), blake256(&without_seal));
+        assert_eq!(header.bare_hash(), header_with_seal(vec![]).bare_hash());
+    }
+
+    #[test]
+    fn mutation_invalidates_both_hashes() {
+        let mut header = header_with_seal(vec![::rlp::encode(&1u64).into_vec()]);
+        let hash = header.hash();
+        let bare_hash = header.bare_hash();
+
+        header.set_timestamp(1_001);
+        assert_ne!(hash, header.hash());
+        assert_ne!(bare_hash, header.bare_hash());
+
+        let hash = header.hash();
+        let bare_hash = header.bare_hash();
+        header.extra_data_mut().push(0);
+        assert_ne!(hash, header.hash());
+        assert_ne!(bare_hash, header.bare_hash());
+    }
+
+    #[test]
+    fn set_seal_changes_only_the_sealed_hash() {
+        let mut header = header_with_seal(vec![::rlp::encode(&1u64).into_vec()]);
+        let hash = header.hash();
+        let bare_hash = header.bare_hash();
+
+        header.set_seal(vec![::rlp::encode(&2u64).into_vec()]);
+        assert_ne!(hash, header.hash());
+        assert_eq!(bare_hash, header.bare_hash());
+        assert_eq!(header.hash(), header.rlp_blake(Seal::With));
+    }
+
+    #[test]
+    fn decoded_header_hashes_match() {
+        let header = header_with_seal(vec![::rlp::encode(&1u64).into_vec()]);
+        let decoded: Header = ::rlp::decode(&::rlp::encode(&header));
+        assert_eq!(header, decoded);
+        assert_eq!(header.hash(), decoded.hash());
+        assert_eq!(header.bare_hash(), decoded.bare_hash());
+    }
+
+    #[test]
+    fn seal_at_decodes_fields() {
+        let hash = H256::random();
+        let header = header_with_seal(vec![::rlp::encode(&7u64).into_vec(), ::rlp::encode(&hash).into_vec()]);
+        assert_eq!(header.seal_at::<u64>(0), Some(7));
+        assert_eq!(header.seal_at::<H256>(1), Some(hash));
+        assert_eq!(header.seal_at::<H256>(0), None);
+        assert_eq!(header.seal_at::<u64>(2), None);
+    }
+}
@@ -25,17 +25,21 @@ extern crate codechain_merkle as cmerkle;
 extern crate codechain_network as cnetwork;
 extern crate codechain_types as ctypes;
 extern crate codechain_vm as cvm;
+extern crate crossbeam;
 extern crate hashdb;
 extern crate heapsize;
 extern crate journaldb;
 extern crate kvdb;
 extern crate kvdb_memorydb;
 extern crate kvdb_rocksdb;
+#[macro_use]
+extern crate lazy_static;
 extern crate linked_hash_map;
 extern crate lru_cache;
 extern crate memorydb;
 extern crate multimap;
 extern crate num_cpus;
+extern crate panic_hook;
 extern crate patricia_trie as trie;
 extern crate rand;
 extern crate rlp;
@@ -46,6 +50,8 @@ extern crate parking_lot;
 extern crate rustc_hex;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(test)]
+extern crate serde_json;
 extern crate table;
 extern crate time;
 extern crate triehash;
@@ -63,6 +69,7 @@ mod client;
 mod codechain_machine;
 mod consensus;
 mod db;
+mod decode_limits;
 pub mod encoded;
 mod error;
 mod header;
@@ -86,22 +93,31 @@ mod tests;
 
 pub use account_provider::AccountProvider;
 pub use block::Block;
-pub use blockchain::ParcelInvoice;
+pub use blockchain::{Inconsistency, IndexDivergence, ParcelInvoice};
 pub use client::{
-    Balance, BlockChainClient, BlockInfo, ChainInfo, ChainNotify, Client, ImportBlock, Nonce, RegularKey,
-    TestBlockChainClient,
+    Balance, BlockChainClient, BlockImportOutcome, BlockInfo, ChainInfo, ChainNotify, Client, ClientConfig,
+    DatabaseColumnConfig, DatabaseCompactionProfile, DatabaseRecovery, ImportBlock, Nonce, ParcelInfo, RegularKey,
+    StateOrBlock, TestBlock, TestBlockChainClient, TestClientCall, TestClientFailure,
 };
-pub use db::COL_STATE;
+pub use consensus::{CodeChainEngine, ConsensusEngine};
+pub use db::{COL_STATE, NUM_COLUMNS};
+pub use decode_limits::DecodeLimits;
 pub use error::{BlockImportError, Error, ImportError};
 pub use header::{Header, Seal};
 pub use invoice::Invoice;
-pub use miner::{Miner, MinerOptions, MinerService};
+pub use miner::{
+    DumpedParcel, LocalParcelStatus, MemPoolDump, MemPoolMetrics, Miner, MinerOptions, MinerService,
+    OrderingStrategy, ParcelOrigin, ParcelsByOrigin, PooledParcel, RejectionReason, RemovalReason, SenderDump,
+    SenderIssues, SubsequentParcels, PARCEL_AGE_BUCKETS,
+};
 pub use parcel::{
-    parcel_error_message, Action, AssetOutPoint, AssetTransferInput, AssetTransferOutput, LocalizedParcel, Parcel,
-    SignedParcel, UnverifiedParcel,
+    clear_sender_cache, parcel_error_message, recover_senders, Action, ActionKind, AssetOutPoint, AssetTransferInput,
+    AssetTransferOutput, LocalizedParcel, Parcel, ParcelError, SignedParcel, UnverifiedParcel,
 };
 pub use service::ClientService;
-pub use spec::Spec;
-pub use state::{Asset, AssetAddress, AssetScheme, AssetSchemeAddress, ShardStateInfo, TopStateInfo};
+pub use spec::{CommonParams, ParamsTransition, Spec};
+pub use state::{
+    Account, Asset, AssetAddress, AssetScheme, AssetSchemeAddress, Shard, ShardStateInfo, TopStateInfo,
+};
 pub use transaction::{Error as TransactionError, Transaction};
-pub use types::{BlockId, BlockNumber, ParcelId};
+pub use types::{BlockId, BlockNumber, BlockStatus, MemoryInfo, ParcelId};
@@ -0,0 +1,140 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+#[derive(Default)]
+struct Counters {
+    mem_pool: u64,
+    chain_head: u64,
+}
+
+/// Counts the changes of the mem pool and the moves of the chain head,
+/// so that a cache built from them can tell whether it is stale or wait until it is.
+#[derive(Default)]
+pub struct Changes {
+    counters: Mutex<Counters>,
+    changed: Condvar,
+}
+
+impl Changes {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a counter which is increased whenever the parcels in the mem pool change.
+    pub fn mem_pool_generation(&self) -> u64 {
+        self.counters.lock().mem_pool
+    }
+
+    /// Must be called while the mem pool is locked for the change, so that nobody sees
+    /// the changed parcels with the old generation.
+    pub fn mem_pool_changed(&self) {
+        self.counters.lock().mem_pool += 1;
+        self.changed.notify_all();
+    }
+
+    pub fn chain_head_moved(&self) {
+        self.counters.lock().chain_head += 1;
+        self.changed.notify_all();
+    }
+
+    /// Waits until the mem pool generation is not `generation` anymore or the chain head moves,
+    /// at most for `timeout`. Returns the mem pool generation.
+    pub fn wait_for_change(&self, generation: u64, timeout: Duration) -> u64 {
+        let deadline = Instant::now() + timeout;
+        let mut counters = self.counters.lock();
+        let chain_head = counters.chain_head;
+        while counters.mem_pool == generation && counters.chain_head == chain_head {
+            if self.changed.wait_until(&mut counters, deadline).timed_out() {
+                break
+            }
+        }
+        counters.mem_pool
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    use super::*;
+
+    fn long_enough() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    #[test]
+    fn waiting_times_out_without_changes() {
+        let changes = Changes::new();
+        changes.mem_pool_changed();
+        assert_eq!(1, changes.wait_for_change(1, Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn stale_generation_returns_immediately() {
+        let changes = Changes::new();
+        changes.mem_pool_changed();
+        let started = Instant::now();
+        assert_eq!(1, changes.wait_for_change(0, long_enough()));
+        assert!(started.elapsed() < long_enough());
+    }
+
+    #[test]
+    fn moving_the_chain_head_wakes_the_waiter() {
+        let changes = Arc::new(Changes::new());
+        let waiter = {
+            let changes = Arc::clone(&changes);
+            thread::spawn(move || {
+                let started = Instant::now();
+                changes.wait_for_change(0, long_enough());
+                started.elapsed()
+            })
+        };
+        thread::sleep(Duration::from_millis(10));
+        changes.chain_head_moved();
+        assert!(waiter.join().unwrap() < long_enough());
+    }
+
+    #[test]
+    fn no_change_racing_the_waiters_is_lost() {
+        let changes = Arc::new(Changes::new());
+        for round in 0..200 {
+            let barrier = Arc::new(Barrier::new(4));
+            let waiters = (0..3)
+                .map(|_| {
+                    let changes = Arc::clone(&changes);
+                    let barrier = Arc::clone(&barrier);
+                    thread::spawn(move || {
+                        barrier.wait();
+                        let started = Instant::now();
+                        (changes.wait_for_change(round, long_enough()), started.elapsed())
+                    })
+                })
+                .collect::<Vec<_>>();
+            barrier.wait();
+            changes.mem_pool_changed();
+            for waiter in waiters {
+                let (generation, waited) = waiter.join().unwrap();
+                assert_eq!(round + 1, generation);
+                assert!(waited < long_enough(), "A waiter missed the change of round {}", round);
+            }
+        }
+    }
+}
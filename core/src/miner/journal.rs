@@ -0,0 +1,262 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{rename, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ctypes::H256;
+use kvdb::{DBTransaction, KeyValueDB};
+use rlp::{self, RlpStream, UntrustedRlp};
+
+use super::super::db::COL_MEM_POOL;
+use super::super::parcel::{SignedParcel, UnverifiedParcel};
+
+/// Keeps the local parcels of the mem pool in a file, so that they survive a restart.
+///
+/// The whole list is rewritten whenever it changes. It is written and synced to a temporary file first,
+/// so the journal is never left half-written.
+pub struct LocalParcelsJournal {
+    path: PathBuf,
+    /// The hashes of the parcels in the file.
+    written: Vec<H256>,
+}
+
+impl LocalParcelsJournal {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            written: Vec::new(),
+        }
+    }
+
+    /// Reads the parcels in the journal.
+    /// A missing journal is read as empty, and so is a corrupted one after a warning.
+    pub fn load(&mut self) -> Vec<UnverifiedParcel> {
+        let mut bytes = Vec::new();
+        match File::open(&self.path).and_then(|mut file| file.read_to_end(&mut bytes)) {
+            Ok(_) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            Err(err) => {
+                cwarn!(MINER, "Cannot read the local parcels from {:?}: {}", self.path, err);
+                return Vec::new()
+            }
+        }
+        let parcels: Vec<UnverifiedParcel> = match UntrustedRlp::new(&bytes).as_list() {
+            Ok(parcels) => parcels,
+            Err(err) => {
+                cwarn!(MINER, "The local parcels in {:?} are corrupted: {:?}", self.path, err);
+                return Vec::new()
+            }
+        };
+        self.written = parcels.iter().map(|parcel| parcel.hash()).collect();
+        parcels
+    }
+
+    /// Writes `parcels` to the journal unless they are the ones written already.
+    pub fn save(&mut self, parcels: &[SignedParcel]) {
+        let hashes: Vec<H256> = parcels.iter().map(|parcel| parcel.hash()).collect();
+        if hashes == self.written {
+            return
+        }
+        match self.write(parcels) {
+            Ok(()) => self.written = hashes,
+            Err(err) => cwarn!(MINER, "Cannot write the local parcels to {:?}: {}", self.path, err),
+        }
+    }
+
+    fn write(&self, parcels: &[SignedParcel]) -> io::Result<()> {
+        let mut stream = RlpStream::new_list(parcels.len());
+        for parcel in parcels {
+            stream.append(parcel);
+        }
+        write_atomically(&self.path, &stream.out())
+    }
+}
+
+/// Writes `bytes` to a temporary file first, so the file at `path` is never left half-written.
+/// The temporary file is synced before the rename, so a crash can't leave an empty file in place of the old one.
+fn write_atomically(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    rename(&temp_path, path)
+}
+
+/// The parcels which entered or left the mem pool since they were written to the journal.
+#[derive(Default)]
+pub struct MemPoolChanges {
+    added: HashMap<H256, SignedParcel>,
+    removed: HashSet<H256>,
+}
+
+impl MemPoolChanges {
+    pub fn add(&mut self, parcel: &SignedParcel) {
+        self.added.insert(parcel.hash(), parcel.clone());
+    }
+
+    pub fn remove(&mut self, hash: &H256) {
+        // A parcel added after the last write isn't in the journal yet.
+        if self.added.remove(hash).is_none() {
+            self.removed.insert(*hash);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Keeps all the parcels of the mem pool in a column of the database,
+/// so that a restarted node doesn't start with an empty pool.
+///
+/// A parcel is put when it enters the pool and deleted when it leaves,
+/// so only the changes of the pool are written.
+pub struct MemPoolJournal {
+    db: Arc<KeyValueDB>,
+}
+
+impl MemPoolJournal {
+    pub fn new(db: Arc<KeyValueDB>) -> Self {
+        Self {
+            db,
+        }
+    }
+
+    /// Returns the parcels in the journal in the ascending order of their nonces,
+    /// so that the parcels of a sender are imported in order. The corrupted ones are skipped after a warning.
+    pub fn load(&self) -> Vec<UnverifiedParcel> {
+        let mut parcels: Vec<UnverifiedParcel> = self.db
+            .iter(COL_MEM_POOL)
+            .filter_map(|(_, value)| match UntrustedRlp::new(&value).as_val() {
+                Ok(parcel) => Some(parcel),
+                Err(err) => {
+                    cwarn!(MINER, "A parcel in the mem pool journal is corrupted: {:?}", err);
+                    None
+                }
+            })
+            .collect();
+        parcels.sort_by_key(|parcel| parcel.nonce);
+        parcels
+    }
+
+    /// Writes the parcels which entered or left the mem pool.
+    pub fn write(&self, changes: MemPoolChanges) {
+        let mut batch = DBTransaction::new();
+        for hash in changes.removed.iter().filter(|hash| !changes.added.contains_key(hash)) {
+            batch.delete(COL_MEM_POOL, hash);
+        }
+        for (hash, parcel) in &changes.added {
+            batch.put(COL_MEM_POOL, hash, &rlp::encode(parcel));
+        }
+        if let Err(err) = self.db.write(batch) {
+            cwarn!(MINER, "Cannot write to the mem pool journal: {}", err);
+        }
+    }
+
+    /// Deletes the parcels of `hashes`, e.g. the ones discarded when the pool is recovered.
+    pub fn delete(&self, hashes: &[H256]) {
+        let mut changes = MemPoolChanges::default();
+        changes.removed.extend(hashes);
+        self.write(changes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::remove_file;
+
+    use ckeys::{Generator, Random};
+    use ctypes::{Address, U256};
+    use kvdb_memorydb;
+
+    use super::super::super::db::NUM_COLUMNS;
+    use super::super::super::parcel::{Action, Parcel};
+    use super::super::mem_pool::{AccountDetails, MemPool, ParcelOrigin};
+    use super::*;
+
+    fn parcel(nonce: u64) -> SignedParcel {
+        let keypair = Random.generate().unwrap();
+        Parcel {
+            nonce: U256::from(nonce),
+            fee: U256::from(10),
+            network_id: 17,
+            action: Action::ChangeShardState {
+                transactions: vec![],
+            },
+        }.sign(keypair.private())
+    }
+
+    #[test]
+    fn saved_parcels_are_loaded_again() {
+        let path = env::temp_dir().join(format!("codechain-journal-{}", H256::random()));
+        let parcels = vec![parcel(0), parcel(1)];
+        LocalParcelsJournal::new(path.clone()).save(&parcels);
+
+        let loaded = LocalParcelsJournal::new(path.clone()).load();
+        let expected: Vec<UnverifiedParcel> = parcels.into_iter().map(Into::into).collect();
+        assert_eq!(expected, loaded);
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mem_pool_journal_keeps_the_parcels_in_the_pool() {
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: U256::max_value(),
+        };
+        let db: Arc<KeyValueDB> = Arc::new(kvdb_memorydb::create(NUM_COLUMNS.unwrap_or(0)));
+        let journal = MemPoolJournal::new(Arc::clone(&db));
+        let mut pool = MemPool::new();
+        pool.record_journal_changes();
+        let (kept, removed, added, transient) = (parcel(0), parcel(0), parcel(0), parcel(0));
+        pool.add(kept.clone(), ParcelOrigin::External, 0, &fetch_account).unwrap();
+        pool.add(removed.clone(), ParcelOrigin::External, 0, &fetch_account).unwrap();
+        journal.write(pool.take_journal_changes().unwrap());
+
+        assert_eq!(vec![removed.hash()], pool.remove_all_from_sender(&removed.sender()));
+        pool.add(added.clone(), ParcelOrigin::External, 0, &fetch_account).unwrap();
+        // The parcel which enters and leaves the pool between the writes is never written.
+        pool.add(transient.clone(), ParcelOrigin::External, 0, &fetch_account).unwrap();
+        assert_eq!(vec![transient.hash()], pool.remove_all_from_sender(&transient.sender()));
+        journal.write(pool.take_journal_changes().unwrap());
+
+        let loaded: Vec<H256> = journal.load().iter().map(|parcel| parcel.hash()).collect();
+        assert_eq!(2, loaded.len());
+        assert!(loaded.contains(&kept.hash()));
+        assert!(loaded.contains(&added.hash()));
+
+        // The corrupted parcel is skipped.
+        let mut batch = DBTransaction::new();
+        batch.put(COL_MEM_POOL, &H256::random(), b"not rlp");
+        db.write(batch).unwrap();
+        assert_eq!(2, MemPoolJournal::new(db).load().len());
+    }
+
+    #[test]
+    fn missing_or_corrupted_journal_is_read_as_empty() {
+        let path = env::temp_dir().join(format!("codechain-journal-{}", H256::random()));
+        assert_eq!(Vec::<UnverifiedParcel>::new(), LocalParcelsJournal::new(path.clone()).load());
+
+        File::create(&path).unwrap().write_all(b"not rlp").unwrap();
+        assert_eq!(Vec::<UnverifiedParcel>::new(), LocalParcelsJournal::new(path.clone()).load());
+        remove_file(path).unwrap();
+    }
+}
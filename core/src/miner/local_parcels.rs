@@ -40,6 +40,10 @@ pub enum Status {
     Invalid(SignedParcel),
     /// Parcel was canceled.
     Canceled(SignedParcel),
+    /// Parcel stayed in the mem pool too long.
+    Expired(SignedParcel),
+    /// The sender of the parcel is banned.
+    Banned(SignedParcel),
 }
 
 impl Status {
@@ -48,6 +52,9 @@ impl Status {
     }
 }
 
+/// The number of the parcels no longer in the queue which are kept in the list.
+const MAX_OLD_PARCELS: usize = 1024;
+
 /// Keeps track of local parcels that are in the queue or were mined/dropped recently.
 #[derive(Debug)]
 pub struct LocalParcelsList {
@@ -57,7 +64,7 @@ pub struct LocalParcelsList {
 
 impl Default for LocalParcelsList {
     fn default() -> Self {
-        Self::new(10)
+        Self::new(MAX_OLD_PARCELS)
     }
 }
 
@@ -118,6 +125,20 @@ impl LocalParcelsList {
         self.clear_old();
     }
 
+    /// Mark parcel as removed because its sender is banned.
+    pub fn mark_banned(&mut self, signed: SignedParcel) {
+        cwarn!(OWN_PARCEL, "Parcel of a banned sender removed (hash {:?})", signed.hash());
+        self.parcels.insert(signed.hash(), Status::Banned(signed));
+        self.clear_old();
+    }
+
+    /// Mark parcel as expired.
+    pub fn mark_expired(&mut self, signed: SignedParcel) {
+        cwarn!(OWN_PARCEL, "Parcel expired (hash {:?})", signed.hash());
+        self.parcels.insert(signed.hash(), Status::Expired(signed));
+        self.clear_old();
+    }
+
     /// Mark parcel as dropped because of limit.
     pub fn mark_dropped(&mut self, signed: SignedParcel) {
         cwarn!(OWN_PARCEL, "Parcel dropped (hash {:?})", signed.hash());
@@ -16,29 +16,52 @@
 
 use std::cmp;
 use std::cmp::Ordering;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::ops::Deref;
+use std::sync::Arc;
 
 use ctypes::{Address, H256, U256};
 use heapsize::HeapSizeOf;
 use linked_hash_map::LinkedHashMap;
 use multimap::MultiMap;
+use rlp::Encodable;
 use table::Table;
 
-use super::super::parcel::{Action, ParcelError, SignedParcel};
+use super::super::parcel::{Action, ActionKind, ParcelError, SignedParcel};
 use super::super::types::BlockNumber;
 use super::local_parcels::{LocalParcelsList, Status as LocalParcelStatus};
-use super::ParcelImportResult;
+use super::changes::Changes;
+use super::journal::MemPoolChanges;
+use super::ranked_set::RankedSet;
+use super::{OrderingStrategy, ParcelImportResult, ParcelsByOrigin, PooledParcel, SenderIssues, SubsequentParcels};
 
 /// Parcel with the same (sender, nonce) can be replaced only if
-/// `new_fee > old_fee + old_fee >> SHIFT`
-const FEE_BUMP_SHIFT: usize = 3; // 2 = 25%, 3 = 12.5%, 4 = 6.25%
+/// `new_fee >= old_fee + old_fee * PERMILLE / 1000` and `new_fee > old_fee`
+pub const DEFAULT_FEE_BUMP_PERMILLE: u64 = 125; // 12.5%
+
+/// The number of missing nonces listed in `SenderIssues` at most.
+const MAX_LISTED_GAPS: usize = 1024;
+
+/// The fewest pending parcels from which `MemPool::fee_percentile` estimates a fee.
+const MIN_PARCELS_FOR_FEE_ESTIMATE: usize = 4;
 
 /// Point in time when parcel was inserted.
 pub type PoolingInstant = BlockNumber;
-const DEFAULT_POOLING_PERIOD: BlockNumber = 128;
+/// The number of blocks an external parcel can stay in the pool.
+pub const DEFAULT_POOLING_PERIOD: BlockNumber = 128;
+/// The number of blocks a local parcel can stay in the pool.
+pub const DEFAULT_LOCAL_POOLING_PERIOD: BlockNumber = 1024;
+/// How far the nonce of an external parcel can be ahead of the nonce of its sender in the state.
+pub const DEFAULT_MAX_NONCE_GAP: u64 = 128;
+/// How far the nonce of a local parcel can be ahead of the nonce of its sender in the state.
+pub const DEFAULT_LOCAL_MAX_NONCE_GAP: u64 = 1024;
 
 /// Parcel origin
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+///
+/// The origins are ordered by their priority: `Local < RetractedBlock < External`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub enum ParcelOrigin {
     /// Parcel coming from local RPC
     Local,
@@ -48,6 +71,32 @@ pub enum ParcelOrigin {
     RetractedBlock,
 }
 
+impl ParcelsByOrigin {
+    fn count_mut(&mut self, origin: ParcelOrigin) -> &mut usize {
+        match origin {
+            ParcelOrigin::Local => &mut self.local,
+            ParcelOrigin::External => &mut self.external,
+            ParcelOrigin::RetractedBlock => &mut self.retracted,
+        }
+    }
+
+    fn add(&mut self, origin: ParcelOrigin) {
+        *self.count_mut(origin) += 1;
+    }
+
+    fn sub(&mut self, origin: ParcelOrigin) {
+        *self.count_mut(origin) -= 1;
+    }
+
+    fn sum(&self, other: &ParcelsByOrigin) -> ParcelsByOrigin {
+        ParcelsByOrigin {
+            local: self.local + other.local,
+            external: self.external + other.external,
+            retracted: self.retracted + other.retracted,
+        }
+    }
+}
+
 impl PartialOrd for ParcelOrigin {
     fn partial_cmp(&self, other: &ParcelOrigin) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -56,23 +105,26 @@ impl PartialOrd for ParcelOrigin {
 
 impl Ord for ParcelOrigin {
     fn cmp(&self, other: &ParcelOrigin) -> Ordering {
-        if *other == *self {
-            return Ordering::Equal
-        }
-
-        match (*self, *other) {
-            (ParcelOrigin::RetractedBlock, _) => Ordering::Less,
-            (_, ParcelOrigin::RetractedBlock) => Ordering::Greater,
-            (ParcelOrigin::Local, _) => Ordering::Less,
-            _ => Ordering::Greater,
-        }
+        self.rank().cmp(&other.rank())
     }
 }
 
 impl ParcelOrigin {
+    fn rank(&self) -> u8 {
+        match self {
+            ParcelOrigin::Local => 0,
+            ParcelOrigin::RetractedBlock => 1,
+            ParcelOrigin::External => 2,
+        }
+    }
+
     fn is_local(&self) -> bool {
         *self == ParcelOrigin::Local
     }
+
+    fn is_own_or_retracted(&self) -> bool {
+        self.is_local() || *self == ParcelOrigin::RetractedBlock
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -86,23 +138,32 @@ struct ParcelOrder {
     fee: U256,
     /// Heap usage of this parcel.
     mem_usage: usize,
+    /// The size of the RLP encoded parcel.
+    encoded_size: usize,
+    /// The fee and the value the sender pays for the parcel.
+    cost: U256,
     /// Hash to identify associated parcel
     hash: H256,
     /// Incremental id assigned when parcel is inserted to the pool.
     insertion_id: u64,
     /// Origin of the parcel
     origin: ParcelOrigin,
+    /// How the fee is compared
+    ordering: OrderingStrategy,
 }
 
 impl ParcelOrder {
-    fn for_parcel(item: &MemPoolItem, base_nonce: U256) -> Self {
+    fn for_parcel(item: &MemPoolItem, base_nonce: U256, ordering: OrderingStrategy) -> Self {
         Self {
             nonce_height: item.nonce() - base_nonce,
             fee: item.parcel.fee,
             mem_usage: item.parcel.heap_size_of_children(),
+            encoded_size: item.encoded_size,
+            cost: item.cost(),
             hash: item.hash(),
             insertion_id: item.insertion_id,
             origin: item.origin,
+            ordering,
         }
     }
 
@@ -137,8 +198,20 @@ impl Ord for ParcelOrder {
         }
 
         // Then compare fee
-        if self.fee != b.fee {
-            return b.fee.cmp(&self.fee)
+        match self.ordering {
+            OrderingStrategy::Fee => {
+                if self.fee != b.fee {
+                    return b.fee.cmp(&self.fee)
+                }
+            }
+            OrderingStrategy::FeePerByte => {
+                // Multiplied crosswise not to lose the precision by the division
+                let fee_per_byte = self.fee.full_mul(b.encoded_size.into());
+                let other_fee_per_byte = b.fee.full_mul(self.encoded_size.into());
+                if fee_per_byte != other_fee_per_byte {
+                    return other_fee_per_byte.cmp(&fee_per_byte)
+                }
+            }
         }
 
         // Lastly compare insertion_id
@@ -157,11 +230,14 @@ struct MemPoolItem {
     insertion_time: PoolingInstant,
     /// ID assigned upon insertion, should be unique.
     insertion_id: u64,
+    /// The size of the RLP encoded parcel.
+    encoded_size: usize,
 }
 
 impl MemPoolItem {
     fn new(parcel: SignedParcel, origin: ParcelOrigin, insertion_time: PoolingInstant, insertion_id: u64) -> Self {
         MemPoolItem {
+            encoded_size: parcel.rlp_bytes().len(),
             parcel,
             origin,
             insertion_time,
@@ -182,23 +258,83 @@ impl MemPoolItem {
     }
 
     fn cost(&self) -> U256 {
-        match &self.parcel.action {
-            Action::Payment {
-                value,
-                ..
-            } => self.parcel.fee + *value,
-            _ => self.parcel.fee,
+        cost_of(&self.parcel)
+    }
+}
+
+/// All the parcels in the pool indexed by hash.
+/// The parcels entering and leaving the pool are recorded here for the journals.
+struct ParcelsByHash {
+    items: HashMap<H256, MemPoolItem>,
+    /// The changes not taken by the mem pool journal yet. They are not recorded if it's `None`.
+    journal_changes: Option<MemPoolChanges>,
+    /// Set when a local parcel enters or leaves the pool, until the local parcels journal takes them.
+    local_parcels_changed: bool,
+}
+
+impl ParcelsByHash {
+    fn new() -> Self {
+        ParcelsByHash {
+            items: HashMap::new(),
+            journal_changes: None,
+            local_parcels_changed: false,
         }
     }
+
+    fn insert(&mut self, hash: H256, item: MemPoolItem) -> Option<MemPoolItem> {
+        if let Some(ref mut changes) = self.journal_changes {
+            changes.add(&item.parcel);
+        }
+        self.local_parcels_changed |= item.origin.is_local();
+        self.items.insert(hash, item)
+    }
+
+    fn remove(&mut self, hash: &H256) -> Option<MemPoolItem> {
+        let removed = self.items.remove(hash)?;
+        if let Some(ref mut changes) = self.journal_changes {
+            changes.remove(hash);
+        }
+        self.local_parcels_changed |= removed.origin.is_local();
+        Some(removed)
+    }
+
+    fn clear(&mut self) {
+        if let Some(ref mut changes) = self.journal_changes {
+            for hash in self.items.keys() {
+                changes.remove(hash);
+            }
+        }
+        self.local_parcels_changed |= self.items.values().any(|item| item.origin.is_local());
+        self.items.clear();
+    }
+}
+
+impl Deref for ParcelsByHash {
+    type Target = HashMap<H256, MemPoolItem>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
 }
 
 /// Holds parcels accessible by (address, nonce) and by priority
 struct ParcelSet {
-    by_priority: BTreeSet<ParcelOrder>,
+    by_priority: RankedSet<ParcelOrder>,
     by_address: Table<Address, U256, ParcelOrder>,
     by_fee: MultiMap<U256, H256>,
     limit: usize,
-    memory_limit: usize,
+    /// Heap memory used by the parcels in this set, updated on every insertion and removal
+    mem_usage: usize,
+    /// The size of the RLP encoded parcels in this set, updated on every insertion and removal
+    encoded_size: usize,
+    /// The number of the parcels in this set by their origins, updated on every insertion and removal
+    by_origin: ParcelsByOrigin,
+    /// The total cost of the parcels in this set by their senders, updated on every insertion and removal
+    cost_by_sender: HashMap<Address, U256>,
+    /// A parcel replacing another one with the same (sender, nonce) must pay `fee_bump_permille / 1000` more fee
+    fee_bump_permille: u64,
+    /// How the parcels in `by_priority` are ordered
+    ordering: OrderingStrategy,
 }
 
 impl ParcelSet {
@@ -211,8 +347,16 @@ impl ParcelSet {
         }
         let order_hash = order.hash.clone();
         let order_fee = order.fee.clone();
+        self.mem_usage += order.mem_usage;
+        self.encoded_size += order.encoded_size;
+        self.by_origin.add(order.origin);
+        add_cost(&mut self.cost_by_sender, sender, order.cost);
         let by_address_replaced = self.by_address.insert(sender, nonce, order);
         if let Some(ref old_order) = by_address_replaced {
+            self.mem_usage -= old_order.mem_usage;
+            self.encoded_size -= old_order.encoded_size;
+            self.by_origin.sub(old_order.origin);
+            sub_cost(&mut self.cost_by_sender, &sender, old_order.cost);
             assert!(
                 self.by_priority.remove(old_order),
                 "hash is in `by_address`; all parcels in `by_address` must be in `by_priority`; qed"
@@ -228,31 +372,38 @@ impl ParcelSet {
         by_address_replaced
     }
 
-    /// Remove low priority parcels if there is more than specified by given `limit`.
+    /// The number of parcels ordered before `order`.
+    fn position(&self, order: &ParcelOrder) -> usize {
+        self.by_priority.rank(order)
+    }
+
+    /// Remove the cheapest parcels if there is more than specified by given `limit`.
+    /// It is used for `future`; the parcels over the limit of `current` are moved to `future` instead.
+    /// The memory limit is enforced by `MemPool::enforce_memory_limit` over both sets.
     ///
     /// It drops parecls from this set but also removes associated `VerifiedParcel`.
     /// Returns addresses and lowest nonces of parcels removed because of limit.
     fn enforce_limit(
         &mut self,
-        by_hash: &mut HashMap<H256, MemPoolItem>,
+        by_hash: &mut ParcelsByHash,
         local: &mut LocalParcelsList,
+        metrics: &mut MemPoolMetrics,
     ) -> Option<HashMap<Address, U256>> {
-        let mut count = 0;
-        let mut mem_usage = 0;
         let to_drop: Vec<(Address, U256)> = {
-            self.by_priority
+            let excess = self.by_priority.len().saturating_sub(self.limit);
+            let mut candidates: Vec<_> = self.by_priority
                 .iter()
-                .filter(|order| {
-                    // update parcel count and mem usage
-                    count += 1;
-                    mem_usage += order.mem_usage;
-
-                    let is_own_or_retracted = order.origin.is_local() || order.origin == ParcelOrigin::RetractedBlock;
-                    // Own and retracted parcels are allowed to go above all limits.
-                    !is_own_or_retracted && (mem_usage > self.memory_limit || count > self.limit)
-                })
-                .map(|order| {
-                    by_hash.get(&order.hash).expect(
+                // Own and retracted parcels are allowed to go above all limits.
+                .filter(|order| !order.origin.is_own_or_retracted())
+                .map(|order| (order.fee, cmp::Reverse(order.insertion_id), order.hash))
+                .collect();
+            // The cheapest first, and the latest first among the parcels paying the same fee
+            candidates.sort();
+            candidates
+                .into_iter()
+                .take(excess)
+                .map(|(_, _, hash)| {
+                    by_hash.get(&hash).expect(
                         "All parcels in `self.by_priority` and `self.by_address` are kept in sync with `by_hash`.",
                     )
                 })
@@ -272,6 +423,7 @@ impl ParcelSet {
             if order.origin.is_local() {
                 local.mark_dropped(order.parcel);
             }
+            metrics.count_removal(RemovalReason::Dropped);
 
             let min = removed.get(&sender).map_or(nonce, |val| cmp::min(*val, nonce));
             removed.insert(sender, min);
@@ -290,6 +442,10 @@ impl ParcelSet {
                 self.by_priority.remove(&parcel_order),
                 "hash is in `by_address`; all parcels in `by_address` must be in `by_priority`; qed"
             );
+            self.mem_usage -= parcel_order.mem_usage;
+            self.encoded_size -= parcel_order.encoded_size;
+            self.by_origin.sub(parcel_order.origin);
+            sub_cost(&mut self.cost_by_sender, sender, parcel_order.cost);
             assert_eq!(self.by_priority.len(), self.by_address.len());
             assert_eq!(self.by_fee.values().map(|v| v.len()).fold(0, |a, b| a + b), self.by_address.len());
             return Some(parcel_order)
@@ -304,6 +460,35 @@ impl ParcelSet {
     fn clear(&mut self) {
         self.by_priority.clear();
         self.by_address.clear();
+        self.mem_usage = 0;
+        self.encoded_size = 0;
+        self.by_origin = ParcelsByOrigin::default();
+        self.cost_by_sender.clear();
+    }
+
+    /// Reorders the parcels in this set by `ordering`.
+    fn set_ordering(&mut self, ordering: OrderingStrategy) {
+        self.ordering = ordering;
+        let senders: Vec<Address> = self.by_address.keys().cloned().collect();
+        for sender in senders {
+            let row = self.by_address.row_mut(&sender).expect("The sender is in `by_address`");
+            for order in row.values_mut() {
+                order.ordering = ordering;
+            }
+        }
+        self.by_priority = self.by_priority
+            .iter()
+            .cloned()
+            .map(|mut order| {
+                order.ordering = ordering;
+                order
+            })
+            .collect();
+    }
+
+    /// The total cost of the parcels of `sender` in this set.
+    fn cost_of(&self, sender: &Address) -> U256 {
+        self.cost_by_sender.get(sender).cloned().unwrap_or_else(U256::zero)
     }
 
     /// Sets new limit for number of parcels in this `ParcelSet`.
@@ -325,22 +510,40 @@ impl ParcelSet {
 pub struct MemPool {
     /// Fee threshold for parcels that can be imported to this pool (defaults to 0)
     minimal_fee: U256,
-    /// Maximal time parcel may occupy the pool.
+    /// Fee thresholds of the kinds of actions overriding `minimal_fee`
+    minimal_fees_by_kind: HashMap<ActionKind, U256>,
+    /// The highest fee of parcels that can be imported to this pool (defaults to no limit)
+    max_fee: Option<U256>,
+    /// Maximal time external parcel may occupy the pool.
     /// When we reach `max_time_in_pool / 2^3` we re-validate
     /// account balance.
     max_time_in_pool: PoolingInstant,
+    /// Maximal time local parcel may occupy the pool.
+    max_time_in_pool_for_local: PoolingInstant,
+    /// Maximal distance from the state nonce to the nonce of an external parcel.
+    max_nonce_gap: u64,
+    /// Maximal distance from the state nonce to the nonce of a local parcel.
+    max_nonce_gap_for_local: u64,
     /// Priority queue for parcels that can go to block
     current: ParcelSet,
     /// Priority queue for parcels that has been received but are not yet valid to go to block
     future: ParcelSet,
     /// All parcels managed by pool indexed by hash
-    by_hash: HashMap<H256, MemPoolItem>,
+    by_hash: ParcelsByHash,
     /// Last nonce of parcel in current (to quickly check next expected parcel)
     last_nonces: HashMap<Address, U256>,
     /// List of local parcels and their statuses.
     local_parcels: LocalParcelsList,
     /// Next id that should be assigned to a parcel imported to the pool.
     next_parcel_id: u64,
+    /// Counts the changes of the parcels in the pool.
+    changes: Arc<Changes>,
+    /// Counts the decisions made on the parcels.
+    metrics: MemPoolMetrics,
+    /// The maximal size of the RLP encoded parcels in the pool (both current and future).
+    memory_limit: usize,
+    /// The number of external parcels a sender can have in the pool (current and future).
+    max_parcels_per_sender: usize,
 }
 
 impl Default for MemPool {
@@ -358,40 +561,124 @@ impl MemPool {
     /// Create new instance of this Queue with specified limits
     pub fn with_limits(limit: usize, memory_limit: usize) -> Self {
         let current = ParcelSet {
-            by_priority: BTreeSet::new(),
+            by_priority: RankedSet::new(),
             by_address: Table::new(),
             by_fee: MultiMap::default(),
             limit,
-            memory_limit,
+            mem_usage: 0,
+            encoded_size: 0,
+            by_origin: ParcelsByOrigin::default(),
+            cost_by_sender: HashMap::new(),
+            fee_bump_permille: DEFAULT_FEE_BUMP_PERMILLE,
+            ordering: OrderingStrategy::Fee,
         };
 
         let future = ParcelSet {
-            by_priority: BTreeSet::new(),
+            by_priority: RankedSet::new(),
             by_address: Table::new(),
             by_fee: MultiMap::default(),
             limit,
-            memory_limit,
+            mem_usage: 0,
+            encoded_size: 0,
+            by_origin: ParcelsByOrigin::default(),
+            cost_by_sender: HashMap::new(),
+            fee_bump_permille: DEFAULT_FEE_BUMP_PERMILLE,
+            ordering: OrderingStrategy::Fee,
         };
 
         MemPool {
             minimal_fee: U256::zero(),
+            minimal_fees_by_kind: HashMap::new(),
+            max_fee: None,
             max_time_in_pool: DEFAULT_POOLING_PERIOD,
+            max_time_in_pool_for_local: DEFAULT_LOCAL_POOLING_PERIOD,
+            max_nonce_gap: DEFAULT_MAX_NONCE_GAP,
+            max_nonce_gap_for_local: DEFAULT_LOCAL_MAX_NONCE_GAP,
             current,
             future,
-            by_hash: HashMap::new(),
+            by_hash: ParcelsByHash::new(),
             last_nonces: HashMap::new(),
             local_parcels: LocalParcelsList::default(),
             next_parcel_id: 0,
+            changes: Arc::new(Changes::new()),
+            metrics: MemPoolMetrics::default(),
+            memory_limit,
+            max_parcels_per_sender: usize::max_value(),
         }
     }
 
-    /// Set the new limit for `current` and `future` queue.
+    /// Set the new limit for `current` queue.
+    /// The parcels over the limit are moved to `future`, where the limit of `future` applies.
     pub fn set_limit(&mut self, limit: usize) {
+        self.changes.mem_pool_changed();
         self.current.set_limit(limit);
+        // And ensure the limit
+        let demoted = self.enforce_current_limit(None);
+        self.update_last_nonces(&demoted);
+    }
+
+    /// Set the new limit for `future` queue. The cheapest external parcels over the limit are removed.
+    pub fn set_future_limit(&mut self, limit: usize) {
+        self.changes.mem_pool_changed();
         self.future.set_limit(limit);
-        // And ensure the limits
-        self.current.enforce_limit(&mut self.by_hash, &mut self.local_parcels);
-        self.future.enforce_limit(&mut self.by_hash, &mut self.local_parcels);
+        self.future.enforce_limit(&mut self.by_hash, &mut self.local_parcels, &mut self.metrics);
+    }
+
+    /// Sets how much more fee a parcel must pay to replace another one with the same (sender, nonce).
+    /// The replacing fee must be higher by `permille / 1000`: 250 = 25%, 125 = 12.5%, 62 = 6.2%.
+    pub fn set_fee_bump_permille(&mut self, permille: u64) {
+        self.current.fee_bump_permille = permille;
+        self.future.fee_bump_permille = permille;
+    }
+
+    /// Set how the parcels in the pool are prioritized. The parcels in the pool already are reordered.
+    pub fn set_ordering(&mut self, ordering: OrderingStrategy) {
+        self.changes.mem_pool_changed();
+        self.current.set_ordering(ordering);
+        self.future.set_ordering(ordering);
+    }
+
+    /// Returns how the parcels in the pool are prioritized.
+    pub fn ordering(&self) -> OrderingStrategy {
+        self.current.ordering
+    }
+
+    /// Set the number of blocks external parcels can stay in the pool.
+    pub fn set_max_time_in_pool(&mut self, period: PoolingInstant) {
+        self.max_time_in_pool = period;
+    }
+
+    /// Set the number of blocks local parcels can stay in the pool.
+    pub fn set_max_time_in_pool_for_local(&mut self, period: PoolingInstant) {
+        self.max_time_in_pool_for_local = period;
+    }
+
+    /// Set how far the nonce of an external parcel can be ahead of the state nonce of its sender.
+    /// The parcels in the pool already are not affected.
+    pub fn set_max_nonce_gap(&mut self, gap: u64) {
+        self.max_nonce_gap = gap;
+    }
+
+    /// Set how far the nonce of a local parcel can be ahead of the state nonce of its sender.
+    /// The parcels in the pool already are not affected.
+    pub fn set_max_nonce_gap_for_local(&mut self, gap: u64) {
+        self.max_nonce_gap_for_local = gap;
+    }
+
+    /// Set the maximal size of the RLP encoded parcels in the pool.
+    /// The limit is enforced when a parcel is added next.
+    pub fn set_memory_limit(&mut self, memory_limit: usize) {
+        self.memory_limit = memory_limit;
+    }
+
+    /// Returns the maximal size of the RLP encoded parcels in the pool.
+    pub fn memory_limit(&self) -> usize {
+        self.memory_limit
+    }
+
+    /// Returns the size of the RLP encoded parcels in the pool.
+    pub fn encoded_size(&self) -> usize {
+        self.current.encoded_size + self.future.encoded_size
     }
 
     /// Returns current limit of parcels in the pool.
@@ -399,17 +686,136 @@ impl MemPool {
         self.current.limit
     }
 
+    /// Returns the limit of parcels waiting in `future`.
+    pub fn future_limit(&self) -> usize {
+        self.future.limit
+    }
+
+    /// Set the number of parcels a sender can have in the pool.
+    /// Local parcels can exceed it, and the parcels in the pool already are kept.
+    pub fn set_max_parcels_per_sender(&mut self, limit: usize) {
+        self.max_parcels_per_sender = limit;
+    }
+
+    /// Returns the number of parcels a sender can have in the pool.
+    pub fn max_parcels_per_sender(&self) -> usize {
+        self.max_parcels_per_sender
+    }
+
+    /// Returns a counter which is increased whenever the parcels in the pool change.
+    pub fn generation(&self) -> u64 {
+        self.changes.mem_pool_generation()
+    }
+
+    /// Returns the counter of the changes, which can be waited on without locking the pool.
+    pub fn changes(&self) -> Arc<Changes> {
+        Arc::clone(&self.changes)
+    }
+
+    /// Starts recording the parcels entering and leaving the pool for the mem pool journal.
+    /// The parcels already in the pool are recorded as entering it.
+    pub fn record_journal_changes(&mut self) {
+        let mut changes = MemPoolChanges::default();
+        for item in self.by_hash.values() {
+            changes.add(&item.parcel);
+        }
+        self.by_hash.journal_changes = Some(changes);
+    }
+
+    /// Returns the parcels which entered or left the pool since the last call.
+    /// It's `None` unless `record_journal_changes` is called.
+    pub fn take_journal_changes(&mut self) -> Option<MemPoolChanges> {
+        self.by_hash.journal_changes.as_mut().map(|changes| mem::replace(changes, MemPoolChanges::default()))
+    }
+
     /// Get the minimal fee.
     pub fn minimal_fee(&self) -> &U256 {
         &self.minimal_fee
     }
 
     /// Sets new fee threshold for incoming parcels.
+    /// It applies to the kinds of actions without their own thresholds.
     /// Any parcel already imported to the pool is not affected.
     pub fn set_minimal_fee(&mut self, min_fee: U256) {
         self.minimal_fee = min_fee;
     }
 
+    /// Get the minimal fee of the parcels with the actions of `kind`.
+    pub fn minimal_fee_for(&self, kind: ActionKind) -> U256 {
+        self.minimal_fees_by_kind.get(&kind).cloned().unwrap_or(self.minimal_fee)
+    }
+
+    /// Sets the fee threshold for the incoming parcels with the actions of `kind`, overriding the minimal fee.
+    /// `None` makes them follow the minimal fee again.
+    /// Any parcel already imported to the pool is not affected.
+    pub fn set_minimal_fee_for(&mut self, kind: ActionKind, min_fee: Option<U256>) {
+        match min_fee {
+            Some(min_fee) => self.minimal_fees_by_kind.insert(kind, min_fee),
+            None => self.minimal_fees_by_kind.remove(&kind),
+        };
+    }
+
+    /// Get the maximal fee.
+    pub fn max_fee(&self) -> Option<U256> {
+        self.max_fee
+    }
+
+    /// Sets the highest fee of incoming parcels, both local and external. `None` removes the limit.
+    /// Any parcel already imported to the pool is not affected.
+    pub fn set_max_fee(&mut self, max_fee: Option<U256>) {
+        self.max_fee = max_fee;
+    }
+
+    /// Removes external parcels whose fee is below the minimal fee of their kinds of actions.
+    /// Local parcels are kept regardless of their fee.
+    /// Returns the number of removed parcels.
+    pub fn remove_below_fee<F>(&mut self, fetch_account: &F) -> usize
+    where
+        F: Fn(&Address) -> AccountDetails, {
+        let underpriced = self.by_hash
+            .iter()
+            .filter(|&(_, ref parcel)| {
+                !parcel.origin.is_local() && parcel.parcel.fee < self.minimal_fee_for(parcel.parcel.action.kind())
+            })
+            .map(|(hash, parcel)| (*hash, parcel.sender()))
+            .collect::<Vec<_>>();
+        let nonces = underpriced
+            .iter()
+            .map(|(_, sender)| (*sender, fetch_account(sender).nonce))
+            .collect::<HashMap<_, _>>();
+        let fetch_nonce = |a: &Address| nonces[a];
+        for (hash, _) in underpriced.iter() {
+            self.remove(hash, &fetch_nonce, RemovalReason::NotAllowed);
+        }
+        if !underpriced.is_empty() {
+            cdebug!(MEM_POOL, "Removed {} parcels below the minimal fees", underpriced.len());
+        }
+        underpriced.len()
+    }
+
+    /// Returns the fee at `percentile` (0 - 100) among the fees of the pending parcels,
+    /// or `None` if there are too few pending parcels to estimate a fee from.
+    ///
+    /// It walks the pending parcels grouped by their fees, so nothing is sorted.
+    pub fn fee_percentile(&self, percentile: u8) -> Option<U256> {
+        let count = self.current.by_priority.len();
+        if count < MIN_PARCELS_FOR_FEE_ESTIMATE {
+            return None
+        }
+        let percentile = cmp::min(percentile, 100) as usize;
+        // The nearest rank, which starts from 1
+        let rank = cmp::max((percentile * count + 99) / 100, 1);
+        let mut seen = 0;
+        self.current
+            .by_fee
+            .iter()
+            .find(|(_, hashes)| {
+                seen += hashes.len();
+                seen >= rank
+            })
+            .map(|(fee, _)| *fee)
+    }
+
     /// Get one more than the lowest fee in the pool iff the pool is
     /// full, otherwise 0.
     pub fn effective_minimum_fee(&self) -> U256 {
@@ -421,6 +827,112 @@ impl MemPool {
         MemPoolStatus {
             pending: self.current.by_priority.len(),
             future: self.future.by_priority.len(),
+            generation: self.generation(),
+            encoded_size: self.encoded_size(),
+            mem_usage: self.mem_usage(),
+            by_origin: self.current.by_origin.sum(&self.future.by_origin),
+        }
+    }
+
+    /// Returns the counters of the decisions made on the parcels, with the ages of the parcels in the pool.
+    pub fn metrics(&self, current_time: PoolingInstant) -> MemPoolMetrics {
+        let mut metrics = self.metrics.clone();
+        metrics.ages = vec![0; PARCEL_AGE_BUCKETS.len() + 1];
+        for item in self.by_hash.values() {
+            let age = current_time.saturating_sub(item.insertion_time);
+            let bucket = PARCEL_AGE_BUCKETS.iter().position(|bound| age < *bound).unwrap_or(PARCEL_AGE_BUCKETS.len());
+            metrics.ages[bucket] += 1;
+        }
+        metrics
+    }
+
+    /// Returns all the parcels in the pool grouped by their senders.
+    pub fn dump(&self) -> MemPoolDump {
+        let mut senders: BTreeMap<Address, Vec<DumpedParcel>> = BTreeMap::new();
+        for item in self.by_hash.values() {
+            let sender = item.sender();
+            let nonce = item.nonce();
+            let hash = item.hash();
+            let is_future = self.future.by_address.get(&sender, &nonce).map_or(false, |order| order.hash == hash);
+            senders.entry(sender).or_insert_with(Vec::new).push(DumpedParcel {
+                nonce,
+                hash,
+                fee: item.parcel.fee,
+                origin: item.origin,
+                insertion_time: item.insertion_time,
+                is_future,
+            });
+        }
+        MemPoolDump {
+            senders: senders
+                .into_iter()
+                .map(|(sender, mut parcels)| {
+                    parcels.sort_by_key(|parcel| parcel.nonce);
+                    SenderDump {
+                        sender,
+                        parcels,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the heap memory used by the parcels in the pool.
+    /// The value is maintained incrementally, so it is cheap to call.
+    pub fn mem_usage(&self) -> usize {
+        self.current.mem_usage + self.future.mem_usage
+    }
+
+    /// Describes the gaps between `state_nonce` and the nonces of the queued parcels from `sender`.
+    ///
+    /// The replacement fee is the fee an external parcel needs to replace the stuck parcel
+    /// with the lowest nonce, which is also bounded by the minimal fee and the lowest fee of a full pool.
+    pub fn sender_issues(&self, sender: &Address, state_nonce: U256) -> SenderIssues {
+        let mut nonces: Vec<U256> = self.current
+            .by_address
+            .row(sender)
+            .into_iter()
+            .chain(self.future.by_address.row(sender))
+            .flat_map(|row| row.keys().cloned())
+            .filter(|nonce| *nonce >= state_nonce)
+            .collect();
+        nonces.sort();
+
+        let mut gaps = Vec::new();
+        let mut next_nonce = state_nonce;
+        for nonce in nonces {
+            while next_nonce < nonce && gaps.len() < MAX_LISTED_GAPS {
+                gaps.push(next_nonce);
+                next_nonce = next_nonce + U256::one();
+            }
+            next_nonce = nonce + U256::one();
+        }
+
+        let mut ready_end = state_nonce;
+        if let Some(row) = self.current.by_address.row(sender) {
+            while row.contains_key(&ready_end) {
+                ready_end = ready_end + U256::one();
+            }
+        }
+
+        let stuck: Vec<_> = match self.future.by_address.row(sender) {
+            Some(row) => row.iter().filter(|(nonce, _)| **nonce >= state_nonce).collect(),
+            None => Vec::new(),
+        };
+        let replacement_fee = stuck.iter().min_by_key(|(nonce, _)| **nonce).and_then(|(_, order)| {
+            let bumped_fee = min_replacing_fee(order.fee, self.future.fee_bump_permille)?;
+            let kind = self.by_hash[&order.hash].parcel.action.kind();
+            Some(cmp::max(bumped_fee, cmp::max(self.minimal_fee_for(kind), self.effective_minimum_fee())))
+        });
+        let mut gapped: Vec<U256> = stuck.iter().map(|(nonce, _)| **nonce).collect();
+        gapped.sort();
+        SenderIssues {
+            state_nonce,
+            ready: state_nonce..ready_end,
+            gapped,
+            gaps,
+            stuck: stuck.len(),
+            replacement_fee,
         }
     }
 
@@ -435,6 +947,32 @@ impl MemPool {
         time: PoolingInstant,
         fetch_account: &F,
     ) -> Result<ParcelImportResult, ParcelError>
+    where
+        F: Fn(&Address) -> AccountDetails, {
+        let result = self.add_with_origin(parcel, origin, time, fetch_account);
+        match result {
+            Ok(_) => {
+                self.changes.mem_pool_changed();
+                self.metrics.accepted += 1;
+            }
+            Err(ref err) => self.count_rejection(err),
+        }
+        result
+    }
+
+    /// Counts `err` in the metrics. The errors of the parcels rejected before reaching the pool,
+    /// e.g. by their signatures or the bans of their senders, are counted with it.
+    pub fn count_rejection(&mut self, err: &ParcelError) {
+        *self.metrics.rejected.entry(RejectionReason::from(err)).or_insert(0) += 1;
+    }
+
+    fn add_with_origin<F>(
+        &mut self,
+        parcel: SignedParcel,
+        origin: ParcelOrigin,
+        time: PoolingInstant,
+        fetch_account: &F,
+    ) -> Result<ParcelImportResult, ParcelError>
     where
         F: Fn(&Address) -> AccountDetails, {
         if origin == ParcelOrigin::Local {
@@ -475,21 +1013,17 @@ impl MemPool {
             .collect::<HashMap<_, _>>();
 
         for (sender, details) in senders.iter() {
-            self.cull(*sender, details.nonce);
+            self.cull_affordable(*sender, details);
+            self.demote_unaffordable(sender, details.balance);
         }
 
-        let max_time = self.max_time_in_pool;
-        let balance_check = max_time >> 3;
-        // Clear parcels occupying the pool too long
+        let balance_check = self.max_time_in_pool >> 3;
+        // Clear parcels which the senders can't pay for anymore
         let invalid = self.by_hash
             .iter()
             .filter(|&(_, ref parcel)| !parcel.origin.is_local())
             .map(|(hash, parcel)| (hash, parcel, current_time.saturating_sub(parcel.insertion_time)))
             .filter_map(|(hash, parcel, time_diff)| {
-                if time_diff > max_time {
-                    return Some(*hash)
-                }
-
                 if time_diff > balance_check {
                     return match senders.get(&parcel.sender()) {
                         Some(details) if parcel.cost() > details.balance => Some(*hash),
@@ -507,22 +1041,50 @@ impl MemPool {
         }
     }
 
+    /// Removes the parcels occupying the pool longer than `max_time_in_pool`,
+    /// or `max_time_in_pool_for_local` for the local parcels.
+    /// Returns the number of removed parcels.
+    pub fn cull_expired<F>(&mut self, fetch_nonce: &F, current_time: PoolingInstant) -> usize
+    where
+        F: Fn(&Address) -> U256, {
+        let expired = self.by_hash
+            .iter()
+            .filter(|(_, parcel)| {
+                let max_time = if parcel.origin.is_local() {
+                    self.max_time_in_pool_for_local
+                } else {
+                    self.max_time_in_pool
+                };
+                current_time.saturating_sub(parcel.insertion_time) > max_time
+            })
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+
+        let mut removed = 0;
+        for hash in expired {
+            ctrace!(MEM_POOL, "Removing expired parcel: {:?}", hash);
+            // The parcel may have been removed already as an old parcel of its sender.
+            if self.remove(&hash, fetch_nonce, RemovalReason::Expired).is_some() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
     /// Removes invalid parcel identified by hash from pool.
     /// Assumption is that this parcel nonce is not related to client nonce,
     /// so parcels left in pool are processed according to client nonce.
     ///
     /// If gap is introduced marks subsequent parcels as future
-    pub fn remove<F>(&mut self, parcel_hash: &H256, fetch_nonce: &F, reason: RemovalReason)
+    /// Returns the removed parcel, or `None` if the pool doesn't know it.
+    pub fn remove<F>(&mut self, parcel_hash: &H256, fetch_nonce: &F, reason: RemovalReason) -> Option<SignedParcel>
     where
         F: Fn(&Address) -> U256, {
         assert_eq!(self.future.by_priority.len() + self.current.by_priority.len(), self.by_hash.len());
-        let parcel = self.by_hash.remove(parcel_hash);
-        if parcel.is_none() {
-            // We don't know this parcel
-            return
-        }
-
-        let parcel = parcel.expect("None is tested in early-exit condition above; qed");
+        // We don't know this parcel if it's not found
+        let parcel = self.by_hash.remove(parcel_hash)?;
+        self.changes.mem_pool_changed();
+        self.metrics.count_removal(reason);
         let sender = parcel.sender();
         let nonce = parcel.nonce();
         let current_nonce = fetch_nonce(&sender);
@@ -532,9 +1094,12 @@ impl MemPool {
         // Mark in locals
         if self.local_parcels.contains(parcel_hash) {
             match reason {
-                RemovalReason::Invalid => self.local_parcels.mark_invalid(parcel.parcel.into()),
-                RemovalReason::NotAllowed => self.local_parcels.mark_invalid(parcel.parcel.into()),
-                RemovalReason::Canceled => self.local_parcels.mark_canceled(parcel.parcel.into()),
+                RemovalReason::Invalid => self.local_parcels.mark_invalid(parcel.parcel.clone()),
+                RemovalReason::NotAllowed => self.local_parcels.mark_invalid(parcel.parcel.clone()),
+                RemovalReason::Canceled => self.local_parcels.mark_canceled(parcel.parcel.clone()),
+                RemovalReason::Dropped => self.local_parcels.mark_dropped(parcel.parcel.clone()),
+                RemovalReason::Expired => self.local_parcels.mark_expired(parcel.parcel.clone()),
+                RemovalReason::Banned => self.local_parcels.mark_banned(parcel.parcel.clone()),
             }
         }
 
@@ -545,8 +1110,11 @@ impl MemPool {
             // And now lets check if there is some chain of parcels in future
             // that should be placed in current
             self.move_matching_future_to_current(sender, current_nonce, current_nonce);
+            // The promoted parcels may not fit in current.
+            let demoted = self.enforce_current_limit(None);
+            self.update_last_nonces(&demoted);
             assert_eq!(self.future.by_priority.len() + self.current.by_priority.len(), self.by_hash.len());
-            return
+            return Some(parcel.parcel)
         }
 
         // Remove from current
@@ -554,10 +1122,90 @@ impl MemPool {
         if order.is_some() {
             // This will keep consistency in pool
             // Moves all to future and then promotes a batch from current:
-            self.cull_internal(sender, current_nonce);
+            self.cull_internal(sender, current_nonce, U256::max_value());
             assert_eq!(self.future.by_priority.len() + self.current.by_priority.len(), self.by_hash.len());
-            return
         }
+        Some(parcel.parcel)
+    }
+
+    /// Cancels the parcel identified by hash.
+    /// The parcels of the same sender with higher nonces are moved to future or removed as well,
+    /// following `subsequent`.
+    pub fn cancel<F>(
+        &mut self,
+        parcel_hash: &H256,
+        fetch_nonce: &F,
+        subsequent: SubsequentParcels,
+    ) -> Option<SignedParcel>
+    where
+        F: Fn(&Address) -> U256, {
+        let parcel = self.remove(parcel_hash, fetch_nonce, RemovalReason::Canceled)?;
+        if subsequent == SubsequentParcels::Drop {
+            // The gap left by the canceled parcel keeps all the higher nonces in future
+            let sender = parcel.sender();
+            let subsequent_hashes = match self.future.by_address.row(&sender) {
+                Some(by_nonce) => {
+                    by_nonce.iter().filter(|&(nonce, _)| *nonce > parcel.nonce).map(|(_, order)| order.hash).collect()
+                }
+                None => Vec::new(),
+            };
+            for hash in subsequent_hashes {
+                self.remove(&hash, fetch_nonce, RemovalReason::Canceled);
+            }
+        }
+        Some(parcel)
+    }
+
+    /// Removes all parcels of `sender` in both current and future, and returns them.
+    /// It's used to ban the sender.
+    pub fn remove_sender(&mut self, sender: &Address) -> Vec<SignedParcel> {
+        self.remove_all_from_sender_with_reason(sender, RemovalReason::Banned)
+    }
+
+    /// Cancels all parcels of `sender` in both current and future, and returns their hashes in the order of nonces.
+    /// The parcels of the sender can be imported again from the state nonce right after it.
+    pub fn remove_all_from_sender(&mut self, sender: &Address) -> Vec<H256> {
+        self.remove_all_from_sender_with_reason(sender, RemovalReason::Canceled)
+            .into_iter()
+            .map(|parcel| parcel.hash())
+            .collect()
+    }
+
+    fn remove_all_from_sender_with_reason(&mut self, sender: &Address, reason: RemovalReason) -> Vec<SignedParcel> {
+        let mut nonces: Vec<U256> = Vec::new();
+        for set in &[&self.current, &self.future] {
+            if let Some(by_nonce) = set.by_address.row(sender) {
+                nonces.extend(by_nonce.keys());
+            }
+        }
+        if nonces.is_empty() {
+            return Vec::new()
+        }
+        nonces.sort();
+
+        self.changes.mem_pool_changed();
+        self.last_nonces.remove(sender);
+        let mut removed = Vec::with_capacity(nonces.len());
+        for nonce in nonces {
+            let order = self.current
+                .drop(sender, &nonce)
+                .or_else(|| self.future.drop(sender, &nonce))
+                .expect("The nonce is found in `current` or `future` above");
+            let item = self.by_hash
+                .remove(&order.hash)
+                .expect("All parcels in `current` and `future` are always included in `by_hash`");
+            if self.local_parcels.contains(&order.hash) {
+                match reason {
+                    RemovalReason::Canceled => self.local_parcels.mark_canceled(item.parcel.clone()),
+                    RemovalReason::Banned => self.local_parcels.mark_banned(item.parcel.clone()),
+                    _ => self.local_parcels.mark_invalid(item.parcel.clone()),
+                }
+            }
+            self.metrics.count_removal(reason);
+            removed.push(item.parcel);
+        }
+        assert_eq!(self.future.by_priority.len() + self.current.by_priority.len(), self.by_hash.len());
+        removed
     }
 
     /// Removes all parcels from particular sender up to (excluding) given client (state) nonce.
@@ -578,26 +1226,165 @@ impl MemPool {
             return
         }
 
-        self.cull_internal(sender, client_nonce);
+        self.changes.mem_pool_changed();
+        self.cull_internal(sender, client_nonce, U256::max_value());
+    }
+
+    /// Like `cull`, but promotes only the parcels in `future` which the balance can pay for.
+    /// Otherwise the parcels `demote_unaffordable` moved to `future` would come back and go again at every cull.
+    fn cull_affordable(&mut self, sender: Address, details: &AccountDetails) {
+        let client_nonce = details.nonce;
+        let has_old_parcels = {
+            let is_old = |nonce: &U256| *nonce < client_nonce;
+            let in_current = self.current.by_address.row(&sender).map_or(false, |row| row.keys().any(&is_old));
+            let in_future = self.future.by_address.row(&sender).map_or(false, |row| row.keys().any(&is_old));
+            in_current || in_future
+        };
+        let is_promotable = self.future
+            .by_address
+            .get(&sender, &client_nonce)
+            .map_or(false, |order| self.current.cost_of(&sender).saturating_add(order.cost) <= details.balance);
+        if !has_old_parcels && !is_promotable {
+            return
+        }
+
+        self.changes.mem_pool_changed();
+        self.cull_internal(sender, client_nonce, details.balance);
     }
 
     /// Removes all elements (in any state) from the pool
     #[allow(dead_code)]
     pub fn clear(&mut self) {
+        self.changes.mem_pool_changed();
         self.current.clear();
         self.future.clear();
         self.by_hash.clear();
         self.last_nonces.clear();
     }
 
+    /// Panics if the pool is inconsistent, given the state nonces the pool is culled with.
+    ///
+    /// Only the own and retracted parcels may go over the limit of `current`.
+    /// A future parcel may come right after the pending parcels of its sender without a gap,
+    /// since the limit of `current` moves the parcels to `future` that way.
+    #[cfg(test)]
+    fn assert_invariants<F>(&self, fetch_nonce: &F)
+    where
+        F: Fn(&Address) -> U256, {
+        assert_eq!(self.current.by_priority.len() + self.future.by_priority.len(), self.by_hash.len());
+        for set in &[&self.current, &self.future] {
+            assert_eq!(set.by_priority.len(), set.by_address.len());
+            assert_eq!(set.by_priority.len(), set.by_fee.values().map(HashSet::len).sum::<usize>());
+            for order in &set.by_priority {
+                let item = self.by_hash.get(&order.hash).expect("All parcels in the sets must be in `by_hash`");
+                let by_address = set.by_address.get(&item.sender(), &item.nonce());
+                assert_eq!(Some(order.hash), by_address.map(|order| order.hash));
+                assert!(set.by_fee.get(&order.fee).map_or(false, |hashes| hashes.contains(&order.hash)));
+                assert_eq!(set.ordering, order.ordering);
+            }
+            let orders: Vec<&ParcelOrder> = set.by_priority.iter().collect();
+            for pair in orders.windows(2) {
+                assert_eq!(Ordering::Less, pair[0].cmp(pair[1]), "{:?} is not ordered before {:?}", pair[0], pair[1]);
+            }
+        }
+
+        let limited = self.current.by_priority.iter().filter(|order| !order.origin.is_own_or_retracted()).count();
+        assert!(
+            self.current.by_priority.len() <= self.current.limit || limited == 0,
+            "{} parcels are in current over its limit {}",
+            self.current.by_priority.len(),
+            self.current.limit
+        );
+        for sender in self.current.by_address.keys() {
+            let mut nonces: Vec<U256> = self.current.by_address.row(sender).unwrap().keys().cloned().collect();
+            if nonces.is_empty() {
+                continue
+            }
+            nonces.sort();
+            // The first pending parcel follows the state, and the others follow the pending ones.
+            let mut expected = fetch_nonce(sender);
+            for nonce in &nonces {
+                assert_eq!(expected, *nonce, "The pending parcels of {:?} have a gap", sender);
+                expected = expected + U256::one();
+            }
+            assert_eq!(nonces.last(), self.last_nonces.get(sender));
+        }
+        for sender in self.future.by_address.keys() {
+            let first_future = match self.future.by_address.row(sender).unwrap().keys().min() {
+                Some(nonce) => nonce,
+                None => continue,
+            };
+            assert!(*first_future >= fetch_nonce(sender), "{:?} has an old parcel in future", sender);
+            if let Some(pending_nonces) = self.current.by_address.row(sender) {
+                let last_pending = pending_nonces.keys().max().unwrap();
+                assert!(first_future > last_pending, "The parcels of {:?} in current and future cross", sender);
+            }
+        }
+    }
+
     /// Finds parcel in the pool by hash (if any)
-    #[allow(dead_code)]
     pub fn find(&self, hash: &H256) -> Option<SignedParcel> {
         self.by_hash.get(hash).map(|parcel| parcel.parcel.clone())
     }
 
+    /// Returns the hashes of all the parcels in the pool, both pending and future.
+    pub fn hashes(&self) -> HashSet<H256> {
+        self.by_hash.keys().cloned().collect()
+    }
+
+    /// Returns where the parcel of `hash` is among the pending parcels, or `None` if it's not pending.
+    pub fn pending_position(&self, hash: &H256) -> Option<PendingPosition> {
+        let item = self.by_hash.get(hash)?;
+        let order = self.current.by_address.get(&item.sender(), &item.nonce())?;
+        if order.hash != *hash {
+            return None
+        }
+        Some(PendingPosition {
+            position: self.current.position(order),
+            pending: self.current.by_priority.len(),
+        })
+    }
+
+    /// Returns true if the parcel of `hash` is in the future part of the pool.
+    pub fn is_in_future(&self, hash: &H256) -> bool {
+        match self.by_hash.get(hash) {
+            Some(item) => {
+                let order = self.future.by_address.get(&item.sender(), &item.nonce());
+                order.map_or(false, |order| order.hash == *hash)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the parcels of `sender` in both current and future, in the order of their nonces.
+    pub fn parcels_by_sender(&self, sender: &Address) -> Vec<PooledParcel> {
+        let mut orders: Vec<(U256, H256, bool)> = Vec::new();
+        for (set, is_future) in &[(&self.current, false), (&self.future, true)] {
+            if let Some(by_nonce) = set.by_address.row(sender) {
+                orders.extend(by_nonce.iter().map(|(nonce, order)| (*nonce, order.hash, *is_future)));
+            }
+        }
+        orders.sort();
+        orders
+            .into_iter()
+            .map(|(_, hash, is_future)| PooledParcel {
+                parcel: self.by_hash
+                    .get(&hash)
+                    .expect("All parcels in `current` and `future` are always included in `by_hash`")
+                    .parcel
+                    .clone(),
+                is_future,
+            })
+            .collect()
+    }
+
+    /// Returns the total cost of the parcels of `sender` in the pool, both current and future.
+    pub fn cumulative_cost(&self, sender: &Address) -> U256 {
+        self.current.cost_of(sender).saturating_add(self.future.cost_of(sender))
+    }
+
     /// Returns highest parcel nonce for given address.
-    #[allow(dead_code)]
+    /// Only the parcels in current count, since the ones in future can't be mined until their gaps are filled.
     pub fn last_nonce(&self, address: &Address) -> Option<U256> {
         self.last_nonces.get(address).cloned()
     }
@@ -616,8 +1403,119 @@ impl MemPool {
             .collect()
     }
 
-    /// Return all future parcels.
-    pub fn future_parcels(&self) -> Vec<SignedParcel> {
+    /// Returns at most `limit` pending parcels in the order of `top_parcels`, skipping the first `offset` of them.
+    pub fn pending_page(&self, offset: usize, limit: usize) -> ParcelPage {
+        self.page_of(&self.current, offset, limit)
+    }
+
+    /// Returns at most `limit` future parcels in the order of `future_parcels`, skipping the first `offset` of them.
+    pub fn future_page(&self, offset: usize, limit: usize) -> ParcelPage {
+        self.page_of(&self.future, offset, limit)
+    }
+
+    /// Only the parcels in the page are cloned.
+    fn page_of(&self, set: &ParcelSet, offset: usize, limit: usize) -> ParcelPage {
+        let parcels = set.by_priority
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|order| {
+                self.by_hash
+                    .get(&order.hash)
+                    .expect("All parcels in `current` and `future` are always included in `by_hash`")
+            })
+            .map(|item| item.parcel.clone())
+            .collect();
+        ParcelPage {
+            parcels,
+            total: set.by_priority.len(),
+            generation: self.generation(),
+        }
+    }
+
+    /// Returns top parcels from the pool ordered by priority, at most `max_count` of them
+    /// whose encoded sizes add up to at most `max_bytes`.
+    /// A parcel which doesn't fit in the remaining bytes is skipped with the later parcels of its sender,
+    /// so the parcels of a sender never have a nonce gap.
+    /// Only the collected parcels are cloned, because the block and the relayed messages take them by value.
+    pub fn top_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel> {
+        self.collect_top_parcels(max_count, max_bytes, &HashSet::new(), |_| true)
+    }
+
+    /// Same as `top_parcels_with_limit`, but the parcels in `taken` are left out.
+    /// They still count as collected, so the following parcels of their senders can be collected.
+    pub fn top_parcels_after(&self, max_count: usize, max_bytes: usize, taken: &HashSet<H256>) -> Vec<SignedParcel> {
+        self.collect_top_parcels(max_count, max_bytes, taken, |_| true)
+    }
+
+    /// Same as `top_parcels_with_limit`, but only the local parcels are collected.
+    pub fn top_local_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel> {
+        self.collect_top_parcels(max_count, max_bytes, &HashSet::new(), ParcelOrigin::is_local)
+    }
+
+    fn collect_top_parcels<F>(
+        &self,
+        max_count: usize,
+        max_bytes: usize,
+        taken: &HashSet<H256>,
+        filter: F,
+    ) -> Vec<SignedParcel>
+    where
+        F: Fn(&ParcelOrigin) -> bool, {
+        let mut parcels = Vec::with_capacity(cmp::min(max_count, self.current.by_priority.len()));
+        // The number of the collected parcels of each sender
+        let mut collected: HashMap<Address, U256> = HashMap::new();
+        // The parcels which come before the lower nonces of their senders, by their senders and nonce heights.
+        // Local parcels go first, so a parcel may come before the external one with the lower nonce.
+        let mut waiting: HashMap<Address, HashMap<U256, &ParcelOrder>> = HashMap::new();
+        let mut total_bytes = 0;
+        for order in &self.current.by_priority {
+            if parcels.len() >= max_count || total_bytes >= max_bytes {
+                break
+            }
+            if !filter(&order.origin) {
+                continue
+            }
+            let sender = self.by_hash
+                .get(&order.hash)
+                .expect("All parcels in `current` and `future` are always included in `by_hash`")
+                .sender();
+            let collected_of_sender = collected.get(&sender).cloned().unwrap_or_else(U256::zero);
+            if order.nonce_height != collected_of_sender {
+                // It is collected right after the parcel with the lower nonce.
+                waiting.entry(sender).or_insert_with(HashMap::new).insert(order.nonce_height, order);
+                continue
+            }
+
+            let mut next = Some(order);
+            while let Some(order) = next {
+                if parcels.len() >= max_count || total_bytes >= max_bytes {
+                    break
+                }
+                if !taken.contains(&order.hash) {
+                    let item = self.by_hash
+                        .get(&order.hash)
+                        .expect("All parcels in `current` and `future` are always included in `by_hash`");
+                    if item.encoded_size > max_bytes - total_bytes {
+                        // The following parcels of the sender are left out as well.
+                        break
+                    }
+                    total_bytes += item.encoded_size;
+                    parcels.push(item.parcel.clone());
+                }
+                let collected_of_sender = {
+                    let collected_of_sender = collected.entry(sender).or_insert_with(U256::zero);
+                    *collected_of_sender = *collected_of_sender + 1.into();
+                    *collected_of_sender
+                };
+                next = waiting.get_mut(&sender).and_then(|orders| orders.remove(&collected_of_sender));
+            }
+        }
+        parcels
+    }
+
+    /// Return all future parcels.
+    pub fn future_parcels(&self) -> Vec<SignedParcel> {
         self.future
             .by_priority
             .iter()
@@ -635,6 +1533,26 @@ impl MemPool {
         self.current.by_priority.iter().any(|parcel| parcel.origin == ParcelOrigin::Local)
     }
 
+    /// Returns the local parcels in the pool (both current and future), ordered by their senders and nonces.
+    pub fn local_parcels_in_pool(&self) -> Vec<SignedParcel> {
+        let mut parcels: Vec<_> = self.by_hash
+            .values()
+            .filter(|item| item.origin.is_local())
+            .map(|item| item.parcel.clone())
+            .collect();
+        parcels.sort_by_key(|parcel| (parcel.sender(), parcel.nonce));
+        parcels
+    }
+
+    /// Returns the local parcels in the pool if they changed since the last call.
+    pub fn take_changed_local_parcels(&mut self) -> Option<Vec<SignedParcel>> {
+        if !self.by_hash.local_parcels_changed {
+            return None
+        }
+        self.by_hash.local_parcels_changed = false;
+        Some(self.local_parcels_in_pool())
+    }
+
     /// Returns local parcels (some of them might not be part of the pool anymore).
     #[allow(dead_code)]
     pub fn local_parcels(&self) -> &LinkedHashMap<H256, LocalParcelStatus> {
@@ -651,17 +1569,34 @@ impl MemPool {
     ) -> Result<ParcelImportResult, ParcelError>
     where
         F: Fn(&Address) -> AccountDetails, {
-        if origin != ParcelOrigin::Local && parcel.fee < self.minimal_fee {
+        if let Some(max_fee) = self.max_fee {
+            if parcel.fee > max_fee {
+                ctrace!(
+                    MEM_POOL,
+                    "Dropping parcel above maximal fee: {:?} (gp: {} > {})",
+                    parcel.hash(),
+                    parcel.fee,
+                    max_fee
+                );
+                return Err(ParcelError::FeeTooHigh {
+                    maximum: max_fee,
+                    got: parcel.fee,
+                })
+            }
+        }
+
+        let minimal_fee = self.minimal_fee_for(parcel.action.kind());
+        if origin != ParcelOrigin::Local && parcel.fee < minimal_fee {
             ctrace!(
                 MEM_POOL,
                 "Dropping parcel below minimal fee: {:?} (gp: {} < {})",
                 parcel.hash(),
                 parcel.fee,
-                self.minimal_fee
+                minimal_fee
             );
 
             return Err(ParcelError::InsufficientFee {
-                minimal: self.minimal_fee,
+                minimal: minimal_fee,
                 got: parcel.fee,
             })
         }
@@ -683,29 +1618,149 @@ impl MemPool {
         }
 
         let client_account = fetch_account(&parcel.sender());
-        if client_account.balance < parcel.fee {
+        let required = checked_cost_of(&parcel);
+        if required.map_or(true, |required| client_account.balance < required) {
+            let required = required.unwrap_or_else(U256::max_value);
             ctrace!(
                 MEM_POOL,
                 "Dropping parcel without sufficient balance: {:?} ({} < {})",
                 parcel.hash(),
                 client_account.balance,
-                parcel.fee
+                required
             );
 
             return Err(ParcelError::InsufficientBalance {
                 address: parcel.sender(),
-                cost: parcel.fee,
+                required,
+                balance: client_account.balance,
+            })
+        }
+        let max_nonce_gap = U256::from(if origin == ParcelOrigin::Local {
+            self.max_nonce_gap_for_local
+        } else {
+            self.max_nonce_gap
+        });
+        if parcel.nonce > client_account.nonce && parcel.nonce - client_account.nonce > max_nonce_gap {
+            ctrace!(
+                MEM_POOL,
+                "Dropping parcel with a nonce too far in the future: {:?} ({} > {} + {})",
+                parcel.hash(),
+                parcel.nonce,
+                client_account.nonce,
+                max_nonce_gap
+            );
+
+            return Err(ParcelError::NonceTooFarInFuture {
+                expected_max: client_account.nonce + max_nonce_gap,
+                got: parcel.nonce,
+            })
+        }
+        let cumulative_cost = {
+            let sender = parcel.sender();
+            let replaced_cost = self.current
+                .by_address
+                .get(&sender, &parcel.nonce)
+                .or_else(|| self.future.by_address.get(&sender, &parcel.nonce))
+                .map_or_else(U256::zero, |order| order.cost);
+            self.cumulative_cost(&sender).saturating_sub(replaced_cost).saturating_add(cost_of(&parcel))
+        };
+        if cumulative_cost > client_account.balance {
+            ctrace!(
+                MEM_POOL,
+                "Dropping parcel the sender can't pay for with the queued ones: {:?} ({} < {})",
+                parcel.hash(),
+                client_account.balance,
+                cumulative_cost
+            );
+
+            return Err(ParcelError::InsufficientBalanceForQueue {
                 balance: client_account.balance,
+                cumulative_cost,
             })
         }
+        if origin != ParcelOrigin::Local {
+            let sender = parcel.sender();
+            let queued_nonces = self.current
+                .by_address
+                .row(&sender)
+                .into_iter()
+                .chain(self.future.by_address.row(&sender))
+                .flat_map(|row| row.keys())
+                .filter(|nonce| **nonce >= client_account.nonce);
+            let mut queued = 0;
+            let mut is_replacement = false;
+            for nonce in queued_nonces {
+                queued += 1;
+                is_replacement |= *nonce == parcel.nonce;
+            }
+            if !is_replacement && queued >= self.max_parcels_per_sender {
+                ctrace!(
+                    MEM_POOL,
+                    "Dropping parcel of a sender who has too many parcels: {:?} ({} queued)",
+                    parcel.hash(),
+                    queued
+                );
+
+                return Err(ParcelError::SenderLimitReached {
+                    limit: self.max_parcels_per_sender,
+                })
+            }
+        }
+
         parcel.check_low_s()?;
         // No invalid parcels beyond this point.
         let id = self.next_parcel_id;
         self.next_parcel_id += 1;
+        let hash = parcel.hash();
         let vparcel = MemPoolItem::new(parcel, origin, time, id);
-        let r = self.import_parcel(vparcel, client_account.nonce);
+        let r = self.import_parcel(vparcel, client_account.nonce)?;
+
+        let fetch_nonce = |a: &Address| fetch_account(a).nonce;
+        let removed = self.enforce_memory_limit(&fetch_nonce);
         assert_eq!(self.future.by_priority.len() + self.current.by_priority.len(), self.by_hash.len());
-        r
+        // Trigger error if the parcel we are importing was removed.
+        if removed.contains(&hash) {
+            // It's counted as rejected, not as removed.
+            self.metrics.uncount_removal(RemovalReason::Dropped);
+            return Err(ParcelError::MemoryLimitReached {
+                limit: self.memory_limit,
+            })
+        }
+        Ok(r)
+    }
+
+    /// Removes the parcels paying the lowest fees until the RLP encoded parcels fit in the memory limit.
+    /// Own and retracted parcels are removed only if they alone exceed the limit.
+    ///
+    /// Returns the hashes of the removed parcels.
+    fn enforce_memory_limit<F>(&mut self, fetch_nonce: &F) -> Vec<H256>
+    where
+        F: Fn(&Address) -> U256, {
+        if self.encoded_size() <= self.memory_limit {
+            return Vec::new()
+        }
+
+        let mut candidates: Vec<_> = self.current
+            .by_priority
+            .iter()
+            .chain(self.future.by_priority.iter())
+            .map(|order| (order.origin.is_own_or_retracted(), order.fee, cmp::Reverse(order.insertion_id), order.hash))
+            .collect();
+        // The external parcels first, the cheapest first, and the latest first among the parcels paying the same fee
+        candidates.sort();
+
+        let mut removed = Vec::new();
+        for (_, _, _, hash) in candidates {
+            if self.encoded_size() <= self.memory_limit {
+                break
+            }
+            ctrace!(MEM_POOL, "Dropping parcel out of memory limit: {:?}", hash);
+            // The parcel may have been removed already as an old parcel of its sender.
+            if self.remove(&hash, fetch_nonce, RemovalReason::Dropped).is_some() {
+                removed.push(hash);
+            }
+        }
+        removed
     }
 
     /// Adds VerifiedParcel to this pool.
@@ -735,7 +1790,10 @@ impl MemPool {
         if nonce < state_nonce {
             // Droping parcel
             ctrace!(MEM_POOL, "Dropping old parcel: {:?} (nonce: {} < {})", parcel.hash(), nonce, state_nonce);
-            return Err(ParcelError::Old)
+            return Err(ParcelError::Old {
+                expected: state_nonce,
+                got: nonce,
+            })
         }
 
         // Update nonces of parcels in future (remove old parcels)
@@ -753,6 +1811,7 @@ impl MemPool {
         if nonce > next_nonce {
             // We have a gap - put to future.
             // Insert parcel (or replace old one with lower fee)
+            let is_replacing = self.future.by_address.get(&address, &nonce).is_some();
             check_too_cheap(Self::replace_parcel(
                 parcel,
                 state_nonce,
@@ -760,10 +1819,13 @@ impl MemPool {
                 &mut self.by_hash,
                 &mut self.local_parcels,
             ))?;
+            if is_replacing {
+                self.metrics.replaced += 1;
+            }
             // Enforce limit in Future
-            let removed = self.future.enforce_limit(&mut self.by_hash, &mut self.local_parcels);
+            let removed = self.future.enforce_limit(&mut self.by_hash, &mut self.local_parcels, &mut self.metrics);
             // Return an error if this parcel was not imported because of limit.
-            check_if_removed(&address, &nonce, removed)?;
+            check_if_removed(&address, &nonce, removed, self.future.limit)?;
 
             cdebug!(MEM_POOL, "Importing parcel to future: {:?}", hash);
             cdebug!(MEM_POOL, "status: {:?}", self.status());
@@ -775,6 +1837,7 @@ impl MemPool {
         self.move_matching_future_to_current(address, nonce + U256::one(), state_nonce);
 
         // Replace parcel if any
+        let is_replacing = self.current.by_address.get(&address, &nonce).is_some();
         check_too_cheap(Self::replace_parcel(
             parcel,
             state_nonce,
@@ -782,24 +1845,136 @@ impl MemPool {
             &mut self.by_hash,
             &mut self.local_parcels,
         ))?;
+        if is_replacing {
+            self.metrics.replaced += 1;
+        }
         // Keep track of highest nonce stored in current
         let new_max = self.last_nonces.get(&address).map_or(nonce, |n| cmp::max(nonce, *n));
         self.last_nonces.insert(address, new_max);
 
         // Also enforce the limit
-        let removed = self.current.enforce_limit(&mut self.by_hash, &mut self.local_parcels);
-        // If some parcel were removed because of limit we need to update last_nonces also.
-        self.update_last_nonces(&removed);
+        let demoted = self.enforce_current_limit(Some(hash));
+        // If some parcel were moved out of current because of limit we need to update last_nonces also.
+        self.update_last_nonces(&demoted);
         // Trigger error if the parcel we are importing was removed.
-        check_if_removed(&address, &nonce, removed)?;
+        check_if_removed(&address, &nonce, demoted, self.current.limit)?;
 
         cdebug!(MEM_POOL, "Imported parcel to current: {:?}", hash);
         cdebug!(MEM_POOL, "status: {:?}", self.status());
         Ok(ParcelImportResult::Current)
     }
 
+    /// Moves the cheapest parcels out of `current` until it fits in its limit.
+    /// The parcels of the sender with higher nonces are moved together, since they can't be in `current` alone.
+    /// The moved parcels wait in `future`, where the limit of `future` is enforced,
+    /// except `importing` which is removed from the pool so that its import fails.
+    ///
+    /// Returns addresses and lowest nonces of parcels moved out of `current`.
+    fn enforce_current_limit(&mut self, importing: Option<H256>) -> Option<HashMap<Address, U256>> {
+        if self.current.by_priority.len() <= self.current.limit {
+            return None
+        }
+
+        let mut candidates: Vec<_> = self.current
+            .by_priority
+            .iter()
+            // Own and retracted parcels are allowed to go above all limits.
+            .filter(|order| !order.origin.is_own_or_retracted())
+            .map(|order| (order.fee, cmp::Reverse(order.insertion_id), order.hash))
+            .collect();
+        // The cheapest first, and the latest first among the parcels paying the same fee
+        candidates.sort();
+
+        let mut demoted = HashMap::new();
+        for (_, _, hash) in candidates {
+            if self.current.by_priority.len() <= self.current.limit {
+                break
+            }
+            let (sender, nonce) = match self.by_hash.get(&hash) {
+                Some(item) => (item.sender(), item.nonce()),
+                None => continue,
+            };
+            // The parcel may have been moved already with a parcel of its sender paying less.
+            if self.current.by_address.get(&sender, &nonce).map_or(true, |order| order.hash != hash) {
+                continue
+            }
+            self.move_to_future_from(sender, nonce, importing);
+            let min = demoted.get(&sender).map_or(nonce, |val| cmp::min(*val, nonce));
+            demoted.insert(sender, min);
+        }
+        self.future.enforce_limit(&mut self.by_hash, &mut self.local_parcels, &mut self.metrics);
+        Some(demoted)
+    }
+
+    /// Moves the parcels of `sender` in `current` which can't be paid for with `balance` to `future`.
+    /// They are the parcels from the first nonce at which the cumulative cost exceeds the balance.
+    fn demote_unaffordable(&mut self, sender: &Address, balance: U256) {
+        let first_unaffordable = {
+            let row = match self.current.by_address.row(sender) {
+                Some(row) => row,
+                None => return,
+            };
+            let mut costs: Vec<(U256, U256)> = row.iter().map(|(nonce, order)| (*nonce, order.cost)).collect();
+            costs.sort();
+            let mut cumulative_cost = U256::zero();
+            costs
+                .into_iter()
+                .find(|&(_, cost)| {
+                    cumulative_cost = cumulative_cost.saturating_add(cost);
+                    cumulative_cost > balance
+                })
+                .map(|(nonce, _)| nonce)
+        };
+        let nonce = match first_unaffordable {
+            Some(nonce) => nonce,
+            None => return,
+        };
+
+        ctrace!(MEM_POOL, "Moving the parcels of {:?} the balance can't pay for to future: from {}", sender, nonce);
+        self.changes.mem_pool_changed();
+        self.move_to_future_from(*sender, nonce, None);
+        let mut demoted = HashMap::new();
+        demoted.insert(*sender, nonce);
+        self.update_last_nonces(&Some(demoted));
+        self.future.enforce_limit(&mut self.by_hash, &mut self.local_parcels, &mut self.metrics);
+        assert_eq!(self.future.by_priority.len() + self.current.by_priority.len(), self.by_hash.len());
+    }
+
+    /// Moves the parcels of `sender` with nonces from `nonce` in `current` to `future`,
+    /// except `importing` which is removed from the pool.
+    fn move_to_future_from(&mut self, sender: Address, nonce: U256, importing: Option<H256>) {
+        let nonces: Vec<U256> = match self.current.by_address.row(&sender) {
+            Some(row) => row.keys().filter(|n| **n >= nonce).cloned().collect(),
+            None => return,
+        };
+        for n in nonces {
+            let order = self.current.drop(&sender, &n).expect("iterating over nonces found above; qed");
+            if Some(order.hash) == importing {
+                ctrace!(MEM_POOL, "Dropped out of limit parcel: {:?}", order.hash);
+                self.by_hash.remove(&order.hash).expect("All parcels in `current` are also in `by_hash`");
+                continue
+            }
+            ctrace!(MEM_POOL, "Moved parcel to future: {:?}", order.hash);
+            if order.origin.is_local() {
+                self.local_parcels.mark_future(order.hash);
+            }
+            if let Some(old) = self.future.insert(sender, n, order.clone()) {
+                Self::replace_orders(
+                    sender,
+                    n,
+                    old,
+                    order,
+                    &mut self.future,
+                    &mut self.by_hash,
+                    &mut self.local_parcels,
+                );
+            }
+        }
+    }
+
     /// Always updates future and moves parcel from current to future.
-    fn cull_internal(&mut self, sender: Address, client_nonce: U256) {
+    /// Only the parcels `balance` can pay for are promoted back to current.
+    fn cull_internal(&mut self, sender: Address, client_nonce: U256, balance: U256) {
         // We will either move parcel to future or remove it completely
         // so there will be no parcels from this sender in current
         self.last_nonces.remove(&sender);
@@ -809,7 +1984,10 @@ impl MemPool {
         self.move_all_to_future(&sender, client_nonce);
         // And now lets check if there is some batch of parcels in future
         // that should be placed in current. It should also update last_nonces.
-        self.move_matching_future_to_current(sender, client_nonce, client_nonce);
+        self.move_affordable_future_to_current(sender, client_nonce, client_nonce, balance);
+        // The promoted parcels may not fit in current.
+        let demoted = self.enforce_current_limit(None);
+        self.update_last_nonces(&demoted);
         assert_eq!(self.future.by_priority.len() + self.current.by_priority.len(), self.by_hash.len());
     }
 
@@ -841,24 +2019,51 @@ impl MemPool {
                 ctrace!(MEM_POOL, "Removing old parcel: {:?} (nonce: {} < {})", order.hash, k, current_nonce);
                 // Remove the parcel completely
                 self.by_hash.remove(&order.hash).expect("All parcels in `future` are also in `by_hash`");
+                self.metrics.culled += 1;
             }
         }
     }
 
     /// Checks if there are any parcels in `future` that should actually be promoted to `current`
     /// (because nonce matches).
-    fn move_matching_future_to_current(&mut self, address: Address, mut current_nonce: U256, first_nonce: U256) {
+    fn move_matching_future_to_current(&mut self, address: Address, current_nonce: U256, first_nonce: U256) {
+        self.move_affordable_future_to_current(address, current_nonce, first_nonce, U256::max_value())
+    }
+
+    /// Promotes the matching parcels like `move_matching_future_to_current`,
+    /// but stops at the first one which makes the cost of the parcels in current exceed `balance`.
+    fn move_affordable_future_to_current(
+        &mut self,
+        address: Address,
+        mut current_nonce: U256,
+        first_nonce: U256,
+        balance: U256,
+    ) {
         let mut update_last_nonce_to = None;
         {
+            let mut cost = self.current.cost_of(&address);
             let by_nonce = self.future.by_address.row_mut(&address);
             if by_nonce.is_none() {
                 return
             }
             let by_nonce = by_nonce.expect("None is tested in early-exit condition above; qed");
-            while let Some(order) = by_nonce.remove(&current_nonce) {
+            loop {
+                let is_affordable = match by_nonce.get(&current_nonce) {
+                    Some(order) => cost.saturating_add(order.cost) <= balance,
+                    None => false,
+                };
+                if !is_affordable {
+                    break
+                }
+                let order = by_nonce.remove(&current_nonce).expect("It's found above");
+                cost = cost.saturating_add(order.cost);
                 // remove also from priority and fee
                 self.future.by_priority.remove(&order);
                 self.future.by_fee.remove(&order.fee, &order.hash);
+                self.future.mem_usage -= order.mem_usage;
+                self.future.encoded_size -= order.encoded_size;
+                self.future.by_origin.sub(order.origin);
+                sub_cost(&mut self.future.cost_by_sender, &address, order.cost);
                 // Put to current
                 let order = order.update_height(current_nonce, first_nonce);
                 if order.origin.is_local() {
@@ -920,9 +2125,10 @@ impl MemPool {
                 if parcel.origin.is_local() {
                     self.local_parcels.mark_mined(parcel.parcel);
                 }
+                self.metrics.culled += 1;
             }
         }
-        self.future.enforce_limit(&mut self.by_hash, &mut self.local_parcels);
+        self.future.enforce_limit(&mut self.by_hash, &mut self.local_parcels, &mut self.metrics);
     }
 
     /// Marks all parcels from particular sender as local parcels
@@ -969,10 +2175,10 @@ impl MemPool {
         parcel: MemPoolItem,
         base_nonce: U256,
         set: &mut ParcelSet,
-        by_hash: &mut HashMap<H256, MemPoolItem>,
+        by_hash: &mut ParcelsByHash,
         local: &mut LocalParcelsList,
     ) -> bool {
-        let order = ParcelOrder::for_parcel(&parcel, base_nonce);
+        let order = ParcelOrder::for_parcel(&parcel, base_nonce, set.ordering);
         let hash = parcel.hash();
         let address = parcel.sender();
         let nonce = parcel.nonce();
@@ -995,7 +2201,7 @@ impl MemPool {
         old: ParcelOrder,
         order: ParcelOrder,
         set: &mut ParcelSet,
-        by_hash: &mut HashMap<H256, MemPoolItem>,
+        by_hash: &mut ParcelsByHash,
         local: &mut LocalParcelsList,
     ) -> bool {
         // There was already parcel in pool. Let's check which one should stay
@@ -1004,9 +2210,9 @@ impl MemPool {
 
         let old_fee = old.fee;
         let new_fee = order.fee;
-        let min_required_fee = old_fee + (old_fee >> FEE_BUMP_SHIFT);
+        let min_required_fee = min_replacing_fee(old_fee, set.fee_bump_permille);
 
-        if min_required_fee > new_fee {
+        if min_required_fee.map_or(true, |min_required_fee| min_required_fee > new_fee) {
             ctrace!(
                 MEM_POOL,
                 "Didn't insert parcel because fee was too low: {:?} ({:?} stays in the pool)",
@@ -1043,9 +2249,67 @@ pub struct MemPoolStatus {
     pub pending: usize,
     /// Number of future parcels (waiting for parcels with lower nonces first)
     pub future: usize,
+    /// The generation of the pool when the status is taken
+    pub generation: u64,
+    /// The size of the RLP encoded parcels in the pool
+    pub encoded_size: usize,
+    /// The heap memory used by the parcels in the pool
+    pub mem_usage: usize,
+    /// The number of the parcels in the pool by their origins
+    pub by_origin: ParcelsByOrigin,
 }
 
-#[derive(Debug)]
+/// Where a parcel is in the order the pending parcels are mined
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PendingPosition {
+    /// The number of pending parcels mined before the parcel
+    pub position: usize,
+    /// The number of pending parcels
+    pub pending: usize,
+}
+
+/// A part of the pending or the future parcels, for reading them in pages
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParcelPage {
+    /// The parcels in the page, ordered by priority
+    pub parcels: Vec<SignedParcel>,
+    /// The number of all the parcels paged through
+    pub total: usize,
+    /// The generation of the pool when the page is taken.
+    /// The pages taken at different generations may miss or repeat parcels.
+    pub generation: u64,
+}
+
+/// A snapshot of all the parcels in the pool
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MemPoolDump {
+    /// The senders ordered by their addresses
+    pub senders: Vec<SenderDump>,
+}
+
+/// The parcels of a sender in `MemPoolDump`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SenderDump {
+    pub sender: Address,
+    /// The parcels ordered by their nonces
+    pub parcels: Vec<DumpedParcel>,
+}
+
+/// A parcel in `MemPoolDump`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DumpedParcel {
+    pub nonce: U256,
+    pub hash: H256,
+    pub fee: U256,
+    pub origin: ParcelOrigin,
+    /// The block number when the parcel is inserted
+    pub insertion_time: PoolingInstant,
+    /// Whether the parcel waits for parcels with lower nonces first
+    pub is_future: bool,
+}
+
+#[derive(Clone, Debug)]
 /// Details of account
 pub struct AccountDetails {
     /// Most recent account nonce
@@ -1054,16 +2318,185 @@ pub struct AccountDetails {
     pub balance: U256,
 }
 
+/// The upper bounds of the ages in `MemPoolMetrics::ages`, in blocks.
+pub const PARCEL_AGE_BUCKETS: [PoolingInstant; 4] = [1, 10, 100, 1000];
+
+/// Counters of the decisions made on the parcels since the pool was created
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemPoolMetrics {
+    /// Number of parcels accepted to the pool
+    pub accepted: u64,
+    /// Number of parcels rejected, by the reason
+    pub rejected: BTreeMap<RejectionReason, u64>,
+    /// Number of parcels replaced by ones with the same sender and nonce paying more
+    pub replaced: u64,
+    /// Number of parcels removed from the pool, by the reason
+    pub removed: BTreeMap<RemovalReason, u64>,
+    /// Number of parcels removed from the pool since the chain passed their nonces
+    pub culled: u64,
+    /// Number of parcels in the pool by their ages.
+    /// `ages[i]` counts the parcels younger than `PARCEL_AGE_BUCKETS[i]` blocks and not counted before,
+    /// and the last one counts the rest.
+    pub ages: Vec<usize>,
+}
+
+impl MemPoolMetrics {
+    fn count_removal(&mut self, reason: RemovalReason) {
+        *self.removed.entry(reason).or_insert(0) += 1;
+    }
+
+    fn uncount_removal(&mut self, reason: RemovalReason) {
+        let is_zero = match self.removed.get_mut(&reason) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => return,
+        };
+        if is_zero {
+            self.removed.remove(&reason);
+        }
+    }
+}
+
+/// Reason the pool rejected a parcel, stable across the versions of `ParcelError`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RejectionReason {
+    /// The parcel is in the pool or the chain already, or twice in a batch
+    AlreadyImported,
+    /// The nonce is below the state nonce of the sender
+    Old,
+    /// The parcel doesn't pay enough to replace the one with the same sender and nonce
+    TooCheapToReplace,
+    /// The parcel is for another network
+    InvalidNetworkId,
+    /// The pool is full of the parcels paying more
+    LimitReached,
+    /// The parcels paying more use up the memory limit of the pool
+    MemoryLimitReached,
+    /// The sender has too many parcels in the pool
+    SenderLimitReached,
+    /// The fee is below the minimal fee
+    InsufficientFee,
+    /// The fee is above the maximal fee
+    FeeTooHigh,
+    /// The balance of the sender can't pay for the parcel
+    InsufficientBalance,
+    /// The balance of the sender can't pay for the parcel with the other parcels of the sender in the pool
+    InsufficientBalanceForQueue,
+    /// The nonce doesn't match the state nonce
+    InvalidNonce,
+    /// The nonce is too far ahead of the state nonce
+    NonceTooFarInFuture,
+    /// The machine doesn't allow the parcel
+    NotAllowed,
+    /// The sender is banned from the pool
+    SenderBanned,
+    /// The signature is malformed or doesn't recover a sender
+    InvalidSignature,
+}
+
+impl<'a> From<&'a ParcelError> for RejectionReason {
+    fn from(err: &'a ParcelError) -> Self {
+        match err {
+            ParcelError::AlreadyImported => RejectionReason::AlreadyImported,
+            ParcelError::Old {
+                ..
+            } => RejectionReason::Old,
+            ParcelError::TooCheapToReplace => RejectionReason::TooCheapToReplace,
+            ParcelError::InvalidNetworkId => RejectionReason::InvalidNetworkId,
+            ParcelError::LimitReached {
+                ..
+            } => RejectionReason::LimitReached,
+            ParcelError::MemoryLimitReached {
+                ..
+            } => RejectionReason::MemoryLimitReached,
+            ParcelError::SenderLimitReached {
+                ..
+            } => RejectionReason::SenderLimitReached,
+            ParcelError::InsufficientFee {
+                ..
+            } => RejectionReason::InsufficientFee,
+            ParcelError::FeeTooHigh {
+                ..
+            } => RejectionReason::FeeTooHigh,
+            ParcelError::InsufficientBalance {
+                ..
+            } => RejectionReason::InsufficientBalance,
+            ParcelError::InsufficientBalanceForQueue {
+                ..
+            } => RejectionReason::InsufficientBalanceForQueue,
+            ParcelError::InvalidNonce {
+                ..
+            } => RejectionReason::InvalidNonce,
+            ParcelError::NonceTooFarInFuture {
+                ..
+            } => RejectionReason::NonceTooFarInFuture,
+            ParcelError::NotAllowed => RejectionReason::NotAllowed,
+            ParcelError::SenderBanned => RejectionReason::SenderBanned,
+            ParcelError::InvalidSignature(_) => RejectionReason::InvalidSignature,
+        }
+    }
+}
+
 /// Reason to remove single parcel from the pool.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum RemovalReason {
     /// Parcel is invalid
     Invalid,
     /// Parcel was canceled
-    #[allow(dead_code)]
     Canceled,
     /// Parcel is not allowed,
     NotAllowed,
+    /// Parcel is dropped because of the limits of the pool
+    Dropped,
+    /// Parcel occupied the pool too long
+    Expired,
+    /// The sender of the parcel is banned
+    Banned,
+}
+
+/// The fee and the value the sender pays for `parcel`, or `None` if it overflows.
+fn checked_cost_of(parcel: &SignedParcel) -> Option<U256> {
+    match &parcel.action {
+        Action::Payment {
+            value,
+            ..
+        } => parcel.fee.checked_add(*value),
+        _ => Some(parcel.fee),
+    }
+}
+
+/// The fee and the value the sender pays for `parcel`, saturated at `U256::max_value()`.
+fn cost_of(parcel: &SignedParcel) -> U256 {
+    checked_cost_of(parcel).unwrap_or_else(U256::max_value)
+}
+
+fn add_cost(cost_by_sender: &mut HashMap<Address, U256>, sender: Address, cost: U256) {
+    let total = cost_by_sender.entry(sender).or_insert_with(U256::zero);
+    *total = total.saturating_add(cost);
+}
+
+fn sub_cost(cost_by_sender: &mut HashMap<Address, U256>, sender: &Address, cost: U256) {
+    let is_empty = match cost_by_sender.get_mut(sender) {
+        Some(total) => {
+            *total = total.saturating_sub(cost);
+            total.is_zero()
+        }
+        None => return,
+    };
+    if is_empty {
+        cost_by_sender.remove(sender);
+    }
+}
+
+/// The lowest fee of a parcel replacing the one paying `old_fee`, or `None` if no fee is high enough.
+fn min_replacing_fee(old_fee: U256, fee_bump_permille: u64) -> Option<U256> {
+    let permille = U256::from(fee_bump_permille);
+    let thousand = U256::from(1000);
+    // Divided before multiplied not to overflow. The remainder times `permille` always fits.
+    let bump = (old_fee / thousand).checked_mul(permille)?.checked_add(old_fee % thousand * permille / thousand)?;
+    Some(cmp::max(old_fee.checked_add(bump)?, old_fee.checked_add(U256::one())?))
 }
 
 fn check_too_cheap(is_in: bool) -> Result<(), ParcelError> {
@@ -1078,10 +2511,13 @@ fn check_if_removed(
     sender: &Address,
     nonce: &U256,
     dropped: Option<HashMap<Address, U256>>,
+    limit: usize,
 ) -> Result<(), ParcelError> {
     match dropped {
         Some(dropped) => match dropped.get(sender) {
-            Some(min) if nonce >= min => Err(ParcelError::LimitReached),
+            Some(min) if nonce >= min => Err(ParcelError::LimitReached {
+                limit,
+            }),
             _ => Ok(()),
         },
         _ => Ok(()),
@@ -1090,9 +2526,12 @@ fn check_if_removed(
 
 #[cfg(test)]
 pub mod test {
+    use std::cell::Cell;
     use std::cmp::Ordering;
 
-    use ckeys::{Generator, Random};
+    use ckeys::{Generator, KeyPair, Random};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use serde_json;
 
     use super::super::super::{Parcel, Transaction};
     use super::*;
@@ -1100,12 +2539,17 @@ pub mod test {
     #[test]
     fn test_ordering() {
         assert_eq!(ParcelOrigin::Local.cmp(&ParcelOrigin::External), Ordering::Less);
-        assert_eq!(ParcelOrigin::RetractedBlock.cmp(&ParcelOrigin::Local), Ordering::Less);
+        assert_eq!(ParcelOrigin::Local.cmp(&ParcelOrigin::RetractedBlock), Ordering::Less);
         assert_eq!(ParcelOrigin::RetractedBlock.cmp(&ParcelOrigin::External), Ordering::Less);
 
         assert_eq!(ParcelOrigin::External.cmp(&ParcelOrigin::Local), Ordering::Greater);
-        assert_eq!(ParcelOrigin::Local.cmp(&ParcelOrigin::RetractedBlock), Ordering::Greater);
+        assert_eq!(ParcelOrigin::RetractedBlock.cmp(&ParcelOrigin::Local), Ordering::Greater);
         assert_eq!(ParcelOrigin::External.cmp(&ParcelOrigin::RetractedBlock), Ordering::Greater);
+
+        assert_eq!(ParcelOrigin::Local.cmp(&ParcelOrigin::Local), Ordering::Equal);
+        let mut origins = vec![ParcelOrigin::External, ParcelOrigin::RetractedBlock, ParcelOrigin::Local];
+        origins.sort();
+        assert_eq!(vec![ParcelOrigin::Local, ParcelOrigin::RetractedBlock, ParcelOrigin::External], origins);
     }
 
     #[test]
@@ -1207,4 +2651,1839 @@ pub mod test {
 
         assert_eq!(fee + value, item.cost());
     }
+
+    #[test]
+    fn mem_usage_grows_and_shrinks_with_pool() {
+        let keypair = Random.generate().unwrap();
+        let sender = keypair.address();
+        let mut pool = MemPool::new();
+        assert_eq!(0, pool.mem_usage());
+
+        let count = 10;
+        let mut expected = 0;
+        for nonce in 0..count {
+            let transactions = vec![Transaction::AssetMint {
+                metadata: "M".repeat(1024),
+                lock_script_hash: H256::zero(),
+                parameters: vec![vec![0u8; 256]],
+                amount: None,
+                registrar: None,
+                nonce: 0,
+            }];
+            let parcel = Parcel {
+                nonce: U256::from(nonce),
+                fee: U256::from(100),
+                network_id: 200,
+                action: Action::ChangeShardState {
+                    transactions,
+                },
+            };
+            let signed = parcel.sign(keypair.private());
+            expected += signed.heap_size_of_children();
+
+            let before = pool.mem_usage();
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+            assert!(pool.mem_usage() > before);
+        }
+        // Every parcel holds at least its metadata and parameters on the heap.
+        assert!(pool.mem_usage() >= count as usize * (1024 + 256));
+        assert_eq!(expected, pool.mem_usage());
+
+        pool.cull(sender, U256::from(count / 2));
+        assert!(pool.mem_usage() < expected);
+        assert!(pool.mem_usage() > 0);
+
+        pool.cull(sender, U256::from(count));
+        assert_eq!(0, pool.status().pending);
+        assert_eq!(0, pool.mem_usage());
+    }
+
+    fn default_account_details(_: &Address) -> AccountDetails {
+        AccountDetails {
+            nonce: U256::zero(),
+            balance: U256::max_value(),
+        }
+    }
+
+    fn payment(nonce: u64, fee: u64) -> Parcel {
+        Parcel {
+            nonce: U256::from(nonce),
+            fee: U256::from(fee),
+            network_id: 200,
+            action: Action::Payment {
+                receiver: Address::default(),
+                value: U256::zero(),
+            },
+        }
+    }
+
+    /// Adds a parcel paying `old_fee` and tries to replace it with another one paying `new_fee`.
+    fn replace(
+        pool: &mut MemPool,
+        nonce: u64,
+        old_fee: u64,
+        new_fee: u64,
+    ) -> (SignedParcel, SignedParcel, Result<ParcelImportResult, ParcelError>) {
+        let keypair = Random.generate().unwrap();
+        let old = payment(nonce, old_fee).sign(keypair.private());
+        pool.add(old.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+
+        // Pays a different value so that the hash differs even if the fee is the same
+        let mut new = payment(nonce, new_fee);
+        new.action = Action::Payment {
+            receiver: Address::default(),
+            value: U256::one(),
+        };
+        let new = new.sign(keypair.private());
+        let result = pool.add(new.clone(), ParcelOrigin::External, 0, &default_account_details);
+        (old, new, result)
+    }
+
+    #[test]
+    fn parcels_paying_the_same_fee_are_ordered_by_insertion() {
+        // The hashes are random, so the order must not depend on them.
+        for _ in 0..10 {
+            let mut pool = MemPool::new();
+            let first = payment(0, 10).sign(Random.generate().unwrap().private());
+            let second = payment(0, 10).sign(Random.generate().unwrap().private());
+            pool.add(first.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+            pool.add(second.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+            assert_eq!(vec![first, second], pool.top_parcels());
+        }
+    }
+
+    #[test]
+    fn replacing_parcel_is_ordered_as_inserted_last() {
+        let mut pool = MemPool::new();
+        let replaced_sender = Random.generate().unwrap();
+        let replaced = payment(0, 8).sign(replaced_sender.private());
+        let other = payment(0, 10).sign(Random.generate().unwrap().private());
+        pool.add(replaced, ParcelOrigin::External, 0, &default_account_details).unwrap();
+        pool.add(other.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+
+        let replacing = payment(0, 10).sign(replaced_sender.private());
+        pool.add(replacing.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        assert_eq!(vec![other, replacing], pool.top_parcels());
+    }
+
+    #[test]
+    fn parcel_paying_the_same_fee_does_not_replace_the_old_one() {
+        let mut pool = MemPool::new();
+        let (old, _, result) = replace(&mut pool, 0, 100, 100);
+        assert_eq!(Err(ParcelError::TooCheapToReplace), result);
+        assert_eq!(vec![old], pool.top_parcels());
+
+        // The bump of a small fee is rounded down to zero, but the fee must still increase
+        let mut pool = MemPool::new();
+        let (old, _, result) = replace(&mut pool, 0, 5, 5);
+        assert_eq!(Err(ParcelError::TooCheapToReplace), result);
+        assert_eq!(vec![old], pool.top_parcels());
+    }
+
+    #[test]
+    fn parcel_paying_marginally_more_fee_does_not_replace_the_old_one() {
+        let mut pool = MemPool::new();
+        let (old, _, result) = replace(&mut pool, 0, 100, 111);
+        assert_eq!(Err(ParcelError::TooCheapToReplace), result);
+        assert_eq!(vec![old], pool.top_parcels());
+        assert_eq!(1, pool.status().pending);
+    }
+
+    #[test]
+    fn parcel_paying_enough_more_fee_replaces_the_old_one() {
+        let mut pool = MemPool::new();
+        let (old, new, result) = replace(&mut pool, 0, 100, 112);
+        assert_eq!(Ok(ParcelImportResult::Current), result);
+        assert_eq!(vec![new], pool.top_parcels());
+        assert_eq!(None, pool.find(&old.hash()));
+        assert_eq!(1, pool.status().pending);
+
+        let mut pool = MemPool::new();
+        let (old, new, result) = replace(&mut pool, 2, 100, 112);
+        assert_eq!(Ok(ParcelImportResult::Future), result);
+        assert_eq!(vec![new], pool.future_parcels());
+        assert_eq!(None, pool.find(&old.hash()));
+        assert_eq!(1, pool.status().future);
+    }
+
+    #[test]
+    fn fee_bump_for_replacement_is_configurable() {
+        let mut pool = MemPool::new();
+        pool.set_fee_bump_permille(250);
+        let (_, _, result) = replace(&mut pool, 0, 100, 124);
+        assert_eq!(Err(ParcelError::TooCheapToReplace), result);
+
+        let mut pool = MemPool::new();
+        pool.set_fee_bump_permille(250);
+        let (_, new, result) = replace(&mut pool, 0, 100, 125);
+        assert_eq!(Ok(ParcelImportResult::Current), result);
+        assert_eq!(vec![new], pool.top_parcels());
+    }
+
+    #[test]
+    fn min_replacing_fee_does_not_overflow() {
+        assert_eq!(Some(U256::from(112)), min_replacing_fee(U256::from(100), DEFAULT_FEE_BUMP_PERMILLE));
+        assert_eq!(Some(U256::from(6)), min_replacing_fee(U256::from(5), DEFAULT_FEE_BUMP_PERMILLE));
+        let bumped = U256::from(1000) + U256::from(u64::max_value());
+        assert_eq!(Some(bumped), min_replacing_fee(U256::from(1000), u64::max_value()));
+
+        let half = U256::max_value() / 2;
+        let bumped = half + half / 8;
+        assert_eq!(Some(bumped), min_replacing_fee(half, DEFAULT_FEE_BUMP_PERMILLE));
+        assert_eq!(Some(half * 2), min_replacing_fee(half, 1000));
+        assert_eq!(None, min_replacing_fee(half, 1001));
+        assert_eq!(None, min_replacing_fee(U256::max_value(), 0));
+    }
+
+    #[test]
+    fn parcels_above_the_maximal_fee_are_rejected() {
+        let keypair = Random.generate().unwrap();
+        let mut pool = MemPool::new();
+        assert_eq!(None, pool.max_fee());
+        pool.set_max_fee(Some(U256::from(1000)));
+
+        for &origin in &[ParcelOrigin::Local, ParcelOrigin::External] {
+            let signed = payment(0, 1001).sign(keypair.private());
+            assert_eq!(
+                Err(ParcelError::FeeTooHigh {
+                    maximum: U256::from(1000),
+                    got: U256::from(1001),
+                }),
+                pool.add(signed, origin, 0, &default_account_details)
+            );
+        }
+        let signed = payment(0, 1000).sign(keypair.private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+        );
+
+        pool.set_max_fee(None);
+        let signed = payment(1, 1_000_000).sign(keypair.private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+        );
+    }
+
+    #[test]
+    fn insufficient_fee_reports_the_minimal_fee() {
+        let keypair = Random.generate().unwrap();
+        let mut pool = MemPool::new();
+        pool.set_minimal_fee(U256::from(200));
+
+        let signed = payment(0, 100).sign(keypair.private());
+        assert_eq!(
+            Err(ParcelError::InsufficientFee {
+                minimal: U256::from(200),
+                got: U256::from(100),
+            }),
+            pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+        );
+    }
+
+    #[test]
+    fn random_operations_keep_the_pool_consistent() {
+        let senders: Vec<KeyPair> = (1..6u64).map(|i| KeyPair::from_private(H256::from(i).into()).unwrap()).collect();
+        let origins = [ParcelOrigin::Local, ParcelOrigin::External, ParcelOrigin::RetractedBlock];
+        // The senders and the seed are fixed, so a failure can be reproduced.
+        let mut rng = XorShiftRng::from_seed([0x3c, 0x0d, 0xe, 0x7a]);
+        let mut state_nonces: HashMap<Address, U256> = HashMap::new();
+        let nonce_of = |nonces: &HashMap<Address, U256>, address: &Address| {
+            nonces.get(address).cloned().unwrap_or_else(U256::zero)
+        };
+        let mut pool = MemPool::with_limits(30, usize::max_value());
+        pool.set_future_limit(30);
+
+        for _ in 0..3000 {
+            let sender = &senders[rng.gen_range(0, senders.len())];
+            let state_nonce = nonce_of(&state_nonces, &sender.address());
+            match rng.gen_range(0, 20) {
+                0...11 => {
+                    let nonce = state_nonce.low_u64() + rng.gen_range(0, 8);
+                    let signed = payment(nonce, rng.gen_range(1, 50)).sign(sender.private());
+                    let origin = origins[rng.gen_range(0, origins.len())];
+                    let fetch_account = |address: &Address| AccountDetails {
+                        nonce: nonce_of(&state_nonces, address),
+                        balance: U256::max_value(),
+                    };
+                    let _ = pool.add(signed, origin, 0, &fetch_account);
+                }
+                12...14 => {
+                    let mut hashes: Vec<H256> = pool.hashes().into_iter().collect();
+                    if !hashes.is_empty() {
+                        hashes.sort();
+                        let hash = hashes[rng.gen_range(0, hashes.len())];
+                        let fetch_nonce = |address: &Address| nonce_of(&state_nonces, address);
+                        pool.remove(&hash, &fetch_nonce, RemovalReason::Invalid);
+                    }
+                }
+                15...17 => {
+                    // Some parcels of the sender are mined.
+                    let state_nonce = state_nonce + U256::from(rng.gen_range(0, 3));
+                    state_nonces.insert(sender.address(), state_nonce);
+                    pool.cull(sender.address(), state_nonce);
+                }
+                _ => pool.set_limit(rng.gen_range(5, 40)),
+            }
+            pool.assert_invariants(&|address: &Address| nonce_of(&state_nonces, address));
+        }
+    }
+
+    #[test]
+    fn pages_of_the_parcels_make_up_the_order_of_the_pool() {
+        let mut pool = MemPool::with_limits(1000, usize::max_value());
+        for i in 0..250 {
+            let signed = payment(0, 10 + i % 7).sign(Random.generate().unwrap().private());
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        let keypair = Random.generate().unwrap();
+        for nonce in 1..4 {
+            let signed = payment(nonce, 10).sign(keypair.private());
+            assert_eq!(
+                Ok(ParcelImportResult::Future),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        let generation = pool.generation();
+
+        let mut paged = Vec::new();
+        for offset in &[0, 100, 200] {
+            let page = pool.pending_page(*offset, 100);
+            assert_eq!(250, page.total);
+            assert_eq!(generation, page.generation);
+            paged.extend(page.parcels);
+        }
+        assert_eq!(pool.top_parcels(), paged);
+        assert_eq!(pool.future_parcels(), pool.future_page(0, 100).parcels);
+        assert_eq!(Vec::<SignedParcel>::new(), pool.pending_page(300, 100).parcels);
+
+        let signed = payment(0, 10).sign(Random.generate().unwrap().private());
+        pool.add(signed, ParcelOrigin::External, 0, &default_account_details).unwrap();
+        let page = pool.pending_page(0, 100);
+        assert_eq!(251, page.total);
+        assert_ne!(generation, page.generation);
+    }
+
+    #[test]
+    fn pending_position_follows_the_fee() {
+        let mut pool = MemPool::new();
+        for (expected_position, fee) in [30, 20, 10].iter().enumerate() {
+            let signed = payment(0, *fee).sign(Random.generate().unwrap().private());
+            let hash = signed.hash();
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+            assert_eq!(
+                Some(PendingPosition {
+                    position: expected_position,
+                    pending: expected_position + 1,
+                }),
+                pool.pending_position(&hash)
+            );
+        }
+
+        let expensive = payment(0, 40).sign(Random.generate().unwrap().private());
+        let hash = expensive.hash();
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(expensive, ParcelOrigin::External, 0, &default_account_details)
+        );
+        assert_eq!(
+            Some(PendingPosition {
+                position: 0,
+                pending: 4,
+            }),
+            pool.pending_position(&hash)
+        );
+
+        let future = payment(1, 50).sign(Random.generate().unwrap().private());
+        let hash = future.hash();
+        assert_eq!(
+            Ok(ParcelImportResult::Future),
+            pool.add(future, ParcelOrigin::External, 0, &default_account_details)
+        );
+        assert_eq!(None, pool.pending_position(&hash));
+    }
+
+    #[test]
+    fn minimal_fee_is_chosen_by_the_kind_of_the_action() {
+        let mut pool = MemPool::new();
+        pool.set_minimal_fee(U256::from(10));
+        pool.set_minimal_fee_for(ActionKind::Payment, Some(U256::from(5)));
+        pool.set_minimal_fee_for(ActionKind::ChangeShardState, Some(U256::from(50)));
+        let with_action = |fee: u64, action: Action| -> SignedParcel {
+            let mut parcel = payment(0, fee);
+            parcel.action = action;
+            parcel.sign(Random.generate().unwrap().private())
+        };
+        let change_shard_state = || Action::ChangeShardState {
+            transactions: vec![],
+        };
+        let set_regular_key = || Action::SetRegularKey {
+            key: Default::default(),
+        };
+
+        let cheap_payment = payment(0, 5).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(cheap_payment, ParcelOrigin::External, 0, &default_account_details)
+        );
+        assert_eq!(
+            Err(ParcelError::InsufficientFee {
+                minimal: U256::from(50),
+                got: U256::from(49),
+            }),
+            pool.add(with_action(49, change_shard_state()), ParcelOrigin::External, 0, &default_account_details)
+        );
+        let result =
+            pool.add(with_action(50, change_shard_state()), ParcelOrigin::External, 0, &default_account_details);
+        assert_eq!(Ok(ParcelImportResult::Current), result);
+
+        // The kinds without their own minimal fees follow the minimal fee.
+        assert_eq!(U256::from(10), pool.minimal_fee_for(ActionKind::SetRegularKey));
+        assert_eq!(
+            Err(ParcelError::InsufficientFee {
+                minimal: U256::from(10),
+                got: U256::from(9),
+            }),
+            pool.add(with_action(9, set_regular_key()), ParcelOrigin::External, 0, &default_account_details)
+        );
+        let result = pool.add(with_action(10, set_regular_key()), ParcelOrigin::External, 0, &default_account_details);
+        assert_eq!(Ok(ParcelImportResult::Current), result);
+
+        pool.set_minimal_fee_for(ActionKind::Payment, None);
+        assert_eq!(U256::from(10), pool.minimal_fee_for(ActionKind::Payment));
+        assert_eq!(1, pool.remove_below_fee(&default_account_details));
+        assert_eq!(2, pool.status().pending);
+    }
+
+    #[test]
+    fn raising_the_minimal_fee_removes_underpriced_external_parcels() {
+        let mut pool = MemPool::new();
+        pool.set_minimal_fee(U256::from(5));
+
+        let external = Random.generate().unwrap();
+        for nonce in 0..2 {
+            let signed = payment(nonce, 5).sign(external.private());
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        let expensive = payment(0, 10).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(expensive, ParcelOrigin::External, 0, &default_account_details)
+        );
+        let local = payment(0, 5).sign(Random.generate().unwrap().private());
+        let local_hash = local.hash();
+        assert_eq!(Ok(ParcelImportResult::Current), pool.add(local, ParcelOrigin::Local, 0, &default_account_details));
+        assert_eq!(4, pool.status().pending);
+
+        pool.set_minimal_fee(U256::from(10));
+        assert_eq!(4, pool.status().pending, "Raising the minimal fee alone doesn't touch the pool");
+
+        assert_eq!(2, pool.remove_below_fee(&default_account_details));
+        assert_eq!(2, pool.status().pending);
+        assert_eq!(0, pool.status().future);
+        assert!(pool.find(&local_hash).is_some());
+        assert!(pool.top_parcels().iter().all(|parcel| parcel.fee >= U256::from(10) || parcel.hash() == local_hash));
+
+        assert_eq!(0, pool.remove_below_fee(&default_account_details));
+    }
+
+    #[test]
+    fn generation_increases_only_when_the_parcels_change() {
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: U256::from(50),
+        };
+        let fetch_nonce = |_: &Address| U256::zero();
+        let mut pool = MemPool::new();
+        let generation = pool.generation();
+
+        let keypair = Random.generate().unwrap();
+        let too_expensive = payment(0, 100).sign(keypair.private());
+        assert!(pool.add(too_expensive, ParcelOrigin::External, 0, &fetch_account).is_err());
+        assert_eq!(generation, pool.generation());
+
+        let signed = payment(0, 10).sign(keypair.private());
+        let hash = signed.hash();
+        assert_eq!(Ok(ParcelImportResult::Current), pool.add(signed, ParcelOrigin::External, 0, &fetch_account));
+        assert!(pool.generation() > generation);
+
+        let generation = pool.generation();
+        pool.remove(&H256::random(), &fetch_nonce, RemovalReason::Invalid);
+        assert_eq!(generation, pool.generation());
+        pool.remove(&hash, &fetch_nonce, RemovalReason::Invalid);
+        assert!(pool.generation() > generation);
+    }
+
+    #[test]
+    fn local_parcels_are_taken_only_when_they_change() {
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: U256::from(50),
+        };
+        let fetch_nonce = |_: &Address| U256::zero();
+        let mut pool = MemPool::new();
+        assert_eq!(None, pool.take_changed_local_parcels());
+
+        let external = payment(0, 10).sign(Random.generate().unwrap().private());
+        pool.add(external, ParcelOrigin::External, 0, &fetch_account).unwrap();
+        assert_eq!(None, pool.take_changed_local_parcels());
+
+        let local = payment(0, 10).sign(Random.generate().unwrap().private());
+        pool.add(local.clone(), ParcelOrigin::Local, 0, &fetch_account).unwrap();
+        assert_eq!(Some(vec![local.clone()]), pool.take_changed_local_parcels());
+        assert_eq!(None, pool.take_changed_local_parcels());
+
+        pool.remove(&local.hash(), &fetch_nonce, RemovalReason::Invalid);
+        assert_eq!(Some(vec![]), pool.take_changed_local_parcels());
+    }
+
+    #[test]
+    fn insufficient_balance_reports_the_balance_and_the_required_amount() {
+        let keypair = Random.generate().unwrap();
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: U256::from(50),
+        };
+        let mut pool = MemPool::new();
+
+        let signed = payment(0, 100).sign(keypair.private());
+        assert_eq!(
+            Err(ParcelError::InsufficientBalance {
+                address: keypair.address(),
+                balance: U256::from(50),
+                required: U256::from(100),
+            }),
+            pool.add(signed, ParcelOrigin::External, 0, &fetch_account)
+        );
+    }
+
+    #[test]
+    fn insufficient_balance_counts_the_value_of_the_payment() {
+        let keypair = Random.generate().unwrap();
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: U256::from(150),
+        };
+        let mut pool = MemPool::new();
+        let with_value = |value: U256| -> SignedParcel {
+            let mut parcel = payment(0, 100);
+            parcel.action = Action::Payment {
+                receiver: Address::default(),
+                value,
+            };
+            parcel.sign(keypair.private())
+        };
+
+        assert_eq!(
+            Err(ParcelError::InsufficientBalance {
+                address: keypair.address(),
+                balance: U256::from(150),
+                required: U256::from(151),
+            }),
+            pool.add(with_value(51.into()), ParcelOrigin::External, 0, &fetch_account)
+        );
+        assert_eq!(
+            Err(ParcelError::InsufficientBalance {
+                address: keypair.address(),
+                balance: U256::from(150),
+                required: U256::from(151),
+            }),
+            pool.add(with_value(51.into()), ParcelOrigin::Local, 0, &fetch_account)
+        );
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(with_value(50.into()), ParcelOrigin::External, 0, &fetch_account)
+        );
+    }
+
+    #[test]
+    fn cost_overflowing_u256_is_rejected_as_insufficient_balance() {
+        let keypair = Random.generate().unwrap();
+        let mut pool = MemPool::new();
+        let mut parcel = payment(0, 100);
+        parcel.action = Action::Payment {
+            receiver: Address::default(),
+            value: U256::max_value(),
+        };
+
+        assert_eq!(
+            Err(ParcelError::InsufficientBalance {
+                address: keypair.address(),
+                balance: U256::max_value(),
+                required: U256::max_value(),
+            }),
+            pool.add(parcel.sign(keypair.private()), ParcelOrigin::External, 0, &default_account_details)
+        );
+        assert_eq!(0, pool.status().pending);
+    }
+
+    #[test]
+    fn parcels_costing_more_than_the_balance_together_are_rejected() {
+        let keypair = Random.generate().unwrap();
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: U256::from(25),
+        };
+        let fetch_nonce = |_: &Address| U256::zero();
+        let mut pool = MemPool::new();
+        for nonce in 0..2 {
+            let signed = payment(nonce, 10).sign(keypair.private());
+            assert_eq!(Ok(ParcelImportResult::Current), pool.add(signed, ParcelOrigin::External, 0, &fetch_account));
+        }
+        assert_eq!(U256::from(20), pool.cumulative_cost(&keypair.address()));
+
+        let signed = payment(2, 10).sign(keypair.private());
+        assert_eq!(
+            Err(ParcelError::InsufficientBalanceForQueue {
+                balance: U256::from(25),
+                cumulative_cost: U256::from(30),
+            }),
+            pool.add(signed, ParcelOrigin::External, 0, &fetch_account)
+        );
+
+        // Only the cost of the new one counts for a replacement.
+        let replacing = payment(1, 15).sign(keypair.private());
+        let result = pool.add(replacing.clone(), ParcelOrigin::External, 0, &fetch_account);
+        assert_eq!(Ok(ParcelImportResult::Current), result);
+        assert_eq!(U256::from(25), pool.cumulative_cost(&keypair.address()));
+
+        pool.remove(&replacing.hash(), &fetch_nonce, RemovalReason::Invalid);
+        assert_eq!(U256::from(10), pool.cumulative_cost(&keypair.address()));
+        pool.cull(keypair.address(), U256::one());
+        assert_eq!(U256::zero(), pool.cumulative_cost(&keypair.address()));
+    }
+
+    #[test]
+    fn unaffordable_parcels_are_demoted_at_the_next_cull() {
+        let keypair = Random.generate().unwrap();
+        let balance = Cell::new(U256::from(100));
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::zero(),
+            balance: balance.get(),
+        };
+        let mut pool = MemPool::new();
+        let parcels: Vec<_> = (0..3).map(|nonce| payment(nonce, 10).sign(keypair.private())).collect();
+        for parcel in &parcels {
+            let result = pool.add(parcel.clone(), ParcelOrigin::External, 0, &fetch_account);
+            assert_eq!(Ok(ParcelImportResult::Current), result);
+        }
+
+        balance.set(U256::from(15));
+        pool.remove_old(&fetch_account, 0);
+        assert_eq!(vec![parcels[0].clone()], pool.top_parcels());
+        assert!(pool.is_in_future(&parcels[1].hash()));
+        assert!(pool.is_in_future(&parcels[2].hash()));
+        assert_eq!(U256::from(30), pool.cumulative_cost(&keypair.address()));
+
+        // A new parcel can't be queued while the demoted ones are in the pool.
+        let signed = payment(3, 10).sign(keypair.private());
+        assert_eq!(
+            Err(ParcelError::InsufficientBalanceForQueue {
+                balance: U256::from(15),
+                cumulative_cost: U256::from(40),
+            }),
+            pool.add(signed, ParcelOrigin::External, 0, &fetch_account)
+        );
+    }
+
+    #[test]
+    fn demoted_parcels_are_promoted_only_when_they_are_affordable() {
+        let keypair = Random.generate().unwrap();
+        let nonce = Cell::new(U256::zero());
+        let balance = Cell::new(U256::from(100));
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: nonce.get(),
+            balance: balance.get(),
+        };
+        let mut pool = MemPool::new();
+        let parcels: Vec<_> = (0..3).map(|nonce| payment(nonce, 10).sign(keypair.private())).collect();
+        for parcel in &parcels {
+            let result = pool.add(parcel.clone(), ParcelOrigin::External, 0, &fetch_account);
+            assert_eq!(Ok(ParcelImportResult::Current), result);
+        }
+        balance.set(U256::from(5));
+        pool.remove_old(&fetch_account, 0);
+        assert_eq!(0, pool.status().pending);
+
+        // The next cull with the same state changes nothing.
+        let generation = pool.changes().mem_pool_generation();
+        pool.remove_old(&fetch_account, 0);
+        assert_eq!(generation, pool.changes().mem_pool_generation());
+        assert_eq!(0, pool.status().pending);
+
+        // Only the parcels the new balance can pay for are promoted.
+        nonce.set(U256::one());
+        balance.set(U256::from(15));
+        pool.remove_old(&fetch_account, 0);
+        assert_eq!(vec![parcels[1].clone()], pool.top_parcels());
+        assert!(pool.is_in_future(&parcels[2].hash()));
+    }
+
+    #[test]
+    fn old_parcel_reports_the_state_nonce() {
+        let keypair = Random.generate().unwrap();
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::from(3),
+            balance: U256::max_value(),
+        };
+        let mut pool = MemPool::new();
+
+        let signed = payment(1, 100).sign(keypair.private());
+        assert_eq!(
+            Err(ParcelError::Old {
+                expected: U256::from(3),
+                got: U256::from(1),
+            }),
+            pool.add(signed, ParcelOrigin::External, 0, &fetch_account)
+        );
+    }
+
+    #[test]
+    fn limit_reached_reports_the_limit() {
+        let mut pool = MemPool::with_limits(1, usize::max_value());
+
+        // Both parcels have a nonce gap, so they go to the future queue.
+        let first = payment(5, 100).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Ok(ParcelImportResult::Future),
+            pool.add(first, ParcelOrigin::External, 0, &default_account_details)
+        );
+        let second = payment(5, 100).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Err(ParcelError::LimitReached {
+                limit: 1,
+            }),
+            pool.add(second, ParcelOrigin::External, 0, &default_account_details)
+        );
+    }
+
+    #[test]
+    fn local_parcel_survives_the_overflow_of_the_queue() {
+        let mut pool = MemPool::with_limits(3, usize::max_value());
+        for fee in 20..23 {
+            let external = payment(0, fee).sign(Random.generate().unwrap().private());
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(external, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        // The local parcel pays less than any external parcel.
+        let local = payment(0, 10).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(local.clone(), ParcelOrigin::Local, 0, &default_account_details)
+        );
+
+        for fee in 30..33 {
+            let external = payment(0, fee).sign(Random.generate().unwrap().private());
+            pool.add(external, ParcelOrigin::External, 0, &default_account_details).unwrap();
+        }
+        assert!(pool.top_parcels().contains(&local));
+        assert_eq!(1, pool.status().by_origin.local);
+    }
+
+    #[test]
+    fn external_parcel_is_rejected_if_only_local_parcels_can_be_evicted() {
+        let mut pool = MemPool::with_limits(2, usize::max_value());
+        let locals: Vec<_> = (0..2).map(|_| payment(0, 10).sign(Random.generate().unwrap().private())).collect();
+        for local in locals.clone() {
+            pool.add(local, ParcelOrigin::Local, 0, &default_account_details).unwrap();
+        }
+
+        let external = payment(0, 100).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Err(ParcelError::LimitReached {
+                limit: 2,
+            }),
+            pool.add(external.clone(), ParcelOrigin::External, 0, &default_account_details)
+        );
+        assert_eq!(None, pool.find(&external.hash()));
+        assert_eq!(2, pool.top_parcels().len());
+        for local in locals {
+            assert!(pool.top_parcels().contains(&local));
+        }
+    }
+
+    #[test]
+    fn full_future_drops_the_cheapest_parcel() {
+        let mut pool = MemPool::new();
+        pool.set_future_limit(2);
+        assert_eq!(2, pool.future_limit());
+        assert_eq!(8192, pool.limit());
+
+        // All parcels have a nonce gap, so they go to the future queue.
+        let cheap = payment(5, 10).sign(Random.generate().unwrap().private());
+        let expensive = payment(5, 30).sign(Random.generate().unwrap().private());
+        let middle = payment(5, 20).sign(Random.generate().unwrap().private());
+        for parcel in vec![cheap.clone(), expensive.clone(), middle.clone()] {
+            assert_eq!(
+                Ok(ParcelImportResult::Future),
+                pool.add(parcel, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        let cheapest = payment(5, 5).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Err(ParcelError::LimitReached {
+                limit: 2,
+            }),
+            pool.add(cheapest, ParcelOrigin::External, 0, &default_account_details)
+        );
+
+        let future = pool.future_parcels();
+        assert_eq!(2, future.len());
+        assert!(future.contains(&expensive));
+        assert!(future.contains(&middle));
+        assert!(pool.find(&cheap.hash()).is_none());
+
+        let generation = pool.generation();
+        pool.set_future_limit(1);
+        assert!(pool.generation() > generation);
+        assert_eq!(vec![expensive], pool.future_parcels());
+
+        // The full future queue doesn't keep the ready parcels out.
+        let ready = payment(0, 1).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(ready, ParcelOrigin::External, 0, &default_account_details)
+        );
+    }
+
+    #[test]
+    fn gap_filling_parcel_moves_the_cheapest_ready_parcel_to_future() {
+        let mut pool = MemPool::with_limits(3, usize::max_value());
+        pool.set_future_limit(2);
+        let sender = Random.generate().unwrap();
+
+        let first = payment(0, 100).sign(sender.private());
+        let third = payment(2, 100).sign(sender.private());
+        let cheap = payment(0, 10).sign(Random.generate().unwrap().private());
+        let waiting = payment(5, 50).sign(Random.generate().unwrap().private());
+        for parcel in vec![first.clone(), third.clone(), cheap.clone(), waiting.clone()] {
+            pool.add(parcel, ParcelOrigin::External, 0, &default_account_details).unwrap();
+        }
+        assert_eq!(2, pool.status().pending);
+        assert_eq!(2, pool.status().future);
+
+        // The second parcel fills the gap and promotes the third one over the limit of current.
+        let second = payment(1, 100).sign(sender.private());
+        let result = pool.add(second.clone(), ParcelOrigin::External, 0, &default_account_details);
+        assert_eq!(Ok(ParcelImportResult::Current), result);
+
+        assert_eq!(vec![first, second, third], pool.top_parcels());
+        let future = pool.future_parcels();
+        assert_eq!(2, future.len());
+        assert!(future.contains(&cheap));
+        assert!(future.contains(&waiting));
+        assert_eq!(None, pool.last_nonce(&cheap.sender()));
+    }
+
+    #[test]
+    fn parcels_promoted_by_a_cull_do_not_go_over_the_limit_of_current() {
+        let mut pool = MemPool::with_limits(2, usize::max_value());
+        let sender = Random.generate().unwrap();
+
+        let mined = payment(0, 100).sign(sender.private());
+        let third = payment(2, 100).sign(sender.private());
+        let fourth = payment(3, 100).sign(sender.private());
+        let cheap = payment(0, 10).sign(Random.generate().unwrap().private());
+        for parcel in vec![mined, third.clone(), fourth.clone(), cheap.clone()] {
+            pool.add(parcel, ParcelOrigin::External, 0, &default_account_details).unwrap();
+        }
+        assert_eq!(2, pool.status().pending);
+        assert_eq!(2, pool.status().future);
+
+        // The first two parcels of the sender are mined, so the others become ready.
+        pool.cull(sender.address(), U256::from(2));
+
+        assert_eq!(vec![third, fourth], pool.top_parcels());
+        assert_eq!(vec![cheap.clone()], pool.future_parcels());
+        assert_eq!(None, pool.last_nonce(&cheap.sender()));
+        pool.assert_invariants(&|address: &Address| {
+            if *address == sender.address() {
+                U256::from(2)
+            } else {
+                U256::zero()
+            }
+        });
+    }
+
+    #[test]
+    fn dump_is_serialized_with_every_parcel_in_the_pool() {
+        let mut pool = MemPool::new();
+        let sender = Random.generate().unwrap();
+        let local = Random.generate().unwrap();
+
+        let ready = payment(0, 10).sign(sender.private());
+        let waiting = payment(2, 20).sign(sender.private());
+        let own = payment(0, 30).sign(local.private());
+        pool.add(waiting.clone(), ParcelOrigin::External, 3, &default_account_details).unwrap();
+        pool.add(ready.clone(), ParcelOrigin::External, 4, &default_account_details).unwrap();
+        pool.add(own.clone(), ParcelOrigin::Local, 5, &default_account_details).unwrap();
+
+        let dump = pool.dump();
+        let mut addresses = vec![sender.address(), local.address()];
+        addresses.sort();
+        assert_eq!(addresses, dump.senders.iter().map(|dump| dump.sender).collect::<Vec<_>>());
+
+        let json = serde_json::to_value(&dump).unwrap();
+        let senders = json["senders"].as_array().unwrap();
+        assert_eq!(2, senders.len());
+        let mut count = 0;
+        for (sender_dump, sender_json) in dump.senders.iter().zip(senders) {
+            assert_eq!(serde_json::to_value(sender_dump.sender).unwrap(), sender_json["sender"]);
+            let parcels = sender_json["parcels"].as_array().unwrap();
+            assert_eq!(sender_dump.parcels.len(), parcels.len());
+            for (parcel, parcel_json) in sender_dump.parcels.iter().zip(parcels) {
+                let found = pool.find(&parcel.hash).unwrap();
+                assert_eq!(sender_dump.sender, found.sender());
+                assert_eq!(serde_json::to_value(parcel.hash).unwrap(), parcel_json["hash"]);
+                assert_eq!(serde_json::to_value(found.nonce).unwrap(), parcel_json["nonce"]);
+                assert_eq!(serde_json::to_value(found.fee).unwrap(), parcel_json["fee"]);
+                assert_eq!(serde_json::Value::Bool(pool.is_in_future(&parcel.hash)), parcel_json["isFuture"]);
+                assert_eq!(serde_json::Value::from(parcel.insertion_time), parcel_json["insertionTime"]);
+                count += 1;
+            }
+        }
+        assert_eq!(3, count);
+
+        let sender_dump = dump.senders.iter().find(|dump| dump.sender == sender.address()).unwrap();
+        assert_eq!(
+            vec![(ready.hash(), false, 4, ParcelOrigin::External), (waiting.hash(), true, 3, ParcelOrigin::External)],
+            sender_dump
+                .parcels
+                .iter()
+                .map(|parcel| (parcel.hash, parcel.is_future, parcel.insertion_time, parcel.origin))
+                .collect::<Vec<_>>()
+        );
+        let index = dump.senders.iter().position(|dump| dump.sender == local.address()).unwrap();
+        assert_eq!(own.hash(), dump.senders[index].parcels[0].hash);
+        assert_eq!(serde_json::Value::from("local"), senders[index]["parcels"][0]["origin"]);
+    }
+
+    #[test]
+    fn parcels_with_nonces_too_far_in_the_future_are_rejected() {
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::one(),
+            balance: U256::max_value(),
+        };
+        let mut pool = MemPool::new();
+        pool.set_max_nonce_gap(2);
+        pool.set_max_nonce_gap_for_local(3);
+        let sender = Random.generate().unwrap();
+        let local = Random.generate().unwrap();
+
+        let third = payment(2, 100).sign(sender.private());
+        let fourth = payment(3, 100).sign(sender.private());
+        let result = pool.add(third.clone(), ParcelOrigin::External, 0, &default_account_details);
+        assert_eq!(Ok(ParcelImportResult::Future), result);
+        assert_eq!(
+            Err(ParcelError::NonceTooFarInFuture {
+                expected_max: 2.into(),
+                got: 3.into(),
+            }),
+            pool.add(fourth.clone(), ParcelOrigin::External, 0, &default_account_details)
+        );
+
+        let local_parcel = payment(3, 100).sign(local.private());
+        assert_eq!(
+            Ok(ParcelImportResult::Future),
+            pool.add(local_parcel.clone(), ParcelOrigin::Local, 0, &default_account_details)
+        );
+        assert_eq!(
+            Err(ParcelError::NonceTooFarInFuture {
+                expected_max: 3.into(),
+                got: 4.into(),
+            }),
+            pool.add(payment(4, 100).sign(local.private()), ParcelOrigin::Local, 0, &default_account_details)
+        );
+
+        // The chain passed the first nonce of the sender, so the fourth parcel is close enough now.
+        pool.cull(sender.address(), U256::one());
+        assert_eq!(Ok(ParcelImportResult::Future), pool.add(fourth.clone(), ParcelOrigin::External, 1, &fetch_account));
+        let second = payment(1, 100).sign(sender.private());
+        let result = pool.add(second.clone(), ParcelOrigin::External, 1, &fetch_account);
+        assert_eq!(Ok(ParcelImportResult::Current), result);
+
+        assert_eq!(vec![second, third, fourth], pool.top_parcels());
+        assert_eq!(vec![local_parcel], pool.future_parcels());
+        assert_eq!(Some(&2), pool.metrics(1).rejected.get(&RejectionReason::NonceTooFarInFuture));
+    }
+
+    /// Returns a pool holding pending parcels paying `fees`.
+    fn pool_with_fees(fees: &[u64]) -> MemPool {
+        let mut pool = MemPool::new();
+        let keypair = Random.generate().unwrap();
+        for (nonce, fee) in fees.iter().enumerate() {
+            let parcel = payment(nonce as u64, *fee).sign(keypair.private());
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(parcel, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        pool
+    }
+
+    #[test]
+    fn fee_percentile_needs_enough_pending_parcels() {
+        assert_eq!(None, pool_with_fees(&[]).fee_percentile(50));
+        assert_eq!(None, pool_with_fees(&[10, 20, 30]).fee_percentile(50));
+        assert_eq!(Some(U256::from(20)), pool_with_fees(&[10, 20, 30, 40]).fee_percentile(50));
+    }
+
+    #[test]
+    fn fee_percentile_of_uniform_fees() {
+        let pool = pool_with_fees(&[50; 10]);
+        for percentile in &[0, 1, 50, 99, 100, 255] {
+            assert_eq!(Some(U256::from(50)), pool.fee_percentile(*percentile));
+        }
+    }
+
+    #[test]
+    fn fee_percentile_of_skewed_fees() {
+        let pool = pool_with_fees(&[10, 10, 10, 10, 10, 10, 10, 10, 20, 1000]);
+        assert_eq!(Some(U256::from(10)), pool.fee_percentile(0));
+        assert_eq!(Some(U256::from(10)), pool.fee_percentile(50));
+        assert_eq!(Some(U256::from(10)), pool.fee_percentile(80));
+        assert_eq!(Some(U256::from(20)), pool.fee_percentile(81));
+        assert_eq!(Some(U256::from(20)), pool.fee_percentile(90));
+        assert_eq!(Some(U256::from(1000)), pool.fee_percentile(91));
+        assert_eq!(Some(U256::from(1000)), pool.fee_percentile(100));
+    }
+
+    #[test]
+    fn metrics_count_the_decisions_on_the_parcels() {
+        let fetch_nonce = |_: &Address| U256::zero();
+        let mut pool = MemPool::new();
+        pool.set_minimal_fee(U256::from(5));
+        let sender = Random.generate().unwrap();
+        let other = Random.generate().unwrap();
+
+        let first = payment(0, 10).sign(sender.private());
+        pool.add(first.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        assert!(pool.add(first, ParcelOrigin::External, 0, &default_account_details).is_err());
+        assert!(pool
+            .add(payment(0, 1).sign(other.private()), ParcelOrigin::External, 0, &default_account_details)
+            .is_err());
+        pool.add(payment(0, 20).sign(sender.private()), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        pool.add(payment(1, 20).sign(sender.private()), ParcelOrigin::External, 5, &default_account_details).unwrap();
+        let other_parcel = payment(0, 10).sign(other.private());
+        pool.add(other_parcel.clone(), ParcelOrigin::External, 20, &default_account_details).unwrap();
+
+        let metrics = pool.metrics(25);
+        assert_eq!(4, metrics.accepted);
+        assert_eq!(1, metrics.replaced);
+        let mut rejected = BTreeMap::new();
+        rejected.insert(RejectionReason::AlreadyImported, 1);
+        rejected.insert(RejectionReason::InsufficientFee, 1);
+        assert_eq!(rejected, metrics.rejected);
+        assert_eq!(vec![0, 1, 2, 0, 0], metrics.ages);
+
+        // The chain accepted the first parcel of the sender
+        pool.cull(sender.address(), U256::one());
+        pool.remove(&other_parcel.hash(), &fetch_nonce, RemovalReason::Invalid);
+        pool.set_future_limit(0);
+        pool.set_limit(0);
+
+        let metrics = pool.metrics(25);
+        assert_eq!(1, metrics.culled);
+        let mut removed = BTreeMap::new();
+        removed.insert(RemovalReason::Invalid, 1);
+        removed.insert(RemovalReason::Dropped, 1);
+        assert_eq!(removed, metrics.removed);
+        assert_eq!(vec![0; PARCEL_AGE_BUCKETS.len() + 1], metrics.ages);
+        assert_eq!(4, metrics.accepted);
+    }
+
+    #[test]
+    fn every_parcel_error_has_its_rejection_reason() {
+        let value = U256::from(10);
+        let cases = vec![
+            (ParcelError::AlreadyImported, RejectionReason::AlreadyImported),
+            (
+                ParcelError::Old {
+                    expected: value,
+                    got: value,
+                },
+                RejectionReason::Old,
+            ),
+            (ParcelError::TooCheapToReplace, RejectionReason::TooCheapToReplace),
+            (ParcelError::InvalidNetworkId, RejectionReason::InvalidNetworkId),
+            (
+                ParcelError::LimitReached {
+                    limit: 1,
+                },
+                RejectionReason::LimitReached,
+            ),
+            (
+                ParcelError::MemoryLimitReached {
+                    limit: 1,
+                },
+                RejectionReason::MemoryLimitReached,
+            ),
+            (
+                ParcelError::SenderLimitReached {
+                    limit: 1,
+                },
+                RejectionReason::SenderLimitReached,
+            ),
+            (
+                ParcelError::InsufficientFee {
+                    minimal: value,
+                    got: value,
+                },
+                RejectionReason::InsufficientFee,
+            ),
+            (
+                ParcelError::FeeTooHigh {
+                    maximum: value,
+                    got: value,
+                },
+                RejectionReason::FeeTooHigh,
+            ),
+            (
+                ParcelError::InsufficientBalance {
+                    address: Address::default(),
+                    balance: value,
+                    required: value,
+                },
+                RejectionReason::InsufficientBalance,
+            ),
+            (
+                ParcelError::InsufficientBalanceForQueue {
+                    balance: value,
+                    cumulative_cost: value,
+                },
+                RejectionReason::InsufficientBalanceForQueue,
+            ),
+            (
+                ParcelError::InvalidNonce {
+                    expected: value,
+                    got: value,
+                },
+                RejectionReason::InvalidNonce,
+            ),
+            (
+                ParcelError::NonceTooFarInFuture {
+                    expected_max: value,
+                    got: value,
+                },
+                RejectionReason::NonceTooFarInFuture,
+            ),
+            (ParcelError::NotAllowed, RejectionReason::NotAllowed),
+            (ParcelError::SenderBanned, RejectionReason::SenderBanned),
+            (ParcelError::InvalidSignature("invalid".to_string()), RejectionReason::InvalidSignature),
+        ];
+        let mut pool = MemPool::new();
+        let mut expected = BTreeMap::new();
+        for (err, reason) in cases {
+            assert_eq!(reason, RejectionReason::from(&err));
+            pool.count_rejection(&err);
+            expected.insert(reason, 1);
+        }
+        assert_eq!(16, expected.len(), "Every reason is counted separately");
+        assert_eq!(expected, pool.metrics(0).rejected);
+    }
+
+    /// A parcel of a new sender carrying 1KB of metadata.
+    fn large_parcel(fee: u64) -> SignedParcel {
+        let transactions = vec![Transaction::AssetMint {
+            metadata: "M".repeat(1024),
+            lock_script_hash: H256::zero(),
+            parameters: vec![],
+            amount: None,
+            registrar: None,
+            nonce: 0,
+        }];
+        Parcel {
+            nonce: U256::zero(),
+            fee: U256::from(fee),
+            network_id: 200,
+            action: Action::ChangeShardState {
+                transactions,
+            },
+        }.sign(Random.generate().unwrap().private())
+    }
+
+    #[test]
+    fn cheapest_external_parcels_are_dropped_out_of_the_memory_limit() {
+        let size = large_parcel(10).rlp_bytes().len();
+        assert!(size > 1024);
+        let mut pool = MemPool::new();
+        pool.set_memory_limit(size * 3);
+
+        let parcels: Vec<_> = [30, 10, 20].iter().map(|fee| large_parcel(*fee)).collect();
+        for parcel in &parcels {
+            pool.add(parcel.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        }
+        assert_eq!(size * 3, pool.status().encoded_size);
+
+        let local = large_parcel(1);
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(local.clone(), ParcelOrigin::Local, 0, &default_account_details)
+        );
+        assert_eq!(None, pool.find(&parcels[1].hash()), "The parcel paying 10 is dropped first");
+
+        let expensive = large_parcel(40);
+        let result = pool.add(expensive.clone(), ParcelOrigin::External, 0, &default_account_details);
+        assert_eq!(Ok(ParcelImportResult::Current), result);
+        assert_eq!(None, pool.find(&parcels[2].hash()), "The parcel paying 20 is dropped next");
+
+        let cheap = large_parcel(5);
+        assert_eq!(
+            Err(ParcelError::MemoryLimitReached {
+                limit: size * 3,
+            }),
+            pool.add(cheap.clone(), ParcelOrigin::External, 0, &default_account_details)
+        );
+        assert_eq!(None, pool.find(&cheap.hash()));
+        let metrics = pool.metrics(0);
+        assert_eq!(Some(&1), metrics.rejected.get(&RejectionReason::MemoryLimitReached));
+        // The rejected parcel is not counted as removed.
+        assert_eq!(Some(&2), metrics.removed.get(&RemovalReason::Dropped));
+
+        let mut remaining = pool.top_parcels();
+        remaining.sort_by_key(|parcel| parcel.fee);
+        assert_eq!(vec![local, parcels[0].clone(), expensive], remaining);
+        assert_eq!(size * 3, pool.status().encoded_size);
+    }
+
+    #[test]
+    fn fee_per_byte_puts_the_smaller_parcel_first() {
+        let large = large_parcel(10);
+        let small = Parcel {
+            nonce: U256::zero(),
+            fee: U256::from(10),
+            network_id: 200,
+            action: Action::ChangeShardState {
+                transactions: vec![],
+            },
+        }.sign(Random.generate().unwrap().private());
+        let mut pool = MemPool::new();
+        pool.add(large.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        pool.add(small.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+
+        assert_eq!(OrderingStrategy::Fee, pool.ordering());
+        assert_eq!(vec![large.clone(), small.clone()], pool.top_parcels());
+
+        pool.set_ordering(OrderingStrategy::FeePerByte);
+        assert_eq!(vec![small.clone(), large.clone()], pool.top_parcels());
+        assert_eq!(Some(large.clone()), pool.remove(&large.hash(), &|_| U256::zero(), RemovalReason::Canceled));
+        assert_eq!(vec![small.clone()], pool.top_parcels());
+
+        pool.set_ordering(OrderingStrategy::Fee);
+        pool.add(large.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        assert_eq!(vec![small, large], pool.top_parcels());
+    }
+
+    #[test]
+    fn local_parcels_are_dropped_only_if_they_alone_exceed_the_memory_limit() {
+        let size = large_parcel(10).rlp_bytes().len();
+        let mut pool = MemPool::new();
+        pool.set_memory_limit(size * 2);
+
+        let first = large_parcel(10);
+        let second = large_parcel(20);
+        pool.add(first.clone(), ParcelOrigin::Local, 0, &default_account_details).unwrap();
+        pool.add(second.clone(), ParcelOrigin::Local, 0, &default_account_details).unwrap();
+        let external = large_parcel(100);
+        assert_eq!(
+            Err(ParcelError::MemoryLimitReached {
+                limit: size * 2,
+            }),
+            pool.add(external, ParcelOrigin::External, 0, &default_account_details)
+        );
+        assert_eq!(2, pool.status().pending);
+
+        pool.set_memory_limit(size);
+        let third = large_parcel(30);
+        pool.add(third.clone(), ParcelOrigin::Local, 0, &default_account_details).unwrap();
+        assert_eq!(None, pool.find(&first.hash()));
+        assert_eq!(None, pool.find(&second.hash()));
+        assert_eq!(vec![third], pool.top_parcels());
+        match pool.local_parcels().get(&first.hash()) {
+            Some(LocalParcelStatus::Dropped(_)) => {}
+            status => panic!("The local parcel must be marked as dropped: {:?}", status),
+        }
+    }
+
+    #[test]
+    fn parcels_expire_after_their_lifetime() {
+        let fetch_nonce = |_: &Address| U256::zero();
+        let mut pool = MemPool::new();
+        pool.set_max_time_in_pool(10);
+        pool.set_max_time_in_pool_for_local(20);
+
+        let external = Random.generate().unwrap();
+        let pending = payment(0, 10).sign(external.private());
+        let gapped = payment(2, 10).sign(external.private());
+        pool.add(pending.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        assert_eq!(
+            Ok(ParcelImportResult::Future),
+            pool.add(gapped, ParcelOrigin::External, 5, &default_account_details)
+        );
+        let local = payment(0, 10).sign(Random.generate().unwrap().private());
+        pool.add(local.clone(), ParcelOrigin::Local, 0, &default_account_details).unwrap();
+
+        assert_eq!(0, pool.cull_expired(&fetch_nonce, 10));
+        assert_eq!(1, pool.cull_expired(&fetch_nonce, 11));
+        assert_eq!(None, pool.find(&pending.hash()));
+        assert_eq!(1, pool.status().future, "The gapped parcel is younger");
+
+        assert_eq!(1, pool.cull_expired(&fetch_nonce, 16));
+        assert_eq!(0, pool.status().future);
+        assert_eq!(vec![local.clone()], pool.top_parcels(), "Local parcels live longer");
+
+        assert_eq!(1, pool.cull_expired(&fetch_nonce, 21));
+        assert_eq!(0, pool.status().pending);
+        assert_eq!(Some(&LocalParcelStatus::Expired(local.clone())), pool.local_parcels().get(&local.hash()));
+    }
+
+    #[test]
+    fn external_sender_cannot_exceed_the_parcels_per_sender() {
+        let mut pool = MemPool::new();
+        pool.set_max_parcels_per_sender(50);
+
+        let keypair = Random.generate().unwrap();
+        for nonce in 0..50 {
+            let signed = payment(nonce, 10).sign(keypair.private());
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        let signed = payment(50, 10).sign(keypair.private());
+        assert_eq!(
+            Err(ParcelError::SenderLimitReached {
+                limit: 50,
+            }),
+            pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+        );
+        assert_eq!(50, pool.status().pending);
+
+        // Replacing a queued parcel doesn't add to the parcels of the sender.
+        let replacing = payment(49, 20).sign(keypair.private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(replacing, ParcelOrigin::External, 0, &default_account_details)
+        );
+
+        let other = payment(0, 10).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(other, ParcelOrigin::External, 0, &default_account_details)
+        );
+        assert_eq!(51, pool.status().pending);
+    }
+
+    #[test]
+    fn local_parcels_can_exceed_the_parcels_per_sender() {
+        let mut pool = MemPool::new();
+        pool.set_max_parcels_per_sender(2);
+
+        let keypair = Random.generate().unwrap();
+        for nonce in 0..3 {
+            let signed = payment(nonce, 10).sign(keypair.private());
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::Local, 0, &default_account_details)
+            );
+        }
+        let signed = payment(3, 10).sign(keypair.private());
+        assert_eq!(
+            Err(ParcelError::SenderLimitReached {
+                limit: 2,
+            }),
+            pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+        );
+    }
+
+    #[test]
+    fn top_parcels_with_limit_is_a_prefix_of_top_parcels() {
+        let mut pool = MemPool::new();
+        for fee in 1..11 {
+            let signed = payment(0, fee).sign(Random.generate().unwrap().private());
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        let top = pool.top_parcels();
+        let size = top[0].rlp_bytes().len();
+
+        assert_eq!(top[..3].to_vec(), pool.top_parcels_with_limit(3, usize::max_value()));
+        assert_eq!(top[..4].to_vec(), pool.top_parcels_with_limit(usize::max_value(), size * 4));
+        assert_eq!(top[..4].to_vec(), pool.top_parcels_with_limit(usize::max_value(), size * 5 - 1));
+        assert_eq!(top, pool.top_parcels_with_limit(usize::max_value(), usize::max_value()));
+        assert_eq!(Vec::<SignedParcel>::new(), pool.top_parcels_with_limit(0, usize::max_value()));
+        assert_eq!(Vec::<SignedParcel>::new(), pool.top_parcels_with_limit(10, size - 1));
+    }
+
+    #[test]
+    fn top_parcels_with_limit_never_leaves_a_nonce_gap() {
+        let mut pool = MemPool::new();
+        let small_sender = Random.generate().unwrap();
+        let large_sender = Random.generate().unwrap();
+
+        let small = payment(0, 30).sign(small_sender.private());
+        // The value makes the first parcel of the large sender larger than the others.
+        let mut large = payment(0, 20);
+        large.action = Action::Payment {
+            receiver: Address::default(),
+            value: U256::from(u64::max_value()),
+        };
+        let large_parcels = vec![large.sign(large_sender.private()), payment(1, 20).sign(large_sender.private())];
+        for signed in Some(small.clone()).into_iter().chain(large_parcels.clone()) {
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        let size = small.rlp_bytes().len();
+        assert!(large_parcels[0].rlp_bytes().len() > size);
+        assert_eq!(size, large_parcels[1].rlp_bytes().len());
+
+        // The second parcel of the large sender fits, but its first one doesn't.
+        assert_eq!(vec![small.clone()], pool.top_parcels_with_limit(usize::max_value(), size * 2));
+
+        let total_size: usize = pool.top_parcels().iter().map(|parcel| parcel.rlp_bytes().len()).sum();
+        for max_bytes in 0..total_size + 1 {
+            let parcels = pool.top_parcels_with_limit(usize::max_value(), max_bytes);
+            assert!(parcels.iter().map(|parcel| parcel.rlp_bytes().len()).sum::<usize>() <= max_bytes);
+
+            let mut next_nonces: HashMap<Address, U256> = HashMap::new();
+            for parcel in parcels {
+                let next_nonce = next_nonces.entry(parcel.sender()).or_insert_with(U256::zero);
+                assert_eq!(*next_nonce, parcel.nonce, "No nonce gap within {} bytes", max_bytes);
+                *next_nonce = *next_nonce + 1.into();
+            }
+        }
+        assert_eq!(pool.top_parcels(), pool.top_parcels_with_limit(usize::max_value(), total_size));
+    }
+
+    #[test]
+    fn top_parcels_after_continues_from_the_taken_parcels() {
+        let mut pool = MemPool::new();
+        let keypair = Random.generate().unwrap();
+        for nonce in 0..3 {
+            let signed = payment(nonce, 10).sign(keypair.private());
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+        let other = payment(0, 5).sign(Random.generate().unwrap().private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(other, ParcelOrigin::External, 0, &default_account_details)
+        );
+        let top = pool.top_parcels();
+
+        let taken: HashSet<_> = top[..2].iter().map(SignedParcel::hash).collect();
+        assert_eq!(top[2..3].to_vec(), pool.top_parcels_after(1, usize::max_value(), &taken));
+        assert_eq!(top[2..].to_vec(), pool.top_parcels_after(usize::max_value(), usize::max_value(), &taken));
+        let taken: HashSet<_> = top.iter().map(SignedParcel::hash).collect();
+        assert_eq!(Vec::<SignedParcel>::new(), pool.top_parcels_after(usize::max_value(), usize::max_value(), &taken));
+    }
+
+    #[test]
+    fn a_parcel_ranked_above_the_lower_nonce_of_its_sender_is_collected_after_it() {
+        let mut pool = MemPool::new();
+        let keypair = Random.generate().unwrap();
+        let external = payment(0, 10).sign(keypair.private());
+        let retracted = payment(1, 10).sign(keypair.private());
+        let other = payment(0, 5).sign(Random.generate().unwrap().private());
+        pool.add(external.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        pool.add(retracted.clone(), ParcelOrigin::RetractedBlock, 0, &default_account_details).unwrap();
+        pool.add(other.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        assert_eq!(retracted, pool.top_parcels()[0]);
+
+        let collected = vec![external.clone(), retracted.clone(), other.clone()];
+        assert_eq!(collected, pool.top_parcels_with_limit(usize::max_value(), usize::max_value()));
+        assert_eq!(collected[..2].to_vec(), pool.top_parcels_with_limit(2, usize::max_value()));
+        let taken: HashSet<_> = vec![external.hash()].into_iter().collect();
+        assert_eq!(collected[1..].to_vec(), pool.top_parcels_after(usize::max_value(), usize::max_value(), &taken));
+    }
+
+    #[test]
+    fn status_counts_the_parcels_by_origin() {
+        let mut pool = MemPool::new();
+        let local = Random.generate().unwrap();
+        let external = Random.generate().unwrap();
+        let other = Random.generate().unwrap();
+        let local_parcels: Vec<_> = (0..2).map(|nonce| payment(nonce, 10).sign(local.private())).collect();
+        for signed in local_parcels.clone() {
+            pool.add(signed, ParcelOrigin::Local, 0, &default_account_details).unwrap();
+        }
+        for &nonce in &[0, 2] {
+            pool.add(payment(nonce, 10).sign(external.private()), ParcelOrigin::External, 0, &default_account_details)
+                .unwrap();
+        }
+        pool.add(payment(0, 10).sign(other.private()), ParcelOrigin::RetractedBlock, 0, &default_account_details)
+            .unwrap();
+        let status = pool.status();
+        assert_eq!(
+            ParcelsByOrigin {
+                local: 2,
+                external: 2,
+                retracted: 1,
+            },
+            status.by_origin
+        );
+        assert_eq!(1, status.future);
+        assert_eq!(pool.mem_usage(), status.mem_usage);
+
+        // Promoted from future
+        pool.add(payment(1, 10).sign(external.private()), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        // Replaced by a parcel of another origin
+        pool.add(payment(0, 20).sign(other.private()), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        assert_eq!(0, pool.status().future);
+        assert_eq!(
+            ParcelsByOrigin {
+                local: 2,
+                external: 4,
+                retracted: 0,
+            },
+            pool.status().by_origin
+        );
+
+        pool.remove(&local_parcels[0].hash(), &|_: &Address| U256::zero(), RemovalReason::Invalid);
+        let status = pool.status();
+        assert_eq!(1, status.by_origin.local);
+        assert_eq!(1, status.future);
+        assert_eq!(pool.mem_usage(), status.mem_usage);
+    }
+
+    #[test]
+    fn remove_all_from_sender_cancels_both_current_and_future_parcels() {
+        let mut pool = MemPool::new();
+        let keypair = Random.generate().unwrap();
+        let parcels: Vec<_> = [0, 1, 3].iter().map(|nonce| payment(*nonce, 10).sign(keypair.private())).collect();
+        for parcel in parcels.clone() {
+            pool.add(parcel, ParcelOrigin::Local, 0, &default_account_details).unwrap();
+        }
+        assert_eq!(2, pool.status().pending);
+        assert_eq!(1, pool.status().future);
+        assert_eq!(Some(U256::from(1)), pool.last_nonce(&keypair.address()));
+
+        let hashes: Vec<H256> = parcels.iter().map(|parcel| parcel.hash()).collect();
+        assert_eq!(hashes, pool.remove_all_from_sender(&keypair.address()));
+        assert_eq!(0, pool.status().pending);
+        assert_eq!(0, pool.status().future);
+        assert_eq!(0, pool.encoded_size());
+        assert_eq!(0, pool.mem_usage());
+        assert_eq!(U256::zero(), pool.cumulative_cost(&keypair.address()));
+        assert_eq!(None, pool.last_nonce(&keypair.address()));
+        assert_eq!(Some(&3), pool.metrics(0).removed.get(&RemovalReason::Canceled));
+        for parcel in &parcels {
+            assert_eq!(Some(&LocalParcelStatus::Canceled(parcel.clone())), pool.local_parcels().get(&parcel.hash()));
+        }
+
+        // The sender can start again from the state nonce.
+        let again = payment(0, 20).sign(keypair.private());
+        assert_eq!(
+            Ok(ParcelImportResult::Current),
+            pool.add(again.clone(), ParcelOrigin::Local, 0, &default_account_details)
+        );
+        assert_eq!(vec![again], pool.top_parcels());
+    }
+
+    #[test]
+    fn remove_sender_removes_both_current_and_future_parcels() {
+        let mut pool = MemPool::new();
+        let keypair = Random.generate().unwrap();
+        let other = Random.generate().unwrap();
+        for &nonce in &[0, 1, 3] {
+            pool.add(payment(nonce, 10).sign(keypair.private()), ParcelOrigin::Local, 0, &default_account_details)
+                .unwrap();
+        }
+        let other_parcel = payment(0, 10).sign(other.private());
+        pool.add(other_parcel.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        assert_eq!(3, pool.status().pending);
+        assert_eq!(1, pool.status().future);
+        let generation = pool.generation();
+
+        let removed = pool.remove_sender(&keypair.address());
+        let removed_nonces: Vec<U256> = removed.iter().map(|parcel| parcel.nonce).collect();
+        assert_eq!(vec![U256::from(0), U256::from(1), U256::from(3)], removed_nonces);
+        assert_ne!(generation, pool.generation());
+        assert_eq!(vec![other_parcel], pool.top_parcels());
+        assert_eq!(Vec::<SignedParcel>::new(), pool.future_parcels());
+        assert_eq!(None, pool.last_nonce(&keypair.address()));
+        for parcel in &removed {
+            assert_eq!(Some(&LocalParcelStatus::Banned(parcel.clone())), pool.local_parcels().get(&parcel.hash()));
+        }
+        let metrics = pool.metrics(0);
+        assert_eq!(Some(&3), metrics.removed.get(&RemovalReason::Banned));
+        assert_eq!(None, metrics.removed.get(&RemovalReason::NotAllowed));
+
+        let generation = pool.generation();
+        assert_eq!(Vec::<SignedParcel>::new(), pool.remove_sender(&keypair.address()));
+        assert_eq!(generation, pool.generation());
+    }
+
+    #[test]
+    fn sender_issues_list_the_nonce_gaps() {
+        let keypair = Random.generate().unwrap();
+        let sender = keypair.address();
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::from(5),
+            balance: U256::max_value(),
+        };
+        let mut pool = MemPool::new();
+        pool.set_minimal_fee(U256::from(10));
+
+        let queued = [(5, 20), (6, 20), (8, 80), (9, 100), (12, 20)];
+        for &(nonce, fee) in &queued {
+            let signed = payment(nonce, fee).sign(keypair.private());
+            pool.add(signed, ParcelOrigin::External, 0, &fetch_account).unwrap();
+        }
+        assert_eq!(2, pool.status().pending);
+
+        assert_eq!(
+            SenderIssues {
+                state_nonce: U256::from(5),
+                ready: U256::from(5)..U256::from(7),
+                gapped: vec![U256::from(8), U256::from(9), U256::from(12)],
+                gaps: vec![U256::from(7), U256::from(10), U256::from(11)],
+                stuck: 3,
+                replacement_fee: Some(U256::from(90)),
+            },
+            pool.sender_issues(&sender, U256::from(5))
+        );
+
+        let filler = payment(7, 20).sign(keypair.private());
+        assert_eq!(Ok(ParcelImportResult::Current), pool.add(filler, ParcelOrigin::External, 0, &fetch_account));
+        assert_eq!(
+            SenderIssues {
+                state_nonce: U256::from(5),
+                ready: U256::from(5)..U256::from(10),
+                gapped: vec![U256::from(12)],
+                gaps: vec![U256::from(10), U256::from(11)],
+                stuck: 1,
+                replacement_fee: Some(U256::from(22)),
+            },
+            pool.sender_issues(&sender, U256::from(5))
+        );
+    }
+
+    #[test]
+    fn healthy_sender_has_no_issues() {
+        let keypair = Random.generate().unwrap();
+        let mut pool = MemPool::new();
+        for nonce in 0..3 {
+            let signed = payment(nonce, 10).sign(keypair.private());
+            assert_eq!(
+                Ok(ParcelImportResult::Current),
+                pool.add(signed, ParcelOrigin::External, 0, &default_account_details)
+            );
+        }
+
+        assert_eq!(
+            SenderIssues {
+                ready: U256::zero()..U256::from(3),
+                ..Default::default()
+            },
+            pool.sender_issues(&keypair.address(), U256::zero())
+        );
+        assert_eq!(SenderIssues::default(), pool.sender_issues(&Address::default(), U256::zero()));
+    }
+
+    #[test]
+    fn sender_issues_show_the_ready_range_and_the_gapped_nonces() {
+        let keypair = Random.generate().unwrap();
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::from(5),
+            balance: U256::max_value(),
+        };
+        let mut pool = MemPool::new();
+        for &nonce in &[5, 6, 9] {
+            let signed = payment(nonce, 10).sign(keypair.private());
+            pool.add(signed, ParcelOrigin::External, 0, &fetch_account).unwrap();
+        }
+
+        let issues = pool.sender_issues(&keypair.address(), U256::from(5));
+        assert_eq!(U256::from(5), issues.state_nonce);
+        assert_eq!(U256::from(5)..U256::from(7), issues.ready);
+        assert_eq!(vec![U256::from(7), U256::from(8)], issues.gaps);
+        assert_eq!(vec![U256::from(9)], issues.gapped);
+        assert_eq!(1, issues.stuck);
+
+        // The mem pool is not culled yet after the chain accepted the nonce 5.
+        let issues = pool.sender_issues(&keypair.address(), U256::from(6));
+        assert_eq!(U256::from(6)..U256::from(7), issues.ready);
+        assert_eq!(vec![U256::from(7), U256::from(8)], issues.gaps);
+    }
+
+    #[test]
+    fn canceling_a_parcel_demotes_the_later_parcels_of_the_sender() {
+        let keypair = Random.generate().unwrap();
+        let fetch_nonce = |_: &Address| U256::zero();
+        let mut pool = MemPool::new();
+        let parcels = (0..4).map(|nonce| payment(nonce, 10).sign(keypair.private())).collect::<Vec<_>>();
+        for parcel in &parcels {
+            pool.add(parcel.clone(), ParcelOrigin::Local, 0, &default_account_details).unwrap();
+        }
+        assert_eq!(4, pool.status().pending);
+
+        assert_eq!(Some(parcels[1].clone()), pool.cancel(&parcels[1].hash(), &fetch_nonce, SubsequentParcels::Demote));
+        assert_eq!(1, pool.status().pending);
+        assert_eq!(2, pool.status().future);
+        assert_eq!(parcels[2..].to_vec(), pool.future_parcels());
+        assert_eq!(Some(&LocalParcelStatus::Future), pool.local_parcels().get(&parcels[2].hash()));
+        assert_eq!(
+            Some(&LocalParcelStatus::Canceled(parcels[1].clone())),
+            pool.local_parcels().get(&parcels[1].hash())
+        );
+
+        // The later parcels are promoted again once the nonce is filled
+        pool.add(parcels[1].clone(), ParcelOrigin::Local, 0, &default_account_details).unwrap();
+        assert_eq!(4, pool.status().pending);
+    }
+
+    #[test]
+    fn last_nonce_ignores_the_gapped_parcels() {
+        let fetch_account = |_: &Address| AccountDetails {
+            nonce: U256::from(5),
+            balance: U256::max_value(),
+        };
+        let fetch_nonce = |_: &Address| U256::from(5);
+        let mut pool = MemPool::new();
+        let keypair = Random.generate().unwrap();
+        let sender = keypair.address();
+        assert_eq!(None, pool.last_nonce(&sender));
+
+        let parcels: Vec<_> = [5, 6, 9].iter().map(|nonce| payment(*nonce, 10).sign(keypair.private())).collect();
+        for parcel in parcels.clone() {
+            pool.add(parcel, ParcelOrigin::External, 0, &fetch_account).unwrap();
+        }
+        assert_eq!(Some(U256::from(6)), pool.last_nonce(&sender));
+
+        let replacing = payment(6, 20).sign(keypair.private());
+        let result = pool.add(replacing.clone(), ParcelOrigin::External, 0, &fetch_account);
+        assert_eq!(Ok(ParcelImportResult::Current), result);
+        assert_eq!(Some(U256::from(6)), pool.last_nonce(&sender));
+
+        pool.remove(&replacing.hash(), &fetch_nonce, RemovalReason::Invalid);
+        assert_eq!(Some(U256::from(5)), pool.last_nonce(&sender));
+        pool.remove(&parcels[0].hash(), &fetch_nonce, RemovalReason::Invalid);
+        assert_eq!(None, pool.last_nonce(&sender));
+    }
+
+    #[test]
+    fn parcels_by_sender_are_listed_in_the_order_of_nonces() {
+        let mut pool = MemPool::new();
+        let sender = Random.generate().unwrap();
+        let other = Random.generate().unwrap();
+        let parcels: Vec<_> = [1, 3, 0].iter().map(|nonce| payment(*nonce, 10).sign(sender.private())).collect();
+        for (nonce, parcel) in parcels.iter().enumerate() {
+            pool.add(parcel.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+            let others = payment(nonce as u64, 20).sign(other.private());
+            pool.add(others, ParcelOrigin::External, 0, &default_account_details).unwrap();
+        }
+
+        assert_eq!(
+            vec![
+                PooledParcel {
+                    parcel: parcels[2].clone(),
+                    is_future: false,
+                },
+                PooledParcel {
+                    parcel: parcels[0].clone(),
+                    is_future: false,
+                },
+                PooledParcel {
+                    parcel: parcels[1].clone(),
+                    is_future: true,
+                },
+            ],
+            pool.parcels_by_sender(&sender.address())
+        );
+        assert_eq!(3, pool.parcels_by_sender(&other.address()).len());
+        assert_eq!(Vec::<PooledParcel>::new(), pool.parcels_by_sender(&Address::random()));
+    }
+
+    #[test]
+    fn parcels_are_found_in_either_part_of_the_pool() {
+        let keypair = Random.generate().unwrap();
+        let mut pool = MemPool::new();
+        let current = payment(0, 10).sign(keypair.private());
+        let future = payment(2, 10).sign(keypair.private());
+        let unknown = payment(1, 10).sign(keypair.private());
+        pool.add(current.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        pool.add(future.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+
+        assert_eq!(Some(current.clone()), pool.find(&current.hash()));
+        assert!(!pool.is_in_future(&current.hash()));
+        assert_eq!(Some(future.clone()), pool.find(&future.hash()));
+        assert!(pool.is_in_future(&future.hash()));
+        assert_eq!(None, pool.find(&unknown.hash()));
+        assert!(!pool.is_in_future(&unknown.hash()));
+    }
+
+    #[test]
+    fn canceling_a_parcel_can_drop_the_later_parcels_of_the_sender() {
+        let keypair = Random.generate().unwrap();
+        let other = Random.generate().unwrap();
+        let fetch_nonce = |_: &Address| U256::zero();
+        let mut pool = MemPool::new();
+        let parcels = (0..4).map(|nonce| payment(nonce, 10).sign(keypair.private())).collect::<Vec<_>>();
+        for parcel in &parcels {
+            pool.add(parcel.clone(), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        }
+        pool.add(payment(0, 10).sign(other.private()), ParcelOrigin::External, 0, &default_account_details).unwrap();
+
+        assert_eq!(Some(parcels[1].clone()), pool.cancel(&parcels[1].hash(), &fetch_nonce, SubsequentParcels::Drop));
+        assert_eq!(2, pool.status().pending);
+        assert_eq!(0, pool.status().future);
+        assert_eq!(None, pool.find(&parcels[2].hash()));
+        assert_eq!(None, pool.find(&parcels[3].hash()));
+    }
+
+    #[test]
+    fn canceling_an_unknown_parcel_changes_nothing() {
+        let keypair = Random.generate().unwrap();
+        let mut pool = MemPool::new();
+        pool.add(payment(0, 10).sign(keypair.private()), ParcelOrigin::External, 0, &default_account_details).unwrap();
+        let generation = pool.generation();
+
+        assert_eq!(None, pool.cancel(&H256::random(), &|_: &Address| U256::zero(), SubsequentParcels::Drop));
+        assert_eq!(1, pool.status().pending);
+        assert_eq!(generation, pool.generation());
+    }
+
+    #[test]
+    fn top_local_parcels_with_limit_skips_the_external_parcels() {
+        let mut pool = MemPool::new();
+        let mut local = Vec::new();
+        for fee in 1..11 {
+            let signed = payment(0, fee).sign(Random.generate().unwrap().private());
+            let origin = if fee % 3 == 0 {
+                local.push(signed.hash());
+                ParcelOrigin::Local
+            } else {
+                ParcelOrigin::External
+            };
+            assert_eq!(Ok(ParcelImportResult::Current), pool.add(signed, origin, 0, &default_account_details));
+        }
+        let top_local: Vec<_> =
+            pool.top_parcels().into_iter().filter(|parcel| local.contains(&parcel.hash())).collect();
+        assert_eq!(3, top_local.len());
+
+        assert_eq!(top_local, pool.top_local_parcels_with_limit(usize::max_value(), usize::max_value()));
+        assert_eq!(top_local[..2].to_vec(), pool.top_local_parcels_with_limit(2, usize::max_value()));
+    }
 }
@@ -14,26 +14,43 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use crossbeam;
 use ctypes::{Address, Bytes, H256, U256};
+use kvdb::KeyValueDB;
 use parking_lot::{Mutex, RwLock};
+use rlp::Encodable;
+use unexpected::OutOfBounds;
 
 use super::super::account_provider::{AccountProvider, SignError};
 use super::super::block::{Block, ClosedBlock, IsBlock};
 use super::super::client::{AccountData, BlockChain, BlockProducer, ImportSealedBlock, MiningBlockChainClient};
 use super::super::consensus::{CodeChainEngine, Seal};
-use super::super::error::Error;
+use super::super::error::{BlockError, Error};
 use super::super::header::Header;
-use super::super::parcel::{ParcelError, SignedParcel, UnverifiedParcel};
-use super::super::spec::Spec;
+use super::super::parcel::{ActionKind, ParcelError, SignedParcel, UnverifiedParcel};
+use super::super::spec::{CommonParams, Spec};
 use super::super::state::TopLevelState;
 use super::super::types::{BlockId, BlockNumber, ParcelId};
-use super::mem_pool::{AccountDetails, MemPool, ParcelOrigin, RemovalReason};
+use super::changes::Changes;
+use super::journal::{LocalParcelsJournal, MemPoolJournal};
+use super::mem_pool::{
+    AccountDetails, MemPool, ParcelOrigin, PendingPosition, RemovalReason, DEFAULT_FEE_BUMP_PERMILLE,
+    DEFAULT_LOCAL_MAX_NONCE_GAP, DEFAULT_LOCAL_POOLING_PERIOD, DEFAULT_MAX_NONCE_GAP, DEFAULT_POOLING_PERIOD,
+};
+use super::reseal::ResealSchedule;
 use super::sealing_queue::SealingQueue;
-use super::{MinerService, MinerStatus, ParcelImportResult};
+use super::{
+    LocalParcelStatus, MemPoolDump, MemPoolMetrics, MinerService, MinerStatus, OrderingStrategy, ParcelImportOutcome,
+    ParcelImportResult, ParcelPage, PooledParcel, SealedBlockInfo, SenderIssues, SubsequentParcels,
+};
 
 /// Configures the behaviour of the miner.
 #[derive(Debug, PartialEq)]
@@ -42,14 +59,38 @@ pub struct MinerOptions {
     pub reseal_on_external_parcel: bool,
     /// Reseal on receipt of new local parcels.
     pub reseal_on_own_parcel: bool,
-    /// Minimum period between parcel-inspired reseals.
+    /// Minimum period between reseals. The reseals requested within it are postponed.
     pub reseal_min_period: Duration,
+    /// Maximum period the postponed reseals wait for, even if the min period hasn't passed.
+    pub reseal_max_period: Duration,
+    /// Seal a block on every new chain head even if it has no parcels, on the engines sealing internally.
+    pub force_sealing: bool,
     /// Maximum size of the mem pool.
     pub mem_pool_size: usize,
-    /// Maximum memory usage of parcels in the queue (current / future).
+    /// Maximum number of the parcels waiting for their nonces in the mem pool.
+    pub mem_pool_future_size: usize,
+    /// Maximum size of the RLP encoded parcels in the queue (current and future together).
     pub mem_pool_memory_limit: Option<usize>,
+    /// The number of blocks an external parcel can stay in the queue.
+    pub mem_pool_lifetime: BlockNumber,
+    /// The number of blocks a local parcel can stay in the queue.
+    pub mem_pool_local_lifetime: BlockNumber,
+    /// How far the nonce of an external parcel can be ahead of the state nonce of its sender.
+    pub mem_pool_max_nonce_gap: u64,
+    /// How far the nonce of a local parcel can be ahead of the state nonce of its sender.
+    pub mem_pool_local_max_nonce_gap: u64,
+    /// A parcel replacing another one with the same sender and nonce must pay `permille / 1000` more fee.
+    pub mem_pool_fee_bump_permille: u64,
+    /// How the parcels in the queue are prioritized.
+    pub mem_pool_ordering: OrderingStrategy,
     /// How many historical work packages can we store before running out?
     pub work_queue_size: usize,
+    /// Maximum number of threads verifying the signatures of a batch of parcels.
+    pub parcel_verification_threads: usize,
+    /// The file keeping the local parcels across restarts. They are not kept if it's `None`, which is the default.
+    pub local_parcels_journal: Option<PathBuf>,
+    /// Whether all the parcels of the mem pool are kept in the database across restarts.
+    pub mem_pool_journal: bool,
 }
 
 impl Default for MinerOptions {
@@ -58,9 +99,21 @@ impl Default for MinerOptions {
             reseal_on_external_parcel: false,
             reseal_on_own_parcel: true,
             reseal_min_period: Duration::from_secs(2),
+            reseal_max_period: Duration::from_secs(120),
+            force_sealing: false,
             mem_pool_size: 8192,
-            mem_pool_memory_limit: Some(2 * 1024 * 1024),
+            mem_pool_future_size: 8192,
+            mem_pool_memory_limit: Some(4 * 1024 * 1024),
+            mem_pool_lifetime: DEFAULT_POOLING_PERIOD,
+            mem_pool_local_lifetime: DEFAULT_LOCAL_POOLING_PERIOD,
+            mem_pool_max_nonce_gap: DEFAULT_MAX_NONCE_GAP,
+            mem_pool_local_max_nonce_gap: DEFAULT_LOCAL_MAX_NONCE_GAP,
+            mem_pool_fee_bump_permille: DEFAULT_FEE_BUMP_PERMILLE,
+            mem_pool_ordering: OrderingStrategy::Fee,
             work_queue_size: 20,
+            parcel_verification_threads: 4,
+            local_parcels_journal: None,
+            mem_pool_journal: false,
         }
     }
 }
@@ -74,18 +127,54 @@ pub trait NotifyWork: Send + Sync {
 struct SealingWork {
     queue: SealingQueue,
     enabled: bool,
+    /// The mem pool generation the last work was prepared from.
+    prepared_generation: Option<u64>,
+    /// The hash of the work last reported by `prepare_work_sealing`.
+    reported_work_hash: Option<H256>,
+    /// The number of parcels in the last prepared block. Reset to 0 when the chain head moves.
+    pending_block_parcels: usize,
+    /// The last block made by `prepare_block`.
+    prepared_block: Option<PreparedBlock>,
+}
+
+/// A block reused by `prepare_block` while the mem pool and the chain head it was built from don't change.
+struct PreparedBlock {
+    block: ClosedBlock,
+    generation: u64,
+    parent_hash: H256,
 }
 
 pub struct Miner {
     mem_pool: Arc<RwLock<MemPool>>,
+    /// The changes of the mem pool and the chain head, shared with the mem pool.
+    changes: Arc<Changes>,
     parcel_listener: RwLock<Vec<Box<Fn(&[H256]) + Send + Sync>>>,
-    next_allowed_reseal: Mutex<Instant>,
+    reseal_schedule: Mutex<ResealSchedule>,
     author: RwLock<Address>,
+    /// The parcels sent by these addresses are imported as local ones, even if they are relayed by peers.
+    local_addresses: RwLock<HashSet<Address>>,
+    /// The parcels sent by these addresses are rejected.
+    banned_addresses: RwLock<HashSet<Address>>,
     extra_data: RwLock<Bytes>,
+    /// The number of parcels in a block is limited to this, if it's lower than the limit of the spec.
+    block_parcels_limit: RwLock<Option<usize>>,
     sealing_block_last_request: Mutex<u64>,
     sealing_work: Mutex<SealingWork>,
+    /// Held while `prepare_work_sealing` prepares the work, so that concurrent callers don't duplicate it.
+    work_preparation: Mutex<()>,
+    /// Set while a block is sealed and imported, since importing the block triggers sealing again.
+    sealing_in_progress: AtomicBool,
+    /// Set when a sealing is requested, so that the one in progress runs again for it.
+    reseal_requested: AtomicBool,
+    /// Whether the blocks without parcels are sealed.
+    force_sealing: AtomicBool,
+    last_sealed_block: RwLock<Option<SealedBlockInfo>>,
     engine: Arc<CodeChainEngine>,
     options: MinerOptions,
+    /// Locked before the mem pool when both are needed, so that the journals are written in the order of the changes.
+    local_parcels_journal: Option<Mutex<LocalParcelsJournal>>,
+    /// Opened by `open_mem_pool_journal`. Locked before the mem pool, like `local_parcels_journal`.
+    mem_pool_journal: Mutex<Option<MemPoolJournal>>,
 
     accounts: Option<Arc<AccountProvider>>,
     notifiers: RwLock<Vec<Box<NotifyWork>>>,
@@ -102,23 +191,49 @@ impl Miner {
 
     fn new_raw(options: MinerOptions, spec: &Spec, accounts: Option<Arc<AccountProvider>>) -> Self {
         let mem_limit = options.mem_pool_memory_limit.unwrap_or_else(usize::max_value);
-        let mem_pool = Arc::new(RwLock::new(MemPool::with_limits(options.mem_pool_size, mem_limit)));
+        let mut mem_pool = MemPool::with_limits(options.mem_pool_size, mem_limit);
+        mem_pool.set_future_limit(options.mem_pool_future_size);
+        mem_pool.set_fee_bump_permille(options.mem_pool_fee_bump_permille);
+        mem_pool.set_ordering(options.mem_pool_ordering);
+        mem_pool.set_max_time_in_pool(options.mem_pool_lifetime);
+        mem_pool.set_max_time_in_pool_for_local(options.mem_pool_local_lifetime);
+        mem_pool.set_max_nonce_gap(options.mem_pool_max_nonce_gap);
+        mem_pool.set_max_nonce_gap_for_local(options.mem_pool_local_max_nonce_gap);
+        let changes = mem_pool.changes();
+        let mem_pool = Arc::new(RwLock::new(mem_pool));
         // FIXME: Get the list of notifiers from options.
         let notifiers: Vec<Box<NotifyWork>> = Vec::new();
+        let local_parcels_journal =
+            options.local_parcels_journal.clone().map(|path| Mutex::new(LocalParcelsJournal::new(path)));
 
         Self {
             mem_pool,
+            changes,
             parcel_listener: RwLock::new(vec![]),
-            next_allowed_reseal: Mutex::new(Instant::now()),
+            reseal_schedule: Mutex::new(ResealSchedule::new(options.reseal_min_period, options.reseal_max_period)),
             author: RwLock::new(Address::default()),
+            local_addresses: RwLock::new(HashSet::new()),
+            banned_addresses: RwLock::new(HashSet::new()),
             extra_data: RwLock::new(Vec::new()),
+            block_parcels_limit: RwLock::new(None),
             sealing_block_last_request: Mutex::new(0),
             sealing_work: Mutex::new(SealingWork {
                 queue: SealingQueue::new(options.work_queue_size),
                 enabled: spec.engine.seals_internally().is_some(),
+                prepared_generation: None,
+                reported_work_hash: None,
+                pending_block_parcels: 0,
+                prepared_block: None,
             }),
+            work_preparation: Mutex::new(()),
+            sealing_in_progress: AtomicBool::new(false),
+            reseal_requested: AtomicBool::new(false),
+            force_sealing: AtomicBool::new(options.force_sealing),
+            last_sealed_block: RwLock::new(None),
             engine: spec.engine.clone(),
             options,
+            local_parcels_journal,
+            mem_pool_journal: Mutex::new(None),
             accounts,
             notifiers: RwLock::new(notifiers),
         }
@@ -129,9 +244,65 @@ impl Miner {
         self.parcel_listener.write().push(f);
     }
 
-    /// Get `Some` `clone()` of the current pending block's state or `None` if we're not sealing.
-    pub fn pending_state(&self, latest_block_number: BlockNumber) -> Option<TopLevelState<::state_db::StateDB>> {
-        self.map_pending_block(|b| b.state().clone(), latest_block_number)
+    /// Makes the engine sign with the account of `address` in `ap`.
+    /// The key stays encrypted in `ap`, and the engine decrypts it with `password` whenever it signs,
+    /// so the password is checked here before it is handed to the engine.
+    pub fn set_engine_signer_from_account(
+        &self,
+        ap: Arc<AccountProvider>,
+        address: Address,
+        password: String,
+    ) -> Result<(), SignError> {
+        if self.engine.seals_internally().is_none() {
+            cwarn!(MINER, "Cannot set engine signer on a PoW chain.");
+            return Err(SignError::InappropriateChain)
+        }
+        if !ap.has_account(address)? {
+            cwarn!(MINER, "No account for the engine signer {:?}", address);
+            return Err(SignError::NotFound)
+        }
+        if !ap.test_password(address, &password)? {
+            cwarn!(MINER, "Invalid password for the engine signer {:?}", address);
+            return Err(SignError::InvalidPassword)
+        }
+        ctrace!(MINER, "Set engine signer to {:?}", address);
+        self.engine.set_signer(ap, address, password);
+        self.add_local_address(address);
+        Ok(())
+    }
+
+    /// Seals the blocks without parcels too if `force_sealing` is true.
+    /// It applies only to the engines sealing internally.
+    pub fn set_force_sealing(&self, force_sealing: bool) {
+        self.force_sealing.store(force_sealing, Ordering::SeqCst);
+    }
+
+    /// Imports the parcels sent by `address` as local ones from now on.
+    /// The author and the engine signer are added when they are set.
+    pub fn add_local_address(&self, address: Address) {
+        self.local_addresses.write().insert(address);
+    }
+
+    /// Rejects the parcels sent by `address` from now on, and removes its parcels from the mem pool.
+    pub fn ban_address(&self, address: Address) {
+        self.banned_addresses.write().insert(address);
+        let removed = self.mem_pool.write().remove_sender(&address);
+        if !removed.is_empty() {
+            cinfo!(MINER, "Removed {} parcels of the banned address {:?}", removed.len(), address);
+            self.journal_parcels();
+        }
+    }
+
+    /// Accepts the parcels sent by `address` again. Returns false if it wasn't banned.
+    pub fn unban_address(&self, address: &Address) -> bool {
+        self.banned_addresses.write().remove(address)
+    }
+
+    /// Returns the banned addresses in ascending order.
+    pub fn banned_addresses(&self) -> Vec<Address> {
+        let mut addresses: Vec<Address> = self.banned_addresses.read().iter().cloned().collect();
+        addresses.sort();
+        addresses
     }
 
     /// Get `Some` `clone()` of the current pending block or `None` if we're not sealing.
@@ -144,8 +315,111 @@ impl Miner {
         self.map_pending_block(|b| b.header().clone(), latest_block_number)
     }
 
+    /// Imports the local parcels kept in the journal again, which were in the mem pool when the node stopped.
+    /// The parcels which are not valid anymore, e.g. the ones mined already, are discarded from the journal.
+    /// Returns the number of the recovered parcels.
+    pub fn recover_local_parcels<C: AccountData + BlockChain>(&self, client: &C) -> usize {
+        let (hashes, (results, inserted)) = {
+            let mut journal = match self.local_parcels_journal {
+                Some(ref journal) => journal.lock(),
+                None => return 0,
+            };
+            let parcels = journal.load();
+            let hashes: Vec<H256> = parcels.iter().map(|parcel| parcel.hash()).collect();
+            let verified = self.verify_parcels(client, parcels, ParcelOrigin::Local);
+            let (imported, local_parcels) = {
+                let mut mem_pool = self.mem_pool.write();
+                let imported = self.add_verified_parcels_to_pool(client, verified, &mut mem_pool);
+                // The journal is saved even if no parcel is recovered, so that the discarded ones are removed.
+                let changed = mem_pool.take_changed_local_parcels();
+                (imported, changed.unwrap_or_else(|| mem_pool.local_parcels_in_pool()))
+            };
+            journal.save(&local_parcels);
+            (hashes, imported)
+        };
+        self.journal_parcels();
+        self.notify_parcel_listeners(&inserted);
+
+        let mut recovered = 0;
+        for (hash, result) in hashes.into_iter().zip(results) {
+            match result {
+                Ok(_) => recovered += 1,
+                Err(e) => cdebug!(OWN_PARCEL, "Discarded the local parcel {:?}: {:?}", hash, e),
+            }
+        }
+        cinfo!(OWN_PARCEL, "Recovered {} local parcels", recovered);
+        recovered
+    }
+
+    /// Imports the parcels kept in the mem pool journal again, which were in the mem pool when the node stopped.
+    /// They are verified as the external parcels, so the ones which are not valid anymore are discarded.
+    /// It should be called after `recover_local_parcels`, so that the local parcels are imported as local ones.
+    /// Returns the number of the recovered parcels.
+    pub fn recover_mem_pool<C: AccountData + BlockChain>(&self, client: &C) -> usize {
+        let (results, inserted) = {
+            let journal = self.mem_pool_journal.lock();
+            let journal = match *journal {
+                Some(ref journal) => journal,
+                None => return 0,
+            };
+            let parcels = journal.load();
+            let hashes: Vec<H256> = parcels.iter().map(|parcel| parcel.hash()).collect();
+            let verified = self.verify_parcels(client, parcels, ParcelOrigin::External);
+            let (imported, discarded) = {
+                let mut mem_pool = self.mem_pool.write();
+                let imported = self.add_verified_parcels_to_pool(client, verified, &mut mem_pool);
+                let discarded: Vec<H256> = hashes.into_iter().filter(|hash| mem_pool.find(hash).is_none()).collect();
+                (imported, discarded)
+            };
+            journal.delete(&discarded);
+            imported
+        };
+        self.journal_parcels();
+        self.notify_parcel_listeners(&inserted);
+
+        let recovered = results.iter().filter(|result| result.is_ok()).count();
+        cinfo!(MINER, "Recovered {} parcels of the mem pool, discarded {}", recovered, results.len() - recovered);
+        recovered
+    }
+
+    /// Keeps all the parcels of the mem pool in `db` from now on, if `MinerOptions::mem_pool_journal` is set.
+    /// The client calls it with its database.
+    pub fn open_mem_pool_journal(&self, db: Arc<KeyValueDB>) {
+        if !self.options.mem_pool_journal {
+            return
+        }
+        let mut journal = self.mem_pool_journal.lock();
+        self.mem_pool.write().record_journal_changes();
+        *journal = Some(MemPoolJournal::new(db));
+    }
+
+    /// Writes the changes of the mem pool to the journals.
+    /// It must be called after the mem pool is unlocked, since it locks the journals first.
+    /// The mem pool is locked only to take the changes, and the journals are written after unlocking it.
+    fn journal_parcels(&self) {
+        if let Some(ref journal) = self.local_parcels_journal {
+            let mut journal = journal.lock();
+            let changed = self.mem_pool.write().take_changed_local_parcels();
+            if let Some(parcels) = changed {
+                journal.save(&parcels);
+            }
+        }
+        let journal = self.mem_pool_journal.lock();
+        if let Some(ref journal) = *journal {
+            let changes = self.mem_pool.write().take_journal_changes().expect("Recorded since the journal is opened");
+            if !changes.is_empty() {
+                journal.write(changes);
+            }
+        }
+    }
+
+    /// Get the heap memory used by the parcels in the memory pool.
+    pub fn mem_pool_mem_usage(&self) -> usize {
+        self.mem_pool.read().mem_usage()
+    }
+
     /// Check is reseal is allowed and necessary.
-    fn requires_reseal(&self, best_block: BlockNumber) -> bool {
+    fn requires_reseal(&self, best_block: BlockNumber, now: Instant) -> bool {
         let has_local_parcels = self.mem_pool.read().has_local_pending_parcels();
         let mut sealing_work = self.sealing_work.lock();
         if sealing_work.enabled {
@@ -167,10 +441,11 @@ impl Miner {
                 ctrace!(MINER, "Miner sleeping");
                 sealing_work.enabled = false;
                 sealing_work.queue.reset();
+                sealing_work.prepared_block = None;
                 false
             } else {
                 // sealing enabled and we don't want to sleep.
-                *self.next_allowed_reseal.lock() = Instant::now() + self.options.reseal_min_period;
+                self.reseal_schedule.lock().note_reseal(now);
                 true
             }
         } else {
@@ -179,111 +454,184 @@ impl Miner {
         }
     }
 
-    fn add_parcels_to_pool<C: AccountData + BlockChain>(
+    /// Verifies the parcels and decides their origins, without locking the mem pool.
+    fn verify_parcels<C: AccountData + BlockChain>(
         &self,
         client: &C,
         parcels: Vec<UnverifiedParcel>,
         default_origin: ParcelOrigin,
-        mem_pool: &mut MemPool,
-    ) -> Vec<Result<ParcelImportResult, Error>> {
+    ) -> Vec<Result<(SignedParcel, ParcelOrigin), Error>> {
         let best_block_header = client.best_block_header().decode();
-        let insertion_time = client.chain_info().best_block_number;
-        let mut inserted = Vec::with_capacity(parcels.len());
-
-        let results = parcels
+        // The fee is checked against the parameters of the block the parcels are going to be included in.
+        let mut pending_header = best_block_header.clone();
+        pending_header.set_number(best_block_header.number() + 1);
+        let mut seen = HashSet::with_capacity(parcels.len());
+        // Only the parcels passing the cheap checks are sent to the verifiers.
+        let mut unverified = Vec::with_capacity(parcels.len());
+
+        let checked: Vec<_> = parcels
             .into_iter()
             .map(|parcel| {
                 let hash = parcel.hash();
+                if !seen.insert(hash) {
+                    cdebug!(MINER, "Rejected parcel {:?}: duplicated in the batch", hash);
+                    return Err(Error::Parcel(ParcelError::AlreadyImported))
+                }
                 if client.parcel_block(ParcelId::Hash(hash)).is_some() {
                     cdebug!(MINER, "Rejected parcel {:?}: already in the blockchain", hash);
                     return Err(Error::Parcel(ParcelError::AlreadyImported))
                 }
-                match self.engine
-                    .verify_parcel_basic(&parcel, &best_block_header)
-                    .and_then(|_| self.engine.verify_parcel_unordered(parcel, &best_block_header))
-                {
+                if let Err(e) = self.engine.verify_parcel_basic(&parcel, &pending_header) {
+                    cdebug!(MINER, "Rejected parcel {:?}: {:?}", hash, e);
+                    return Err(e)
+                }
+                unverified.push(parcel);
+                Ok(hash)
+            })
+            .collect();
+
+        let threads = self.options.parcel_verification_threads;
+        let mut verified = verify_parcels_unordered(&*self.engine, unverified, &best_block_header, threads).into_iter();
+
+        checked
+            .into_iter()
+            .map(|checked| {
+                let hash = checked?;
+                match verified.next().expect("Every parcel passing the checks is verified") {
                     Err(e) => {
                         cdebug!(MINER, "Rejected parcel {:?} with invalid signature: {:?}", hash, e);
                         Err(e)
                     }
                     Ok(parcel) => {
+                        if self.banned_addresses.read().contains(&parcel.sender()) {
+                            cdebug!(MINER, "Rejected parcel {:?}: the sender is banned", hash);
+                            return Err(ParcelError::SenderBanned.into())
+                        }
                         // This check goes here because verify_parcel takes SignedParcel parameter
                         self.engine.machine().verify_parcel(&parcel, &best_block_header, client)?;
 
-                        let origin = self.accounts
-                            .as_ref()
-                            .and_then(|accounts| match accounts.has_account(parcel.sender()) {
-                                Ok(true) => Some(ParcelOrigin::Local),
-                                Ok(false) => None,
-                                Err(_) => None,
-                            })
-                            .unwrap_or(default_origin);
-
-                        let fetch_account = |a: &Address| -> AccountDetails {
-                            AccountDetails {
-                                nonce: client.latest_nonce(a),
-                                balance: client.latest_balance(a),
-                            }
+                        let is_local_address = self.local_addresses.read().contains(&parcel.sender());
+                        let origin = if is_local_address {
+                            ParcelOrigin::Local
+                        } else {
+                            self.accounts
+                                .as_ref()
+                                .and_then(|accounts| match accounts.has_account(parcel.sender()) {
+                                    Ok(true) => Some(ParcelOrigin::Local),
+                                    Ok(false) => None,
+                                    Err(_) => None,
+                                })
+                                .unwrap_or(default_origin)
                         };
-                        let hash = parcel.hash();
-                        let result = mem_pool.add(parcel, origin, insertion_time, &fetch_account)?;
+                        Ok((parcel, origin))
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Adds the parcels verified by `verify_parcels` to the mem pool.
+    /// Returns the result of each parcel and the hashes of the inserted ones.
+    /// The listeners are not notified here, because the mem pool is locked by the caller.
+    fn add_verified_parcels_to_pool<C: AccountData + BlockChain>(
+        &self,
+        client: &C,
+        verified: Vec<Result<(SignedParcel, ParcelOrigin), Error>>,
+        mem_pool: &mut MemPool,
+    ) -> (Vec<Result<ParcelImportResult, Error>>, Vec<H256>) {
+        let (results, inserted) = self.add_verified_parcels_to_pool_with_positions(client, verified, mem_pool);
+        (results.into_iter().map(|(result, _)| result).collect(), inserted)
+    }
 
+    /// Adds the parcels like `add_verified_parcels_to_pool`, with the position of each pending one
+    /// among the pending parcels right after its insertion.
+    fn add_verified_parcels_to_pool_with_positions<C: AccountData + BlockChain>(
+        &self,
+        client: &C,
+        verified: Vec<Result<(SignedParcel, ParcelOrigin), Error>>,
+        mem_pool: &mut MemPool,
+    ) -> (Vec<(Result<ParcelImportResult, Error>, Option<PendingPosition>)>, Vec<H256>) {
+        let insertion_time = client.chain_info().best_block_number;
+        let accounts = AccountDetailsCache::new(|a: &Address| AccountDetails {
+            nonce: client.latest_nonce(a),
+            balance: client.latest_balance(a),
+        });
+        let fetch_account = |a: &Address| accounts.fetch(a);
+        let mut inserted = Vec::with_capacity(verified.len());
+
+        let results = verified
+            .into_iter()
+            .map(|verified| {
+                let (parcel, origin) = match verified {
+                    Ok(verified) => verified,
+                    Err(err) => {
+                        if let Error::Parcel(ref err) = err {
+                            mem_pool.count_rejection(err);
+                        }
+                        return (Err(err), None)
+                    }
+                };
+                let hash = parcel.hash();
+                match mem_pool.add(parcel, origin, insertion_time, &fetch_account) {
+                    Ok(result) => {
                         inserted.push(hash);
-                        Ok(result)
+                        let position = match result {
+                            ParcelImportResult::Current => mem_pool.pending_position(&hash),
+                            ParcelImportResult::Future => None,
+                        };
+                        (Ok(result), position)
                     }
+                    Err(err) => (Err(err.into()), None),
                 }
             })
             .collect();
 
-        for listener in &*self.parcel_listener.read() {
-            listener(&inserted);
-        }
+        (results, inserted)
+    }
+
+    /// Imports the external parcels, with the position of each pending one right after its insertion.
+    fn import_external_parcels_with_positions<C: MiningBlockChainClient>(
+        &self,
+        client: &C,
+        parcels: Vec<UnverifiedParcel>,
+    ) -> Vec<(Result<ParcelImportResult, Error>, Option<PendingPosition>)> {
+        ctrace!(EXTERNAL_PARCEL, "Importing external parcels");
+        // The parcels are verified before locking the mem pool, so that the readers are not blocked meanwhile.
+        let verified = self.verify_parcels(client, parcels, ParcelOrigin::External);
+        let (results, inserted) = {
+            let mut mem_pool = self.mem_pool.write();
+            // The parcels of our accounts are imported as local ones.
+            self.add_verified_parcels_to_pool_with_positions(client, verified, &mut mem_pool)
+        };
+        self.journal_parcels();
+        self.notify_parcel_listeners(&inserted);
 
+        if !inserted.is_empty() && self.options.reseal_on_external_parcel && self.parcel_reseal_allowed() {
+            // ------------------------------------------------------------------
+            // | NOTE Code below requires mem_pool and sealing_queue locks.     |
+            // | Make sure to release the locks before calling that method.     |
+            // ------------------------------------------------------------------
+            self.update_sealing(client);
+        }
         results
     }
 
-    /// Returns true if we had to prepare new pending block.
-    fn prepare_work_sealing<C: AccountData + BlockChain + BlockProducer>(&self, client: &C) -> bool {
-        ctrace!(MINER, "prepare_work_sealing: entering");
-        let prepare_new = {
-            let mut sealing_work = self.sealing_work.lock();
-            let have_work = sealing_work.queue.peek_last_ref().is_some();
-            ctrace!(MINER, "prepare_work_sealing: have_work={}", have_work);
-            if !have_work {
-                sealing_work.enabled = true;
-                true
-            } else {
-                false
-            }
-        };
-        if prepare_new {
-            // --------------------------------------------------------------------------
-            // | NOTE Code below requires transaction_queue and sealing_work locks.     |
-            // | Make sure to release the locks before calling that method.             |
-            // --------------------------------------------------------------------------
-            let (block, original_work_hash) = self.prepare_block(client);
-            self.prepare_work(block, original_work_hash);
+    /// Notifies the listeners of the parcels inserted to the mem pool.
+    /// It must be called without the mem pool lock, since the listeners may call back into the miner.
+    fn notify_parcel_listeners(&self, inserted: &[H256]) {
+        if inserted.is_empty() {
+            return
         }
-        let mut sealing_block_last_request = self.sealing_block_last_request.lock();
-        let best_number = client.chain_info().best_block_number;
-        if *sealing_block_last_request != best_number {
-            ctrace!(
-                MINER,
-                "prepare_work_sealing: Miner received request (was {}, now {}) - waking up.",
-                *sealing_block_last_request,
-                best_number
-            );
-            *sealing_block_last_request = best_number;
+        for listener in &*self.parcel_listener.read() {
+            listener(inserted);
         }
-
-        // Return if we restarted
-        prepare_new
     }
 
     /// Prepares work which has to be done to seal.
-    fn prepare_work(&self, block: ClosedBlock, original_work_hash: Option<H256>) {
+    fn prepare_work(&self, block: ClosedBlock, original_work_hash: Option<H256>, generation: u64) {
         let (work, is_new) = {
             let mut sealing_work = self.sealing_work.lock();
+            sealing_work.prepared_generation = Some(generation);
             let last_work_hash = sealing_work.queue.peek_last_ref().map(|pb| pb.block().header().hash());
             ctrace!(
                 MINER,
@@ -302,6 +650,7 @@ impl Miner {
                 let number = block.block().header().number();
                 let score = *block.block().header().score();
                 let is_new = original_work_hash.map_or(true, |h| block.block().header().hash() != h);
+                sealing_work.pending_block_parcels = block.parcels().len();
                 sealing_work.queue.push(block);
                 // If push notifications are enabled we assume all work items are used.
                 if !self.notifiers.read().is_empty() && is_new {
@@ -328,16 +677,37 @@ impl Miner {
     }
 
     /// Prepares new block for sealing including top parcels from queue.
-    fn prepare_block<C: AccountData + BlockChain + BlockProducer>(&self, chain: &C) -> (ClosedBlock, Option<H256>) {
-        let (parcels, mut open_block, original_work_hash) = {
-            let parcels = self.mem_pool.read().top_parcels();
+    /// Returns the block, the hash of the previous work and the mem pool generation the block was prepared from.
+    fn prepare_block<C: AccountData + BlockChain + BlockProducer>(&self, chain: &C) -> (ClosedBlock, Option<H256>, u64) {
+        let chain_info = chain.chain_info();
+        let best_block_hash = chain_info.best_block_hash;
+        {
+            let generation = self.mem_pool.read().generation();
+            let sealing_work = self.sealing_work.lock();
+            if let Some(ref prepared) = sealing_work.prepared_block {
+                if prepared.generation == generation && prepared.parent_hash == best_block_hash {
+                    ctrace!(MINER, "prepare_block: Nothing changed - reusing the prepared block");
+                    let last_work_hash = sealing_work.queue.peek_last_ref().map(|pb| pb.block().header().hash());
+                    return (prepared.block.clone(), last_work_hash, generation)
+                }
+            }
+        }
+
+        let params = self.engine.params().at(chain_info.best_block_number + 1);
+        let mut fullness = BodyFullness::new(&params, *self.block_parcels_limit.read());
+        let (mut parcels, generation, mut open_block, original_work_hash) = {
+            let (parcels, generation) = {
+                let mem_pool = self.mem_pool.read();
+                let parcels = mem_pool.top_parcels_with_limit(fullness.remaining_parcels(), fullness.remaining_size());
+                (parcels, mem_pool.generation())
+            };
             let mut sealing_work = self.sealing_work.lock();
             let last_work_hash = sealing_work.queue.peek_last_ref().map(|pb| pb.block().header().hash());
 
             ctrace!(MINER, "prepare_block: No existing work - making new block");
             let open_block = chain.prepare_open_block(self.author(), self.extra_data());
 
-            (parcels, open_block, last_work_hash)
+            (parcels, generation, open_block, last_work_hash)
         };
 
         let mut invalid_parcels = HashSet::new();
@@ -345,41 +715,73 @@ impl Miner {
         let block_number = open_block.block().header().number();
 
         let mut parcel_count: usize = 0;
-        let parcel_total = parcels.len();
-        for parcel in parcels {
-            let hash = parcel.hash();
-            let start = Instant::now();
-            // Check whether parcel type is allowed for sender
-            let result = match self.engine.machine().verify_parcel(&parcel, open_block.header(), chain) {
-                Err(Error::Parcel(ParcelError::NotAllowed)) => Err(ParcelError::NotAllowed.into()),
-                _ => open_block.push_parcel(parcel, None),
-            };
-            let took = start.elapsed();
-
-            ctrace!(MINER, "Adding parcel {:?} took {:?}", hash, took);
-            match result {
-                // already have parcel - ignore
-                Err(Error::Parcel(ParcelError::AlreadyImported)) => {}
-                Err(Error::Parcel(ParcelError::NotAllowed)) => {
-                    non_allowed_parcels.insert(hash);
-                    cdebug!(MINER, "Skipping non-allowed parcel for sender {:?}", hash);
+        // The parcels collected from the pool, whether they are pushed or not
+        let mut taken = HashSet::new();
+        // Parcels of a sender are ordered by nonce, so the ones following a skipped parcel can't be pushed either.
+        let mut skipped_senders = HashSet::new();
+        loop {
+            for parcel in parcels {
+                if fullness.is_full() {
+                    break
+                }
+                let hash = parcel.hash();
+                taken.insert(hash);
+                let sender = parcel.sender();
+                if skipped_senders.contains(&sender) {
+                    continue
+                }
+                let parcel_size = parcel.rlp_bytes().len();
+                if !fullness.has_room_for(parcel_size) {
+                    ctrace!(MINER, "Skipping parcel {:?} of {} bytes: not enough room in the block", hash, parcel_size);
+                    skipped_senders.insert(sender);
+                    continue
                 }
-                Err(e) => {
-                    invalid_parcels.insert(hash);
-                    cdebug!(
-                        MINER,
-                        "Error adding parcel to block: number={}. parcel_hash={:?}, Error: {:?}",
-                        block_number,
-                        hash,
-                        e
-                    );
+                let start = Instant::now();
+                // Check whether parcel type is allowed for sender
+                let result = match self.engine.machine().verify_parcel(&parcel, open_block.header(), chain) {
+                    Err(Error::Parcel(ParcelError::NotAllowed)) => Err(ParcelError::NotAllowed.into()),
+                    _ => open_block.push_parcel(parcel, None),
+                };
+                let took = start.elapsed();
+
+                ctrace!(MINER, "Adding parcel {:?} took {:?}", hash, took);
+                match result {
+                    // already have parcel - ignore
+                    Err(Error::Parcel(ParcelError::AlreadyImported)) => {}
+                    Err(Error::Parcel(ParcelError::NotAllowed)) => {
+                        non_allowed_parcels.insert(hash);
+                        cdebug!(MINER, "Skipping non-allowed parcel for sender {:?}", hash);
+                    }
+                    Err(e) => {
+                        invalid_parcels.insert(hash);
+                        // The later parcels of the sender would fail by the nonce. They are kept in the pool.
+                        skipped_senders.insert(sender);
+                        cdebug!(
+                            MINER,
+                            "Error adding parcel to block: number={}. parcel_hash={:?}, Error: {:?}",
+                            block_number,
+                            hash,
+                            e
+                        );
+                    }
+                    _ => {
+                        parcel_count += 1;
+                        fullness.add(parcel_size);
+                    } // imported ok
                 }
-                _ => {
-                    parcel_count += 1;
-                } // imported ok
+            }
+            if fullness.is_full() {
+                ctrace!(MINER, "Block is full: {} parcels", parcel_count);
+                break
+            }
+            // The skipped parcels may have left room, so the block is filled with the parcels following them.
+            let mem_pool = self.mem_pool.read();
+            parcels = mem_pool.top_parcels_after(fullness.remaining_parcels(), fullness.remaining_size(), &taken);
+            if parcels.is_empty() {
+                break
             }
         }
-        ctrace!(MINER, "Pushed {}/{} parcels", parcel_count, parcel_total);
+        ctrace!(MINER, "Pushed {}/{} parcels", parcel_count, taken.len());
 
         let (parcels_root, invoices_root) = {
             let parent_hash = open_block.header().parent_hash();
@@ -400,7 +802,19 @@ impl Miner {
                 queue.remove(&hash, &fetch_nonce, RemovalReason::NotAllowed);
             }
         }
-        (block, original_work_hash)
+        self.journal_parcels();
+        self.sealing_work.lock().prepared_block = Some(PreparedBlock {
+            block: block.clone(),
+            generation,
+            parent_hash: *block.header().parent_hash(),
+        });
+        (block, original_work_hash, generation)
+    }
+
+    /// Makes the next `prepare_block` build a new block.
+    /// Called when something the block is built from changes outside of the mem pool and the chain.
+    fn invalidate_prepared_block(&self) {
+        self.sealing_work.lock().prepared_block = None;
     }
 
     /// Attempts to perform internal sealing (one that does not require work) and handles the result depending on the type of Seal.
@@ -408,7 +822,7 @@ impl Miner {
     where
         C: BlockChain + ImportSealedBlock, {
         ctrace!(MINER, "seal_block_internally: attempting internal seal.");
-        if block.parcels().is_empty() {
+        if block.parcels().is_empty() && !self.force_sealing.load(Ordering::SeqCst) {
             return false
         }
 
@@ -442,7 +856,14 @@ impl Miner {
             Seal::Regular(seal) => block
                 .lock()
                 .seal(&*self.engine, seal)
-                .map(|sealed| chain.import_sealed_block(sealed).is_ok())
+                .map(|sealed| {
+                    let header = sealed.header().clone();
+                    let imported = chain.import_sealed_block(sealed).is_ok();
+                    if imported {
+                        self.note_sealed_block(&header);
+                    }
+                    imported
+                })
                 .unwrap_or_else(|e| {
                     warn!("ERROR: seal failed when given internally generated seal: {}", e);
                     false
@@ -451,9 +872,128 @@ impl Miner {
         }
     }
 
-    /// Are we allowed to do a non-mandatory reseal?
+    fn note_sealed_block(&self, header: &Header) {
+        *self.last_sealed_block.write() = Some(SealedBlockInfo {
+            hash: header.hash(),
+            number: header.number(),
+            timestamp: header.timestamp(),
+        });
+    }
+
+    /// Runs `seal` unless another sealing is in progress. Returns false if nothing is sealed.
+    ///
+    /// A sealing requested while another one is in progress, e.g. by importing the sealed block,
+    /// makes the one in progress run `seal` again once it finishes, as long as there are parcels to seal.
+    fn seal_exclusively<F>(&self, seal: F) -> bool
+    where
+        F: Fn() -> bool, {
+        let mut sealed = false;
+        loop {
+            // The request is made before checking the flag, so that the sealing in progress can't miss it.
+            self.reseal_requested.store(true, Ordering::SeqCst);
+            if self.sealing_in_progress.compare_and_swap(false, true, Ordering::SeqCst) {
+                ctrace!(MINER, "Postponing the reentrant sealing until the one in progress finishes");
+                return sealed
+            }
+            {
+                let _guard = SealingGuard(&self.sealing_in_progress);
+                self.reseal_requested.store(false, Ordering::SeqCst);
+                sealed |= seal();
+            }
+            if !self.reseal_requested.load(Ordering::SeqCst) || self.mem_pool.read().status().pending == 0 {
+                return sealed
+            }
+            ctrace!(MINER, "Running the sealing requested while sealing");
+        }
+    }
+
+    /// Are we allowed to do a non-mandatory reseal? If not, the reseal is postponed.
     fn parcel_reseal_allowed(&self) -> bool {
-        Instant::now() > *self.next_allowed_reseal.lock()
+        self.reseal_schedule.lock().request(Instant::now())
+    }
+
+    /// Reseals the postponed work once `reseal_min_period` has passed.
+    /// Called periodically, since nothing else may trigger the reseal.
+    pub fn reseal_pending_work<C>(&self, chain: &C)
+    where
+        C: AccountData + BlockChain + BlockProducer + ImportSealedBlock, {
+        self.reseal_pending_work_at(chain, Instant::now())
+    }
+
+    fn reseal_pending_work_at<C>(&self, chain: &C, now: Instant)
+    where
+        C: AccountData + BlockChain + BlockProducer + ImportSealedBlock, {
+        if self.engine.seals_internally().is_some() && self.reseal_schedule.lock().is_due(now) {
+            ctrace!(MINER, "reseal_pending_work: resealing the postponed work");
+            self.update_sealing_at(chain, now);
+        }
+    }
+
+    fn update_sealing_at<C>(&self, chain: &C, now: Instant)
+    where
+        C: AccountData + BlockChain + BlockProducer + ImportSealedBlock, {
+        ctrace!(MINER, "update_sealing: preparing a block");
+
+        if self.engine.seals_internally().is_some() {
+            let mut schedule = self.reseal_schedule.lock();
+            if !schedule.is_due(now) && !schedule.request(now) {
+                ctrace!(MINER, "update_sealing: postponed until the min period passes");
+                return
+            }
+        }
+
+        self.seal_exclusively(|| {
+            if !self.requires_reseal(chain.chain_info().best_block_number, now) {
+                return false
+            }
+            let (block, original_work_hash, generation) = self.prepare_block(chain);
+
+            match self.engine.seals_internally() {
+                Some(true) => {
+                    ctrace!(MINER, "update_sealing: engine indicates internal sealing");
+                    let imported = self.seal_and_import_block_internally(chain, block);
+                    if imported {
+                        ctrace!(MINER, "update_sealing: imported internally sealed block");
+                    }
+                    imported
+                }
+                Some(false) => {
+                    ctrace!(MINER, "update_sealing: engine is not keen to seal internally right now");
+                    false
+                }
+                None => {
+                    ctrace!(MINER, "update_sealing: engine does not seal internally, preparing work");
+                    self.prepare_work(block, original_work_hash, generation);
+                    false
+                }
+            }
+        });
+    }
+
+    /// Seals the last work internally. Returns true if a block was sealed.
+    fn seal_pending_work_internally<C>(&self, chain: &C) -> bool
+    where
+        C: BlockChain + ImportSealedBlock, {
+        let block = self.sealing_work.lock().queue.peek_last_ref().cloned();
+        match block {
+            Some(block) => self.seal_and_import_block_internally(chain, block),
+            None => false,
+        }
+    }
+
+    /// Returns the state of the pending block in a single lock of the sealing work.
+    /// Returns `None` if nothing is prepared, and `Some(None)` if the pending block is not built on `best_block_hash`
+    /// or the mem pool changed after it was prepared.
+    fn fresh_pending_state(&self, best_block_hash: &H256) -> Option<Option<TopLevelState<::state_db::StateDB>>> {
+        let sealing_work = self.sealing_work.lock();
+        let block = sealing_work.queue.peek_last_ref()?;
+        let is_stale = sealing_work.prepared_generation != Some(self.changes.mem_pool_generation())
+            || block.block().header().parent_hash() != best_block_hash;
+        if is_stale {
+            Some(None)
+        } else {
+            Some(Some(block.state().clone()))
+        }
     }
 
     fn map_pending_block<F, T>(&self, f: F, latest_block_number: BlockNumber) -> Option<T>
@@ -472,19 +1012,183 @@ impl Miner {
 
 const SEALING_TIMEOUT_IN_BLOCKS: u64 = 5;
 
+/// The batches smaller than this are verified in the calling thread, since spawning the verifiers costs more.
+const MIN_PARALLEL_VERIFICATION_BATCH: usize = 64;
+
+/// Runs `verify_parcel_unordered` for each of `parcels` using at most `max_threads` threads.
+/// The results are in the order of `parcels`.
+fn verify_parcels_unordered(
+    engine: &CodeChainEngine,
+    mut parcels: Vec<UnverifiedParcel>,
+    header: &Header,
+    max_threads: usize,
+) -> Vec<Result<SignedParcel, Error>> {
+    fn verify_chunk(
+        engine: &CodeChainEngine,
+        chunk: Vec<UnverifiedParcel>,
+        header: &Header,
+    ) -> Vec<Result<SignedParcel, Error>> {
+        chunk.into_iter().map(|parcel| engine.verify_parcel_unordered(parcel, header)).collect()
+    }
+
+    if max_threads <= 1 || parcels.len() < MIN_PARALLEL_VERIFICATION_BATCH {
+        return verify_chunk(engine, parcels, header)
+    }
+    let chunk_size = (parcels.len() + max_threads - 1) / max_threads;
+    let mut chunks = Vec::with_capacity(max_threads);
+    while parcels.len() > chunk_size {
+        let rest = parcels.split_off(chunk_size);
+        chunks.push(parcels);
+        parcels = rest;
+    }
+    chunks.push(parcels);
+    crossbeam::scope(|scope| {
+        let handles: Vec<_> =
+            chunks.into_iter().map(|chunk| scope.spawn(move || verify_chunk(engine, chunk, header))).collect();
+        handles.into_iter().flat_map(|handle| handle.join()).collect()
+    })
+}
+
+/// Clears the sealing flag when the sealing finishes, even if it panics.
+struct SealingGuard<'a>(&'a AtomicBool);
+
+impl<'a> Drop for SealingGuard<'a> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Tracks the room left in the body of a block being prepared.
+struct BodyFullness {
+    max_parcels: usize,
+    max_body_size: usize,
+    parcels: usize,
+    payload_size: usize,
+}
+
+impl BodyFullness {
+    /// `parcels_limit` lowers the number of parcels the spec allows in a block.
+    fn new(params: &CommonParams, parcels_limit: Option<usize>) -> Self {
+        let max_parcels = parcels_limit.map_or(params.max_parcels_per_block, |limit| {
+            cmp::min(limit, params.max_parcels_per_block)
+        });
+        Self {
+            max_parcels,
+            max_body_size: params.max_body_size,
+            parcels: 0,
+            payload_size: 0,
+        }
+    }
+
+    fn remaining_parcels(&self) -> usize {
+        self.max_parcels.saturating_sub(self.parcels)
+    }
+
+    /// The bytes left for the parcels, not counting the list header of the body.
+    fn remaining_size(&self) -> usize {
+        self.max_body_size.saturating_sub(encoded_list_size(self.payload_size))
+    }
+
+    /// Returns true if no more parcels can be added.
+    fn is_full(&self) -> bool {
+        self.parcels >= self.max_parcels
+    }
+
+    /// Returns true if a parcel of the given encoded size fits in the body.
+    fn has_room_for(&self, parcel_size: usize) -> bool {
+        !self.is_full() && encoded_list_size(self.payload_size + parcel_size) <= self.max_body_size
+    }
+
+    fn add(&mut self, parcel_size: usize) {
+        debug_assert!(self.has_room_for(parcel_size));
+        self.parcels += 1;
+        self.payload_size += parcel_size;
+    }
+}
+
+/// Looks up each account once while a batch of parcels is added to the mem pool.
+/// It must not outlive the batch, since the accounts change with the chain.
+struct AccountDetailsCache<F> {
+    fetch_account: F,
+    cache: RefCell<HashMap<Address, AccountDetails>>,
+}
+
+impl<F> AccountDetailsCache<F>
+where
+    F: Fn(&Address) -> AccountDetails,
+{
+    fn new(fetch_account: F) -> Self {
+        Self {
+            fetch_account,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn fetch(&self, address: &Address) -> AccountDetails {
+        if let Some(details) = self.cache.borrow().get(address) {
+            return details.clone()
+        }
+        let details = (self.fetch_account)(address);
+        self.cache.borrow_mut().insert(*address, details.clone());
+        details
+    }
+}
+
+/// The size of an RLP list whose items take `payload_size` bytes.
+fn encoded_list_size(payload_size: usize) -> usize {
+    const MAX_SHORT_PAYLOAD_SIZE: usize = 55;
+    if payload_size <= MAX_SHORT_PAYLOAD_SIZE {
+        return 1 + payload_size
+    }
+    let length_size = (64 - (payload_size as u64).leading_zeros() as usize + 7) / 8;
+    1 + length_size + payload_size
+}
+
 impl MinerService for Miner {
     type State = TopLevelState<::state_db::StateDB>;
 
+    fn pending_state<C: AccountData + BlockChain + BlockProducer>(&self, chain: &C) -> Option<Self::State> {
+        let best_block_hash = chain.chain_info().best_block_hash;
+        if let Some(state) = self.fresh_pending_state(&best_block_hash)? {
+            return Some(state)
+        }
+        let _work_preparation = self.work_preparation.lock();
+        // Another caller may have prepared the work while we were waiting for it.
+        if let Some(state) = self.fresh_pending_state(&best_block_hash)? {
+            return Some(state)
+        }
+        ctrace!(MINER, "pending_state: The pending block is stale - preparing it again");
+        let (block, original_work_hash, generation) = self.prepare_block(chain);
+        let state = block.state().clone();
+        self.prepare_work(block, original_work_hash, generation);
+        Some(state)
+    }
+
     fn status(&self) -> MinerStatus {
         let status = self.mem_pool.read().status();
         let sealing_work = self.sealing_work.lock();
         MinerStatus {
             parcels_in_pending_queue: status.pending,
             parcels_in_future_queue: status.future,
-            parcels_in_pending_block: sealing_work.queue.peek_last_ref().map_or(0, |b| b.parcels().len()),
+            parcels_in_pending_block: sealing_work.pending_block_parcels,
+            mem_pool_generation: status.generation,
+            mem_pool_encoded_size: status.encoded_size,
+            mem_pool_mem_usage: status.mem_usage,
+            mem_pool_parcels_by_origin: status.by_origin,
+            last_sealed_block: *self.last_sealed_block.read(),
+            sealing_enabled: sealing_work.enabled,
+            sealing_in_progress: self.sealing_in_progress.load(Ordering::SeqCst),
         }
     }
 
+    fn mem_pool_metrics<C: BlockChain>(&self, chain: &C) -> MemPoolMetrics {
+        self.mem_pool.read().metrics(chain.chain_info().best_block_number)
+    }
+
+    fn mem_pool_dump(&self) -> MemPoolDump {
+        self.mem_pool.read().dump()
+    }
+
     fn author(&self) -> Address {
         *self.author.read()
     }
@@ -492,29 +1196,39 @@ impl MinerService for Miner {
     fn set_author(&self, author: Address) {
         ctrace!(MINER, "Set author to {:?}", author);
         *self.author.write() = author;
+        self.add_local_address(author);
+        self.invalidate_prepared_block();
     }
 
     fn extra_data(&self) -> Bytes {
         self.extra_data.read().clone()
     }
 
-    fn set_extra_data(&self, extra_data: Bytes) {
+    fn set_extra_data(&self, extra_data: Bytes) -> Result<(), Error> {
+        let maximum_extra_data_size = self.engine.maximum_extra_data_size();
+        if extra_data.len() > maximum_extra_data_size {
+            return Err(BlockError::ExtraDataOutOfBounds(OutOfBounds {
+                min: None,
+                max: Some(maximum_extra_data_size),
+                found: extra_data.len(),
+            }).into())
+        }
         *self.extra_data.write() = extra_data;
+        self.invalidate_prepared_block();
+        Ok(())
     }
 
     fn set_engine_signer(&self, address: Address, password: String) -> Result<(), SignError> {
-        if self.engine.seals_internally().is_some() {
-            if let Some(ref ap) = self.accounts {
-                ctrace!(MINER, "Set engine signer to {:?}", address);
-                self.engine.set_signer(ap.clone(), address, password);
-                Ok(())
-            } else {
+        match self.accounts {
+            Some(ref ap) => self.set_engine_signer_from_account(Arc::clone(ap), address, password),
+            None if self.engine.seals_internally().is_none() => {
+                cwarn!(MINER, "Cannot set engine signer on a PoW chain.");
+                Err(SignError::InappropriateChain)
+            }
+            None => {
                 cwarn!(MINER, "No account provider");
                 Err(SignError::NotFound)
             }
-        } else {
-            cwarn!(MINER, "Cannot set engine signer on a PoW chain.");
-            Err(SignError::InappropriateChain)
         }
     }
 
@@ -524,37 +1238,123 @@ impl MinerService for Miner {
 
     fn set_minimal_fee(&self, min_fee: U256) {
         self.mem_pool.write().set_minimal_fee(min_fee);
+        self.invalidate_prepared_block();
     }
 
-    fn parcels_limit(&self) -> usize {
-        self.mem_pool.read().limit()
+    fn minimal_fee_for(&self, kind: ActionKind) -> U256 {
+        self.mem_pool.read().minimal_fee_for(kind)
     }
 
-    fn set_parcels_limit(&self, limit: usize) {
-        self.mem_pool.write().set_limit(limit)
+    fn set_minimal_fee_for(&self, kind: ActionKind, min_fee: Option<U256>) {
+        self.mem_pool.write().set_minimal_fee_for(kind, min_fee);
+        self.invalidate_prepared_block();
     }
 
-    fn chain_new_blocks<C>(
-        &self,
-        chain: &C,
-        _imported: &[H256],
-        _invalid: &[H256],
-        _enacted: &[H256],
-        retracted: &[H256],
-    ) where
-        C: AccountData + BlockChain + BlockProducer + ImportSealedBlock, {
-        ctrace!(MINER, "chain_new_blocks");
+    fn fee_estimate(&self, percentile: u8) -> U256 {
+        let mem_pool = self.mem_pool.read();
+        let minimal_fee = *mem_pool.minimal_fee();
+        mem_pool.fee_percentile(percentile).map_or(minimal_fee, |fee| cmp::max(fee, minimal_fee))
+    }
 
-        // Then import all parcels...
-        {
-            let mut mem_pool = self.mem_pool.write();
-            for hash in retracted {
-                let block = chain.block((*hash).into()).expect(
-                    "Client is sending message after commit to db and inserting to chain; the block is available; qed",
-                );
-                let parcels = block.parcels();
-                let _ = self.add_parcels_to_pool(chain, parcels, ParcelOrigin::RetractedBlock, &mut mem_pool);
-            }
+    fn max_fee(&self) -> Option<U256> {
+        self.mem_pool.read().max_fee()
+    }
+
+    fn set_max_fee(&self, max_fee: Option<U256>) {
+        self.mem_pool.write().set_max_fee(max_fee);
+    }
+
+    fn remove_below_minimal_fee<C: AccountData>(&self, chain: &C) -> usize {
+        let fetch_account = |a: &Address| AccountDetails {
+            nonce: chain.latest_nonce(a),
+            balance: chain.latest_balance(a),
+        };
+        let removed = self.mem_pool.write().remove_below_fee(&fetch_account);
+        self.journal_parcels();
+        removed
+    }
+
+    fn set_minimal_fee_and_cull<C: AccountData>(&self, min_fee: U256, chain: &C) -> usize {
+        let fetch_account = |a: &Address| AccountDetails {
+            nonce: chain.latest_nonce(a),
+            balance: chain.latest_balance(a),
+        };
+        let removed = {
+            let mut mem_pool = self.mem_pool.write();
+            mem_pool.set_minimal_fee(min_fee);
+            mem_pool.remove_below_fee(&fetch_account)
+        };
+        self.journal_parcels();
+        self.invalidate_prepared_block();
+        removed
+    }
+
+    fn block_parcels_limit(&self) -> Option<usize> {
+        *self.block_parcels_limit.read()
+    }
+
+    fn set_block_parcels_limit(&self, limit: Option<usize>) {
+        *self.block_parcels_limit.write() = limit;
+        self.invalidate_prepared_block();
+    }
+
+    fn parcels_limit(&self) -> usize {
+        self.mem_pool.read().limit()
+    }
+
+    fn set_parcels_limit(&self, limit: usize) {
+        self.mem_pool.write().set_limit(limit);
+        self.journal_parcels();
+    }
+
+    fn future_parcels_limit(&self) -> usize {
+        self.mem_pool.read().future_limit()
+    }
+
+    fn set_future_parcels_limit(&self, limit: usize) {
+        self.mem_pool.write().set_future_limit(limit);
+        self.journal_parcels();
+    }
+
+    fn max_parcels_per_sender(&self) -> usize {
+        self.mem_pool.read().max_parcels_per_sender()
+    }
+
+    fn set_max_parcels_per_sender(&self, limit: usize) {
+        self.mem_pool.write().set_max_parcels_per_sender(limit)
+    }
+
+    fn chain_new_blocks<C>(
+        &self,
+        chain: &C,
+        _imported: &[H256],
+        _invalid: &[H256],
+        enacted: &[H256],
+        retracted: &[H256],
+    ) where
+        C: AccountData + BlockChain + BlockProducer + ImportSealedBlock, {
+        ctrace!(MINER, "chain_new_blocks");
+        if !enacted.is_empty() || !retracted.is_empty() {
+            self.changes.chain_head_moved();
+            // The pending block is built on the old head until it's prepared again.
+            self.sealing_work.lock().pending_block_parcels = 0;
+        }
+
+        // Then import all parcels...
+        let mut verified = Vec::new();
+        for hash in retracted {
+            let block = match chain.block((*hash).into()) {
+                Some(block) => block,
+                None => {
+                    cwarn!(MINER, "The body of the retracted block {:?} is not available", hash);
+                    continue
+                }
+            };
+            verified.extend(self.verify_parcels(chain, block.parcels(), ParcelOrigin::RetractedBlock));
+        }
+        if !verified.is_empty() {
+            let (_, inserted) = self.add_verified_parcels_to_pool(chain, verified, &mut self.mem_pool.write());
+            self.notify_parcel_listeners(&inserted);
         }
 
         // ...and at the end remove the old ones
@@ -563,38 +1363,91 @@ impl MinerService for Miner {
                 nonce: chain.latest_nonce(a),
                 balance: chain.latest_balance(a),
             };
+            let fetch_nonce = |a: &Address| chain.latest_nonce(a);
             let time = chain.chain_info().best_block_number;
             let mut mem_pool = self.mem_pool.write();
+            mem_pool.cull_expired(&fetch_nonce, time);
             mem_pool.remove_old(&fetch_account, time);
         }
+        self.journal_parcels();
+
+        // The remaining parcels are sealed on the new head.
+        // The work for external sealing is prepared when it's requested.
+        if !enacted.is_empty() && self.engine.seals_internally().is_some() {
+            self.update_sealing(chain);
+        } else {
+            self.reseal_pending_work(chain);
+        }
     }
 
     fn can_produce_work_package(&self) -> bool {
         self.engine.seals_internally().is_none()
     }
 
+    fn is_currently_sealing(&self) -> bool {
+        self.sealing_in_progress.load(Ordering::SeqCst)
+    }
+
     fn update_sealing<C>(&self, chain: &C)
     where
         C: AccountData + BlockChain + BlockProducer + ImportSealedBlock, {
-        ctrace!(MINER, "update_sealing: preparing a block");
+        self.update_sealing_at(chain, Instant::now())
+    }
 
-        if self.requires_reseal(chain.chain_info().best_block_number) {
-            let (block, original_work_hash) = self.prepare_block(chain);
+    fn prepare_work_sealing<C: AccountData + BlockChain + BlockProducer>(&self, client: &C) -> bool {
+        ctrace!(MINER, "prepare_work_sealing: entering");
+        // Callers arriving while the work is being prepared wait for it instead of preparing it again.
+        let _work_preparation = self.work_preparation.lock();
 
-            match self.engine.seals_internally() {
-                Some(true) => {
-                    ctrace!(MINER, "update_sealing: engine indicates internal sealing");
-                    if self.seal_and_import_block_internally(chain, block) {
-                        ctrace!(MINER, "update_sealing: imported internally sealed block");
-                    }
-                }
-                Some(false) => ctrace!(MINER, "update_sealing: engine is not keen to seal internally right now"),
-                None => {
-                    ctrace!(MINER, "update_sealing: engine does not seal internally, preparing work");
-                    self.prepare_work(block, original_work_hash)
-                }
+        let chain_info = client.chain_info();
+        let generation = self.mem_pool.read().generation();
+        let prepare_new = {
+            let mut sealing_work = self.sealing_work.lock();
+            let have_work = sealing_work.queue.peek_last_ref().is_some();
+            let is_stale = sealing_work.prepared_generation != Some(generation)
+                || sealing_work
+                    .queue
+                    .peek_last_ref()
+                    .map_or(true, |b| b.block().header().parent_hash() != &chain_info.best_block_hash);
+            ctrace!(MINER, "prepare_work_sealing: have_work={}, is_stale={}", have_work, is_stale);
+            if !have_work {
+                sealing_work.enabled = true;
+            }
+            is_stale
+        };
+        if prepare_new {
+            // --------------------------------------------------------------------------
+            // | NOTE Code below requires transaction_queue and sealing_work locks.     |
+            // | Make sure to release the locks before calling that method.             |
+            // --------------------------------------------------------------------------
+            let (block, original_work_hash, generation) = self.prepare_block(client);
+            self.prepare_work(block, original_work_hash, generation);
+        }
+        {
+            let mut sealing_block_last_request = self.sealing_block_last_request.lock();
+            let best_number = chain_info.best_block_number;
+            if *sealing_block_last_request != best_number {
+                ctrace!(
+                    MINER,
+                    "prepare_work_sealing: Miner received request (was {}, now {}) - waking up.",
+                    *sealing_block_last_request,
+                    best_number
+                );
+                *sealing_block_last_request = best_number;
             }
         }
+
+        let mut sealing_work = self.sealing_work.lock();
+        let work_hash = sealing_work.queue.peek_last_ref().map(|b| b.block().header().hash());
+        let is_new_work = work_hash != sealing_work.reported_work_hash;
+        sealing_work.reported_work_hash = work_hash;
+        is_new_work
+    }
+
+    fn map_sealing_work<F, T>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(&Header) -> T, {
+        self.sealing_work.lock().queue.use_last_ref().map(|b| f(b.block().header()))
     }
 
     fn submit_seal<C: ImportSealedBlock>(&self, chain: &C, block_hash: H256, seal: Vec<Bytes>) -> Result<(), Error> {
@@ -616,10 +1469,10 @@ impl MinerService for Miner {
             Err(Error::PowHashInvalid)
         };
         result.and_then(|sealed| {
-            let n = sealed.header().number();
-            let h = sealed.header().hash();
+            let header = sealed.header().clone();
             chain.import_sealed_block(sealed)?;
-            cinfo!(MINER, "Submitted block imported OK. #{}: {}", n, h);
+            self.note_sealed_block(&header);
+            cinfo!(MINER, "Submitted block imported OK. #{}: {}", header.number(), header.hash());
             Ok(())
         })
     }
@@ -629,20 +1482,25 @@ impl MinerService for Miner {
         client: &C,
         parcels: Vec<UnverifiedParcel>,
     ) -> Vec<Result<ParcelImportResult, Error>> {
-        ctrace!(EXTERNAL_PARCEL, "Importing external parcels");
-        let results = {
-            let mut mem_pool = self.mem_pool.write();
-            self.add_parcels_to_pool(client, parcels, ParcelOrigin::External, &mut mem_pool)
-        };
+        self.import_external_parcels_with_positions(client, parcels).into_iter().map(|(result, _)| result).collect()
+    }
 
-        if !results.is_empty() && self.options.reseal_on_external_parcel && self.parcel_reseal_allowed() {
-            // ------------------------------------------------------------------
-            // | NOTE Code below requires mem_pool and sealing_queue locks.     |
-            // | Make sure to release the locks before calling that method.     |
-            // ------------------------------------------------------------------
-            self.update_sealing(client);
-        }
-        results
+    fn import_external_parcels_detailed<C: MiningBlockChainClient>(
+        &self,
+        client: &C,
+        parcels: Vec<UnverifiedParcel>,
+    ) -> Vec<ParcelImportOutcome> {
+        let hashes: Vec<H256> = parcels.iter().map(|parcel| parcel.hash()).collect();
+        let results = self.import_external_parcels_with_positions(client, parcels);
+        hashes
+            .into_iter()
+            .zip(results)
+            .map(|(hash, (result, position))| ParcelImportOutcome {
+                hash,
+                result,
+                position,
+            })
+            .collect()
     }
 
     fn import_own_parcel<C: MiningBlockChainClient>(
@@ -650,51 +1508,1554 @@ impl MinerService for Miner {
         chain: &C,
         parcel: SignedParcel,
     ) -> Result<ParcelImportResult, Error> {
-        ctrace!(OWN_PARCEL, "Importing parcel: {:?}", parcel);
+        self.import_own_parcels(chain, vec![parcel])
+            .pop()
+            .expect("one result returned per added parcel; one added => one result; qed")
+    }
+
+    fn import_own_parcels<C: MiningBlockChainClient>(
+        &self,
+        chain: &C,
+        parcels: Vec<SignedParcel>,
+    ) -> Vec<Result<ParcelImportResult, Error>> {
+        ctrace!(OWN_PARCEL, "Importing parcels: {:?}", parcels);
 
-        let imported = {
+        // We need to re-validate parcels
+        let parcels = parcels.into_iter().map(Into::into).collect();
+        let verified = self.verify_parcels(chain, parcels, ParcelOrigin::Local);
+        let (results, inserted) = {
             // Be sure to release the lock before we call prepare_work_sealing
             let mut mem_pool = self.mem_pool.write();
-            // We need to re-validate parcels
-            let import = self.add_parcels_to_pool(chain, vec![parcel.into()], ParcelOrigin::Local, &mut mem_pool)
-                .pop()
-                .expect("one result returned per added parcel; one added => one result; qed");
-
-            match import {
-                Ok(_) => {
-                    ctrace!(OWN_PARCEL, "Status: {:?}", mem_pool.status());
-                }
-                Err(ref e) => {
-                    ctrace!(OWN_PARCEL, "Status: {:?}", mem_pool.status());
-                    cwarn!(OWN_PARCEL, "Error importing parcel: {:?}", e);
-                }
+            let (results, inserted) = self.add_verified_parcels_to_pool(chain, verified, &mut mem_pool);
+
+            ctrace!(OWN_PARCEL, "Status: {:?}", mem_pool.status());
+            for e in results.iter().filter_map(|result| result.as_ref().err()) {
+                cwarn!(OWN_PARCEL, "Error importing parcel: {:?}", e);
             }
-            import
+            (results, inserted)
         };
+        self.journal_parcels();
+        self.notify_parcel_listeners(&inserted);
 
         // ------------------------------------------------------------------
         // | NOTE Code below requires mem_pool and sealing_queue locks.     |
         // | Make sure to release the locks before calling that method.     |
         // ------------------------------------------------------------------
-        if imported.is_ok() && self.options.reseal_on_own_parcel && self.parcel_reseal_allowed() {
+        if !inserted.is_empty() && self.options.reseal_on_own_parcel && self.parcel_reseal_allowed() {
             // Make sure to do it after parcel is imported and lock is dropped.
             // We need to create pending block and enable sealing.
-            if self.engine.seals_internally().unwrap_or(false) || !self.prepare_work_sealing(chain) {
-                // If new block has not been prepared (means we already had one)
-                // or Engine might be able to seal internally,
-                // we need to update sealing.
-                self.update_sealing(chain);
+            let is_new_work = self.prepare_work_sealing(chain);
+            if is_new_work && self.engine.seals_internally().unwrap_or(false)
+                && self.requires_reseal(chain.chain_info().best_block_number, Instant::now())
+                && self.seal_exclusively(|| self.seal_pending_work_internally(chain))
+            {
+                ctrace!(MINER, "import_own_parcels: imported internally sealed block");
             }
         }
-        imported
+        results
     }
 
     fn ready_parcels(&self) -> Vec<SignedParcel> {
         self.mem_pool.read().top_parcels()
     }
 
+    fn ready_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel> {
+        self.mem_pool.read().top_parcels_with_limit(max_count, max_bytes)
+    }
+
+    fn ready_local_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel> {
+        self.mem_pool.read().top_local_parcels_with_limit(max_count, max_bytes)
+    }
+
     /// Get a list of all future parcels.
     fn future_parcels(&self) -> Vec<SignedParcel> {
         self.mem_pool.read().future_parcels()
     }
+
+    fn ready_parcels_page(&self, offset: usize, limit: usize) -> ParcelPage {
+        self.mem_pool.read().pending_page(offset, limit)
+    }
+
+    fn future_parcels_page(&self, offset: usize, limit: usize) -> ParcelPage {
+        self.mem_pool.read().future_page(offset, limit)
+    }
+
+    fn local_parcels(&self) -> BTreeMap<H256, LocalParcelStatus> {
+        self.mem_pool.read().local_parcels().iter().map(|(hash, status)| (*hash, status.clone())).collect()
+    }
+
+    fn pending_parcels_from(&self, sender: &Address) -> Vec<PooledParcel> {
+        self.mem_pool.read().parcels_by_sender(sender)
+    }
+
+    fn last_nonce(&self, sender: &Address) -> Option<U256> {
+        self.mem_pool.read().last_nonce(sender)
+    }
+
+    fn parcel(&self, hash: &H256) -> Option<PooledParcel> {
+        let mem_pool = self.mem_pool.read();
+        mem_pool.find(hash).map(|parcel| PooledParcel {
+            parcel,
+            is_future: mem_pool.is_in_future(hash),
+        })
+    }
+
+    fn clear_sender(&self, sender: &Address) -> Vec<H256> {
+        let removed = self.mem_pool.write().remove_all_from_sender(sender);
+        if !removed.is_empty() {
+            ctrace!(MINER, "Removed {} parcels of {:?} from the mem pool", removed.len(), sender);
+            self.journal_parcels();
+        }
+        removed
+    }
+
+    fn remove_parcel<C: AccountData>(
+        &self,
+        chain: &C,
+        hash: &H256,
+        subsequent: SubsequentParcels,
+    ) -> Option<SignedParcel> {
+        let fetch_nonce = |a: &Address| chain.latest_nonce(a);
+        let removed = self.mem_pool.write().cancel(hash, &fetch_nonce, subsequent);
+        self.journal_parcels();
+        if removed.is_some() {
+            ctrace!(MINER, "Removed parcel {:?} from the mem pool", hash);
+        }
+        removed
+    }
+
+    fn parcel_ordering(&self) -> OrderingStrategy {
+        self.mem_pool.read().ordering()
+    }
+
+    fn set_parcel_ordering(&self, ordering: OrderingStrategy) {
+        self.mem_pool.write().set_ordering(ordering)
+    }
+
+    fn sender_issues<C: AccountData>(&self, chain: &C, sender: &Address) -> SenderIssues {
+        self.mem_pool.read().sender_issues(sender, chain.latest_nonce(sender))
+    }
+
+    fn mem_pool_generation(&self) -> u64 {
+        self.changes.mem_pool_generation()
+    }
+
+    fn wait_for_change(&self, generation: u64, timeout: Duration) -> u64 {
+        self.changes.wait_for_change(generation, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::remove_file;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use ckeys::{Generator, KeyPair, Private, Random};
+    use cnetwork::NetworkExtension;
+    use ctypes::{Address, H256, U256};
+    use kvdb::KeyValueDB;
+    use kvdb_memorydb;
+    use parking_lot::Mutex;
+    use rlp::{self, Encodable, Rlp, RlpStream};
+    use unexpected::OutOfBounds;
+
+    use super::super::super::account_provider::{AccountProvider, SignError};
+    use super::super::super::block::{ExecutedBlock, IsBlock};
+    use super::super::super::client::{AccountData, BlockInfo, ChainInfo, TestBlockChainClient};
+    use super::super::super::codechain_machine::CodeChainMachine;
+    use super::super::super::consensus::{CodeChainEngine, ConsensusEngine};
+    use super::super::super::db::NUM_COLUMNS;
+    use super::super::super::error::{BlockError, Error};
+    use super::super::super::header::Header;
+    use super::super::super::parcel::{
+        clear_sender_cache, Action, Parcel, ParcelError, SignedParcel, UnverifiedParcel,
+    };
+    use super::super::super::spec::Spec;
+    use super::super::super::state::TopStateInfo;
+    use super::super::super::tests::helpers::generate_dummy_client_with_miner;
+    use super::super::super::transaction::Transaction;
+    use super::super::super::types::BlockId;
+    use super::super::journal::{LocalParcelsJournal, MemPoolJournal};
+    use super::super::{MinerService, ParcelImportResult, ParcelsByOrigin, SealedBlockInfo, SubsequentParcels};
+    use super::super::mem_pool::{AccountDetails, ParcelOrigin, PendingPosition, RejectionReason, RemovalReason};
+    use super::{encoded_list_size, verify_parcels_unordered, Miner, MinerOptions};
+
+    #[test]
+    fn encoded_list_size_matches_rlp() {
+        for &payload_size in &[0, 1, 55, 56, 255, 256, 65_535, 65_536] {
+            let mut s = RlpStream::new_list(1);
+            s.append_raw(&vec![0u8; payload_size], 1);
+            assert_eq!(s.out().len(), encoded_list_size(payload_size), "payload size {}", payload_size);
+        }
+    }
+
+    #[test]
+    fn prepare_work_sealing_reports_whether_the_work_changed() {
+        let client = TestBlockChainClient::new();
+        let miner = &client.miner;
+
+        assert!(miner.prepare_work_sealing(&client), "The first call prepares the work");
+        assert!(!miner.prepare_work_sealing(&client), "Nothing changed");
+
+        // The sender can't pay the fee on the pending state, so the parcel is dropped and the work stays the same.
+        client.insert_parcel_to_pool();
+        assert!(!miner.prepare_work_sealing(&client));
+        assert!(miner.ready_parcels().is_empty(), "The work was prepared again after the mem pool changed");
+
+        // A parcel the sender can pay for changes the work.
+        let funded = funded_keypair();
+        client.set_balance(funded.address(), 100.into());
+        let imported = miner.import_external_parcels(&client, vec![parcel(&client, &funded, 0).into()]);
+        assert_eq!(ParcelImportResult::Current, *imported[0].as_ref().unwrap());
+        assert!(miner.prepare_work_sealing(&client), "The mem pool changed");
+        assert_eq!(Some(1), miner.map_sealing_work(|header| header.number()));
+        assert!(!miner.prepare_work_sealing(&client));
+
+        client.add_blocks(1, 0);
+        assert!(miner.prepare_work_sealing(&client), "The chain head moved");
+        let work_number = miner.map_sealing_work(|header| header.number());
+        assert_eq!(Some(2), work_number);
+        assert!(!miner.prepare_work_sealing(&client));
+    }
+
+    #[test]
+    fn seal_for_unknown_work_is_rejected() {
+        let client = TestBlockChainClient::new();
+        assert!(client.miner.prepare_work_sealing(&client));
+        assert!(client.miner.map_sealing_work(|header| header.hash()).is_some());
+
+        match client.miner.submit_seal(&client, H256::random(), vec![]) {
+            Err(Error::PowHashInvalid) => {}
+            result => panic!("The seal for unknown work must be rejected: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn seals_for_the_work_of_recent_heads_are_accepted() {
+        let client = TestBlockChainClient::new();
+        let options = MinerOptions {
+            work_queue_size: 2,
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &client.spec, None);
+        let mut work_hashes = Vec::new();
+        for _ in 0..3 {
+            assert!(miner.prepare_work_sealing(&client));
+            work_hashes.push(miner.map_sealing_work(|header| header.hash()).unwrap());
+            client.add_blocks(1, 0);
+        }
+
+        // Only the last two works are kept.
+        match miner.submit_seal(&client, work_hashes[0], vec![]) {
+            Err(Error::PowHashInvalid) => {}
+            result => panic!("The seal for the work out of the history must be rejected: {:?}", result),
+        }
+        miner.submit_seal(&client, work_hashes[1], vec![]).unwrap();
+        miner.submit_seal(&client, work_hashes[2], vec![]).unwrap();
+        match miner.submit_seal(&client, work_hashes[1], vec![]) {
+            Err(Error::PowHashInvalid) => {}
+            result => panic!("The work must be sealed only once: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn concurrent_prepare_work_sealing_prepares_the_work_once() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let handles = (0..4)
+            .map(|_| {
+                let client = Arc::clone(&client);
+                thread::spawn(move || client.miner.prepare_work_sealing(&*client))
+            })
+            .collect::<Vec<_>>();
+        let new_work = handles.into_iter().map(|handle| handle.join().unwrap()).filter(|is_new| *is_new);
+        assert_eq!(1, new_work.count());
+    }
+
+    fn parcel(client: &TestBlockChainClient, keypair: &KeyPair, nonce: u64) -> SignedParcel {
+        Parcel {
+            nonce: U256::from(nonce),
+            fee: U256::from(10),
+            network_id: client.spec.params().network_id,
+            action: Action::ChangeShardState {
+                transactions: vec![],
+            },
+        }.sign(keypair.private())
+    }
+
+    /// Counts the parcels verified by the engine of the test spec.
+    struct CountingEngine {
+        inner: Arc<CodeChainEngine>,
+        verified: AtomicUsize,
+        closed_blocks: AtomicUsize,
+    }
+
+    impl CountingEngine {
+        fn wrap(spec: &mut Spec) -> Arc<Self> {
+            let engine = Arc::new(CountingEngine {
+                inner: Arc::clone(&spec.engine),
+                verified: AtomicUsize::new(0),
+                closed_blocks: AtomicUsize::new(0),
+            });
+            spec.engine = Arc::clone(&engine) as Arc<CodeChainEngine>;
+            engine
+        }
+    }
+
+    impl ConsensusEngine<CodeChainMachine> for CountingEngine {
+        fn name(&self) -> &str {
+            "CountingEngine"
+        }
+
+        fn machine(&self) -> &CodeChainMachine {
+            self.inner.machine()
+        }
+
+        fn verify_local_seal(&self, header: &Header) -> Result<(), Error> {
+            self.inner.verify_local_seal(header)
+        }
+
+        fn network_extension(&self) -> Option<Arc<NetworkExtension>> {
+            None
+        }
+
+        fn verify_parcel_unordered(&self, parcel: UnverifiedParcel, header: &Header) -> Result<SignedParcel, Error> {
+            self.verified.fetch_add(1, Ordering::SeqCst);
+            self.inner.verify_parcel_unordered(parcel, header)
+        }
+
+        fn on_close_block(&self, block: &mut ExecutedBlock) -> Result<(), Error> {
+            self.closed_blocks.fetch_add(1, Ordering::SeqCst);
+            self.inner.on_close_block(block)
+        }
+    }
+
+    /// A new account with `balance` in the latest state of `client`.
+    fn keypair_with_balance(client: &TestBlockChainClient, balance: u64) -> KeyPair {
+        let keypair = Random.generate().unwrap();
+        client.set_balance(keypair.address(), balance.into());
+        keypair
+    }
+
+    fn funded_keypair() -> KeyPair {
+        // The account is funded in the genesis state, which the pending block is built on.
+        let secret = "ede1d4ccb4ec9a8bbbae9a13db3f4a7b56ea04189be86ac3a6a439d9a0a1addd";
+        KeyPair::from_private(Private::from(secret)).unwrap()
+    }
+
+    #[test]
+    fn block_is_prepared_with_the_author_and_the_extra_data() {
+        let client = TestBlockChainClient::new();
+        let author = Random.generate().unwrap().address();
+        client.miner.set_author(author);
+        client.miner.set_extra_data(b"extra".to_vec()).unwrap();
+
+        let (block, _, _) = client.miner.prepare_block(&client);
+        assert_eq!(Vec::<SignedParcel>::new(), block.parcels().to_vec());
+        assert_eq!(&author, block.header().author());
+        assert_eq!(&b"extra".to_vec(), block.header().extra_data());
+        assert_eq!(client.chain_info().best_block_hash, *block.header().parent_hash());
+    }
+
+    #[test]
+    fn engine_signer_is_set_only_with_the_right_password() {
+        let spec = Spec::new_test_solo_authority();
+        let ap = AccountProvider::transient_provider();
+        let address = ap.insert_account(Random.generate().unwrap().private().clone(), "password").unwrap();
+        let miner = Miner::new(Default::default(), &spec, Some(Arc::clone(&ap)));
+
+        match miner.set_engine_signer(address, "wrong".to_string()) {
+            Err(SignError::InvalidPassword) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match miner.set_engine_signer(Address::random(), "password".to_string()) {
+            Err(SignError::NotFound) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(miner.engine.sign(H256::random()).is_err());
+
+        miner.set_engine_signer_from_account(ap, address, "password".to_string()).unwrap();
+        assert!(miner.engine.sign(H256::random()).is_ok());
+    }
+
+    #[test]
+    fn engine_signer_needs_an_engine_sealing_internally() {
+        let client = TestBlockChainClient::new();
+        let ap = AccountProvider::transient_provider();
+        let address = ap.insert_account(Random.generate().unwrap().private().clone(), "password").unwrap();
+        match client.miner.set_engine_signer_from_account(ap, address, "password".to_string()) {
+            Err(SignError::InappropriateChain) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn extra_data_longer_than_the_engine_allows_is_rejected() {
+        let client = TestBlockChainClient::new();
+        // The engine of the test spec allows 32 bytes.
+        client.miner.set_extra_data(vec![1; 32]).unwrap();
+
+        match client.miner.set_extra_data(vec![2; 33]) {
+            Err(Error::Block(BlockError::ExtraDataOutOfBounds(oob))) => assert_eq!(
+                OutOfBounds {
+                    min: None,
+                    max: Some(32),
+                    found: 33,
+                },
+                oob
+            ),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(vec![1; 32], client.miner.extra_data());
+    }
+
+    #[test]
+    fn duplicated_parcels_in_a_batch_are_verified_once() {
+        let client = TestBlockChainClient::new();
+        let funded = funded_keypair();
+        client.set_balance(funded.address(), 100.into());
+        let mut spec = Spec::new_test();
+        let engine = CountingEngine::wrap(&mut spec);
+        let miner = Miner::new(Default::default(), &spec, None);
+
+        let first = parcel(&client, &funded, 0);
+        let second = parcel(&client, &funded, 1);
+        let batch = vec![first.clone().into(), first.into(), second.into()];
+        let mut results = miner.import_external_parcels(&client, batch).into_iter();
+
+        assert_eq!(ParcelImportResult::Current, results.next().unwrap().unwrap());
+        match results.next().unwrap() {
+            Err(Error::Parcel(ParcelError::AlreadyImported)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(ParcelImportResult::Current, results.next().unwrap().unwrap());
+        assert_eq!(2, engine.verified.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn prepared_block_is_reused_while_nothing_it_is_built_from_changes() {
+        let mut spec = Spec::new_test();
+        let engine = CountingEngine::wrap(&mut spec);
+        let client = TestBlockChainClient::new_with_spec(spec);
+        let funded = funded_keypair();
+        client.set_balance(funded.address(), 100.into());
+        let closed_blocks = || engine.closed_blocks.load(Ordering::SeqCst);
+
+        let (first, _, _) = client.miner.prepare_block(&client);
+        let (second, _, _) = client.miner.prepare_block(&client);
+        assert_eq!(1, closed_blocks());
+        assert_eq!(first.block().header().hash(), second.block().header().hash());
+
+        client.miner.import_external_parcels(&client, vec![parcel(&client, &funded, 0).into()]);
+        client.miner.prepare_block(&client);
+        client.miner.prepare_block(&client);
+        assert_eq!(2, closed_blocks());
+
+        client.add_blocks(1, 0);
+        client.miner.prepare_block(&client);
+        assert_eq!(3, closed_blocks());
+
+        client.miner.set_author(Random.generate().unwrap().address());
+        client.miner.prepare_block(&client);
+        assert_eq!(4, closed_blocks());
+
+        client.miner.set_extra_data(b"extra".to_vec()).unwrap();
+        client.miner.prepare_block(&client);
+        assert_eq!(5, closed_blocks());
+
+        client.miner.set_minimal_fee(5.into());
+        client.miner.prepare_block(&client);
+        client.miner.prepare_block(&client);
+        assert_eq!(6, closed_blocks());
+    }
+
+    #[test]
+    fn accounts_are_looked_up_once_per_sender_in_a_batch() {
+        let client = TestBlockChainClient::new();
+        let senders: Vec<KeyPair> = (0..3).map(|_| Random.generate().unwrap()).collect();
+        for sender in &senders {
+            client.set_balance(sender.address(), 1_000_000.into());
+        }
+        let parcels: Vec<UnverifiedParcel> = (0..70)
+            .flat_map(|nonce| senders.iter().map(move |sender| (nonce, sender)))
+            .map(|(nonce, sender)| parcel(&client, sender, nonce).into())
+            .collect();
+        let verified = client.miner.verify_parcels(&client, parcels, ParcelOrigin::External);
+
+        client.account_lookups.store(0, Ordering::SeqCst);
+        let mut mem_pool = client.miner.mem_pool.write();
+        let (results, _) = client.miner.add_verified_parcels_to_pool(&client, verified, &mut mem_pool);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(210, mem_pool.status().pending);
+        // The nonce and the balance of each sender
+        assert_eq!(2 * senders.len(), client.account_lookups.load(Ordering::SeqCst));
+    }
+
+    /// Returns the RLP of `parcel` whose signature cannot be recovered.
+    fn with_invalid_signature(parcel: &SignedParcel) -> Vec<u8> {
+        let encoded = parcel.rlp_bytes();
+        let fields = Rlp::new(&encoded);
+        let mut s = RlpStream::new_list(7);
+        for i in 0..5 {
+            s.append_raw(fields.at(i).as_raw(), 1);
+        }
+        s.append(&U256::max_value());
+        s.append_raw(fields.at(6).as_raw(), 1);
+        s.out()
+    }
+
+    #[test]
+    fn parallel_verification_gives_the_same_results_as_the_serial_one() {
+        let client = TestBlockChainClient::new();
+        let keypair = Random.generate().unwrap();
+        let is_invalid = |nonce: usize| nonce % 100 == 7;
+        let encoded: Vec<Vec<u8>> = (0..1000)
+            .map(|nonce| {
+                let parcel = parcel(&client, &keypair, nonce as u64);
+                if is_invalid(nonce) {
+                    with_invalid_signature(&parcel)
+                } else {
+                    parcel.rlp_bytes().into_vec()
+                }
+            })
+            .collect();
+        // Decoded parcels don't carry the recovered senders.
+        let decoded = || encoded.iter().map(|bytes| rlp::decode(bytes)).collect::<Vec<UnverifiedParcel>>();
+        let engine = &*client.spec.engine;
+        let header = Header::default();
+
+        clear_sender_cache();
+        let serial = verify_parcels_unordered(engine, decoded(), &header, 1);
+        clear_sender_cache();
+        let parallel = verify_parcels_unordered(engine, decoded(), &header, 4);
+
+        assert_eq!(format!("{:?}", serial), format!("{:?}", parallel));
+        for (nonce, result) in parallel.iter().enumerate() {
+            assert_eq!(is_invalid(nonce), result.is_err(), "The result of the parcel {} is misplaced", nonce);
+        }
+    }
+
+    #[test]
+    fn detailed_import_reports_each_parcel_with_its_hash() {
+        let client = TestBlockChainClient::new();
+        let funded = funded_keypair();
+        client.set_balance(funded.address(), 100.into());
+        client.set_nonce(funded.address(), 1.into());
+        client.miner.set_minimal_fee(5.into());
+        let old = parcel(&client, &funded, 0);
+        let cheap = {
+            let mut unsigned = parcel(&client, &funded, 1).as_unsigned().clone();
+            unsigned.fee = 1.into();
+            unsigned.sign(funded.private())
+        };
+        let ready = parcel(&client, &funded, 1);
+        let batch = vec![old.clone().into(), cheap.clone().into(), ready.clone().into()];
+
+        let outcomes = client.miner.import_external_parcels_detailed(&client, batch);
+        let hashes: Vec<H256> = outcomes.iter().map(|outcome| outcome.hash).collect();
+        assert_eq!(vec![old.hash(), cheap.hash(), ready.hash()], hashes);
+        match outcomes[0].result {
+            Err(Error::Parcel(ParcelError::Old {
+                expected,
+                got,
+            })) => {
+                assert_eq!(U256::from(1), expected);
+                assert_eq!(U256::zero(), got);
+            }
+            ref result => panic!("Unexpected result: {:?}", result),
+        }
+        match outcomes[1].result {
+            Err(Error::Parcel(ParcelError::InsufficientFee {
+                minimal,
+                got,
+            })) => {
+                assert_eq!(U256::from(5), minimal);
+                assert_eq!(U256::from(1), got);
+            }
+            ref result => panic!("Unexpected result: {:?}", result),
+        }
+        match outcomes[2].result {
+            Ok(ParcelImportResult::Current) => {}
+            ref result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(None, outcomes[0].position);
+        assert_eq!(None, outcomes[1].position);
+        assert_eq!(
+            Some(PendingPosition {
+                position: 0,
+                pending: 1,
+            }),
+            outcomes[2].position
+        );
+    }
+
+    #[test]
+    fn detailed_import_reports_the_positions_at_the_insertion_of_each_parcel() {
+        let client = TestBlockChainClient::new();
+        let parcel_with_fee = |fee: u64| -> UnverifiedParcel {
+            let keypair = keypair_with_balance(&client, 1_000);
+            let mut unsigned = parcel(&client, &keypair, 0).as_unsigned().clone();
+            unsigned.fee = fee.into();
+            unsigned.sign(keypair.private()).into()
+        };
+        let batch = vec![parcel_with_fee(30), parcel_with_fee(20), parcel_with_fee(10)];
+
+        let positions: Vec<_> = client
+            .miner
+            .import_external_parcels_detailed(&client, batch)
+            .into_iter()
+            .map(|outcome| outcome.position)
+            .collect();
+        let expected: Vec<_> = (0..3)
+            .map(|position| {
+                Some(PendingPosition {
+                    position,
+                    pending: position + 1,
+                })
+            })
+            .collect();
+        assert_eq!(expected, positions);
+
+        let outcomes = client.miner.import_external_parcels_detailed(&client, vec![parcel_with_fee(40)]);
+        assert_eq!(
+            Some(PendingPosition {
+                position: 0,
+                pending: 4,
+            }),
+            outcomes[0].position
+        );
+    }
+
+    #[test]
+    fn parcels_failing_in_the_block_are_skipped_and_removed() {
+        let client = TestBlockChainClient::new();
+        let funded = funded_keypair();
+        client.set_balance(funded.address(), 100.into());
+        // The mem pool accepts the parcels of the account, but it has nothing in the state of the block.
+        let poor = keypair_with_balance(&client, 100);
+        let funded_parcels = (0..2).map(|nonce| parcel(&client, &funded, nonce)).collect::<Vec<_>>();
+        let poor_parcels = (0..2).map(|nonce| parcel(&client, &poor, nonce)).collect::<Vec<_>>();
+        let unverified = funded_parcels.iter().chain(&poor_parcels).cloned().map(Into::into).collect();
+        client.miner.import_external_parcels(&client, unverified);
+        assert_eq!(4, client.miner.status().parcels_in_pending_queue);
+
+        let (block, _, _) = client.miner.prepare_block(&client);
+        assert_eq!(funded_parcels, block.parcels().to_vec());
+        assert_eq!(None, client.miner.parcel(&poor_parcels[0].hash()));
+        // The later parcel of the failed sender waits for the nonce again.
+        assert!(client.miner.parcel(&poor_parcels[1].hash()).unwrap().is_future);
+        assert_eq!(funded_parcels, client.miner.ready_parcels());
+    }
+
+    /// The test spec with the given block limits, where `funded` accounts have a balance in the genesis state.
+    fn spec_with_limits(max_body_size: usize, max_parcels: usize, funded: &[Address]) -> Spec {
+        let accounts = funded.iter().map(|address| format!("\"{:x}\": {{ \"balance\": \"1000\" }},", address));
+        let json = include_str!("../../res/null.json")
+            .replace("\"maxBodySize\": \"0x400000\"", &format!("\"maxBodySize\": \"{:#x}\"", max_body_size))
+            .replace("\"maxParcelsPerBlock\": \"0x1000\"", &format!("\"maxParcelsPerBlock\": \"{:#x}\"", max_parcels))
+            .replace("\"accounts\": {", &format!("\"accounts\": {{{}", accounts.collect::<String>()));
+        Spec::load(json.as_bytes()).unwrap()
+    }
+
+    /// A parcel minting an asset with `metadata_size` bytes of metadata, to control the size of the parcel.
+    fn minting_parcel(network_id: u64, keypair: &KeyPair, fee: u64, metadata_size: usize) -> SignedParcel {
+        let mint = Transaction::AssetMint {
+            metadata: "m".repeat(metadata_size),
+            lock_script_hash: H256::random(),
+            parameters: vec![],
+            amount: Some(30),
+            registrar: None,
+            nonce: 0,
+        };
+        Parcel {
+            nonce: U256::zero(),
+            fee: U256::from(fee),
+            network_id,
+            action: Action::ChangeShardState {
+                transactions: vec![mint],
+            },
+        }.sign(keypair.private())
+    }
+
+    /// The minting parcels of `metadata_sizes` from different senders, from the highest fee to the lowest.
+    fn minting_parcels(metadata_sizes: &[usize]) -> Vec<SignedParcel> {
+        let network_id = Spec::new_test().params().network_id;
+        metadata_sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| minting_parcel(network_id, &Random.generate().unwrap(), 100 - i as u64, size))
+            .collect()
+    }
+
+    /// The test client with the given block limits, where `parcels` are imported.
+    fn client_with_parcels(max_body_size: usize, max_parcels: usize, parcels: &[SignedParcel]) -> TestBlockChainClient {
+        let funded: Vec<_> = parcels.iter().map(SignedParcel::sender).collect();
+        let client = TestBlockChainClient::new_with_spec(spec_with_limits(max_body_size, max_parcels, &funded));
+        for address in &funded {
+            client.set_balance(*address, 1_000.into());
+        }
+        let unverified = parcels.iter().cloned().map(Into::into).collect();
+        assert!(client.miner.import_external_parcels(&client, unverified).iter().all(Result::is_ok));
+        client
+    }
+
+    fn body_size(parcels: &[SignedParcel]) -> usize {
+        encoded_list_size(parcels.iter().map(|parcel| parcel.rlp_bytes().len()).sum())
+    }
+
+    #[test]
+    fn oversized_parcels_are_skipped_without_stopping() {
+        let parcels = minting_parcels(&[300, 2000, 200, 50, 10]);
+        // The body has room for every parcel but the second one.
+        let expected = vec![parcels[0].clone(), parcels[2].clone(), parcels[3].clone(), parcels[4].clone()];
+        let client = client_with_parcels(body_size(&expected), 10, &parcels);
+
+        let (block, _, _) = client.miner.prepare_block(&client);
+        assert_eq!(expected, block.parcels().to_vec());
+        assert!(body_size(block.parcels()) <= client.spec.params().max_body_size);
+        // The skipped parcel waits in the queue for the next block.
+        assert!(!client.miner.parcel(&parcels[1].hash()).unwrap().is_future);
+    }
+
+    #[test]
+    fn packing_stops_at_the_parcel_limit() {
+        let parcels = minting_parcels(&[10, 900, 20, 30, 40]);
+        let client = client_with_parcels(0x400000, 3, &parcels);
+
+        let (block, _, _) = client.miner.prepare_block(&client);
+        assert_eq!(parcels[..3].to_vec(), block.parcels().to_vec());
+        assert_eq!(5, client.miner.status().parcels_in_pending_queue);
+    }
+
+    #[test]
+    fn the_parcels_following_an_invalid_one_fill_the_block() {
+        let parcels = minting_parcels(&[10, 20, 30, 40]);
+        // The sender of the first parcel has no balance in the block, so the parcel fails to be pushed.
+        let funded: Vec<_> = parcels[1..].iter().map(SignedParcel::sender).collect();
+        let client = TestBlockChainClient::new_with_spec(spec_with_limits(0x400000, 2, &funded));
+        for parcel in &parcels {
+            client.set_balance(parcel.sender(), 1_000.into());
+        }
+        let unverified = parcels.iter().cloned().map(Into::into).collect();
+        assert!(client.miner.import_external_parcels(&client, unverified).iter().all(Result::is_ok));
+
+        let (block, _, _) = client.miner.prepare_block(&client);
+        assert_eq!(parcels[1..3].to_vec(), block.parcels().to_vec());
+        assert_eq!(3, client.miner.status().parcels_in_pending_queue);
+    }
+
+    #[test]
+    fn parcels_over_the_block_budget_are_left_in_the_queue() {
+        let network_id = Spec::new_test().params().network_id;
+        let signed = |keypair: &KeyPair, nonce: u64, transactions: Vec<Transaction>| {
+            Parcel {
+                nonce: U256::from(nonce),
+                fee: U256::from(10),
+                network_id,
+                action: Action::ChangeShardState {
+                    transactions,
+                },
+            }.sign(keypair.private())
+        };
+        let mint = Transaction::AssetMint {
+            metadata: "metadata".repeat(100),
+            lock_script_hash: H256::random(),
+            parameters: vec![],
+            amount: Some(30),
+            registrar: None,
+            nonce: 0,
+        };
+        let first = Random.generate().unwrap();
+        let second = Random.generate().unwrap();
+        let first_parcels = vec![signed(&first, 0, vec![]), signed(&first, 1, vec![mint]), signed(&first, 2, vec![])];
+        let second_parcels = vec![signed(&second, 0, vec![]), signed(&second, 1, vec![])];
+
+        // Every parcel but the minting one fits in the body.
+        let small_parcels = first_parcels.iter().chain(&second_parcels).filter(|parcel| match &parcel.action {
+            Action::ChangeShardState {
+                transactions,
+            } => transactions.is_empty(),
+            _ => false,
+        });
+        let max_body_size = encoded_list_size(small_parcels.map(|parcel| parcel.rlp_bytes().len()).sum());
+        let spec = spec_with_limits(max_body_size, 0x1000, &[first.address(), second.address()]);
+        let client = TestBlockChainClient::new_with_spec(spec);
+        client.set_balance(first.address(), 1_000.into());
+        client.set_balance(second.address(), 1_000.into());
+        let unverified = first_parcels.iter().chain(&second_parcels).cloned().map(Into::into).collect();
+        assert!(client.miner.import_external_parcels(&client, unverified).iter().all(Result::is_ok));
+
+        // The last parcel of the first sender fits, but it can't follow the skipped one.
+        let (block, _, _) = client.miner.prepare_block(&client);
+        let mut packed = block.parcels().to_vec();
+        packed.sort_by_key(|parcel| parcel.hash());
+        let mut expected = vec![first_parcels[0].clone(), second_parcels[0].clone(), second_parcels[1].clone()];
+        expected.sort_by_key(|parcel| parcel.hash());
+        assert_eq!(expected, packed);
+        assert_eq!(5, client.miner.status().parcels_in_pending_queue);
+
+        client.miner.set_block_parcels_limit(Some(2));
+        assert_eq!(Some(2), client.miner.block_parcels_limit());
+        let (block, _, _) = client.miner.prepare_block(&client);
+        assert_eq!(2, block.parcels().len());
+        assert_eq!(5, client.miner.status().parcels_in_pending_queue);
+    }
+
+    #[test]
+    fn solo_engine_seals_a_block_for_an_own_parcel() {
+        let spec = Spec::new_test_solo();
+        let miner = Arc::new(Miner::with_spec(&spec));
+        let client = generate_dummy_client_with_miner(&spec, Arc::clone(&miner));
+        let funded = funded_keypair();
+        let parcel = Parcel {
+            nonce: U256::zero(),
+            fee: U256::from(10),
+            network_id: spec.params().network_id,
+            action: Action::ChangeShardState {
+                transactions: vec![],
+            },
+        }.sign(funded.private());
+
+        assert_eq!(ParcelImportResult::Current, miner.import_own_parcel(&*client, parcel.clone()).unwrap());
+        assert_eq!(1, client.chain_info().best_block_number);
+        let block = client.block(BlockId::Latest).unwrap();
+        assert_eq!(vec![parcel.hash()], block.parcel_hashes());
+        assert_eq!(Vec::<SignedParcel>::new(), miner.ready_parcels());
+    }
+
+    #[test]
+    fn solo_engine_seals_empty_blocks_only_if_it_is_forced() {
+        let spec = Spec::new_test_solo();
+        let options = MinerOptions {
+            reseal_min_period: Duration::from_secs(0),
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &spec, None);
+        let client = generate_dummy_client_with_miner(&spec, Arc::clone(&miner));
+
+        let best = client.chain_info().best_block_hash;
+        miner.chain_new_blocks(&*client, &[best], &[], &[best], &[]);
+        assert_eq!(0, client.chain_info().best_block_number);
+
+        miner.set_force_sealing(true);
+        for number in 1..3 {
+            let best = client.chain_info().best_block_hash;
+            miner.chain_new_blocks(&*client, &[best], &[], &[best], &[]);
+            assert_eq!(number, client.chain_info().best_block_number);
+            assert_eq!(Vec::<H256>::new(), client.block(BlockId::Latest).unwrap().parcel_hashes());
+        }
+
+        miner.set_force_sealing(false);
+        let best = client.chain_info().best_block_hash;
+        miner.chain_new_blocks(&*client, &[best], &[], &[best], &[]);
+        assert_eq!(2, client.chain_info().best_block_number);
+    }
+
+    #[test]
+    fn forced_sealing_is_configured_for_a_new_miner() {
+        let spec = Spec::new_test_solo();
+        let options = MinerOptions {
+            force_sealing: true,
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &spec, None);
+        let client = generate_dummy_client_with_miner(&spec, Arc::clone(&miner));
+
+        let best = client.chain_info().best_block_hash;
+        miner.chain_new_blocks(&*client, &[best], &[], &[best], &[]);
+        assert_eq!(1, client.chain_info().best_block_number);
+    }
+
+    #[test]
+    fn reseals_within_the_min_period_are_postponed_until_it_passes() {
+        let spec = Spec::new_test_solo();
+        let options = MinerOptions {
+            reseal_min_period: Duration::from_secs(2),
+            reseal_max_period: Duration::from_secs(10),
+            force_sealing: true,
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &spec, None);
+        let client = generate_dummy_client_with_miner(&spec, Arc::clone(&miner));
+        let start = Instant::now();
+
+        miner.update_sealing_at(&*client, start);
+        assert_eq!(1, client.chain_info().best_block_number);
+
+        miner.update_sealing_at(&*client, start + Duration::from_secs(1));
+        assert_eq!(1, client.chain_info().best_block_number);
+        miner.reseal_pending_work_at(&*client, start + Duration::from_secs(1));
+        assert_eq!(1, client.chain_info().best_block_number);
+
+        // The timer doesn't wait for the max period.
+        miner.reseal_pending_work_at(&*client, start + Duration::from_secs(2));
+        assert_eq!(2, client.chain_info().best_block_number);
+        // Nothing is postponed anymore.
+        miner.reseal_pending_work_at(&*client, start + Duration::from_secs(30));
+        assert_eq!(2, client.chain_info().best_block_number);
+    }
+
+    #[test]
+    fn status_has_the_last_sealed_block() {
+        let spec = Spec::new_test_solo();
+        let options = MinerOptions {
+            force_sealing: true,
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &spec, None);
+        let client = generate_dummy_client_with_miner(&spec, Arc::clone(&miner));
+        let status = miner.status();
+        assert_eq!(None, status.last_sealed_block);
+        assert!(status.sealing_enabled);
+        assert!(!status.sealing_in_progress);
+
+        miner.update_sealing(&*client);
+        let header = client.block_header(BlockId::Latest).unwrap().decode();
+        assert_eq!(1, header.number());
+        let status = miner.status();
+        assert_eq!(
+            Some(SealedBlockInfo {
+                hash: header.hash(),
+                number: 1,
+                timestamp: header.timestamp(),
+            }),
+            status.last_sealed_block
+        );
+        assert!(!status.sealing_in_progress);
+    }
+
+    #[test]
+    fn work_package_is_produced_only_if_the_engine_does_not_seal_internally() {
+        assert!(!Miner::new(Default::default(), &Spec::new_test_solo(), None).can_produce_work_package());
+        assert!(Miner::new(Default::default(), &Spec::new_test(), None).can_produce_work_package());
+    }
+
+    #[test]
+    fn miner_is_currently_sealing_only_while_sealing() {
+        let miner = Miner::new(Default::default(), &Spec::new_test_solo(), None);
+        assert!(!miner.is_currently_sealing());
+        assert!(miner.seal_exclusively(|| miner.is_currently_sealing()));
+        assert!(!miner.is_currently_sealing());
+    }
+
+    #[test]
+    fn miner_is_not_sealing_after_a_sealing_panics() {
+        let miner = Miner::new(Default::default(), &Spec::new_test_solo(), None);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| miner.seal_exclusively(|| panic!("The sealing fails"))));
+        assert!(result.is_err());
+        assert!(!miner.is_currently_sealing());
+    }
+
+    #[test]
+    fn sealing_requested_while_sealing_runs_after_it() {
+        let spec = Spec::new_test_solo();
+        let options = MinerOptions {
+            reseal_min_period: Duration::from_secs(0),
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &spec, None);
+        let client = generate_dummy_client_with_miner(&spec, Arc::clone(&miner));
+        miner.set_block_parcels_limit(Some(1));
+        let funded = funded_keypair();
+        {
+            let fetch_account = |a: &Address| AccountDetails {
+                nonce: client.latest_nonce(a),
+                balance: client.latest_balance(a),
+            };
+            let mut mem_pool = miner.mem_pool.write();
+            for nonce in 0..2 {
+                let parcel = Parcel {
+                    nonce: U256::from(nonce),
+                    fee: U256::from(10),
+                    network_id: spec.params().network_id,
+                    action: Action::ChangeShardState {
+                        transactions: vec![],
+                    },
+                }.sign(funded.private());
+                mem_pool.add(parcel, ParcelOrigin::Local, 0, &fetch_account).unwrap();
+            }
+        }
+
+        // Importing the first block requests the sealing of the second parcel on the new head.
+        miner.update_sealing(&*client);
+        assert_eq!(2, client.chain_info().best_block_number);
+        assert_eq!(Vec::<SignedParcel>::new(), miner.ready_parcels());
+        assert!(!miner.is_currently_sealing());
+    }
+
+    #[test]
+    fn reseal_after_the_min_period_is_not_postponed() {
+        let spec = Spec::new_test_solo();
+        let options = MinerOptions {
+            reseal_min_period: Duration::from_secs(2),
+            force_sealing: true,
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &spec, None);
+        let client = generate_dummy_client_with_miner(&spec, Arc::clone(&miner));
+        let start = Instant::now();
+
+        miner.update_sealing_at(&*client, start);
+        miner.update_sealing_at(&*client, start + Duration::from_secs(2));
+        assert_eq!(2, client.chain_info().best_block_number);
+    }
+
+    #[test]
+    fn pending_state_has_the_own_parcels_applied() {
+        let client = TestBlockChainClient::new();
+        let funded = funded_keypair();
+        client.set_balance(funded.address(), 100.into());
+        let receiver = Random.generate().unwrap().address();
+        let payment = Parcel {
+            nonce: U256::zero(),
+            fee: U256::from(10),
+            network_id: client.spec.params().network_id,
+            action: Action::Payment {
+                receiver,
+                value: U256::from(5),
+            },
+        }.sign(funded.private());
+        assert!(client.miner.pending_state(&client).is_none(), "Nothing is prepared yet");
+
+        client.miner.import_own_parcel(&client, payment).unwrap();
+        let state = client.miner.pending_state(&client).unwrap();
+        assert_eq!(U256::from(85), state.balance(&funded.address()).unwrap());
+        assert_eq!(U256::from(1), state.nonce(&funded.address()).unwrap());
+        assert_eq!(U256::from(5), state.balance(&receiver).unwrap());
+        assert_eq!(U256::from(100), client.latest_balance(&funded.address()));
+        assert_eq!(U256::zero(), client.latest_nonce(&funded.address()));
+
+        // The pending block is prepared again once the mem pool changes.
+        let other = keypair_with_balance(&client, 100);
+        client.miner.import_external_parcels(&client, vec![parcel(&client, &other, 0).into()]);
+        let state = client.miner.pending_state(&client).unwrap();
+        assert_eq!(U256::from(1), state.nonce(&funded.address()).unwrap());
+        assert_eq!(U256::from(1), state.nonce(&other.address()).unwrap());
+        // ...and when the chain head moves past it.
+        client.add_blocks(1, 0);
+        let state = client.miner.pending_state(&client).unwrap();
+        assert_eq!(U256::from(1), state.nonce(&other.address()).unwrap());
+        let best_block_hash = client.chain_info().best_block_hash;
+        let sealing_work = client.miner.sealing_work.lock();
+        assert_eq!(&best_block_hash, sealing_work.queue.peek_last_ref().unwrap().block().header().parent_hash());
+    }
+
+    #[test]
+    fn status_counts_the_parcels_in_the_pending_block() {
+        let client = TestBlockChainClient::new();
+        let funded = funded_keypair();
+        client.set_balance(funded.address(), 100.into());
+        let parcels = (0..3).map(|nonce| parcel(&client, &funded, nonce).into()).collect();
+        for result in client.miner.import_external_parcels(&client, parcels) {
+            assert_eq!(ParcelImportResult::Current, result.unwrap());
+        }
+        assert_eq!(0, client.miner.status().parcels_in_pending_block);
+
+        assert!(client.miner.prepare_work_sealing(&client));
+        assert_eq!(3, client.miner.status().parcels_in_pending_block);
+
+        client.add_blocks(1, 0);
+        let best = client.chain_info().best_block_hash;
+        client.miner.chain_new_blocks(&client, &[best], &[], &[best], &[]);
+        assert_eq!(0, client.miner.status().parcels_in_pending_block);
+
+        assert!(client.miner.prepare_work_sealing(&client));
+        assert_eq!(3, client.miner.status().parcels_in_pending_block);
+    }
+
+    #[test]
+    fn fee_estimate_is_never_below_the_minimal_fee() {
+        let client = TestBlockChainClient::new();
+        let keypair = keypair_with_balance(&client, 1_000);
+        let miner = Miner::new(Default::default(), &client.spec, None);
+        miner.set_minimal_fee(5.into());
+        assert_eq!(U256::from(5), miner.fee_estimate(50));
+
+        let parcels = (0..4).map(|nonce| parcel(&client, &keypair, nonce).into()).collect();
+        assert!(miner.import_external_parcels(&client, parcels).iter().all(Result::is_ok));
+        assert_eq!(U256::from(10), miner.fee_estimate(50));
+
+        miner.set_minimal_fee(20.into());
+        assert_eq!(U256::from(20), miner.fee_estimate(50));
+    }
+
+    #[test]
+    fn raising_the_minimal_fee_culls_only_the_external_parcels_below_it() {
+        let client = TestBlockChainClient::new();
+        let ours = keypair_with_balance(&client, 1_000);
+        let theirs = keypair_with_balance(&client, 1_000);
+        let rich = keypair_with_balance(&client, 1_000);
+        let options = MinerOptions {
+            reseal_on_own_parcel: false,
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &client.spec, None);
+
+        let own_parcels = (0..2).map(|nonce| parcel(&client, &ours, nonce)).collect();
+        assert!(miner.import_own_parcels(&client, own_parcels).iter().all(Result::is_ok));
+        let cheap_parcels = (0..3).map(|nonce| parcel(&client, &theirs, nonce).into()).collect();
+        assert!(miner.import_external_parcels(&client, cheap_parcels).iter().all(Result::is_ok));
+        let expensive = Parcel {
+            fee: U256::from(30),
+            ..parcel(&client, &rich, 0).as_unsigned().clone()
+        }.sign(rich.private());
+        assert!(miner.import_external_parcels(&client, vec![expensive.clone().into()])[0].is_ok());
+        assert_eq!(6, miner.status().parcels_in_pending_queue);
+
+        assert_eq!(3, miner.set_minimal_fee_and_cull(20.into(), &client));
+        assert_eq!(U256::from(20), miner.minimal_fee());
+        let pending = miner.ready_parcels();
+        assert_eq!(3, pending.len());
+        assert!(pending.iter().all(|parcel| parcel.sender() != theirs.address()));
+        assert!(pending.contains(&expensive));
+
+        assert_eq!(0, miner.set_minimal_fee_and_cull(20.into(), &client));
+    }
+
+    #[test]
+    fn status_counts_the_parcels_by_origin() {
+        let client = TestBlockChainClient::new();
+        let ours = Random.generate().unwrap();
+        let theirs = Random.generate().unwrap();
+        client.set_balance(ours.address(), 1_000.into());
+        client.set_balance(theirs.address(), 1_000.into());
+        let options = MinerOptions {
+            reseal_on_own_parcel: false,
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &client.spec, None);
+
+        let own_parcels = (0..2).map(|nonce| parcel(&client, &ours, nonce)).collect();
+        assert!(miner.import_own_parcels(&client, own_parcels).iter().all(Result::is_ok));
+        let external_parcels = (0..3).map(|nonce| parcel(&client, &theirs, nonce).into()).collect();
+        assert!(miner.import_external_parcels(&client, external_parcels).iter().all(Result::is_ok));
+
+        let status = miner.status();
+        assert_eq!(
+            ParcelsByOrigin {
+                local: 2,
+                external: 3,
+                retracted: 0,
+            },
+            status.mem_pool_parcels_by_origin
+        );
+        assert_eq!(5, status.parcels_in_pending_queue);
+    }
+
+    #[test]
+    fn external_parcels_are_checked_against_the_account_of_the_sender() {
+        let client = TestBlockChainClient::new();
+        let rich = Random.generate().unwrap();
+        client.set_nonce(rich.address(), 3.into());
+        client.set_balance(rich.address(), 1_000.into());
+        let poor = Random.generate().unwrap();
+
+        let parcels = vec![
+            parcel(&client, &rich, 2).into(),
+            parcel(&client, &rich, 4).into(),
+            parcel(&client, &rich, 3).into(),
+            parcel(&client, &poor, 0).into(),
+        ];
+        let mut results = client.miner.import_external_parcels(&client, parcels).into_iter();
+
+        match results.next().unwrap() {
+            Err(Error::Parcel(ParcelError::Old {
+                ..
+            })) => {}
+            result => panic!("The parcel below the nonce must be rejected: {:?}", result),
+        }
+        assert_eq!(ParcelImportResult::Future, results.next().unwrap().unwrap());
+        assert_eq!(ParcelImportResult::Current, results.next().unwrap().unwrap());
+        match results.next().unwrap() {
+            Err(Error::Parcel(ParcelError::InsufficientBalance {
+                ..
+            })) => {}
+            result => panic!("The parcel of the sender without balance must be rejected: {:?}", result),
+        }
+        assert_eq!(2, client.miner.ready_parcels().len());
+    }
+
+    #[test]
+    fn removed_parcel_leaves_the_later_parcels_of_the_sender_in_future() {
+        let client = TestBlockChainClient::new();
+        let keypair = keypair_with_balance(&client, 1_000);
+        let parcels = (0..3).map(|nonce| parcel(&client, &keypair, nonce)).collect::<Vec<_>>();
+        let unverified = parcels.iter().cloned().map(Into::into).collect();
+        for result in client.miner.import_external_parcels(&client, unverified) {
+            assert_eq!(ParcelImportResult::Current, result.unwrap());
+        }
+        assert_eq!(3, client.miner.status().parcels_in_pending_queue);
+
+        let hash = parcels[1].hash();
+        assert_eq!(Some(parcels[1].clone()), client.miner.remove_parcel(&client, &hash, SubsequentParcels::Demote));
+        let status = client.miner.status();
+        assert_eq!(1, status.parcels_in_pending_queue);
+        assert_eq!(1, status.parcels_in_future_queue);
+        assert_eq!(vec![parcels[2].clone()], client.miner.future_parcels());
+
+        assert_eq!(None, client.miner.remove_parcel(&client, &hash, SubsequentParcels::Demote));
+        let demoted = client.miner.parcel(&parcels[2].hash()).unwrap();
+        assert_eq!(parcels[2], demoted.parcel);
+        assert!(demoted.is_future);
+        assert!(!client.miner.parcel(&parcels[0].hash()).unwrap().is_future);
+    }
+
+    #[test]
+    fn relayed_parcels_of_local_addresses_are_imported_as_local() {
+        let client = TestBlockChainClient::new();
+        let ours = keypair_with_balance(&client, 1_000);
+        let foreign = keypair_with_balance(&client, 1_000);
+        let other = keypair_with_balance(&client, 1_000);
+        client.miner.add_local_address(ours.address());
+        client.miner.set_parcels_limit(1);
+
+        let foreign_parcel = parcel(&client, &foreign, 0);
+        let results = client.miner.import_external_parcels(&client, vec![foreign_parcel.clone().into()]);
+        assert_eq!(ParcelImportResult::Current, *results[0].as_ref().unwrap());
+
+        // An external parcel cannot evict another one paying the same fee.
+        let results = client.miner.import_external_parcels(&client, vec![parcel(&client, &other, 0).into()]);
+        assert!(results[0].is_err());
+        assert_eq!(vec![foreign_parcel], client.miner.ready_parcels());
+
+        let our_parcel = parcel(&client, &ours, 0);
+        let results = client.miner.import_external_parcels(&client, vec![our_parcel.clone().into()]);
+        assert_eq!(ParcelImportResult::Current, *results[0].as_ref().unwrap());
+        assert_eq!(vec![our_parcel], client.miner.ready_parcels());
+    }
+
+    #[test]
+    fn parcels_of_a_banned_address_are_removed_and_rejected() {
+        let client = TestBlockChainClient::new();
+        let banned = Random.generate().unwrap();
+        let other = Random.generate().unwrap();
+        client.set_balance(banned.address(), 1_000.into());
+        client.set_balance(other.address(), 1_000.into());
+
+        let queued: Vec<UnverifiedParcel> =
+            [0, 1, 3].iter().map(|&nonce| parcel(&client, &banned, nonce).into()).collect();
+        client.miner.import_external_parcels(&client, queued);
+        let other_parcel = parcel(&client, &other, 0);
+        client.miner.import_external_parcels(&client, vec![other_parcel.clone().into()]);
+        assert_eq!(3, client.miner.ready_parcels().len());
+        assert_eq!(1, client.miner.future_parcels().len());
+
+        client.miner.ban_address(banned.address());
+        assert_eq!(vec![banned.address()], client.miner.banned_addresses());
+        assert_eq!(vec![other_parcel], client.miner.ready_parcels());
+        assert_eq!(Vec::<SignedParcel>::new(), client.miner.future_parcels());
+        let removed = client.miner.mem_pool_metrics(&client).removed;
+        assert_eq!(Some(&3), removed.get(&RemovalReason::Banned));
+
+        let results = client.miner.import_external_parcels(&client, vec![parcel(&client, &banned, 0).into()]);
+        match results[0] {
+            Err(Error::Parcel(ParcelError::SenderBanned)) => {}
+            ref result => panic!("The parcel of the banned address is imported: {:?}", result),
+        }
+        assert_eq!(1, client.miner.ready_parcels().len());
+        let rejected = client.miner.mem_pool_metrics(&client).rejected;
+        assert_eq!(Some(&1), rejected.get(&RejectionReason::SenderBanned));
+        assert_eq!(None, rejected.get(&RejectionReason::NotAllowed));
+
+        assert!(client.miner.unban_address(&banned.address()));
+        assert!(!client.miner.unban_address(&banned.address()));
+        assert_eq!(Vec::<Address>::new(), client.miner.banned_addresses());
+        let results = client.miner.import_external_parcels(&client, vec![parcel(&client, &banned, 0).into()]);
+        assert_eq!(ParcelImportResult::Current, *results[0].as_ref().unwrap());
+    }
+
+    #[test]
+    fn own_parcels_are_imported_in_a_batch() {
+        let client = TestBlockChainClient::new();
+        let keypair = keypair_with_balance(&client, 1_000_000);
+        let options = MinerOptions {
+            reseal_on_own_parcel: false,
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &client.spec, None);
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        {
+            let batches = Arc::clone(&batches);
+            miner.add_parcels_listener(Box::new(move |hashes| batches.lock().push(hashes.len())));
+        }
+
+        let mut parcels = (0..100).map(|nonce| parcel(&client, &keypair, nonce)).collect::<Vec<_>>();
+        let invalid = Parcel {
+            network_id: client.spec.params().network_id + 1,
+            ..parcels[50].as_unsigned().clone()
+        }.sign(keypair.private());
+        parcels[50] = invalid;
+        let results = miner.import_own_parcels(&client, parcels);
+
+        assert_eq!(100, results.len());
+        assert!(results[..50].iter().all(|result| *result.as_ref().unwrap() == ParcelImportResult::Current));
+        assert!(results[50].is_err());
+        assert!(results[51..].iter().all(|result| *result.as_ref().unwrap() == ParcelImportResult::Future));
+        // The whole batch is inserted at once.
+        assert_eq!(vec![99], *batches.lock());
+    }
+
+    #[test]
+    fn local_parcels_track_the_replaced_and_dropped_own_parcels() {
+        let client = TestBlockChainClient::new();
+        let keypair = keypair_with_balance(&client, 1_000);
+        let other = keypair_with_balance(&client, 1_000);
+        let options = MinerOptions {
+            reseal_on_own_parcel: false,
+            mem_pool_memory_limit: Some(parcel(&client, &keypair, 0).rlp_bytes().len()),
+            ..Default::default()
+        };
+        let miner = Miner::new(options, &client.spec, None);
+
+        let first = parcel(&client, &keypair, 0);
+        miner.import_own_parcel(&client, first.clone()).unwrap();
+        assert_eq!(Some(&LocalParcelStatus::Pending), miner.local_parcels().get(&first.hash()));
+
+        let replacing = Parcel {
+            fee: U256::from(20),
+            ..first.as_unsigned().clone()
+        }.sign(keypair.private());
+        miner.import_own_parcel(&client, replacing.clone()).unwrap();
+        let local_parcels = miner.local_parcels();
+        assert_eq!(
+            Some(&LocalParcelStatus::Replaced(first.clone(), U256::from(20), replacing.hash())),
+            local_parcels.get(&first.hash())
+        );
+        assert_eq!(Some(&LocalParcelStatus::Pending), local_parcels.get(&replacing.hash()));
+
+        // The cheaper one is dropped out of the memory limit.
+        let expensive = Parcel {
+            fee: U256::from(30),
+            ..parcel(&client, &other, 0).as_unsigned().clone()
+        }.sign(other.private());
+        miner.import_own_parcel(&client, expensive.clone()).unwrap();
+        let local_parcels = miner.local_parcels();
+        assert_eq!(Some(&LocalParcelStatus::Dropped(replacing.clone())), local_parcels.get(&replacing.hash()));
+        assert_eq!(Some(&LocalParcelStatus::Pending), local_parcels.get(&expensive.hash()));
+
+        client.set_nonce(other.address(), 1.into());
+        miner.chain_new_blocks(&client, &[], &[], &[], &[]);
+        assert_eq!(Some(&LocalParcelStatus::Mined(expensive.clone())), miner.local_parcels().get(&expensive.hash()));
+        assert_eq!(3, miner.local_parcels().len());
+    }
+
+    #[test]
+    fn listeners_see_only_the_inserted_parcels() {
+        let client = TestBlockChainClient::new();
+        let keypair = keypair_with_balance(&client, 1_000);
+        client.set_nonce(keypair.address(), 1.into());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        {
+            let seen = Arc::clone(&seen);
+            let miner = Arc::downgrade(&client.miner);
+            client.miner.add_parcels_listener(Box::new(move |hashes| {
+                // The mem pool must not be locked while the listeners are called.
+                miner.upgrade().unwrap().status();
+                seen.lock().extend_from_slice(hashes);
+            }));
+        }
+
+        let parcels = (0..3).map(|nonce| parcel(&client, &keypair, nonce)).collect::<Vec<_>>();
+        let unverified = vec![parcels[0].clone().into(), parcels[1].clone().into()];
+        let results = client.miner.import_external_parcels(&client, unverified);
+        assert!(results[0].is_err());
+        assert!(client.miner.import_own_parcel(&client, parcels[0].clone()).is_err());
+        client.miner.import_own_parcel(&client, parcels[2].clone()).unwrap();
+
+        assert_eq!(vec![parcels[1].hash(), parcels[2].hash()], *seen.lock());
+    }
+
+    #[test]
+    fn local_parcels_are_recovered_from_the_journal() {
+        let client = TestBlockChainClient::new();
+        let keypair = keypair_with_balance(&client, 1_000);
+        let path = env::temp_dir().join(format!("codechain-local-parcels-{}", H256::random()));
+        let options = || MinerOptions {
+            reseal_on_own_parcel: false,
+            local_parcels_journal: Some(path.clone()),
+            ..Default::default()
+        };
+
+        {
+            let miner = Miner::new(options(), &client.spec, None);
+            for nonce in &[0, 1, 3] {
+                miner.import_own_parcel(&client, parcel(&client, &keypair, *nonce)).unwrap();
+            }
+        }
+
+        let miner = Miner::new(options(), &client.spec, None);
+        assert_eq!(0, miner.status().parcels_in_pending_queue);
+        assert_eq!(3, miner.recover_local_parcels(&client));
+        let status = miner.status();
+        assert_eq!(2, status.parcels_in_pending_queue);
+        assert_eq!(1, status.parcels_in_future_queue);
+
+        // The parcel mined while the node was down is discarded.
+        client.set_nonce(keypair.address(), 1.into());
+        let miner = Miner::new(options(), &client.spec, None);
+        assert_eq!(2, miner.recover_local_parcels(&client));
+        assert_eq!(2, LocalParcelsJournal::new(path.clone()).load().len());
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mem_pool_is_recovered_from_the_journal() {
+        let client = TestBlockChainClient::new();
+        let senders: Vec<KeyPair> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        for sender in &senders {
+            client.set_balance(sender.address(), 1_000.into());
+        }
+        let db: Arc<KeyValueDB> = Arc::new(kvdb_memorydb::create(NUM_COLUMNS.unwrap_or(0)));
+        let options = || MinerOptions {
+            reseal_on_own_parcel: false,
+            mem_pool_journal: true,
+            ..Default::default()
+        };
+
+        let (pending, future) = {
+            let miner = Miner::new(options(), &client.spec, None);
+            miner.open_mem_pool_journal(Arc::clone(&db));
+            let own_parcels = (0..10).map(|nonce| parcel(&client, &senders[0], nonce)).collect();
+            assert!(miner.import_own_parcels(&client, own_parcels).iter().all(Result::is_ok));
+            let mut external_parcels = Vec::new();
+            for sender in &senders[1..] {
+                external_parcels.extend((0..12).map(|nonce| parcel(&client, sender, nonce).into()));
+            }
+            // The parcels after the gap wait in the future queue.
+            external_parcels.extend((15..19).map(|nonce| parcel(&client, &senders[3], nonce).into()));
+            assert!(miner.import_external_parcels(&client, external_parcels).iter().all(Result::is_ok));
+            // The removed parcels are not recovered.
+            assert_eq!(12, miner.clear_sender(&senders[2].address()).len());
+            let status = miner.status();
+            assert_eq!(34, status.parcels_in_pending_queue);
+            assert_eq!(4, status.parcels_in_future_queue);
+            (miner.ready_parcels(), miner.future_parcels())
+        };
+
+        // The parcels mined while the node was down are discarded.
+        client.set_nonce(senders[1].address(), 5.into());
+        let miner = Miner::new(options(), &client.spec, None);
+        miner.open_mem_pool_journal(Arc::clone(&db));
+        assert_eq!(0, miner.status().parcels_in_pending_queue);
+        assert_eq!(33, miner.recover_mem_pool(&client));
+
+        let sorted = |parcels: Vec<SignedParcel>| {
+            let mut hashes: Vec<H256> = parcels.iter().map(|parcel| parcel.hash()).collect();
+            hashes.sort();
+            hashes
+        };
+        let expected =
+            pending.into_iter().filter(|parcel| parcel.sender() != senders[1].address() || parcel.nonce >= 5.into());
+        assert_eq!(sorted(expected.collect()), sorted(miner.ready_parcels()));
+        assert_eq!(sorted(future), sorted(miner.future_parcels()));
+        // The discarded parcels are deleted from the journal.
+        assert_eq!(33, MemPoolJournal::new(db).load().len());
+    }
+
+    #[test]
+    fn mined_parcels_leave_the_pool_and_promote_the_next_ones() {
+        let client = TestBlockChainClient::new();
+        let keypair = keypair_with_balance(&client, 1_000);
+        let parcels = vec![parcel(&client, &keypair, 0), parcel(&client, &keypair, 2)];
+        let unverified = parcels.iter().cloned().map(Into::into).collect();
+        client.miner.import_external_parcels(&client, unverified);
+        assert_eq!(vec![parcels[0].clone()], client.miner.ready_parcels());
+        assert_eq!(vec![parcels[1].clone()], client.miner.future_parcels());
+
+        // The block mining the parcel of nonce 0 and another one of nonce 1 is imported.
+        client.add_blocks(1, 0);
+        client.set_nonce(keypair.address(), 2.into());
+        let best = client.chain_info().best_block_hash;
+        // The body of a retracted block may not be available.
+        let unknown = H256::random();
+        client.miner.chain_new_blocks(&client, &[best], &[], &[best], &[unknown]);
+
+        assert_eq!(vec![parcels[1].clone()], client.miner.ready_parcels());
+        assert_eq!(Vec::<SignedParcel>::new(), client.miner.future_parcels());
+    }
+
+    #[test]
+    fn waiters_are_woken_by_concurrent_imports() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let keypair = keypair_with_balance(&client, 1_000_000);
+        let long_enough = Duration::from_secs(10);
+
+        for nonce in 0..50 {
+            let generation = client.miner.mem_pool_generation();
+            let barrier = Arc::new(Barrier::new(3));
+            let waiters = (0..2)
+                .map(|_| {
+                    let client = Arc::clone(&client);
+                    let barrier = Arc::clone(&barrier);
+                    thread::spawn(move || {
+                        barrier.wait();
+                        let started = Instant::now();
+                        (client.miner.wait_for_change(generation, long_enough), started.elapsed())
+                    })
+                })
+                .collect::<Vec<_>>();
+            barrier.wait();
+            let parcels = vec![parcel(&client, &keypair, nonce).into()];
+            let result = client.miner.import_external_parcels(&*client, parcels).pop().unwrap();
+            assert_eq!(ParcelImportResult::Current, result.unwrap());
+
+            for waiter in waiters {
+                let (woken_generation, waited) = waiter.join().unwrap();
+                assert!(woken_generation > generation);
+                assert!(waited < long_enough, "A waiter missed the import of the parcel {}", nonce);
+            }
+        }
+        assert_eq!(client.miner.mem_pool_generation(), client.miner.status().mem_pool_generation);
+    }
+
+    #[test]
+    fn readers_are_not_blocked_while_the_imported_parcels_are_verified() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let keypair = keypair_with_balance(&client, 1_000_000);
+        let parcels: Vec<UnverifiedParcel> = (0..500).map(|nonce| parcel(&client, &keypair, nonce).into()).collect();
+
+        let is_done = Arc::new(AtomicBool::new(false));
+        let barrier = Arc::new(Barrier::new(2));
+        let reader = {
+            let client = Arc::clone(&client);
+            let is_done = Arc::clone(&is_done);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                let mut reads = 0;
+                while !is_done.load(Ordering::SeqCst) {
+                    client.miner.ready_parcels();
+                    reads += 1;
+                }
+                reads
+            })
+        };
+        barrier.wait();
+        let results = client.miner.import_external_parcels(&*client, parcels);
+        is_done.store(true, Ordering::SeqCst);
+        let reads = reader.join().unwrap();
+
+        assert!(results.into_iter().all(|result| result.is_ok()));
+        assert_eq!(500, client.miner.ready_parcels().len());
+        // If the parcels were verified under the lock, the reader would complete only the reads before and after it.
+        assert!(reads > 10, "Only {} reads are completed during the import", reads);
+    }
+
+    #[test]
+    fn moving_the_chain_head_wakes_the_waiters() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let generation = client.miner.mem_pool_generation();
+        let waiter = {
+            let client = Arc::clone(&client);
+            thread::spawn(move || client.miner.wait_for_change(generation, Duration::from_secs(10)))
+        };
+        thread::sleep(Duration::from_millis(10));
+        let best = client.chain_info().best_block_hash;
+        client.miner.chain_new_blocks(&*client, &[best], &[], &[best], &[]);
+        assert_eq!(generation, waiter.join().unwrap());
+    }
 }
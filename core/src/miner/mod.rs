@@ -14,28 +14,54 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod changes;
+mod journal;
 mod local_parcels;
 mod mem_pool;
 mod miner;
+mod ranked_set;
+mod reseal;
 mod sealing_queue;
 
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::Duration;
+
 use ctypes::{Address, Bytes, H256, U256};
 
+pub use self::local_parcels::Status as LocalParcelStatus;
+pub use self::mem_pool::{
+    DumpedParcel, MemPoolDump, MemPoolMetrics, ParcelOrigin, ParcelPage, PendingPosition, RejectionReason,
+    RemovalReason, SenderDump, PARCEL_AGE_BUCKETS,
+};
 pub use self::miner::{Miner, MinerOptions};
 use super::account_provider::SignError;
 use super::client::{AccountData, BlockChain, BlockProducer, ImportSealedBlock, MiningBlockChainClient};
 use super::error::Error;
-use super::parcel::{SignedParcel, UnverifiedParcel};
+use super::header::Header;
+use super::parcel::{ActionKind, SignedParcel, UnverifiedParcel};
 use super::state::TopStateInfo;
+use super::types::BlockNumber;
 
 /// Miner client API
 pub trait MinerService: Send + Sync {
     /// Type representing chain state
     type State: TopStateInfo + 'static;
 
+    /// Returns the state of the pending block, i.e. the latest state with the ready parcels applied.
+    /// The pending block is prepared again if it is not built on the best block of `chain`,
+    /// or the mem pool changed after it was prepared. Returns `None` if nothing is prepared.
+    fn pending_state<C: AccountData + BlockChain + BlockProducer>(&self, chain: &C) -> Option<Self::State>;
+
     /// Returns miner's status.
     fn status(&self) -> MinerStatus;
 
+    /// Returns the counters of the decisions the mem pool made, with the ages of the parcels in it.
+    fn mem_pool_metrics<C: BlockChain>(&self, chain: &C) -> MemPoolMetrics;
+
+    /// Returns all the parcels in the mem pool, taken at once.
+    fn mem_pool_dump(&self) -> MemPoolDump;
+
     /// Get the author that we will seal blocks as.
     fn author(&self) -> Address;
 
@@ -46,31 +72,93 @@ pub trait MinerService: Send + Sync {
     fn extra_data(&self) -> Bytes;
 
     /// Set the extra_data that we will seal blocks with.
-    fn set_extra_data(&self, extra_data: Bytes);
+    /// Fails if it is longer than the engine allows in a block header.
+    fn set_extra_data(&self, extra_data: Bytes) -> Result<(), Error>;
 
     /// Set info necessary to sign consensus messages.
+    /// Fails if the account provider of the miner doesn't have the account or `password` doesn't decrypt it.
     fn set_engine_signer(&self, address: Address, password: String) -> Result<(), SignError>;
 
     /// Get current minimal fee for parcels accepted to queue.
     fn minimal_fee(&self) -> U256;
 
     /// Set minimal fee of parcel to be accepted for mining.
+    /// It applies to the kinds of actions without their own minimal fees.
     fn set_minimal_fee(&self, min_fee: U256);
 
+    /// Get the minimal fee for the parcels with the actions of `kind`.
+    fn minimal_fee_for(&self, kind: ActionKind) -> U256;
+
+    /// Set the minimal fee for the parcels with the actions of `kind`. `None` makes them follow the minimal fee.
+    fn set_minimal_fee_for(&self, kind: ActionKind, min_fee: Option<U256>);
+
+    /// Returns the fee at `percentile` (0 - 100) among the fees of the pending parcels,
+    /// which is a hint of the fee for a new parcel. It is never below the minimal fee,
+    /// and it is the minimal fee if there are only a few pending parcels.
+    fn fee_estimate(&self, percentile: u8) -> U256;
+
+    /// Get the highest fee of parcels accepted to queue, `None` if there is no limit.
+    fn max_fee(&self) -> Option<U256>;
+
+    /// Set the highest fee of parcels accepted to queue, both own and external. `None` removes the limit.
+    fn set_max_fee(&self, max_fee: Option<U256>);
+
+    /// Remove external parcels paying less than the minimal fee from the queue.
+    /// Returns the number of removed parcels.
+    fn remove_below_minimal_fee<C: AccountData>(&self, chain: &C) -> usize;
+
+    /// Set minimal fee and remove the queued external parcels paying less than it, without letting
+    /// any import in between. Returns the number of removed parcels.
+    fn set_minimal_fee_and_cull<C: AccountData>(&self, min_fee: U256, chain: &C) -> usize;
+
+    /// Get the limit of the number of parcels in a block, `None` if only the spec limits it.
+    fn block_parcels_limit(&self) -> Option<usize>;
+
+    /// Limit the number of parcels in a block below the limit of the spec. `None` removes the limit.
+    /// The parcels left out stay in the queue for the next blocks.
+    fn set_block_parcels_limit(&self, limit: Option<usize>);
+
     /// Get current parcels limit in queue.
     fn parcels_limit(&self) -> usize;
 
-    /// Set maximal number of parcels kept in the queue (both current and future).
+    /// Set maximal number of parcels ready to be mined in the queue.
+    /// The parcels over the limit wait in the future queue.
     fn set_parcels_limit(&self, limit: usize);
 
+    /// Get the limit of parcels waiting for their nonces in the queue.
+    fn future_parcels_limit(&self) -> usize;
+
+    /// Set maximal number of parcels waiting for their nonces in the queue.
+    fn set_future_parcels_limit(&self, limit: usize);
+
+    /// Get the number of parcels a sender can have in the queue.
+    fn max_parcels_per_sender(&self) -> usize;
+
+    /// Set the number of external parcels a sender can have in the queue (both current and future).
+    fn set_max_parcels_per_sender(&self, limit: usize);
+
     /// Called when blocks are imported to chain, updates parcels queue.
     fn chain_new_blocks<C>(&self, chain: &C, imported: &[H256], invalid: &[H256], enacted: &[H256], retracted: &[H256])
     where
         C: AccountData + BlockChain + BlockProducer + ImportSealedBlock;
 
     /// PoW chain - can produce work package
+    /// Returns false if the engine seals internally, so that no external work is ever required.
     fn can_produce_work_package(&self) -> bool;
 
+    /// Returns true while a block is being sealed.
+    fn is_currently_sealing(&self) -> bool;
+
+    /// Makes sure that the work is prepared for the current best block and the current mem pool.
+    /// Returns true if the work changed since the last call.
+    fn prepare_work_sealing<C: AccountData + BlockChain + BlockProducer>(&self, client: &C) -> bool;
+
+    /// Applies `f` to the header of the current work, marking the work as handed out.
+    /// Returns `None` if there's no work.
+    fn map_sealing_work<F, T>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(&Header) -> T;
+
     /// New chain head event. Restart mining operation.
     fn update_sealing<C>(&self, chain: &C)
     where
@@ -87,6 +175,13 @@ pub trait MinerService: Send + Sync {
         parcels: Vec<UnverifiedParcel>,
     ) -> Vec<Result<ParcelImportResult, Error>>;
 
+    /// Imports parcels to mem pool, and returns the result of each parcel with its hash.
+    fn import_external_parcels_detailed<C: MiningBlockChainClient>(
+        &self,
+        client: &C,
+        parcels: Vec<UnverifiedParcel>,
+    ) -> Vec<ParcelImportOutcome>;
+
     /// Imports own (node owner) parcel to mem pool.
     fn import_own_parcel<C: MiningBlockChainClient>(
         &self,
@@ -94,11 +189,74 @@ pub trait MinerService: Send + Sync {
         parcel: SignedParcel,
     ) -> Result<ParcelImportResult, Error>;
 
+    /// Imports own parcels to mem pool under a single lock of the mem pool.
+    /// The results are in the order of `parcels`, and a rejected parcel only keeps the parcels depending on its nonce
+    /// in the future queue.
+    fn import_own_parcels<C: MiningBlockChainClient>(
+        &self,
+        chain: &C,
+        parcels: Vec<SignedParcel>,
+    ) -> Vec<Result<ParcelImportResult, Error>>;
+
     /// Get a list of all pending parcels in the mem pool.
     fn ready_parcels(&self) -> Vec<SignedParcel>;
 
+    /// Get the pending parcels with the highest priority, at most `max_count` of them and `max_bytes` in total.
+    fn ready_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel>;
+
+    /// Same as `ready_parcels_with_limit`, but only the parcels imported by `import_own_parcel` are collected.
+    fn ready_local_parcels_with_limit(&self, max_count: usize, max_bytes: usize) -> Vec<SignedParcel>;
+
     /// Get a list of all future parcels.
     fn future_parcels(&self) -> Vec<SignedParcel>;
+
+    /// Get at most `limit` pending parcels in the order of `ready_parcels`, skipping the first `offset` of them.
+    fn ready_parcels_page(&self, offset: usize, limit: usize) -> ParcelPage;
+
+    /// Get at most `limit` future parcels in the order of `future_parcels`, skipping the first `offset` of them.
+    fn future_parcels_page(&self, offset: usize, limit: usize) -> ParcelPage;
+
+    /// Get the own parcels which are in the mem pool or left it recently, with their statuses.
+    fn local_parcels(&self) -> BTreeMap<H256, LocalParcelStatus>;
+
+    /// Finds the parcel of `hash` in the mem pool.
+    fn parcel(&self, hash: &H256) -> Option<PooledParcel>;
+
+    /// Get the parcels of `sender` in the mem pool, in the order of their nonces.
+    fn pending_parcels_from(&self, sender: &Address) -> Vec<PooledParcel>;
+
+    /// Get the highest nonce of the ready parcels of `sender` in the mem pool.
+    /// The next parcel of the sender should take the next nonce; the state nonce if it's `None`.
+    fn last_nonce(&self, sender: &Address) -> Option<U256>;
+
+    /// Removes all parcels of `sender` from the mem pool, e.g. to send them again from the state nonce.
+    /// Returns the hashes of the removed parcels in the order of their nonces.
+    fn clear_sender(&self, sender: &Address) -> Vec<H256>;
+
+    /// Removes the parcel of `hash` from the mem pool.
+    /// Returns the removed parcel, or `None` if there is no such parcel.
+    fn remove_parcel<C: AccountData>(
+        &self,
+        chain: &C,
+        hash: &H256,
+        subsequent: SubsequentParcels,
+    ) -> Option<SignedParcel>;
+
+    /// Get how the parcels in the mem pool are prioritized.
+    fn parcel_ordering(&self) -> OrderingStrategy;
+
+    /// Set how the parcels in the mem pool are prioritized. The parcels in the mem pool already are reordered.
+    fn set_parcel_ordering(&self, ordering: OrderingStrategy);
+
+    /// Describes the nonce gaps which keep the parcels of `sender` in the future queue.
+    fn sender_issues<C: AccountData>(&self, chain: &C, sender: &Address) -> SenderIssues;
+
+    /// Returns a counter which is increased whenever the parcels in the mem pool change.
+    fn mem_pool_generation(&self) -> u64;
+
+    /// Waits until the mem pool generation is not `generation` anymore or the chain head moves,
+    /// at most for `timeout`. Returns the mem pool generation.
+    fn wait_for_change(&self, generation: u64, timeout: Duration) -> u64;
 }
 
 /// Mining status
@@ -110,6 +268,89 @@ pub struct MinerStatus {
     pub parcels_in_future_queue: usize,
     /// Number of parcels included in currently mined block
     pub parcels_in_pending_block: usize,
+    /// The generation of the mem pool
+    pub mem_pool_generation: u64,
+    /// The size of the RLP encoded parcels in the mem pool
+    pub mem_pool_encoded_size: usize,
+    /// The heap memory used by the parcels in the mem pool
+    pub mem_pool_mem_usage: usize,
+    /// The number of the parcels in the mem pool by their origins
+    pub mem_pool_parcels_by_origin: ParcelsByOrigin,
+    /// The last block sealed by this miner. `None` if it has never sealed a block
+    pub last_sealed_block: Option<SealedBlockInfo>,
+    /// Whether the miner prepares the blocks to seal
+    pub sealing_enabled: bool,
+    /// Whether a block is being sealed and imported now
+    pub sealing_in_progress: bool,
+}
+
+/// The block sealed and imported by the miner
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SealedBlockInfo {
+    /// The hash of the sealed block
+    pub hash: H256,
+    /// The number of the sealed block
+    pub number: BlockNumber,
+    /// The timestamp in the header of the sealed block
+    pub timestamp: u64,
+}
+
+/// The number of parcels by where they came from
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ParcelsByOrigin {
+    /// Imported by this node, or sent by one of the local addresses
+    pub local: usize,
+    /// Received from peers
+    pub external: usize,
+    /// Returned from the retracted blocks
+    pub retracted: usize,
+}
+
+/// The reasons the parcels of a sender are not ready to be mined
+#[derive(Debug, Default, PartialEq)]
+pub struct SenderIssues {
+    /// The account nonce of the sender the issues are found against
+    pub state_nonce: U256,
+    /// The nonces of the ready parcels from the account nonce, which ends at the nonce the mem pool expects next
+    pub ready: Range<U256>,
+    /// The nonces queued after the gaps, in ascending order
+    pub gapped: Vec<U256>,
+    /// The nonces missing between the account nonce and the highest queued nonce, in ascending order
+    pub gaps: Vec<U256>,
+    /// Number of parcels waiting in the future queue for the missing nonces
+    pub stuck: usize,
+    /// The minimal fee of a parcel replacing the lowest stuck parcel, `None` if no parcel is stuck
+    pub replacement_fee: Option<U256>,
+}
+
+/// What happens to the parcels of the same sender with higher nonces when a parcel is removed from the mem pool
+///
+/// They can't be kept among the parcels ready to be mined, because the removed nonce leaves a gap below them.
+/// So they are kept in the future queue, or removed for a sender who is not going to fill the nonce again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubsequentParcels {
+    /// Keep them in the future queue until the nonce is filled again.
+    Demote,
+    /// Remove them as well.
+    Drop,
+}
+
+/// How the parcels ready to be mined are prioritized after their origins and nonces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderingStrategy {
+    /// The parcel paying more fee comes first.
+    Fee,
+    /// The parcel paying more fee for each byte of its RLP encoding comes first.
+    FeePerByte,
+}
+
+/// A parcel in the mem pool
+#[derive(Debug, Clone, PartialEq)]
+pub struct PooledParcel {
+    /// The parcel found in the mem pool
+    pub parcel: SignedParcel,
+    /// Whether the parcel is in the future queue, waiting for the parcels with lower nonces
+    pub is_future: bool,
 }
 
 /// Represents the result of importing parcel.
@@ -120,3 +361,14 @@ pub enum ParcelImportResult {
     /// Parcel was imported to future queue.
     Future,
 }
+
+/// The result of importing a parcel, with the hash of the parcel.
+#[derive(Debug)]
+pub struct ParcelImportOutcome {
+    /// The hash of the imported parcel
+    pub hash: H256,
+    /// Whether the parcel is imported to the current or the future queue, or why it's rejected
+    pub result: Result<ParcelImportResult, Error>,
+    /// Where the parcel is among the pending parcels right after the import, `None` if it's not pending.
+    pub position: Option<PendingPosition>,
+}
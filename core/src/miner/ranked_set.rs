@@ -0,0 +1,278 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::iter::FromIterator;
+
+type Link<T> = Option<Box<Node<T>>>;
+
+struct Node<T> {
+    value: T,
+    /// The heap order of the treap, which keeps it balanced in expectation
+    priority: u64,
+    /// The number of the values in the subtree rooted at this node
+    size: usize,
+    left: Link<T>,
+    right: Link<T>,
+}
+
+impl<T> Node<T> {
+    fn update_size(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+    }
+}
+
+fn size<T>(link: &Link<T>) -> usize {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+/// Splits `link` into the values less than `value` and the rest.
+/// The values equal to `value` go to the first part if `inclusive` is true.
+fn split<T: Ord>(link: Link<T>, value: &T, inclusive: bool) -> (Link<T>, Link<T>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (None, None),
+    };
+    let goes_left = match node.value.cmp(value) {
+        Ordering::Less => true,
+        Ordering::Equal => inclusive,
+        Ordering::Greater => false,
+    };
+    if goes_left {
+        let (left, right) = split(node.right.take(), value, inclusive);
+        node.right = left;
+        node.update_size();
+        (Some(node), right)
+    } else {
+        let (left, right) = split(node.left.take(), value, inclusive);
+        node.left = right;
+        node.update_size();
+        (left, Some(node))
+    }
+}
+
+/// Joins two treaps, all values of `left` being less than the values of `right`.
+fn merge<T>(left: Link<T>, right: Link<T>) -> Link<T> {
+    match (left, right) {
+        (None, right) => right,
+        (left, None) => left,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority > right.priority {
+                left.right = merge(left.right.take(), Some(right));
+                left.update_size();
+                Some(left)
+            } else {
+                right.left = merge(Some(left), right.left.take());
+                right.update_size();
+                Some(right)
+            }
+        }
+    }
+}
+
+/// An ordered set which also tells the rank of a value in `O(log n)`,
+/// since every node counts the values under it.
+pub struct RankedSet<T> {
+    root: Link<T>,
+    /// The state of the xorshift generator for the priorities of the nodes
+    seed: u64,
+}
+
+impl<T: Ord> RankedSet<T> {
+    pub fn new() -> Self {
+        Self {
+            root: None,
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.root = None;
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        let mut link = &self.root;
+        while let Some(ref node) = *link {
+            link = match value.cmp(&node.value) {
+                Ordering::Less => &node.left,
+                Ordering::Equal => return true,
+                Ordering::Greater => &node.right,
+            };
+        }
+        false
+    }
+
+    /// Returns false if the set already has `value`.
+    pub fn insert(&mut self, value: T) -> bool {
+        if self.contains(&value) {
+            return false
+        }
+        let node = Box::new(Node {
+            value,
+            priority: self.next_priority(),
+            size: 1,
+            left: None,
+            right: None,
+        });
+        let (left, right) = split(self.root.take(), &node.value, false);
+        self.root = merge(merge(left, Some(node)), right);
+        true
+    }
+
+    /// Returns false if the set doesn't have `value`.
+    pub fn remove(&mut self, value: &T) -> bool {
+        let (left, rest) = split(self.root.take(), value, false);
+        let (removed, right) = split(rest, value, true);
+        self.root = merge(left, right);
+        removed.is_some()
+    }
+
+    /// The number of the values less than `value`.
+    pub fn rank(&self, value: &T) -> usize {
+        let mut rank = 0;
+        let mut link = &self.root;
+        while let Some(ref node) = *link {
+            link = if node.value < *value {
+                rank += size(&node.left) + 1;
+                &node.right
+            } else {
+                &node.left
+            };
+        }
+        rank
+    }
+
+    pub fn iter(&self) -> Iter<T> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            remaining: self.len(),
+        };
+        iter.push_left_edge(&self.root);
+        iter
+    }
+
+    fn next_priority(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+impl<T: Ord> Default for RankedSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord> FromIterator<T> for RankedSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for value in iter {
+            set.insert(value);
+        }
+        set
+    }
+}
+
+impl<'a, T: Ord> IntoIterator for &'a RankedSet<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterates over the values of a `RankedSet` in ascending order.
+pub struct Iter<'a, T: 'a> {
+    stack: Vec<&'a Node<T>>,
+    remaining: usize,
+}
+
+impl<'a, T> Iter<'a, T> {
+    fn push_left_edge(&mut self, mut link: &'a Link<T>) {
+        while let Some(ref node) = *link {
+            self.stack.push(node);
+            link = &node.left;
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left_edge(&node.right);
+        self.remaining -= 1;
+        Some(&node.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_iterated_in_order() {
+        let set: RankedSet<u32> = [5, 3, 9, 1, 7, 3].iter().cloned().collect();
+        assert_eq!(5, set.len());
+        assert_eq!(vec![1, 3, 5, 7, 9], set.iter().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn insert_and_remove_report_whether_the_set_changed() {
+        let mut set = RankedSet::new();
+        assert!(set.insert(1));
+        assert!(!set.insert(1));
+        assert!(set.contains(&1));
+        assert!(!set.remove(&2));
+        assert!(set.remove(&1));
+        assert!(!set.contains(&1));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn rank_is_the_number_of_the_smaller_values() {
+        let mut set: RankedSet<u32> = (0..1000).map(|n| n * 2).collect();
+        for n in 0..1000 {
+            assert_eq!(n as usize, set.rank(&(n * 2)));
+            assert_eq!(n as usize + 1, set.rank(&(n * 2 + 1)));
+        }
+        for n in 0..500 {
+            assert!(set.remove(&(n * 4)));
+        }
+        assert_eq!(500, set.len());
+        assert_eq!(0, set.rank(&2));
+        assert_eq!(1, set.rank(&6));
+        assert_eq!(500, set.rank(&2000));
+    }
+}
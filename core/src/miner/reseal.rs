@@ -0,0 +1,131 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant};
+
+/// Throttles the reseals, so that a burst of parcels doesn't seal a burst of tiny blocks.
+///
+/// A reseal requested within `min_period` from the last one is refused but remembered.
+/// The remembered work is due once `min_period` has passed from the last reseal,
+/// or `max_period` from the refused request, whichever comes first.
+pub struct ResealSchedule {
+    min_period: Duration,
+    max_period: Duration,
+    /// `None` until the first reseal.
+    last_reseal: Option<Instant>,
+    /// When the first refused reseal was requested after the last reseal.
+    pending_since: Option<Instant>,
+}
+
+impl ResealSchedule {
+    pub fn new(min_period: Duration, max_period: Duration) -> Self {
+        Self {
+            min_period,
+            max_period,
+            last_reseal: None,
+            pending_since: None,
+        }
+    }
+
+    fn has_passed(&self, period: Duration, now: Instant) -> bool {
+        match self.last_reseal {
+            Some(last) => now >= last + period,
+            None => true,
+        }
+    }
+
+    /// Returns true if a reseal is allowed at `now`. Otherwise the request is kept as pending work.
+    pub fn request(&mut self, now: Instant) -> bool {
+        if self.has_passed(self.min_period, now) {
+            return true
+        }
+        if self.pending_since.is_none() {
+            self.pending_since = Some(now);
+        }
+        false
+    }
+
+    /// Returns true if there is pending work that shouldn't wait anymore.
+    pub fn is_due(&self, now: Instant) -> bool {
+        match self.pending_since {
+            Some(since) => self.has_passed(self.min_period, now) || now >= since + self.max_period,
+            None => false,
+        }
+    }
+
+    /// Records a reseal at `now`, which includes all the pending work.
+    pub fn note_reseal(&mut self, now: Instant) {
+        self.last_reseal = Some(now);
+        self.pending_since = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn first_reseal_is_always_allowed() {
+        let now = Instant::now();
+        let mut schedule = ResealSchedule::new(seconds(2), seconds(10));
+        assert!(schedule.request(now));
+        assert!(!schedule.is_due(now + seconds(60)));
+    }
+
+    #[test]
+    fn reseals_within_the_min_period_are_kept_pending() {
+        let start = Instant::now();
+        let mut schedule = ResealSchedule::new(seconds(2), seconds(10));
+        schedule.note_reseal(start);
+
+        assert!(!schedule.request(start + seconds(1)));
+        assert!(!schedule.is_due(start + seconds(1)));
+
+        assert!(schedule.request(start + seconds(2)));
+        schedule.note_reseal(start + seconds(2));
+        assert!(!schedule.is_due(start + seconds(60)));
+    }
+
+    #[test]
+    fn pending_work_is_due_after_the_min_period() {
+        let start = Instant::now();
+        let mut schedule = ResealSchedule::new(seconds(2), seconds(10));
+        schedule.note_reseal(start);
+        assert!(!schedule.is_due(start + seconds(10)));
+
+        schedule.request(start + seconds(1));
+        assert!(!schedule.is_due(start + seconds(1)));
+        assert!(schedule.is_due(start + seconds(2)));
+
+        schedule.note_reseal(start + seconds(2));
+        assert!(!schedule.is_due(start + seconds(30)));
+    }
+
+    #[test]
+    fn pending_work_waits_for_the_max_period_at_most() {
+        let start = Instant::now();
+        let mut schedule = ResealSchedule::new(seconds(30), seconds(10));
+        schedule.note_reseal(start);
+
+        schedule.request(start + seconds(5));
+        assert!(!schedule.is_due(start + seconds(14)));
+        assert!(schedule.is_due(start + seconds(15)));
+    }
+}
@@ -14,13 +14,17 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::error;
 use std::fmt;
 use std::ops::Deref;
 
 use ccrypto::blake256;
+use crossbeam;
 use ckeys::{self, public_to_address, recover_ecdsa, sign_ecdsa, ECDSASignature, Private, Public};
 use ctypes::{Address, Bytes, H160, H256, U256};
 use heapsize::HeapSizeOf;
+use lru_cache::LruCache;
+use parking_lot::{Mutex, RwLock};
 use rlp::{self, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
 use super::types::BlockNumber;
@@ -32,14 +36,32 @@ pub enum ParcelError {
     /// Parcel is already imported to the queue
     AlreadyImported,
     /// Parcel is not valid anymore (state already has higher nonce)
-    Old,
+    Old {
+        /// Nonce of the sender in the state.
+        expected: U256,
+        /// Parcel nonce
+        got: U256,
+    },
     /// Parcel has too low fee
     /// (there is already a parcel with the same sender-nonce but higher gas price)
     TooCheapToReplace,
     /// Invalid chain ID given.
     InvalidNetworkId,
     /// Parcel was not imported to the queue because limit has been reached.
-    LimitReached,
+    LimitReached {
+        /// The number of parcels the queue can hold.
+        limit: usize,
+    },
+    /// Parcel was not imported to the queue because the parcels in the queue are too large.
+    MemoryLimitReached {
+        /// The maximal size of the RLP encoded parcels in the queue.
+        limit: usize,
+    },
+    /// Parcel was not imported to the queue because its sender has too many parcels in the queue.
+    SenderLimitReached {
+        /// The number of parcels a sender can have in the queue.
+        limit: usize,
+    },
     /// Parcel's fee is below currently set minimal fee requirement.
     InsufficientFee {
         /// Minimal expected fee
@@ -47,13 +69,27 @@ pub enum ParcelError {
         /// Parcel fee
         got: U256,
     },
+    /// Parcel's fee is above currently set maximal fee, which usually means a mistake of the sender.
+    FeeTooHigh {
+        /// Maximal allowed fee
+        maximum: U256,
+        /// Parcel fee
+        got: U256,
+    },
     /// Sender doesn't have enough funds to pay for this Parcel
     InsufficientBalance {
         address: Address,
         /// Senders balance
         balance: U256,
-        /// Parcel cost
-        cost: U256,
+        /// The fee and the value of the parcel, saturated at `U256::max_value()`
+        required: U256,
+    },
+    /// Sender can't pay for this parcel together with the other parcels in the queue.
+    InsufficientBalanceForQueue {
+        /// Senders balance
+        balance: U256,
+        /// The cost of the parcels of the sender in the queue, including this one
+        cumulative_cost: U256,
     },
     /// Returned when parcel nonce does not match state nonce.
     InvalidNonce {
@@ -62,34 +98,79 @@ pub enum ParcelError {
         /// Nonce found.
         got: U256,
     },
+    /// Parcel nonce is too far ahead of the state nonce of the sender.
+    NonceTooFarInFuture {
+        /// The highest nonce accepted now.
+        expected_max: U256,
+        /// Parcel nonce
+        got: U256,
+    },
     /// Not enough permissions given by permission contract.
     NotAllowed,
+    /// The sender is banned from the queue.
+    SenderBanned,
     /// Signature error
     InvalidSignature(String),
 }
 
+fn hex(value: &U256) -> String {
+    if value.is_zero() {
+        "0x0".into()
+    } else {
+        format!("0x{:x}", value)
+    }
+}
+
 pub fn parcel_error_message(error: &ParcelError) -> String {
     use self::ParcelError::*;
     match error {
         AlreadyImported => "Already imported".into(),
-        Old => "No longer valid".into(),
+        Old {
+            expected,
+            got,
+        } => format!("No longer valid. Nonce={}, Given={}", hex(expected), hex(got)),
         TooCheapToReplace => "Gas price too low to replace".into(),
         InvalidNetworkId => "Parcel of this network ID is not allowed on this chain.".into(),
-        LimitReached => "Parcel limit reached".into(),
+        LimitReached {
+            limit,
+        } => format!("Parcel limit reached. Limit={:#x}", limit),
+        MemoryLimitReached {
+            limit,
+        } => format!("Parcel memory limit reached. Limit={:#x}", limit),
+        SenderLimitReached {
+            limit,
+        } => format!("Too many parcels from the sender. Limit={:#x}", limit),
         InsufficientFee {
             minimal,
             got,
-        } => format!("Insufficient fee. Min={}, Given={}", minimal, got),
+        } => format!("Insufficient fee. Min={}, Given={}", hex(minimal), hex(got)),
+        FeeTooHigh {
+            maximum,
+            got,
+        } => format!("Fee too high. Max={}, Given={}", hex(maximum), hex(got)),
         InsufficientBalance {
             address,
             balance,
-            cost,
-        } => format!("{} has only {:?} but it must be larger than {:?}", address, balance, cost),
+            required,
+        } => format!("{:?} has only {} but it must be larger than {}", address, hex(balance), hex(required)),
+        InsufficientBalanceForQueue {
+            balance,
+            cumulative_cost,
+        } => format!(
+            "Insufficient balance for the queued parcels. Balance={}, Cost={}",
+            hex(balance),
+            hex(cumulative_cost)
+        ),
         InvalidNonce {
             expected,
             got,
-        } => format!("Invalid parcel nonce: expected {}, found {}", expected, got),
+        } => format!("Invalid parcel nonce: expected {}, found {}", hex(expected), hex(got)),
+        NonceTooFarInFuture {
+            expected_max,
+            got,
+        } => format!("Nonce too far in the future. Max={}, Given={}", hex(expected_max), hex(got)),
         NotAllowed => "Sender does not have permissions to execute this type of transction".into(),
+        SenderBanned => "The sender is banned".into(),
         InvalidSignature(err) => format!("Parcel has invalid signature: {}.", err),
     }
 }
@@ -102,6 +183,12 @@ impl fmt::Display for ParcelError {
     }
 }
 
+impl error::Error for ParcelError {
+    fn description(&self) -> &str {
+        "Parcel error"
+    }
+}
+
 impl From<ckeys::Error> for ParcelError {
     fn from(err: ckeys::Error) -> Self {
         ParcelError::InvalidSignature(format!("{}", err))
@@ -123,8 +210,7 @@ pub struct Parcel {
     pub action: Action,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
-#[serde(rename_all = "camelCase", tag = "action")]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Action {
     ChangeShardState {
         /// Transaction, can be either asset mint or asset transfer
@@ -148,13 +234,53 @@ impl Default for Action {
     }
 }
 
+/// The variant of an `Action`, without its contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ActionKind {
+    ChangeShardState,
+    Payment,
+    SetRegularKey,
+}
+
+impl Action {
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            Action::ChangeShardState {
+                ..
+            } => ActionKind::ChangeShardState,
+            Action::Payment {
+                ..
+            } => ActionKind::Payment,
+            Action::SetRegularKey {
+                ..
+            } => ActionKind::SetRegularKey,
+        }
+    }
+}
+
 const CHANGE_SHARD_STATE: u8 = 1;
 const PAYMENT: u8 = 2;
 const SET_REGULAR_KEY: u8 = 3;
 
 impl HeapSizeOf for Parcel {
     fn heap_size_of_children(&self) -> usize {
-        0
+        self.action.heap_size_of_children()
+    }
+}
+
+impl HeapSizeOf for Action {
+    fn heap_size_of_children(&self) -> usize {
+        match self {
+            Action::ChangeShardState {
+                transactions,
+            } => transactions.heap_size_of_children(),
+            Action::Payment {
+                ..
+            } => 0,
+            Action::SetRegularKey {
+                ..
+            } => 0,
+        }
     }
 }
 
@@ -206,6 +332,50 @@ impl rlp::Decodable for Action {
     }
 }
 
+/// The number of recovered public keys kept in the shared sender cache.
+const SENDER_CACHE_SIZE: usize = 32_768;
+
+lazy_static! {
+    /// Public keys recovered so far, keyed by the hash of the signed parcel.
+    ///
+    /// The hash covers the signature, so a malleated parcel never hits another parcel's entry.
+    static ref SENDER_CACHE: Mutex<LruCache<H256, Public>> = Mutex::new(LruCache::new(SENDER_CACHE_SIZE));
+}
+
+/// Memoized public key of the sender. It is carried through clones and ignored by comparisons.
+#[derive(Default)]
+struct RecoveredPublic(RwLock<Option<Public>>);
+
+impl RecoveredPublic {
+    fn get(&self) -> Option<Public> {
+        *self.0.read()
+    }
+
+    fn set(&self, public: Public) {
+        *self.0.write() = Some(public);
+    }
+}
+
+impl Clone for RecoveredPublic {
+    fn clone(&self) -> Self {
+        RecoveredPublic(RwLock::new(self.get()))
+    }
+}
+
+impl PartialEq for RecoveredPublic {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for RecoveredPublic {}
+
+impl fmt::Debug for RecoveredPublic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.get())
+    }
+}
+
 impl Parcel {
     /// Append object with a without signature into RLP stream
     pub fn rlp_append_unsigned_parcel(&self, s: &mut RlpStream) {
@@ -237,6 +407,7 @@ impl Parcel {
             s: sig.s().into(),
             v: sig.v(),
             hash: 0.into(),
+            public: Default::default(),
         }.compute_hash()
     }
 }
@@ -255,6 +426,8 @@ pub struct UnverifiedParcel {
     s: U256,
     /// Hash of the parcel
     hash: H256,
+    /// Public key of the sender, once recovered.
+    public: RecoveredPublic,
 }
 
 impl Deref for UnverifiedParcel {
@@ -282,6 +455,7 @@ impl rlp::Decodable for UnverifiedParcel {
             r: d.val_at(5)?,
             s: d.val_at(6)?,
             hash,
+            public: Default::default(),
         })
     }
 }
@@ -333,8 +507,24 @@ impl UnverifiedParcel {
     }
 
     /// Recovers the public key of the sender.
+    ///
+    /// The result is memoized on the parcel and in the shared sender cache,
+    /// so the same parcel received again (e.g. in a block after gossip) is not recovered twice.
     pub fn recover_public(&self) -> Result<Public, ckeys::Error> {
-        Ok(recover_ecdsa(&self.signature(), &self.unsigned.hash())?)
+        if let Some(public) = self.public.get() {
+            return Ok(public)
+        }
+        let cached = SENDER_CACHE.lock().get_mut(&self.hash).cloned();
+        let public = match cached {
+            Some(public) => public,
+            None => {
+                let public = recover_ecdsa(&self.signature(), &self.unsigned.hash())?;
+                SENDER_CACHE.lock().insert(self.hash, public);
+                public
+            }
+        };
+        self.public.set(public);
+        Ok(public)
     }
 
     /// Checks whether the signature has a low 's' value.
@@ -432,6 +622,35 @@ impl SignedParcel {
     }
 }
 
+/// Recovers the senders of `parcels` using at most `max_threads` threads.
+///
+/// The recovered public keys are memoized on the parcels, so creating `SignedParcel`s from them afterwards is cheap.
+/// Fails if the sender of any parcel cannot be recovered.
+pub fn recover_senders(parcels: &[UnverifiedParcel], max_threads: usize) -> Result<(), ckeys::Error> {
+    fn recover_chunk(parcels: &[UnverifiedParcel]) -> Result<(), ckeys::Error> {
+        for parcel in parcels.iter().filter(|parcel| !parcel.is_unsigned()) {
+            parcel.recover_public()?;
+        }
+        Ok(())
+    }
+
+    if max_threads <= 1 || parcels.len() <= 1 {
+        return recover_chunk(parcels)
+    }
+    let chunk_size = (parcels.len() + max_threads - 1) / max_threads;
+    crossbeam::scope(|scope| {
+        let handles: Vec<_> =
+            parcels.chunks(chunk_size).map(|chunk| scope.spawn(move || recover_chunk(chunk))).collect();
+        handles.into_iter().map(|handle| handle.join()).collect()
+    })
+}
+
+#[doc(hidden)]
+/// Clears the shared sender cache. Used to measure the cost of the recovery itself.
+pub fn clear_sender_cache() {
+    SENDER_CACHE.lock().clear();
+}
+
 /// Signed Parcel that is a part of canon blockchain.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LocalizedParcel {
@@ -472,8 +691,7 @@ impl Deref for LocalizedParcel {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, RlpDecodable, RlpEncodable, Serialize)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Eq, PartialEq, RlpDecodable, RlpEncodable)]
 pub struct AssetOutPoint {
     pub transaction_hash: H256,
     pub index: usize,
@@ -481,16 +699,20 @@ pub struct AssetOutPoint {
     pub amount: u64,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, RlpDecodable, RlpEncodable, Serialize)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Eq, PartialEq, RlpDecodable, RlpEncodable)]
 pub struct AssetTransferInput {
     pub prev_out: AssetOutPoint,
     pub lock_script: Bytes,
     pub unlock_script: Bytes,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, RlpDecodable, RlpEncodable, Serialize)]
-#[serde(rename_all = "camelCase")]
+impl HeapSizeOf for AssetTransferInput {
+    fn heap_size_of_children(&self) -> usize {
+        self.lock_script.heap_size_of_children() + self.unlock_script.heap_size_of_children()
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, RlpDecodable, RlpEncodable)]
 pub struct AssetTransferOutput {
     pub lock_script_hash: H256,
     pub parameters: Vec<Bytes>,
@@ -498,8 +720,15 @@ pub struct AssetTransferOutput {
     pub amount: u64,
 }
 
+impl HeapSizeOf for AssetTransferOutput {
+    fn heap_size_of_children(&self) -> usize {
+        self.parameters.heap_size_of_children()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use ckeys::{Generator, Random};
     use ctypes::{Address, H256, Public, U256};
     use rlp::Encodable;
 
@@ -513,6 +742,7 @@ mod tests {
             r: U256::default(),
             s: U256::default(),
             hash: H256::default(),
+            public: Default::default(),
         }.compute_hash();
         assert_eq!(parcel, ::rlp::decode(parcel.rlp_bytes().as_ref()));
     }
@@ -591,6 +821,7 @@ mod tests {
             r: U256::default(),
             s: U256::default(),
             hash: H256::default(),
+            public: Default::default(),
         }.compute_hash();
 
         let encoded = origin.rlp_bytes();
@@ -614,6 +845,7 @@ mod tests {
             r: U256::default(),
             s: U256::default(),
             hash: H256::default(),
+            public: Default::default(),
         }.compute_hash();
 
         let encoded = origin.rlp_bytes();
@@ -621,4 +853,150 @@ mod tests {
 
         assert_eq!(origin, decoded);
     }
+
+    #[test]
+    fn recovered_sender_is_memoized_and_cloned() {
+        let keypair = Random.generate().unwrap();
+        let parcel: UnverifiedParcel = Parcel::default().sign(keypair.private()).into();
+        let decoded: UnverifiedParcel = ::rlp::decode(&parcel.rlp_bytes());
+        assert_eq!(None, decoded.public.get());
+
+        assert_eq!(Ok(*keypair.public()), decoded.recover_public());
+        assert_eq!(Some(*keypair.public()), decoded.public.get());
+        assert_eq!(Some(*keypair.public()), decoded.clone().public.get());
+        assert_eq!(parcel, decoded);
+    }
+
+    #[test]
+    fn sender_cache_is_keyed_by_the_signed_hash() {
+        let keypair = Random.generate().unwrap();
+        let parcel: UnverifiedParcel = Parcel::default().sign(keypair.private()).into();
+        parcel.recover_public().unwrap();
+
+        let mut malleated = parcel.clone();
+        malleated.v ^= 1;
+        malleated.public = Default::default();
+        let malleated = malleated.compute_hash();
+        assert_ne!(parcel.hash(), malleated.hash());
+        assert_ne!(Ok(*keypair.public()), malleated.recover_public());
+    }
+
+    #[test]
+    fn recover_senders_in_parallel() {
+        let keypairs: Vec<_> = (0..4).map(|_| Random.generate().unwrap()).collect();
+        let parcels: Vec<UnverifiedParcel> = (0..10)
+            .map(|i| {
+                let parcel = Parcel {
+                    nonce: i.into(),
+                    ..Parcel::default()
+                };
+                let signed: UnverifiedParcel = parcel.sign(keypairs[i % 4].private()).into();
+                ::rlp::decode(&signed.rlp_bytes())
+            })
+            .collect();
+
+        assert_eq!(Ok(()), recover_senders(&parcels, 3));
+        for (i, parcel) in parcels.iter().enumerate() {
+            assert_eq!(Some(*keypairs[i % 4].public()), parcel.public.get());
+        }
+    }
+
+    #[test]
+    fn recover_senders_fails_if_any_recovery_fails() {
+        let keypair = Random.generate().unwrap();
+        let mut parcels: Vec<UnverifiedParcel> = (0..4)
+            .map(|i| {
+                let parcel = Parcel {
+                    nonce: i.into(),
+                    ..Parcel::default()
+                };
+                parcel.sign(keypair.private()).into()
+            })
+            .collect();
+        let mut invalid = parcels[2].clone();
+        invalid.r = U256::max_value();
+        invalid.public = Default::default();
+        parcels[2] = invalid.compute_hash();
+
+        assert!(recover_senders(&parcels, 2).is_err());
+    }
+
+    #[test]
+    fn error_messages_render_numbers_in_hex() {
+        assert_eq!(
+            "Insufficient fee. Min=0xc8, Given=0x64",
+            parcel_error_message(&ParcelError::InsufficientFee {
+                minimal: 200.into(),
+                got: 100.into(),
+            })
+        );
+        assert_eq!(
+            "Fee too high. Max=0x3e8, Given=0x3e9",
+            parcel_error_message(&ParcelError::FeeTooHigh {
+                maximum: 1000.into(),
+                got: 1001.into(),
+            })
+        );
+        assert_eq!(
+            format!("{:?} has only 0x32 but it must be larger than 0x64", Address::default()),
+            parcel_error_message(&ParcelError::InsufficientBalance {
+                address: Address::default(),
+                balance: 50.into(),
+                required: 100.into(),
+            })
+        );
+        assert_eq!(
+            "Insufficient balance for the queued parcels. Balance=0x32, Cost=0x64",
+            parcel_error_message(&ParcelError::InsufficientBalanceForQueue {
+                balance: 50.into(),
+                cumulative_cost: 100.into(),
+            })
+        );
+        assert_eq!(
+            "No longer valid. Nonce=0x3, Given=0x1",
+            parcel_error_message(&ParcelError::Old {
+                expected: 3.into(),
+                got: 1.into(),
+            })
+        );
+        assert_eq!(
+            "Invalid parcel nonce: expected 0x10, found 0x11",
+            parcel_error_message(&ParcelError::InvalidNonce {
+                expected: 16.into(),
+                got: 17.into(),
+            })
+        );
+        assert_eq!(
+            "Invalid parcel nonce: expected 0x0, found 0x1",
+            parcel_error_message(&ParcelError::InvalidNonce {
+                expected: 0.into(),
+                got: 1.into(),
+            })
+        );
+        assert_eq!(
+            "Nonce too far in the future. Max=0x80, Given=0x81",
+            parcel_error_message(&ParcelError::NonceTooFarInFuture {
+                expected_max: 128.into(),
+                got: 129.into(),
+            })
+        );
+        assert_eq!(
+            "Parcel limit reached. Limit=0x400",
+            parcel_error_message(&ParcelError::LimitReached {
+                limit: 1024,
+            })
+        );
+        assert_eq!(
+            "Parcel memory limit reached. Limit=0x400",
+            parcel_error_message(&ParcelError::MemoryLimitReached {
+                limit: 1024,
+            })
+        );
+        assert_eq!(
+            "Too many parcels from the sender. Limit=0x10",
+            parcel_error_message(&ParcelError::SenderLimitReached {
+                limit: 16,
+            })
+        );
+    }
 }
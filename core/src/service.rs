@@ -17,9 +17,10 @@
 use std::path::Path;
 use std::sync::Arc;
 
-use cio::{IoContext, IoHandler, IoHandlerResult, IoService};
+use cio::{IoContext, IoHandler, IoHandlerResult, IoService, TimerToken};
 use cnetwork::NodeId;
 use ctypes::Bytes;
+use kvdb::KeyValueDB;
 use kvdb_rocksdb::{Database, DatabaseConfig};
 
 use super::client::{Client, ClientConfig};
@@ -40,19 +41,34 @@ impl ClientService {
         client_path: &Path,
         miner: Arc<Miner>,
     ) -> Result<ClientService, Error> {
-        let io_service = IoService::<ClientIoMessage>::start()?;
-
         let mut db_config = DatabaseConfig::with_columns(super::db::NUM_COLUMNS);
 
         db_config.memory_budget = config.db_cache_size;
         db_config.compaction = config.db_compaction.compaction_profile(client_path);
         db_config.wal = config.db_wal;
+        db_config.column_configs = config
+            .db_column_configs
+            .iter()
+            .map(|(col, column_config)| (*col, column_config.column_config(client_path)))
+            .collect();
 
         let db = Arc::new(Database::open(
             &db_config,
             &client_path.to_str().expect("DB path could not be converted to string."),
         ).map_err(::client::Error::Database)?);
 
+        Self::start_with_db(config, spec, db, miner)
+    }
+
+    /// Starts the client with the given database, e.g. an in-memory one.
+    pub fn start_with_db(
+        config: ClientConfig,
+        spec: &Spec,
+        db: Arc<KeyValueDB>,
+        miner: Arc<Miner>,
+    ) -> Result<ClientService, Error> {
+        let io_service = IoService::<ClientIoMessage>::start()?;
+
         let client = Client::new(config, &spec, db, miner, io_service.channel())?;
 
         let client_io = Arc::new(ClientIoHandler {
@@ -89,7 +105,31 @@ struct ClientIoHandler {
     client: Arc<Client>,
 }
 
+const FUTURE_BLOCKS_TIMER: TimerToken = 0;
+const FUTURE_BLOCKS_INTERVAL_MS: u64 = 1000;
+const RESEAL_TIMER: TimerToken = 1;
+const RESEAL_INTERVAL_MS: u64 = 1000;
+
 impl IoHandler<ClientIoMessage> for ClientIoHandler {
+    fn initialize(&self, io: &IoContext<ClientIoMessage>) -> IoHandlerResult<()> {
+        io.register_timer(FUTURE_BLOCKS_TIMER, FUTURE_BLOCKS_INTERVAL_MS)?;
+        io.register_timer(RESEAL_TIMER, RESEAL_INTERVAL_MS)?;
+        Ok(())
+    }
+
+    fn timeout(&self, _io: &IoContext<ClientIoMessage>, timer: TimerToken) -> IoHandlerResult<()> {
+        match timer {
+            FUTURE_BLOCKS_TIMER => {
+                self.client.release_future_blocks();
+            }
+            RESEAL_TIMER => {
+                self.client.reseal_pending_work();
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
     fn message(&self, _io: &IoContext<ClientIoMessage>, net_message: &ClientIoMessage) -> IoHandlerResult<()> {
         match net_message {
             ClientIoMessage::BlockVerified => {
@@ -105,3 +145,43 @@ impl IoHandler<ClientIoMessage> for ClientIoHandler {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use ctypes::Address;
+
+    use super::super::block::IsBlock;
+    use super::super::client::{BlockChainClient, ImportBlock, PrepareOpenBlock};
+    use super::super::tests::helpers::new_db;
+    use super::super::types::{BlockId, BlockStatus};
+    use super::*;
+
+    #[test]
+    fn early_blocks_are_imported_once_the_timer_releases_them() {
+        let spec = Spec::new_test();
+        let config = ClientConfig::default();
+        let drift = config.queue.allowed_future_drift;
+        let service = ClientService::start_with_db(config, &spec, new_db(), Arc::new(Miner::with_spec(&spec))).unwrap();
+        let client = service.client();
+
+        let genesis = spec.genesis_header();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let mut block = client.prepare_open_block(Address::default(), vec![]);
+        block.set_timestamp(now + drift + 2);
+        let block = block.close_and_lock(*genesis.parcels_root(), *genesis.invoices_root());
+        let block = block.seal(&*spec.engine, vec![]).unwrap();
+        let hash = block.header().hash();
+
+        assert_eq!(hash, client.import_block(block.rlp_bytes()).unwrap());
+        assert_eq!(BlockStatus::Queued, client.block_status(BlockId::Hash(hash)));
+
+        let deadline = Instant::now() + Duration::from_secs(drift);
+        while client.block_status(BlockId::Hash(hash)) != BlockStatus::InChain {
+            assert!(Instant::now() < deadline, "The early block is not imported");
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
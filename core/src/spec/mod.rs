@@ -19,4 +19,4 @@ mod seal;
 mod spec;
 
 pub use self::genesis::Genesis;
-pub use self::spec::{CommonParams, Spec};
+pub use self::spec::{CommonParams, ParamsTransition, Spec};
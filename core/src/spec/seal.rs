@@ -15,7 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use cjson;
-use ctypes::H520;
+use ctypes::{Signature, H520};
 use rlp::RlpStream;
 
 /// Tendermint seal.
@@ -23,9 +23,9 @@ pub struct Tendermint {
     /// Seal round.
     pub round: usize,
     /// Proposal seal signature.
-    pub proposal: H520,
+    pub proposal: Signature,
     /// Precommit seal signatures.
-    pub precommits: Vec<H520>,
+    pub precommits: Vec<Signature>,
 }
 
 impl Into<Generic> for Tendermint {
@@ -51,8 +51,8 @@ impl From<cjson::spec::Seal> for Seal {
         match s {
             cjson::spec::Seal::Tendermint(tender) => Seal::Tendermint(Tendermint {
                 round: tender.round.into(),
-                proposal: tender.proposal.into(),
-                precommits: tender.precommits.into_iter().map(Into::into).collect(),
+                proposal: H520::from(tender.proposal).into(),
+                precommits: tender.precommits.into_iter().map(|precommit| H520::from(precommit).into()).collect(),
             }),
             cjson::spec::Seal::Generic(g) => Seal::Generic(Generic(g.into())),
         }
@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::cmp;
 use std::io::Read;
 use std::sync::Arc;
 
@@ -35,22 +36,82 @@ use super::super::state::BasicBackend;
 use super::seal::Generic as GenericSeal;
 use super::Genesis;
 
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, PartialEq, Default, Clone)]
 pub struct CommonParams {
     /// Maximum size of extra data.
     pub maximum_extra_data_size: usize,
+    /// Maximum size of block body.
+    pub max_body_size: usize,
+    /// Maximum number of parcels in a block.
+    pub max_parcels_per_block: usize,
     /// Network id.
     pub network_id: u64,
     /// Minimum parcel cost.
     pub min_parcel_cost: U256,
+    /// Number of shards.
+    pub shard_count: u32,
+    /// Overrides of the parameters from a block on, ordered by the block number.
+    pub transitions: Vec<ParamsTransition>,
+}
+
+impl CommonParams {
+    /// The parameters in effect for the block `block_number`, with the overrides activated by then.
+    pub fn at(&self, block_number: u64) -> CommonParams {
+        let mut params = self.clone();
+        for transition in self.transitions.iter().take_while(|t| t.block_number <= block_number) {
+            if let Some(max_body_size) = transition.max_body_size {
+                params.max_body_size = max_body_size;
+            }
+            if let Some(max_parcels_per_block) = transition.max_parcels_per_block {
+                params.max_parcels_per_block = max_parcels_per_block;
+            }
+            if let Some(min_parcel_cost) = transition.min_parcel_cost {
+                params.min_parcel_cost = min_parcel_cost;
+            }
+        }
+        params
+    }
+
+    /// The largest body size allowed at any height.
+    pub fn largest_max_body_size(&self) -> usize {
+        self.transitions.iter().filter_map(|t| t.max_body_size).fold(self.max_body_size, cmp::max)
+    }
 }
 
 impl From<cjson::spec::Params> for CommonParams {
     fn from(p: cjson::spec::Params) -> Self {
+        let mut transitions: Vec<ParamsTransition> =
+            p.transitions.unwrap_or_default().into_iter().map(Into::into).collect();
+        transitions.sort_by_key(|t| t.block_number);
         Self {
             maximum_extra_data_size: p.maximum_extra_data_size.into(),
+            max_body_size: p.max_body_size.into(),
+            max_parcels_per_block: p.max_parcels_per_block.into(),
             network_id: p.network_id.into(),
             min_parcel_cost: p.min_parcel_cost.into(),
+            shard_count: p.shard_count.into(),
+            transitions,
+        }
+    }
+}
+
+/// Overrides of the common parameters from a block on.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParamsTransition {
+    /// The first block the parameters are overridden for.
+    pub block_number: u64,
+    pub max_body_size: Option<usize>,
+    pub max_parcels_per_block: Option<usize>,
+    pub min_parcel_cost: Option<U256>,
+}
+
+impl From<cjson::spec::ParamsTransition> for ParamsTransition {
+    fn from(t: cjson::spec::ParamsTransition) -> Self {
+        Self {
+            block_number: t.block_number.into(),
+            max_body_size: t.max_body_size.map(Into::into),
+            max_parcels_per_block: t.max_parcels_per_block.map(Into::into),
+            min_parcel_cost: t.min_parcel_cost.map(Into::into),
         }
     }
 }
@@ -207,6 +268,8 @@ impl Spec {
         header.set_extra_data(self.extra_data.clone());
         header.set_state_root(self.state_root());
         header.set_invoices_root(self.invoices_root.clone());
+        // The genesis state has no shards yet.
+        header.set_shard_roots(vec![BLAKE_NULL_RLP; self.params().shard_count as usize]);
         header.set_score(self.score.clone());
         header.set_seal({
             let r = Rlp::new(&self.seal_rlp);
@@ -259,6 +259,19 @@ impl<B: Backend + TopBackend + ShardBackend + Clone> TopLevelState<B> {
         &self.root
     }
 
+    /// Returns the roots of the shards from 0 to `shard_count`.
+    /// A shard which has never been changed has the null root.
+    pub fn shard_roots(&self, shard_count: u32) -> TrieResult<Vec<H256>> {
+        (0..shard_count)
+            .map(|shard_id| Ok(self.shard_root(&ShardAddress::new(shard_id))?.unwrap_or(BLAKE_NULL_RLP)))
+            .collect()
+    }
+
+    /// Get a copy of account `a`. `None` if the account doesn't exist.
+    pub fn account(&self, a: &Address) -> TrieResult<Option<Account>> {
+        self.ensure_account_cached(a, |a| a.cloned())
+    }
+
     /// Destroy the current object and return root and database.
     pub fn drop(mut self) -> (H256, B) {
         self.propagate_to_global_cache();
@@ -300,7 +313,7 @@ impl<B: Backend + TopBackend + ShardBackend + Clone> TopLevelState<B> {
         if fee > balance {
             return Err(ParcelError::InsufficientBalance {
                 address: fee_payer,
-                cost: fee,
+                required: fee,
                 balance,
             }.into())
         }
@@ -491,7 +504,7 @@ impl<B: Backend + TopBackend + ShardBackend + Clone> TopState<B> for TopLevelSta
         if &balance < by {
             return Err(ParcelError::InsufficientBalance {
                 address: *from,
-                cost: *by,
+                required: *by,
                 balance,
             }.into())
         }
@@ -602,7 +615,7 @@ mod tests {
                     ParcelError::InsufficientBalance {
                         address: sender,
                         balance: 4.into(),
-                        cost: 5.into(),
+                        required: 5.into(),
                     },
                     err
                 );
@@ -703,7 +716,7 @@ mod tests {
                 error: Some(ParcelError::InsufficientBalance {
                     address: sender,
                     balance: 15.into(),
-                    cost: 30.into(),
+                    required: 30.into(),
                 })
             },
             res
@@ -994,6 +1007,36 @@ mod tests {
         assert_eq!(Ok(Some(Asset::new(asset_scheme_address.into(), lock_script_hash, parameters, amount))), asset);
     }
 
+    #[test]
+    fn mint_changes_only_the_root_of_its_shard() {
+        let mut state = get_temp_state();
+        assert_eq!(Ok(vec![BLAKE_NULL_RLP, BLAKE_NULL_RLP]), state.shard_roots(2));
+
+        let transaction = Transaction::AssetMint {
+            metadata: "metadata".to_string(),
+            lock_script_hash: H256::random(),
+            parameters: vec![],
+            amount: Some(30),
+            registrar: None,
+            nonce: 0,
+        };
+        let signed_parcel = Parcel {
+            fee: 11.into(),
+            action: Action::ChangeShardState {
+                transactions: vec![transaction],
+            },
+            ..Parcel::default()
+        }.sign(&secret().into());
+        state.add_balance(&signed_parcel.sender(), &U256::from(69u64)).unwrap();
+        state.apply(&signed_parcel).unwrap();
+        state.commit().unwrap();
+
+        let shard_roots = state.shard_roots(2).unwrap();
+        assert_ne!(BLAKE_NULL_RLP, shard_roots[0]);
+        assert_eq!(BLAKE_NULL_RLP, shard_roots[1]);
+        assert_eq!(Ok(Some(shard_roots[0])), state.shard_root(&ShardAddress::new(0)));
+    }
+
     #[test]
     fn mint_infinite_permissioned_asset() {
         let mut state = {
@@ -347,6 +347,12 @@ impl StateDB {
         }
     }
 
+    /// Returns the accounts buffered for the global cache, with a flag which is
+    /// set if the account was modified. Must be called before `sync_cache`.
+    pub fn local_accounts(&self) -> Vec<(Address, Option<Account>, bool)> {
+        self.local_account_cache.iter().map(|item| (item.address, item.item.clone(), item.modified)).collect()
+    }
+
     /// Conversion method to interpret self as `HashDB` reference
     pub fn as_hashdb(&self) -> &HashDB {
         self.db.as_hashdb()
@@ -16,10 +16,13 @@
 
 use std::sync::Arc;
 
+use cio::IoChannel;
+use client::{Client, ClientConfig};
 use ctypes::Bytes;
 use ctypes::{H256, U256};
 use header::Header;
 use kvdb::KeyValueDB;
+use miner::Miner;
 use parcel::SignedParcel;
 use rlp::{self, RlpStream};
 use spec::Spec;
@@ -61,7 +64,7 @@ pub fn get_good_dummy_block_hash() -> (H256, Bytes) {
     (block_header.hash(), create_test_block(&block_header))
 }
 
-fn new_db() -> Arc<KeyValueDB> {
+pub fn new_db() -> Arc<KeyValueDB> {
     Arc::new(::kvdb_memorydb::create(::db::NUM_COLUMNS.unwrap_or(0)))
 }
 
@@ -71,6 +74,19 @@ pub fn get_temp_state_db() -> StateDB {
     StateDB::new(journal_db, 5 * 1024 * 1024)
 }
 
+pub fn generate_dummy_client_with_db(spec: &Spec, db: Arc<KeyValueDB>) -> Arc<Client> {
+    let miner = Arc::new(Miner::with_spec(spec));
+    Client::new(ClientConfig::default(), spec, db, miner, IoChannel::disconnected()).unwrap()
+}
+
+pub fn generate_dummy_client(spec: &Spec) -> Arc<Client> {
+    generate_dummy_client_with_db(spec, new_db())
+}
+
+pub fn generate_dummy_client_with_miner(spec: &Spec, miner: Arc<Miner>) -> Arc<Client> {
+    Client::new(ClientConfig::default(), spec, new_db(), miner, IoChannel::disconnected()).unwrap()
+}
+
 pub fn get_temp_state() -> TopLevelState<StateDB> {
     let journal_db = get_temp_state_db();
     TopLevelState::new(journal_db, Default::default())
@@ -14,20 +14,20 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::error;
 use std::fmt;
 
 use ccrypto::blake256;
 use ctypes::{Address, Bytes, H256, U256};
+use heapsize::HeapSizeOf;
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 use unexpected::Mismatch;
 
 use super::parcel::{AssetTransferInput, AssetTransferOutput};
 
 /// Parcel transaction type.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
-#[serde(rename_all = "camelCase", tag = "type", content = "data")]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Transaction {
-    #[serde(rename_all = "camelCase")]
     AssetMint {
         metadata: String,
         lock_script_hash: H256,
@@ -36,7 +36,6 @@ pub enum Transaction {
         registrar: Option<Address>,
         nonce: u64,
     },
-    #[serde(rename_all = "camelCase")]
     AssetTransfer {
         network_id: u64,
         burns: Vec<AssetTransferInput>,
@@ -93,6 +92,24 @@ impl Transaction {
     }
 }
 
+impl HeapSizeOf for Transaction {
+    fn heap_size_of_children(&self) -> usize {
+        match self {
+            Transaction::AssetMint {
+                metadata,
+                parameters,
+                ..
+            } => metadata.heap_size_of_children() + parameters.heap_size_of_children(),
+            Transaction::AssetTransfer {
+                burns,
+                inputs,
+                outputs,
+                ..
+            } => burns.heap_size_of_children() + inputs.heap_size_of_children() + outputs.heap_size_of_children(),
+        }
+    }
+}
+
 type TransactionId = u8;
 const ASSET_MINT_ID: TransactionId = 0x03;
 const ASSET_TRANSFER_ID: TransactionId = 0x04;
@@ -224,3 +241,9 @@ impl fmt::Display for Error {
         }
     }
 }
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Transaction error"
+    }
+}
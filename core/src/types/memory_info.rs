@@ -0,0 +1,37 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// Snapshot of the heap memory used by the caches and queues of a client.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryInfo {
+    /// Heap memory used by the parcels in the memory pool
+    pub mem_pool: usize,
+    /// Heap memory used by the state caches
+    pub state_cache: usize,
+    /// Heap memory used by the header, body and invoice caches of the chain
+    pub chain_cache: usize,
+    /// Heap memory used by the block verification queue
+    pub block_queue: usize,
+    /// Heap memory used by the header verification queue
+    pub header_queue: usize,
+}
+
+impl MemoryInfo {
+    /// The total heap memory used in bytes.
+    pub fn total(&self) -> usize {
+        self.mem_pool + self.state_cache + self.chain_cache + self.block_queue + self.header_queue
+    }
+}
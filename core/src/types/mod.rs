@@ -16,10 +16,12 @@
 
 mod block_status;
 mod ids;
+mod memory_info;
 mod verification_queue_info;
 
 pub use self::block_status::BlockStatus;
 pub use self::ids::{BlockId, ParcelId, TransactionId};
+pub use self::memory_info::MemoryInfo;
 pub use self::verification_queue_info::VerificationQueueInfo;
 
 pub type BlockNumber = u64;
@@ -34,6 +34,9 @@ pub trait BlockLike {
 
     /// Get the score of this item.
     fn score(&self) -> U256;
+
+    /// Get the timestamp of this item.
+    fn timestamp(&self) -> u64;
 }
 
 /// Defines transitions between stages of verification.
@@ -60,10 +63,12 @@ pub trait Kind: 'static + Sized + Send + Sync {
     fn create(input: Self::Input, engine: &CodeChainEngine) -> Result<Self::Unverified, Error>;
 
     /// Attempt to verify the `Unverified` item using the given engine.
+    /// `recovery_threads` is the maximum number of threads used to recover the parcel senders.
     fn verify(
         unverified: Self::Unverified,
         engine: &CodeChainEngine,
         check_seal: bool,
+        recovery_threads: usize,
     ) -> Result<Self::Verified, Error>;
 
     fn signal() -> ClientIoMessage;
@@ -73,6 +78,7 @@ pub trait Kind: 'static + Sized + Send + Sync {
 pub mod headers {
 
     use ctypes::{H256, U256};
+    use panic_hook;
 
     use super::super::super::super::consensus::CodeChainEngine;
     use super::super::super::super::error::Error;
@@ -94,6 +100,10 @@ pub mod headers {
         fn score(&self) -> U256 {
             self.score().clone()
         }
+
+        fn timestamp(&self) -> u64 {
+            self.timestamp()
+        }
     }
 
     /// A mode for verifying headers.
@@ -109,7 +119,13 @@ pub mod headers {
             verify_header_params(&input, engine).map(|_| input)
         }
 
-        fn verify(un: Self::Unverified, engine: &CodeChainEngine, check_seal: bool) -> Result<Self::Verified, Error> {
+        fn verify(
+            un: Self::Unverified,
+            engine: &CodeChainEngine,
+            check_seal: bool,
+            _recovery_threads: usize,
+        ) -> Result<Self::Verified, Error> {
+            let _guard = panic_hook::with_context("header", un.hash());
             match check_seal {
                 true => engine.verify_block_unordered(&un).map(|_| un),
                 false => Ok(un),
@@ -124,8 +140,11 @@ pub mod headers {
 
 /// The blocks verification module.
 pub mod blocks {
+    use std::rc::Rc;
+
     use ctypes::{Bytes, H256, U256};
     use heapsize::HeapSizeOf;
+    use panic_hook;
 
     use super::super::super::super::consensus::CodeChainEngine;
     use super::super::super::super::error::Error;
@@ -152,10 +171,27 @@ pub mod blocks {
             }
         }
 
-        fn verify(un: Self::Unverified, engine: &CodeChainEngine, check_seal: bool) -> Result<Self::Verified, Error> {
+        fn verify(
+            un: Self::Unverified,
+            engine: &CodeChainEngine,
+            check_seal: bool,
+            recovery_threads: usize,
+        ) -> Result<Self::Verified, Error> {
             let hash = un.hash();
-            match verify_block_unordered(un.header, un.bytes, engine, check_seal) {
-                Ok(verified) => Ok(verified),
+            let header = un.header;
+            let bytes = Rc::new(un.bytes);
+            let verified = {
+                // Dump the block if the verification panics. The dump shares the bytes instead of copying them.
+                let _guard = panic_hook::with_dump("block", hash, Rc::clone(&bytes));
+                verify_block_unordered(&header, &bytes, engine, check_seal, recovery_threads)
+            };
+            let bytes = Rc::try_unwrap(bytes).expect("The dump is removed with the guard");
+            match verified {
+                Ok(parcels) => Ok(PreverifiedBlock {
+                    header,
+                    parcels,
+                    bytes,
+                }),
                 Err(e) => {
                     cwarn!(CLIENT, "Stage 2 block verification failed for {}: {:?}", hash, e);
                     Err(e)
@@ -205,6 +241,10 @@ pub mod blocks {
         fn score(&self) -> U256 {
             self.header.score().clone()
         }
+
+        fn timestamp(&self) -> u64 {
+            self.header.timestamp()
+        }
     }
 
     impl BlockLike for PreverifiedBlock {
@@ -219,5 +259,9 @@ pub mod blocks {
         fn score(&self) -> U256 {
             self.header.score().clone()
         }
+
+        fn timestamp(&self) -> u64 {
+            self.header.timestamp()
+        }
     }
 }
@@ -21,10 +21,12 @@ use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::{Arc, Condvar as SCondvar, Mutex as SMutex};
 use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use cio::IoChannel;
 use ctypes::{H256, U256};
 use heapsize::HeapSizeOf;
+use lru_cache::LruCache;
 use num_cpus;
 use parking_lot::{Mutex, RwLock};
 
@@ -33,9 +35,12 @@ use super::super::consensus::CodeChainEngine;
 use super::super::error::{BlockError, Error, ImportError};
 use super::super::service::ClientIoMessage;
 use super::super::types::{BlockStatus as Status, VerificationQueueInfo as QueueInfo};
+use super::verification::verify_timestamp;
 
 const MIN_MEM_LIMIT: usize = 16384;
 const MIN_QUEUE_LIMIT: usize = 512;
+/// The number of rejected items whose errors are kept for `take_rejection`.
+const MAX_REJECTIONS: usize = 128;
 
 // maximum possible number of verification threads.
 const MAX_VERIFIERS: usize = 8;
@@ -53,6 +58,12 @@ pub struct Config {
     /// Maximum heap memory to use.
     /// When the limit is reached, is_full returns true.
     pub max_mem_use: usize,
+    /// Maximum number of threads each verifier uses to recover the parcel senders of a block.
+    pub parcel_recovery_threads: usize,
+    /// Maximum number of items held until their timestamps are no longer in the future.
+    pub max_future_items: usize,
+    /// How far, in seconds, a timestamp can be ahead of the local clock.
+    pub allowed_future_drift: u64,
 }
 
 impl Default for Config {
@@ -60,25 +71,45 @@ impl Default for Config {
         Config {
             max_queue_size: 30000,
             max_mem_use: 50 * 1024 * 1024,
+            parcel_recovery_threads: 4,
+            max_future_items: 128,
+            allowed_future_drift: 15,
         }
     }
 }
 
+/// The local clock which the timestamps of the items are checked against.
+pub trait Clock: Send + Sync {
+    /// Seconds since the UNIX epoch.
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+    }
+}
+
 pub struct VerificationQueue<K: Kind> {
     engine: Arc<CodeChainEngine>,
     verification: Arc<Verification<K>>,
     processing: RwLock<HashMap<H256, U256>>, // hash to score
-    #[allow(dead_code)]
+    /// Items which passed the basic verification but are early, until the clock catches up.
+    future: Mutex<HashMap<H256, K::Unverified>>,
+    clock: Arc<Clock>,
     deleting: Arc<AtomicBool>,
     ready_signal: Arc<QueueSignal>,
     total_score: RwLock<U256>,
-    #[allow(dead_code)]
     empty: Arc<SCondvar>,
     more_to_verify: Arc<SCondvar>,
     #[allow(dead_code)]
     verifier_handles: Vec<JoinHandle<()>>,
     max_queue_size: usize,
     max_mem_use: usize,
+    max_future_items: usize,
+    allowed_future_drift: u64,
 }
 
 struct QueueSignal {
@@ -128,6 +159,16 @@ impl<K: Kind> VerificationQueue<K> {
         engine: Arc<CodeChainEngine>,
         message_channel: IoChannel<ClientIoMessage>,
         check_seal: bool,
+    ) -> Self {
+        Self::with_clock(config, engine, message_channel, check_seal, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(
+        config: Config,
+        engine: Arc<CodeChainEngine>,
+        message_channel: IoChannel<ClientIoMessage>,
+        check_seal: bool,
+        clock: Arc<Clock>,
     ) -> Self {
         let verification = Arc::new(Verification {
             unverified: Mutex::new(VecDeque::new()),
@@ -140,6 +181,8 @@ impl<K: Kind> VerificationQueue<K> {
                 verified: AtomicUsize::new(0),
             },
             check_seal,
+            recovery_threads: cmp::max(config.parcel_recovery_threads, 1),
+            rejections: Mutex::new(LruCache::new(MAX_REJECTIONS)),
             empty_mutex: SMutex::new(()),
             more_to_verify_mutex: SMutex::new(()),
         });
@@ -174,6 +217,8 @@ impl<K: Kind> VerificationQueue<K> {
             engine,
             verification,
             processing: RwLock::new(HashMap::new()),
+            future: Mutex::new(HashMap::new()),
+            clock,
             deleting,
             ready_signal,
             total_score: RwLock::new(0.into()),
@@ -182,6 +227,8 @@ impl<K: Kind> VerificationQueue<K> {
             verifier_handles,
             max_queue_size: cmp::max(config.max_queue_size, MIN_QUEUE_LIMIT),
             max_mem_use: cmp::max(config.max_mem_use, MIN_MEM_LIMIT),
+            max_future_items: config.max_future_items,
+            allowed_future_drift: config.allowed_future_drift,
         }
     }
 
@@ -198,8 +245,12 @@ impl<K: Kind> VerificationQueue<K> {
             {
                 let mut more_to_verify_mutex = verification.more_to_verify_mutex.lock().unwrap();
 
-                if verification.unverified.lock().is_empty() && verification.verifying.lock().is_empty() {
-                    empty.notify_all();
+                {
+                    // `flush` checks the queue while holding `empty_mutex`, so the notification is not lost.
+                    let _empty_mutex = verification.empty_mutex.lock().unwrap();
+                    if verification.unverified.lock().is_empty() && verification.verifying.lock().is_empty() {
+                        empty.notify_all();
+                    }
                 }
 
                 while verification.unverified.lock().is_empty() {
@@ -227,7 +278,7 @@ impl<K: Kind> VerificationQueue<K> {
             };
 
             let hash = item.hash();
-            let is_ready = match K::verify(item, &*engine, verification.check_seal) {
+            let is_ready = match K::verify(item, &*engine, verification.check_seal, verification.recovery_threads) {
                 Ok(verified) => {
                     let mut verifying = verification.verifying.lock();
                     let mut idx = None;
@@ -259,12 +310,13 @@ impl<K: Kind> VerificationQueue<K> {
                         false
                     }
                 }
-                Err(_) => {
+                Err(err) => {
                     let mut verifying = verification.verifying.lock();
                     let mut verified = verification.verified.lock();
                     let mut bad = verification.bad.lock();
 
                     bad.insert(hash.clone());
+                    verification.rejections.lock().insert(hash, err);
                     verifying.retain(|e| e.hash != hash);
 
                     if verifying.front().map_or(false, |x| x.output.is_some()) {
@@ -315,20 +367,24 @@ impl<K: Kind> VerificationQueue<K> {
 
     /// Check if the item is currently in the queue
     pub fn status(&self, hash: &H256) -> Status {
-        if self.processing.read().contains_key(hash) {
-            return Status::Queued
-        }
+        // An item rejected by the verifiers is still in `processing`.
         if self.verification.bad.lock().contains(hash) {
             return Status::Bad
         }
+        if self.processing.read().contains_key(hash) || self.future.lock().contains_key(hash) {
+            return Status::Queued
+        }
         Status::Unknown
     }
 
     /// Add a block to the queue.
+    ///
+    /// A block whose timestamp is a little ahead of the local clock is held
+    /// and enters the queue by `release_future` once the clock catches up.
     pub fn import(&self, input: K::Input) -> Result<H256, Error> {
         let h = input.hash();
         {
-            if self.processing.read().contains_key(&h) {
+            if self.processing.read().contains_key(&h) || self.future.lock().contains_key(&h) {
                 return Err(ImportError::AlreadyQueued.into())
             }
 
@@ -342,31 +398,129 @@ impl<K: Kind> VerificationQueue<K> {
                 return Err(ImportError::KnownBad.into())
             }
         }
-        match K::create(input, &*self.engine) {
-            Ok(item) => {
-                self.verification.sizes.unverified.fetch_add(item.heap_size_of_children(), AtomicOrdering::SeqCst);
+        let early = match verify_timestamp(input.timestamp(), self.clock.now(), self.allowed_future_drift) {
+            Ok(()) => None,
+            Err(err @ BlockError::TemporarilyInvalid(_)) => Some(err),
+            Err(err) => {
+                self.verification.bad.lock().insert(h);
+                return Err(err.into())
+            }
+        };
+        // The early items are verified first, so that invalid ones can't take the slots of the held items.
+        let item = self.create(input)?;
+        match early {
+            None => {
+                self.enqueue(item);
+                Ok(h)
+            }
+            Some(err) => self.hold(item, err),
+        }
+    }
 
-                self.processing.write().insert(h.clone(), item.score());
-                {
-                    let mut ts = self.total_score.write();
-                    *ts = *ts + item.score();
+    fn create(&self, input: K::Input) -> Result<K::Unverified, Error> {
+        let h = input.hash();
+        K::create(input, &*self.engine).map_err(|err| {
+            match err {
+                // The hash is of the header, so a broken body must not mark the block as bad.
+                Error::Decoder(_) => {}
+                _ => {
+                    self.verification.bad.lock().insert(h);
                 }
-
-                self.verification.unverified.lock().push_back(item);
-                self.more_to_verify.notify_all();
-                Ok(h)
             }
-            Err(err) => {
-                match err {
-                    // Don't mark future blocks as bad.
-                    Error::Block(BlockError::TemporarilyInvalid(_)) => {}
-                    _ => {
-                        self.verification.bad.lock().insert(h.clone());
-                    }
+            err
+        })
+    }
+
+    fn enqueue(&self, item: K::Unverified) {
+        self.verification.sizes.unverified.fetch_add(item.heap_size_of_children(), AtomicOrdering::SeqCst);
+
+        self.processing.write().insert(item.hash(), item.score());
+        {
+            let mut ts = self.total_score.write();
+            *ts = *ts + item.score();
+        }
+
+        self.verification.unverified.lock().push_back(item);
+        self.more_to_verify.notify_all();
+    }
+
+    fn hold(&self, item: K::Unverified, err: BlockError) -> Result<H256, Error> {
+        let h = item.hash();
+        let mut future = self.future.lock();
+        if future.len() >= self.max_future_items {
+            // Don't mark future blocks as bad.
+            return Err(err.into())
+        }
+        ctrace!(CLIENT, "{} is held until its timestamp {} is reached", h, item.timestamp());
+        future.insert(h, item);
+        Ok(h)
+    }
+
+    /// Moves the held items whose timestamps are no longer in the future into the queue.
+    /// Returns the number of the released items.
+    pub fn release_future(&self) -> usize {
+        let max_time = self.clock.now() + self.allowed_future_drift;
+        let mut due = {
+            let mut future = self.future.lock();
+            let hashes: Vec<H256> =
+                future.iter().filter(|&(_, item)| item.timestamp() <= max_time).map(|(hash, _)| *hash).collect();
+            hashes.iter().map(|hash| future.remove(hash).expect("The hash is collected above")).collect::<Vec<_>>()
+        };
+        // A child is always later than its parent.
+        due.sort_by_key(|item| item.timestamp());
+
+        let released = due.len();
+        for item in due {
+            {
+                let mut bad = self.verification.bad.lock();
+                if bad.contains(&item.parent_hash()) {
+                    cdebug!(CLIENT, "The held item {} is rejected because its parent is bad", item.hash());
+                    bad.insert(item.hash());
+                    continue
                 }
-                Err(err)
             }
+            self.enqueue(item);
         }
+        released
+    }
+
+    /// Drops the held items which descend from the blocks retracted by a reorganization.
+    pub fn retract_future(&self, retracted: &[H256]) {
+        let mut future = self.future.lock();
+        let mut dropped: HashSet<H256> = retracted.iter().cloned().collect();
+        loop {
+            let children: Vec<H256> = future
+                .iter()
+                .filter(|&(_, item)| dropped.contains(&item.parent_hash()))
+                .map(|(hash, _)| *hash)
+                .collect();
+            if children.is_empty() {
+                break
+            }
+            for hash in children {
+                ctrace!(CLIENT, "The held item {} is dropped because its parent is retracted", hash);
+                future.remove(&hash);
+                dropped.insert(hash);
+            }
+        }
+    }
+
+    /// Waits until every imported item is verified or rejected.
+    pub fn flush(&self) {
+        let mut lock = self.verification.empty_mutex.lock().unwrap();
+        while !self.verification.unverified.lock().is_empty() || !self.verification.verifying.lock().is_empty() {
+            lock = self.empty.wait(lock).unwrap();
+        }
+    }
+
+    /// Records the reason why the item is rejected after it left the queue.
+    pub fn reject(&self, hash: H256, error: Error) {
+        self.verification.rejections.lock().insert(hash, error);
+    }
+
+    /// Takes the reason why the item is rejected, if it is still kept.
+    pub fn take_rejection(&self, hash: &H256) -> Option<Error> {
+        self.verification.rejections.lock().remove(hash)
     }
 
     /// Removes up to `max` verified items from the queue
@@ -476,6 +630,18 @@ impl<K: Kind> VerificationQueue<K> {
     }
 }
 
+impl<K: Kind> Drop for VerificationQueue<K> {
+    fn drop(&mut self) {
+        self.deleting.store(true, AtomicOrdering::SeqCst);
+
+        let future = self.future.get_mut();
+        if !future.is_empty() {
+            cinfo!(CLIENT, "{} items held for their timestamps are dropped", future.len());
+            future.clear();
+        }
+    }
+}
+
 // the internal queue sizes.
 struct Sizes {
     unverified: AtomicUsize,
@@ -490,7 +656,8 @@ struct Verification<K: Kind> {
     bad: Mutex<HashSet<H256>>,
     sizes: Sizes,
     check_seal: bool,
-    #[allow(dead_code)]
+    recovery_threads: usize,
+    rejections: Mutex<LruCache<H256, Error>>,
     empty_mutex: SMutex<()>,
     more_to_verify_mutex: SMutex<()>,
 }
@@ -503,13 +670,19 @@ pub struct Verifying<K: Kind> {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use cio::IoChannel;
+    use ctypes::{H256, U256};
+    use parking_lot::Mutex;
     use tests::helpers::*;
 
-    use super::super::super::error::{Error, ImportError};
+    use super::super::super::error::{BlockError, Error, ImportError};
+    use super::super::super::header::Header;
     use super::super::super::spec::Spec;
+    use super::super::super::types::BlockStatus;
     use super::kind::blocks::Unverified;
-    use super::{BlockQueue, Config};
+    use super::{BlockQueue, Clock, Config};
 
     // create a test block queue.
     // auto_scaling enables verifier adjustment.
@@ -559,4 +732,134 @@ mod tests {
             }
         }
     }
+
+    struct MockClock(Mutex<u64>);
+
+    impl MockClock {
+        fn advance(&self, secs: u64) {
+            *self.0.lock() += secs;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> u64 {
+            *self.0.lock()
+        }
+    }
+
+    const NOW: u64 = 1_000_000;
+
+    fn get_test_queue_with_clock(max_future_items: usize) -> (BlockQueue, Arc<MockClock>) {
+        let spec = Spec::new_test();
+        let clock = Arc::new(MockClock(Mutex::new(NOW)));
+        let config = Config {
+            max_future_items,
+            allowed_future_drift: 5,
+            ..Config::default()
+        };
+        let queue = BlockQueue::with_clock(config, spec.engine, IoChannel::disconnected(), true, clock.clone());
+        (queue, clock)
+    }
+
+    fn block_at(parent_hash: H256, number: u64, timestamp: u64) -> (H256, Unverified) {
+        let mut header = Header::new();
+        header.set_score(U256::from(0x20000));
+        header.set_timestamp(timestamp);
+        header.set_number(number);
+        header.set_parent_hash(parent_hash);
+        (header.hash(), Unverified::new(create_test_block(&header)))
+    }
+
+    #[test]
+    fn future_blocks_are_imported_once_their_timestamps_are_reached() {
+        let (queue, clock) = get_test_queue_with_clock(8);
+        let genesis = Spec::new_test().genesis_header().hash();
+        let (hash, block) = block_at(genesis, 1, NOW + 10);
+
+        assert_eq!(hash, queue.import(block).unwrap());
+        assert_eq!(BlockStatus::Queued, queue.status(&hash));
+        assert_eq!(0, queue.release_future());
+        queue.flush();
+        assert!(queue.drain(1).is_empty());
+
+        clock.advance(4);
+        assert_eq!(0, queue.release_future());
+
+        clock.advance(1);
+        assert_eq!(1, queue.release_future());
+        queue.flush();
+        let verified = queue.drain(1);
+        assert_eq!(1, verified.len());
+        assert_eq!(hash, verified[0].header.hash());
+    }
+
+    #[test]
+    fn future_blocks_over_the_limit_are_not_marked_as_bad() {
+        let (queue, _) = get_test_queue_with_clock(1);
+        let genesis = Spec::new_test().genesis_header().hash();
+        let (first, first_block) = block_at(genesis, 1, NOW + 10);
+        let (second, second_block) = block_at(genesis, 1, NOW + 11);
+
+        assert_eq!(first, queue.import(first_block).unwrap());
+        match queue.import(second_block) {
+            Err(Error::Block(BlockError::TemporarilyInvalid(_))) => {}
+            result => panic!("The second block must not be held: {:?}", result),
+        }
+        assert_eq!(BlockStatus::Unknown, queue.status(&second));
+    }
+
+    #[test]
+    fn invalid_future_blocks_are_not_held() {
+        let (queue, _) = get_test_queue_with_clock(1);
+        let spec = Spec::new_test();
+        let genesis = spec.genesis_header().hash();
+        let mut header = Header::new();
+        header.set_number(1);
+        header.set_parent_hash(genesis);
+        header.set_timestamp(NOW + 10);
+        header.set_extra_data(vec![0; spec.engine.maximum_extra_data_size() + 1]);
+        let hash = header.hash();
+
+        match queue.import(Unverified::new(create_test_block(&header))) {
+            Err(Error::Block(BlockError::ExtraDataOutOfBounds(_))) => {}
+            result => panic!("The block must be invalid: {:?}", result),
+        }
+        assert_eq!(BlockStatus::Bad, queue.status(&hash));
+
+        let (valid, valid_block) = block_at(genesis, 1, NOW + 10);
+        assert_eq!(valid, queue.import(valid_block).unwrap());
+    }
+
+    #[test]
+    fn blocks_too_far_in_the_future_are_bad() {
+        let (queue, _) = get_test_queue_with_clock(8);
+        let genesis = Spec::new_test().genesis_header().hash();
+        let (hash, block) = block_at(genesis, 1, NOW + 100);
+
+        match queue.import(block) {
+            Err(Error::Block(BlockError::InvalidTimestamp(_))) => {}
+            result => panic!("The block must be invalid: {:?}", result),
+        }
+        assert_eq!(BlockStatus::Bad, queue.status(&hash));
+    }
+
+    #[test]
+    fn future_blocks_on_retracted_blocks_are_dropped() {
+        let (queue, clock) = get_test_queue_with_clock(8);
+        let retracted = H256::random();
+        let (child, child_block) = block_at(retracted, 2, NOW + 10);
+        let (grandchild, grandchild_block) = block_at(child, 3, NOW + 11);
+        let (other, other_block) = block_at(H256::random(), 2, NOW + 10);
+        queue.import(child_block).unwrap();
+        queue.import(grandchild_block).unwrap();
+        queue.import(other_block).unwrap();
+
+        queue.retract_future(&[retracted]);
+        assert_eq!(BlockStatus::Unknown, queue.status(&child));
+        assert_eq!(BlockStatus::Unknown, queue.status(&grandchild));
+        assert_eq!(BlockStatus::Queued, queue.status(&other));
+
+        clock.advance(10);
+        assert_eq!(1, queue.release_future());
+    }
 }
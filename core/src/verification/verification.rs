@@ -14,8 +14,6 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::time::{SystemTime, UNIX_EPOCH};
-
 use cmerkle::skewed_merkle_root;
 use ctypes::{Bytes, H256};
 use heapsize::HeapSizeOf;
@@ -27,7 +25,7 @@ use super::super::client::BlockInfo;
 use super::super::consensus::CodeChainEngine;
 use super::super::error::{BlockError, Error};
 use super::super::header::Header;
-use super::super::parcel::{SignedParcel, UnverifiedParcel};
+use super::super::parcel::{recover_senders, SignedParcel, UnverifiedParcel};
 use super::super::types::BlockNumber;
 use super::super::views::BlockView;
 
@@ -50,6 +48,7 @@ impl HeapSizeOf for PreverifiedBlock {
 /// Phase 1 quick block verification. Only does checks that are cheap. Operates on a single block
 pub fn verify_block_basic(header: &Header, bytes: &[u8], engine: &CodeChainEngine) -> Result<(), Error> {
     verify_header_params(&header, engine)?;
+    verify_body_size(&UntrustedRlp::new(bytes).at(1)?, header.number(), engine)?;
     engine.verify_block_basic(&header)?;
 
     for t in UntrustedRlp::new(bytes).at(1)?.iter().map(|rlp| rlp.as_val::<UnverifiedParcel>()) {
@@ -75,6 +74,14 @@ pub fn verify_header_params(header: &Header, engine: &CodeChainEngine) -> Result
             found: header.number(),
         })))
     }
+    let shard_count = engine.params().shard_count as usize;
+    if header.shard_roots().len() != shard_count {
+        return Err(From::from(BlockError::InvalidShardRootCount(Mismatch {
+            expected: shard_count,
+            found: header.shard_roots().len(),
+        })))
+    }
+
     let maximum_extra_data_size = engine.maximum_extra_data_size();
     if header.number() != 0 && header.extra_data().len() > maximum_extra_data_size {
         return Err(From::from(BlockError::ExtraDataOutOfBounds(OutOfBounds {
@@ -84,31 +91,57 @@ pub fn verify_header_params(header: &Header, engine: &CodeChainEngine) -> Result
         })))
     }
 
-    const ACCEPTABLE_DRIFT_SECS: u64 = 15;
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
-    let max_time = now.as_secs() + ACCEPTABLE_DRIFT_SECS;
-    let invalid_threshold = max_time + ACCEPTABLE_DRIFT_SECS * 9;
-    let timestamp = header.timestamp();
+    Ok(())
+}
+
+/// Check the timestamp against the local clock, `now` in seconds since the UNIX epoch.
+/// A timestamp up to `allowed_drift` seconds ahead of the clock is accepted,
+/// and one a little further ahead is only temporarily invalid.
+pub fn verify_timestamp(timestamp: u64, now: u64, allowed_drift: u64) -> Result<(), BlockError> {
+    let max_time = now + allowed_drift;
+    let invalid_threshold = max_time + allowed_drift * 9;
 
     if timestamp > invalid_threshold {
-        return Err(From::from(BlockError::InvalidTimestamp(OutOfBounds {
+        return Err(BlockError::InvalidTimestamp(OutOfBounds {
             max: Some(max_time),
             min: None,
             found: timestamp,
-        })))
+        }))
     }
 
     if timestamp > max_time {
-        return Err(From::from(BlockError::TemporarilyInvalid(OutOfBounds {
+        return Err(BlockError::TemporarilyInvalid(OutOfBounds {
             max: Some(max_time),
             min: None,
             found: timestamp,
-        })))
+        }))
     }
 
     Ok(())
 }
 
+/// Check the size and the number of parcels of the block body against the limits used by the miner.
+fn verify_body_size(body: &UntrustedRlp, block_number: BlockNumber, engine: &CodeChainEngine) -> Result<(), Error> {
+    let params = engine.params().at(block_number);
+    let body_size = body.as_raw().len();
+    if body_size > params.max_body_size {
+        return Err(From::from(BlockError::BodySizeOutOfBounds(OutOfBounds {
+            min: None,
+            max: Some(params.max_body_size),
+            found: body_size,
+        })))
+    }
+    let parcel_count = body.item_count()?;
+    if parcel_count > params.max_parcels_per_block {
+        return Err(From::from(BlockError::ParcelCountOutOfBounds(OutOfBounds {
+            min: None,
+            max: Some(params.max_parcels_per_block),
+            found: parcel_count,
+        })))
+    }
+    Ok(())
+}
+
 /// Verify block data against header: parcels root
 fn verify_parcels_root(block: &[u8], parcels_root: &H256, parent_parcels_root: H256) -> Result<(), Error> {
     let block = UntrustedRlp::new(block);
@@ -125,30 +158,29 @@ fn verify_parcels_root(block: &[u8], parcels_root: &H256, parent_parcels_root: H
 
 /// Phase 2 verification. Perform costly checks such as parcel signatures and block nonce for ethash.
 /// Still operates on a individual block
-/// Returns a `PreverifiedBlock` structure populated with parcels
+/// Returns the verified parcels, which populate a `PreverifiedBlock` with the header and the bytes.
+/// The bytes are borrowed, so that the caller can keep them for a crash dump meanwhile.
+/// Senders are recovered with at most `recovery_threads` threads.
 pub fn verify_block_unordered(
-    header: Header,
-    bytes: Bytes,
+    header: &Header,
+    bytes: &[u8],
     engine: &CodeChainEngine,
     check_seal: bool,
-) -> Result<PreverifiedBlock, Error> {
+    recovery_threads: usize,
+) -> Result<Vec<SignedParcel>, Error> {
     if check_seal {
-        engine.verify_block_unordered(&header)?;
+        engine.verify_block_unordered(header)?;
     }
     // Verify parcels.
     let mut parcels = Vec::new();
-    {
-        let v = BlockView::new(&bytes);
-        for t in v.parcels() {
-            let signed_parcel = engine.verify_parcel_unordered(t, &header)?;
-            parcels.push(signed_parcel);
-        }
+    let v = BlockView::new(bytes);
+    let unverified = v.parcels();
+    recover_senders(&unverified, recovery_threads)?;
+    for t in unverified {
+        let signed_parcel = engine.verify_parcel_unordered(t, header)?;
+        parcels.push(signed_parcel);
     }
-    Ok(PreverifiedBlock {
-        header,
-        parcels,
-        bytes,
-    })
+    Ok(parcels)
 }
 
 /// Parameters for full verification of block family
@@ -238,5 +270,19 @@ pub fn verify_block_final(expected: &Header, got: &Header) -> Result<(), Error>
             found: got.invoices_root().clone(),
         })))
     }
+    if expected.shard_roots().len() != got.shard_roots().len() {
+        return Err(From::from(BlockError::InvalidShardRootCount(Mismatch {
+            expected: expected.shard_roots().len(),
+            found: got.shard_roots().len(),
+        })))
+    }
+    for (expected_root, got_root) in expected.shard_roots().iter().zip(got.shard_roots()) {
+        if expected_root != got_root {
+            return Err(From::from(BlockError::InvalidShardRoot(Mismatch {
+                expected: *expected_root,
+                found: *got_root,
+            })))
+        }
+    }
     Ok(())
 }
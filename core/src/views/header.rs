@@ -95,10 +95,15 @@ impl<'a> HeaderView<'a> {
         self.rlp.val_at(8)
     }
 
+    /// Returns the state roots of the shards.
+    pub fn shard_roots(&self) -> Vec<H256> {
+        self.rlp.list_at(9)
+    }
+
     /// Returns a vector of post-RLP-encoded seal fields.
     pub fn seal(&self) -> Vec<Bytes> {
         let mut seal = vec![];
-        for i in 9..self.rlp.item_count() {
+        for i in 10..self.rlp.item_count() {
             seal.push(self.rlp.at(i).as_raw().to_vec());
         }
         seal
@@ -30,7 +30,7 @@ pub use self::account::Account;
 pub use self::engine::Engine;
 pub use self::genesis::Genesis;
 pub use self::null_engine::{NullEngine, NullEngineParams};
-pub use self::params::Params;
+pub use self::params::{Params, ParamsTransition};
 pub use self::seal::{Seal, TendermintSeal};
 pub use self::solo::{Solo, SoloParams};
 pub use self::solo_authority::{SoloAuthority, SoloAuthorityParams};
@@ -22,12 +22,40 @@ pub struct Params {
     /// Maximum size of extra data.
     #[serde(rename = "maximumExtraDataSize")]
     pub maximum_extra_data_size: Uint,
+    /// Maximum size of block body.
+    #[serde(rename = "maxBodySize")]
+    pub max_body_size: Uint,
+    /// Maximum number of parcels in a block.
+    #[serde(rename = "maxParcelsPerBlock")]
+    pub max_parcels_per_block: Uint,
     /// Network id.
     #[serde(rename = "networkID")]
     pub network_id: Uint,
     /// Minimum parcel cost.
     #[serde(rename = "minParcelCost")]
     pub min_parcel_cost: Uint,
+    /// Number of shards.
+    #[serde(rename = "shardCount")]
+    pub shard_count: Uint,
+    /// Overrides of the params from a block on.
+    pub transitions: Option<Vec<ParamsTransition>>,
+}
+
+/// Overrides of the spec params from a block on.
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct ParamsTransition {
+    /// The first block the params are overridden for.
+    #[serde(rename = "blockNumber")]
+    pub block_number: Uint,
+    /// Maximum size of block body.
+    #[serde(rename = "maxBodySize")]
+    pub max_body_size: Option<Uint>,
+    /// Maximum number of parcels in a block.
+    #[serde(rename = "maxParcelsPerBlock")]
+    pub max_parcels_per_block: Option<Uint>,
+    /// Minimum parcel cost.
+    #[serde(rename = "minParcelCost")]
+    pub min_parcel_cost: Option<Uint>,
 }
 
 #[cfg(test)]
@@ -36,19 +64,53 @@ mod tests {
     use serde_json;
 
     use super::super::super::uint::Uint;
-    use super::Params;
+    use super::{Params, ParamsTransition};
 
     #[test]
     fn params_deserialization() {
         let s = r#"{
 			"maximumExtraDataSize": "0x20",
+			"maxBodySize" : "0x400000",
+			"maxParcelsPerBlock" : "0x1000",
 			"networkID" : "0x1",
-			"minParcelCost" : "10"
+			"minParcelCost" : "10",
+			"shardCount" : "0x1"
 		}"#;
 
         let deserialized: Params = serde_json::from_str(s).unwrap();
         assert_eq!(deserialized.maximum_extra_data_size, Uint(U256::from(0x20)));
+        assert_eq!(deserialized.max_body_size, Uint(U256::from(0x400000)));
+        assert_eq!(deserialized.max_parcels_per_block, Uint(U256::from(0x1000)));
         assert_eq!(deserialized.network_id, Uint(U256::from(0x1)));
         assert_eq!(deserialized.min_parcel_cost, Uint(U256::from(10)));
+        assert_eq!(deserialized.shard_count, Uint(U256::from(0x1)));
+        assert_eq!(deserialized.transitions, None);
+    }
+
+    #[test]
+    fn params_with_transitions_deserialization() {
+        let s = r#"{
+			"maximumExtraDataSize": "0x20",
+			"maxBodySize" : "0x400000",
+			"maxParcelsPerBlock" : "0x1000",
+			"networkID" : "0x1",
+			"minParcelCost" : "10",
+			"shardCount" : "0x1",
+			"transitions": [{
+				"blockNumber": "0x64",
+				"minParcelCost": "20"
+			}]
+		}"#;
+
+        let deserialized: Params = serde_json::from_str(s).unwrap();
+        assert_eq!(
+            deserialized.transitions,
+            Some(vec![ParamsTransition {
+                block_number: Uint(U256::from(100)),
+                max_body_size: None,
+                max_parcels_per_block: None,
+                min_parcel_cost: Some(Uint(U256::from(20))),
+            }])
+        );
     }
 }
@@ -74,8 +74,11 @@ mod tests {
 	},
 	"params": {
 		"maximumExtraDataSize": "0x20",
+		"maxBodySize" : "0x400000",
+		"maxParcelsPerBlock" : "0x1000",
 		"networkID" : "0x2",
-		"minParcelCost" : "10"
+		"minParcelCost" : "10",
+		"shardCount" : "0x1"
 	},
 	"genesis": {
 		"seal": {
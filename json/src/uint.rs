@@ -43,6 +43,11 @@ impl Into<usize> for Uint {
         u64::from(self.0) as usize
     }
 }
+impl Into<u32> for Uint {
+    fn into(self) -> u32 {
+        u64::from(self.0) as u32
+    }
+}
 impl Into<u8> for Uint {
     fn into(self) -> u8 {
         u64::from(self.0) as u8
@@ -16,6 +16,7 @@
 
 use bech32::Error as Bech32Error;
 use secp256k1::Error as SecpError;
+use std::error;
 use std::fmt;
 
 #[derive(Debug, PartialEq)]
@@ -65,6 +66,12 @@ impl fmt::Display for Error {
     }
 }
 
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Key error"
+    }
+}
+
 impl Into<String> for Error {
     fn into(self) -> String {
         format!("{}", self)
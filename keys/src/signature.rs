@@ -36,155 +36,14 @@ use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 
-use codechain_types::{H256, H512, H520};
+use codechain_types::{H512, Signature};
 use rustc_hex::{FromHex, ToHex};
 use secp256k1::{key, schnorr, Error as SecpError, Message as SecpMessage, RecoverableSignature, RecoveryId};
 
 use super::{public_to_address, Address, Error, Message, Private, Public, SECP256K1};
 
-/// Signature encoded as RSV components
-#[repr(C)]
-pub struct ECDSASignature([u8; 65]);
-
-impl ECDSASignature {
-    /// Get a slice into the 'r' portion of the data.
-    pub fn r(&self) -> &[u8] {
-        &self.0[0..32]
-    }
-
-    /// Get a slice into the 's' portion of the data.
-    pub fn s(&self) -> &[u8] {
-        &self.0[32..64]
-    }
-
-    /// Get the recovery byte.
-    pub fn v(&self) -> u8 {
-        self.0[64]
-    }
-
-    /// Create a signature object from the sig.
-    pub fn from_rsv(r: &H256, s: &H256, v: u8) -> Self {
-        let mut sig = [0u8; 65];
-        sig[0..32].copy_from_slice(&r);
-        sig[32..64].copy_from_slice(&s);
-        sig[64] = v;
-        ECDSASignature(sig)
-    }
-
-    /// Check if this is a "low" signature.
-    pub fn is_low_s(&self) -> bool {
-        H256::from_slice(self.s()) <= "7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF5D576E7357A4501DDFE92F46681B20A0".into()
-    }
-
-    /// Check if each component of the signature is in range.
-    pub fn is_valid(&self) -> bool {
-        self.v() <= 1
-            && H256::from_slice(self.r()) < "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141".into()
-            && H256::from_slice(self.r()) >= 1.into()
-            && H256::from_slice(self.s()) < "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141".into()
-            && H256::from_slice(self.s()) >= 1.into()
-    }
-}
-
-// manual implementation large arrays don't have trait impls by default.
-// remove when integer generics exist
-impl PartialEq for ECDSASignature {
-    fn eq(&self, other: &Self) -> bool {
-        &self.0[..] == &other.0[..]
-    }
-}
-
-// manual implementation required in Rust 1.13+, see `std::cmp::AssertParamIsEq`.
-impl Eq for ECDSASignature {}
-
-// also manual for the same reason, but the pretty printing might be useful.
-impl fmt::Debug for ECDSASignature {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        f.debug_struct("Signature")
-            .field("r", &self.0[0..32].to_hex())
-            .field("s", &self.0[32..64].to_hex())
-            .field("v", &self.0[64..65].to_hex())
-            .finish()
-    }
-}
-
-impl fmt::Display for ECDSASignature {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}", self.to_hex())
-    }
-}
-
-impl FromStr for ECDSASignature {
-    type Err = SecpError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.from_hex() {
-            Ok(ref hex) if hex.len() == 65 => {
-                let mut data = [0; 65];
-                data.copy_from_slice(&hex[0..65]);
-                Ok(ECDSASignature(data))
-            }
-            _ => Err(SecpError::InvalidSignature),
-        }
-    }
-}
-
-impl Default for ECDSASignature {
-    fn default() -> Self {
-        ECDSASignature([0; 65])
-    }
-}
-
-impl Hash for ECDSASignature {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        H520::from(self.0).hash(state);
-    }
-}
-
-impl Clone for ECDSASignature {
-    fn clone(&self) -> Self {
-        ECDSASignature(self.0)
-    }
-}
-
-impl From<[u8; 65]> for ECDSASignature {
-    fn from(s: [u8; 65]) -> Self {
-        ECDSASignature(s)
-    }
-}
-
-impl Into<[u8; 65]> for ECDSASignature {
-    fn into(self) -> [u8; 65] {
-        self.0
-    }
-}
-
-impl From<ECDSASignature> for H520 {
-    fn from(s: ECDSASignature) -> Self {
-        H520::from(s.0)
-    }
-}
-
-impl From<H520> for ECDSASignature {
-    fn from(bytes: H520) -> Self {
-        ECDSASignature(bytes.into())
-    }
-}
-
-impl Deref for ECDSASignature {
-    type Target = [u8; 65];
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl DerefMut for ECDSASignature {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
-
+/// Recoverable ECDSA signature, see `codechain_types::Signature`.
+pub type ECDSASignature = Signature;
 
 pub fn sign_ecdsa(private: &Private, message: &Message) -> Result<ECDSASignature, Error> {
     let context = &SECP256K1;
@@ -196,10 +55,13 @@ pub fn sign_ecdsa(private: &Private, message: &Message) -> Result<ECDSASignature
     // no need to check if s is low, it always is
     data_arr[0..64].copy_from_slice(&data[0..64]);
     data_arr[64] = rec_id.to_i32() as u8;
-    Ok(ECDSASignature(data_arr))
+    Ok(ECDSASignature::from(data_arr))
 }
 
 pub fn verify_ecdsa(public: &Public, signature: &ECDSASignature, message: &Message) -> Result<bool, Error> {
+    if !signature.is_canonical() {
+        return Err(Error::InvalidSignature)
+    }
     let context = &SECP256K1;
     let rsig =
         RecoverableSignature::from_compact(context, &signature[0..64], RecoveryId::from_i32(signature[64] as i32)?)?;
@@ -226,6 +88,9 @@ pub fn verify_ecdsa_address(address: &Address, signature: &ECDSASignature, messa
 }
 
 pub fn recover_ecdsa(signature: &ECDSASignature, message: &Message) -> Result<Public, Error> {
+    if !signature.is_canonical() {
+        return Err(Error::InvalidSignature)
+    }
     let context = &SECP256K1;
     let rsig =
         RecoverableSignature::from_compact(context, &signature[0..64], RecoveryId::from_i32(signature[64] as i32)?)?;
@@ -379,10 +244,20 @@ pub fn recover_schnorr(signature: &SchnorrSignature, message: &Message) -> Resul
 
 #[cfg(test)]
 mod tests {
-    use super::super::{Generator, Message, Random};
+    use codechain_types::{H256, U256};
+    use rlp;
+
+    use super::super::{Error, Generator, Message, Random};
     use super::{recover_ecdsa, sign_ecdsa, verify_ecdsa, verify_ecdsa_address, ECDSASignature};
     use std::str::FromStr;
 
+    /// Turns a signature into the other valid signature of the same message, which has a high 's' value.
+    fn malleate(signature: &ECDSASignature) -> ECDSASignature {
+        let order = U256::from("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141");
+        let s = H256::from(order - U256::from(signature.s()));
+        ECDSASignature::from_rsv(&H256::from_slice(signature.r()), &s, signature.v() ^ 1)
+    }
+
     #[test]
     fn signature_to_and_from_str() {
         let keypair = Random.generate().unwrap();
@@ -416,4 +291,25 @@ mod tests {
         let signature = sign_ecdsa(keypair.private(), &message).unwrap();
         assert!(verify_ecdsa_address(&keypair.address(), &signature, &message).unwrap());
     }
+
+    #[test]
+    fn signature_rlp_round_trip() {
+        let keypair = Random.generate().unwrap();
+        let signature = sign_ecdsa(keypair.private(), &Message::default()).unwrap();
+        assert_eq!(signature, rlp::decode(&rlp::encode(&signature)));
+    }
+
+    #[test]
+    fn high_s_signature_is_rejected() {
+        let keypair = Random.generate().unwrap();
+        let message = Message::default();
+        let signature = sign_ecdsa(keypair.private(), &message).unwrap();
+        assert!(signature.is_canonical());
+
+        let malleated = malleate(&signature);
+        assert!(malleated.is_valid());
+        assert!(!malleated.is_canonical());
+        assert_eq!(Err(Error::InvalidSignature), recover_ecdsa(&malleated, &message));
+        assert_eq!(Err(Error::InvalidSignature), verify_ecdsa(keypair.public(), &malleated, &message));
+    }
 }
@@ -17,6 +17,7 @@
 use ccrypto;
 use ccrypto::Error as CCryptoError;
 use ckeys::Error as CKeysError;
+use std::error;
 use std::fmt;
 use std::io::Error as IoError;
 
@@ -64,6 +65,21 @@ impl fmt::Display for Error {
     }
 }
 
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Account error"
+    }
+
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            Error::CKeys(ref err) => Some(err),
+            Error::CCrypto(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
 impl From<IoError> for Error {
     fn from(err: IoError) -> Self {
         Error::Io(err)
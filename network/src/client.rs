@@ -53,6 +53,15 @@ impl Api for ClientApi {
         }
     }
 
+    fn disconnect(&self, id: &NodeId) {
+        let node_id = *id;
+        if let Err(err) = self.p2p_channel.send(P2pMessage::Disconnect {
+            node_id,
+        }) {
+            cwarn!(NETAPI, "Cannot disconnect {:?} : {:?}", id, err);
+        }
+    }
+
     fn set_timer(&self, timer_id: usize, duration: Duration) -> NetworkExtensionResult<()> {
         if let Some(extension) = self.extension.upgrade() {
             let extension_name = extension.name();
@@ -217,6 +226,10 @@ mod tests {
             unimplemented!()
         }
 
+        fn disconnect(&self, _id: &NodeId) {
+            unimplemented!()
+        }
+
         fn set_timer(&self, _timer_id: usize, _duration: Duration) -> NetworkExtensionResult<()> {
             unimplemented!()
         }
@@ -0,0 +1,252 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use unexpected::Mismatch;
+
+use super::{IntoSocketAddr, NodeId, SocketAddr};
+
+/// A peer connected by a dial.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Connected {
+    pub node_id: NodeId,
+    /// The extensions the peer accepted, with the negotiated versions.
+    pub extensions: Vec<(String, u64)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DialError {
+    /// The handshake didn't finish in time.
+    Timeout,
+    /// The peer closed the connection before the handshake finished.
+    Refused,
+    /// The peer denied the session.
+    HandshakeDenied(String),
+    /// The peer is not the expected node.
+    NodeIdMismatch(Mismatch<NodeId>),
+    AlreadyConnected,
+    /// The maximum number of peers are connected already.
+    TooManyPeers,
+    /// The network service is not running.
+    Stopped,
+}
+
+impl fmt::Display for DialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DialError::Timeout => write!(f, "The handshake timed out"),
+            DialError::Refused => write!(f, "The connection is refused"),
+            DialError::HandshakeDenied(reason) => write!(f, "The handshake is denied: {}", reason),
+            DialError::NodeIdMismatch(mismatch) => {
+                write!(f, "Unexpected node id: expected {}, found {}", mismatch.expected, mismatch.found)
+            }
+            DialError::AlreadyConnected => write!(f, "Already connected"),
+            DialError::TooManyPeers => write!(f, "Too many peers are connected"),
+            DialError::Stopped => write!(f, "The network service is stopped"),
+        }
+    }
+}
+
+pub type DialResult = Result<Connected, DialError>;
+
+struct Dial {
+    expected_node_id: Option<NodeId>,
+    waiters: Vec<Sender<DialResult>>,
+    /// The number of negotiations which are not answered yet. `None` until they are requested.
+    pending_negotiations: Option<usize>,
+    extensions: Vec<(String, u64)>,
+}
+
+/// The dials waiting for the outcome of their handshakes, keyed by the address of the peer.
+///
+/// The session initiator and the p2p handler report the progress of the handshake here,
+/// and the waiters are notified once it succeeds or fails.
+pub struct Dials {
+    dials: Mutex<HashMap<SocketAddr, Dial>>,
+}
+
+impl Dials {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            dials: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Starts waiting for the handshake with `address`.
+    pub fn start(&self, address: SocketAddr, expected_node_id: Option<NodeId>) -> Receiver<DialResult> {
+        let (sender, receiver) = channel();
+        let mut dials = self.dials.lock();
+        let dial = dials.entry(address).or_insert_with(|| Dial {
+            expected_node_id,
+            waiters: Vec::new(),
+            pending_negotiations: None,
+            extensions: Vec::new(),
+        });
+        dial.waiters.push(sender);
+        receiver
+    }
+
+    pub fn contains(&self, address: &SocketAddr) -> bool {
+        self.dials.lock().contains_key(address)
+    }
+
+    /// Stops waiting for `address` without notifying anyone.
+    pub fn cancel(&self, address: &SocketAddr) {
+        self.dials.lock().remove(address);
+    }
+
+    /// Fails the dial to `address`. Returns false if there is no such dial.
+    pub fn fail(&self, address: &SocketAddr, error: DialError) -> bool {
+        match self.dials.lock().remove(address) {
+            Some(dial) => {
+                notify(dial.waiters, Err(error));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records that `count` negotiations are requested to the peer.
+    pub fn negotiations_requested(&self, node_id: &NodeId, count: usize) {
+        let address = node_id.into_addr();
+        let mut dials = self.dials.lock();
+        let is_done = match dials.get_mut(&address) {
+            Some(dial) => {
+                dial.pending_negotiations = Some(count);
+                count == 0
+            }
+            None => return,
+        };
+        if is_done {
+            let dial = dials.remove(&address).expect("The dial is found above");
+            succeed(dial, *node_id);
+        }
+    }
+
+    /// Records the answer to a negotiation. `version` is `None` if the peer denied the extension.
+    pub fn negotiated(&self, node_id: &NodeId, extension_name: String, version: Option<u64>) {
+        let address = node_id.into_addr();
+        let mut dials = self.dials.lock();
+        let is_done = match dials.get_mut(&address) {
+            Some(dial) => match dial.pending_negotiations {
+                Some(ref mut pending) if *pending > 0 => {
+                    *pending -= 1;
+                    if let Some(version) = version {
+                        dial.extensions.push((extension_name, version));
+                    }
+                    *pending == 0
+                }
+                _ => false,
+            },
+            None => return,
+        };
+        if is_done {
+            let dial = dials.remove(&address).expect("The dial is found above");
+            succeed(dial, *node_id);
+        }
+    }
+}
+
+fn succeed(dial: Dial, node_id: NodeId) {
+    let result = match dial.expected_node_id {
+        Some(expected) if expected != node_id => Err(DialError::NodeIdMismatch(Mismatch {
+            expected,
+            found: node_id,
+        })),
+        _ => {
+            let mut extensions = dial.extensions;
+            extensions.sort();
+            Ok(Connected {
+                node_id,
+                extensions,
+            })
+        }
+    };
+    notify(dial.waiters, result);
+}
+
+fn notify(waiters: Vec<Sender<DialResult>>, result: DialResult) {
+    for waiter in waiters {
+        // The waiter may have timed out already.
+        let _ = waiter.send(result.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> (NodeId, SocketAddr) {
+        let address = SocketAddr::v4(127, 0, 0, 1, 3485);
+        (address.into(), address)
+    }
+
+    #[test]
+    fn dial_succeeds_when_every_negotiation_is_answered() {
+        let (node_id, address) = peer();
+        let dials = Dials::new();
+        let receiver = dials.start(address, None);
+
+        dials.negotiations_requested(&node_id, 2);
+        dials.negotiated(&node_id, "sync".to_string(), Some(1));
+        assert!(receiver.try_recv().is_err());
+        dials.negotiated(&node_id, "discovery".to_string(), None);
+
+        assert_eq!(
+            Ok(Connected {
+                node_id,
+                extensions: vec![("sync".to_string(), 1)],
+            }),
+            receiver.try_recv().unwrap()
+        );
+        assert!(!dials.contains(&address));
+    }
+
+    #[test]
+    fn dial_to_an_unexpected_node_fails() {
+        let (node_id, address) = peer();
+        let expected = SocketAddr::v4(127, 0, 0, 1, 3486).into();
+        let dials = Dials::new();
+        let receiver = dials.start(address, Some(expected));
+
+        dials.negotiations_requested(&node_id, 0);
+        assert_eq!(
+            Err(DialError::NodeIdMismatch(Mismatch {
+                expected,
+                found: node_id,
+            })),
+            receiver.try_recv().unwrap()
+        );
+    }
+
+    #[test]
+    fn every_waiter_is_notified_of_a_failure() {
+        let (_, address) = peer();
+        let dials = Dials::new();
+        let first = dials.start(address, None);
+        let second = dials.start(address, None);
+
+        assert!(dials.fail(&address, DialError::Refused));
+        assert_eq!(Err(DialError::Refused), first.try_recv().unwrap());
+        assert_eq!(Err(DialError::Refused), second.try_recv().unwrap());
+        assert!(!dials.fail(&address, DialError::Refused));
+    }
+}
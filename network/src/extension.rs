@@ -42,6 +42,8 @@ pub type Result<T> = result::Result<T, Error>;
 
 pub trait Api: Send + Sync {
     fn send(&self, node: &NodeId, message: &[u8]);
+    /// Closes the connection to the node, which is removed from every extension.
+    fn disconnect(&self, node: &NodeId);
 
     fn set_timer(&self, timer: TimerToken, d: Duration) -> Result<()>;
     fn set_timer_once(&self, timer: TimerToken, d: Duration) -> Result<()>;
@@ -41,6 +41,7 @@ extern crate codechain_logger as clogger;
 mod addr;
 mod client;
 mod config;
+mod dial;
 mod discovery;
 mod extension;
 mod limited_table;
@@ -57,6 +58,7 @@ pub mod session;
 
 pub use self::addr::SocketAddr;
 pub use self::config::Config as NetworkConfig;
+pub use self::dial::{Connected as DialConnected, DialError};
 pub use self::discovery::Api as DiscoveryApi;
 pub use self::extension::{
     Api, Error as NetworkExtensionError, Extension as NetworkExtension, Result as NetworkExtensionResult, TimerToken,
@@ -29,6 +29,7 @@ use unexpected::Mismatch;
 
 use super::super::addr::convert_to_node_id;
 use super::super::client::Client;
+use super::super::dial::{DialError, Dials};
 use super::super::token_generator::TokenGenerator;
 use super::super::RoutingTable;
 use super::super::{IntoSocketAddr, NodeId, SocketAddr};
@@ -45,6 +46,7 @@ struct Manager {
 
     routing_table: Arc<RoutingTable>,
     connections: Connections,
+    dials: Arc<Dials>,
 
     port: u16,
 }
@@ -72,6 +74,9 @@ pub enum Message {
         need_encryption: bool,
         data: Vec<u8>,
     },
+    Disconnect {
+        node_id: NodeId,
+    },
 }
 
 #[derive(Debug)]
@@ -98,7 +103,7 @@ impl ::std::fmt::Display for Error {
 }
 
 impl Manager {
-    pub fn listen(socket_address: &SocketAddr, routing_table: Arc<RoutingTable>) -> io::Result<Self> {
+    pub fn listen(socket_address: &SocketAddr, routing_table: Arc<RoutingTable>, dials: Arc<Dials>) -> io::Result<Self> {
         Ok(Manager {
             listener: Listener::bind(&socket_address)?,
 
@@ -106,6 +111,7 @@ impl Manager {
 
             routing_table,
             connections: Connections::new(),
+            dials,
 
             port: socket_address.port(),
         })
@@ -259,14 +265,16 @@ impl Manager {
                         if let Some(name) = self.connections.remove_requested_negotiation(stream, &seq) {
                             let node_id = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
                             client.on_node_added(&name, &node_id, *extension_version);
+                            self.dials.negotiated(&node_id, name, Some(*extension_version));
                         } else {
                             ctrace!(NET, "Negotiation::Allowed message received from non requested seq");
                         }
                     }
                     NegotiationBody::Denied => {
                         let seq = msg.seq();
-                        if let Some(_) = self.connections.remove_requested_negotiation(stream, &seq) {
-                            self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
+                        if let Some(name) = self.connections.remove_requested_negotiation(stream, &seq) {
+                            let node_id = self.connections.node_id(&stream).ok_or(Error::InvalidStream(*stream))?;
+                            self.dials.negotiated(&node_id, name, None);
                         } else {
                             ctrace!(NET, "Negotiation::Denied message received from non requested seq");
                         }
@@ -311,13 +319,15 @@ impl Handler {
         socket_address: SocketAddr,
         client: Arc<Client>,
         routing_table: Arc<RoutingTable>,
+        dials: Arc<Dials>,
         min_peers: usize,
         max_peers: usize,
     ) -> ::std::result::Result<Self, String> {
         if MAX_CONNECTIONS < max_peers {
             return Err(format!("Max peers must be less than {}", MAX_CONNECTIONS))
         }
-        let manager = Mutex::new(Manager::listen(&socket_address, routing_table).expect("Cannot listen TCP port"));
+        let manager =
+            Mutex::new(Manager::listen(&socket_address, routing_table, dials).expect("Cannot listen TCP port"));
         debug_assert!(max_peers < MAX_CONNECTIONS);
         Ok(Self {
             socket_address,
@@ -371,11 +381,26 @@ impl IoHandler<Message> for Handler {
                 let number_of_connections = manager.connections.len();
                 if self.max_peers <= manager.connections.len() {
                     ctrace!(NET, "Already has maximum peers({})", number_of_connections);
+                    manager.dials.fail(socket_address, DialError::TooManyPeers);
+                    return Ok(())
+                }
+                if manager.routing_table.is_established(socket_address) {
+                    ctrace!(NET, "Already connected to {:?}", socket_address);
                     return Ok(())
                 }
 
                 ctrace!(NET, "Connecting to {:?}", socket_address);
-                let token = manager.connect(&socket_address)?.ok_or(Error::General("Cannot create connection"))?;
+                let token = match manager.connect(&socket_address) {
+                    Ok(Some(token)) => token,
+                    Ok(None) => {
+                        manager.dials.fail(socket_address, DialError::Refused);
+                        return Err(Error::General("Cannot create connection").into())
+                    }
+                    Err(err) => {
+                        manager.dials.fail(socket_address, DialError::Refused);
+                        return Err(err)
+                    }
+                };
                 io.register_stream(token)?;
                 Ok(())
             }
@@ -384,6 +409,7 @@ impl IoHandler<Message> for Handler {
             } => {
                 let mut manager = self.manager.lock();
                 let versions = self.client.extension_versions();
+                manager.dials.negotiations_requested(node_id, versions.len());
                 for (extension_name, versions) in versions.into_iter() {
                     let token = manager.connections.stream_token(&node_id).ok_or(Error::InvalidNode(*node_id))?;
                     if !manager.connections.enqueue_negotiation_request(&token, extension_name, versions) {
@@ -407,6 +433,17 @@ impl IoHandler<Message> for Handler {
                 io.update_registration(token)?;
                 Ok(())
             }
+            Message::Disconnect {
+                node_id,
+            } => {
+                let manager = self.manager.lock();
+                let token = manager.connections.stream_token(node_id).ok_or(Error::InvalidNode(*node_id))?;
+                ctrace!(NET, "Disconnecting {:?}", node_id);
+                manager.routing_table.remove_node(node_id.into_addr());
+                self.client.on_node_removed(node_id);
+                io.deregister_stream(token)?;
+                Ok(())
+            }
         }
     }
 
@@ -428,6 +465,7 @@ impl IoHandler<Message> for Handler {
                 }
                 let node_id = manager.connections.node_id(&stream).ok_or(Error::InvalidStream(stream))?;
                 manager.routing_table.remove_node(node_id.into_addr());
+                manager.dials.fail(&node_id.into_addr(), DialError::Refused);
                 self.client.on_node_removed(&node_id);
                 io.deregister_stream(stream)?;
             }
@@ -272,6 +272,21 @@ impl RoutingTable {
         false
     }
 
+    pub fn is_established(&self, remote_address: &SocketAddr) -> bool {
+        let entries = self.entries.read();
+        let remote_node_id = remote_address.into();
+        entries.get(&remote_node_id).map_or(false, |entry| {
+            let entry = entry.lock();
+            let state = entry.replace(State::Intermediate);
+            let result = match state {
+                State::Established(_) => true,
+                _ => false,
+            };
+            entry.set(state);
+            result
+        })
+    }
+
     pub fn unestablished_session(&self, remote_address: &SocketAddr) -> Option<Session> {
         let entries = self.entries.read();
         let remote_node_id = remote_address.into();
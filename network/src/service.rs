@@ -15,24 +15,28 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
+use std::time::Duration;
 
 use cio::{IoError, IoService};
 
 use super::client::Client;
+use super::dial::{Connected, DialError, Dials};
 use super::p2p;
 use super::routing_table::RoutingTable;
 use super::session_initiator;
 use super::timer;
 use super::DiscoveryApi;
-use super::{NetworkExtension, SocketAddr};
+use super::{NetworkExtension, NodeId, SocketAddr};
 
 pub struct Service {
     session_initiator: IoService<session_initiator::Message>,
-    _p2p: IoService<p2p::Message>,
+    p2p: IoService<p2p::Message>,
     timer: IoService<timer::Message>,
     client: Arc<Client>,
     routing_table: Arc<RoutingTable>,
+    dials: Arc<Dials>,
 }
 
 impl Service {
@@ -42,6 +46,7 @@ impl Service {
         let session_initiator = IoService::start()?;
 
         let routing_table = RoutingTable::new();
+        let dials = Dials::new();
 
         let client = Client::new(p2p.channel(), timer.channel());
 
@@ -49,6 +54,7 @@ impl Service {
             address.clone(),
             Arc::clone(&client),
             Arc::clone(&routing_table),
+            Arc::clone(&dials),
             min_peers,
             max_peers,
         )?);
@@ -56,15 +62,21 @@ impl Service {
 
         timer.register_handler(Arc::new(timer::Handler::new(Arc::clone(&client))))?;
 
-        let session_initiator_handler = Arc::new(session_initiator::Handler::new(address, Arc::clone(&routing_table)));
+        let session_initiator_handler = Arc::new(session_initiator::Handler::new(
+            address,
+            Arc::clone(&routing_table),
+            Arc::clone(&dials),
+            p2p.channel(),
+        ));
         session_initiator.register_handler(session_initiator_handler)?;
 
         Ok(Self {
             session_initiator,
-            _p2p: p2p,
+            p2p,
             timer,
             client,
             routing_table,
+            dials,
         })
     }
 
@@ -88,6 +100,53 @@ impl Service {
         }
     }
 
+    /// Connects to `address` and waits until the handshake finishes.
+    ///
+    /// If the handshake fails, the routing entry is removed only when the dial added it.
+    /// A peer which turns out to be another node is disconnected.
+    pub fn dial(
+        &self,
+        address: SocketAddr,
+        node_id: Option<NodeId>,
+        timeout: Duration,
+    ) -> Result<Connected, DialError> {
+        if self.routing_table.is_established(&address) {
+            return Err(DialError::AlreadyConnected)
+        }
+
+        let is_new_entry = self.routing_table.add_candidate(address);
+        let receiver = self.dials.start(address, node_id);
+        if let Err(err) = self.session_initiator.send_message(session_initiator::Message::ConnectTo(address)) {
+            cwarn!(NET, "Cannot dial to {:?}: {:?}", address, err);
+            self.dials.cancel(&address);
+            if is_new_entry {
+                self.routing_table.remove_node(address);
+            }
+            return Err(DialError::Stopped)
+        }
+
+        let result = match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                self.dials.cancel(&address);
+                Err(DialError::Timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(DialError::Stopped),
+        };
+        if let Err(DialError::NodeIdMismatch(mismatch)) = &result {
+            let message = p2p::Message::Disconnect {
+                node_id: mismatch.found,
+            };
+            if let Err(err) = self.p2p.send_message(message) {
+                cwarn!(NET, "Cannot disconnect {:?}: {:?}", address, err);
+            }
+        }
+        if result.is_err() && is_new_entry {
+            self.routing_table.remove_node(address);
+        }
+        result
+    }
+
     pub fn set_routing_table(&self, disc: &DiscoveryApi) {
         disc.set_routing_table(Arc::clone(&self.routing_table));
     }
@@ -110,3 +169,90 @@ impl From<String> for Error {
         Error::General(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use cio::IoChannel;
+    use unexpected::Mismatch;
+
+    use super::*;
+
+    const TIMEOUT_SECS: u64 = 5;
+
+    fn unused_address() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        SocketAddr::v4(127, 0, 0, 1, port)
+    }
+
+    fn dial(node: &Service, address: SocketAddr, node_id: Option<NodeId>) -> Result<Connected, DialError> {
+        node.dial(address, node_id, Duration::from_secs(TIMEOUT_SECS))
+    }
+
+    fn start(max_peers: usize) -> (Service, SocketAddr) {
+        let address = unused_address();
+        (Service::start(address, 0, max_peers).unwrap(), address)
+    }
+
+    #[test]
+    fn dial_to_a_node_succeeds() {
+        let (node, _) = start(10);
+        let (_peer, peer_address) = start(10);
+
+        assert_eq!(
+            Ok(Connected {
+                node_id: peer_address.into(),
+                extensions: Vec::new(),
+            }),
+            dial(&node, peer_address, Some(peer_address.into()))
+        );
+        assert!(node.routing_table.is_established(&peer_address));
+        assert_eq!(Err(DialError::AlreadyConnected), dial(&node, peer_address, None));
+    }
+
+    #[test]
+    fn dial_to_another_node_fails() {
+        let (node, _) = start(10);
+        let (_peer, peer_address) = start(10);
+        let expected = unused_address().into();
+
+        assert_eq!(
+            Err(DialError::NodeIdMismatch(Mismatch {
+                expected,
+                found: peer_address.into(),
+            })),
+            dial(&node, peer_address, Some(expected))
+        );
+        assert!(!node.routing_table.all_addresses().contains(&peer_address));
+    }
+
+    #[test]
+    fn dial_to_a_node_which_refuses_the_connection_fails() {
+        let (node, _) = start(10);
+        // The peer answers the handshake, but nothing listens to its TCP port.
+        let peer_address = unused_address();
+        let peer: IoService<session_initiator::Message> = IoService::start().unwrap();
+        let handler =
+            session_initiator::Handler::new(peer_address, RoutingTable::new(), Dials::new(), IoChannel::disconnected());
+        peer.register_handler(Arc::new(handler)).unwrap();
+
+        assert_eq!(Err(DialError::Refused), dial(&node, peer_address, None));
+        assert!(!node.routing_table.all_addresses().contains(&peer_address));
+    }
+
+    #[test]
+    fn failed_dial_removes_only_the_entry_it_added() {
+        let (node, _) = start(0);
+        let (_known_peer, known_address) = start(10);
+        let (_new_peer, new_address) = start(10);
+        assert!(node.routing_table.add_candidate(known_address));
+
+        assert_eq!(Err(DialError::TooManyPeers), dial(&node, known_address, None));
+        assert!(node.routing_table.all_addresses().contains(&known_address));
+
+        assert_eq!(Err(DialError::TooManyPeers), dial(&node, new_address, None));
+        assert!(!node.routing_table.all_addresses().contains(&new_address));
+    }
+}
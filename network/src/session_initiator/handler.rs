@@ -22,13 +22,17 @@ use std::sync::Arc;
 
 use ccrypto::aes::SymmetricCipherError;
 use cfinally::finally;
-use cio::{IoContext, IoError as CIoError, IoHandler, IoHandlerResult, IoManager, StreamToken, TimerToken};
+use cio::{
+    IoChannel, IoContext, IoError as CIoError, IoHandler, IoHandlerResult, IoManager, StreamToken, TimerToken,
+};
 use ckeys::Error as KeysError;
 use mio::deprecated::EventLoop;
 use mio::Token;
 use parking_lot::Mutex;
 use rlp::DecoderError;
 
+use super::super::dial::{DialError, Dials};
+use super::super::p2p::Message as P2pMessage;
 use super::super::token_generator::TokenGenerator;
 use super::super::RoutingTable;
 use super::super::SocketAddr;
@@ -87,6 +91,9 @@ struct SessionInitiator {
 
     routing_table: Arc<RoutingTable>,
     requests: Requests,
+
+    dials: Arc<Dials>,
+    p2p_channel: IoChannel<P2pMessage>,
 }
 
 #[derive(Debug)]
@@ -186,12 +193,19 @@ pub enum Message {
 const MESSAGE_TIMEOUT_MS: u64 = 10_000;
 
 impl SessionInitiator {
-    fn bind(socket_address: &SocketAddr, routing_table: Arc<RoutingTable>) -> Result<Self> {
+    fn bind(
+        socket_address: &SocketAddr,
+        routing_table: Arc<RoutingTable>,
+        dials: Arc<Dials>,
+        p2p_channel: IoChannel<P2pMessage>,
+    ) -> Result<Self> {
         let server = Server::bind(socket_address)?;
         Ok(Self {
             server,
             routing_table,
             requests: Requests::new(),
+            dials,
+            p2p_channel,
         })
     }
 
@@ -304,6 +318,7 @@ impl SessionInitiator {
                 if self.routing_table.remove_node(*from) {
                     cinfo!(NET, "Shared Secret to {:?} denied (reason: {})", from, reason);
                 }
+                self.dials.fail(from, DialError::HandshakeDenied(reason.clone()));
                 Ok(())
             }
             message::Body::NonceRequest(encrypted_temporary_nonce) => {
@@ -327,6 +342,9 @@ impl SessionInitiator {
 
                 if !self.routing_table.create_allowed_session(from, &encrypted_nonce) {
                     cwarn!(NET, "Cannot create session to {:?}", from);
+                    self.dials.fail(from, DialError::HandshakeDenied("Cannot create session".to_string()));
+                } else if self.dials.contains(from) {
+                    self.p2p_channel.send(P2pMessage::RequestConnection(from.clone()))?;
                 }
                 Ok(())
             }
@@ -337,6 +355,7 @@ impl SessionInitiator {
                 }
 
                 cinfo!(NET, "Connection to {:?} refused(reason: {})", from, reason);
+                self.dials.fail(from, DialError::HandshakeDenied(reason.clone()));
                 Ok(())
             }
         }
@@ -356,9 +375,15 @@ pub struct Handler {
 }
 
 impl Handler {
-    pub fn new(socket_address: SocketAddr, routing_table: Arc<RoutingTable>) -> Self {
-        let session_initiator =
-            Mutex::new(SessionInitiator::bind(&socket_address, routing_table).expect("Cannot bind UDP port"));
+    pub fn new(
+        socket_address: SocketAddr,
+        routing_table: Arc<RoutingTable>,
+        dials: Arc<Dials>,
+        p2p_channel: IoChannel<P2pMessage>,
+    ) -> Self {
+        let session_initiator = Mutex::new(
+            SessionInitiator::bind(&socket_address, routing_table, dials, p2p_channel).expect("Cannot bind UDP port"),
+        );
         Self {
             session_initiator,
         }
@@ -390,6 +415,7 @@ impl IoHandler<Message> for Handler {
                     None => {}
                     Some(address) => {
                         session_initiator.routing_table.remove_node(address);
+                        session_initiator.dials.fail(&address, DialError::Timeout);
                     }
                 }
                 Ok(())
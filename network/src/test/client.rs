@@ -28,6 +28,7 @@ use super::super::NodeId;
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum Call {
     Send(NodeId, Vec<u8>),
+    Disconnect(NodeId),
     Negotiate(NodeId),
     SetTimer {
         token: TimerToken,
@@ -72,6 +73,10 @@ impl Api for TestApi {
         self.calls.lock().push_back(Call::Send(*node, message.to_vec()));
     }
 
+    fn disconnect(&self, node: &NodeId) {
+        self.calls.lock().push_back(Call::Disconnect(*node));
+    }
+
     fn set_timer(&self, token: TimerToken, duration: Duration) -> Result<()> {
         let mut timers = self.timers.lock();
         if timers.contains_key(&token) {
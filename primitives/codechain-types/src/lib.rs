@@ -1,5 +1,12 @@
 extern crate ethcore_bytes as ebytes;
 extern crate ethereum_types;
+extern crate rustc_hex;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+mod signature;
 
 pub use ebytes::Bytes;
 pub use ethereum_types::{H1024, H128, H160, H256, H264, H32, H512, H520, H64};
@@ -8,7 +15,7 @@ pub use ethereum_types::{U128, U256, U512};
 pub type Address = H160;
 pub type Secret = H256;
 pub type Public = H512;
-pub type Signature = H520;
+pub use signature::Signature;
 
 pub mod bytes {
     pub use ebytes::ToPretty;
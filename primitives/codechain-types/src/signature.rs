@@ -0,0 +1,317 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+
+use ethereum_types::{H256, H520};
+use rustc_hex::{FromHex, FromHexError, ToHex};
+
+/// Order of the secp256k1 curve.
+const CURVE_ORDER: &str = "fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141";
+/// Half of the order of the secp256k1 curve. Canonical signatures have `s` at most this value.
+const HALF_CURVE_ORDER: &str = "7fffffffffffffffffffffffffffffff5d576e7357a4501ddfe92f46681b20a0";
+
+/// Recoverable ECDSA signature encoded as RSV components.
+#[repr(C)]
+pub struct Signature([u8; 65]);
+
+impl Signature {
+    /// Get a slice into the 'r' portion of the data.
+    pub fn r(&self) -> &[u8] {
+        &self.0[0..32]
+    }
+
+    /// Get a slice into the 's' portion of the data.
+    pub fn s(&self) -> &[u8] {
+        &self.0[32..64]
+    }
+
+    /// Get the recovery byte.
+    pub fn v(&self) -> u8 {
+        self.0[64]
+    }
+
+    /// Create a signature object from the sig.
+    pub fn from_rsv(r: &H256, s: &H256, v: u8) -> Self {
+        let mut sig = [0u8; 65];
+        sig[0..32].copy_from_slice(&r);
+        sig[32..64].copy_from_slice(&s);
+        sig[64] = v;
+        Signature(sig)
+    }
+
+    /// Check if this is a "low" signature.
+    pub fn is_low_s(&self) -> bool {
+        H256::from_slice(self.s()) <= HALF_CURVE_ORDER.into()
+    }
+
+    /// Check if each component of the signature is in range.
+    pub fn is_valid(&self) -> bool {
+        let order: H256 = CURVE_ORDER.into();
+        self.v() <= 1
+            && H256::from_slice(self.r()) < order
+            && H256::from_slice(self.r()) >= 1.into()
+            && H256::from_slice(self.s()) < order
+            && H256::from_slice(self.s()) >= 1.into()
+    }
+
+    /// Check if the signature is valid and in the canonical, low-s form.
+    ///
+    /// For every valid signature `(r, s, v)`, `(r, n - s, v ^ 1)` is also valid for the same message and key,
+    /// so only the low-s one is accepted to keep signatures from being malleated.
+    pub fn is_canonical(&self) -> bool {
+        self.is_valid() && self.is_low_s()
+    }
+}
+
+// Compares all bytes regardless of where the first difference is, not to leak it through timing.
+impl PartialEq for Signature {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.iter().zip(other.0.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+// manual implementation required in Rust 1.13+, see `std::cmp::AssertParamIsEq`.
+impl Eq for Signature {}
+
+// also manual for the same reason, but the pretty printing might be useful.
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Signature")
+            .field("r", &self.0[0..32].to_hex())
+            .field("s", &self.0[32..64].to_hex())
+            .field("v", &self.0[64..65].to_hex())
+            .finish()
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+/// Parses a hex string of 65 bytes, with or without the `0x` prefix.
+impl FromStr for Signature {
+    type Err = FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = if s.starts_with("0x") {
+            &s[2..]
+        } else {
+            s
+        };
+        let hex: Vec<u8> = s.from_hex()?;
+        if hex.len() != 65 {
+            return Err(FromHexError::InvalidHexLength)
+        }
+        let mut data = [0; 65];
+        data.copy_from_slice(&hex);
+        Ok(Signature(data))
+    }
+}
+
+impl Default for Signature {
+    fn default() -> Self {
+        Signature([0; 65])
+    }
+}
+
+impl Hash for Signature {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        H520::from(self.0).hash(state);
+    }
+}
+
+impl Clone for Signature {
+    fn clone(&self) -> Self {
+        Signature(self.0)
+    }
+}
+
+impl Copy for Signature {}
+
+impl From<[u8; 65]> for Signature {
+    fn from(s: [u8; 65]) -> Self {
+        Signature(s)
+    }
+}
+
+impl Into<[u8; 65]> for Signature {
+    fn into(self) -> [u8; 65] {
+        self.0
+    }
+}
+
+impl From<Signature> for H520 {
+    fn from(s: Signature) -> Self {
+        H520::from(s.0)
+    }
+}
+
+impl From<H520> for Signature {
+    fn from(bytes: H520) -> Self {
+        Signature(bytes.into())
+    }
+}
+
+impl Deref for Signature {
+    type Target = [u8; 65];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Signature {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serialization {
+    use std::fmt;
+
+    use serde::de::{Error, Visitor};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Signature;
+
+    impl Serialize for Signature {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer, {
+            serializer.serialize_str(&format!("0x{}", self))
+        }
+    }
+
+    impl<'a> Deserialize<'a> for Signature {
+        fn deserialize<D>(deserializer: D) -> Result<Signature, D::Error>
+        where
+            D: Deserializer<'a>, {
+            deserializer.deserialize_str(SignatureVisitor)
+        }
+    }
+
+    struct SignatureVisitor;
+
+    impl<'a> Visitor<'a> for SignatureVisitor {
+        type Value = Signature;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            write!(formatter, "a 0x-prefixed, hex-encoded signature of 65 bytes")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: Error, {
+            if !value.starts_with("0x") {
+                return Err(Error::custom("Invalid signature format. Expected a 0x-prefixed hex string"))
+            }
+            value.parse().map_err(|e| Error::custom(format!("Invalid signature: {}", e)))
+        }
+
+        fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+        where
+            E: Error, {
+            self.visit_str(value.as_ref())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethereum_types::U256;
+
+    use super::*;
+
+    fn signature() -> Signature {
+        let mut data = [0u8; 65];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        data[64] = 1;
+        Signature::from(data)
+    }
+
+    #[test]
+    fn rsv_accessors() {
+        let r = H256::from(1);
+        let s = H256::from(2);
+        let signature = Signature::from_rsv(&r, &s, 1);
+        assert_eq!(&r[..], signature.r());
+        assert_eq!(&s[..], signature.s());
+        assert_eq!(1, signature.v());
+    }
+
+    #[test]
+    fn from_str_round_trip() {
+        let signature = signature();
+        assert_eq!(signature, signature.to_string().parse().unwrap());
+        assert_eq!(signature, format!("0x{}", signature).parse().unwrap());
+    }
+
+    #[test]
+    fn from_str_rejects_invalid_input() {
+        match Signature::from_str("0x00") {
+            Err(FromHexError::InvalidHexLength) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        match Signature::from_str(&"00".repeat(66)) {
+            Err(FromHexError::InvalidHexLength) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert!(Signature::from_str(&"zz".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn equality_compares_every_byte() {
+        let signature = signature();
+        for i in 0..65 {
+            let mut other = signature;
+            other[i] ^= 1;
+            assert_ne!(signature, other);
+        }
+        assert_eq!(signature, signature.clone());
+    }
+
+    #[test]
+    fn high_s_is_not_canonical() {
+        let order: H256 = CURVE_ORDER.into();
+        let r = H256::from(1);
+        let low_s = H256::from(2);
+        let high_s = H256::from(U256::from(order) - U256::from(low_s));
+
+        let low = Signature::from_rsv(&r, &low_s, 0);
+        let high = Signature::from_rsv(&r, &high_s, 1);
+        assert!(low.is_canonical());
+        assert!(high.is_valid());
+        assert!(!high.is_low_s());
+        assert!(!high.is_canonical());
+    }
+
+    #[test]
+    fn out_of_range_components_are_not_canonical() {
+        let zero = Signature::from_rsv(&H256::zero(), &H256::from(1), 0);
+        let overflow = Signature::from_rsv(&CURVE_ORDER.into(), &H256::from(1), 0);
+        let bad_v = Signature::from_rsv(&H256::from(1), &H256::from(1), 2);
+        assert!(!zero.is_canonical());
+        assert!(!overflow.is_canonical());
+        assert!(!bad_v.is_canonical());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        use serde_json;
+
+        let signature = signature();
+        let serialized = serde_json::to_string(&signature).unwrap();
+        assert_eq!(format!("\"0x{}\"", signature), serialized);
+        assert_eq!(signature, serde_json::from_str::<Signature>(&serialized).unwrap());
+
+        let unprefixed = format!("\"{}\"", signature);
+        assert!(serde_json::from_str::<Signature>(&unprefixed).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use csync::{HeaderBatchStatus, SyncStatus};
+use jsonrpc_http_server::hyper::header::ContentType;
+use jsonrpc_http_server::hyper::{Method, Request, StatusCode};
+use jsonrpc_http_server::{RequestMiddleware, RequestMiddlewareAction, Response};
+use parking_lot::RwLock;
+use serde_json;
+
+/// The HTTP path answering whether the node is ready.
+pub const HEALTH_PATH: &'static str = "/health";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthConfig {
+    /// The minimum number of connected peers for the node to be ready.
+    pub min_peers: usize,
+    /// The maximum number of blocks the node can be behind its peers while it is ready.
+    pub max_sync_lag: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            min_peers: 1,
+            max_sync_lag: 16,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// The conditions which keep the node from being ready.
+    pub failures: Vec<String>,
+}
+
+/// The conditions of the node's readiness.
+///
+/// Everything is read from snapshots which are updated by their owners,
+/// so answering a health check never waits for the chain.
+pub struct Health {
+    config: HealthConfig,
+    db_opened: AtomicBool,
+    sync: RwLock<Option<Arc<SyncStatus>>>,
+}
+
+impl Health {
+    pub fn new(config: HealthConfig) -> Self {
+        Self {
+            config,
+            db_opened: AtomicBool::new(false),
+            sync: RwLock::new(None),
+        }
+    }
+
+    /// Marks that the database has been opened without errors.
+    pub fn set_db_opened(&self) {
+        self.db_opened.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_sync_status(&self, status: Arc<SyncStatus>) {
+        *self.sync.write() = Some(status);
+    }
+
+    pub fn readiness(&self) -> Readiness {
+        let mut failures = Vec::new();
+        if !self.db_opened.load(Ordering::SeqCst) {
+            failures.push("The database is not opened".to_string());
+        }
+
+        let (peers, lag, header_batches) = match *self.sync.read() {
+            Some(ref status) => (status.peers(), status.lag(), status.header_batches()),
+            None => (0, 0, HeaderBatchStatus::default()),
+        };
+        if peers < self.config.min_peers {
+            failures.push(format!("Not enough peers: {} connected, {} required", peers, self.config.min_peers));
+        }
+        if lag > self.config.max_sync_lag {
+            failures.push(format!("Syncing: {} blocks behind, at most {} allowed", lag, self.config.max_sync_lag));
+        }
+        if header_batches.pending + header_batches.in_flight > 0 {
+            failures.push(format!(
+                "Downloading headers: {} batches pending, {} in flight",
+                header_batches.pending, header_batches.in_flight
+            ));
+        }
+
+        Readiness {
+            ready: failures.is_empty(),
+            failures,
+        }
+    }
+}
+
+/// Answers `GET /health` on the JSON-RPC HTTP server.
+/// The response is 200 if the node is ready and 503 otherwise.
+pub struct HealthMiddleware {
+    health: Arc<Health>,
+}
+
+impl HealthMiddleware {
+    pub fn new(health: Arc<Health>) -> Self {
+        Self {
+            health,
+        }
+    }
+}
+
+impl RequestMiddleware for HealthMiddleware {
+    fn on_request(&self, request: Request) -> RequestMiddlewareAction {
+        if request.method() != &Method::Get || request.path() != HEALTH_PATH {
+            return request.into()
+        }
+
+        let readiness = self.health.readiness();
+        let code = if readiness.ready {
+            StatusCode::Ok
+        } else {
+            StatusCode::ServiceUnavailable
+        };
+        Response {
+            code,
+            content_type: ContentType::json(),
+            content: serde_json::to_string(&readiness).expect("Readiness is always serializable"),
+        }.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cnetwork::SocketAddr;
+
+    use super::*;
+
+    fn ready_health() -> (Health, Arc<SyncStatus>) {
+        let health = Health::new(HealthConfig::default());
+        let status = Arc::new(SyncStatus::new(100));
+        status.set_peers(1);
+        health.set_db_opened();
+        health.set_sync_status(Arc::clone(&status));
+        assert!(health.readiness().ready);
+        (health, status)
+    }
+
+    #[test]
+    fn not_ready_until_the_database_is_opened() {
+        let health = Health::new(HealthConfig {
+            min_peers: 0,
+            max_sync_lag: 16,
+        });
+        assert_eq!(vec!["The database is not opened".to_string()], health.readiness().failures);
+
+        health.set_db_opened();
+        assert!(health.readiness().ready);
+    }
+
+    #[test]
+    fn not_enough_peers_flips_the_readiness() {
+        let (health, status) = ready_health();
+
+        status.set_peers(0);
+        let readiness = health.readiness();
+        assert!(!readiness.ready);
+        assert_eq!(vec!["Not enough peers: 0 connected, 1 required".to_string()], readiness.failures);
+
+        status.set_peers(2);
+        assert!(health.readiness().ready);
+    }
+
+    #[test]
+    fn syncing_flips_the_readiness() {
+        let (health, status) = ready_health();
+
+        status.note_block_number(&SocketAddr::v4(127, 0, 0, 1, 3485).into(), 117);
+        let readiness = health.readiness();
+        assert!(!readiness.ready);
+        assert_eq!(vec!["Syncing: 17 blocks behind, at most 16 allowed".to_string()], readiness.failures);
+
+        status.set_best_block_number(110);
+        assert!(health.readiness().ready);
+    }
+
+    #[test]
+    fn downloading_header_batches_flips_the_readiness() {
+        let (health, status) = ready_health();
+
+        status.set_header_batches(HeaderBatchStatus {
+            pending: 14,
+            in_flight: 2,
+            complete: 0,
+        });
+        let readiness = health.readiness();
+        assert!(!readiness.ready);
+        assert_eq!(vec!["Downloading headers: 14 batches pending, 2 in flight".to_string()], readiness.failures);
+
+        status.set_header_batches(HeaderBatchStatus::default());
+        assert!(health.readiness().ready);
+    }
+
+    #[test]
+    fn readiness_is_reported_as_json() {
+        let (health, status) = ready_health();
+        status.set_peers(0);
+        assert_eq!(
+            r#"{"ready":false,"failures":["Not enough peers: 0 connected, 1 required"]}"#,
+            serde_json::to_string(&health.readiness()).unwrap()
+        );
+    }
+}
@@ -17,6 +17,8 @@
 extern crate codechain_core as ccore;
 #[macro_use]
 extern crate codechain_logger as clogger;
+extern crate codechain_network as cnetwork;
+extern crate codechain_sync as csync;
 extern crate codechain_types as ctypes;
 extern crate jsonrpc_core;
 extern crate jsonrpc_http_server;
@@ -25,18 +27,21 @@ extern crate kvdb;
 extern crate kvdb_rocksdb as rocksdb;
 #[macro_use]
 extern crate log;
+extern crate parking_lot;
 extern crate rlp;
 extern crate rustc_hex;
 extern crate rustc_serialize;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
 extern crate tokio_core;
 
 #[macro_use]
 extern crate jsonrpc_macros;
 
+pub mod health;
 pub mod rpc_server;
 pub mod v1;
 
@@ -45,6 +50,8 @@ pub use rustc_serialize::hex;
 pub use jsonrpc_core::{Compatibility, Error, MetaIoHandler, Params, Value};
 pub use jsonrpc_http_server::tokio_core::reactor::Remote;
 
+pub use health::{Health, HealthConfig};
+
 pub use jsonrpc_http_server::Server as HttpServer;
 pub use rpc_server::start_http;
 
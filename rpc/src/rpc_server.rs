@@ -15,18 +15,21 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 // TODO: panic handler
+use health::{Health, HealthMiddleware};
 use jsonrpc_core;
 use jsonrpc_http_server::{self, Host, Server as HttpServer, ServerBuilder as HttpServerBuilder};
 use jsonrpc_ipc_server::{Server as IpcServer, ServerBuilder as IpcServerBuilder};
 use std::default::Default;
 use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 /// Start http server asynchronously and returns result with `Server` handle on success or an error.
 pub fn start_http<M: jsonrpc_core::Metadata>(
     addr: &SocketAddr,
     cors_domains: Option<Vec<String>>,
     allowed_hosts: Option<Vec<String>>,
+    health: Arc<Health>,
     handler: jsonrpc_core::MetaIoHandler<M>,
 ) -> Result<HttpServer, io::Error>
 where
@@ -45,6 +48,7 @@ where
     HttpServerBuilder::new(handler)
         .cors(cors_domains.into())
         .allowed_hosts(allowed_hosts.map(|hosts| hosts.into_iter().map(Host::from).collect()).into())
+        .request_middleware(HealthMiddleware::new(health))
         .start_http(addr)
 }
 
@@ -14,33 +14,196 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ccore::Error as CoreError;
+use std::error::Error as StdError;
+
+use ccore::{BlockImportError, Error as CoreError, ParcelError};
+use cnetwork::{DialError, IntoSocketAddr};
 use kvdb::Error as KVDBError;
 use rlp::DecoderError;
 
 use jsonrpc_core::{Error, ErrorCode, Value};
 
 mod codes {
+    pub const NO_WORK: i64 = -32001;
     pub const NO_WORK_REQUIRED: i64 = -32004;
+    pub const SEALING_IN_PROGRESS: i64 = -32005;
     pub const UNKNOWN_ERROR: i64 = -32009;
-    pub const PARCEL_ERROR: i64 = -32010;
     pub const KVDB_ERROR: i64 = -32011;
+    pub const PARCEL_ALREADY_IMPORTED: i64 = -32020;
+    pub const PARCEL_OLD: i64 = -32021;
+    pub const PARCEL_TOO_CHEAP_TO_REPLACE: i64 = -32022;
+    pub const PARCEL_INVALID_NETWORK_ID: i64 = -32023;
+    pub const PARCEL_LIMIT_REACHED: i64 = -32024;
+    pub const PARCEL_INSUFFICIENT_FEE: i64 = -32025;
+    pub const PARCEL_INSUFFICIENT_BALANCE: i64 = -32026;
+    pub const PARCEL_INVALID_NONCE: i64 = -32027;
+    pub const PARCEL_NOT_ALLOWED: i64 = -32028;
+    pub const PARCEL_INVALID_SIGNATURE: i64 = -32029;
+    pub const BLOCK_INVALID: i64 = -32030;
+    pub const BLOCK_NOT_IMPORTED: i64 = -32031;
+    pub const PARCEL_SENDER_LIMIT_REACHED: i64 = -32032;
+    pub const PARCEL_MEMORY_LIMIT_REACHED: i64 = -32033;
+    pub const PARCEL_SENDER_BANNED: i64 = -32034;
+    pub const PARCEL_FEE_TOO_HIGH: i64 = -32035;
+    pub const PARCEL_NONCE_TOO_FAR_IN_FUTURE: i64 = -32036;
+    pub const PARCEL_INSUFFICIENT_BALANCE_FOR_QUEUE: i64 = -32037;
+    pub const NETWORK_DISABLED: i64 = -32040;
+    pub const DIAL_TIMEOUT: i64 = -32041;
+    pub const DIAL_REFUSED: i64 = -32042;
+    pub const DIAL_HANDSHAKE_DENIED: i64 = -32043;
+    pub const DIAL_NODE_ID_MISMATCH: i64 = -32044;
+    pub const DIAL_ALREADY_CONNECTED: i64 = -32045;
+    pub const DIAL_TOO_MANY_PEERS: i64 = -32046;
+    pub const SNAPSHOT_IN_PROGRESS: i64 = -32050;
+}
+
+fn parcel_error_code_and_data(error: &ParcelError) -> (i64, Option<Value>) {
+    match error {
+        ParcelError::AlreadyImported => (codes::PARCEL_ALREADY_IMPORTED, None),
+        ParcelError::Old {
+            expected,
+            got,
+        } => (
+            codes::PARCEL_OLD,
+            Some(json!({
+                "expected": expected,
+                "got": got,
+            })),
+        ),
+        ParcelError::TooCheapToReplace => (codes::PARCEL_TOO_CHEAP_TO_REPLACE, None),
+        ParcelError::InvalidNetworkId => (codes::PARCEL_INVALID_NETWORK_ID, None),
+        ParcelError::LimitReached {
+            limit,
+        } => (
+            codes::PARCEL_LIMIT_REACHED,
+            Some(json!({
+                "limit": limit,
+            })),
+        ),
+        ParcelError::MemoryLimitReached {
+            limit,
+        } => (
+            codes::PARCEL_MEMORY_LIMIT_REACHED,
+            Some(json!({
+                "limit": limit,
+            })),
+        ),
+        ParcelError::SenderLimitReached {
+            limit,
+        } => (
+            codes::PARCEL_SENDER_LIMIT_REACHED,
+            Some(json!({
+                "limit": limit,
+            })),
+        ),
+        ParcelError::InsufficientFee {
+            minimal,
+            got,
+        } => (
+            codes::PARCEL_INSUFFICIENT_FEE,
+            Some(json!({
+                "minimal": minimal,
+                "got": got,
+            })),
+        ),
+        ParcelError::FeeTooHigh {
+            maximum,
+            got,
+        } => (
+            codes::PARCEL_FEE_TOO_HIGH,
+            Some(json!({
+                "maximum": maximum,
+                "got": got,
+            })),
+        ),
+        ParcelError::InsufficientBalance {
+            address,
+            balance,
+            required,
+        } => (
+            codes::PARCEL_INSUFFICIENT_BALANCE,
+            Some(json!({
+                "address": address,
+                "balance": balance,
+                "required": required,
+            })),
+        ),
+        ParcelError::InsufficientBalanceForQueue {
+            balance,
+            cumulative_cost,
+        } => (
+            codes::PARCEL_INSUFFICIENT_BALANCE_FOR_QUEUE,
+            Some(json!({
+                "balance": balance,
+                "cumulativeCost": cumulative_cost,
+            })),
+        ),
+        ParcelError::InvalidNonce {
+            expected,
+            got,
+        } => (
+            codes::PARCEL_INVALID_NONCE,
+            Some(json!({
+                "expected": expected,
+                "got": got,
+            })),
+        ),
+        ParcelError::NonceTooFarInFuture {
+            expected_max,
+            got,
+        } => (
+            codes::PARCEL_NONCE_TOO_FAR_IN_FUTURE,
+            Some(json!({
+                "expectedMax": expected_max,
+                "got": got,
+            })),
+        ),
+        ParcelError::NotAllowed => (codes::PARCEL_NOT_ALLOWED, None),
+        ParcelError::SenderBanned => (codes::PARCEL_SENDER_BANNED, None),
+        ParcelError::InvalidSignature(err) => (codes::PARCEL_INVALID_SIGNATURE, Some(Value::String(err.clone()))),
+    }
 }
 
 pub fn parcel<T: Into<CoreError>>(error: T) -> Error {
     let error = error.into();
     if let CoreError::Parcel(e) = error {
+        let (code, data) = parcel_error_code_and_data(&e);
         Error {
-            code: ErrorCode::ServerError(codes::PARCEL_ERROR),
+            code: ErrorCode::ServerError(code),
             message: ::ccore::parcel_error_message(&e),
-            data: None,
+            data,
         }
     } else {
-        Error {
-            code: ErrorCode::ServerError(codes::UNKNOWN_ERROR),
-            message: "Unknown error when sending parcel.".into(),
-            data: Some(Value::String(format!("{:?}", error))),
-        }
+        core(error)
+    }
+}
+
+pub fn block_import(error: BlockImportError) -> Error {
+    let code = match error {
+        BlockImportError::Block(_) => codes::BLOCK_INVALID,
+        BlockImportError::Import(_) => codes::BLOCK_NOT_IMPORTED,
+        BlockImportError::Other(err) => return core(*err),
+    };
+    Error {
+        code: ErrorCode::ServerError(code),
+        message: error.to_string(),
+        data: None,
+    }
+}
+
+/// Converts a core error, keeping the messages of its causes in `data`.
+pub fn core<T: Into<CoreError>>(error: T) -> Error {
+    let error = error.into();
+    let mut causes = Vec::new();
+    let mut cause = error.source();
+    while let Some(err) = cause {
+        causes.push(Value::String(err.to_string()));
+        cause = err.source();
+    }
+    Error {
+        code: ErrorCode::ServerError(codes::UNKNOWN_ERROR),
+        message: error.to_string(),
+        data: Some(Value::Array(causes)),
     }
 }
 
@@ -60,6 +223,14 @@ pub fn rlp(error: DecoderError) -> Error {
     }
 }
 
+pub fn no_work() -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::NO_WORK),
+        message: "No work package is available.".into(),
+        data: None,
+    }
+}
+
 pub fn no_work_required() -> Error {
     Error {
         code: ErrorCode::ServerError(codes::NO_WORK_REQUIRED),
@@ -67,3 +238,175 @@ pub fn no_work_required() -> Error {
         data: None,
     }
 }
+
+pub fn sealing_in_progress() -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::SEALING_IN_PROGRESS),
+        message: "A block is being sealed. Try again later.".into(),
+        data: None,
+    }
+}
+
+pub fn network_disabled() -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::NETWORK_DISABLED),
+        message: "Network is disabled.".into(),
+        data: None,
+    }
+}
+
+pub fn snapshot_in_progress() -> Error {
+    Error {
+        code: ErrorCode::ServerError(codes::SNAPSHOT_IN_PROGRESS),
+        message: "Another snapshot is being written.".into(),
+        data: None,
+    }
+}
+
+pub fn dial(error: DialError) -> Error {
+    let (code, data) = match &error {
+        DialError::Timeout => (codes::DIAL_TIMEOUT, None),
+        DialError::Refused => (codes::DIAL_REFUSED, None),
+        DialError::HandshakeDenied(reason) => (codes::DIAL_HANDSHAKE_DENIED, Some(Value::String(reason.clone()))),
+        DialError::NodeIdMismatch(mismatch) => (
+            codes::DIAL_NODE_ID_MISMATCH,
+            Some(json!({
+                "expected": mismatch.expected.into_addr().to_string(),
+                "found": mismatch.found.into_addr().to_string(),
+            })),
+        ),
+        DialError::AlreadyConnected => (codes::DIAL_ALREADY_CONNECTED, None),
+        DialError::TooManyPeers => (codes::DIAL_TOO_MANY_PEERS, None),
+        DialError::Stopped => (codes::NETWORK_DISABLED, None),
+    };
+    Error {
+        code: ErrorCode::ServerError(code),
+        message: error.to_string(),
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ctypes::Address;
+
+    use super::*;
+
+    #[test]
+    fn core_error_keeps_the_chain_of_causes() {
+        let error = core(CoreError::from(DecoderError::RlpIsTooShort));
+        assert_eq!(DecoderError::RlpIsTooShort.to_string(), error.message);
+        assert_eq!(Some(json!([DecoderError::RlpIsTooShort.to_string()])), error.data);
+    }
+
+    #[test]
+    fn parcel_errors_have_distinct_codes() {
+        let errors = vec![
+            ParcelError::AlreadyImported,
+            ParcelError::Old {
+                expected: 1.into(),
+                got: 0.into(),
+            },
+            ParcelError::TooCheapToReplace,
+            ParcelError::InvalidNetworkId,
+            ParcelError::LimitReached {
+                limit: 1,
+            },
+            ParcelError::MemoryLimitReached {
+                limit: 1,
+            },
+            ParcelError::SenderLimitReached {
+                limit: 1,
+            },
+            ParcelError::InsufficientFee {
+                minimal: 1.into(),
+                got: 0.into(),
+            },
+            ParcelError::FeeTooHigh {
+                maximum: 1.into(),
+                got: 2.into(),
+            },
+            ParcelError::InsufficientBalance {
+                address: Address::default(),
+                balance: 0.into(),
+                required: 1.into(),
+            },
+            ParcelError::InsufficientBalanceForQueue {
+                balance: 1.into(),
+                cumulative_cost: 2.into(),
+            },
+            ParcelError::InvalidNonce {
+                expected: 1.into(),
+                got: 0.into(),
+            },
+            ParcelError::NonceTooFarInFuture {
+                expected_max: 1.into(),
+                got: 2.into(),
+            },
+            ParcelError::NotAllowed,
+            ParcelError::SenderBanned,
+            ParcelError::InvalidSignature("invalid".into()),
+        ];
+        let mut codes: Vec<_> = errors.iter().map(|e| parcel_error_code_and_data(e).0).collect();
+        let count = codes.len();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(count, codes.len());
+    }
+
+    #[test]
+    fn denied_handshake_reports_the_reason() {
+        let error = dial(DialError::HandshakeDenied("Invalid secret".to_string()));
+        assert_eq!(ErrorCode::ServerError(codes::DIAL_HANDSHAKE_DENIED), error.code);
+        assert_eq!("The handshake is denied: Invalid secret", error.message);
+        assert_eq!(Some(Value::String("Invalid secret".to_string())), error.data);
+    }
+
+    #[test]
+    fn insufficient_fee_error_has_data() {
+        let error = parcel(ParcelError::InsufficientFee {
+            minimal: 200.into(),
+            got: 100.into(),
+        });
+        assert_eq!(ErrorCode::ServerError(codes::PARCEL_INSUFFICIENT_FEE), error.code);
+        assert_eq!(
+            Some(json!({
+                "minimal": "0xc8",
+                "got": "0x64",
+            })),
+            error.data
+        );
+    }
+
+    #[test]
+    fn insufficient_balance_error_has_data() {
+        let error = parcel(ParcelError::InsufficientBalance {
+            address: Address::default(),
+            balance: 50.into(),
+            required: 100.into(),
+        });
+        assert_eq!(ErrorCode::ServerError(codes::PARCEL_INSUFFICIENT_BALANCE), error.code);
+        assert_eq!(
+            Some(json!({
+                "address": Address::default(),
+                "balance": "0x32",
+                "required": "0x64",
+            })),
+            error.data
+        );
+    }
+
+    #[test]
+    fn limit_reached_error_has_data() {
+        let error = parcel(ParcelError::LimitReached {
+            limit: 1024,
+        });
+        assert_eq!(ErrorCode::ServerError(codes::PARCEL_LIMIT_REACHED), error.code);
+        assert_eq!(
+            Some(json!({
+                "limit": 1024,
+            })),
+            error.data
+        );
+    }
+}
@@ -17,17 +17,21 @@
 use std::sync::Arc;
 
 use ccore::{
-    Asset, AssetAddress, AssetScheme, AssetSchemeAddress, Balance, BlockChainClient, BlockId, BlockInfo, ChainInfo,
-    Client, Invoice, Miner, MinerService, Nonce, ParcelInvoice, RegularKey, SignedParcel, TopStateInfo,
+    AssetAddress, AssetSchemeAddress, Balance, BlockChainClient, BlockId, BlockInfo, ChainInfo, Client,
+    CodeChainEngine, ConsensusEngine, Miner, MinerService, Nonce, RegularKey, SignedParcel, StateOrBlock,
+    TopStateInfo,
 };
-use ctypes::{H160, H256, Public, U256};
+use ctypes;
 use rlp::UntrustedRlp;
 
 use jsonrpc_core::Result;
 
 use super::super::errors;
 use super::super::traits::Chain;
-use super::super::types::{Block, Bytes, Parcel};
+use super::super::types::{
+    Asset, AssetScheme, Block, BlockNumber, Bytes, ChainParams, Invoice, Parcel, ParcelInvoice, Quantity, H160, H256,
+    H512,
+};
 
 pub struct ChainClient {
     client: Arc<Client>,
@@ -41,12 +45,34 @@ impl ChainClient {
             miner: miner.clone(),
         }
     }
+
+    /// Resolves `number` to the state it names.
+    ///
+    /// `Pending` names the state of the block being sealed, or the latest state if the miner isn't sealing.
+    fn state_or_block(&self, number: Option<BlockNumber>) -> StateOrBlock {
+        let block_id = match number.unwrap_or_default() {
+            BlockNumber::Num(number) => BlockId::Number(number),
+            BlockNumber::Latest => BlockId::Latest,
+            BlockNumber::Earliest => BlockId::Earliest,
+            BlockNumber::Pending => {
+                match self.miner.pending_state(&*self.client) {
+                    Some(state) => return state.into(),
+                    None => BlockId::Latest,
+                }
+            }
+        };
+        block_id.into()
+    }
 }
 
 impl Chain for ChainClient {
     fn send_signed_parcel(&self, raw: Bytes) -> Result<H256> {
-        UntrustedRlp::new(&raw.into_vec())
-            .as_val()
+        let raw = raw.into_vec();
+        let rlp = UntrustedRlp::new(&raw);
+        self.client
+            .decode_limits()
+            .check_parcel(&rlp)
+            .and_then(|_| rlp.as_val())
             .map_err(errors::rlp)
             .and_then(|parcel| SignedParcel::new(parcel).map_err(errors::parcel))
             .and_then(|signed| {
@@ -57,6 +83,7 @@ impl Chain for ChainClient {
     }
 
     fn get_parcel(&self, parcel_hash: H256) -> Result<Option<Parcel>> {
+        let parcel_hash: ctypes::H256 = parcel_hash.into();
         match self.client.parcel(parcel_hash.into()) {
             Some(parcel) => Ok(Some(parcel.into())),
             None => Ok(None),
@@ -64,18 +91,20 @@ impl Chain for ChainClient {
     }
 
     fn get_parcel_invoice(&self, parcel_hash: H256) -> Result<Option<ParcelInvoice>> {
-        Ok(self.client.parcel_invoice(parcel_hash.into()))
+        let parcel_hash: ctypes::H256 = parcel_hash.into();
+        Ok(self.client.parcel_invoice(parcel_hash.into()).map(Into::into))
     }
 
     fn get_transaction_invoice(&self, transaction_hash: H256) -> Result<Option<Invoice>> {
-        Ok(self.client.transaction_invoice(transaction_hash.into()))
+        let transaction_hash: ctypes::H256 = transaction_hash.into();
+        Ok(self.client.transaction_invoice(transaction_hash.into()).map(Into::into))
     }
 
     fn get_asset_scheme(&self, transaction_hash: H256) -> Result<Option<AssetScheme>> {
         if let Some(state) = self.client.state_at(BlockId::Latest) {
             let shard_id = 0; // FIXME
-            let address = AssetSchemeAddress::new(transaction_hash);
-            Ok(state.asset_scheme(shard_id, &address).map_err(errors::parcel)?)
+            let address = AssetSchemeAddress::new(transaction_hash.into());
+            Ok(state.asset_scheme(shard_id, &address).map_err(errors::core)?.map(Into::into))
         } else {
             Ok(None)
         }
@@ -84,26 +113,30 @@ impl Chain for ChainClient {
     fn get_asset(&self, transaction_hash: H256, index: usize) -> Result<Option<Asset>> {
         if let Some(state) = self.client.state_at(BlockId::Latest) {
             let shard_id = 0; // FIXME
-            let address = AssetAddress::new(transaction_hash, index);
-            Ok(state.asset(shard_id, &address).map_err(errors::parcel)?)
+            let address = AssetAddress::new(transaction_hash.into(), index);
+            Ok(state.asset(shard_id, &address).map_err(errors::core)?.map(Into::into))
         } else {
             Ok(None)
         }
     }
 
-    fn get_nonce(&self, address: H160, block_number: Option<u64>) -> Result<Option<U256>> {
-        let block_id = block_number.map(BlockId::Number).unwrap_or(BlockId::Latest);
-        Ok(self.client.nonce(&address.into(), block_id))
+    fn get_nonce(&self, address: H160, block_number: Option<BlockNumber>) -> Result<Option<Quantity>> {
+        let address: ctypes::H160 = address.into();
+        let nonce = match self.state_or_block(block_number) {
+            StateOrBlock::State(state) => state.nonce(&address).ok(),
+            StateOrBlock::Block(block_id) => self.client.nonce(&address, block_id),
+        };
+        Ok(nonce.map(Into::into))
     }
 
-    fn get_balance(&self, address: H160, block_number: Option<u64>) -> Result<Option<U256>> {
-        let block_id = block_number.map(BlockId::Number).unwrap_or(BlockId::Latest);
-        Ok(self.client.balance(&address.into(), block_id.into()))
+    fn get_balance(&self, address: H160, block_number: Option<BlockNumber>) -> Result<Option<Quantity>> {
+        let address: ctypes::H160 = address.into();
+        Ok(self.client.balance(&address, self.state_or_block(block_number)).map(Into::into))
     }
 
-    fn get_regular_key(&self, address: H160, block_number: Option<u64>) -> Result<Option<Public>> {
-        let block_id = block_number.map(BlockId::Number).unwrap_or(BlockId::Latest);
-        Ok(self.client.regular_key(&address.into(), block_id.into()))
+    fn get_regular_key(&self, address: H160, block_number: Option<BlockNumber>) -> Result<Option<H512>> {
+        let address: ctypes::H160 = address.into();
+        Ok(self.client.regular_key(&address, self.state_or_block(block_number)).map(Into::into))
     }
 
     fn get_block_number(&self) -> Result<u64> {
@@ -111,7 +144,7 @@ impl Chain for ChainClient {
     }
 
     fn get_block_hash(&self, block_number: u64) -> Result<Option<H256>> {
-        Ok(self.client.block_hash(BlockId::Number(block_number)))
+        Ok(self.client.block_hash(BlockId::Number(block_number)).map(Into::into))
     }
 
     fn get_block_by_number(&self, block_number: u64) -> Result<Option<Block>> {
@@ -119,10 +152,16 @@ impl Chain for ChainClient {
     }
 
     fn get_block_by_hash(&self, block_hash: H256) -> Result<Option<Block>> {
-        Ok(self.client.block(BlockId::Hash(block_hash)).map(|block| block.decode().into()))
+        Ok(self.client.block(BlockId::Hash(block_hash.into())).map(|block| block.decode().into()))
     }
 
     fn get_pending_parcels(&self) -> Result<Vec<Parcel>> {
         Ok(self.client.ready_parcels().into_iter().map(|signed| signed.into()).collect())
     }
+
+    fn get_params(&self) -> Result<ChainParams> {
+        let engine = self.client.engine();
+        let next_block_number = self.client.chain_info().best_block_number + 1;
+        Ok(ChainParams::new(&engine.params().at(next_block_number), engine.block_reward(next_block_number)))
+    }
 }
@@ -19,23 +19,28 @@ use std::sync::Arc;
 use std::vec::Vec;
 
 use ccore::{Client, COL_STATE};
-use ctypes::H256;
+use csync::SnapshotService;
+use ctypes;
 use jsonrpc_core::Result;
 use kvdb::KeyValueDB;
 use rlp::UntrustedRlp;
 
 use super::super::errors;
 use super::super::traits::Devel;
-use super::super::types::Bytes;
+use super::super::types::{Bytes, ImportedBlock, MemoryInfo, H256};
 
 pub struct DevelClient {
+    client: Arc<Client>,
     db: Arc<KeyValueDB>,
+    snapshot_service: Arc<SnapshotService>,
 }
 
 impl DevelClient {
-    pub fn new(client: &Client) -> Self {
+    pub fn new(client: &Arc<Client>, snapshot_service: &Arc<SnapshotService>) -> Self {
         Self {
+            client: client.clone(),
             db: client.database(),
+            snapshot_service: snapshot_service.clone(),
         }
     }
 }
@@ -43,10 +48,11 @@ impl DevelClient {
 impl Devel for DevelClient {
     fn get_state_trie_keys(&self, offset: usize, limit: usize) -> Result<Vec<H256>> {
         let iter = self.db.iter(COL_STATE);
-        Ok(iter.skip(offset).take(limit).map(|val| H256::from(val.0.deref())).collect())
+        Ok(iter.skip(offset).take(limit).map(|val| ctypes::H256::from(val.0.deref()).into()).collect())
     }
 
     fn get_state_trie_value(&self, key: H256) -> Result<Vec<Bytes>> {
+        let key: ctypes::H256 = key.into();
         match self.db.get(COL_STATE, &key) {
             Ok(Some(value)) => {
                 let rlp = UntrustedRlp::new(&value);
@@ -56,4 +62,23 @@ impl Devel for DevelClient {
             Err(err) => Err(errors::kvdb(err)),
         }
     }
+
+    fn get_memory_info(&self) -> Result<MemoryInfo> {
+        Ok(self.client.memory_info().into())
+    }
+
+    fn import_block(&self, bytes: Bytes) -> Result<ImportedBlock> {
+        self.client.import_block_rlp(bytes.into()).map(ImportedBlock::from).map_err(errors::block_import)
+    }
+
+    fn verify_chain_indexes(&self, depth: u64) -> Result<Vec<String>> {
+        match self.client.verify_chain_indexes(depth) {
+            Ok(()) => Ok(Vec::new()),
+            Err(divergences) => Ok(divergences.iter().map(ToString::to_string).collect()),
+        }
+    }
+
+    fn take_snapshot(&self) -> Result<H256> {
+        self.snapshot_service.take_snapshot().map(Into::into).ok_or_else(errors::snapshot_in_progress)
+    }
 }
@@ -0,0 +1,42 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use ccore::{ChainInfo, Client};
+use jsonrpc_core::Result;
+
+use super::super::traits::Engine;
+use super::super::types::EngineInfo;
+
+pub struct EngineClient {
+    client: Arc<Client>,
+}
+
+impl EngineClient {
+    pub fn new(client: &Arc<Client>) -> Self {
+        Self {
+            client: client.clone(),
+        }
+    }
+}
+
+impl Engine for EngineClient {
+    fn get_info(&self) -> Result<EngineInfo> {
+        let best_block_hash = self.client.chain_info().best_block_hash;
+        Ok(EngineInfo::new(self.client.engine(), &best_block_hash))
+    }
+}
@@ -16,20 +16,22 @@
 
 use std::sync::Arc;
 
-use ccore::{self, MinerService};
+use ccore::{self, Client, MinerService};
 use jsonrpc_core::Result;
 
 use super::super::errors;
 use super::super::traits::Miner;
-use super::super::types::{Bytes, Work};
+use super::super::types::{Bytes, Quantity, Work};
 
 pub struct MinerClient {
+    client: Arc<Client>,
     miner: Arc<ccore::Miner>,
 }
 
 impl MinerClient {
-    pub fn new(miner: &Arc<ccore::Miner>) -> Self {
+    pub fn new(client: &Arc<Client>, miner: &Arc<ccore::Miner>) -> Self {
         Self {
+            client: client.clone(),
             miner: miner.clone(),
         }
     }
@@ -41,7 +43,20 @@ impl Miner for MinerClient {
             cwarn!(MINER, "Cannot give work package - engine seals internally.");
             return Err(errors::no_work_required())
         }
-        unimplemented!();
+        self.miner.prepare_work_sealing(&*self.client);
+        self.miner
+            .map_sealing_work(|header| Work {
+                block_hash: header.hash().into(),
+                score: (*header.score()).into(),
+                block_number: header.number().into(),
+            })
+            .ok_or_else(|| {
+                if self.miner.is_currently_sealing() {
+                    errors::sealing_in_progress()
+                } else {
+                    errors::no_work()
+                }
+            })
     }
 
     fn submit_work(&self, _nonce: Bytes, _pow_hash: Bytes) -> Result<bool> {
@@ -51,4 +66,13 @@ impl Miner for MinerClient {
         }
         unimplemented!();
     }
+
+    fn set_minimal_fee(&self, min_fee: Quantity, remove_underpriced: Option<bool>) -> Result<usize> {
+        if remove_underpriced.unwrap_or(false) {
+            Ok(self.miner.set_minimal_fee_and_cull(min_fee.into(), &*self.client))
+        } else {
+            self.miner.set_minimal_fee(min_fee.into());
+            Ok(0)
+        }
+    }
 }
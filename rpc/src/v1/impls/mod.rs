@@ -16,8 +16,14 @@
 
 mod chain;
 mod devel;
+mod engine;
+mod mempool;
 mod miner;
+mod net;
 
 pub use self::chain::ChainClient;
 pub use self::devel::DevelClient;
+pub use self::engine::EngineClient;
+pub use self::mempool::MempoolClient;
 pub use self::miner::MinerClient;
+pub use self::net::NetClient;
@@ -0,0 +1,67 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use cnetwork::{NetworkService, SocketAddr};
+use csync::ParcelRelayPolicy;
+use jsonrpc_core::{Error, Result};
+
+use super::super::errors;
+use super::super::traits::Net;
+use super::super::types::ConnectedPeer;
+
+/// The time to wait for the handshake of `net_connect`.
+const DIAL_TIMEOUT_MS: u64 = 15_000;
+
+pub struct NetClient {
+    network: Option<Arc<NetworkService>>,
+    parcel_relay_policy: ParcelRelayPolicy,
+}
+
+impl NetClient {
+    pub fn new(network: &Option<Arc<NetworkService>>, parcel_relay_policy: ParcelRelayPolicy) -> Self {
+        Self {
+            network: network.clone(),
+            parcel_relay_policy,
+        }
+    }
+}
+
+impl Net for NetClient {
+    fn connect(&self, address: String, node_id: Option<String>) -> Result<ConnectedPeer> {
+        let network = self.network.as_ref().ok_or_else(errors::network_disabled)?;
+        let address = parse_address(&address)?;
+        let node_id = match node_id {
+            Some(node_id) => Some(parse_address(&node_id)?.into()),
+            None => None,
+        };
+        network
+            .dial(address, node_id, Duration::from_millis(DIAL_TIMEOUT_MS))
+            .map(ConnectedPeer::from)
+            .map_err(errors::dial)
+    }
+
+    fn get_parcel_relay_policy(&self) -> Result<String> {
+        Ok(self.parcel_relay_policy.to_string())
+    }
+}
+
+fn parse_address(address: &str) -> Result<SocketAddr> {
+    SocketAddr::from_str(address).map_err(|_| Error::invalid_params(format!("Invalid address: {}", address)))
+}
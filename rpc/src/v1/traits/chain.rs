@@ -14,12 +14,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ccore::{Asset, AssetScheme, Invoice, ParcelInvoice};
-use ctypes::{H160, H256, Public, U256};
-
 use jsonrpc_core::Result;
 
-use super::super::types::{Block, Bytes, Parcel};
+use super::super::types::{
+    Asset, AssetScheme, Block, BlockNumber, Bytes, ChainParams, Invoice, Parcel, ParcelInvoice, Quantity, H160, H256,
+    H512,
+};
 
 build_rpc_trait! {
     pub trait Chain {
@@ -49,15 +49,15 @@ build_rpc_trait! {
 
         /// Gets nonce with given account.
         # [rpc(name = "chain_getNonce")]
-        fn get_nonce(&self, H160, Option<u64>) -> Result<Option<U256>>;
+        fn get_nonce(&self, H160, Option<BlockNumber>) -> Result<Option<Quantity>>;
 
         /// Gets balance with given account.
         # [rpc(name = "chain_getBalance")]
-        fn get_balance(&self, H160, Option<u64>) -> Result<Option<U256>>;
+        fn get_balance(&self, H160, Option<BlockNumber>) -> Result<Option<Quantity>>;
 
         /// Gets regular key with given account
         # [rpc(name = "chain_getRegularKey")]
-        fn get_regular_key(&self, H160, Option<u64>) -> Result<Option<Public>>;
+        fn get_regular_key(&self, H160, Option<BlockNumber>) -> Result<Option<H512>>;
 
         /// Gets number of best block.
         # [rpc(name = "chain_getBlockNumber")]
@@ -78,5 +78,9 @@ build_rpc_trait! {
         /// Gets parcels in the current mem pool.
         # [rpc(name = "chain_getPendingParcels")]
         fn get_pending_parcels(&self) -> Result<Vec<Parcel>>;
+
+        /// Gets the parameters which parcels are built against.
+        # [rpc(name = "chain_getParams")]
+        fn get_params(&self) -> Result<ChainParams>;
     }
 }
@@ -14,11 +14,9 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ctypes::H256;
-
 use jsonrpc_core::Result;
 
-use super::super::types::Bytes;
+use super::super::types::{Bytes, ImportedBlock, MemoryInfo, H256};
 
 build_rpc_trait! {
     pub trait Devel {
@@ -27,5 +25,17 @@ build_rpc_trait! {
 
         # [rpc(name = "devel_getStateTrieValue")]
         fn get_state_trie_value(&self, H256) -> Result<Vec<Bytes>>;
+
+        # [rpc(name = "devel_getMemoryInfo")]
+        fn get_memory_info(&self) -> Result<MemoryInfo>;
+
+        # [rpc(name = "devel_importBlock")]
+        fn import_block(&self, Bytes) -> Result<ImportedBlock>;
+
+        # [rpc(name = "devel_verifyChainIndexes")]
+        fn verify_chain_indexes(&self, u64) -> Result<Vec<String>>;
+
+        # [rpc(name = "devel_takeSnapshot")]
+        fn take_snapshot(&self) -> Result<H256>;
     }
 }
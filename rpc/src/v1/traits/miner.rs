@@ -16,7 +16,7 @@
 
 use jsonrpc_core::Result;
 
-use super::super::types::{Bytes, Work};
+use super::super::types::{Bytes, Quantity, Work};
 
 build_rpc_trait! {
     pub trait Miner {
@@ -25,5 +25,10 @@ build_rpc_trait! {
 
         # [rpc(name = "miner_submitWork")]
         fn submit_work(&self, Bytes, Bytes) -> Result<bool>;
+
+        /// Sets the minimal fee of parcels to be accepted. If the second parameter is true, removes the queued
+        /// external parcels paying less than the new minimal fee. Returns the number of removed parcels.
+        # [rpc(name = "miner_setMinimalFee")]
+        fn set_minimal_fee(&self, Quantity, Option<bool>) -> Result<usize>;
     }
 }
@@ -16,8 +16,14 @@
 
 mod chain;
 mod devel;
+mod engine;
+mod mempool;
 mod miner;
+mod net;
 
 pub use self::chain::Chain;
 pub use self::devel::Devel;
+pub use self::engine::Engine;
+pub use self::mempool::Mempool;
 pub use self::miner::Miner;
+pub use self::net::Net;
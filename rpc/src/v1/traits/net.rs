@@ -0,0 +1,32 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use jsonrpc_core::Result;
+
+use super::super::types::ConnectedPeer;
+
+build_rpc_trait! {
+    pub trait Net {
+        /// Connects to the given address, optionally checking the node id of the peer,
+        /// and waits until the handshake finishes.
+        # [rpc(name = "net_connect")]
+        fn connect(&self, String, Option<String>) -> Result<ConnectedPeer>;
+
+        /// Gets which parcels are relayed to the peers: "none", "local-only" or "all".
+        # [rpc(name = "net_getParcelRelayPolicy")]
+        fn get_parcel_relay_policy(&self) -> Result<String>;
+    }
+}
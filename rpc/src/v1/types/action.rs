@@ -0,0 +1,117 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::Action as CoreAction;
+
+use super::{Quantity, Transaction, H160, H512};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "action")]
+pub enum Action {
+    ChangeShardState {
+        transactions: Vec<Transaction>,
+    },
+    Payment {
+        receiver: H160,
+        value: Quantity,
+    },
+    SetRegularKey {
+        key: H512,
+    },
+}
+
+impl From<CoreAction> for Action {
+    fn from(action: CoreAction) -> Self {
+        match action {
+            CoreAction::ChangeShardState {
+                transactions,
+            } => Action::ChangeShardState {
+                transactions: transactions.into_iter().map(Into::into).collect(),
+            },
+            CoreAction::Payment {
+                receiver,
+                value,
+            } => Action::Payment {
+                receiver: receiver.into(),
+                value: value.into(),
+            },
+            CoreAction::SetRegularKey {
+                key,
+            } => Action::SetRegularKey {
+                key: key.into(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ctypes;
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn payment_round_trips() {
+        let action = Action::from(CoreAction::Payment {
+            receiver: ctypes::H160::from(1),
+            value: ctypes::U256::from(1000),
+        });
+        let json = serde_json::to_value(&action).unwrap();
+        assert_eq!(
+            json!({
+                "action": "payment",
+                "receiver": "0x0000000000000000000000000000000000000001",
+                "value": "0x3e8",
+            }),
+            json
+        );
+        assert_eq!(action, serde_json::from_value(json).unwrap());
+    }
+
+    #[test]
+    fn set_regular_key_round_trips() {
+        let action = Action::from(CoreAction::SetRegularKey {
+            key: ctypes::H512::from(1),
+        });
+        let json = serde_json::to_value(&action).unwrap();
+        let key = format!("0x{}01", "0".repeat(126));
+        assert_eq!(
+            json!({
+                "action": "setRegularKey",
+                "key": key,
+            }),
+            json
+        );
+        assert_eq!(action, serde_json::from_value(json).unwrap());
+    }
+
+    #[test]
+    fn change_shard_state_round_trips() {
+        let action = Action::from(CoreAction::ChangeShardState {
+            transactions: vec![],
+        });
+        let json = serde_json::to_value(&action).unwrap();
+        assert_eq!(
+            json!({
+                "action": "changeShardState",
+                "transactions": [],
+            }),
+            json
+        );
+        assert_eq!(action, serde_json::from_value(json).unwrap());
+    }
+}
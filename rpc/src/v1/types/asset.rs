@@ -0,0 +1,97 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::{Asset as CoreAsset, AssetScheme as CoreAssetScheme};
+
+use super::{Bytes, Quantity, H160, H256};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Asset {
+    pub asset_type: H256,
+    pub lock_script_hash: H256,
+    pub parameters: Vec<Bytes>,
+    pub amount: Quantity,
+}
+
+impl From<CoreAsset> for Asset {
+    fn from(asset: CoreAsset) -> Self {
+        Self {
+            asset_type: (*asset.asset_type()).into(),
+            lock_script_hash: (*asset.lock_script_hash()).into(),
+            parameters: asset.parameters().iter().cloned().map(Into::into).collect(),
+            amount: (*asset.amount()).into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetScheme {
+    pub metadata: String,
+    pub amount: Quantity,
+    pub registrar: Option<H160>,
+}
+
+impl From<CoreAssetScheme> for AssetScheme {
+    fn from(scheme: CoreAssetScheme) -> Self {
+        Self {
+            metadata: scheme.metadata().clone(),
+            amount: (*scheme.amount()).into(),
+            registrar: (*scheme.registrar()).map(Into::into),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ctypes;
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn asset_round_trips() {
+        let asset = Asset::from(CoreAsset::new(ctypes::H256::from(1), ctypes::H256::from(2), vec![vec![0xab]], 1000));
+        let json = serde_json::to_value(&asset).unwrap();
+        assert_eq!(
+            json!({
+                "assetType": "0x0000000000000000000000000000000000000000000000000000000000000001",
+                "lockScriptHash": "0x0000000000000000000000000000000000000000000000000000000000000002",
+                "parameters": ["0xab"],
+                "amount": "0x3e8",
+            }),
+            json
+        );
+        assert_eq!(asset, serde_json::from_value(json).unwrap());
+    }
+
+    #[test]
+    fn asset_scheme_round_trips() {
+        let registrar = Some(ctypes::H160::from(3));
+        let scheme = AssetScheme::from(CoreAssetScheme::new("metadata".to_string(), 1000, registrar));
+        let json = serde_json::to_value(&scheme).unwrap();
+        assert_eq!(
+            json!({
+                "metadata": "metadata",
+                "amount": "0x3e8",
+                "registrar": "0x0000000000000000000000000000000000000003",
+            }),
+            json
+        );
+        assert_eq!(scheme, serde_json::from_value(json).unwrap());
+    }
+}
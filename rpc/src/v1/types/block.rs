@@ -15,26 +15,27 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use ccore::Block as CoreBlock;
-use ctypes::{H160, H256, U256};
+use ctypes::U256;
 
-use super::Parcel;
+use super::{Bytes, Parcel, Quantity, H160, H256};
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Block {
     parent_hash: H256,
-    timestamp: u64,
-    number: u64,
+    timestamp: Quantity,
+    number: Quantity,
     author: H160,
 
-    extra_data: Vec<u8>,
+    extra_data: Bytes,
 
     parcels_root: H256,
     state_root: H256,
     invoices_root: H256,
+    shard_roots: Vec<H256>,
 
-    score: U256,
-    seal: Vec<Vec<u8>>,
+    score: Quantity,
+    seal: Vec<Bytes>,
 
     hash: H256,
     parcels: Vec<Parcel>,
@@ -45,21 +46,22 @@ impl From<CoreBlock> for Block {
         let block_number = block.header.number();
         let block_hash = block.header.hash();
         Block {
-            parent_hash: block.header.parent_hash().clone(),
-            timestamp: block.header.timestamp(),
-            number: block.header.number(),
-            author: block.header.author().clone(),
+            parent_hash: (*block.header.parent_hash()).into(),
+            timestamp: block.header.timestamp().into(),
+            number: block.header.number().into(),
+            author: (*block.header.author()).into(),
 
-            extra_data: block.header.extra_data().clone(),
+            extra_data: block.header.extra_data().clone().into(),
 
-            parcels_root: block.header.parcels_root().clone(),
-            state_root: block.header.state_root().clone(),
-            invoices_root: block.header.invoices_root().clone(),
+            parcels_root: (*block.header.parcels_root()).into(),
+            state_root: (*block.header.state_root()).into(),
+            invoices_root: (*block.header.invoices_root()).into(),
+            shard_roots: block.header.shard_roots().iter().map(|root| (*root).into()).collect(),
 
-            score: block.header.score().clone(),
-            seal: block.header.seal().clone().to_vec(),
+            score: (*block.header.score()).into(),
+            seal: block.header.seal().iter().map(|field| field.clone().into()).collect(),
 
-            hash: block.header.hash(),
+            hash: block_hash.into(),
             parcels: block
                 .parcels
                 .into_iter()
@@ -67,20 +69,52 @@ impl From<CoreBlock> for Block {
                 .map(|(i, unverified)| {
                     let sig = unverified.signature();
                     Parcel {
-                        block_number: Some(block_number),
-                        block_hash: Some(block_hash),
+                        block_number: Some(block_number.into()),
+                        block_hash: Some(block_hash.into()),
                         parcel_index: Some(i),
-                        nonce: unverified.as_unsigned().nonce.clone(),
-                        fee: unverified.as_unsigned().fee.clone(),
-                        network_id: unverified.as_unsigned().network_id,
-                        action: unverified.as_unsigned().action.clone(),
-                        hash: unverified.hash(),
+                        nonce: unverified.as_unsigned().nonce.into(),
+                        fee: unverified.as_unsigned().fee.into(),
+                        network_id: unverified.as_unsigned().network_id.into(),
+                        action: unverified.as_unsigned().action.clone().into(),
+                        hash: unverified.hash().into(),
                         v: sig.v(),
-                        r: sig.r().into(),
-                        s: sig.s().into(),
+                        r: U256::from(sig.r()).into(),
+                        s: U256::from(sig.s()).into(),
                     }
                 })
                 .collect(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ccore::Header;
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn block_fields_are_serialized_as_hex() {
+        let mut header = Header::new();
+        header.set_number(3);
+        header.set_score(U256::from(0x20000));
+        header.set_extra_data(vec![1, 2]);
+        header.set_seal(vec![vec![0xab]]);
+        let hash = header.hash();
+        let block = Block::from(CoreBlock {
+            header,
+            parcels: Vec::new(),
+        });
+
+        let value = serde_json::to_value(&block).unwrap();
+        assert_eq!(json!("0x3"), value["number"]);
+        assert_eq!(json!("0x0"), value["timestamp"]);
+        assert_eq!(json!("0x20000"), value["score"]);
+        assert_eq!(json!("0x0102"), value["extraData"]);
+        assert_eq!(json!(["0xab"]), value["seal"]);
+        assert_eq!(json!(format!("0x{:x}", hash)), value["hash"]);
+        assert_eq!(json!(format!("0x{}", "0".repeat(64))), value["parentHash"]);
+        assert_eq!(json!(format!("0x{}", "0".repeat(40))), value["author"]);
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2015-2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Block number parameter which may also name a block by its position.
+
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Block number, or one of `"latest"`, `"earliest"` and `"pending"`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BlockNumber {
+    /// Number of a block in the canonical chain.
+    Num(u64),
+    /// Latest imported block.
+    Latest,
+    /// Genesis block.
+    Earliest,
+    /// Block being sealed by the miner.
+    Pending,
+}
+
+impl Default for BlockNumber {
+    fn default() -> Self {
+        BlockNumber::Latest
+    }
+}
+
+impl Serialize for BlockNumber {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer, {
+        match *self {
+            BlockNumber::Num(number) => serializer.serialize_u64(number),
+            BlockNumber::Latest => serializer.serialize_str("latest"),
+            BlockNumber::Earliest => serializer.serialize_str("earliest"),
+            BlockNumber::Pending => serializer.serialize_str("pending"),
+        }
+    }
+}
+
+impl<'a> Deserialize<'a> for BlockNumber {
+    fn deserialize<D>(deserializer: D) -> Result<BlockNumber, D::Error>
+    where
+        D: Deserializer<'a>, {
+        deserializer.deserialize_any(BlockNumberVisitor)
+    }
+}
+
+struct BlockNumberVisitor;
+
+impl<'a> Visitor<'a> for BlockNumberVisitor {
+    type Value = BlockNumber;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a block number, a 0x-prefixed block number, 'latest', 'earliest' or 'pending'")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: Error, {
+        Ok(BlockNumber::Num(value))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: Error, {
+        match value {
+            "latest" => Ok(BlockNumber::Latest),
+            "earliest" => Ok(BlockNumber::Earliest),
+            "pending" => Ok(BlockNumber::Pending),
+            _ if value.starts_with("0x") => u64::from_str_radix(&value[2..], 16)
+                .map(BlockNumber::Num)
+                .map_err(|e| Error::custom(format!("Invalid block number: {}", e))),
+            _ => Err(Error::custom("Invalid block number. Expected a number, 'latest', 'earliest' or 'pending'")),
+        }
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+    where
+        E: Error, {
+        self.visit_str(value.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_block_number_deserialize() {
+        let numbers: Vec<BlockNumber> =
+            serde_json::from_str(r#"[10, "0xa", "latest", "earliest", "pending"]"#).unwrap();
+        assert_eq!(
+            numbers,
+            vec![
+                BlockNumber::Num(10),
+                BlockNumber::Num(10),
+                BlockNumber::Latest,
+                BlockNumber::Earliest,
+                BlockNumber::Pending,
+            ]
+        );
+
+        let invalid: Result<BlockNumber, serde_json::Error> = serde_json::from_str(r#""newest""#);
+        assert!(invalid.is_err());
+        let invalid: Result<BlockNumber, serde_json::Error> = serde_json::from_str(r#""0xgg""#);
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_block_number_serialize() {
+        let numbers = vec![BlockNumber::Num(10), BlockNumber::Latest, BlockNumber::Earliest, BlockNumber::Pending];
+        assert_eq!(serde_json::to_string(&numbers).unwrap(), r#"[10,"latest","earliest","pending"]"#);
+    }
+}
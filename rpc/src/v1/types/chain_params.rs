@@ -0,0 +1,134 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::CommonParams;
+use ctypes::U256;
+
+use super::Quantity;
+
+/// The parameters which parcels must be built against.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainParams {
+    pub network_id: Quantity,
+    pub min_parcel_cost: Quantity,
+    /// The reward for the author of the next block.
+    pub block_reward: Quantity,
+    pub maximum_extra_data_size: usize,
+    pub max_body_size: usize,
+    pub max_parcels_per_block: usize,
+    pub shard_count: u32,
+}
+
+impl ChainParams {
+    pub fn new(params: &CommonParams, block_reward: U256) -> Self {
+        Self {
+            network_id: params.network_id.into(),
+            min_parcel_cost: params.min_parcel_cost.into(),
+            block_reward: block_reward.into(),
+            maximum_extra_data_size: params.maximum_extra_data_size,
+            max_body_size: params.max_body_size,
+            max_parcels_per_block: params.max_parcels_per_block,
+            shard_count: params.shard_count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ccore::{CodeChainEngine, CommonParams, ConsensusEngine, ParamsTransition, Spec};
+    use serde_json;
+
+    use super::ChainParams;
+
+    #[test]
+    fn params_of_the_solo_spec() {
+        let engine = Spec::new_test_solo().engine;
+        let params = ChainParams::new(engine.params(), engine.block_reward(1));
+        assert_eq!(
+            json!({
+                "networkId": "0x11",
+                "minParcelCost": "0xa",
+                "blockReward": "0x0",
+                "maximumExtraDataSize": 32,
+                "maxBodySize": 4194304,
+                "maxParcelsPerBlock": 4096,
+                "shardCount": 1,
+            }),
+            serde_json::to_value(&params).unwrap()
+        );
+    }
+
+    #[test]
+    fn params_of_the_tendermint_spec() {
+        let engine = Spec::new_test_tendermint().engine;
+        let params = ChainParams::new(engine.params(), engine.block_reward(1));
+        assert_eq!(
+            json!({
+                "networkId": "0x11",
+                "minParcelCost": "0xa",
+                "blockReward": "0x0",
+                "maximumExtraDataSize": 32,
+                "maxBodySize": 4194304,
+                "maxParcelsPerBlock": 4096,
+                "shardCount": 1,
+            }),
+            serde_json::to_value(&params).unwrap()
+        );
+    }
+
+    #[test]
+    fn params_have_the_overrides_activated_by_the_block() {
+        let common_params = CommonParams {
+            maximum_extra_data_size: 32,
+            max_body_size: 1000,
+            max_parcels_per_block: 10,
+            network_id: 17,
+            min_parcel_cost: 10.into(),
+            shard_count: 1,
+            transitions: vec![
+                ParamsTransition {
+                    block_number: 10,
+                    max_body_size: None,
+                    max_parcels_per_block: None,
+                    min_parcel_cost: Some(20.into()),
+                },
+                ParamsTransition {
+                    block_number: 20,
+                    max_body_size: Some(2000),
+                    max_parcels_per_block: Some(20),
+                    min_parcel_cost: None,
+                },
+            ],
+        };
+        let json_at = |block_number| serde_json::to_value(&ChainParams::new(&common_params.at(block_number), 0.into()));
+        let expected = |min_parcel_cost: &str, max_body_size: usize, max_parcels_per_block: usize| {
+            json!({
+                "networkId": "0x11",
+                "minParcelCost": min_parcel_cost,
+                "blockReward": "0x0",
+                "maximumExtraDataSize": 32,
+                "maxBodySize": max_body_size,
+                "maxParcelsPerBlock": max_parcels_per_block,
+                "shardCount": 1,
+            })
+        };
+        assert_eq!(expected("0xa", 1000, 10), json_at(9).unwrap());
+        assert_eq!(expected("0x14", 1000, 10), json_at(10).unwrap());
+        assert_eq!(expected("0x14", 2000, 20), json_at(20).unwrap());
+        assert_eq!(2000, common_params.largest_max_body_size());
+    }
+}
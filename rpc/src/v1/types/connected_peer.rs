@@ -0,0 +1,47 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use cnetwork::{DialConnected, IntoSocketAddr};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedPeer {
+    pub node_id: String,
+    pub extensions: Vec<NegotiatedExtension>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NegotiatedExtension {
+    pub name: String,
+    pub version: u64,
+}
+
+impl From<DialConnected> for ConnectedPeer {
+    fn from(connected: DialConnected) -> Self {
+        Self {
+            node_id: connected.node_id.into_addr().to_string(),
+            extensions: connected
+                .extensions
+                .into_iter()
+                .map(|(name, version)| NegotiatedExtension {
+                    name,
+                    version,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+use ccore::{CodeChainEngine, ConsensusEngine};
+use ctypes::H256;
+
+use super::Quantity;
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EngineInfo {
+    pub name: String,
+    pub seals_internally: Option<bool>,
+    /// The number of validators for the next block. None if the engine has no validators.
+    pub validator_count: Option<usize>,
+    /// The timeouts of the consensus steps in milliseconds. None if the engine has no steps.
+    pub timeouts: Option<BTreeMap<String, Quantity>>,
+}
+
+impl EngineInfo {
+    /// Describes `engine` for the child of the block `parent`.
+    pub fn new(engine: &CodeChainEngine, parent: &H256) -> Self {
+        let timeouts = engine.step_timeouts();
+        Self {
+            name: engine.name().to_string(),
+            seals_internally: engine.seals_internally(),
+            validator_count: engine.validator_count(parent),
+            timeouts: if timeouts.is_empty() {
+                None
+            } else {
+                Some(timeouts.into_iter().map(|(step, timeout)| (step.to_string(), timeout.into())).collect())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ccore::Spec;
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn info_of_the_solo_engine() {
+        let spec = Spec::new_test_solo();
+        let info = EngineInfo::new(&*spec.engine, &spec.genesis_header().hash());
+        assert_eq!(
+            json!({
+                "name": "Solo",
+                "sealsInternally": true,
+                "validatorCount": null,
+                "timeouts": null,
+            }),
+            serde_json::to_value(&info).unwrap()
+        );
+    }
+
+    #[test]
+    fn info_of_the_tendermint_engine() {
+        let spec = Spec::new_test_tendermint();
+        let info = EngineInfo::new(&*spec.engine, &spec.genesis_header().hash());
+        assert_eq!(
+            json!({
+                "name": "Tendermint",
+                "sealsInternally": false,
+                "validatorCount": 4,
+                "timeouts": {
+                    "propose": "0x2710",
+                    "prevote": "0x2710",
+                    "precommit": "0x2710",
+                    "commit": "0x2710",
+                },
+            }),
+            serde_json::to_value(&info).unwrap()
+        );
+    }
+}
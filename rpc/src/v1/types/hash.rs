@@ -0,0 +1,149 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Serializable wrappers around fixed-size hashes
+
+use std::fmt;
+
+use ctypes;
+use rustc_hex::{FromHex, ToHex};
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+macro_rules! impl_hash {
+    ($name:ident, $inner:ident, $size:expr, $kind:expr) => {
+        /// A 0x-prefixed hex string of exactly the size of the hash.
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+        pub struct $name(ctypes::$inner);
+
+        impl From<ctypes::$inner> for $name {
+            fn from(hash: ctypes::$inner) -> Self {
+                $name(hash)
+            }
+        }
+
+        impl Into<ctypes::$inner> for $name {
+            fn into(self) -> ctypes::$inner {
+                self.0
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer, {
+                let mut serialized = "0x".to_string();
+                serialized.push_str(self.0.to_hex().as_ref());
+                serializer.serialize_str(serialized.as_ref())
+            }
+        }
+
+        impl<'a> Deserialize<'a> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<$name, D::Error>
+            where
+                D: Deserializer<'a>, {
+                struct HashVisitor;
+
+                impl<'a> Visitor<'a> for HashVisitor {
+                    type Value = $name;
+
+                    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                        write!(formatter, "a 0x-prefixed hex string of {} bytes", $size)
+                    }
+
+                    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+                    where
+                        E: Error, {
+                        let invalid = || {
+                            let message = format!("Expected a 0x-prefixed hex string of {} bytes", $size);
+                            Error::custom(format!("Invalid {} {:?}. {}", $kind, value, message))
+                        };
+                        if !value.starts_with("0x") || value.len() != 2 + 2 * $size {
+                            return Err(invalid())
+                        }
+                        let bytes: Vec<u8> = value[2..].from_hex().map_err(|_| invalid())?;
+                        Ok($name(ctypes::$inner::from_slice(&bytes)))
+                    }
+
+                    fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+                    where
+                        E: Error, {
+                        self.visit_str(value.as_ref())
+                    }
+                }
+
+                deserializer.deserialize_any(HashVisitor)
+            }
+        }
+    };
+}
+
+impl_hash!(H160, H160, 20, "address");
+impl_hash!(H256, H256, 32, "hash");
+impl_hash!(H512, H512, 64, "public key");
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn hashes_are_serialized_in_full() {
+        assert_eq!(
+            r#""0x0000000000000000000000000000000000000001""#,
+            serde_json::to_string(&H160::from(ctypes::H160::from(1))).unwrap()
+        );
+        assert_eq!(
+            r#""0x0000000000000000000000000000000000000000000000000000000000000000""#,
+            serde_json::to_string(&H256::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn hashes_round_trip() {
+        let hash = H256::from(ctypes::H256::from(0x1234));
+        assert_eq!(hash, serde_json::from_str(&serde_json::to_string(&hash).unwrap()).unwrap());
+        let public = H512::from(ctypes::H512::from(0x1234));
+        assert_eq!(public, serde_json::from_str(&serde_json::to_string(&public).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn malformed_hashes_are_rejected() {
+        let cases = [
+            r#""0000000000000000000000000000000000000001""#,
+            r#""0x000000000000000000000000000000000000001""#,
+            r#""0x000000000000000000000000000000000000000001""#,
+            r#""0x000000000000000000000000000000000000000g""#,
+            r#""0x""#,
+            "1",
+        ];
+        for case in cases.iter() {
+            assert!(serde_json::from_str::<H160>(case).is_err(), "{} is accepted", case);
+        }
+    }
+
+    #[test]
+    fn errors_name_what_the_hash_is() {
+        let error = serde_json::from_str::<H160>(r#""0x12""#).unwrap_err().to_string();
+        let expected = r#"Invalid address "0x12". Expected a 0x-prefixed hex string of 20 bytes"#;
+        assert!(error.starts_with(expected), "{}", error);
+        let error = serde_json::from_str::<H256>(r#""0x12""#).unwrap_err().to_string();
+        assert!(error.starts_with(r#"Invalid hash "0x12""#), "{}", error);
+        let error = serde_json::from_str::<H512>(r#""0x12""#).unwrap_err().to_string();
+        assert!(error.starts_with(r#"Invalid public key "0x12""#), "{}", error);
+    }
+}
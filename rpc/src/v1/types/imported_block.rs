@@ -0,0 +1,48 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::BlockImportOutcome;
+
+use super::H256;
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportStatus {
+    Imported,
+    AlreadyInChain,
+    Queued,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedBlock {
+    pub hash: H256,
+    pub status: ImportStatus,
+}
+
+impl From<BlockImportOutcome> for ImportedBlock {
+    fn from(outcome: BlockImportOutcome) -> Self {
+        let (hash, status) = match outcome {
+            BlockImportOutcome::Imported(hash) => (hash, ImportStatus::Imported),
+            BlockImportOutcome::AlreadyInChain(hash) => (hash, ImportStatus::AlreadyInChain),
+            BlockImportOutcome::Queued(hash) => (hash, ImportStatus::Queued),
+        };
+        Self {
+            hash: hash.into(),
+            status,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::{Invoice as CoreInvoice, ParcelInvoice as CoreParcelInvoice};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Invoice {
+    Success,
+    Failed,
+}
+
+impl From<CoreInvoice> for Invoice {
+    fn from(invoice: CoreInvoice) -> Self {
+        match invoice {
+            CoreInvoice::Success => Invoice::Success,
+            CoreInvoice::Failed => Invoice::Failed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ParcelInvoice {
+    Single(Invoice),
+    Multiple(Vec<Invoice>),
+}
+
+impl From<CoreParcelInvoice> for ParcelInvoice {
+    fn from(invoice: CoreParcelInvoice) -> Self {
+        match invoice {
+            CoreParcelInvoice::Single(invoice) => ParcelInvoice::Single(invoice.into()),
+            CoreParcelInvoice::Multiple(invoices) => {
+                ParcelInvoice::Multiple(invoices.into_iter().map(Into::into).collect())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn invoices_round_trip() {
+        let invoice = ParcelInvoice::from(CoreParcelInvoice::new(vec![CoreInvoice::Success, CoreInvoice::Failed]));
+        let json = serde_json::to_value(&invoice).unwrap();
+        assert_eq!(json!(["Success", "Failed"]), json);
+        assert_eq!(invoice, serde_json::from_value(json).unwrap());
+
+        let invoice = ParcelInvoice::from(CoreParcelInvoice::Single(CoreInvoice::Failed));
+        let json = serde_json::to_value(&invoice).unwrap();
+        assert_eq!(json!("Failed"), json);
+        assert_eq!(invoice, serde_json::from_value(json).unwrap());
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::MemoryInfo as CoreMemoryInfo;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryInfo {
+    pub mem_pool: usize,
+    pub state_cache: usize,
+    pub chain_cache: usize,
+    pub block_queue: usize,
+    pub header_queue: usize,
+}
+
+impl From<CoreMemoryInfo> for MemoryInfo {
+    fn from(info: CoreMemoryInfo) -> Self {
+        Self {
+            mem_pool: info.mem_pool,
+            state_cache: info.state_cache,
+            chain_cache: info.chain_cache,
+            block_queue: info.block_queue,
+            header_queue: info.header_queue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn memory_info_round_trips() {
+        let info = MemoryInfo::from(CoreMemoryInfo {
+            mem_pool: 1,
+            state_cache: 2,
+            chain_cache: 3,
+            block_queue: 4,
+            header_queue: 5,
+        });
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(
+            json!({
+                "memPool": 1,
+                "stateCache": 2,
+                "chainCache": 3,
+                "blockQueue": 4,
+                "headerQueue": 5,
+            }),
+            json
+        );
+        assert_eq!(info, serde_json::from_value(json).unwrap());
+    }
+}
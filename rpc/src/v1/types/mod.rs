@@ -14,12 +14,38 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod action;
+mod asset;
 mod block;
+mod block_number;
 mod bytes;
+mod chain_params;
+mod connected_peer;
+mod engine_info;
+mod hash;
+mod imported_block;
+mod invoice;
+mod memory_info;
 mod parcel;
+mod quantity;
+mod sender_issues;
+mod transaction;
 mod work;
 
+pub use self::action::Action;
+pub use self::asset::{Asset, AssetScheme};
 pub use self::block::Block;
+pub use self::block_number::BlockNumber;
 pub use self::bytes::Bytes;
+pub use self::chain_params::ChainParams;
+pub use self::connected_peer::{ConnectedPeer, NegotiatedExtension};
+pub use self::engine_info::EngineInfo;
+pub use self::hash::{H160, H256, H512};
+pub use self::imported_block::{ImportStatus, ImportedBlock};
+pub use self::invoice::{Invoice, ParcelInvoice};
+pub use self::memory_info::MemoryInfo;
 pub use self::parcel::Parcel;
+pub use self::quantity::Quantity;
+pub use self::sender_issues::SenderIssues;
+pub use self::transaction::{AssetOutPoint, AssetTransferInput, AssetTransferOutput, Transaction};
 pub use self::work::Work;
@@ -14,40 +14,42 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ccore::{Action, LocalizedParcel, SignedParcel};
-use ctypes::{H256, U256};
+use ccore::{LocalizedParcel, SignedParcel};
+use ctypes::U256;
+
+use super::{Action, Quantity, H256};
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Parcel {
-    pub block_number: Option<u64>,
+    pub block_number: Option<Quantity>,
     pub block_hash: Option<H256>,
     pub parcel_index: Option<usize>,
-    pub nonce: U256,
-    pub fee: U256,
-    pub network_id: u64,
+    pub nonce: Quantity,
+    pub fee: Quantity,
+    pub network_id: Quantity,
     pub action: Action,
     pub hash: H256,
     pub v: u8,
-    pub r: U256,
-    pub s: U256,
+    pub r: Quantity,
+    pub s: Quantity,
 }
 
 impl From<LocalizedParcel> for Parcel {
     fn from(p: LocalizedParcel) -> Self {
         let sig = p.signature();
         Self {
-            block_number: Some(p.block_number),
-            block_hash: Some(p.block_hash),
+            block_number: Some(p.block_number.into()),
+            block_hash: Some(p.block_hash.into()),
             parcel_index: Some(p.parcel_index),
-            nonce: p.nonce,
-            fee: p.fee,
-            network_id: p.network_id,
-            action: p.action.clone(),
-            hash: p.hash(),
+            nonce: p.nonce.into(),
+            fee: p.fee.into(),
+            network_id: p.network_id.into(),
+            action: p.action.clone().into(),
+            hash: p.hash().into(),
             v: sig.v(),
-            r: sig.r().into(),
-            s: sig.s().into(),
+            r: U256::from(sig.r()).into(),
+            s: U256::from(sig.s()).into(),
         }
     }
 }
@@ -59,14 +61,14 @@ impl From<SignedParcel> for Parcel {
             block_number: None,
             block_hash: None,
             parcel_index: None,
-            nonce: p.nonce,
-            fee: p.fee,
-            network_id: p.network_id,
-            action: p.action.clone(),
-            hash: p.hash(),
+            nonce: p.nonce.into(),
+            fee: p.fee.into(),
+            network_id: p.network_id.into(),
+            action: p.action.clone().into(),
+            hash: p.hash().into(),
             v: sig.v(),
-            r: sig.r().into(),
-            s: sig.s().into(),
+            r: U256::from(sig.r()).into(),
+            s: U256::from(sig.s()).into(),
         }
     }
 }
@@ -0,0 +1,152 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Serializable wrapper around unsigned integers
+
+use std::fmt;
+
+use ctypes::U256;
+use rustc_hex::{FromHex, ToHex};
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A 0x-prefixed hex number without leading zeros. Zero is "0x0".
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Quantity(U256);
+
+impl From<U256> for Quantity {
+    fn from(value: U256) -> Self {
+        Quantity(value)
+    }
+}
+
+impl From<u64> for Quantity {
+    fn from(value: u64) -> Self {
+        Quantity(value.into())
+    }
+}
+
+impl Into<U256> for Quantity {
+    fn into(self) -> U256 {
+        self.0
+    }
+}
+
+impl Serialize for Quantity {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer, {
+        let mut bytes = [0u8; 32];
+        self.0.to_big_endian(&mut bytes);
+        let hex: String = bytes.to_hex();
+        let digits = hex.trim_left_matches('0');
+        let mut serialized = "0x".to_string();
+        serialized.push_str(if digits.is_empty() {
+            "0"
+        } else {
+            digits
+        });
+        serializer.serialize_str(serialized.as_ref())
+    }
+}
+
+impl<'a> Deserialize<'a> for Quantity {
+    fn deserialize<D>(deserializer: D) -> Result<Quantity, D::Error>
+    where
+        D: Deserializer<'a>, {
+        deserializer.deserialize_any(QuantityVisitor)
+    }
+}
+
+struct QuantityVisitor;
+
+impl<'a> Visitor<'a> for QuantityVisitor {
+    type Value = Quantity;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a 0x-prefixed hex number without leading zeros")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: Error, {
+        let invalid = || {
+            let message = "Expected a 0x-prefixed hex number without leading zeros";
+            Error::custom(format!("Invalid quantity {:?}. {}", value, message))
+        };
+        if !value.starts_with("0x") {
+            return Err(invalid())
+        }
+        let digits = &value[2..];
+        if digits.is_empty() || digits.len() > 64 || (digits.starts_with('0') && digits != "0") {
+            return Err(invalid())
+        }
+
+        let padded = if digits.len() % 2 == 1 {
+            format!("0{}", digits)
+        } else {
+            digits.to_string()
+        };
+        let bytes: Vec<u8> = padded.from_hex().map_err(|_| invalid())?;
+        Ok(Quantity(U256::from(bytes.as_slice())))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+    where
+        E: Error, {
+        self.visit_str(value.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn quantities_are_serialized_without_leading_zeros() {
+        let cases: [(U256, &str); 5] = [
+            (U256::zero(), r#""0x0""#),
+            (U256::from(1), r#""0x1""#),
+            (U256::from(0x10), r#""0x10""#),
+            (U256::from(0x1234), r#""0x1234""#),
+            (U256::max_value(), r#""0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff""#),
+        ];
+        for (value, serialized) in cases.iter() {
+            assert_eq!(*serialized, serde_json::to_string(&Quantity::from(*value)).unwrap());
+            assert_eq!(Quantity::from(*value), serde_json::from_str(serialized).unwrap());
+        }
+        assert_eq!(r#""0xa""#, serde_json::to_string(&Quantity::from(10u64)).unwrap());
+    }
+
+    #[test]
+    fn malformed_quantities_are_rejected() {
+        let cases = [
+            r#""""#,
+            r#""0x""#,
+            r#""0x01""#,
+            r#""0x00""#,
+            r#""12""#,
+            r#""0xg""#,
+            r#""0x10000000000000000000000000000000000000000000000000000000000000000""#,
+            "12",
+        ];
+        for case in cases.iter() {
+            assert!(serde_json::from_str::<Quantity>(case).is_err(), "{} is accepted", case);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::SenderIssues as CoreSenderIssues;
+
+use super::Quantity;
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SenderIssues {
+    /// The account nonce of the sender.
+    pub state_nonce: Quantity,
+    /// The nonce the mem pool expects next. The parcels from the account nonce up to it are ready.
+    pub next_nonce: Quantity,
+    /// The nonces queued after the gaps.
+    pub gapped_nonces: Vec<Quantity>,
+    /// The nonces missing between the account nonce and the highest queued nonce.
+    pub gaps: Vec<Quantity>,
+    /// The number of parcels waiting for the missing nonces.
+    pub stuck_count: usize,
+    /// The minimal fee to replace the stuck parcel with the lowest nonce. None if nothing is stuck.
+    pub replacement_fee: Option<Quantity>,
+}
+
+impl From<CoreSenderIssues> for SenderIssues {
+    fn from(issues: CoreSenderIssues) -> Self {
+        Self {
+            state_nonce: issues.state_nonce.into(),
+            next_nonce: issues.ready.end.into(),
+            gapped_nonces: issues.gapped.into_iter().map(Into::into).collect(),
+            gaps: issues.gaps.into_iter().map(Into::into).collect(),
+            stuck_count: issues.stuck,
+            replacement_fee: issues.replacement_fee.map(Into::into),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ctypes::U256;
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn issues_of_a_gapped_sender() {
+        let issues = SenderIssues::from(CoreSenderIssues {
+            state_nonce: U256::from(5),
+            ready: U256::from(5)..U256::from(7),
+            gapped: vec![U256::from(8), U256::from(9)],
+            gaps: vec![U256::from(7), U256::from(10)],
+            stuck: 2,
+            replacement_fee: Some(U256::from(90)),
+        });
+        assert_eq!(
+            json!({
+                "stateNonce": "0x5",
+                "nextNonce": "0x7",
+                "gappedNonces": ["0x8", "0x9"],
+                "gaps": ["0x7", "0xa"],
+                "stuckCount": 2,
+                "replacementFee": "0x5a",
+            }),
+            serde_json::to_value(&issues).unwrap()
+        );
+    }
+
+    #[test]
+    fn healthy_sender_has_no_issues() {
+        let issues = SenderIssues::from(CoreSenderIssues::default());
+        assert_eq!(
+            json!({
+                "stateNonce": "0x0",
+                "nextNonce": "0x0",
+                "gappedNonces": [],
+                "gaps": [],
+                "stuckCount": 0,
+                "replacementFee": null,
+            }),
+            serde_json::to_value(&issues).unwrap()
+        );
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::{
+    AssetOutPoint as CoreAssetOutPoint, AssetTransferInput as CoreAssetTransferInput,
+    AssetTransferOutput as CoreAssetTransferOutput, Transaction as CoreTransaction,
+};
+
+use super::{Bytes, Quantity, H160, H256};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetOutPoint {
+    pub transaction_hash: H256,
+    pub index: usize,
+    pub asset_type: H256,
+    pub amount: Quantity,
+}
+
+impl From<CoreAssetOutPoint> for AssetOutPoint {
+    fn from(out_point: CoreAssetOutPoint) -> Self {
+        Self {
+            transaction_hash: out_point.transaction_hash.into(),
+            index: out_point.index,
+            asset_type: out_point.asset_type.into(),
+            amount: out_point.amount.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetTransferInput {
+    pub prev_out: AssetOutPoint,
+    pub lock_script: Bytes,
+    pub unlock_script: Bytes,
+}
+
+impl From<CoreAssetTransferInput> for AssetTransferInput {
+    fn from(input: CoreAssetTransferInput) -> Self {
+        Self {
+            prev_out: input.prev_out.into(),
+            lock_script: input.lock_script.into(),
+            unlock_script: input.unlock_script.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetTransferOutput {
+    pub lock_script_hash: H256,
+    pub parameters: Vec<Bytes>,
+    pub asset_type: H256,
+    pub amount: Quantity,
+}
+
+impl From<CoreAssetTransferOutput> for AssetTransferOutput {
+    fn from(output: CoreAssetTransferOutput) -> Self {
+        Self {
+            lock_script_hash: output.lock_script_hash.into(),
+            parameters: output.parameters.into_iter().map(Into::into).collect(),
+            asset_type: output.asset_type.into(),
+            amount: output.amount.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "type", content = "data")]
+pub enum Transaction {
+    #[serde(rename_all = "camelCase")]
+    AssetMint {
+        metadata: String,
+        lock_script_hash: H256,
+        parameters: Vec<Bytes>,
+        amount: Option<Quantity>,
+        registrar: Option<H160>,
+        nonce: Quantity,
+    },
+    #[serde(rename_all = "camelCase")]
+    AssetTransfer {
+        network_id: Quantity,
+        burns: Vec<AssetTransferInput>,
+        inputs: Vec<AssetTransferInput>,
+        outputs: Vec<AssetTransferOutput>,
+        nonce: Quantity,
+    },
+}
+
+impl From<CoreTransaction> for Transaction {
+    fn from(transaction: CoreTransaction) -> Self {
+        match transaction {
+            CoreTransaction::AssetMint {
+                metadata,
+                lock_script_hash,
+                parameters,
+                amount,
+                registrar,
+                nonce,
+            } => Transaction::AssetMint {
+                metadata,
+                lock_script_hash: lock_script_hash.into(),
+                parameters: parameters.into_iter().map(Into::into).collect(),
+                amount: amount.map(Into::into),
+                registrar: registrar.map(Into::into),
+                nonce: nonce.into(),
+            },
+            CoreTransaction::AssetTransfer {
+                network_id,
+                burns,
+                inputs,
+                outputs,
+                nonce,
+            } => Transaction::AssetTransfer {
+                network_id: network_id.into(),
+                burns: burns.into_iter().map(Into::into).collect(),
+                inputs: inputs.into_iter().map(Into::into).collect(),
+                outputs: outputs.into_iter().map(Into::into).collect(),
+                nonce: nonce.into(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ctypes;
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn asset_mint_round_trips() {
+        let transaction = Transaction::from(CoreTransaction::AssetMint {
+            metadata: "metadata".to_string(),
+            lock_script_hash: ctypes::H256::from(1),
+            parameters: vec![vec![0xab]],
+            amount: Some(1000),
+            registrar: None,
+            nonce: 10,
+        });
+        let json = serde_json::to_value(&transaction).unwrap();
+        assert_eq!(
+            json!({
+                "type": "assetMint",
+                "data": {
+                    "metadata": "metadata",
+                    "lockScriptHash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+                    "parameters": ["0xab"],
+                    "amount": "0x3e8",
+                    "registrar": null,
+                    "nonce": "0xa",
+                },
+            }),
+            json
+        );
+        assert_eq!(transaction, serde_json::from_value(json).unwrap());
+    }
+
+    #[test]
+    fn asset_transfer_round_trips() {
+        let input = CoreAssetTransferInput {
+            prev_out: CoreAssetOutPoint {
+                transaction_hash: ctypes::H256::from(1),
+                index: 0,
+                asset_type: ctypes::H256::from(2),
+                amount: 30,
+            },
+            lock_script: vec![0x01],
+            unlock_script: vec![0x02],
+        };
+        let output = CoreAssetTransferOutput {
+            lock_script_hash: ctypes::H256::from(3),
+            parameters: vec![],
+            asset_type: ctypes::H256::from(2),
+            amount: 30,
+        };
+        let transaction = Transaction::from(CoreTransaction::AssetTransfer {
+            network_id: 17,
+            burns: vec![],
+            inputs: vec![input],
+            outputs: vec![output],
+            nonce: 0,
+        });
+        let json = serde_json::to_value(&transaction).unwrap();
+        assert_eq!(
+            json!({
+                "type": "assetTransfer",
+                "data": {
+                    "networkId": "0x11",
+                    "burns": [],
+                    "inputs": [{
+                        "prevOut": {
+                            "transactionHash": "0x0000000000000000000000000000000000000000000000000000000000000001",
+                            "index": 0,
+                            "assetType": "0x0000000000000000000000000000000000000000000000000000000000000002",
+                            "amount": "0x1e",
+                        },
+                        "lockScript": "0x01",
+                        "unlockScript": "0x02",
+                    }],
+                    "outputs": [{
+                        "lockScriptHash": "0x0000000000000000000000000000000000000000000000000000000000000003",
+                        "parameters": [],
+                        "assetType": "0x0000000000000000000000000000000000000000000000000000000000000002",
+                        "amount": "0x1e",
+                    }],
+                    "nonce": "0x0",
+                },
+            }),
+            json
+        );
+        assert_eq!(transaction, serde_json::from_value(json).unwrap());
+    }
+}
@@ -14,12 +14,37 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ctypes::{H256, U256};
+use super::{Quantity, H256};
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Work {
     pub block_hash: H256,
-    pub score: U256,
-    pub block_number: u64,
+    pub score: Quantity,
+    pub block_number: Quantity,
+}
+
+#[cfg(test)]
+mod tests {
+    use ctypes;
+    use serde_json;
+
+    use super::*;
+
+    #[test]
+    fn work_is_serialized_with_hex_quantities() {
+        let work = Work {
+            block_hash: ctypes::H256::from(1).into(),
+            score: 0x100u64.into(),
+            block_number: 16u64.into(),
+        };
+        assert_eq!(
+            json!({
+                "blockHash": format!("0x{}1", "0".repeat(63)),
+                "score": "0x100",
+                "blockNumber": "0x10",
+            }),
+            serde_json::to_value(&work).unwrap()
+        );
+    }
 }
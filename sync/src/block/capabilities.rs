@@ -0,0 +1,123 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::ops::BitOr;
+
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+/// Optional features a peer supports, advertised in the `Status` message.
+///
+/// Bits which are not known to this version are kept as they are but ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    /// Serves `Headers` requests
+    pub const SERVE_HEADERS: Capabilities = Capabilities(1 << 0);
+    /// Serves `Bodies` requests
+    pub const SERVE_BODIES: Capabilities = Capabilities(1 << 1);
+    /// Serves `StateChunk` requests for the snapshot in the `SnapshotManifest` response.
+    /// `StateHead` requests are never served.
+    pub const SERVE_STATE_CHUNKS: Capabilities = Capabilities(1 << 2);
+    // 1 << 3 was meant for the parcel relay, which is negotiated by the parcel extension instead.
+    /// Serves `SnapshotManifest` requests
+    pub const SERVE_SNAPSHOT_MANIFESTS: Capabilities = Capabilities(1 << 4);
+
+    pub fn empty() -> Self {
+        Capabilities(0)
+    }
+
+    /// Capabilities of a peer which doesn't advertise them, i.e. speaks the protocol version 0.
+    pub fn legacy() -> Self {
+        Self::SERVE_HEADERS | Self::SERVE_BODIES
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Capabilities(bits)
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(&self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Capabilities) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Capabilities) {
+        self.0 &= !other.0;
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::SERVE_HEADERS | Self::SERVE_BODIES
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+impl Encodable for Capabilities {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.append(&self.0);
+    }
+}
+
+impl Decodable for Capabilities {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        Ok(Capabilities(rlp.as_val()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlp;
+
+    use super::Capabilities;
+
+    #[test]
+    fn capabilities_rlp() {
+        let capabilities = Capabilities::SERVE_HEADERS | Capabilities::SERVE_BODIES;
+        assert_eq!(capabilities, rlp::decode(&rlp::encode(&capabilities)));
+    }
+
+    #[test]
+    fn unknown_bits_are_ignored() {
+        let capabilities: Capabilities = rlp::decode(&rlp::encode(&((1u64 << 63) | (1 << 1))));
+        assert!(capabilities.contains(Capabilities::SERVE_BODIES));
+        assert!(!capabilities.contains(Capabilities::SERVE_HEADERS));
+        assert!(!capabilities.contains(Capabilities::SERVE_STATE_CHUNKS));
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut capabilities = Capabilities::empty();
+        capabilities.insert(Capabilities::SERVE_BODIES);
+        assert!(capabilities.contains(Capabilities::SERVE_BODIES));
+        capabilities.remove(Capabilities::SERVE_BODIES);
+        assert_eq!(Capabilities::empty(), capabilities);
+    }
+}
@@ -0,0 +1,492 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use ccore::{BlockNumber, Header};
+use ctypes::H256;
+
+/// The progress of the header download, counted in batches.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeaderBatchStatus {
+    /// The batches which are not requested yet.
+    pub pending: usize,
+    /// The batches which are requested and not answered yet.
+    pub in_flight: usize,
+    /// The batches which are downloaded, but wait for the batches below them.
+    pub complete: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Batch<P> {
+    count: u64,
+    /// The peer which failed to answer this batch in time or answered a fork.
+    /// The batch is assigned to another peer if possible.
+    failed_peer: Option<P>,
+}
+
+struct InFlight<P> {
+    count: u64,
+    peer: P,
+    deadline: Instant,
+}
+
+/// Downloads the headers of (best, target] from several peers at once.
+///
+/// The range is split into batches of a fixed size, which are keyed by their first numbers.
+/// A peer is assigned at most one batch at a time, and a batch which isn't answered until its deadline
+/// goes back to the pending batches. Downloaded batches are handed over in order,
+/// once every batch below them is handed over.
+/// An empty answer means that the peer doesn't have the headers, and the download ends below them.
+pub struct HeaderBatchDownloader<P> {
+    batch_size: u64,
+    timeout: Duration,
+    /// The number of the next header to hand over.
+    next_number: BlockNumber,
+    /// The hash of the last header handed over, which is the parent of the next one.
+    last_hash: H256,
+    /// The number after the last batch.
+    end_number: BlockNumber,
+    pending: BTreeMap<BlockNumber, Batch<P>>,
+    in_flight: HashMap<BlockNumber, InFlight<P>>,
+    /// The downloaded batches with the peers which sent them.
+    complete: BTreeMap<BlockNumber, (P, Vec<Header>)>,
+}
+
+impl<P> HeaderBatchDownloader<P>
+where
+    P: Copy + Eq + Hash,
+{
+    pub fn new(batch_size: u64, timeout: Duration) -> Self {
+        assert_ne!(0, batch_size);
+        Self {
+            batch_size,
+            timeout,
+            next_number: 0,
+            last_hash: H256::zero(),
+            end_number: 0,
+            pending: BTreeMap::new(),
+            in_flight: HashMap::new(),
+            complete: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.in_flight.is_empty() && self.complete.is_empty()
+    }
+
+    pub fn status(&self) -> HeaderBatchStatus {
+        HeaderBatchStatus {
+            pending: self.pending.len(),
+            in_flight: self.in_flight.len(),
+            complete: self.complete.len(),
+        }
+    }
+
+    /// The number of the next header to hand over.
+    pub fn next_number(&self) -> BlockNumber {
+        self.next_number
+    }
+
+    /// Extends the download up to `target`.
+    /// When there is no batch left, the download goes on from the last header handed over,
+    /// or starts over from the given best block if it is not below that header.
+    pub fn extend_to(&mut self, best_number: BlockNumber, best_hash: H256, target: BlockNumber) {
+        if self.is_idle() && best_number >= self.next_number {
+            self.next_number = best_number.saturating_add(1);
+            self.last_hash = best_hash;
+            self.end_number = self.next_number;
+        }
+        while self.end_number <= target {
+            let count = (target - self.end_number).saturating_add(1).min(self.batch_size);
+            self.pending.insert(
+                self.end_number,
+                Batch {
+                    count,
+                    failed_peer: None,
+                },
+            );
+            self.end_number = match self.end_number.checked_add(count) {
+                Some(number) => number,
+                None => break,
+            };
+        }
+    }
+
+    /// Drops every batch, so that the next download starts over from the best block.
+    pub fn reset(&mut self) {
+        self.next_number = 0;
+        self.last_hash = H256::zero();
+        self.end_number = 0;
+        self.pending.clear();
+        self.in_flight.clear();
+        self.complete.clear();
+    }
+
+    /// Assigns the lowest pending batch to the peer, and returns its first number and its length.
+    /// Returns `None` if the peer is assigned a batch already or there is no batch for it.
+    pub fn assign(&mut self, peer: P, now: Instant) -> Option<(BlockNumber, u64)> {
+        if self.in_flight.values().any(|in_flight| in_flight.peer == peer) {
+            return None
+        }
+        let start_number = {
+            let mut batches = self.pending.iter();
+            // A batch is given back to the peer which failed it only if no other batch is left.
+            batches
+                .clone()
+                .find(|(_, batch)| batch.failed_peer != Some(peer))
+                .or_else(|| batches.next())
+                .map(|(start_number, _)| *start_number)?
+        };
+        let batch = self.pending.remove(&start_number).expect("The batch is found above");
+        self.in_flight.insert(
+            start_number,
+            InFlight {
+                count: batch.count,
+                peer,
+                deadline: now + self.timeout,
+            },
+        );
+        Some((start_number, batch.count))
+    }
+
+    /// Records the headers the peer answered for the batch from `start_number`.
+    /// A shorter answer completes a prefix of the batch and leaves the rest pending.
+    /// An empty answer drops the batches from `start_number`.
+    /// Returns false if the batch is not assigned to the peer or the headers don't make up its prefix.
+    pub fn complete(&mut self, peer: P, start_number: BlockNumber, headers: Vec<Header>) -> bool {
+        let count = match self.in_flight.get(&start_number) {
+            Some(in_flight) if in_flight.peer == peer => in_flight.count,
+            _ => return false,
+        };
+        if !is_chain_from(&headers, start_number) || headers.len() as u64 > count {
+            return false
+        }
+        self.in_flight.remove(&start_number);
+        if headers.is_empty() {
+            self.truncate(start_number);
+            return true
+        }
+        let answered = headers.len() as u64;
+        if answered < count {
+            self.pending.insert(
+                start_number + answered,
+                Batch {
+                    count: count - answered,
+                    failed_peer: None,
+                },
+            );
+        }
+        self.complete.insert(start_number, (peer, headers));
+        true
+    }
+
+    /// Ends the download below `end_number`.
+    fn truncate(&mut self, end_number: BlockNumber) {
+        self.pending.split_off(&end_number);
+        self.in_flight.retain(|start_number, _| *start_number < end_number);
+        self.complete.split_off(&end_number);
+        self.end_number = end_number;
+    }
+
+    /// Takes the downloaded batches which follow the headers handed over already, in ascending order,
+    /// with the peers which sent them.
+    /// A batch which doesn't attach to the headers below it is downloaded again, and its peer is returned as well.
+    pub fn drain(&mut self) -> (Vec<(P, Vec<Header>)>, Option<P>) {
+        let mut drained = Vec::new();
+        while let Some((peer, headers)) = self.complete.remove(&self.next_number) {
+            if *headers[0].parent_hash() != self.last_hash {
+                self.pending.insert(
+                    self.next_number,
+                    Batch {
+                        count: headers.len() as u64,
+                        failed_peer: Some(peer),
+                    },
+                );
+                return (drained, Some(peer))
+            }
+            self.next_number += headers.len() as u64;
+            self.last_hash = headers.last().expect("Completed batches are not empty").hash();
+            drained.push((peer, headers));
+        }
+        (drained, None)
+    }
+
+    /// Puts the batches which are not answered until `now` back to the pending ones,
+    /// and returns the peers which failed to answer.
+    pub fn expire(&mut self, now: Instant) -> Vec<P> {
+        let expired: Vec<_> = self
+            .in_flight
+            .iter()
+            .filter(|(_, in_flight)| now >= in_flight.deadline)
+            .map(|(start_number, _)| *start_number)
+            .collect();
+        let mut failed_peers = Vec::new();
+        for start_number in expired {
+            let in_flight = self.in_flight.remove(&start_number).expect("The batch is found above");
+            self.pending.insert(
+                start_number,
+                Batch {
+                    count: in_flight.count,
+                    failed_peer: Some(in_flight.peer),
+                },
+            );
+            failed_peers.push(in_flight.peer);
+        }
+        failed_peers
+    }
+
+    /// Puts the batch assigned to the peer back to the pending ones.
+    pub fn remove_peer(&mut self, peer: P) {
+        let assigned: Vec<_> = self
+            .in_flight
+            .iter()
+            .filter(|(_, in_flight)| in_flight.peer == peer)
+            .map(|(start_number, _)| *start_number)
+            .collect();
+        for start_number in assigned {
+            let in_flight = self.in_flight.remove(&start_number).expect("The batch is found above");
+            self.pending.insert(
+                start_number,
+                Batch {
+                    count: in_flight.count,
+                    failed_peer: None,
+                },
+            );
+        }
+    }
+}
+
+/// Returns true if the headers are a chain whose first number is `start_number`.
+fn is_chain_from(headers: &[Header], start_number: BlockNumber) -> bool {
+    match headers.first() {
+        Some(first) if first.number() != start_number => return false,
+        _ => {}
+    }
+    headers.windows(2).all(|neighbors| {
+        let (parent, child) = (&neighbors[0], &neighbors[1]);
+        child.number() == parent.number() + 1 && *child.parent_hash() == parent.hash()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BATCH_SIZE: u64 = 4;
+    const TIMEOUT: u64 = 10;
+
+    /// Builds the headers of the numbers from 1 to `last`, on top of a genesis whose hash is `genesis_hash`.
+    fn chain(genesis_hash: H256, last: BlockNumber) -> Vec<Header> {
+        let mut headers: Vec<Header> = Vec::new();
+        for number in 1..last + 1 {
+            let mut header = Header::default();
+            header.set_number(number);
+            header.set_parent_hash(headers.last().map_or(genesis_hash, |parent| parent.hash()));
+            headers.push(header);
+        }
+        headers
+    }
+
+    /// The headers of the batch from `start_number`.
+    fn batch(chain: &[Header], start_number: BlockNumber, count: u64) -> Vec<Header> {
+        chain[(start_number - 1) as usize..(start_number - 1 + count) as usize].to_vec()
+    }
+
+    fn numbers(batches: &[(i32, Vec<Header>)]) -> Vec<BlockNumber> {
+        batches.iter().flat_map(|(_, headers)| headers.iter().map(|header| header.number())).collect()
+    }
+
+    #[test]
+    fn batches_are_assigned_to_distinct_peers() {
+        let now = Instant::now();
+        let mut downloader = HeaderBatchDownloader::new(BATCH_SIZE, Duration::from_secs(TIMEOUT));
+        downloader.extend_to(0, H256::zero(), 10);
+        assert_eq!(
+            HeaderBatchStatus {
+                pending: 3,
+                in_flight: 0,
+                complete: 0,
+            },
+            downloader.status()
+        );
+
+        assert_eq!(Some((1, 4)), downloader.assign(1, now));
+        assert_eq!(None, downloader.assign(1, now));
+        assert_eq!(Some((5, 4)), downloader.assign(2, now));
+        assert_eq!(Some((9, 2)), downloader.assign(3, now));
+        assert_eq!(None, downloader.assign(4, now));
+
+        downloader.extend_to(0, H256::zero(), 12);
+        assert_eq!(Some((11, 2)), downloader.assign(4, now));
+    }
+
+    #[test]
+    fn batches_completed_out_of_order_are_drained_in_order() {
+        let now = Instant::now();
+        let chain = chain(H256::zero(), 12);
+        let mut downloader = HeaderBatchDownloader::new(BATCH_SIZE, Duration::from_secs(TIMEOUT));
+        downloader.extend_to(0, H256::zero(), 12);
+        for peer in 1..4 {
+            downloader.assign(peer, now).unwrap();
+        }
+
+        assert!(downloader.complete(3, 9, batch(&chain, 9, 4)));
+        assert!(downloader.complete(2, 5, batch(&chain, 5, 4)));
+        assert!(downloader.drain().0.is_empty());
+        assert_eq!(2, downloader.status().complete);
+
+        assert!(downloader.complete(1, 1, batch(&chain, 1, 4)));
+        let (drained, forked_peer) = downloader.drain();
+        assert_eq!((1..13).collect::<Vec<_>>(), numbers(&drained));
+        assert_eq!(vec![1, 2, 3], drained.iter().map(|(peer, _)| *peer).collect::<Vec<_>>());
+        assert_eq!(None, forked_peer);
+        assert!(downloader.is_idle());
+    }
+
+    #[test]
+    fn idle_download_goes_on_from_the_last_header_handed_over() {
+        let now = Instant::now();
+        let chain = chain(H256::zero(), 8);
+        let mut downloader = HeaderBatchDownloader::new(BATCH_SIZE, Duration::from_secs(TIMEOUT));
+        downloader.extend_to(0, H256::zero(), 4);
+        downloader.assign(1, now).unwrap();
+        assert!(downloader.complete(1, 1, batch(&chain, 1, 4)));
+        assert_eq!(vec![1, 2, 3, 4], numbers(&downloader.drain().0));
+        assert!(downloader.is_idle());
+
+        // The bodies are not imported yet, so that the best block stays.
+        downloader.extend_to(0, H256::zero(), 8);
+        assert_eq!(Some((5, 4)), downloader.assign(1, now));
+        assert!(downloader.complete(1, 5, batch(&chain, 5, 4)));
+        assert_eq!(vec![5, 6, 7, 8], numbers(&downloader.drain().0));
+
+        downloader.reset();
+        downloader.extend_to(0, H256::zero(), 8);
+        assert_eq!(Some((1, 4)), downloader.assign(1, now), "The reset download starts over from the best block");
+    }
+
+    #[test]
+    fn batches_near_the_largest_number_do_not_overflow() {
+        let now = Instant::now();
+        let mut downloader = HeaderBatchDownloader::new(BATCH_SIZE, Duration::from_secs(TIMEOUT));
+        downloader.extend_to(BlockNumber::max_value() - 2, H256::zero(), BlockNumber::max_value());
+        assert_eq!(Some((BlockNumber::max_value() - 1, 2)), downloader.assign(1, now));
+        assert_eq!(None, downloader.assign(2, now));
+    }
+
+    #[test]
+    fn timed_out_batch_is_reassigned_to_another_peer() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(TIMEOUT);
+        let chain = chain(H256::zero(), 12);
+        let mut downloader = HeaderBatchDownloader::new(BATCH_SIZE, Duration::from_secs(TIMEOUT));
+        downloader.extend_to(0, H256::zero(), 12);
+        assert_eq!(Some((1, 4)), downloader.assign(1, now));
+        assert_eq!(Some((5, 4)), downloader.assign(2, now));
+        assert!(downloader.complete(2, 5, batch(&chain, 5, 4)));
+
+        assert!(downloader.expire(now).is_empty());
+        assert_eq!(vec![1], downloader.expire(later));
+        assert_eq!(2, downloader.status().pending);
+
+        assert_eq!(Some((9, 4)), downloader.assign(1, later), "The failed batch is left to another peer");
+        assert_eq!(Some((1, 4)), downloader.assign(3, later));
+        assert!(!downloader.complete(1, 1, batch(&chain, 1, 4)), "The late answer is not accepted");
+        assert!(downloader.complete(3, 1, batch(&chain, 1, 4)));
+        assert!(downloader.complete(1, 9, batch(&chain, 9, 4)));
+        assert_eq!((1..13).collect::<Vec<_>>(), numbers(&downloader.drain().0));
+    }
+
+    #[test]
+    fn failed_batch_goes_back_to_the_failed_peer_if_it_is_the_only_one() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(TIMEOUT);
+        let mut downloader = HeaderBatchDownloader::<u32>::new(BATCH_SIZE, Duration::from_secs(TIMEOUT));
+        downloader.extend_to(0, H256::zero(), 4);
+        assert_eq!(Some((1, 4)), downloader.assign(1, now));
+        assert_eq!(vec![1], downloader.expire(later));
+        assert_eq!(Some((1, 4)), downloader.assign(1, later));
+    }
+
+    #[test]
+    fn short_answer_leaves_the_rest_of_the_batch_pending() {
+        let now = Instant::now();
+        let chain = chain(H256::zero(), 4);
+        let mut downloader = HeaderBatchDownloader::new(BATCH_SIZE, Duration::from_secs(TIMEOUT));
+        downloader.extend_to(0, H256::zero(), 4);
+        downloader.assign(1, now).unwrap();
+
+        assert!(downloader.complete(1, 1, batch(&chain, 1, 3)));
+        assert_eq!(vec![1, 2, 3], numbers(&downloader.drain().0));
+        assert_eq!(Some((4, 1)), downloader.assign(1, now));
+        assert!(downloader.complete(1, 4, batch(&chain, 4, 1)));
+        assert_eq!(vec![4], numbers(&downloader.drain().0));
+        assert!(downloader.is_idle());
+    }
+
+    #[test]
+    fn empty_answer_ends_the_download_below_the_batch() {
+        let now = Instant::now();
+        let chain = chain(H256::zero(), 4);
+        let mut downloader = HeaderBatchDownloader::new(BATCH_SIZE, Duration::from_secs(TIMEOUT));
+        downloader.extend_to(0, H256::zero(), 16);
+        for peer in 1..4 {
+            downloader.assign(peer, now).unwrap();
+        }
+
+        assert!(downloader.complete(2, 5, Vec::new()));
+        assert_eq!(
+            HeaderBatchStatus {
+                pending: 0,
+                in_flight: 1,
+                complete: 0,
+            },
+            downloader.status()
+        );
+        assert!(!downloader.complete(3, 9, Vec::new()), "The batches above are dropped");
+        assert_eq!(None, downloader.assign(2, now));
+
+        assert!(downloader.complete(1, 1, batch(&chain, 1, 4)));
+        assert_eq!(vec![1, 2, 3, 4], numbers(&downloader.drain().0));
+        assert!(downloader.is_idle());
+    }
+
+    #[test]
+    fn batch_not_attached_to_the_headers_below_is_downloaded_again() {
+        let now = Instant::now();
+        let chain = chain(H256::zero(), 8);
+        let fork = self::chain(H256::from(1), 8);
+        let mut downloader = HeaderBatchDownloader::new(BATCH_SIZE, Duration::from_secs(TIMEOUT));
+        downloader.extend_to(0, H256::zero(), 8);
+        downloader.assign(1, now).unwrap();
+        downloader.assign(2, now).unwrap();
+
+        assert!(!downloader.complete(2, 5, batch(&chain, 4, 4)), "The batch must start at its first number");
+        assert!(downloader.complete(2, 5, batch(&fork, 5, 4)));
+        assert!(downloader.complete(1, 1, batch(&chain, 1, 4)));
+        let (drained, forked_peer) = downloader.drain();
+        assert_eq!(vec![1, 2, 3, 4], numbers(&drained));
+        assert_eq!(Some(2), forked_peer);
+        downloader.extend_to(0, H256::zero(), 12);
+        assert_eq!(Some((9, 4)), downloader.assign(2, now), "The forked batch is left to another peer");
+        assert_eq!(Some((5, 4)), downloader.assign(3, now));
+        assert!(downloader.complete(3, 5, batch(&chain, 5, 4)));
+        assert_eq!(vec![5, 6, 7, 8], numbers(&downloader.drain().0));
+    }
+}
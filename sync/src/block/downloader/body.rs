@@ -21,6 +21,8 @@ use ctypes::H256;
 
 use super::super::message::RequestMessage;
 
+const MAX_BODY_REQUEST_LENGTH: usize = 128;
+
 pub struct BodyDownloader {
     targets: Vec<(H256, H256, H256)>,
     downloading: HashSet<H256>,
@@ -39,6 +41,9 @@ impl BodyDownloader {
     pub fn create_request(&mut self) -> Option<RequestMessage> {
         let mut hashes = Vec::new();
         for (hash, ..) in &self.targets {
+            if hashes.len() == MAX_BODY_REQUEST_LENGTH {
+                break
+            }
             if !self.downloading.contains(hash) && !self.downloaded.contains_key(hash) {
                 hashes.push(*hash);
             }
@@ -51,6 +56,26 @@ impl BodyDownloader {
         }
     }
 
+    /// Requests the bodies of the given targets only, skipping the ones which are being downloaded.
+    pub fn create_request_for(&mut self, hashes: &[H256]) -> Option<RequestMessage> {
+        let hashes: Vec<_> = hashes
+            .iter()
+            .filter(|hash| {
+                self.targets.iter().any(|(h, ..)| h == *hash)
+                    && !self.downloading.contains(*hash)
+                    && !self.downloaded.contains_key(*hash)
+            })
+            .take(MAX_BODY_REQUEST_LENGTH)
+            .cloned()
+            .collect();
+        if hashes.len() != 0 {
+            self.downloading.extend(&hashes);
+            Some(RequestMessage::Bodies(hashes))
+        } else {
+            None
+        }
+    }
+
     pub fn import_bodies(&mut self, hashes: Vec<H256>, bodies: Vec<Vec<UnverifiedParcel>>) {
         for (hash, body) in hashes.into_iter().zip(bodies) {
             if self.downloading.contains(&hash) {
@@ -67,6 +92,13 @@ impl BodyDownloader {
         }
     }
 
+    /// Marks the given hashes as not being downloaded, so that they can be requested again.
+    pub fn reset_downloading(&mut self, hashes: Vec<H256>) {
+        for hash in hashes {
+            self.downloading.remove(&hash);
+        }
+    }
+
     pub fn add_target(&mut self, targets: Vec<(H256, H256, H256)>) {
         self.targets.extend(targets);
     }
@@ -81,6 +113,18 @@ impl BodyDownloader {
         }
     }
 
+    /// Removes the targets before the given one, whose blocks are not imported anymore.
+    pub fn remove_targets_before(&mut self, hash: &H256) {
+        let index = match self.targets.iter().position(|(h, ..)| h == hash) {
+            Some(index) => index,
+            None => return,
+        };
+        for (hash, ..) in self.targets.drain(..index) {
+            self.downloading.remove(&hash);
+            self.downloaded.remove(&hash);
+        }
+    }
+
     pub fn drain(&mut self) -> Vec<(H256, Vec<UnverifiedParcel>)> {
         let mut result = Vec::new();
         for (target, ..) in &self.targets {
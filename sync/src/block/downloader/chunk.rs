@@ -0,0 +1,441 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ccore::BlockNumber;
+use ccrypto::blake256;
+use ctypes::H256;
+use kvdb::KeyValueDB;
+
+use super::super::super::snapshot::{Manifest, SnapshotError, SnapshotRestorer};
+use super::super::message::RequestMessage;
+
+/// Restores the state from a snapshot of the peers, so that the blocks below the snapshot are not downloaded.
+///
+/// The manifest of each peer is requested once. The restoration starts from the newest manifest
+/// which is accepted, and its chunks are requested only to the peers which have the same manifest.
+/// A peer is assigned one chunk at a time, and a chunk which isn't restored goes back to the pending ones.
+/// Nothing is restored once a block is imported, since the restored block must be the first one after the genesis.
+pub struct ChunkDownloader<P> {
+    db: Arc<KeyValueDB>,
+    progress_path: PathBuf,
+    /// A manifest which isn't accepted in this time is dropped.
+    manifest_timeout: Duration,
+    /// The manifests of the peers with the time they are received.
+    /// `None` if the peer has no snapshot, hasn't answered yet, or its manifest is dropped.
+    manifests: HashMap<P, Option<(Manifest, Instant)>>,
+    /// The peers whose manifests are requested and not answered yet.
+    unanswered: HashSet<P>,
+    restorer: Option<SnapshotRestorer>,
+    /// The chunks which are requested and not answered yet, with the peers which are requested.
+    downloading: HashMap<H256, P>,
+    /// Set when the restoration can't be started or finished anymore.
+    is_abandoned: bool,
+}
+
+impl<P> ChunkDownloader<P>
+where
+    P: Copy + Eq + Hash,
+{
+    /// Resumes the restoration recorded in `progress_path`, if there is one.
+    pub fn new(db: Arc<KeyValueDB>, progress_path: PathBuf, manifest_timeout: Duration) -> Self {
+        let restorer = SnapshotRestorer::resume(Arc::clone(&db), progress_path.clone());
+        Self {
+            db,
+            progress_path,
+            manifest_timeout,
+            manifests: HashMap::new(),
+            unanswered: HashSet::new(),
+            restorer,
+            downloading: HashMap::new(),
+            is_abandoned: false,
+        }
+    }
+
+    /// The manifest of the snapshot being restored.
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.restorer.as_ref().map(|restorer| restorer.manifest())
+    }
+
+    /// Whether some chunks of the snapshot being restored are not restored yet.
+    pub fn is_downloading(&self) -> bool {
+        self.restorer.as_ref().map_or(false, |restorer| !restorer.is_complete())
+    }
+
+    /// Whether a manifest is not answered yet, or a manifest received from a peer may be accepted later.
+    pub fn is_waiting(&self) -> bool {
+        !self.is_abandoned
+            && self.restorer.is_none()
+            && (!self.unanswered.is_empty() || self.manifests.values().any(Option::is_some))
+    }
+
+    /// Returns a request for the manifest of the peer, unless it is requested already.
+    pub fn create_manifest_request(&mut self, peer: P) -> Option<RequestMessage> {
+        if self.is_abandoned || self.restorer.is_some() || self.manifests.contains_key(&peer) {
+            return None
+        }
+        self.manifests.insert(peer, None);
+        self.unanswered.insert(peer);
+        Some(RequestMessage::SnapshotManifest)
+    }
+
+    /// Records the answer of the peer. `None` is also given when the request expires.
+    pub fn import_manifest(&mut self, peer: P, manifest: Option<Manifest>, now: Instant) {
+        self.unanswered.remove(&peer);
+        if let Some(entry) = self.manifests.get_mut(&peer) {
+            *entry = manifest.map(|manifest| (manifest, now));
+        }
+    }
+
+    /// Forgets the manifest of the peer. The chunks requested to it are requested again.
+    pub fn remove_peer(&mut self, peer: &P) {
+        self.manifests.remove(peer);
+        self.unanswered.remove(peer);
+        let requested: Vec<_> =
+            self.downloading.iter().filter(|(_, p)| *p == peer).map(|(chunk_hash, _)| *chunk_hash).collect();
+        for chunk_hash in requested {
+            self.downloading.remove(&chunk_hash);
+        }
+    }
+
+    /// Starts restoring the newest snapshot which `is_accepted`.
+    ///
+    /// `is_accepted` returns `None` while it is not known yet whether the snapshot is accepted.
+    /// The rejected manifests and the ones waiting longer than the timeout are dropped,
+    /// and nothing is started if a block after the genesis is imported already.
+    /// Returns the manifest whose restoration is started.
+    pub fn start<F>(
+        &mut self,
+        best_number: BlockNumber,
+        now: Instant,
+        is_accepted: F,
+    ) -> Result<Option<Manifest>, SnapshotError>
+    where
+        F: Fn(&Manifest) -> Option<bool>, {
+        if self.is_abandoned || self.restorer.is_some() {
+            return Ok(None)
+        }
+        if best_number != 0 {
+            self.abandon();
+            return Ok(None)
+        }
+
+        let manifest_timeout = self.manifest_timeout;
+        let mut newest: Option<Manifest> = None;
+        for entry in self.manifests.values_mut() {
+            let accepted = match *entry {
+                Some((ref manifest, received_at)) => match is_accepted(manifest) {
+                    None if now < received_at + manifest_timeout => continue,
+                    result => result == Some(true),
+                },
+                None => continue,
+            };
+            if !accepted {
+                *entry = None;
+                continue
+            }
+            let manifest = &entry.as_ref().expect("Checked above").0;
+            if newest.as_ref().map_or(true, |newest| newest.block_number < manifest.block_number) {
+                newest = Some(manifest.clone());
+            }
+        }
+        let manifest = match newest {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+        let restorer = SnapshotRestorer::new(Arc::clone(&self.db), self.progress_path.clone(), manifest.clone())?;
+        self.restorer = Some(restorer);
+        Ok(Some(manifest))
+    }
+
+    /// Stops waiting for the manifests. A restoration in progress is kept in its progress file.
+    pub fn abandon(&mut self) {
+        self.is_abandoned = true;
+        self.restorer = None;
+        self.downloading.clear();
+    }
+
+    /// Returns a request for a pending chunk, if the peer has the snapshot being restored
+    /// and isn't requested any other chunk.
+    pub fn create_request(&mut self, peer: P) -> Option<RequestMessage> {
+        let (block_hash, pending) = {
+            let restorer = self.restorer.as_ref()?;
+            (restorer.manifest().block_hash, restorer.pending_chunks())
+        };
+        let has_snapshot = match self.manifests.get(&peer) {
+            Some(Some((manifest, _))) => manifest.block_hash == block_hash,
+            _ => false,
+        };
+        if !has_snapshot || self.downloading.values().any(|p| *p == peer) {
+            return None
+        }
+        let chunk_hash = pending.into_iter().find(|chunk_hash| !self.downloading.contains_key(chunk_hash))?;
+        self.downloading.insert(chunk_hash, peer);
+        Some(RequestMessage::StateChunk {
+            block_hash,
+            chunk_hash,
+        })
+    }
+
+    /// Restores the chunk requested as `chunk_hash`. A chunk which isn't restored goes back to the pending ones.
+    pub fn import_chunk(&mut self, chunk_hash: &H256, chunk: &[u8]) -> Result<(), SnapshotError> {
+        self.downloading.remove(chunk_hash);
+        let hash = blake256(chunk);
+        if hash != *chunk_hash {
+            return Err(SnapshotError::UnexpectedChunk(hash))
+        }
+        match self.restorer.as_mut() {
+            Some(restorer) => restorer.feed(chunk).map(|_| ()),
+            None => Err(SnapshotError::UnexpectedChunk(hash)),
+        }
+    }
+
+    /// Marks the chunk as not being downloaded, so that it can be requested again.
+    pub fn reset_downloading(&mut self, chunk_hash: &H256) {
+        self.downloading.remove(chunk_hash);
+    }
+
+    /// Takes the restoration out once every chunk is restored, so that its block can be imported.
+    pub fn take_complete(&mut self) -> Option<SnapshotRestorer> {
+        if self.restorer.as_ref().map_or(false, |restorer| restorer.is_complete()) {
+            self.is_abandoned = true;
+            self.restorer.take()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::Path;
+
+    use ccore::{Account, COL_STATE, NUM_COLUMNS};
+    use journaldb::{self, Algorithm};
+    use kvdb_memorydb;
+    use rlp;
+    use tempdir::TempDir;
+    use trie::{TrieDBMut, TrieMut};
+
+    use super::super::super::super::snapshot::write_snapshot;
+    use super::*;
+
+    const TIMEOUT: u64 = 10;
+
+    fn new_db() -> Arc<KeyValueDB> {
+        Arc::new(kvdb_memorydb::create(NUM_COLUMNS.unwrap_or(0)))
+    }
+
+    /// Writes the snapshot of a state with a thousand accounts, taken at the block `number`.
+    fn snapshot(dir: &Path, number: BlockNumber) -> Manifest {
+        let db = new_db();
+        let mut journal_db = journaldb::new(Arc::clone(&db), Algorithm::Archive, COL_STATE);
+        let mut root = H256::zero();
+        {
+            let mut trie = TrieDBMut::new(journal_db.as_hashdb_mut(), &mut root);
+            for i in 0..1000u64 {
+                let key = blake256(&rlp::encode(&(i + number)));
+                trie.insert(&key, &rlp::encode(&Account::new(i.into(), 0.into()))).unwrap();
+            }
+        }
+        let mut batch = db.transaction();
+        journal_db.inject(&mut batch).unwrap();
+        db.write(batch).unwrap();
+        write_snapshot(db, dir, H256::from(number), number, root).unwrap()
+    }
+
+    fn read_chunk(dir: &Path, manifest: &Manifest, chunk_hash: &H256) -> Vec<u8> {
+        let path = dir.join(format!("{:x}", manifest.block_hash)).join(format!("{:x}", chunk_hash));
+        let mut bytes = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn downloader(dir: &Path) -> ChunkDownloader<i32> {
+        ChunkDownloader::new(new_db(), dir.join("restoration"), Duration::from_secs(TIMEOUT))
+    }
+
+    fn requested_chunk(request: Option<RequestMessage>) -> H256 {
+        match request {
+            Some(RequestMessage::StateChunk {
+                chunk_hash,
+                ..
+            }) => chunk_hash,
+            request => panic!("Unexpected request: {:?}", request),
+        }
+    }
+
+    #[test]
+    fn manifest_is_requested_once_from_each_peer() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let mut downloader = downloader(dir.path());
+        assert!(!downloader.is_waiting());
+        assert_eq!(Some(RequestMessage::SnapshotManifest), downloader.create_manifest_request(1));
+        assert_eq!(None, downloader.create_manifest_request(1));
+        assert!(downloader.is_waiting(), "The answer may have a snapshot");
+        downloader.import_manifest(1, None, Instant::now());
+        assert!(!downloader.is_waiting());
+        assert_eq!(None, downloader.create_manifest_request(1));
+        assert_eq!(Some(RequestMessage::SnapshotManifest), downloader.create_manifest_request(2));
+
+        downloader.remove_peer(&1);
+        downloader.remove_peer(&2);
+        assert!(!downloader.is_waiting());
+        assert_eq!(Some(RequestMessage::SnapshotManifest), downloader.create_manifest_request(1));
+        downloader.abandon();
+        assert!(!downloader.is_waiting());
+        assert_eq!(None, downloader.create_manifest_request(3));
+    }
+
+    #[test]
+    fn newest_accepted_snapshot_is_restored() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let old = snapshot(dir.path(), 1);
+        let new = snapshot(dir.path(), 2);
+        let forged = snapshot(dir.path(), 3);
+        let now = Instant::now();
+        let mut downloader = downloader(dir.path());
+        for peer in 1..4 {
+            downloader.create_manifest_request(peer);
+        }
+        downloader.import_manifest(1, Some(old.clone()), now);
+        downloader.import_manifest(2, Some(new.clone()), now);
+        downloader.import_manifest(3, Some(forged.clone()), now);
+        assert!(downloader.is_waiting());
+
+        let is_accepted = |manifest: &Manifest| match manifest.block_number {
+            3 => Some(false),
+            _ => Some(true),
+        };
+        assert_eq!(Some(new.clone()), downloader.start(0, now, is_accepted).unwrap());
+        assert_eq!(Some(&new), downloader.manifest());
+        assert!(downloader.is_downloading());
+        assert!(!downloader.is_waiting());
+        assert_eq!(None, downloader.start(0, now, is_accepted).unwrap());
+    }
+
+    #[test]
+    fn nothing_is_restored_after_a_block_is_imported() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let manifest = snapshot(dir.path(), 1);
+        let mut downloader = downloader(dir.path());
+        downloader.create_manifest_request(1);
+        downloader.import_manifest(1, Some(manifest), Instant::now());
+
+        assert_eq!(None, downloader.start(1, Instant::now(), |_| Some(true)).unwrap());
+        assert!(!downloader.is_waiting());
+        assert_eq!(None, downloader.start(0, Instant::now(), |_| Some(true)).unwrap());
+    }
+
+    #[test]
+    fn manifest_which_is_not_accepted_in_time_is_dropped() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let manifest = snapshot(dir.path(), 1);
+        let now = Instant::now();
+        let mut downloader = downloader(dir.path());
+        downloader.create_manifest_request(1);
+        downloader.import_manifest(1, Some(manifest), now);
+
+        assert_eq!(None, downloader.start(0, now + Duration::from_secs(TIMEOUT - 1), |_| None).unwrap());
+        assert!(downloader.is_waiting());
+        assert_eq!(None, downloader.start(0, now + Duration::from_secs(TIMEOUT), |_| None).unwrap());
+        assert!(!downloader.is_waiting());
+        assert_eq!(None, downloader.create_manifest_request(1));
+    }
+
+    #[test]
+    fn chunks_are_requested_one_at_a_time_from_the_peers_with_the_snapshot() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let manifest = snapshot(dir.path(), 1);
+        let other = snapshot(dir.path(), 2);
+        let now = Instant::now();
+        let mut downloader = downloader(dir.path());
+        for peer in 1..4 {
+            downloader.create_manifest_request(peer);
+        }
+        downloader.import_manifest(1, Some(manifest.clone()), now);
+        downloader.import_manifest(2, Some(manifest.clone()), now);
+        downloader.import_manifest(3, Some(other), now);
+        downloader.start(0, now, |candidate| Some(candidate.block_number == 1)).unwrap();
+
+        let first = requested_chunk(downloader.create_request(1));
+        assert_eq!(None, downloader.create_request(1));
+        let second = requested_chunk(downloader.create_request(2));
+        assert_ne!(first, second);
+        assert_eq!(None, downloader.create_request(3));
+
+        downloader.remove_peer(&2);
+        assert_eq!(None, downloader.create_request(1));
+        downloader.import_chunk(&first, &read_chunk(dir.path(), &manifest, &first)).unwrap();
+        assert_eq!(second, requested_chunk(downloader.create_request(1)));
+
+        match downloader.import_chunk(&second, &read_chunk(dir.path(), &manifest, &manifest.chunk_hashes[2])) {
+            Err(SnapshotError::UnexpectedChunk(hash)) => assert_eq!(manifest.chunk_hashes[2], hash),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(second, requested_chunk(downloader.create_request(1)));
+    }
+
+    #[test]
+    fn complete_restoration_is_taken_out() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let manifest = snapshot(dir.path(), 1);
+        let mut downloader = downloader(dir.path());
+        downloader.create_manifest_request(1);
+        downloader.import_manifest(1, Some(manifest.clone()), Instant::now());
+        downloader.start(0, Instant::now(), |_| Some(true)).unwrap();
+
+        for _ in 0..manifest.chunk_hashes.len() {
+            assert!(downloader.take_complete().is_none());
+            let chunk_hash = requested_chunk(downloader.create_request(1));
+            downloader.import_chunk(&chunk_hash, &read_chunk(dir.path(), &manifest, &chunk_hash)).unwrap();
+        }
+        assert!(!downloader.is_downloading());
+        assert_eq!(None, downloader.create_request(1));
+        assert_eq!(&manifest, downloader.take_complete().unwrap().manifest());
+        assert_eq!(None, downloader.manifest());
+        assert_eq!(None, downloader.create_manifest_request(2));
+    }
+
+    #[test]
+    fn restoration_is_resumed_from_the_progress_file() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let manifest = snapshot(dir.path(), 1);
+        let db = new_db();
+        let progress_path = dir.path().join("restoration");
+        {
+            let timeout = Duration::from_secs(TIMEOUT);
+            let mut downloader = ChunkDownloader::new(Arc::clone(&db), progress_path.clone(), timeout);
+            downloader.create_manifest_request(1);
+            downloader.import_manifest(1, Some(manifest.clone()), Instant::now());
+            downloader.start(0, Instant::now(), |_| Some(true)).unwrap();
+            let chunk_hash = requested_chunk(downloader.create_request(1));
+            downloader.import_chunk(&chunk_hash, &read_chunk(dir.path(), &manifest, &chunk_hash)).unwrap();
+        }
+
+        let mut downloader = ChunkDownloader::new(db, progress_path, Duration::from_secs(TIMEOUT));
+        assert_eq!(Some(&manifest), downloader.manifest());
+        assert!(downloader.is_downloading());
+        assert_eq!(None, downloader.create_manifest_request(1));
+    }
+}
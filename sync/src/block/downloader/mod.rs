@@ -14,8 +14,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod batch;
 mod body;
+mod chunk;
 mod header;
 
+pub use self::batch::{HeaderBatchDownloader, HeaderBatchStatus};
 pub use self::body::BodyDownloader;
+pub use self::chunk::ChunkDownloader;
 pub use self::header::HeaderDownloader;
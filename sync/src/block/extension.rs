@@ -15,51 +15,233 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use parking_lot::{Mutex, RwLock};
-use std::collections::HashMap;
+use rand::{thread_rng, Rng};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration as StdDuration, Instant};
 
 use ccore::encoded::Header as EncodedHeader;
 use ccore::{
-    Block, BlockChainClient, BlockId, BlockImportError, BlockNumber, ChainNotify, Header, ImportError, Seal,
-    UnverifiedParcel,
+    Block, BlockChainClient, BlockId, BlockImportError, BlockNumber, BlockStatus, ChainNotify, Header, ImportError,
+    Seal, UnverifiedParcel,
 };
 use cnetwork::{Api, NetworkExtension, NodeId, TimerToken};
-use ctypes::{H256, U256};
-use rlp::{Encodable, UntrustedRlp};
+use ctypes::{Bytes, H256, U256};
+use kvdb::KeyValueDB;
+use rlp::{self, Encodable, UntrustedRlp};
 use time::Duration;
 
-use super::downloader::{BodyDownloader, HeaderDownloader};
-use super::message::{Message, RequestMessage, ResponseMessage};
+use super::super::snapshot::{LatestManifest, Manifest, SnapshotError};
+use super::capabilities::Capabilities;
+use super::downloader::{BodyDownloader, ChunkDownloader, HeaderBatchDownloader, HeaderDownloader};
+use super::message::{HeaderStart, Message, RequestMessage, ResponseMessage};
+use super::orphan_pool::OrphanPool;
+use super::status::SyncStatus;
 
 const EXTENSION_NAME: &'static str = "block-propagation";
 const SYNC_TIMER_TOKEN: usize = 0;
 const SYNC_TIMER_INTERVAL: i64 = 1000;
 
-const SNAPSHOT_PERIOD: u64 = (1 << 14);
+/// Maximum serialized size of the bodies in a single `Bodies` response.
+/// A larger response is split into several messages with the same request id.
+const MAX_BODIES_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// The protocol version from which new blocks are announced.
+const NEW_BLOCK_VERSION: u64 = 2;
+/// The protocol version from which the hashes of new blocks are announced.
+const NEW_BLOCK_HASHES_VERSION: u64 = 3;
+/// The protocol version from which the headers can be requested by hash, with skip and reverse.
+const HEADERS_FROM_VERSION: u64 = 4;
+/// The newest protocol version.
+const PROTOCOL_VERSION: u64 = HEADERS_FROM_VERSION;
+/// Peers older than this are disconnected after their status.
+/// The version 0, whose status carries no version, is kept for one more release.
+const MIN_PROTOCOL_VERSION: u64 = 0;
+/// Maximum number of headers in a single `Headers` response.
+const MAX_HEADERS_RESPONSE_COUNT: usize = 512;
+/// Maximum serialized size of the headers in a single `Headers` response.
+const MAX_HEADERS_RESPONSE_SIZE: usize = 1024 * 1024;
+/// Maximum number of bodies served for a single `Bodies` request. The rest of the hashes are ignored.
+const MAX_BODIES_RESPONSE_COUNT: usize = 512;
+/// Maximum number of announced blocks held while their parents are unknown.
+const MAX_ORPHANS: usize = 64;
+/// Maximum time an announced block is held while its parent is unknown.
+const MAX_ORPHAN_AGE_SECS: u64 = 120;
+/// Orphans further ahead of the best block than this are downloaded by a normal sync round.
+const MAX_ORPHAN_DEPTH: u64 = 8;
+/// Maximum time a request waits for its response. The hashes of an expired body request are requested again.
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+/// A peer which sent this many responses to no outstanding request is not requested anymore.
+const MAX_UNEXPECTED_RESPONSES: usize = 16;
+/// Every this time, each peer is forgiven one of its unexpected responses.
+const UNEXPECTED_RESPONSE_DECAY_SECS: u64 = 60;
+/// The number of headers in a batch, which are downloaded from several peers at once while the node is far behind.
+const HEADER_BATCH_SIZE: u64 = 128;
+/// A batch not answered in this time is assigned to another peer.
+const HEADER_BATCH_TIMEOUT_SECS: u64 = 15;
+/// The batches extend at most this many blocks past the best block, so that the headers don't run far ahead of
+/// the bodies.
+const MAX_HEADER_BATCH_LOOKAHEAD: u64 = 16 * HEADER_BATCH_SIZE;
+/// A snapshot manifest whose block doesn't come into the header chain in this time is dropped.
+const SNAPSHOT_MANIFEST_TIMEOUT_SECS: u64 = 10 * 60;
 
 pub struct Extension {
-    requests: RwLock<HashMap<NodeId, Vec<(u64, RequestMessage)>>>,
+    /// The outstanding requests of each peer with their ids and the time they are sent.
+    requests: RwLock<HashMap<NodeId, Vec<(u64, RequestMessage, Instant)>>>,
+    /// The number of responses with unknown or already answered ids from each peer.
+    unexpected_responses: RwLock<HashMap<NodeId, usize>>,
+    /// When the unexpected responses were forgiven last.
+    last_decay: Mutex<Instant>,
     header_downloaders: RwLock<HashMap<NodeId, HeaderDownloader>>,
     body_downloader: Mutex<BodyDownloader>,
     client: Arc<BlockChainClient>,
     api: Mutex<Option<Arc<Api>>>,
     last_request: AtomicUsize,
+    capabilities: Capabilities,
+    min_version: u64,
+    /// The lower of our and the peer's protocol versions.
+    peer_versions: RwLock<HashMap<NodeId, u64>>,
+    peer_capabilities: RwLock<HashMap<NodeId, Capabilities>>,
+    orphans: Mutex<OrphanPool>,
+    /// Ids of the header requests sent to fill the gap below orphans.
+    gap_requests: Mutex<HashSet<u64>>,
+    header_batches: Mutex<HeaderBatchDownloader<NodeId>>,
+    /// Ids of the header requests sent for the batches.
+    batch_requests: Mutex<HashSet<u64>>,
+    status: Arc<SyncStatus>,
+    /// The newest snapshot manifest, which is served
+    snapshot_manifest: Option<Arc<LatestManifest>>,
+    /// Restores the state from a snapshot of the peers, if it is enabled
+    chunk_downloader: Option<Mutex<ChunkDownloader<NodeId>>>,
 }
 
 impl Extension {
     pub fn new(client: Arc<BlockChainClient>) -> Arc<Self> {
+        Self::with_capabilities(client, Capabilities::default())
+    }
+
+    pub fn with_capabilities(client: Arc<BlockChainClient>, capabilities: Capabilities) -> Arc<Self> {
+        Self::create(client, capabilities, None, None, MIN_PROTOCOL_VERSION)
+    }
+
+    /// Serves the manifest of the newest snapshot to the `SnapshotManifest` requests.
+    pub fn with_snapshot_manifest(
+        client: Arc<BlockChainClient>,
+        capabilities: Capabilities,
+        snapshot_manifest: Arc<LatestManifest>,
+    ) -> Arc<Self> {
+        Self::create(client, capabilities, Some(snapshot_manifest), None, MIN_PROTOCOL_VERSION)
+    }
+
+    /// Restores the state from a snapshot of the peers into the state column of `state_db`,
+    /// if no block after the genesis is imported yet. The bodies of the blocks below the snapshot
+    /// are not downloaded. The progress is written to `progress_path`, from which it is resumed.
+    pub fn with_snapshot_restoration(
+        client: Arc<BlockChainClient>,
+        capabilities: Capabilities,
+        snapshot_manifest: Option<Arc<LatestManifest>>,
+        state_db: Arc<KeyValueDB>,
+        progress_path: PathBuf,
+    ) -> Arc<Self> {
+        let mut chunk_downloader =
+            ChunkDownloader::new(state_db, progress_path, StdDuration::from_secs(SNAPSHOT_MANIFEST_TIMEOUT_SECS));
+        if client.chain_info().best_block_number != 0 {
+            chunk_downloader.abandon();
+        } else if let Some(manifest) = chunk_downloader.manifest() {
+            cinfo!(SYNC, "Resume restoring the state of #{}", manifest.block_number);
+        }
+        Self::create(client, capabilities, snapshot_manifest, Some(chunk_downloader), MIN_PROTOCOL_VERSION)
+    }
+
+    fn create(
+        client: Arc<BlockChainClient>,
+        capabilities: Capabilities,
+        snapshot_manifest: Option<Arc<LatestManifest>>,
+        chunk_downloader: Option<ChunkDownloader<NodeId>>,
+        min_version: u64,
+    ) -> Arc<Self> {
+        let status = Arc::new(SyncStatus::new(client.chain_info().best_block_number));
         Arc::new(Self {
             requests: RwLock::new(HashMap::new()),
+            unexpected_responses: RwLock::new(HashMap::new()),
+            last_decay: Mutex::new(Instant::now()),
             header_downloaders: RwLock::new(HashMap::new()),
             body_downloader: Mutex::new(BodyDownloader::new(Vec::new())),
             client,
             api: Mutex::new(None),
             last_request: AtomicUsize::new(0),
+            capabilities,
+            min_version,
+            peer_versions: RwLock::new(HashMap::new()),
+            peer_capabilities: RwLock::new(HashMap::new()),
+            orphans: Mutex::new(OrphanPool::new(MAX_ORPHANS, StdDuration::from_secs(MAX_ORPHAN_AGE_SECS))),
+            gap_requests: Mutex::new(HashSet::new()),
+            header_batches: Mutex::new(HeaderBatchDownloader::new(
+                HEADER_BATCH_SIZE,
+                StdDuration::from_secs(HEADER_BATCH_TIMEOUT_SECS),
+            )),
+            batch_requests: Mutex::new(HashSet::new()),
+            status,
+            snapshot_manifest,
+            chunk_downloader: chunk_downloader.map(Mutex::new),
         })
     }
 
+    /// Returns the sync progress, which is updated as peers and blocks come and go.
+    pub fn status(&self) -> Arc<SyncStatus> {
+        Arc::clone(&self.status)
+    }
+
+    /// A peer sending too many unexpected responses is treated as serving nothing.
+    fn peer_has(&self, token: &NodeId, capabilities: Capabilities) -> bool {
+        if self.unexpected_responses.read().get(token).map_or(false, |count| *count >= MAX_UNEXPECTED_RESPONSES) {
+            return false
+        }
+        self.peer_capabilities.read().get(token).map_or(false, |c| c.contains(capabilities))
+    }
+
+    /// Counts a response which answers nothing outstanding, or which answers uselessly.
+    fn note_unexpected_response(&self, token: &NodeId) {
+        let mut unexpected_responses = self.unexpected_responses.write();
+        let count = unexpected_responses.entry(*token).or_insert(0);
+        *count += 1;
+        if *count == MAX_UNEXPECTED_RESPONSES {
+            cinfo!(SYNC, "Peer #{} sent too many unexpected responses, stop requesting to it", token);
+        }
+    }
+
+    /// The protocol version negotiated with the peer when it is connected,
+    /// lowered to the version in its status if that is older.
+    fn peer_version(&self, token: &NodeId) -> u64 {
+        self.peer_versions.read().get(token).cloned().unwrap_or(0)
+    }
+
+    fn status_message(&self, token: &NodeId) -> Message {
+        let chain_info = self.client.chain_info();
+        // A peer of the version 0 can't decode the version and the capabilities.
+        let version = if self.peer_version(token) == 0 {
+            0
+        } else {
+            PROTOCOL_VERSION
+        };
+        Message::Status {
+            version,
+            total_score: chain_info.total_score,
+            best_hash: chain_info.best_block_hash,
+            genesis_hash: chain_info.genesis_hash,
+            capabilities: self.capabilities,
+        }
+    }
+
+    /// Messages newer than the protocol version of the peer are never sent.
     fn send_message(&self, token: &NodeId, message: Message) {
+        let version = self.peer_version(token);
+        if required_version(&message) > version {
+            cdebug!(SYNC, "Peer #{} of version {} can't receive the message {}", token, version, message.id());
+            return
+        }
         self.api.lock().as_ref().map(|api| {
             api.send(token, &message.rlp_bytes().to_vec());
         });
@@ -67,21 +249,94 @@ impl Extension {
 
     fn dismiss_request(&self, token: &NodeId, id: u64) {
         if let Some(requests) = self.requests.write().get_mut(token) {
-            requests.retain(|(i, _)| *i == id);
+            requests.retain(|(i, ..)| *i != id);
+        }
+    }
+
+    fn update_request(&self, token: &NodeId, id: u64, request: RequestMessage) {
+        if let Some(requests) = self.requests.write().get_mut(token) {
+            if let Some(entry) = requests.iter_mut().find(|(i, ..)| *i == id) {
+                entry.1 = request;
+            }
         }
     }
 
-    fn send_request(&self, token: &NodeId, request: RequestMessage) {
+    /// Returns the id of the request, or `None` if the peer is unknown.
+    fn send_request(&self, token: &NodeId, request: RequestMessage) -> Option<u64> {
         if let Some(requests) = self.requests.write().get_mut(token) {
             let id = self.last_request.fetch_add(1, Ordering::Relaxed) as u64;
-            requests.push((id, request.clone()));
+            requests.push((id, request.clone(), Instant::now()));
             self.send_message(token, Message::Request(id, request));
+            Some(id)
+        } else {
+            None
         }
     }
 
     fn send_response(&self, token: &NodeId, id: u64, response: ResponseMessage) {
         self.send_message(token, Message::Response(id, response));
     }
+
+    /// Drops the requests which are not answered until `now`, so that their targets can be requested again.
+    fn expire_requests(&self, now: Instant) {
+        let timeout = StdDuration::from_secs(REQUEST_TIMEOUT_SECS);
+        // The requests are released before the downloaders are locked, so that they are never held together.
+        let mut expired = Vec::new();
+        for (token, outstanding) in self.requests.write().iter_mut() {
+            let (timed_out, remains): (Vec<_>, Vec<_>) =
+                outstanding.drain(..).partition(|(_, _, sent_at)| now >= *sent_at + timeout);
+            *outstanding = remains;
+            expired.extend(timed_out.into_iter().map(|(id, request, _)| (*token, id, request)));
+        }
+
+        let mut expired_snapshot_requests = Vec::new();
+        {
+            let mut body_downloader = self.body_downloader.lock();
+            let mut gap_requests = self.gap_requests.lock();
+            let mut batch_requests = self.batch_requests.lock();
+            for (token, id, request) in expired {
+                cdebug!(SYNC, "The request {} to peer #{} expired", id, token);
+                gap_requests.remove(&id);
+                batch_requests.remove(&id);
+                match request {
+                    RequestMessage::Bodies(hashes) => body_downloader.reset_downloading(hashes),
+                    RequestMessage::StateChunk {
+                        chunk_hash,
+                        ..
+                    } => expired_snapshot_requests.push((token, Some(chunk_hash))),
+                    RequestMessage::SnapshotManifest => expired_snapshot_requests.push((token, None)),
+                    _ => {}
+                }
+            }
+        }
+        // The chunk downloader is never locked together with the other downloaders.
+        if let Some(ref chunk_downloader) = self.chunk_downloader {
+            let mut chunk_downloader = chunk_downloader.lock();
+            for (token, chunk_hash) in expired_snapshot_requests {
+                match chunk_hash {
+                    Some(chunk_hash) => chunk_downloader.reset_downloading(&chunk_hash),
+                    None => chunk_downloader.import_manifest(token, None, now),
+                }
+            }
+        }
+    }
+
+    /// Forgives one unexpected response of each peer once in `UNEXPECTED_RESPONSE_DECAY_SECS`,
+    /// so that a peer which answered late for a while is requested again.
+    fn decay_unexpected_responses(&self, now: Instant) {
+        {
+            let mut last_decay = self.last_decay.lock();
+            if now < *last_decay + StdDuration::from_secs(UNEXPECTED_RESPONSE_DECAY_SECS) {
+                return
+            }
+            *last_decay = now;
+        }
+        let mut unexpected_responses = self.unexpected_responses.write();
+        for count in unexpected_responses.values_mut() {
+            *count -= 1;
+        }
+        unexpected_responses.retain(|_, count| *count > 0);
+    }
 }
 
 impl NetworkExtension for Extension {
@@ -93,7 +348,11 @@ impl NetworkExtension for Extension {
     }
 
     fn versions(&self) -> Vec<u64> {
-        vec![0]
+        // Version 1 advertises the version and the capabilities in the status message
+        // Version 2 announces new blocks
+        // Version 3 announces the hashes of new blocks
+        // Version 4 requests the headers by hash, with skip and reverse
+        (self.min_version..PROTOCOL_VERSION + 1).collect()
     }
 
     fn on_initialize(&self, api: Arc<Api>) {
@@ -102,36 +361,72 @@ impl NetworkExtension for Extension {
         cinfo!(SYNC, "Sync extension initialized");
     }
 
-    fn on_node_added(&self, token: &NodeId, _version: u64) {
+    fn on_node_added(&self, token: &NodeId, version: u64) {
         cinfo!(SYNC, "New peer detected #{}", token);
-        let chain_info = self.client.chain_info();
-        self.send_message(
-            token,
-            Message::Status {
-                total_score: chain_info.total_score,
-                best_hash: chain_info.best_block_hash,
-                genesis_hash: chain_info.genesis_hash,
-            },
-        );
+        self.peer_versions.write().insert(*token, version);
+        let status = self.status_message(token);
+        self.send_message(token, status);
     }
 
     fn on_node_removed(&self, token: &NodeId) {
-        self.header_downloaders.write().remove(token);
+        {
+            let mut peers = self.header_downloaders.write();
+            peers.remove(token);
+            self.status.set_peers(peers.len());
+        }
+        self.status.remove_peer(token);
+        self.peer_versions.write().remove(token);
+        self.peer_capabilities.write().remove(token);
+        self.unexpected_responses.write().remove(token);
+        // Bodies which were not answered should be requested to other peers.
+        // The entry is taken out first, so that the requests are not locked while the downloaders are.
+        let requests = self.requests.write().remove(token);
+        if let Some(requests) = requests {
+            let mut body_downloader = self.body_downloader.lock();
+            let mut gap_requests = self.gap_requests.lock();
+            let mut batch_requests = self.batch_requests.lock();
+            for (id, request, _) in requests {
+                gap_requests.remove(&id);
+                batch_requests.remove(&id);
+                if let RequestMessage::Bodies(hashes) = request {
+                    body_downloader.reset_downloading(hashes);
+                }
+            }
+        }
+        {
+            let mut batches = self.header_batches.lock();
+            batches.remove_peer(*token);
+            self.status.set_header_batches(batches.status());
+        }
+        if let Some(ref chunk_downloader) = self.chunk_downloader {
+            chunk_downloader.lock().remove_peer(token);
+        }
         cinfo!(SYNC, "Peer removed #{}", token);
     }
 
     fn on_message(&self, token: &NodeId, data: &[u8]) {
-        if let Ok(received_message) = UntrustedRlp::new(data).as_val() {
+        let limits = self.client.decode_limits();
+        if let Ok(received_message) = Message::decode_with_limits(&UntrustedRlp::new(data), &limits) {
+            let version = self.peer_version(token);
+            if required_version(&received_message) > version {
+                cinfo!(SYNC, "Peer #{} of version {} sent the message {}", token, version, received_message.id());
+                return
+            }
             match received_message {
                 Message::Status {
+                    version,
                     total_score,
                     best_hash,
                     genesis_hash,
-                } => {
-                    self.on_peer_status(token, total_score, best_hash, genesis_hash);
-                }
+                    capabilities,
+                } => self.on_peer_status(token, version, total_score, best_hash, genesis_hash, capabilities),
                 Message::Request(id, request) => self.on_peer_request(token, id, request),
                 Message::Response(id, response) => self.on_peer_response(token, id, response),
+                Message::NewBlock {
+                    total_score,
+                    block,
+                } => self.on_new_block(token, total_score, block),
+                Message::NewBlockHashes(hashes) => self.on_new_block_hashes(token, hashes),
             }
         } else {
             cinfo!(SYNC, "Invalid message from peer {}", token);
@@ -141,16 +436,27 @@ impl NetworkExtension for Extension {
     fn on_timeout(&self, timer: TimerToken) {
         debug_assert_eq!(timer, SYNC_TIMER_TOKEN);
 
+        let expired = self.orphans.lock().remove_expired(Instant::now());
+        if expired > 0 {
+            cdebug!(SYNC, "{} orphan blocks expired", expired);
+        }
+        self.expire_requests(Instant::now());
+        self.decay_unexpected_responses(Instant::now());
+        let is_batching = self.request_header_batches(Instant::now());
+
         let total_score = self.client.chain_info().total_score;
         let peer_ids: Vec<_> = self.header_downloaders.read().keys().cloned().collect();
+        self.request_snapshot_chunks(&peer_ids, Instant::now());
+        let is_holding_bodies = self.is_holding_bodies();
         for id in peer_ids {
-            if let Some(peer) = self.header_downloaders.write().get_mut(&id) {
-                if let Some(request) = peer.create_request() {
+            // The headers are not downloaded from each peer while they are downloaded in batches.
+            if !is_batching && self.peer_has(&id, Capabilities::SERVE_HEADERS) {
+                let request = self.header_downloaders.write().get_mut(&id).and_then(|peer| peer.create_request());
+                if let Some(request) = request {
                     self.send_request(&id, request);
                 }
             }
 
-            // FIXME: invalidate expired body requests
             let peer_score = if let Some(peer) = self.header_downloaders.read().get(&id) {
                 peer.total_score()
             } else {
@@ -159,15 +465,20 @@ impl NetworkExtension for Extension {
             let have_body_request = {
                 if let Some(request_list) = self.requests.read().get(&id) {
                     request_list.iter().any(|r| match r {
-                        (_, RequestMessage::Bodies(..)) => true,
+                        (_, RequestMessage::Bodies(..), _) => true,
                         _ => false,
                     })
                 } else {
                     false
                 }
             };
-            if !have_body_request && peer_score > total_score {
-                if let Some(request) = self.body_downloader.lock().create_request() {
+            if !have_body_request
+                && !is_holding_bodies
+                && peer_score > total_score
+                && self.peer_has(&id, Capabilities::SERVE_BODIES)
+            {
+                let request = self.body_downloader.lock().create_request();
+                if let Some(request) = request {
                     self.send_request(&id, request);
                 }
             }
@@ -182,24 +493,71 @@ impl ChainNotify for Extension {
         invalid: Vec<H256>,
         _enacted: Vec<H256>,
         _retracted: Vec<H256>,
-        _sealed: Vec<H256>,
+        sealed: Vec<H256>,
         _duration: u64,
     ) {
+        let best_hash = self.client.chain_info().best_block_hash;
+        self.status.set_best_block_number(self.client.chain_info().best_block_number);
+        for hash in &imported {
+            self.import_orphans_of(hash);
+        }
+        // An imported block which becomes the best is relayed, but not to the peers which already have as much score.
+        let relayed = if imported.contains(&best_hash) && !sealed.contains(&best_hash) {
+            Some(best_hash)
+        } else {
+            None
+        };
         self.body_downloader.lock().remove_target(imported);
         self.body_downloader.lock().remove_target(invalid);
 
+        let announcements: Vec<_> = sealed
+            .into_iter()
+            .map(|hash| (hash, false))
+            .chain(relayed.map(|hash| (hash, true)))
+            .filter_map(|(hash, is_relayed)| {
+                let block = self.client.block(BlockId::Hash(hash))?;
+                let total_score = self.client.block_total_score(BlockId::Hash(hash))?;
+                Some((block, total_score, is_relayed))
+            })
+            .collect();
 
-        let chain_info = self.client.chain_info();
         let peer_ids: Vec<_> = self.header_downloaders.read().keys().cloned().collect();
-        for id in peer_ids {
-            self.send_message(
-                &id,
-                Message::Status {
-                    total_score: chain_info.total_score,
-                    best_hash: chain_info.best_block_hash,
-                    genesis_hash: chain_info.genesis_hash,
-                },
-            );
+        for id in &peer_ids {
+            let status = self.status_message(id);
+            self.send_message(id, status);
+        }
+
+        let version_of = |id: &NodeId| self.peer_version(id);
+        let score_of =
+            |id: &NodeId| self.header_downloaders.read().get(id).map_or(U256::zero(), |peer| peer.total_score());
+        let mut hash_announcements: HashMap<NodeId, Vec<(H256, BlockNumber)>> = HashMap::new();
+        for (block, total_score, is_relayed) in announcements {
+            let receivers: Vec<NodeId> = peer_ids
+                .iter()
+                .filter(|id| version_of(id) >= NEW_BLOCK_VERSION && (!is_relayed || score_of(id) < total_score))
+                .cloned()
+                .collect();
+            // The peers which don't understand the hashes always receive the full block.
+            let (full_only, receivers): (Vec<_>, Vec<_>) =
+                receivers.into_iter().partition(|id| version_of(id) < NEW_BLOCK_HASHES_VERSION);
+            let (full, hashes_only) = split_announcement_receivers(receivers, &mut thread_rng());
+
+            for id in &hashes_only {
+                hash_announcements.entry(*id).or_insert_with(Vec::new).push((block.hash(), block.number()));
+            }
+            let block = block.into_inner();
+            for id in full_only.iter().chain(&full) {
+                self.send_message(
+                    id,
+                    Message::NewBlock {
+                        total_score,
+                        block: block.clone(),
+                    },
+                );
+            }
+        }
+        for (id, hashes) in hash_announcements {
+            self.send_message(&id, Message::NewBlockHashes(hashes));
         }
     }
 
@@ -224,8 +582,14 @@ impl ChainNotify for Extension {
             .collect();
         enacted_headers.sort_unstable_by_key(|header| header.number());
 
+        // The bodies below the snapshot being restored are not imported.
+        let restored_number = self
+            .chunk_downloader
+            .as_ref()
+            .and_then(|chunk_downloader| chunk_downloader.lock().manifest().map(|manifest| manifest.block_number));
         let body_targets = enacted_headers
             .into_iter()
+            .filter(|header| restored_number.map_or(true, |number| header.number() >= number))
             .filter(|header| self.client.block_body(BlockId::Hash(header.hash())).is_none())
             .map(|header| {
                 let prev_root = if let Some(parent) = self.client.block_header(BlockId::Hash(header.parent_hash())) {
@@ -242,7 +606,20 @@ impl ChainNotify for Extension {
 }
 
 impl Extension {
-    fn on_peer_status(&self, from: &NodeId, total_score: U256, best_hash: H256, genesis_hash: H256) {
+    fn on_peer_status(
+        &self,
+        from: &NodeId,
+        version: u64,
+        total_score: U256,
+        best_hash: H256,
+        genesis_hash: H256,
+        capabilities: Capabilities,
+    ) {
+        if version < self.min_version {
+            cinfo!(SYNC, "Peer #{} of version {} is older than the version {}", from, version, self.min_version);
+            self.api.lock().as_ref().map(|api| api.disconnect(from));
+            return
+        }
         // Validity check
         if genesis_hash != self.client.chain_info().genesis_hash {
             cinfo!(SYNC, "Genesis hash mismatch with peer {}", from);
@@ -250,6 +627,12 @@ impl Extension {
         }
 
         ctrace!(SYNC, "Peer #{} status update: total_score: {}, best_hash: {}", from, total_score, best_hash);
+        if let Some(negotiated) = self.peer_versions.write().get_mut(from) {
+            if version < *negotiated {
+                *negotiated = version;
+            }
+        }
+        self.peer_capabilities.write().insert(*from, capabilities);
 
         let mut requests = self.requests.write();
         let mut peers = self.header_downloaders.write();
@@ -258,6 +641,202 @@ impl Extension {
         } else {
             requests.insert(*from, Vec::new());
             peers.insert(*from, HeaderDownloader::new(self.client.clone(), total_score, best_hash));
+            self.status.set_peers(peers.len());
+        }
+    }
+}
+
+impl Extension {
+    fn on_new_block(&self, from: &NodeId, total_score: U256, block: Bytes) {
+        if !self.header_downloaders.read().contains_key(from) {
+            cinfo!(SYNC, "New block from invalid peer #{} received", from);
+            return
+        }
+
+        let header: Header = match UntrustedRlp::new(&block).val_at(0) {
+            Ok(header) => header,
+            Err(e) => {
+                cinfo!(SYNC, "Invalid new block from peer #{}: {:?}", from, e);
+                return
+            }
+        };
+        let hash = header.hash();
+        let parent_hash = *header.parent_hash();
+        ctrace!(SYNC, "Peer #{} announced the block #{} {}", from, header.number(), hash);
+
+        if let Some(peer) = self.header_downloaders.write().get_mut(from) {
+            if total_score > peer.total_score() {
+                peer.update(total_score, hash);
+            }
+        }
+
+        if self.client.block_status(BlockId::Hash(hash)) != BlockStatus::Unknown || self.orphans.lock().contains(&hash)
+        {
+            return
+        }
+
+        match self.client.block_status(BlockId::Hash(parent_hash)) {
+            BlockStatus::InChain | BlockStatus::Queued => self.import_announced_block(from, &header, block),
+            BlockStatus::Bad => cdebug!(SYNC, "The parent of the announced block {} is bad", hash),
+            BlockStatus::Pending | BlockStatus::Unknown => {
+                self.on_orphan_block(from, &header, total_score, block)
+            }
+        }
+    }
+
+    /// Requests the headers and the bodies of the announced blocks which are unknown.
+    fn on_new_block_hashes(&self, from: &NodeId, hashes: Vec<(H256, BlockNumber)>) {
+        if !self.header_downloaders.read().contains_key(from) {
+            cinfo!(SYNC, "New block hashes from invalid peer #{} received", from);
+            return
+        }
+
+        // The announced hashes and numbers are not trusted until their headers are imported.
+        ctrace!(SYNC, "Peer #{} announced {} blocks", from, hashes.len());
+
+        // The peer has the announced blocks whose headers are known, so its best is updated from their own numbers.
+        let highest_known = hashes
+            .iter()
+            .filter_map(|(hash, _)| self.client.block_header(BlockId::Hash(*hash)))
+            .max_by_key(|header| header.number());
+        if let Some(header) = highest_known {
+            self.update_peer_best(from, header.hash(), header.number());
+        }
+
+        let unknown: Vec<_> = hashes
+            .into_iter()
+            .filter(|(hash, _)| self.client.block_status(BlockId::Hash(*hash)) == BlockStatus::Unknown)
+            .filter(|(hash, _)| !self.orphans.lock().contains(hash))
+            .collect();
+        // The blocks whose headers are imported already wait only for their bodies.
+        let (body_missing, header_missing): (Vec<_>, Vec<_>) =
+            unknown.into_iter().partition(|(hash, _)| self.client.block_header(BlockId::Hash(*hash)).is_some());
+
+        if !body_missing.is_empty() && !self.is_holding_bodies() && self.peer_has(from, Capabilities::SERVE_BODIES) {
+            let hashes: Vec<_> = body_missing.into_iter().map(|(hash, _)| hash).collect();
+            let request = self.body_downloader.lock().create_request_for(&hashes);
+            if let Some(request) = request {
+                self.send_request(from, request);
+            }
+        }
+
+        // The blocks far below the best block are forks which the header downloader finds from the common ancestor,
+        // so that a forged low number can't make the request span the whole chain.
+        let best_number = self.client.chain_info().best_block_number;
+        let header_missing: Vec<_> = header_missing
+            .into_iter()
+            .filter(|(_, number)| number.saturating_add(MAX_ORPHAN_DEPTH) >= best_number)
+            .collect();
+        let lowest_number = match header_missing.iter().map(|(_, number)| *number).min() {
+            Some(number) => number,
+            None => return,
+        };
+        let highest_number = header_missing.iter().map(|(_, number)| *number).max().expect("Not empty");
+        if !self.peer_has(from, Capabilities::SERVE_HEADERS) {
+            return
+        }
+        if highest_number > best_number + MAX_ORPHAN_DEPTH {
+            ctrace!(SYNC, "The announced block #{} is too far ahead, start a sync round", highest_number);
+            let request = self.header_downloaders.write().get_mut(from).and_then(|peer| peer.create_request());
+            if let Some(request) = request {
+                self.send_request(from, request);
+            }
+            return
+        }
+        // The headers are imported in the same way as the ones below the orphans.
+        let start_number = lowest_number.saturating_sub(MAX_ORPHAN_DEPTH);
+        let request = RequestMessage::Headers {
+            start_number,
+            max_count: highest_number + 1 - start_number,
+        };
+        if let Some(id) = self.send_request(from, request) {
+            self.gap_requests.lock().insert(id);
+        }
+    }
+
+    /// Records that the peer has the block, which is imported already.
+    fn update_peer_best(&self, from: &NodeId, hash: H256, number: BlockNumber) {
+        self.status.note_block_number(from, number);
+        let total_score = match self.client.block_total_score(BlockId::Hash(hash)) {
+            Some(total_score) => total_score,
+            None => return,
+        };
+        if let Some(peer) = self.header_downloaders.write().get_mut(from) {
+            if peer.total_score() < total_score {
+                peer.update(total_score, hash);
+            }
+        }
+    }
+
+    /// Holds a block whose parent is unknown and requests the headers below it.
+    /// Blocks too far ahead of the best block are left to the header downloader.
+    fn on_orphan_block(&self, from: &NodeId, header: &Header, total_score: U256, block: Bytes) {
+        let number = header.number();
+        let best_number = self.client.chain_info().best_block_number;
+        if number > best_number + MAX_ORPHAN_DEPTH {
+            ctrace!(SYNC, "The announced block #{} is too far ahead, start a sync round", number);
+            if !self.peer_has(from, Capabilities::SERVE_HEADERS) {
+                return
+            }
+            let request = self.header_downloaders.write().get_mut(from).and_then(|peer| peer.create_request());
+            if let Some(request) = request {
+                self.send_request(from, request);
+            }
+            return
+        }
+
+        let hash = header.hash();
+        if !self.orphans.lock().insert(hash, *header.parent_hash(), total_score, block, Instant::now()) {
+            ctrace!(SYNC, "The orphan pool is full, drop the announced block {}", hash);
+            return
+        }
+
+        if !self.peer_has(from, Capabilities::SERVE_HEADERS) {
+            return
+        }
+        let start_number = number.saturating_sub(MAX_ORPHAN_DEPTH);
+        let request = RequestMessage::Headers {
+            start_number,
+            max_count: number - start_number,
+        };
+        if let Some(id) = self.send_request(from, request) {
+            self.gap_requests.lock().insert(id);
+        }
+    }
+
+    fn import_announced_block(&self, from: &NodeId, header: &Header, block: Bytes) {
+        let hash = header.hash();
+        match self.client.import_block(block) {
+            Ok(_) => {
+                self.status.note_block_number(from, header.number());
+                self.import_orphans_of(&hash)
+            }
+            Err(BlockImportError::Import(ImportError::AlreadyInChain))
+            | Err(BlockImportError::Import(ImportError::AlreadyQueued)) => {
+                self.status.note_block_number(from, header.number())
+            }
+            Err(e) => cdebug!(SYNC, "Failed to import the announced block {}: {:?}", hash, e),
+        }
+    }
+
+    /// Imports the orphans which descend from the given block.
+    fn import_orphans_of(&self, hash: &H256) {
+        let descendants = self.orphans.lock().take_descendants(hash);
+        let mut failed = HashSet::new();
+        for orphan in descendants {
+            if failed.contains(&orphan.parent_hash) {
+                failed.insert(orphan.hash);
+                continue
+            }
+            match self.client.import_block(orphan.block) {
+                Ok(_)
+                | Err(BlockImportError::Import(ImportError::AlreadyInChain))
+                | Err(BlockImportError::Import(ImportError::AlreadyQueued)) => {}
+                Err(e) => {
+                    cdebug!(SYNC, "Failed to import the orphan block {}: {:?}", orphan.hash, e);
+                    failed.insert(orphan.hash);
+                }
+            }
         }
     }
 }
@@ -269,25 +848,40 @@ impl Extension {
             return
         }
 
+        if !self.capabilities.contains(request.required_capabilities()) {
+            cinfo!(SYNC, "Unsupported request received from peer #{}", from);
+            return
+        }
+
         if !self.is_valid_request(&request) {
             cinfo!(SYNC, "Invalid request received from peer #{}", from);
             return
         }
 
-        let response = match request {
+        let responses = match request {
             RequestMessage::Headers {
                 start_number,
                 max_count,
-            } => self.create_headers_response(start_number, max_count),
-            RequestMessage::Bodies(hashes) => self.create_bodies_response(hashes),
-            RequestMessage::StateHead(hash) => self.create_state_head_response(hash),
+            } => vec![self.create_headers_response(start_number, max_count)],
+            RequestMessage::HeadersFrom {
+                start,
+                max_count,
+                skip,
+                reverse,
+            } => vec![self.create_headers_from_response(start, max_count, skip, reverse)],
+            RequestMessage::Bodies(hashes) => self.create_bodies_responses(hashes),
+            // State heads are never valid requests.
+            RequestMessage::StateHead(..) => Vec::new(),
             RequestMessage::StateChunk {
                 block_hash,
-                tree_root,
-            } => self.create_state_chunk_response(block_hash, tree_root),
+                chunk_hash,
+            } => vec![self.create_state_chunk_response(block_hash, chunk_hash)],
+            RequestMessage::SnapshotManifest => vec![self.create_snapshot_manifest_response()],
         };
 
-        self.send_response(from, id, response);
+        for response in responses {
+            self.send_response(from, id, response);
+        }
     }
 
     fn is_valid_request(&self, request: &RequestMessage) -> bool {
@@ -295,82 +889,189 @@ impl Extension {
             RequestMessage::Headers {
                 ..
             } => true,
+            RequestMessage::HeadersFrom {
+                ..
+            } => true,
             RequestMessage::Bodies(hashes) => hashes.len() != 0,
-            RequestMessage::StateHead(hash) => match self.client.block_number(BlockId::Hash(*hash)) {
-                Some(number) if number % SNAPSHOT_PERIOD == 0 => true,
-                _ => false,
-            },
+            // The head of a snapshot is served as its first chunk.
+            RequestMessage::StateHead(..) => false,
+            // Only the chunks of the newest snapshot are served.
             RequestMessage::StateChunk {
                 block_hash,
-                tree_root: _tree_root,
-            } => {
-                let _is_checkpoint = match self.client.block_number(BlockId::Hash(*block_hash)) {
-                    Some(number) if number % SNAPSHOT_PERIOD == 0 => true,
-                    _ => false,
-                };
-                // FIXME:  check tree_root
-                unimplemented!()
-            }
+                chunk_hash,
+            } => self.snapshot_manifest.as_ref().map_or(false, |manifest| manifest.has_chunk(block_hash, chunk_hash)),
+            RequestMessage::SnapshotManifest => true,
         }
     }
 
+    /// The response holds the canonical headers from `start_number` in ascending order.
+    /// It stops before the first unknown number and never holds more than `max_count` headers.
     fn create_headers_response(&self, start_number: BlockNumber, max_count: u64) -> ResponseMessage {
-        let headers = (0..max_count)
-            .map(|number| self.client.block(BlockId::Number(start_number + number)))
-            .take_while(|block| block.is_some())
-            .map(|block| block.expect("take_while guarantees existance of item").header().decode())
-            .collect();
-        ResponseMessage::Headers(headers)
+        let numbers = (0..max_count).map(|offset| start_number.saturating_add(offset));
+        ResponseMessage::Headers(self.canonical_headers(numbers, MAX_HEADERS_RESPONSE_SIZE))
+    }
+
+    /// The response walks the canonical chain from `start` by `skip + 1` blocks,
+    /// stopping at either end of the chain. It is empty if `start` is not in the canonical chain.
+    fn create_headers_from_response(
+        &self,
+        start: HeaderStart,
+        max_count: u64,
+        skip: u64,
+        reverse: bool,
+    ) -> ResponseMessage {
+        let start_number = match start {
+            HeaderStart::Number(number) => Some(number),
+            HeaderStart::Hash(hash) => match self.client.block_number(BlockId::Hash(hash)) {
+                Some(number) if self.client.block_hash(BlockId::Number(number)) == Some(hash) => Some(number),
+                _ => None,
+            },
+        };
+        let start_number = match start_number {
+            Some(number) => number,
+            None => return ResponseMessage::Headers(Vec::new()),
+        };
+        let step = skip.saturating_add(1);
+        let numbers = (0..max_count)
+            .map(|index| {
+                index.checked_mul(step).and_then(|distance| {
+                    if reverse {
+                        start_number.checked_sub(distance)
+                    } else {
+                        start_number.checked_add(distance)
+                    }
+                })
+            })
+            .take_while(Option::is_some)
+            .map(|number| number.expect("Checked above"));
+        ResponseMessage::Headers(self.canonical_headers(numbers, MAX_HEADERS_RESPONSE_SIZE))
+    }
+
+    /// Collects the canonical headers of `numbers`, stopping before the first unknown number.
+    /// At most `MAX_HEADERS_RESPONSE_COUNT` headers are collected,
+    /// and their serialized size doesn't exceed `max_size` unless the first header alone does.
+    fn canonical_headers<I>(&self, numbers: I, max_size: usize) -> Vec<Header>
+    where
+        I: Iterator<Item = BlockNumber>, {
+        let mut headers = Vec::new();
+        let mut size = 0;
+        for number in numbers.take(MAX_HEADERS_RESPONSE_COUNT) {
+            let header = match self.client.block_header(BlockId::Number(number)) {
+                Some(header) => header,
+                None => break,
+            };
+            size += header.rlp().as_raw().len();
+            if !headers.is_empty() && size > max_size {
+                break
+            }
+            headers.push(header.decode());
+        }
+        headers
     }
 
-    fn create_bodies_response(&self, hashes: Vec<H256>) -> ResponseMessage {
+    /// The bodies of the hashes after the first `MAX_BODIES_RESPONSE_COUNT` ones are not served.
+    fn create_bodies_responses(&self, hashes: Vec<H256>) -> Vec<ResponseMessage> {
         let mut bodies = Vec::new();
-        for hash in hashes {
+        for hash in hashes.into_iter().take(MAX_BODIES_RESPONSE_COUNT) {
             if let Some(body) = self.client.block_body(BlockId::Hash(hash)) {
                 bodies.push(body.parcels());
             } else {
                 bodies.push(Vec::new());
             }
         }
-        ResponseMessage::Bodies(bodies)
+        split_bodies(bodies, MAX_BODIES_RESPONSE_SIZE).into_iter().map(ResponseMessage::Bodies).collect()
     }
 
-    fn create_state_head_response(&self, _hash: H256) -> ResponseMessage {
-        unimplemented!()
+    /// The response is empty if the snapshot is removed after the request is validated.
+    fn create_state_chunk_response(&self, block_hash: H256, chunk_hash: H256) -> ResponseMessage {
+        let chunk = self.snapshot_manifest.as_ref().and_then(|manifest| manifest.read_chunk(&block_hash, &chunk_hash));
+        ResponseMessage::StateChunk(chunk.unwrap_or_default())
     }
 
-    fn create_state_chunk_response(&self, _hash: H256, _tree_root: H256) -> ResponseMessage {
-        unimplemented!()
+    fn create_snapshot_manifest_response(&self) -> ResponseMessage {
+        ResponseMessage::SnapshotManifest(self.snapshot_manifest.as_ref().and_then(|manifest| manifest.get()))
     }
 }
 
 impl Extension {
-    fn on_peer_response(&self, from: &NodeId, id: u64, mut response: ResponseMessage) {
-        let last_request = self.requests.read()[from].iter().find(|(i, _)| *i == id).cloned();
-        if let Some((_, request)) = last_request {
-            match &mut response {
-                ResponseMessage::Headers(headers) => {
-                    headers.sort_unstable_by_key(|h| h.number());
-                }
-                _ => {}
-            }
-
-            if !self.is_valid_response(&request, &response) {
+    fn on_peer_response(&self, from: &NodeId, id: u64, response: ResponseMessage) {
+        let last_request =
+            self.requests.read().get(from).and_then(|requests| requests.iter().find(|(i, ..)| *i == id).cloned());
+        if let Some((_, request, _)) = last_request {
+            // A peer which doesn't have the headers of a batch answers it with no header.
+            let is_empty_batch = match response {
+                ResponseMessage::Headers(ref headers) => headers.is_empty() && self.batch_requests.lock().contains(&id),
+                _ => false,
+            };
+            if !is_empty_batch && !self.is_valid_response(&request, &response) {
                 return
             }
-            self.dismiss_request(from, id);
 
             match response {
-                ResponseMessage::Headers(headers) => self.on_header_response(from, headers),
+                ResponseMessage::Headers(headers) => {
+                    self.dismiss_request(from, id);
+                    if let RequestMessage::HeadersFrom {
+                        ..
+                    } = request
+                    {
+                        // Headers are not requested by hash until the common ancestors are searched with them.
+                        return
+                    }
+                    if self.gap_requests.lock().remove(&id) {
+                        let best = headers.last().map(|header| (header.hash(), header.number()));
+                        if self.import_headers(from, headers) {
+                            if let Some((hash, number)) = best {
+                                self.update_peer_best(from, hash, number);
+                            }
+                        }
+                    } else if self.batch_requests.lock().remove(&id) {
+                        let start_number = match request {
+                            RequestMessage::Headers {
+                                start_number,
+                                ..
+                            } => start_number,
+                            _ => unreachable!(),
+                        };
+                        self.on_batch_header_response(from, start_number, headers)
+                    } else {
+                        self.on_header_response(from, headers)
+                    }
+                }
                 ResponseMessage::Bodies(bodies) => {
-                    let hashes = match request {
+                    let mut hashes = match request {
                         RequestMessage::Bodies(hashes) => hashes,
                         _ => unreachable!(),
                     };
+                    // The response covers a prefix of the requested hashes.
+                    // The rest is still outstanding and will be answered with the same id.
+                    let remains = hashes.split_off(bodies.len());
+                    if remains.is_empty() {
+                        self.dismiss_request(from, id);
+                    } else {
+                        self.update_request(from, id, RequestMessage::Bodies(remains));
+                    }
                     self.on_body_response(from, hashes, bodies)
                 }
-                _ => unimplemented!(),
+                ResponseMessage::SnapshotManifest(manifest) => {
+                    self.dismiss_request(from, id);
+                    self.on_manifest_response(from, manifest)
+                }
+                ResponseMessage::StateChunk(chunk) => {
+                    self.dismiss_request(from, id);
+                    let chunk_hash = match request {
+                        RequestMessage::StateChunk {
+                            chunk_hash,
+                            ..
+                        } => chunk_hash,
+                        _ => unreachable!(),
+                    };
+                    self.on_chunk_response(from, chunk_hash, chunk)
+                }
+                ResponseMessage::StateHead(..) => unreachable!("State heads are never requested"),
             }
+        } else {
+            cdebug!(SYNC, "Peer #{} answered the unknown request {}", from, id);
+            self.note_unexpected_response(from);
         }
     }
 
@@ -379,10 +1080,13 @@ impl Extension {
             (
                 RequestMessage::Headers {
                     start_number,
-                    ..
+                    max_count,
                 },
                 ResponseMessage::Headers(headers),
             ) => {
+                if headers.len() as u64 > *max_count {
+                    return false
+                }
                 // Continuity check
                 for neighbors in headers.windows(2) {
                     let parent = &neighbors[0];
@@ -394,14 +1098,46 @@ impl Extension {
 
                 headers.first().map(|header| header.number()) == Some(*start_number)
             }
-            (RequestMessage::Bodies(..), ResponseMessage::Bodies(..)) => true,
-            (RequestMessage::StateHead(..), ResponseMessage::StateHead(..)) => unimplemented!(),
+            (
+                RequestMessage::HeadersFrom {
+                    start,
+                    max_count,
+                    skip,
+                    reverse,
+                },
+                ResponseMessage::Headers(headers),
+            ) => {
+                if headers.len() as u64 > *max_count {
+                    return false
+                }
+                let is_start = match (start, headers.first()) {
+                    (_, None) => true,
+                    (HeaderStart::Number(number), Some(first)) => first.number() == *number,
+                    (HeaderStart::Hash(hash), Some(first)) => first.hash() == *hash,
+                };
+                let step = skip.saturating_add(1);
+                is_start
+                    && headers.windows(2).all(|neighbors| {
+                        let (prev, next) = (&neighbors[0], &neighbors[1]);
+                        let expected = if *reverse {
+                            prev.number().checked_sub(step)
+                        } else {
+                            prev.number().checked_add(step)
+                        };
+                        expected == Some(next.number())
+                    })
+            }
+            (RequestMessage::Bodies(hashes), ResponseMessage::Bodies(bodies)) => {
+                !bodies.is_empty() && bodies.len() <= hashes.len()
+            }
+            (RequestMessage::StateHead(..), ResponseMessage::StateHead(..)) => false,
             (
                 RequestMessage::StateChunk {
                     ..
                 },
                 ResponseMessage::StateChunk(..),
-            ) => unimplemented!(),
+            ) => true,
+            (RequestMessage::SnapshotManifest, ResponseMessage::SnapshotManifest(..)) => true,
             _ => false,
         }
     }
@@ -419,50 +1155,1490 @@ impl Extension {
         let mut exists = Vec::new();
         for header in completed {
             let hash = header.hash();
+            let number = header.number();
             // FIXME: handle import errors
             match self.client.import_header(header.into_inner()) {
-                Err(BlockImportError::Import(ImportError::AlreadyInChain)) => exists.push(hash),
+                Ok(_) => self.status.note_block_number(from, number),
+                Err(BlockImportError::Import(ImportError::AlreadyInChain)) => {
+                    self.status.note_block_number(from, number);
+                    exists.push(hash)
+                }
                 _ => {}
             }
         }
 
-        if let Some(peer) = self.header_downloaders.write().get_mut(from) {
+        let request = self.header_downloaders.write().get_mut(from).and_then(|peer| {
             peer.mark_as_imported(exists);
-            if let Some(request) = peer.create_request() {
-                self.send_request(from, request);
-            }
+            peer.create_request()
+        });
+        if let Some(request) = request {
+            self.send_request(from, request);
         }
     }
 
-    fn on_body_response(&self, from: &NodeId, hashes: Vec<H256>, bodies: Vec<Vec<UnverifiedParcel>>) {
-        self.body_downloader.lock().import_bodies(hashes, bodies);
-        let completed = self.body_downloader.lock().drain();
-        let mut exists = Vec::new();
-        for (hash, body) in completed {
-            let header = self.client.block_header(BlockId::Hash(hash)).expect("Downloaded body's header must exist");
-            let block = Block {
-                header: header.decode(),
-                parcels: body,
-            };
-            // FIXME: handle import errors
-            match self.client.import_block(block.rlp_bytes(Seal::With)) {
-                Err(BlockImportError::Import(ImportError::AlreadyInChain)) => exists.push(hash),
-                _ => {}
+    /// Imports the headers the peer sent in order, stopping at the first failure.
+    /// Their bodies are downloaded once the headers are enacted. Returns false if a header is not imported.
+    fn import_headers(&self, from: &NodeId, headers: Vec<Header>) -> bool {
+        for header in headers {
+            let hash = header.hash();
+            let number = header.number();
+            match self.client.import_header(header.rlp_bytes().to_vec()) {
+                Ok(_) | Err(BlockImportError::Import(ImportError::AlreadyInChain)) => {
+                    self.status.note_block_number(from, number)
+                }
+                Err(e) => {
+                    cdebug!(SYNC, "Failed to import the header {}: {:?}", hash, e);
+                    return false
+                }
             }
         }
-        self.body_downloader.lock().remove_target(exists);
+        true
+    }
 
-        let total_score = self.client.chain_info().total_score;
-        let peer_score = if let Some(peer) = self.header_downloaders.read().get(from) {
-            peer.total_score()
-        } else {
-            U256::zero()
+    fn on_batch_header_response(&self, from: &NodeId, start_number: BlockNumber, headers: Vec<Header>) {
+        let is_empty = headers.is_empty();
+        let (drained, forked_peer, is_lowest) = {
+            let mut batches = self.header_batches.lock();
+            let is_lowest = start_number == batches.next_number();
+            if !batches.complete(*from, start_number, headers) {
+                cdebug!(SYNC, "The header batch from #{} is not assigned to peer #{}", start_number, from);
+                return
+            }
+            let (drained, forked_peer) = batches.drain();
+            self.status.set_header_batches(batches.status());
+            (drained, forked_peer, is_lowest)
         };
+        // A peer ahead of us must have the headers right above the ones handed over.
+        if is_empty && is_lowest {
+            cdebug!(SYNC, "Peer #{} has no header from #{}", from, start_number);
+            self.note_unexpected_response(from);
+        }
+        if let Some(peer) = forked_peer {
+            cdebug!(SYNC, "The header batch of peer #{} doesn't attach to the headers below it", peer);
+            self.note_unexpected_response(&peer);
+        }
+
+        for (peer, headers) in drained {
+            if !self.import_headers(&peer, headers) {
+                // The headers above can't be imported either, so that the download starts over from the best block.
+                let mut batches = self.header_batches.lock();
+                batches.reset();
+                self.status.set_header_batches(batches.status());
+                return
+            }
+        }
+    }
+
+    /// Assigns the header batches to the peers ahead of this node while it is far behind them.
+    /// Returns true if some batches are being downloaded.
+    fn request_header_batches(&self, now: Instant) -> bool {
+        let total_score = self.client.chain_info().total_score;
+        let peers: Vec<_> = self
+            .header_downloaders
+            .read()
+            .iter()
+            .filter(|(_, peer)| peer.total_score() > total_score)
+            .map(|(id, peer)| (*id, peer.total_score()))
+            .collect();
+        let peers: Vec<_> =
+            peers.into_iter().filter(|(id, _)| self.peer_has(id, Capabilities::SERVE_HEADERS)).collect();
+        let best_header = self.client.best_block_header();
+
+        let (failed_peers, assignments, is_batching) = {
+            let mut batches = self.header_batches.lock();
+            let failed_peers = batches.expire(now);
+            match peers.iter().map(|(_, score)| *score).max() {
+                Some(highest_score) => {
+                    let target =
+                        header_batch_target(best_header.number(), best_header.score(), total_score, highest_score);
+                    if let Some(target) = target {
+                        batches.extend_to(best_header.number(), best_header.hash(), target);
+                    }
+                }
+                // There is no one to download the batches from.
+                None => batches.reset(),
+            }
+            let assignments: Vec<_> = peers
+                .iter()
+                .filter_map(|(id, _)| batches.assign(*id, now).map(|(start_number, count)| (*id, start_number, count)))
+                .collect();
+            let status = batches.status();
+            self.status.set_header_batches(status);
+            (failed_peers, assignments, status.in_flight > 0)
+        };
+        for peer in failed_peers {
+            cdebug!(SYNC, "Peer #{} didn't answer its header batch in time", peer);
+            self.note_unexpected_response(&peer);
+        }
+
+        for (id, start_number, max_count) in assignments {
+            let request = RequestMessage::Headers {
+                start_number,
+                max_count,
+            };
+            match self.send_request(&id, request) {
+                Some(request_id) => {
+                    self.batch_requests.lock().insert(request_id);
+                }
+                None => self.header_batches.lock().remove_peer(id),
+            }
+        }
+        is_batching
+    }
 
-        if peer_score > total_score {
-            if let Some(request) = self.body_downloader.lock().create_request() {
+    fn on_body_response(&self, from: &NodeId, hashes: Vec<H256>, bodies: Vec<Vec<UnverifiedParcel>>) {
+        self.body_downloader.lock().import_bodies(hashes, bodies);
+        let completed = self.body_downloader.lock().drain();
+        let mut exists = Vec::new();
+        for (hash, body) in completed {
+            let header = self.client.block_header(BlockId::Hash(hash)).expect("Downloaded body's header must exist");
+            let block = Block {
+                header: header.decode(),
+                parcels: body,
+            };
+            if self.is_restored_block(&hash) {
+                self.finish_restoration(block.rlp_bytes(Seal::With));
+                continue
+            }
+            // FIXME: handle import errors
+            match self.client.import_block(block.rlp_bytes(Seal::With)) {
+                Err(BlockImportError::Import(ImportError::AlreadyInChain)) => exists.push(hash),
+                _ => {}
+            }
+        }
+        self.body_downloader.lock().remove_target(exists);
+
+        let total_score = self.client.chain_info().total_score;
+        let peer_score = if let Some(peer) = self.header_downloaders.read().get(from) {
+            peer.total_score()
+        } else {
+            U256::zero()
+        };
+
+        if peer_score > total_score && !self.is_holding_bodies() && self.peer_has(from, Capabilities::SERVE_BODIES) {
+            let request = self.body_downloader.lock().create_request();
+            if let Some(request) = request {
                 self.send_request(from, request);
             }
         }
     }
 }
+
+impl Extension {
+    /// The bodies are not downloaded while a snapshot may be restored, or while its chunks are downloaded,
+    /// since no block can be imported before the state is restored.
+    fn is_holding_bodies(&self) -> bool {
+        self.chunk_downloader.as_ref().map_or(false, |chunk_downloader| {
+            let chunk_downloader = chunk_downloader.lock();
+            chunk_downloader.is_waiting() || chunk_downloader.is_downloading()
+        })
+    }
+
+    /// Whether the block is the one of the snapshot whose chunks are all restored.
+    fn is_restored_block(&self, hash: &H256) -> bool {
+        self.chunk_downloader.as_ref().map_or(false, |chunk_downloader| {
+            let chunk_downloader = chunk_downloader.lock();
+            !chunk_downloader.is_downloading()
+                && chunk_downloader.manifest().map_or(false, |manifest| manifest.block_hash == *hash)
+        })
+    }
+
+    /// A snapshot is restored only if its block is in the canonical header chain with the same state root.
+    /// Returns `None` while the header chain doesn't reach the block.
+    fn is_acceptable_snapshot(&self, manifest: &Manifest) -> Option<bool> {
+        if manifest.block_number == 0 {
+            return Some(false)
+        }
+        let header = self.client.block_header(BlockId::Number(manifest.block_number))?;
+        Some(header.hash() == manifest.block_hash && header.state_root() == manifest.state_root)
+    }
+
+    /// Requests the snapshot manifests of the peers, starts restoring the newest snapshot which is acceptable,
+    /// and requests its chunks to the peers which have the snapshot.
+    fn request_snapshot_chunks(&self, peer_ids: &[NodeId], now: Instant) {
+        let chunk_downloader = match self.chunk_downloader {
+            Some(ref chunk_downloader) => chunk_downloader,
+            None => return,
+        };
+        let best_number = self.client.chain_info().best_block_number;
+        let started =
+            chunk_downloader.lock().start(best_number, now, |manifest| self.is_acceptable_snapshot(manifest));
+        match started {
+            Ok(Some(manifest)) => {
+                cinfo!(SYNC, "Start restoring the state of #{} ({})", manifest.block_number, manifest.block_hash);
+                self.body_downloader.lock().remove_targets_before(&manifest.block_hash);
+            }
+            Ok(None) => {}
+            Err(e) => cwarn!(SYNC, "Failed to start restoring the state: {}", e),
+        }
+
+        for id in peer_ids {
+            let request = if self.peer_has(id, Capabilities::SERVE_STATE_CHUNKS) {
+                chunk_downloader.lock().create_request(*id)
+            } else {
+                None
+            };
+            if let Some(request) = request {
+                self.send_request(id, request);
+            }
+            let request = if self.peer_has(id, Capabilities::SERVE_SNAPSHOT_MANIFESTS) {
+                chunk_downloader.lock().create_manifest_request(*id)
+            } else {
+                None
+            };
+            if let Some(request) = request {
+                self.send_request(id, request);
+            }
+        }
+    }
+
+    fn on_manifest_response(&self, from: &NodeId, manifest: Option<Manifest>) {
+        if let Some(ref chunk_downloader) = self.chunk_downloader {
+            chunk_downloader.lock().import_manifest(*from, manifest, Instant::now());
+        }
+    }
+
+    /// Restores the chunk and requests another one to the peer.
+    /// A peer which sent a chunk other than the requested one is not requested anymore.
+    fn on_chunk_response(&self, from: &NodeId, chunk_hash: H256, chunk: Bytes) {
+        let chunk_downloader = match self.chunk_downloader {
+            Some(ref chunk_downloader) => chunk_downloader,
+            None => return,
+        };
+        if chunk.is_empty() {
+            cdebug!(SYNC, "Peer #{} doesn't have the chunk {} anymore", from, chunk_hash);
+            let mut chunk_downloader = chunk_downloader.lock();
+            chunk_downloader.reset_downloading(&chunk_hash);
+            chunk_downloader.import_manifest(*from, None, Instant::now());
+            return
+        }
+
+        let restored = chunk_downloader.lock().import_chunk(&chunk_hash, &chunk);
+        match restored {
+            Ok(()) => {}
+            Err(e @ SnapshotError::UnexpectedChunk(..)) | Err(e @ SnapshotError::CorruptChunk(..)) => {
+                cinfo!(SYNC, "Peer #{} sent an invalid chunk for {}: {}", from, chunk_hash, e);
+                chunk_downloader.lock().import_manifest(*from, None, Instant::now());
+                self.note_unexpected_response(from);
+                return
+            }
+            Err(e) => {
+                cwarn!(SYNC, "Failed to restore the chunk {}: {}", chunk_hash, e);
+                return
+            }
+        }
+        if !chunk_downloader.lock().is_downloading() {
+            cinfo!(SYNC, "Every chunk of the snapshot is restored, download the bodies");
+        }
+        let request = chunk_downloader.lock().create_request(*from);
+        if let Some(request) = request {
+            self.send_request(from, request);
+        }
+    }
+
+    /// Imports the block of the snapshot as the best block, on top of the restored state.
+    /// The chunk downloader is not locked during the import, which notifies the new block.
+    fn finish_restoration(&self, block: Bytes) {
+        let restorer =
+            self.chunk_downloader.as_ref().and_then(|chunk_downloader| chunk_downloader.lock().take_complete());
+        let restorer = match restorer {
+            Some(restorer) => restorer,
+            None => return,
+        };
+        match restorer.finish(&*self.client, block) {
+            Ok(hash) => cinfo!(SYNC, "The restored block {} is imported", hash),
+            Err(e) => cwarn!(SYNC, "Failed to import the restored block: {}", e),
+        }
+    }
+}
+
+/// Returns the number up to which the headers are downloaded in batches from the peer with `peer_score`,
+/// or `None` if the peer is not far enough ahead.
+///
+/// How far the peer is ahead is estimated from the difference of the total scores,
+/// taking the score of our best block as the score of each block. The estimate is bound to
+/// `MAX_HEADER_BATCH_LOOKAHEAD` blocks past our best block.
+fn header_batch_target(
+    best_number: BlockNumber,
+    block_score: U256,
+    total_score: U256,
+    peer_score: U256,
+) -> Option<BlockNumber> {
+    if peer_score <= total_score {
+        return None
+    }
+    let block_score = if block_score.is_zero() {
+        U256::one()
+    } else {
+        block_score
+    };
+    let ahead = (peer_score - total_score) / block_score;
+    let ahead = if ahead > U256::from(MAX_HEADER_BATCH_LOOKAHEAD) {
+        MAX_HEADER_BATCH_LOOKAHEAD
+    } else {
+        ahead.low_u64()
+    };
+    if ahead <= HEADER_BATCH_SIZE {
+        return None
+    }
+    best_number.checked_add(ahead)
+}
+
+/// The lowest protocol version which carries the message.
+fn required_version(message: &Message) -> u64 {
+    match message {
+        Message::NewBlock {
+            ..
+        } => NEW_BLOCK_VERSION,
+        Message::NewBlockHashes(..) => NEW_BLOCK_HASHES_VERSION,
+        Message::Request(
+            _,
+            RequestMessage::HeadersFrom {
+                ..
+            },
+        ) => HEADERS_FROM_VERSION,
+        _ => 0,
+    }
+}
+
+/// Splits the receivers of a new block into the ones which receive the full block and the others which receive
+/// only its hash. The full block goes to the square root of the receivers, chosen at random.
+fn split_announcement_receivers<T, R: Rng>(mut receivers: Vec<T>, rng: &mut R) -> (Vec<T>, Vec<T>) {
+    rng.shuffle(&mut receivers);
+    let full = (receivers.len() as f64).sqrt().ceil() as usize;
+    let hashes_only = receivers.split_off(full);
+    (receivers, hashes_only)
+}
+
+/// Splits `bodies` into chunks whose serialized size doesn't exceed `max_size`.
+/// The order of the bodies is kept. A body larger than `max_size` forms a chunk by itself.
+fn split_bodies(bodies: Vec<Vec<UnverifiedParcel>>, max_size: usize) -> Vec<Vec<Vec<UnverifiedParcel>>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_size = 0;
+    for body in bodies {
+        let body_size = rlp::encode_list(&body).len();
+        if !chunk.is_empty() && chunk_size + body_size > max_size {
+            chunks.push(chunk);
+            chunk = Vec::new();
+            chunk_size = 0;
+        }
+        chunk_size += body_size;
+        chunk.push(body);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, File};
+    use std::io::Write;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use ccore::{
+        Action, BlockInfo, ChainInfo, Parcel, TestBlock, TestBlockChainClient, TestClientCall, TestClientFailure,
+        Transaction, NUM_COLUMNS,
+    };
+    use ckeys::{Generator, Random};
+    use cnetwork::{TestNetworkCall, TestNetworkClient};
+    use ctypes::U256;
+    use tempdir::TempDir;
+
+    use ccrypto::blake256;
+    use kvdb_memorydb;
+
+    use super::*;
+
+    fn requested_bodies_to_peer(capabilities: Capabilities) -> bool {
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let extension = Extension::new(client);
+        let mut network = TestNetworkClient::new();
+        network.register_extension(extension.clone());
+
+        let peer = NodeId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3485);
+        network.add_node(EXTENSION_NAME, peer);
+        let status = Message::Status {
+            version: PROTOCOL_VERSION,
+            total_score: U256::from(1_000_000),
+            best_hash: H256::from(1),
+            genesis_hash,
+            capabilities,
+        };
+        network.send_message(EXTENSION_NAME, peer, &status.rlp_bytes());
+
+        extension.body_downloader.lock().add_target(vec![(H256::from(2), H256::zero(), H256::from(0xff))]);
+        network.call_timeout(EXTENSION_NAME, SYNC_TIMER_TOKEN);
+
+        let mut requested = false;
+        while let Some(call) = network.pop_call(EXTENSION_NAME) {
+            if let TestNetworkCall::Send(to, bytes) = call {
+                let message: Message = ::rlp::decode(&bytes);
+                if let Message::Request(_, RequestMessage::Bodies(..)) = message {
+                    assert_eq!(peer, to);
+                    requested = true;
+                }
+            }
+        }
+        requested
+    }
+
+    #[test]
+    fn bodies_are_requested_to_peer_serving_bodies() {
+        assert!(requested_bodies_to_peer(Capabilities::SERVE_HEADERS | Capabilities::SERVE_BODIES));
+    }
+
+    #[test]
+    fn bodies_are_never_requested_to_peer_not_serving_bodies() {
+        assert!(!requested_bodies_to_peer(Capabilities::SERVE_HEADERS));
+        assert!(!requested_bodies_to_peer(Capabilities::empty()));
+    }
+
+    fn large_body(nonce: usize) -> Vec<UnverifiedParcel> {
+        let keypair = Random.generate().unwrap();
+        (0..10)
+            .map(|i| {
+                let parcel = Parcel {
+                    nonce: U256::from(nonce * 10 + i),
+                    fee: U256::from(10),
+                    network_id: 200,
+                    action: Action::ChangeShardState {
+                        transactions: vec![Transaction::AssetMint {
+                            metadata: "M".repeat(1000),
+                            lock_script_hash: H256::zero(),
+                            parameters: vec![],
+                            amount: None,
+                            registrar: None,
+                            nonce: 0,
+                        }],
+                    },
+                };
+                parcel.sign(keypair.private()).into()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_bodies_keeps_order_and_bounds_size() {
+        let bodies: Vec<_> = (0..8).map(large_body).collect();
+        let max_size = 3 * rlp::encode_list(&bodies[0]).len();
+
+        let chunks = split_bodies(bodies.clone(), max_size);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(!chunk.is_empty());
+            let size: usize = chunk.iter().map(|body| rlp::encode_list(body).len()).sum();
+            assert!(size <= max_size);
+        }
+        let joined: Vec<_> = chunks.into_iter().flat_map(|chunk| chunk).collect();
+        assert_eq!(bodies, joined);
+    }
+
+    #[test]
+    fn split_bodies_puts_oversized_body_alone() {
+        let bodies: Vec<_> = (0..3).map(large_body).collect();
+        let chunks = split_bodies(bodies, 1);
+        assert_eq!(3, chunks.len());
+        assert!(chunks.iter().all(|chunk| chunk.len() == 1));
+    }
+
+    #[test]
+    fn full_blocks_are_announced_to_the_square_root_of_the_receivers() {
+        for count in 0..50 {
+            let receivers: Vec<usize> = (0..count).collect();
+            let (full, hashes_only) = split_announcement_receivers(receivers.clone(), &mut thread_rng());
+            assert_eq!((count as f64).sqrt().ceil() as usize, full.len());
+
+            let mut joined: Vec<_> = full.into_iter().chain(hashes_only).collect();
+            joined.sort();
+            assert_eq!(receivers, joined);
+        }
+    }
+
+    #[test]
+    fn downloader_requests_a_limited_number_of_bodies_at_once() {
+        let targets = (0..200u64).map(|i| (H256::from(i + 1), H256::zero(), H256::from(0xff))).collect();
+        let mut downloader = BodyDownloader::new(targets);
+        let mut requested = Vec::new();
+        while let Some(RequestMessage::Bodies(hashes)) = downloader.create_request() {
+            assert!(hashes.len() <= 128);
+            requested.extend(hashes);
+        }
+        assert_eq!((0..200u64).map(|i| H256::from(i + 1)).collect::<Vec<_>>(), requested);
+    }
+
+    #[test]
+    fn downloader_assembles_split_bodies() {
+        let bodies: Vec<_> = (0..8).map(large_body).collect();
+        let hashes: Vec<_> = (0..8).map(|i| H256::from(i as u64 + 1)).collect();
+        let targets = hashes.iter().map(|hash| (*hash, H256::zero(), H256::from(0xff))).collect();
+        let mut downloader = BodyDownloader::new(targets);
+
+        let requested = match downloader.create_request() {
+            Some(RequestMessage::Bodies(requested)) => requested,
+            _ => panic!("Bodies request must be created"),
+        };
+        assert_eq!(hashes, requested);
+
+        let max_size = 3 * rlp::encode_list(&bodies[0]).len();
+        let mut outstanding = requested;
+        for chunk in split_bodies(bodies.clone(), max_size) {
+            let remains = outstanding.split_off(chunk.len());
+            downloader.import_bodies(outstanding, chunk);
+            outstanding = remains;
+        }
+        assert!(outstanding.is_empty());
+        assert_eq!(None, downloader.create_request());
+
+        let drained = downloader.drain();
+        assert_eq!(hashes, drained.iter().map(|(hash, _)| *hash).collect::<Vec<_>>());
+        assert_eq!(bodies, drained.into_iter().map(|(_, body)| body).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn downloader_requests_unanswered_bodies_again() {
+        let hashes: Vec<_> = (0..4).map(|i| H256::from(i as u64 + 1)).collect();
+        let targets = hashes.iter().map(|hash| (*hash, H256::zero(), H256::from(0xff))).collect();
+        let mut downloader = BodyDownloader::new(targets);
+        assert!(downloader.create_request().is_some());
+
+        downloader.import_bodies(hashes[..2].to_vec(), vec![large_body(0), large_body(1)]);
+        assert_eq!(None, downloader.create_request());
+
+        downloader.reset_downloading(hashes[2..].to_vec());
+        assert_eq!(Some(RequestMessage::Bodies(hashes[2..].to_vec())), downloader.create_request());
+    }
+
+    fn client_with_64_blocks() -> Arc<TestBlockChainClient> {
+        let client = TestBlockChainClient::new();
+        client.add_blocks(63, 0);
+        assert_eq!(63, client.chain_info().best_block_number);
+        Arc::new(client)
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        let digit = |pair: &[u8]| u8::from_str_radix(::std::str::from_utf8(pair).unwrap(), 16).unwrap();
+        hex.as_bytes().chunks(2).map(digit).collect()
+    }
+
+    #[test]
+    fn headers_response_holds_exactly_the_requested_headers_in_order() {
+        let extension = Extension::new(client_with_64_blocks());
+        let max = u64::max_value();
+        let empty: H256 = "da223b09967c5bd2110743307e0af6d39f61720aa7218a640a08eed12dd575c7".into();
+        let all: H256 = "91c439a6fd35e75171fe28f8ceaec2b657e9581cbaadf2e9b06733aacbe5c623".into();
+        let last: H256 = "f7d4e2cd7c37a8d01e51eaefe6df501f9772bc0bffd8b0e71a9d62328e633e17".into();
+        // (start_number, max_count, expected numbers, the blake256 of the expected response)
+        let cases: Vec<(u64, u64, Vec<u64>, H256)> = vec![
+            (0, 0, vec![], empty),
+            (0, 1, vec![0], "93242818226f20e5bae023ed4794b6b20640befd9cf838cb02fcefb0db5ed2ef".into()),
+            (0, 2, vec![0, 1], "896dfba15e49bd0d459b8a80265405dd0b7cbcbd81568ad0b834bca94cfdd15d".into()),
+            (0, 64, (0..64).collect(), all),
+            (0, 65, (0..64).collect(), all),
+            (0, max, (0..64).collect(), all),
+            (1, 1, vec![1], "56474327ea4a8a1ff125ea035e807acf5dd8f13241cc831e1e6411c703e8096d".into()),
+            (1, 10, (1..11).collect(), "4b1e840c3b795d76168dbb8cd3c51b0582ae66cbc6762e5328563ef57da17da6".into()),
+            (31, 2, vec![31, 32], "435776988f6ac98f1139530f6568afdfa1a024d5212d264982d471ab59084e5f".into()),
+            (32, 32, (32..64).collect(), "e8825b85e6c6e5f2442bcfa4f4d10327373129787105691896bcb36281bbd34b".into()),
+            (62, 5, vec![62, 63], "dc07e6129f2a6ae60caa4a98d8e28750c52b28bf9ab393418b2d8a6b6dbbd9ed".into()),
+            (63, 0, vec![], empty),
+            (63, 1, vec![63], last),
+            (63, max, vec![63], last),
+            (64, 1, vec![], empty),
+            (100, 10, vec![], empty),
+            (max, 2, vec![], empty),
+        ];
+        for (start_number, max_count, expected_numbers, expected_hash) in cases {
+            let response = extension.create_headers_response(start_number, max_count);
+            assert_eq!(
+                expected_hash,
+                blake256(response.rlp_bytes().into_vec()),
+                "start_number: {}, max_count: {}",
+                start_number,
+                max_count
+            );
+            match response {
+                ResponseMessage::Headers(headers) => {
+                    let numbers: Vec<_> = headers.iter().map(|header| header.number()).collect();
+                    assert_eq!(expected_numbers, numbers, "start_number: {}, max_count: {}", start_number, max_count);
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        let genesis = concat!(
+            "f8c4f8c2a000000000000000000000000000000000000000000000000000000000000000009400000000000000000000",
+            "00000000000000000000a028f9123ab715c6e935ce94876a97f68489ff8b7ddcef89001df16b5d71cd584ba045b0cfc2",
+            "20ceec5b7c1c62c4d4193d38e4eba48e8815729ce75f9c0ab0e4c1c0a045b0cfc220ceec5b7c1c62c4d4193d38e4eba4",
+            "8e8815729ce75f9c0ab0e4c1c083020000808080e1a045b0cfc220ceec5b7c1c62c4d4193d38e4eba48e8815729ce75f",
+            "9c0ab0e4c1c0",
+        );
+        assert_eq!(from_hex(genesis), extension.create_headers_response(0, 1).rlp_bytes().into_vec());
+        let best = concat!(
+            "f8a0f89ea0bc31a95b0bf7dc0f56ac4711b2cb389cc0227efe43c64a053f9c23a33bbf14d59400000000000000000000",
+            "00000000000000000000a028f9123ab715c6e935ce94876a97f68489ff8b7ddcef89001df16b5d71cd584ba045b0cfc2",
+            "20ceec5b7c1c62c4d4193d38e4eba48e8815729ce75f9c0ab0e4c1c0a045b0cfc220ceec5b7c1c62c4d4193d38e4eba4",
+            "8e8815729ce75f9c0ab0e4c1c03f3f8080c0",
+        );
+        assert_eq!(from_hex(best), extension.create_headers_response(63, 1).rlp_bytes().into_vec());
+        assert_eq!(vec![0xc0], extension.create_headers_response(64, 1).rlp_bytes().into_vec());
+    }
+
+    fn numbers_in(response: &ResponseMessage) -> Vec<BlockNumber> {
+        match response {
+            ResponseMessage::Headers(headers) => headers.iter().map(|header| header.number()).collect(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn headers_from_the_best_block_are_walked_in_reverse() {
+        let client = client_with_64_blocks();
+        let extension = Extension::new(client.clone());
+        let best = HeaderStart::Hash(client.chain_info().best_block_hash);
+        let max = u64::max_value();
+        // (start, max_count, skip, reverse, expected numbers)
+        let cases: Vec<(HeaderStart, u64, u64, bool, Vec<u64>)> = vec![
+            (best, 5, 0, true, (59..64).rev().collect()),
+            (best, 100, 9, true, vec![63, 53, 43, 33, 23, 13, 3]),
+            (best, max, 0, true, (0..64).rev().collect()),
+            (best, 0, 0, true, vec![]),
+            (best, 5, 0, false, vec![63]),
+            (best, 5, max, true, vec![63]),
+            (HeaderStart::Number(63), 3, 1, true, vec![63, 61, 59]),
+            (HeaderStart::Number(2), 10, 0, true, vec![2, 1, 0]),
+            (HeaderStart::Number(60), 10, 1, false, vec![60, 62]),
+            (HeaderStart::Number(64), 10, 0, true, vec![]),
+            (HeaderStart::Hash(H256::from(0xdead)), 10, 0, true, vec![]),
+        ];
+        for (start, max_count, skip, reverse, expected_numbers) in cases {
+            let request = RequestMessage::HeadersFrom {
+                start,
+                max_count,
+                skip,
+                reverse,
+            };
+            let response = extension.create_headers_from_response(start, max_count, skip, reverse);
+            assert_eq!(expected_numbers, numbers_in(&response), "{:?}", request);
+            assert!(extension.is_valid_response(&request, &response), "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn headers_from_response_in_the_wrong_order_is_rejected() {
+        let client = client_with_64_blocks();
+        let extension = Extension::new(client.clone());
+        let request = RequestMessage::HeadersFrom {
+            start: HeaderStart::Hash(client.chain_info().best_block_hash),
+            max_count: 3,
+            skip: 1,
+            reverse: true,
+        };
+        let headers = |numbers: &[u64]| {
+            ResponseMessage::Headers(
+                numbers.iter().map(|number| client.block_header(BlockId::Number(*number)).unwrap().decode()).collect(),
+            )
+        };
+
+        assert!(extension.is_valid_response(&request, &headers(&[63, 61, 59])));
+        assert!(extension.is_valid_response(&request, &headers(&[])));
+        assert!(!extension.is_valid_response(&request, &headers(&[63, 61, 59, 57])));
+        assert!(!extension.is_valid_response(&request, &headers(&[63, 62, 61])));
+        assert!(!extension.is_valid_response(&request, &headers(&[61, 59])));
+        assert!(!extension.is_valid_response(&request, &headers(&[59, 61, 63])));
+    }
+
+    #[test]
+    fn headers_response_is_clamped_to_the_maximum_count() {
+        let client = TestBlockChainClient::new();
+        client.add_blocks(MAX_HEADERS_RESPONSE_COUNT + 100, 0);
+        let best = HeaderStart::Hash(client.chain_info().best_block_hash);
+        let extension = Extension::new(Arc::new(client));
+
+        let response = extension.create_headers_response(0, u64::max_value());
+        assert_eq!((0..MAX_HEADERS_RESPONSE_COUNT as u64).collect::<Vec<_>>(), numbers_in(&response));
+
+        let response = extension.create_headers_from_response(best, u64::max_value(), 0, true);
+        assert_eq!(MAX_HEADERS_RESPONSE_COUNT, numbers_in(&response).len());
+    }
+
+    #[test]
+    fn headers_response_stops_at_the_size_budget() {
+        let client = client_with_64_blocks();
+        let extension = Extension::new(client.clone());
+        let size_of_ten: usize =
+            (0..10).map(|number| client.block_header(BlockId::Number(number)).unwrap().rlp().as_raw().len()).sum();
+
+        let headers = extension.canonical_headers(0..64, size_of_ten);
+        assert_eq!((0..10).collect::<Vec<_>>(), headers.iter().map(|header| header.number()).collect::<Vec<_>>());
+        let headers = extension.canonical_headers(0..64, size_of_ten - 1);
+        assert_eq!(9, headers.len());
+        // A header is always served, even if it alone exceeds the budget.
+        assert_eq!(1, extension.canonical_headers(0..64, 0).len());
+    }
+
+    #[test]
+    fn bodies_response_is_clamped_to_the_maximum_count() {
+        let extension = Extension::new(Arc::new(TestBlockChainClient::new()));
+        let hashes: Vec<_> = (0..MAX_BODIES_RESPONSE_COUNT as u64 + 100).map(H256::from).collect();
+        let served: usize = extension
+            .create_bodies_responses(hashes)
+            .into_iter()
+            .map(|response| match response {
+                ResponseMessage::Bodies(bodies) => bodies.len(),
+                _ => unreachable!(),
+            })
+            .sum();
+        assert_eq!(MAX_BODIES_RESPONSE_COUNT, served);
+    }
+
+    #[test]
+    fn headers_response_out_of_order_or_with_extras_is_rejected() {
+        let client = client_with_64_blocks();
+        let extension = Extension::new(client.clone());
+        let request = RequestMessage::Headers {
+            start_number: 10,
+            max_count: 3,
+        };
+        let headers: Vec<Header> =
+            (10..14).map(|number| client.block_header(BlockId::Number(number)).unwrap().decode()).collect();
+
+        assert!(extension.is_valid_response(&request, &ResponseMessage::Headers(headers[..3].to_vec())));
+        assert!(extension.is_valid_response(&request, &ResponseMessage::Headers(headers[..1].to_vec())));
+        assert!(!extension.is_valid_response(&request, &ResponseMessage::Headers(headers.clone())));
+        assert!(!extension.is_valid_response(&request, &ResponseMessage::Headers(headers[1..3].to_vec())));
+
+        let mut reversed = headers[..3].to_vec();
+        reversed.reverse();
+        assert!(!extension.is_valid_response(&request, &ResponseMessage::Headers(reversed)));
+    }
+
+    #[test]
+    fn the_newest_snapshot_manifest_is_served() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let manifests: Vec<_> = (1..3)
+            .map(|number| Manifest {
+                block_hash: H256::from(number),
+                block_number: number * 1000,
+                state_root: H256::from(0xff),
+                chunk_hashes: vec![H256::from(number + 0xff)],
+            })
+            .collect();
+        for manifest in &manifests {
+            let path = dir.path().join(format!("{:x}", manifest.block_hash));
+            create_dir_all(&path).unwrap();
+            File::create(path.join("manifest")).unwrap().write_all(&::rlp::encode(manifest)).unwrap();
+        }
+
+        let client = Arc::new(TestBlockChainClient::new());
+        let extension = Extension::new(client.clone());
+        assert_eq!(ResponseMessage::SnapshotManifest(None), extension.create_snapshot_manifest_response());
+
+        let latest_manifest = LatestManifest::new(dir.path().to_path_buf());
+        let extension = Extension::with_snapshot_manifest(client, Capabilities::default(), latest_manifest);
+        assert_eq!(
+            ResponseMessage::SnapshotManifest(Some(manifests[1].clone())),
+            extension.create_snapshot_manifest_response()
+        );
+    }
+
+    #[test]
+    fn only_the_chunks_of_the_newest_snapshot_are_served() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let chunk = vec![1, 2, 3];
+        let manifest = Manifest {
+            block_hash: H256::from(1),
+            block_number: 1000,
+            state_root: H256::from(0xff),
+            chunk_hashes: vec![blake256(&chunk)],
+        };
+        let path = dir.path().join(format!("{:x}", manifest.block_hash));
+        create_dir_all(&path).unwrap();
+        File::create(path.join("manifest")).unwrap().write_all(&::rlp::encode(&manifest)).unwrap();
+        File::create(path.join(format!("{:x}", blake256(&chunk)))).unwrap().write_all(&chunk).unwrap();
+
+        let client = Arc::new(TestBlockChainClient::new());
+        let latest_manifest = LatestManifest::new(dir.path().to_path_buf());
+        let extension = Extension::with_snapshot_manifest(client, Capabilities::default(), latest_manifest);
+        let request = |block_hash, chunk_hash| RequestMessage::StateChunk {
+            block_hash,
+            chunk_hash,
+        };
+        assert!(extension.is_valid_request(&request(manifest.block_hash, blake256(&chunk))));
+        assert!(!extension.is_valid_request(&request(H256::from(2), blake256(&chunk))));
+        assert!(!extension.is_valid_request(&request(manifest.block_hash, H256::from(3))));
+        assert!(!extension.is_valid_request(&RequestMessage::StateHead(manifest.block_hash)));
+        assert_eq!(
+            ResponseMessage::StateChunk(chunk.clone()),
+            extension.create_state_chunk_response(manifest.block_hash, blake256(&chunk))
+        );
+    }
+
+    #[test]
+    fn bodies_are_held_while_a_snapshot_may_be_restored() {
+        let dir = TempDir::new("restoration").unwrap();
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let state_db = Arc::new(kvdb_memorydb::create(NUM_COLUMNS.unwrap_or(0)));
+        let extension = Extension::with_snapshot_restoration(
+            client,
+            Capabilities::default(),
+            None,
+            state_db,
+            dir.path().join("restoration"),
+        );
+        let mut network = TestNetworkClient::new();
+        network.register_extension(extension.clone());
+        let peer = NodeId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3485);
+        network.add_node(EXTENSION_NAME, peer);
+        let status = Message::Status {
+            version: PROTOCOL_VERSION,
+            total_score: U256::from(1_000_000),
+            best_hash: H256::from(1),
+            genesis_hash,
+            capabilities: Capabilities::default()
+                | Capabilities::SERVE_SNAPSHOT_MANIFESTS
+                | Capabilities::SERVE_STATE_CHUNKS,
+        };
+        network.send_message(EXTENSION_NAME, peer, &status.rlp_bytes());
+        extension.body_downloader.lock().add_target(vec![(H256::from(2), H256::zero(), H256::from(0xff))]);
+
+        let requests = |network: &mut TestNetworkClient| {
+            network.call_timeout(EXTENSION_NAME, SYNC_TIMER_TOKEN);
+            let mut sent = Vec::new();
+            while let Some(call) = network.pop_call(EXTENSION_NAME) {
+                if let TestNetworkCall::Send(_, bytes) = call {
+                    let message: Message = ::rlp::decode(&bytes);
+                    if let Message::Request(id, request) = message {
+                        sent.push((id, request));
+                    }
+                }
+            }
+            sent
+        };
+        let is_body_request = |(_, request): &(u64, RequestMessage)| match request {
+            RequestMessage::Bodies(..) => true,
+            _ => false,
+        };
+        let sent = requests(&mut network);
+        assert!(!sent.iter().any(is_body_request), "The manifest is not answered yet");
+        let manifest_requests: Vec<_> =
+            sent.into_iter().filter(|(_, request)| *request == RequestMessage::SnapshotManifest).collect();
+        assert_eq!(1, manifest_requests.len());
+        let id = manifest_requests[0].0;
+
+        // The block of the manifest is not in the header chain yet.
+        let manifest = Manifest {
+            block_hash: H256::from(3),
+            block_number: 1000,
+            state_root: H256::from(0xff),
+            chunk_hashes: vec![H256::from(4)],
+        };
+        let response = Message::Response(id, ResponseMessage::SnapshotManifest(Some(manifest)));
+        network.send_message(EXTENSION_NAME, peer, &response.rlp_bytes());
+        assert!(extension.is_holding_bodies());
+        let sent = requests(&mut network);
+        assert!(!sent.iter().any(is_body_request));
+        assert!(!sent.iter().any(|(_, request)| *request == RequestMessage::SnapshotManifest));
+
+        extension.on_node_removed(&peer);
+        assert!(!extension.is_holding_bodies());
+    }
+
+    fn connected_extension(client: Arc<TestBlockChainClient>) -> (Arc<Extension>, TestNetworkClient, NodeId) {
+        let genesis_hash = client.chain_info().genesis_hash;
+        let extension = Extension::new(client);
+        let mut network = TestNetworkClient::new();
+        network.register_extension(extension.clone());
+
+        let peer = NodeId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3485);
+        network.add_node(EXTENSION_NAME, peer);
+        let status = Message::Status {
+            version: PROTOCOL_VERSION,
+            total_score: U256::zero(),
+            best_hash: genesis_hash,
+            genesis_hash,
+            capabilities: Capabilities::SERVE_HEADERS | Capabilities::SERVE_BODIES,
+        };
+        network.send_message(EXTENSION_NAME, peer, &status.rlp_bytes());
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+        (extension, network, peer)
+    }
+
+    /// Connects another peer at the genesis block.
+    fn add_peer(network: &TestNetworkClient, genesis_hash: H256, port: u16) -> NodeId {
+        let peer = NodeId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+        network.add_node(EXTENSION_NAME, peer);
+        let status = Message::Status {
+            version: PROTOCOL_VERSION,
+            total_score: U256::zero(),
+            best_hash: genesis_hash,
+            genesis_hash,
+            capabilities: Capabilities::SERVE_HEADERS | Capabilities::SERVE_BODIES,
+        };
+        network.send_message(EXTENSION_NAME, peer, &status.rlp_bytes());
+        peer
+    }
+
+    fn announce(network: &TestNetworkClient, peer: NodeId, source: &TestBlockChainClient, number: BlockNumber) {
+        let message = Message::NewBlock {
+            total_score: U256::from(1_000_000),
+            block: source.block(BlockId::Number(number)).unwrap().into_inner(),
+        };
+        network.send_message(EXTENSION_NAME, peer, &message.rlp_bytes());
+    }
+
+    fn requested_headers(network: &mut TestNetworkClient) -> Vec<(BlockNumber, u64)> {
+        let mut requests = Vec::new();
+        while let Some(call) = network.pop_call(EXTENSION_NAME) {
+            if let TestNetworkCall::Send(_, bytes) = call {
+                let message: Message = ::rlp::decode(&bytes);
+                if let Message::Request(
+                    _,
+                    RequestMessage::Headers {
+                        start_number,
+                        max_count,
+                    },
+                ) = message
+                {
+                    requests.push((start_number, max_count));
+                }
+            }
+        }
+        requests
+    }
+
+    #[test]
+    fn announced_child_before_parent_is_imported_after_parent() {
+        let source = TestBlockChainClient::new();
+        source.add_blocks(2, 0);
+        let client = Arc::new(TestBlockChainClient::new());
+        let (extension, mut network, peer) = connected_extension(client.clone());
+
+        announce(&network, peer, &source, 2);
+        assert_eq!(0, client.chain_info().best_block_number);
+        assert!(extension.orphans.lock().contains(&source.block_hash(BlockId::Number(2)).unwrap()));
+        assert_eq!(vec![(0, 2)], requested_headers(&mut network));
+
+        announce(&network, peer, &source, 1);
+        assert_eq!(2, client.chain_info().best_block_number);
+        assert_eq!(source.chain_info().best_block_hash, client.chain_info().best_block_hash);
+        assert_eq!(0, extension.orphans.lock().len());
+    }
+
+    #[test]
+    fn announced_hashes_request_only_the_unknown_headers() {
+        let source = TestBlockChainClient::new();
+        source.add_blocks(2, 0);
+        let client = Arc::new(TestBlockChainClient::new());
+        let (extension, mut network, peer) = connected_extension(client.clone());
+        let hash_of = |number: BlockNumber| source.block_hash(BlockId::Number(number)).unwrap();
+
+        let message = Message::NewBlockHashes(vec![(hash_of(0), 0)]);
+        network.send_message(EXTENSION_NAME, peer, &message.rlp_bytes());
+        assert_eq!(Vec::<(BlockNumber, u64)>::new(), requested_headers(&mut network));
+
+        let message = Message::NewBlockHashes(vec![(hash_of(0), 0), (hash_of(2), 2), (hash_of(1), 1)]);
+        network.send_message(EXTENSION_NAME, peer, &message.rlp_bytes());
+        assert_eq!(vec![(0, 3)], requested_headers(&mut network));
+        assert_eq!(0, extension.status().highest_block_number(), "The announced numbers are not verified");
+    }
+
+    #[test]
+    fn announced_known_blocks_update_the_best_of_the_peer() {
+        let client = Arc::new(TestBlockChainClient::new());
+        client.add_blocks(2, 0);
+        let (extension, network, peer) = connected_extension(client.clone());
+        let hash_of = |number: BlockNumber| client.block_hash(BlockId::Number(number)).unwrap();
+        let peer_score = || extension.header_downloaders.read()[&peer].total_score();
+
+        // The numbers are taken from the known headers, not from the announcement.
+        let message = Message::NewBlockHashes(vec![(hash_of(1), 100)]);
+        network.send_message(EXTENSION_NAME, peer, &message.rlp_bytes());
+        assert_eq!(1, extension.status().highest_block_number());
+        assert_eq!(client.block_total_score(BlockId::Number(1)), Some(peer_score()));
+
+        let message = Message::NewBlockHashes(vec![(hash_of(2), 2), (hash_of(0), 0)]);
+        network.send_message(EXTENSION_NAME, peer, &message.rlp_bytes());
+        assert_eq!(2, extension.status().highest_block_number());
+        assert_eq!(client.block_total_score(BlockId::Number(2)), Some(peer_score()));
+    }
+
+    #[test]
+    fn forged_low_numbers_do_not_widen_the_announced_headers_request() {
+        let client = client_with_64_blocks();
+        let (_, mut network, peer) = connected_extension(client);
+
+        let message = Message::NewBlockHashes(vec![(H256::from(0xdead), 0), (H256::from(0xbeef), 65)]);
+        network.send_message(EXTENSION_NAME, peer, &message.rlp_bytes());
+        assert_eq!(vec![(65 - MAX_ORPHAN_DEPTH, MAX_ORPHAN_DEPTH + 1)], requested_headers(&mut network));
+
+        let message = Message::NewBlockHashes(vec![(H256::from(0xdead), 0)]);
+        network.send_message(EXTENSION_NAME, peer, &message.rlp_bytes());
+        assert!(requested_headers(&mut network).is_empty());
+    }
+
+    #[test]
+    fn announced_blocks_with_known_headers_request_only_their_bodies() {
+        let client = Arc::new(TestBlockChainClient::new());
+        client.add_blocks(2, 1);
+        let (extension, network, peer) = connected_extension(client.clone());
+        let hash = client.block_hash(BlockId::Number(2)).unwrap();
+        extension.body_downloader.lock().add_target(vec![(hash, H256::zero(), H256::from(0xff))]);
+        // The header is known, but the block is not.
+        client.fail(TestClientCall::BlockStatus, TestClientFailure::DatabaseError);
+
+        let message = Message::NewBlockHashes(vec![(hash, 2)]);
+        network.send_message(EXTENSION_NAME, peer, &message.rlp_bytes());
+        let mut requested = Vec::new();
+        while let Some(call) = network.pop_call(EXTENSION_NAME) {
+            if let TestNetworkCall::Send(_, bytes) = call {
+                let message: Message = ::rlp::decode(&bytes);
+                if let Message::Request(_, request) = message {
+                    requested.push(request);
+                }
+            }
+        }
+        assert_eq!(vec![RequestMessage::Bodies(vec![hash])], requested);
+
+        network.send_message(EXTENSION_NAME, peer, &message.rlp_bytes());
+        assert_eq!(None, network.pop_call(EXTENSION_NAME), "The body being downloaded is not requested again");
+    }
+
+    #[test]
+    fn sealed_block_is_announced_in_full_to_some_peers_and_by_hash_to_the_others() {
+        let client = Arc::new(TestBlockChainClient::new());
+        client.add_blocks(1, 0);
+        let chain_info = client.chain_info();
+        let (extension, network, peer) = connected_extension(client.clone());
+        let mut peers = vec![peer];
+        for port in 3486..3489 {
+            peers.push(add_peer(&network, chain_info.genesis_hash, port));
+        }
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+
+        let hash = chain_info.best_block_hash;
+        extension.new_blocks(vec![hash], vec![], vec![hash], vec![], vec![hash], 0);
+        let mut full = Vec::new();
+        let mut hashes_only = Vec::new();
+        while let Some(call) = network.pop_call(EXTENSION_NAME) {
+            if let TestNetworkCall::Send(to, bytes) = call {
+                match ::rlp::decode(&bytes) {
+                    Message::NewBlock {
+                        ..
+                    } => full.push(to),
+                    Message::NewBlockHashes(hashes) => {
+                        assert_eq!(vec![(hash, 1)], hashes);
+                        hashes_only.push(to);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        assert_eq!(2, full.len());
+        assert_eq!(2, hashes_only.len());
+        let mut receivers: Vec<_> = full.into_iter().chain(hashes_only).collect();
+        receivers.sort();
+        peers.sort();
+        assert_eq!(peers, receivers);
+    }
+
+    #[test]
+    fn imported_best_block_is_relayed_except_to_peers_having_it() {
+        let source = TestBlockChainClient::new();
+        source.add_blocks(1, 0);
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let (extension, network, peer) = connected_extension(client.clone());
+        let other = add_peer(&network, genesis_hash, 3486);
+
+        announce(&network, peer, &source, 1);
+        let hash = client.chain_info().best_block_hash;
+        assert_eq!(source.chain_info().best_block_hash, hash);
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+
+        extension.new_blocks(vec![hash], vec![], vec![hash], vec![], vec![], 0);
+        let mut announced_to = Vec::new();
+        while let Some(call) = network.pop_call(EXTENSION_NAME) {
+            if let TestNetworkCall::Send(to, bytes) = call {
+                if let Message::NewBlock {
+                    block,
+                    ..
+                } = ::rlp::decode(&bytes)
+                {
+                    assert_eq!(source.block(BlockId::Hash(hash)).unwrap().into_inner(), block);
+                    announced_to.push(to);
+                }
+            }
+        }
+        assert_eq!(vec![other], announced_to);
+    }
+
+    #[test]
+    fn far_behind_node_downloads_header_batches_from_each_peer_ahead() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let total_score = client.chain_info().total_score;
+        let block_score = ::std::cmp::max(client.best_block_header().score(), U256::one());
+        let (extension, mut network, peer) = connected_extension(client);
+        let other = add_peer(&network, genesis_hash, 3486);
+        let behind = add_peer(&network, genesis_hash, 3487);
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+        let set_score = |score: U256| {
+            for id in &[peer, other] {
+                extension.header_downloaders.write().get_mut(id).unwrap().update(score, genesis_hash);
+            }
+        };
+
+        set_score(total_score + block_score * U256::from(HEADER_BATCH_SIZE));
+        assert!(!extension.request_header_batches(Instant::now()));
+        assert!(requested_headers(&mut network).is_empty());
+
+        // The batches don't go further than the lookahead, however far the peers are ahead.
+        set_score(total_score + block_score * U256::from(1_000_000_000));
+        let now = Instant::now();
+        assert!(extension.request_header_batches(now));
+        let mut requested = requested_headers(&mut network);
+        requested.sort();
+        assert_eq!(vec![(1, HEADER_BATCH_SIZE), (HEADER_BATCH_SIZE + 1, HEADER_BATCH_SIZE)], requested);
+        assert!(extension.requests.read()[&behind].is_empty());
+        let batches = (MAX_HEADER_BATCH_LOOKAHEAD / HEADER_BATCH_SIZE) as usize;
+        assert_eq!(
+            HeaderBatchStatus {
+                pending: batches - 2,
+                in_flight: 2,
+                complete: 0,
+            },
+            extension.status().header_batches()
+        );
+
+        assert!(extension.request_header_batches(now));
+        assert!(requested_headers(&mut network).is_empty());
+
+        extension.on_node_removed(&other);
+        assert_eq!(1, extension.status().header_batches().in_flight);
+        assert_eq!(batches - 1, extension.status().header_batches().pending);
+
+        let later = now + StdDuration::from_secs(HEADER_BATCH_TIMEOUT_SECS);
+        assert!(extension.request_header_batches(later));
+        assert_eq!(Some(&1), extension.unexpected_responses.read().get(&peer), "The timed out batch counts");
+        assert_eq!(vec![(HEADER_BATCH_SIZE + 1, HEADER_BATCH_SIZE)], requested_headers(&mut network));
+
+        extension.on_node_removed(&peer);
+        assert!(!extension.request_header_batches(later));
+        assert_eq!(HeaderBatchStatus::default(), extension.status().header_batches());
+    }
+
+    #[test]
+    fn empty_answer_to_the_lowest_header_batch_ends_the_download() {
+        let source = TestBlockChainClient::new();
+        source.add_blocks(2 * HEADER_BATCH_SIZE as usize, 0);
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let total_score = client.chain_info().total_score;
+        let block_score = ::std::cmp::max(client.best_block_header().score(), U256::one());
+        let (extension, mut network, peer) = connected_extension(client);
+        let other = add_peer(&network, genesis_hash, 3486);
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+        let score = total_score + block_score * U256::from(1_000_000_000);
+        for id in &[peer, other] {
+            extension.header_downloaders.write().get_mut(id).unwrap().update(score, genesis_hash);
+        }
+
+        assert!(extension.request_header_batches(Instant::now()));
+        let mut batch_requests = HashMap::new();
+        while let Some(call) = network.pop_call(EXTENSION_NAME) {
+            if let TestNetworkCall::Send(to, bytes) = call {
+                if let Message::Request(
+                    id,
+                    RequestMessage::Headers {
+                        start_number,
+                        ..
+                    },
+                ) = ::rlp::decode(&bytes)
+                {
+                    batch_requests.insert(start_number, (to, id));
+                }
+            }
+        }
+        assert_eq!(2, batch_requests.len());
+
+        // The upper batch waits for the lower one.
+        let (upper_peer, upper_id) = batch_requests[&(HEADER_BATCH_SIZE + 1)];
+        let headers = (HEADER_BATCH_SIZE + 1..2 * HEADER_BATCH_SIZE + 1)
+            .map(|number| source.block_header(BlockId::Number(number)).unwrap().decode())
+            .collect();
+        let response = Message::Response(upper_id, ResponseMessage::Headers(headers));
+        network.send_message(EXTENSION_NAME, upper_peer, &response.rlp_bytes());
+        assert_eq!(1, extension.status().header_batches().complete);
+        assert_eq!(1, extension.status().header_batches().in_flight);
+
+        let (lower_peer, lower_id) = batch_requests[&1];
+        let response = Message::Response(lower_id, ResponseMessage::Headers(Vec::new()));
+        network.send_message(EXTENSION_NAME, lower_peer, &response.rlp_bytes());
+        assert_eq!(Some(&1), extension.unexpected_responses.read().get(&lower_peer));
+        assert_eq!(None, extension.unexpected_responses.read().get(&upper_peer));
+        assert_eq!(HeaderBatchStatus::default(), extension.status().header_batches());
+    }
+
+    #[test]
+    fn header_batch_target_is_bound_to_the_lookahead() {
+        let score = U256::from(10);
+        let ahead = |blocks: u64| score * U256::from(blocks);
+        assert_eq!(None, header_batch_target(100, score, ahead(50), ahead(40)));
+        assert_eq!(None, header_batch_target(100, score, ahead(50), ahead(50 + HEADER_BATCH_SIZE)));
+        assert_eq!(Some(100 + 500), header_batch_target(100, score, ahead(50), ahead(550)));
+        assert_eq!(
+            Some(100 + MAX_HEADER_BATCH_LOOKAHEAD),
+            header_batch_target(100, score, ahead(50), U256::max_value())
+        );
+        assert_eq!(Some(MAX_HEADER_BATCH_LOOKAHEAD), header_batch_target(0, U256::zero(), U256::zero(), ahead(1_000)));
+        assert_eq!(None, header_batch_target(BlockNumber::max_value() - 1, score, ahead(50), ahead(1_000)));
+    }
+
+    #[test]
+    fn requests_for_capabilities_not_advertised_are_not_served() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let (_extension, mut network, peer) = connected_extension(client);
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+
+        let request = Message::Request(7, RequestMessage::StateHead(genesis_hash));
+        network.send_message(EXTENSION_NAME, peer, &request.rlp_bytes());
+        assert!(network.pop_call(EXTENSION_NAME).is_none());
+    }
+
+    #[test]
+    fn responses_to_requests_of_another_peer_or_answered_already_are_dropped() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let (extension, network, peer) = connected_extension(client);
+        let other = add_peer(&network, genesis_hash, 3486);
+        let id = extension.send_request(&peer, RequestMessage::SnapshotManifest).unwrap();
+        let response = Message::Response(id, ResponseMessage::SnapshotManifest(None));
+
+        network.send_message(EXTENSION_NAME, other, &response.rlp_bytes());
+        assert_eq!(1, extension.requests.read()[&peer].len());
+        assert_eq!(Some(&1), extension.unexpected_responses.read().get(&other));
+
+        network.send_message(EXTENSION_NAME, peer, &response.rlp_bytes());
+        assert!(extension.requests.read()[&peer].is_empty());
+        assert_eq!(None, extension.unexpected_responses.read().get(&peer));
+
+        network.send_message(EXTENSION_NAME, peer, &response.rlp_bytes());
+        assert_eq!(Some(&1), extension.unexpected_responses.read().get(&peer));
+    }
+
+    #[test]
+    fn peer_sending_too_many_unexpected_responses_is_not_requested() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let (extension, network, peer) = connected_extension(client);
+        assert!(extension.peer_has(&peer, Capabilities::SERVE_HEADERS));
+
+        for id in 0..MAX_UNEXPECTED_RESPONSES {
+            let response = Message::Response(id as u64 + 100, ResponseMessage::SnapshotManifest(None));
+            network.send_message(EXTENSION_NAME, peer, &response.rlp_bytes());
+        }
+        assert!(!extension.peer_has(&peer, Capabilities::SERVE_HEADERS));
+        assert!(!extension.peer_has(&peer, Capabilities::SERVE_BODIES));
+
+        extension.decay_unexpected_responses(Instant::now());
+        assert!(!extension.peer_has(&peer, Capabilities::SERVE_HEADERS), "Nothing is forgiven before the period");
+        let later = Instant::now() + StdDuration::from_secs(UNEXPECTED_RESPONSE_DECAY_SECS);
+        extension.decay_unexpected_responses(later);
+        assert!(extension.peer_has(&peer, Capabilities::SERVE_HEADERS));
+        assert_eq!(Some(&(MAX_UNEXPECTED_RESPONSES - 1)), extension.unexpected_responses.read().get(&peer));
+
+        extension.decay_unexpected_responses(later);
+        assert_eq!(Some(&(MAX_UNEXPECTED_RESPONSES - 1)), extension.unexpected_responses.read().get(&peer));
+    }
+
+    #[test]
+    fn expired_body_request_is_requested_again() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let (extension, _network, peer) = connected_extension(client);
+        extension.body_downloader.lock().add_target(vec![(H256::from(2), H256::zero(), H256::from(0xff))]);
+        let request = extension.body_downloader.lock().create_request().unwrap();
+        let id = extension.send_request(&peer, request).unwrap();
+        assert_eq!(None, extension.body_downloader.lock().create_request());
+
+        extension.expire_requests(Instant::now());
+        assert_eq!(1, extension.requests.read()[&peer].len());
+
+        extension.expire_requests(Instant::now() + StdDuration::from_secs(REQUEST_TIMEOUT_SECS));
+        assert!(extension.requests.read()[&peer].is_empty());
+        let request = extension.body_downloader.lock().create_request();
+        assert_eq!(Some(RequestMessage::Bodies(vec![H256::from(2)])), request);
+
+        // The late response is not trusted.
+        extension.on_peer_response(&peer, id, ResponseMessage::Bodies(vec![Vec::new()]));
+        assert_eq!(Some(&1), extension.unexpected_responses.read().get(&peer));
+    }
+
+    #[test]
+    fn orphan_too_far_ahead_starts_a_sync_round() {
+        let source = TestBlockChainClient::new();
+        source.add_blocks(20, 0);
+        let client = Arc::new(TestBlockChainClient::new());
+        let (extension, mut network, peer) = connected_extension(client.clone());
+
+        announce(&network, peer, &source, 20);
+        assert_eq!(0, extension.orphans.lock().len());
+        assert_eq!(vec![(0, 128)], requested_headers(&mut network));
+        assert_eq!(0, client.chain_info().best_block_number);
+    }
+
+    #[test]
+    fn messages_newer_than_the_version_of_the_peer_are_neither_sent_nor_accepted() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let extension = Extension::new(client);
+        let mut network = TestNetworkClient::new();
+        network.register_extension(extension.clone());
+        let peer = NodeId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3485);
+        extension.on_node_added(&peer, 0);
+        let status = Message::Status {
+            version: 0,
+            total_score: U256::zero(),
+            best_hash: genesis_hash,
+            genesis_hash,
+            capabilities: Capabilities::legacy(),
+        };
+        extension.on_message(&peer, &status.rlp_bytes());
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+
+        extension.send_message(&peer, Message::NewBlockHashes(vec![(H256::from(1), 1)]));
+        assert_eq!(None, network.pop_call(EXTENSION_NAME));
+        let status = extension.status_message(&peer).rlp_bytes().to_vec();
+        extension.send_message(&peer, extension.status_message(&peer));
+        assert_eq!(Some(TestNetworkCall::Send(peer, status)), network.pop_call(EXTENSION_NAME));
+
+        let message = Message::NewBlockHashes(vec![(H256::from(1), 10)]);
+        extension.on_message(&peer, &message.rlp_bytes());
+        assert_eq!(0, extension.status().highest_block_number());
+        assert_eq!(None, network.pop_call(EXTENSION_NAME));
+    }
+
+    #[test]
+    fn version_in_the_status_lowers_the_negotiated_one() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let (extension, mut network, peer) = connected_extension(client);
+        assert_eq!(PROTOCOL_VERSION, extension.peer_version(&peer));
+
+        let status = Message::Status {
+            version: NEW_BLOCK_VERSION,
+            total_score: U256::zero(),
+            best_hash: genesis_hash,
+            genesis_hash,
+            capabilities: Capabilities::default(),
+        };
+        network.send_message(EXTENSION_NAME, peer, &status.rlp_bytes());
+        assert_eq!(NEW_BLOCK_VERSION, extension.peer_version(&peer));
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+        extension.send_message(&peer, Message::NewBlockHashes(vec![(H256::from(1), 1)]));
+        assert_eq!(None, network.pop_call(EXTENSION_NAME));
+    }
+
+    #[test]
+    fn peer_older_than_the_minimum_version_is_disconnected_after_its_status() {
+        let client = Arc::new(TestBlockChainClient::new());
+        let genesis_hash = client.chain_info().genesis_hash;
+        let extension = Extension::create(client, Capabilities::default(), None, None, 1);
+        assert_eq!(vec![1, 2, 3, 4], extension.versions());
+        let mut network = TestNetworkClient::new();
+        network.register_extension(extension.clone());
+        let status = |version| Message::Status {
+            version,
+            total_score: U256::zero(),
+            best_hash: genesis_hash,
+            genesis_hash,
+            capabilities: Capabilities::default(),
+        };
+
+        let peer = NodeId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3485);
+        network.add_node(EXTENSION_NAME, peer);
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+        network.send_message(EXTENSION_NAME, peer, &status(0).rlp_bytes());
+        assert_eq!(Some(TestNetworkCall::Disconnect(peer)), network.pop_call(EXTENSION_NAME));
+        assert_eq!(0, extension.status().peers());
+
+        let other = NodeId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3486);
+        network.add_node(EXTENSION_NAME, other);
+        while network.pop_call(EXTENSION_NAME).is_some() {}
+        network.send_message(EXTENSION_NAME, other, &status(1).rlp_bytes());
+        assert_eq!(None, network.pop_call(EXTENSION_NAME));
+        assert_eq!(1, extension.status().peers());
+        assert_eq!(1, extension.peer_version(&other));
+    }
+
+    #[test]
+    fn status_tracks_peers_and_the_blocks_they_sent() {
+        let source = TestBlockChainClient::new();
+        source.add_blocks(20, 0);
+        let client = Arc::new(TestBlockChainClient::new());
+        let (extension, network, peer) = connected_extension(client);
+        let status = extension.status();
+        assert_eq!(1, status.peers());
+        assert_eq!(0, status.lag());
+
+        announce(&network, peer, &source, 20);
+        assert_eq!(0, status.lag(), "The orphan is not verified");
+
+        // The test client doesn't notify the imported blocks, so that the best block of the status stays.
+        announce(&network, peer, &source, 1);
+        assert_eq!(1, status.highest_block_number());
+        assert_eq!(1, status.lag());
+
+        extension.on_node_removed(&peer);
+        assert_eq!(0, status.peers());
+        assert_eq!(0, status.lag());
+    }
+
+    /// Builds a chain with 5 blocks on top of 5 common blocks, each of them has `score`.
+    fn client_on_fork(score: u64) -> TestBlockChainClient {
+        let client = TestBlockChainClient::new();
+        client.add_blocks(5, 0);
+        client.add_chain(BlockId::Latest, vec![TestBlock::default().score(score.into()); 5]);
+        assert_eq!(10, client.chain_info().best_block_number);
+        client
+    }
+
+    fn headers_requested_to_peer_on(source: &TestBlockChainClient) -> Vec<(BlockNumber, u64)> {
+        let (_, mut network, peer) = connected_extension(Arc::new(client_on_fork(2)));
+        let chain_info = source.chain_info();
+        let status = Message::Status {
+            version: PROTOCOL_VERSION,
+            total_score: chain_info.total_score,
+            best_hash: chain_info.best_block_hash,
+            genesis_hash: chain_info.genesis_hash,
+            capabilities: Capabilities::SERVE_HEADERS | Capabilities::SERVE_BODIES,
+        };
+        network.send_message(EXTENSION_NAME, peer, &status.rlp_bytes());
+        network.call_timeout(EXTENSION_NAME, SYNC_TIMER_TOKEN);
+        requested_headers(&mut network)
+    }
+
+    #[test]
+    fn equally_long_fork_is_synced_only_if_it_has_more_score() {
+        assert_eq!(vec![(10, 128)], headers_requested_to_peer_on(&client_on_fork(3)));
+        assert_eq!(Vec::<(BlockNumber, u64)>::new(), headers_requested_to_peer_on(&client_on_fork(1)));
+    }
+
+    #[test]
+    fn announced_blocks_of_equally_long_fork_with_more_score_become_the_best_chain() {
+        let source = client_on_fork(3);
+        let client = Arc::new(client_on_fork(2));
+        let (_, network, peer) = connected_extension(client.clone());
+
+        for number in 6..11 {
+            announce(&network, peer, &source, number);
+        }
+        assert_eq!(source.chain_info().best_block_hash, client.chain_info().best_block_hash);
+        assert_eq!(source.chain_info().total_score, client.chain_info().total_score);
+        assert_eq!(10, client.chain_info().best_block_number);
+    }
+}
@@ -14,13 +14,16 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ctypes::{H256, U256};
+use ccore::{BlockNumber, DecodeLimits};
+use ctypes::{Bytes, H256, U256};
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
+use super::Capabilities;
+
 mod request;
 mod response;
 
-pub use self::request::RequestMessage;
+pub use self::request::{HeaderStart, RequestMessage};
 pub use self::response::ResponseMessage;
 
 const MESSAGE_ID_STATUS: u8 = 0x01;
@@ -32,33 +35,65 @@ const MESSAGE_ID_GET_STATE_HEAD: u8 = 0x06;
 const MESSAGE_ID_STATE_HEAD: u8 = 0x07;
 const MESSAGE_ID_GET_STATE_CHUNK: u8 = 0x08;
 const MESSAGE_ID_STATE_CHUNK: u8 = 0x09;
+const MESSAGE_ID_NEW_BLOCK: u8 = 0x0a;
+const MESSAGE_ID_GET_SNAPSHOT_MANIFEST: u8 = 0x0b;
+const MESSAGE_ID_SNAPSHOT_MANIFEST: u8 = 0x0c;
+const MESSAGE_ID_NEW_BLOCK_HASHES: u8 = 0x0d;
+const MESSAGE_ID_GET_HEADERS_FROM: u8 = 0x0e;
+
+/// Maximum number of hashes in a single `NewBlockHashes` message.
+pub const MAX_NEW_BLOCK_HASHES: usize = 256;
 
 #[derive(Debug, PartialEq)]
 pub enum Message {
+    /// The status of a peer of the protocol version 0 carries neither the version nor the capabilities.
     Status {
+        /// The newest protocol version the peer speaks.
+        version: u64,
         total_score: U256,
         best_hash: H256,
         genesis_hash: H256,
+        /// `Capabilities::legacy()` for the protocol version 0.
+        capabilities: Capabilities,
     },
     Request(u64, RequestMessage),
     Response(u64, ResponseMessage),
+    /// Announces a new block. Sent to the peers of the protocol version 2 or later.
+    NewBlock {
+        total_score: U256,
+        /// The RLP of the block.
+        block: Bytes,
+    },
+    /// Announces the hashes and the numbers of new blocks. Sent to the peers of the protocol version 3 or later.
+    NewBlockHashes(Vec<(H256, BlockNumber)>),
 }
 
 impl Encodable for Message {
     fn rlp_append(&self, s: &mut RlpStream) {
         match self {
             Message::Status {
+                version,
                 total_score,
                 best_hash,
                 genesis_hash,
+                capabilities,
             } => {
                 s.begin_list(2);
                 s.append(&MESSAGE_ID_STATUS);
 
-                s.begin_list(3);
-                s.append(total_score);
-                s.append(best_hash);
-                s.append(genesis_hash);
+                if *version == 0 {
+                    s.begin_list(3);
+                    s.append(total_score);
+                    s.append(best_hash);
+                    s.append(genesis_hash);
+                } else {
+                    s.begin_list(5);
+                    s.append(version);
+                    s.append(total_score);
+                    s.append(best_hash);
+                    s.append(genesis_hash);
+                    s.append(capabilities);
+                }
             }
             Message::Request(request_id, request) => {
                 s.begin_list(3);
@@ -72,10 +107,62 @@ impl Encodable for Message {
                 s.append(response_id);
                 s.append(response);
             }
+            Message::NewBlock {
+                total_score,
+                block,
+            } => {
+                s.begin_list(2);
+                s.append(&MESSAGE_ID_NEW_BLOCK);
+                s.begin_list(2);
+                s.append(total_score);
+                s.append_raw(block, 1);
+            }
+            Message::NewBlockHashes(hashes) => {
+                s.begin_list(2);
+                s.append(&MESSAGE_ID_NEW_BLOCK_HASHES);
+                s.begin_list(hashes.len());
+                for (hash, number) in hashes {
+                    s.begin_list(2);
+                    s.append(hash);
+                    s.append(number);
+                }
+            }
         }
     }
 }
 
+impl Message {
+    pub fn id(&self) -> u8 {
+        match self {
+            Message::Status {
+                ..
+            } => MESSAGE_ID_STATUS,
+            Message::Request(_, request) => request.message_id(),
+            Message::Response(_, response) => response.message_id(),
+            Message::NewBlock {
+                ..
+            } => MESSAGE_ID_NEW_BLOCK,
+            Message::NewBlockHashes(..) => MESSAGE_ID_NEW_BLOCK_HASHES,
+        }
+    }
+
+    /// Decodes a message, rejecting responses and announced blocks whose headers or bodies exceed `limits`,
+    /// and announcements of more than `MAX_NEW_BLOCK_HASHES` hashes.
+    pub fn decode_with_limits(rlp: &UntrustedRlp, limits: &DecodeLimits) -> Result<Self, DecoderError> {
+        let id = rlp.val_at(0)?;
+        if id == MESSAGE_ID_HEADERS || id == MESSAGE_ID_BODIES {
+            ResponseMessage::check_limits(id, &rlp.at(2)?, limits)?;
+        }
+        if id == MESSAGE_ID_NEW_BLOCK {
+            limits.check_block(&rlp.at(1)?.at(1)?)?;
+        }
+        if id == MESSAGE_ID_NEW_BLOCK_HASHES && rlp.at(1)?.item_count()? > MAX_NEW_BLOCK_HASHES {
+            return Err(DecoderError::Custom("Too many hashes are announced"))
+        }
+        Self::decode(rlp)
+    }
+}
+
 impl Decodable for Message {
     fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
         let id = rlp.val_at(0)?;
@@ -85,15 +172,51 @@ impl Decodable for Message {
             }
             let message = rlp.at(1)?;
 
-            if message.item_count()? != 3 {
+            match message.item_count()? {
+                3 => Ok(Message::Status {
+                    version: 0,
+                    total_score: message.val_at(0)?,
+                    best_hash: message.val_at(1)?,
+                    genesis_hash: message.val_at(2)?,
+                    capabilities: Capabilities::legacy(),
+                }),
+                5 => Ok(Message::Status {
+                    version: message.val_at(0)?,
+                    total_score: message.val_at(1)?,
+                    best_hash: message.val_at(2)?,
+                    genesis_hash: message.val_at(3)?,
+                    capabilities: message.val_at(4)?,
+                }),
+                _ => Err(DecoderError::RlpIncorrectListLen),
+            }
+        } else if id == MESSAGE_ID_NEW_BLOCK {
+            if rlp.item_count()? != 2 {
+                return Err(DecoderError::RlpIncorrectListLen)
+            }
+            let message = rlp.at(1)?;
+            if message.item_count()? != 2 {
                 return Err(DecoderError::RlpIncorrectListLen)
             }
 
-            Ok(Message::Status {
+            Ok(Message::NewBlock {
                 total_score: message.val_at(0)?,
-                best_hash: message.val_at(1)?,
-                genesis_hash: message.val_at(2)?,
+                block: message.at(1)?.as_raw().to_vec(),
             })
+        } else if id == MESSAGE_ID_NEW_BLOCK_HASHES {
+            if rlp.item_count()? != 2 {
+                return Err(DecoderError::RlpIncorrectListLen)
+            }
+            let hashes = rlp
+                .at(1)?
+                .iter()
+                .map(|item| {
+                    if item.item_count()? != 2 {
+                        return Err(DecoderError::RlpIncorrectListLen)
+                    }
+                    Ok((item.val_at(0)?, item.val_at(1)?))
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Message::NewBlockHashes(hashes))
         } else {
             if rlp.item_count()? != 3 {
                 return Err(DecoderError::RlpIncorrectListLen)
@@ -102,10 +225,18 @@ impl Decodable for Message {
             let message = rlp.at(2)?;
             match id {
                 MESSAGE_ID_GET_HEADERS
+                | MESSAGE_ID_GET_HEADERS_FROM
                 | MESSAGE_ID_GET_BODIES
                 | MESSAGE_ID_GET_STATE_HEAD
-                | MESSAGE_ID_GET_STATE_CHUNK => Ok(Message::Request(request_id, RequestMessage::decode(id, &message)?)),
-                MESSAGE_ID_HEADERS | MESSAGE_ID_BODIES | MESSAGE_ID_STATE_HEAD | MESSAGE_ID_STATE_CHUNK => {
+                | MESSAGE_ID_GET_STATE_CHUNK
+                | MESSAGE_ID_GET_SNAPSHOT_MANIFEST => {
+                    Ok(Message::Request(request_id, RequestMessage::decode(id, &message)?))
+                }
+                MESSAGE_ID_HEADERS
+                | MESSAGE_ID_BODIES
+                | MESSAGE_ID_STATE_HEAD
+                | MESSAGE_ID_STATE_CHUNK
+                | MESSAGE_ID_SNAPSHOT_MANIFEST => {
                     Ok(Message::Response(request_id, ResponseMessage::decode(id, &message)?))
                 }
                 _ => Err(DecoderError::Custom("Unknown message id detected")),
@@ -116,18 +247,107 @@ impl Decodable for Message {
 
 #[cfg(test)]
 mod tests {
+    use ccore::{DecodeLimits, Header};
     use ctypes::{H256, U256};
-    use rlp::Encodable;
+    use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 
-    use super::Message;
+    use super::super::Capabilities;
+    use super::{Message, MAX_NEW_BLOCK_HASHES};
 
     #[test]
     fn test_status_message_rlp() {
         let message = Message::Status {
-            total_score: U256::default(),
-            best_hash: H256::default(),
-            genesis_hash: H256::default(),
+            version: 4,
+            total_score: U256::from(100),
+            best_hash: H256::from(1),
+            genesis_hash: H256::from(2),
+            capabilities: Capabilities::SERVE_HEADERS | Capabilities::SERVE_BODIES,
         };
+        let mut expected = RlpStream::new_list(2);
+        expected.append(&1u8);
+        expected.begin_list(5);
+        expected.append(&4u64);
+        expected.append(&U256::from(100));
+        expected.append(&H256::from(1));
+        expected.append(&H256::from(2));
+        expected.append(&(Capabilities::SERVE_HEADERS | Capabilities::SERVE_BODIES));
+        assert_eq!(expected.out(), message.rlp_bytes().into_vec());
         assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
     }
+
+    #[test]
+    fn legacy_status_message_is_decoded_as_the_version_0() {
+        let mut legacy = RlpStream::new_list(2);
+        legacy.append(&1u8);
+        legacy.begin_list(3);
+        legacy.append(&U256::from(100));
+        legacy.append(&H256::from(1));
+        legacy.append(&H256::from(2));
+        let legacy = legacy.out();
+
+        let message = Message::Status {
+            version: 0,
+            total_score: U256::from(100),
+            best_hash: H256::from(1),
+            genesis_hash: H256::from(2),
+            capabilities: Capabilities::legacy(),
+        };
+        assert_eq!(message, ::rlp::decode(&legacy));
+        assert_eq!(legacy, message.rlp_bytes().into_vec());
+    }
+
+    #[test]
+    fn status_message_of_another_length_is_rejected() {
+        let mut status = RlpStream::new_list(2);
+        status.append(&1u8);
+        status.begin_list(4);
+        status.append(&U256::from(100));
+        status.append(&H256::from(1));
+        status.append(&H256::from(2));
+        status.append(&Capabilities::SERVE_HEADERS);
+        let status = status.out();
+        assert_eq!(Err(DecoderError::RlpIncorrectListLen), Message::decode(&UntrustedRlp::new(&status)));
+    }
+
+    #[test]
+    fn test_new_block_message_rlp() {
+        let mut block = RlpStream::new_list(2);
+        block.append(&Header::default());
+        block.begin_list(0);
+        let message = Message::NewBlock {
+            total_score: U256::from(100),
+            block: block.out(),
+        };
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn test_new_block_hashes_message_rlp() {
+        let message = Message::NewBlockHashes(vec![(H256::from(1), 1), (H256::from(2), 2)]);
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+
+        let message = Message::NewBlockHashes(Vec::new());
+        assert_eq!(message, ::rlp::decode(message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn too_many_announced_hashes_are_rejected() {
+        let limits = DecodeLimits {
+            max_extra_data_size: 32,
+            max_body_size: 1024,
+            shard_count: 1,
+        };
+        let hashes: Vec<_> = (0..MAX_NEW_BLOCK_HASHES as u64).map(|number| (H256::from(number), number)).collect();
+        let message = Message::NewBlockHashes(hashes.clone());
+        let bytes = message.rlp_bytes();
+        assert_eq!(Ok(message), Message::decode_with_limits(&UntrustedRlp::new(&bytes), &limits));
+
+        let mut hashes = hashes;
+        hashes.push((H256::zero(), 0));
+        let bytes = Message::NewBlockHashes(hashes).rlp_bytes();
+        assert_eq!(
+            Err(DecoderError::Custom("Too many hashes are announced")),
+            Message::decode_with_limits(&UntrustedRlp::new(&bytes), &limits)
+        );
+    }
 }
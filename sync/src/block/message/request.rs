@@ -16,7 +16,36 @@
 
 use ccore::BlockNumber;
 use ctypes::H256;
-use rlp::{DecoderError, Encodable, RlpStream, UntrustedRlp};
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+use super::super::Capabilities;
+
+/// The first header of a `HeadersFrom` request.
+/// It is encoded as a single item, which is a hash if it is 32 bytes long and a number otherwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaderStart {
+    Number(BlockNumber),
+    Hash(H256),
+}
+
+impl Encodable for HeaderStart {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            HeaderStart::Number(number) => s.append(number),
+            HeaderStart::Hash(hash) => s.append(hash),
+        };
+    }
+}
+
+impl Decodable for HeaderStart {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.size() == 32 {
+            Ok(HeaderStart::Hash(rlp.as_val()?))
+        } else {
+            Ok(HeaderStart::Number(rlp.as_val()?))
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum RequestMessage {
@@ -24,12 +53,24 @@ pub enum RequestMessage {
         start_number: BlockNumber,
         max_count: u64,
     },
+    /// Requests the canonical headers from `start`, skipping `skip` headers between each of them,
+    /// toward the best block, or toward the genesis if `reverse` is true.
+    /// Sent to the peers of the protocol version 4 or later.
+    HeadersFrom {
+        start: HeaderStart,
+        max_count: u64,
+        skip: u64,
+        reverse: bool,
+    },
     Bodies(Vec<H256>),
     StateHead(H256),
+    /// Requests a chunk of the snapshot taken at `block_hash`, by its hash in the manifest.
     StateChunk {
         block_hash: H256,
-        tree_root: H256,
+        chunk_hash: H256,
     },
+    /// Requests the manifest of the newest snapshot.
+    SnapshotManifest,
 }
 
 impl Encodable for RequestMessage {
@@ -43,6 +84,18 @@ impl Encodable for RequestMessage {
                 s.append(start_number);
                 s.append(max_count);
             }
+            RequestMessage::HeadersFrom {
+                start,
+                max_count,
+                skip,
+                reverse,
+            } => {
+                s.begin_list(4);
+                s.append(start);
+                s.append(max_count);
+                s.append(skip);
+                s.append(reverse);
+            }
             RequestMessage::Bodies(hashes) => {
                 s.append_list(hashes);
             }
@@ -52,27 +105,52 @@ impl Encodable for RequestMessage {
             }
             RequestMessage::StateChunk {
                 block_hash,
-                tree_root,
+                chunk_hash,
             } => {
                 s.begin_list(2);
                 s.append(block_hash);
-                s.append(tree_root);
+                s.append(chunk_hash);
+            }
+            RequestMessage::SnapshotManifest => {
+                s.begin_list(0);
             }
         };
     }
 }
 
 impl RequestMessage {
+    /// Capabilities which a peer should have to serve this request.
+    pub fn required_capabilities(&self) -> Capabilities {
+        match self {
+            RequestMessage::Headers {
+                ..
+            } => Capabilities::SERVE_HEADERS,
+            RequestMessage::HeadersFrom {
+                ..
+            } => Capabilities::SERVE_HEADERS,
+            RequestMessage::Bodies(..) => Capabilities::SERVE_BODIES,
+            RequestMessage::StateHead(..) => Capabilities::SERVE_STATE_CHUNKS,
+            RequestMessage::StateChunk {
+                ..
+            } => Capabilities::SERVE_STATE_CHUNKS,
+            RequestMessage::SnapshotManifest => Capabilities::SERVE_SNAPSHOT_MANIFESTS,
+        }
+    }
+
     pub fn message_id(&self) -> u8 {
         match self {
             RequestMessage::Headers {
                 ..
             } => super::MESSAGE_ID_GET_HEADERS,
+            RequestMessage::HeadersFrom {
+                ..
+            } => super::MESSAGE_ID_GET_HEADERS_FROM,
             RequestMessage::Bodies(..) => super::MESSAGE_ID_GET_BODIES,
             RequestMessage::StateHead(..) => super::MESSAGE_ID_GET_STATE_HEAD,
             RequestMessage::StateChunk {
                 ..
             } => super::MESSAGE_ID_GET_STATE_CHUNK,
+            RequestMessage::SnapshotManifest => super::MESSAGE_ID_GET_SNAPSHOT_MANIFEST,
         }
     }
 
@@ -87,6 +165,17 @@ impl RequestMessage {
                     max_count: rlp.val_at(1)?,
                 }
             }
+            super::MESSAGE_ID_GET_HEADERS_FROM => {
+                if rlp.item_count()? != 4 {
+                    return Err(DecoderError::RlpIncorrectListLen)
+                }
+                RequestMessage::HeadersFrom {
+                    start: rlp.val_at(0)?,
+                    max_count: rlp.val_at(1)?,
+                    skip: rlp.val_at(2)?,
+                    reverse: rlp.val_at(3)?,
+                }
+            }
             super::MESSAGE_ID_GET_BODIES => RequestMessage::Bodies(rlp.as_list()?),
             super::MESSAGE_ID_GET_STATE_HEAD => {
                 if rlp.item_count()? != 1 {
@@ -100,8 +189,14 @@ impl RequestMessage {
                 }
                 RequestMessage::StateChunk {
                     block_hash: rlp.val_at(0)?,
-                    tree_root: rlp.val_at(1)?,
+                    chunk_hash: rlp.val_at(1)?,
+                }
+            }
+            super::MESSAGE_ID_GET_SNAPSHOT_MANIFEST => {
+                if rlp.item_count()? != 0 {
+                    return Err(DecoderError::RlpIncorrectListLen)
                 }
+                RequestMessage::SnapshotManifest
             }
             _ => return Err(DecoderError::Custom("Unknown message id detected")),
         };
@@ -115,7 +210,7 @@ mod tests {
     use ctypes::H256;
     use rlp::{Encodable, UntrustedRlp};
 
-    use super::RequestMessage;
+    use super::{HeaderStart, RequestMessage};
 
     pub fn decode_bytes(id: u8, bytes: &[u8]) -> RequestMessage {
         let rlp = UntrustedRlp::new(bytes);
@@ -131,6 +226,37 @@ mod tests {
         assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
     }
 
+    #[test]
+    fn test_request_headers_from_number_message_rlp() {
+        let message = RequestMessage::HeadersFrom {
+            start: HeaderStart::Number(100),
+            max_count: 100,
+            skip: 3,
+            reverse: true,
+        };
+        assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn test_request_headers_from_hash_message_rlp() {
+        let message = RequestMessage::HeadersFrom {
+            start: HeaderStart::Hash(H256::from(100)),
+            max_count: 100,
+            skip: 0,
+            reverse: false,
+        };
+        assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
+    }
+
+    #[test]
+    fn header_start_is_a_hash_only_if_it_is_32_bytes_long() {
+        let number = HeaderStart::Number(::std::u64::MAX);
+        assert_eq!(number, ::rlp::decode(&::rlp::encode(&number)));
+        let hash = HeaderStart::Hash(H256::zero());
+        assert_eq!(hash, ::rlp::decode(&::rlp::encode(&hash)));
+        assert_eq!(HeaderStart::Number(0), ::rlp::decode(&::rlp::encode(&0u64)));
+    }
+
     #[test]
     fn test_request_bodies_message_rlp() {
         let message = RequestMessage::Bodies(vec![H256::default()]);
@@ -147,8 +273,14 @@ mod tests {
     fn test_request_state_chunk_message_rlp() {
         let message = RequestMessage::StateChunk {
             block_hash: H256::default(),
-            tree_root: H256::default(),
+            chunk_hash: H256::default(),
         };
         assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
     }
+
+    #[test]
+    fn test_request_snapshot_manifest_message_rlp() {
+        let message = RequestMessage::SnapshotManifest;
+        assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
+    }
 }
@@ -14,15 +14,19 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use ccore::{Header, UnverifiedParcel};
+use ccore::{DecodeLimits, Header, UnverifiedParcel};
 use rlp::{DecoderError, Encodable, RlpStream, UntrustedRlp};
 
+use super::super::super::snapshot::Manifest;
+
 #[derive(Debug, PartialEq)]
 pub enum ResponseMessage {
     Headers(Vec<Header>),
     Bodies(Vec<Vec<UnverifiedParcel>>),
     StateHead(Vec<u8>),
     StateChunk(Vec<u8>),
+    /// `None` if the peer has no snapshot.
+    SnapshotManifest(Option<Manifest>),
 }
 
 impl Encodable for ResponseMessage {
@@ -45,6 +49,15 @@ impl Encodable for ResponseMessage {
                 s.begin_list(1);
                 s.append(bytes);
             }
+            ResponseMessage::SnapshotManifest(manifest) => match manifest {
+                Some(manifest) => {
+                    s.begin_list(1);
+                    s.append(manifest);
+                }
+                None => {
+                    s.begin_list(0);
+                }
+            },
         };
     }
 }
@@ -60,6 +73,7 @@ impl ResponseMessage {
             ResponseMessage::StateChunk {
                 ..
             } => super::MESSAGE_ID_STATE_CHUNK,
+            ResponseMessage::SnapshotManifest(..) => super::MESSAGE_ID_SNAPSHOT_MANIFEST,
         }
     }
 
@@ -85,18 +99,42 @@ impl ResponseMessage {
                 }
                 ResponseMessage::StateChunk(rlp.val_at(0)?)
             }
+            super::MESSAGE_ID_SNAPSHOT_MANIFEST => match rlp.item_count()? {
+                0 => ResponseMessage::SnapshotManifest(None),
+                1 => ResponseMessage::SnapshotManifest(Some(rlp.val_at(0)?)),
+                _ => return Err(DecoderError::RlpIncorrectListLen),
+            },
             _ => return Err(DecoderError::Custom("Unknown message id detected")),
         };
 
         Ok(message)
     }
+
+    /// Rejects oversized headers and bodies before they are decoded.
+    pub fn check_limits(id: u8, rlp: &UntrustedRlp, limits: &DecodeLimits) -> Result<(), DecoderError> {
+        match id {
+            super::MESSAGE_ID_HEADERS => limits.check_headers(rlp),
+            super::MESSAGE_ID_BODIES => {
+                for body in rlp.iter() {
+                    limits.check_body(&body)?;
+                    for parcel in body.iter() {
+                        limits.check_parcel(&parcel)?;
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use ccore::Header;
-    use rlp::{Encodable, UntrustedRlp};
+    use ccore::{DecodeLimits, Header};
+    use ctypes::H256;
+    use rlp::{DecoderError, Encodable, UntrustedRlp};
 
+    use super::super::super::super::snapshot::Manifest;
     use super::ResponseMessage;
 
     pub fn decode_bytes(id: u8, bytes: &[u8]) -> ResponseMessage {
@@ -121,6 +159,25 @@ mod tests {
         assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
     }
 
+    #[test]
+    fn test_headers_with_large_extra_data_exceed_limits() {
+        let limits = DecodeLimits {
+            max_extra_data_size: 32,
+            max_body_size: 1024,
+            shard_count: 1,
+        };
+        let mut header = Header::default();
+        header.set_number(1);
+        header.set_extra_data(vec![0; 33]);
+
+        let message = ResponseMessage::Headers(vec![header]);
+        let bytes = message.rlp_bytes();
+        assert_eq!(
+            Err(DecoderError::Custom("Header exceeds the maximum extra data size")),
+            ResponseMessage::check_limits(message.message_id(), &UntrustedRlp::new(&bytes), &limits)
+        );
+    }
+
     #[test]
     fn test_state_head_message_rlp() {
         let message = ResponseMessage::StateHead(vec![]);
@@ -132,4 +189,18 @@ mod tests {
         let message = ResponseMessage::StateChunk(vec![]);
         assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
     }
+
+    #[test]
+    fn test_snapshot_manifest_message_rlp() {
+        let message = ResponseMessage::SnapshotManifest(Some(Manifest {
+            block_hash: H256::from(1),
+            block_number: 16384,
+            state_root: H256::from(2),
+            chunk_hashes: vec![H256::from(3)],
+        }));
+        assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
+
+        let message = ResponseMessage::SnapshotManifest(None);
+        assert_eq!(message, decode_bytes(message.message_id(), message.rlp_bytes().as_ref()));
+    }
 }
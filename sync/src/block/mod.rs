@@ -14,8 +14,14 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod capabilities;
 mod downloader;
 mod extension;
 mod message;
+mod orphan_pool;
+mod status;
 
+pub use self::capabilities::Capabilities;
+pub use self::downloader::HeaderBatchStatus;
 pub use self::extension::Extension as BlockSyncExtension;
+pub use self::status::SyncStatus;
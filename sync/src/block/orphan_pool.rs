@@ -0,0 +1,178 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use ctypes::{Bytes, H256, U256};
+
+/// A block announced by a peer whose parent is not known yet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Orphan {
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub total_score: U256,
+    /// The RLP of the block.
+    pub block: Bytes,
+    received_at: Instant,
+}
+
+/// Holds orphan blocks until their parents arrive.
+///
+/// The pool is bounded by the number of orphans and by their age.
+/// When it's full, the orphan with the lowest total score is evicted, the oldest one first.
+pub struct OrphanPool {
+    orphans: HashMap<H256, Orphan>,
+    max_count: usize,
+    max_age: Duration,
+}
+
+impl OrphanPool {
+    pub fn new(max_count: usize, max_age: Duration) -> Self {
+        Self {
+            orphans: HashMap::new(),
+            max_count,
+            max_age,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.orphans.contains_key(hash)
+    }
+
+    /// Inserts an orphan, evicting another one if the pool is full.
+    /// Returns false if the orphan is not kept because it's worse than every orphan in the full pool.
+    pub fn insert(&mut self, hash: H256, parent_hash: H256, total_score: U256, block: Bytes, now: Instant) -> bool {
+        if self.orphans.contains_key(&hash) {
+            return true
+        }
+        if self.orphans.len() >= self.max_count {
+            self.remove_expired(now);
+        }
+        if self.orphans.len() >= self.max_count {
+            let victim = {
+                let worst = self.orphans
+                    .values()
+                    .min_by(|a, b| a.total_score.cmp(&b.total_score).then(a.received_at.cmp(&b.received_at)));
+                match worst {
+                    Some(worst) if worst.total_score <= total_score => worst.hash,
+                    _ => return false,
+                }
+            };
+            self.orphans.remove(&victim);
+        }
+        self.orphans.insert(
+            hash,
+            Orphan {
+                hash,
+                parent_hash,
+                total_score,
+                block,
+                received_at: now,
+            },
+        );
+        true
+    }
+
+    /// Removes the orphans older than the maximum age and returns the number of removed orphans.
+    pub fn remove_expired(&mut self, now: Instant) -> usize {
+        let max_age = self.max_age;
+        let before = self.orphans.len();
+        self.orphans.retain(|_, orphan| now.duration_since(orphan.received_at) <= max_age);
+        before - self.orphans.len()
+    }
+
+    /// Removes and returns the descendants of the given block, parents before their children.
+    pub fn take_descendants(&mut self, hash: &H256) -> Vec<Orphan> {
+        let mut descendants = Vec::new();
+        let mut parents = vec![*hash];
+        while let Some(parent) = parents.pop() {
+            let children: Vec<H256> =
+                self.orphans.values().filter(|orphan| orphan.parent_hash == parent).map(|orphan| orphan.hash).collect();
+            for child in children {
+                let orphan = self.orphans.remove(&child).expect("The child is in the pool");
+                parents.push(orphan.hash);
+                descendants.push(orphan);
+            }
+        }
+        descendants
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_AGE: u64 = 60;
+
+    fn insert(pool: &mut OrphanPool, hash: u64, parent: u64, score: u64, now: Instant) -> bool {
+        pool.insert(H256::from(hash), H256::from(parent), U256::from(score), vec![], now)
+    }
+
+    #[test]
+    fn descendants_are_taken_parents_first() {
+        let now = Instant::now();
+        let mut pool = OrphanPool::new(10, Duration::from_secs(MAX_AGE));
+        insert(&mut pool, 3, 2, 3, now);
+        insert(&mut pool, 2, 1, 2, now);
+        insert(&mut pool, 4, 3, 4, now);
+        insert(&mut pool, 10, 9, 10, now);
+
+        let descendants = pool.take_descendants(&H256::from(1));
+        let hashes: Vec<_> = descendants.iter().map(|orphan| orphan.hash).collect();
+        assert_eq!(vec![H256::from(2), H256::from(3), H256::from(4)], hashes);
+        assert_eq!(1, pool.len());
+        assert!(pool.contains(&H256::from(10)));
+        assert!(pool.take_descendants(&H256::from(1)).is_empty());
+    }
+
+    #[test]
+    fn full_pool_evicts_the_lowest_score_and_then_the_oldest() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+        let mut pool = OrphanPool::new(3, Duration::from_secs(MAX_AGE));
+        insert(&mut pool, 1, 100, 5, now);
+        insert(&mut pool, 2, 100, 5, later);
+        insert(&mut pool, 3, 100, 7, now);
+
+        assert!(insert(&mut pool, 4, 100, 6, later));
+        assert!(!pool.contains(&H256::from(1)), "The oldest of the lowest score is evicted");
+        assert!(pool.contains(&H256::from(2)));
+
+        assert!(!insert(&mut pool, 5, 100, 1, later), "An orphan worse than all the others is not kept");
+        assert_eq!(3, pool.len());
+    }
+
+    #[test]
+    fn expired_orphans_are_evicted_first() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(MAX_AGE + 1);
+        let mut pool = OrphanPool::new(2, Duration::from_secs(MAX_AGE));
+        insert(&mut pool, 1, 100, 10, now);
+        insert(&mut pool, 2, 100, 1, later);
+
+        assert!(insert(&mut pool, 3, 100, 1, later));
+        assert!(!pool.contains(&H256::from(1)), "The expired orphan is evicted even though its score is the highest");
+        assert!(pool.contains(&H256::from(2)));
+        assert!(pool.contains(&H256::from(3)));
+
+        assert_eq!(2, pool.remove_expired(later + Duration::from_secs(MAX_AGE + 1)));
+    }
+}
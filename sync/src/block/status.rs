@@ -0,0 +1,161 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ccore::BlockNumber;
+use cnetwork::NodeId;
+use parking_lot::Mutex;
+
+use super::downloader::HeaderBatchStatus;
+
+/// A snapshot of the sync progress which can be read without taking any lock.
+pub struct SyncStatus {
+    peers: AtomicUsize,
+    best_block_number: AtomicUsize,
+    highest_block_number: AtomicUsize,
+    /// The highest number of the blocks and headers which each peer sent and the client accepted.
+    /// Only the writers lock it.
+    peer_block_numbers: Mutex<HashMap<NodeId, BlockNumber>>,
+    pending_header_batches: AtomicUsize,
+    in_flight_header_batches: AtomicUsize,
+    complete_header_batches: AtomicUsize,
+}
+
+impl SyncStatus {
+    pub fn new(best_block_number: BlockNumber) -> Self {
+        Self {
+            peers: AtomicUsize::new(0),
+            best_block_number: AtomicUsize::new(best_block_number as usize),
+            highest_block_number: AtomicUsize::new(best_block_number as usize),
+            peer_block_numbers: Mutex::new(HashMap::new()),
+            pending_header_batches: AtomicUsize::new(0),
+            in_flight_header_batches: AtomicUsize::new(0),
+            complete_header_batches: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of peers which are synchronizing with us.
+    pub fn peers(&self) -> usize {
+        self.peers.load(Ordering::SeqCst)
+    }
+
+    /// The number of our best block.
+    pub fn best_block_number(&self) -> BlockNumber {
+        self.best_block_number.load(Ordering::SeqCst) as BlockNumber
+    }
+
+    /// The highest block number of the connected peers, or the number of our best block if it is higher.
+    /// The number of a peer counts only after the client accepted its header.
+    pub fn highest_block_number(&self) -> BlockNumber {
+        self.highest_block_number.load(Ordering::SeqCst) as BlockNumber
+    }
+
+    /// The number of blocks we are behind the highest block of the peers.
+    pub fn lag(&self) -> u64 {
+        self.highest_block_number().saturating_sub(self.best_block_number())
+    }
+
+    /// The progress of the headers which are downloaded in batches while we are far behind.
+    pub fn header_batches(&self) -> HeaderBatchStatus {
+        HeaderBatchStatus {
+            pending: self.pending_header_batches.load(Ordering::SeqCst),
+            in_flight: self.in_flight_header_batches.load(Ordering::SeqCst),
+            complete: self.complete_header_batches.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn set_header_batches(&self, status: HeaderBatchStatus) {
+        self.pending_header_batches.store(status.pending, Ordering::SeqCst);
+        self.in_flight_header_batches.store(status.in_flight, Ordering::SeqCst);
+        self.complete_header_batches.store(status.complete, Ordering::SeqCst);
+    }
+
+    pub fn set_peers(&self, peers: usize) {
+        self.peers.store(peers, Ordering::SeqCst);
+    }
+
+    pub fn set_best_block_number(&self, number: BlockNumber) {
+        let peer_block_numbers = self.peer_block_numbers.lock();
+        self.best_block_number.store(number as usize, Ordering::SeqCst);
+        self.update_highest(&peer_block_numbers);
+    }
+
+    /// Raises the block number of the peer to `number`.
+    /// It must be the number of a header which the peer sent and the client accepted.
+    pub fn note_block_number(&self, peer: &NodeId, number: BlockNumber) {
+        let mut peer_block_numbers = self.peer_block_numbers.lock();
+        let highest = peer_block_numbers.entry(*peer).or_insert(number);
+        if *highest < number {
+            *highest = number;
+        }
+        self.update_highest(&peer_block_numbers);
+    }
+
+    /// Stops counting the block number of a disconnected peer.
+    pub fn remove_peer(&self, peer: &NodeId) {
+        let mut peer_block_numbers = self.peer_block_numbers.lock();
+        peer_block_numbers.remove(peer);
+        self.update_highest(&peer_block_numbers);
+    }
+
+    fn update_highest(&self, peer_block_numbers: &HashMap<NodeId, BlockNumber>) {
+        let best = self.best_block_number();
+        let highest = peer_block_numbers.values().cloned().fold(best, ::std::cmp::max);
+        self.highest_block_number.store(highest as usize, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    fn peer(port: u16) -> NodeId {
+        NodeId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port)
+    }
+
+    #[test]
+    fn lag_is_measured_from_the_highest_block_of_the_peers() {
+        let status = SyncStatus::new(10);
+        assert_eq!(0, status.lag());
+
+        status.note_block_number(&peer(1), 25);
+        status.note_block_number(&peer(1), 20);
+        status.note_block_number(&peer(2), 22);
+        assert_eq!(25, status.highest_block_number());
+        assert_eq!(15, status.lag());
+
+        status.set_best_block_number(25);
+        assert_eq!(0, status.lag());
+    }
+
+    #[test]
+    fn disconnected_peer_is_not_counted() {
+        let status = SyncStatus::new(10);
+        status.note_block_number(&peer(1), 1_000_000);
+        status.note_block_number(&peer(2), 22);
+        assert_eq!(1_000_000, status.highest_block_number());
+
+        status.remove_peer(&peer(1));
+        assert_eq!(22, status.highest_block_number());
+        status.remove_peer(&peer(2));
+        assert_eq!(10, status.highest_block_number());
+        assert_eq!(0, status.lag());
+    }
+}
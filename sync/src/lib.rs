@@ -17,6 +17,7 @@
 extern crate parking_lot;
 
 extern crate codechain_core as ccore;
+extern crate codechain_crypto as ccrypto;
 extern crate codechain_merkle as cmerkle;
 #[macro_use]
 extern crate codechain_logger as clogger;
@@ -29,6 +30,7 @@ extern crate log;
 extern crate patricia_trie as trie;
 extern crate rand;
 extern crate rlp;
+extern crate rlp_compress;
 extern crate time;
 extern crate triehash;
 
@@ -36,9 +38,15 @@ mod block;
 mod parcel;
 mod snapshot;
 
-pub use self::block::BlockSyncExtension;
-pub use self::parcel::ParcelSyncExtension;
-pub use self::snapshot::SnapshotService;
+pub use self::block::{BlockSyncExtension, Capabilities as BlockSyncCapabilities, HeaderBatchStatus, SyncStatus};
+pub use self::parcel::{ParcelSyncExtension, RelayPolicy as ParcelRelayPolicy};
+pub use self::snapshot::{LatestManifest as LatestSnapshotManifest, SnapshotError, SnapshotRestorer, SnapshotService};
 
 #[cfg(test)]
 extern crate codechain_keys as ckeys;
+#[cfg(test)]
+extern crate journaldb;
+#[cfg(test)]
+extern crate kvdb_memorydb;
+#[cfg(test)]
+extern crate tempdir;
@@ -25,11 +25,15 @@ use rlp::{Encodable, UntrustedRlp};
 use time::Duration;
 
 use super::message::Message;
+use super::relay_policy::RelayPolicy;
 
 const EXTENSION_NAME: &'static str = "parcel-propagation";
 const BROADCAST_TIMER_TOKEN: TimerToken = 0;
 const BROADCAST_TIMER_INTERVAL: i64 = 1000;
 const MAX_HISTORY_SIZE: usize = 100;
+/// The number of parcels with the highest priority considered for a broadcast.
+const MAX_BROADCAST_PARCELS: usize = 1_000;
+const MAX_BROADCAST_BYTES: usize = 1 << 20;
 
 struct Peer {
     history_set: HashSet<H256>,
@@ -63,14 +67,20 @@ pub struct Extension {
     peers: RwLock<HashMap<NodeId, Peer>>,
     client: Arc<BlockChainClient>,
     api: Mutex<Option<Arc<Api>>>,
+    relay_policy: RelayPolicy,
 }
 
 impl Extension {
     pub fn new(client: Arc<BlockChainClient>) -> Arc<Self> {
+        Self::with_relay_policy(client, RelayPolicy::default())
+    }
+
+    pub fn with_relay_policy(client: Arc<BlockChainClient>, relay_policy: RelayPolicy) -> Arc<Self> {
         Arc::new(Self {
             peers: RwLock::new(HashMap::new()),
             client,
             api: Mutex::new(None),
+            relay_policy,
         })
     }
 }
@@ -136,7 +146,13 @@ impl Extension {
     }
 
     fn random_broadcast(&self) {
-        let parcels = self.client.ready_parcels();
+        let parcels = match self.relay_policy {
+            RelayPolicy::None => return,
+            RelayPolicy::LocalOnly => {
+                self.client.ready_local_parcels_with_limit(MAX_BROADCAST_PARCELS, MAX_BROADCAST_BYTES)
+            }
+            RelayPolicy::All => self.client.ready_parcels_with_limit(MAX_BROADCAST_PARCELS, MAX_BROADCAST_BYTES),
+        };
         for (token, peer) in self.peers.write().iter_mut() {
             let unsent: Vec<_> = parcels
                 .iter()
@@ -150,3 +166,109 @@ impl Extension {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use ccore::TestBlockChainClient;
+    use cnetwork::{TestNetworkCall, TestNetworkClient};
+
+    use super::*;
+
+    struct RelayTest {
+        client: Arc<TestBlockChainClient>,
+        network: TestNetworkClient,
+        peer: NodeId,
+        local: H256,
+        external: H256,
+    }
+
+    impl RelayTest {
+        fn new(relay_policy: RelayPolicy) -> Self {
+            let client = Arc::new(TestBlockChainClient::new());
+            let local = client.insert_own_parcel_to_pool();
+            let external = client.insert_parcel_to_pool();
+
+            let extension = Extension::with_relay_policy(client.clone(), relay_policy);
+            let mut network = TestNetworkClient::new();
+            network.register_extension(extension);
+            let peer = NodeId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3485);
+            network.add_node(EXTENSION_NAME, peer);
+
+            Self {
+                client,
+                network,
+                peer,
+                local,
+                external,
+            }
+        }
+
+        /// Returns the hashes of the parcels sent by a broadcast.
+        fn broadcast(&self) -> Vec<H256> {
+            self.network.call_timeout(EXTENSION_NAME, BROADCAST_TIMER_TOKEN);
+            let mut sent = Vec::new();
+            while let Some(call) = self.network.pop_call(EXTENSION_NAME) {
+                if let TestNetworkCall::Send(to, message) = call {
+                    assert_eq!(self.peer, to);
+                    let Message::Parcels(parcels) = ::rlp::decode(&message);
+                    sent.extend(parcels.iter().map(|parcel| parcel.hash()));
+                }
+            }
+            sent.sort();
+            sent
+        }
+
+        fn receive_parcel(&self) -> H256 {
+            let parcel = self.client.funded_parcel();
+            let hash = parcel.hash();
+            let message = Message::Parcels(vec![parcel.into()]);
+            self.network.send_message(EXTENSION_NAME, self.peer, &message.rlp_bytes());
+            hash
+        }
+
+        fn is_pooled(&self, hash: &H256) -> bool {
+            self.client.ready_parcels().iter().any(|parcel| parcel.hash() == *hash)
+        }
+    }
+
+    #[test]
+    fn all_relays_every_parcel() {
+        let test = RelayTest::new(RelayPolicy::All);
+        let mut expected = vec![test.local, test.external];
+        expected.sort();
+        assert_eq!(expected, test.broadcast());
+
+        let received = test.receive_parcel();
+        assert!(test.is_pooled(&received));
+    }
+
+    #[test]
+    fn local_only_relays_only_own_parcels() {
+        let test = RelayTest::new(RelayPolicy::LocalOnly);
+        assert_eq!(vec![test.local], test.broadcast());
+
+        let received = test.receive_parcel();
+        assert!(test.is_pooled(&received));
+        assert_eq!(Vec::<H256>::new(), test.broadcast());
+    }
+
+    #[test]
+    fn none_relays_nothing() {
+        let test = RelayTest::new(RelayPolicy::None);
+        assert_eq!(Vec::<H256>::new(), test.broadcast());
+
+        let received = test.receive_parcel();
+        assert!(test.is_pooled(&received));
+        assert_eq!(Vec::<H256>::new(), test.broadcast());
+    }
+
+    #[test]
+    fn relay_policy_is_parsed_from_its_name() {
+        for policy in &[RelayPolicy::None, RelayPolicy::LocalOnly, RelayPolicy::All] {
+            assert_eq!(Ok(*policy), policy.to_string().parse::<RelayPolicy>());
+        }
+        assert!("local".parse::<RelayPolicy>().is_err());
+    }
+}
@@ -16,5 +16,7 @@
 
 mod extension;
 mod message;
+mod relay_policy;
 
 pub use self::extension::Extension as ParcelSyncExtension;
+pub use self::relay_policy::RelayPolicy;
@@ -0,0 +1,60 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Which parcels in the mem pool are propagated to the peers.
+/// The parcels received from the peers are accepted regardless of the policy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RelayPolicy {
+    /// Never send parcels.
+    None,
+    /// Send only the parcels which are sent to this node's RPC.
+    LocalOnly,
+    /// Send every parcel which is ready.
+    All,
+}
+
+impl Default for RelayPolicy {
+    fn default() -> Self {
+        RelayPolicy::All
+    }
+}
+
+impl FromStr for RelayPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(RelayPolicy::None),
+            "local-only" => Ok(RelayPolicy::LocalOnly),
+            "all" => Ok(RelayPolicy::All),
+            _ => Err("Invalid relay policy given. Expected none/local-only/all.".into()),
+        }
+    }
+}
+
+impl fmt::Display for RelayPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RelayPolicy::None => "none",
+            RelayPolicy::LocalOnly => "local-only",
+            RelayPolicy::All => "all",
+        };
+        write!(f, "{}", name)
+    }
+}
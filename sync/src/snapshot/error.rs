@@ -17,6 +17,7 @@
 use std::fmt::{Display, Formatter, Result as FormatResult};
 use std::io::{Error as FileError, ErrorKind};
 
+use ccore::BlockImportError;
 use ctypes::H256;
 use kvdb::Error as DBError;
 
@@ -25,6 +26,15 @@ pub enum Error {
     NodeNotFound(H256),
     DBError(DBError),
     FileError(ErrorKind),
+    /// The chunk is not in the manifest or is restored already.
+    UnexpectedChunk(H256),
+    /// The chunk has malformed or unsorted nodes.
+    CorruptChunk(H256),
+    /// Some chunks are not restored yet.
+    Incomplete(usize),
+    /// The block is not the one the snapshot is taken at.
+    UnexpectedBlock,
+    Import(BlockImportError),
 }
 
 impl From<DBError> for Error {
@@ -45,6 +55,11 @@ impl Display for Error {
             Error::NodeNotFound(key) => write!(f, "State node not found: {:x}", key),
             Error::DBError(error) => write!(f, "DB Error: {:?}", error),
             Error::FileError(kind) => write!(f, "File system error: {:?}", kind),
+            Error::UnexpectedChunk(hash) => write!(f, "Unexpected chunk: {:x}", hash),
+            Error::CorruptChunk(hash) => write!(f, "Corrupt chunk: {:x}", hash),
+            Error::Incomplete(pending) => write!(f, "{} chunks are not restored yet", pending),
+            Error::UnexpectedBlock => write!(f, "The block doesn't match the snapshot"),
+            Error::Import(error) => write!(f, "Cannot import the restored block: {}", error),
         }
     }
 }
@@ -0,0 +1,71 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use ccore::BlockNumber;
+use ctypes::H256;
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+/// Describes a snapshot: the block it is taken at and the chunks holding the state of the block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub block_hash: H256,
+    pub block_number: BlockNumber,
+    pub state_root: H256,
+    /// The blake256 hashes of the compressed chunks. The head chunk comes first.
+    pub chunk_hashes: Vec<H256>,
+}
+
+impl Encodable for Manifest {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(4);
+        s.append(&self.block_hash);
+        s.append(&self.block_number);
+        s.append(&self.state_root);
+        s.append_list(&self.chunk_hashes);
+    }
+}
+
+impl Decodable for Manifest {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 4 {
+            return Err(DecoderError::RlpIncorrectListLen)
+        }
+        Ok(Self {
+            block_hash: rlp.val_at(0)?,
+            block_number: rlp.val_at(1)?,
+            state_root: rlp.val_at(2)?,
+            chunk_hashes: rlp.list_at(3)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlp;
+
+    use super::*;
+
+    #[test]
+    fn manifest_rlp() {
+        let manifest = Manifest {
+            block_hash: H256::from(1),
+            block_number: 16384,
+            state_root: H256::from(2),
+            chunk_hashes: vec![H256::from(3), H256::from(4)],
+        };
+        assert_eq!(manifest, rlp::decode(&rlp::encode(&manifest)));
+    }
+}
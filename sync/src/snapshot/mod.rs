@@ -15,6 +15,13 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 mod error;
+mod manifest;
+mod restorer;
 mod service;
 
-pub use self::service::Service as SnapshotService;
+pub use self::error::Error as SnapshotError;
+pub use self::manifest::Manifest;
+pub use self::restorer::Restorer as SnapshotRestorer;
+pub use self::service::{LatestManifest, Service as SnapshotService};
+#[cfg(test)]
+pub use self::service::write_snapshot;
@@ -0,0 +1,411 @@
+// Copyright 2018 Kodebox, Inc.
+// This file is part of CodeChain.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as
+// published by the Free Software Foundation, either version 3 of the
+// License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs::{remove_file, rename, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ccore::{Account, Header, ImportBlock, Shard, COL_STATE};
+use ccrypto::blake256;
+use ctypes::{Bytes, H256};
+use kvdb::KeyValueDB;
+use rlp::{RlpStream, UntrustedRlp};
+use rlp_compress::{decompress, snapshot_swapper};
+
+use super::error::Error;
+use super::manifest::Manifest;
+use super::service::{child_keys, get_node};
+
+/// Restores the state of a snapshot from its chunks.
+///
+/// The chunks can be fed in any order. The progress is written to a file after every chunk,
+/// so the restoration can be resumed after a restart.
+pub struct Restorer {
+    db: Arc<KeyValueDB>,
+    progress_path: PathBuf,
+    manifest: Manifest,
+    /// The hashes of the chunks which are restored already
+    restored: Vec<H256>,
+}
+
+impl Restorer {
+    /// Starts restoring the snapshot described by `manifest` into the state column of `db`.
+    pub fn new(db: Arc<KeyValueDB>, progress_path: PathBuf, manifest: Manifest) -> Result<Self, Error> {
+        let restorer = Self {
+            db,
+            progress_path,
+            manifest,
+            restored: Vec::new(),
+        };
+        restorer.save_progress()?;
+        Ok(restorer)
+    }
+
+    /// Resumes the restoration recorded in `progress_path`.
+    /// Returns `None` if there is no restoration in progress.
+    pub fn resume(db: Arc<KeyValueDB>, progress_path: PathBuf) -> Option<Self> {
+        let mut bytes = Vec::new();
+        File::open(&progress_path).ok()?.read_to_end(&mut bytes).ok()?;
+        let rlp = UntrustedRlp::new(&bytes);
+        let manifest = rlp.val_at(0).ok()?;
+        let restored = rlp.list_at(1).ok()?;
+        Some(Self {
+            db,
+            progress_path,
+            manifest,
+            restored,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The hashes of the chunks which are not restored yet.
+    pub fn pending_chunks(&self) -> Vec<H256> {
+        self.manifest.chunk_hashes.iter().filter(|hash| !self.restored.contains(hash)).cloned().collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pending_chunks().is_empty()
+    }
+
+    /// Verifies the compressed chunk and writes its nodes into the state DB.
+    ///
+    /// A chunk is rejected if it is not pending, or if any of its nodes is malformed,
+    /// doesn't match its key, or is out of order. A rejected chunk stays pending.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<H256, Error> {
+        let hash = blake256(chunk);
+        if !self.pending_chunks().contains(&hash) {
+            return Err(Error::UnexpectedChunk(hash))
+        }
+        let nodes = decode_chunk(chunk).ok_or(Error::CorruptChunk(hash))?;
+
+        let mut batch = self.db.transaction();
+        for (key, value) in nodes {
+            batch.put(COL_STATE, &key, &value);
+        }
+        self.db.write(batch)?;
+
+        self.restored.push(hash);
+        self.save_progress()?;
+        ctrace!(SNAPSHOT, "Chunk {:x} is restored, {} chunks left", hash, self.pending_chunks().len());
+        Ok(hash)
+    }
+
+    /// Verifies the restored state and imports `block`, the block the snapshot is taken at,
+    /// as the best block of `client`. The blocks after it can be imported as usual.
+    pub fn finish<C: ImportBlock + ?Sized>(self, client: &C, block: Bytes) -> Result<H256, Error> {
+        let pending = self.pending_chunks().len();
+        if pending != 0 {
+            return Err(Error::Incomplete(pending))
+        }
+        match UntrustedRlp::new(&block).val_at::<Header>(0) {
+            Ok(ref header)
+                if header.hash() == self.manifest.block_hash && *header.state_root() == self.manifest.state_root => {}
+            _ => return Err(Error::UnexpectedBlock),
+        }
+        self.verify_state()?;
+
+        let hash = client.import_restored_block(block).map_err(Error::Import)?;
+        remove_file(&self.progress_path)?;
+        cinfo!(SNAPSHOT, "The state of #{} is restored", self.manifest.block_number);
+        Ok(hash)
+    }
+
+    /// Checks that every node under the state root is restored.
+    fn verify_state(&self) -> Result<(), Error> {
+        let mut keys = vec![self.manifest.state_root];
+        while let Some(key) = keys.pop() {
+            let node = get_node(&self.db, &key)?;
+            keys.extend(child_keys(&node));
+        }
+        Ok(())
+    }
+
+    fn save_progress(&self) -> Result<(), Error> {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&self.manifest);
+        stream.append_list(&self.restored);
+
+        let temp_path = self.progress_path.with_extension("tmp");
+        File::create(&temp_path)?.write_all(&stream.out())?;
+        rename(&temp_path, &self.progress_path)?;
+        Ok(())
+    }
+}
+
+/// Decompresses the chunk and returns its nodes if they are well-formed and sorted by their keys.
+fn decode_chunk(chunk: &[u8]) -> Option<Vec<(H256, Vec<u8>)>> {
+    let decompressed = decompress(chunk, snapshot_swapper());
+    let rlp = UntrustedRlp::new(&decompressed);
+    if !rlp.is_list() {
+        return None
+    }
+
+    let mut nodes: Vec<(H256, Vec<u8>)> = Vec::new();
+    for item in rlp.iter() {
+        if item.item_count().ok()? != 2 {
+            return None
+        }
+        let key: H256 = item.val_at(0).ok()?;
+        let value: Vec<u8> = item.val_at(1).ok()?;
+        if key != blake256(&value) || !is_trie_node(&value) {
+            return None
+        }
+        if let Some((previous, _)) = nodes.last() {
+            if *previous >= key {
+                return None
+            }
+        }
+        nodes.push((key, value));
+    }
+    Some(nodes)
+}
+
+/// Checks that `value` can be decoded as a trie node of the top-level state,
+/// whose children are referred by their hashes and whose leaves are accounts or shards.
+fn is_trie_node(value: &[u8]) -> bool {
+    let rlp = UntrustedRlp::new(value);
+    if rlp.is_data() {
+        return rlp.is_empty()
+    }
+    let data_at = |index| rlp.at(index).and_then(|item| item.data()).ok();
+    let is_reference = |index| data_at(index).map_or(false, |data| data.is_empty() || data.len() == 32);
+    match rlp.item_count() {
+        Ok(2) => match (data_at(0), data_at(1)) {
+            // The flag of a leaf is in the first nibble of the partial key.
+            (Some(path), Some(data)) if !path.is_empty() && path[0] & 32 == 32 => is_state_item(data),
+            (Some(path), Some(data)) if !path.is_empty() => data.len() == 32,
+            _ => false,
+        },
+        // The keys have the same length, so no value is in a branch.
+        Ok(17) => (0..16).all(is_reference) && data_at(16).map_or(false, |data| data.is_empty()),
+        _ => false,
+    }
+}
+
+/// Checks that `value` is an account or a shard.
+fn is_state_item(value: &[u8]) -> bool {
+    let rlp = UntrustedRlp::new(value);
+    match rlp.item_count() {
+        Ok(4) => rlp.as_val::<Account>().is_ok(),
+        Ok(2) => rlp.as_val::<Shard>().is_ok(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use ccore::{ChainInfo, TestBlockChainClient, NUM_COLUMNS};
+    use journaldb::{self, Algorithm};
+    use kvdb_memorydb;
+    use rlp;
+    use rlp_compress::compress;
+    use tempdir::TempDir;
+    use trie::{TrieDBMut, TrieMut};
+
+    use super::super::service::write_snapshot;
+    use super::*;
+
+    fn new_db() -> Arc<KeyValueDB> {
+        Arc::new(kvdb_memorydb::create(NUM_COLUMNS.unwrap_or(0)))
+    }
+
+    fn state() -> (Arc<KeyValueDB>, H256) {
+        let db = new_db();
+        let mut journal_db = journaldb::new(Arc::clone(&db), Algorithm::Archive, COL_STATE);
+        let mut root = H256::zero();
+        {
+            let mut trie = TrieDBMut::new(journal_db.as_hashdb_mut(), &mut root);
+            for i in 0..1000u64 {
+                let key = blake256(&rlp::encode(&i));
+                trie.insert(&key, &rlp::encode(&Account::new(i.into(), 0.into()))).unwrap();
+            }
+        }
+        let mut batch = db.transaction();
+        journal_db.inject(&mut batch).unwrap();
+        db.write(batch).unwrap();
+        (db, root)
+    }
+
+    fn snapshot(dir: &Path) -> (Arc<KeyValueDB>, Manifest) {
+        let (db, root) = state();
+        let manifest = write_snapshot(Arc::clone(&db), dir, H256::from(1), 16384, root).unwrap();
+        (db, manifest)
+    }
+
+    /// Compresses a chunk which has only a leaf with an even-length partial key.
+    fn leaf_chunk(value: &[u8]) -> Vec<u8> {
+        let mut leaf = RlpStream::new_list(2);
+        leaf.append(&vec![0x20u8, 0x12]).append(&value.to_vec());
+        let leaf = leaf.out();
+
+        let mut chunk = RlpStream::new_list(1);
+        chunk.begin_list(2).append(&blake256(&leaf)).append(&leaf);
+        compress(&chunk.out(), snapshot_swapper())
+    }
+
+    fn block(header: &Header) -> Bytes {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(header).begin_list(0);
+        stream.out()
+    }
+
+    fn read_chunk(dir: &Path, manifest: &Manifest, hash: &H256) -> Vec<u8> {
+        let path = dir.join(format!("{:x}", manifest.block_hash)).join(format!("{:x}", hash));
+        let mut bytes = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn every_node_is_restored() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let (source, manifest) = snapshot(dir.path());
+        let db = new_db();
+        let mut restorer = Restorer::new(Arc::clone(&db), dir.path().join("restoration"), manifest.clone()).unwrap();
+
+        for hash in manifest.chunk_hashes.iter().rev() {
+            assert!(restorer.verify_state().is_err());
+            assert_eq!(*hash, restorer.feed(&read_chunk(dir.path(), &manifest, hash)).unwrap());
+        }
+        assert!(restorer.is_complete());
+        restorer.verify_state().unwrap();
+        assert_eq!(source.iter(COL_STATE).count(), db.iter(COL_STATE).count());
+    }
+
+    #[test]
+    fn a_corrupt_chunk_is_discarded_alone() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let (_, manifest) = snapshot(dir.path());
+        let mut restorer = Restorer::new(new_db(), dir.path().join("restoration"), manifest.clone()).unwrap();
+
+        let first = read_chunk(dir.path(), &manifest, &manifest.chunk_hashes[0]);
+        restorer.feed(&first).unwrap();
+        match restorer.feed(&first) {
+            Err(Error::UnexpectedChunk(hash)) => assert_eq!(manifest.chunk_hashes[0], hash),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        let second = read_chunk(dir.path(), &manifest, &manifest.chunk_hashes[1]);
+        let mut corrupted = decompress(&second, snapshot_swapper()).to_vec();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        let corrupted = compress(&corrupted, snapshot_swapper());
+        match restorer.feed(&corrupted) {
+            Err(Error::UnexpectedChunk(_)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(manifest.chunk_hashes[1..].to_vec(), restorer.pending_chunks());
+
+        let mut forged = manifest.clone();
+        forged.chunk_hashes.push(blake256(&corrupted));
+        let mut restorer = Restorer::new(new_db(), dir.path().join("forged"), forged).unwrap();
+        match restorer.feed(&corrupted) {
+            Err(Error::CorruptChunk(hash)) => assert_eq!(blake256(&corrupted), hash),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        restorer.feed(&second).unwrap();
+        assert!(restorer.pending_chunks().contains(&blake256(&corrupted)));
+    }
+
+    #[test]
+    fn leaves_must_be_accounts_or_shards() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let (_, manifest) = snapshot(dir.path());
+        let account = rlp::encode(&Account::new(10.into(), 0.into())).into_vec();
+        let shard = rlp::encode(&Shard::new(H256::from(1))).into_vec();
+        let valid = vec![leaf_chunk(&account), leaf_chunk(&shard)];
+        let invalid = vec![leaf_chunk(&blake256(&account)), leaf_chunk(&[0xc0]), leaf_chunk(&account[1..])];
+
+        let mut forged = manifest.clone();
+        forged.chunk_hashes = valid.iter().chain(invalid.iter()).map(|chunk| blake256(chunk)).collect();
+        let mut restorer = Restorer::new(new_db(), dir.path().join("forged"), forged).unwrap();
+        for chunk in &invalid {
+            match restorer.feed(chunk) {
+                Err(Error::CorruptChunk(hash)) => assert_eq!(blake256(chunk), hash),
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+        for chunk in &valid {
+            assert_eq!(blake256(chunk), restorer.feed(chunk).unwrap());
+        }
+    }
+
+    #[test]
+    fn the_block_of_the_snapshot_becomes_the_best_block() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let (source, state_root) = state();
+        let client = TestBlockChainClient::new();
+        let mut header = Header::default();
+        header.set_parent_hash(client.chain_info().best_block_hash);
+        header.set_number(1);
+        header.set_state_root(state_root);
+        let manifest = write_snapshot(source, dir.path(), header.hash(), 1, state_root).unwrap();
+
+        let db = new_db();
+        let progress_path = dir.path().join("restoration");
+        let mut restorer = Restorer::new(Arc::clone(&db), progress_path.clone(), manifest.clone()).unwrap();
+        for hash in &manifest.chunk_hashes[1..] {
+            restorer.feed(&read_chunk(dir.path(), &manifest, hash)).unwrap();
+        }
+        match restorer.finish(&client, block(&header)) {
+            Err(Error::Incomplete(pending)) => assert_eq!(1, pending),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        let mut restorer = Restorer::resume(Arc::clone(&db), progress_path.clone()).unwrap();
+        restorer.feed(&read_chunk(dir.path(), &manifest, &manifest.chunk_hashes[0])).unwrap();
+        let mut other = header.clone();
+        other.set_timestamp(1);
+        match restorer.finish(&client, block(&other)) {
+            Err(Error::UnexpectedBlock) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(0, client.chain_info().best_block_number);
+
+        let restorer = Restorer::resume(Arc::clone(&db), progress_path.clone()).unwrap();
+        assert_eq!(header.hash(), restorer.finish(&client, block(&header)).unwrap());
+        assert_eq!(header.hash(), client.chain_info().best_block_hash);
+        assert_eq!(1, client.chain_info().best_block_number);
+        assert!(!progress_path.exists());
+    }
+
+    #[test]
+    fn restoration_resumes_from_the_progress() {
+        let dir = TempDir::new("snapshot").unwrap();
+        let (_, manifest) = snapshot(dir.path());
+        let db = new_db();
+        let progress_path = dir.path().join("restoration");
+        assert!(Restorer::resume(Arc::clone(&db), progress_path.clone()).is_none());
+        {
+            let mut restorer = Restorer::new(Arc::clone(&db), progress_path.clone(), manifest.clone()).unwrap();
+            restorer.feed(&read_chunk(dir.path(), &manifest, &manifest.chunk_hashes[0])).unwrap();
+        }
+
+        let mut restorer = Restorer::resume(Arc::clone(&db), progress_path).unwrap();
+        assert_eq!(&manifest, restorer.manifest());
+        assert_eq!(manifest.chunk_hashes[1..].to_vec(), restorer.pending_chunks());
+        for hash in &manifest.chunk_hashes[1..] {
+            restorer.feed(&read_chunk(dir.path(), &manifest, hash)).unwrap();
+        }
+        restorer.verify_state().unwrap();
+    }
+}
@@ -14,20 +14,29 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::fs::{create_dir_all, File};
-use std::io::{ErrorKind, Write};
-use std::path::PathBuf;
+use std::fs::{create_dir_all, read_dir, remove_dir_all, rename, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::spawn;
 
-use ccore::{BlockChainClient, BlockId, BlockInfo, ChainInfo, ChainNotify, Client, COL_STATE};
-use ctypes::H256;
+use ccore::encoded::Header as EncodedHeader;
+use ccore::{BlockChainClient, BlockId, BlockInfo, BlockNumber, ChainInfo, ChainNotify, Client, COL_STATE};
+use ccrypto::blake256;
+use ctypes::{Bytes, H256};
 
 use kvdb::KeyValueDB;
-use rlp::{decode as rlp_decode, RlpStream};
+use parking_lot::RwLock;
+use rlp::{self, decode as rlp_decode, RlpStream, UntrustedRlp};
+use rlp_compress::{compress, snapshot_swapper};
 use trie::{Node, OwnedNode};
 
 use super::error::Error;
+use super::manifest::Manifest;
+
+const MANIFEST_FILE_NAME: &'static str = "manifest";
 
 pub struct Service {
     client: Arc<Client>,
@@ -35,16 +44,71 @@ pub struct Service {
     root_dir: String,
     /// Snapshot creation period in unit of block numbers
     period: u64,
+    /// The number of the latest snapshots kept in the root directory
+    max_snapshots: usize,
+    /// Set while a snapshot is being written
+    in_progress: Arc<AtomicBool>,
+    latest_manifest: Arc<LatestManifest>,
 }
 
 impl Service {
-    pub fn new(client: Arc<Client>, root_dir: String, period: u64) -> Arc<Self> {
+    pub fn new(client: Arc<Client>, root_dir: String, period: u64, max_snapshots: usize) -> Arc<Self> {
+        let latest_manifest = LatestManifest::new(PathBuf::from(&root_dir));
         Arc::new(Self {
             client,
             root_dir,
             period,
+            max_snapshots,
+            in_progress: Arc::new(AtomicBool::new(false)),
+            latest_manifest,
         })
     }
+
+    /// The manifest of the newest snapshot, which is updated whenever a snapshot is written.
+    pub fn latest_manifest(&self) -> Arc<LatestManifest> {
+        Arc::clone(&self.latest_manifest)
+    }
+
+    /// Starts writing the snapshot of the best block in the background.
+    /// Returns the hash of the block, or `None` if another snapshot is being written.
+    pub fn take_snapshot(&self) -> Option<H256> {
+        let header = self.client.block_header(BlockId::Latest).expect("The best block must exist");
+        if self.start(&header) {
+            Some(header.hash())
+        } else {
+            None
+        }
+    }
+
+    fn start(&self, header: &EncodedHeader) -> bool {
+        if self.in_progress.compare_and_swap(false, true, Ordering::SeqCst) {
+            return false
+        }
+
+        let db = self.client.database();
+        let root_dir = PathBuf::from(&self.root_dir);
+        let hash = header.hash();
+        let number = header.number();
+        let state_root = header.state_root();
+        let max_snapshots = self.max_snapshots;
+        let in_progress = Arc::clone(&self.in_progress);
+        let latest_manifest = Arc::clone(&self.latest_manifest);
+        spawn(move || {
+            match write_snapshot(db, &root_dir, hash, number, state_root) {
+                Ok(manifest) => {
+                    cinfo!(SNAPSHOT, "Snapshot of #{} is written in {} chunks", number, manifest.chunk_hashes.len())
+                }
+                Err(Error::FileError(ErrorKind::AlreadyExists)) => {}
+                Err(e) => cerror!(SNAPSHOT, "{}", e),
+            }
+            if let Err(e) = prune_snapshots(&root_dir, max_snapshots) {
+                cerror!(SNAPSHOT, "{}", e);
+            }
+            latest_manifest.refresh();
+            in_progress.store(false, Ordering::SeqCst);
+        });
+        true
+    }
 }
 
 impl ChainNotify for Service {
@@ -66,62 +130,170 @@ impl ChainNotify for Service {
         if is_checkpoint && best_number > self.period {
             let number = (best_number / self.period - 1) * self.period;
             let header = self.client.block_header(BlockId::Number(number)).expect("Snapshot target must exist");
+            if !self.start(&header) {
+                cwarn!(SNAPSHOT, "Snapshot of #{} is skipped since another snapshot is being written", number);
+            }
+        }
+    }
+}
 
-            let db = self.client.database();
-            let path: PathBuf = [self.root_dir.clone(), format!("{:x}", header.hash())].iter().collect();
-            let root = header.state_root();
-            spawn(move || match write_snapshot(db, path, &root) {
-                Ok(_) => {}
-                Err(Error::FileError(ErrorKind::AlreadyExists)) => {}
-                Err(e) => cerror!(SNAPSHOT, "{}", e),
-            });
+/// The manifest of the snapshot with the highest block number under a directory.
+/// The directory is read when it is created and refreshed, not every time the manifest is read.
+pub struct LatestManifest {
+    root_dir: PathBuf,
+    manifest: RwLock<Option<Manifest>>,
+}
+
+impl LatestManifest {
+    pub fn new(root_dir: PathBuf) -> Arc<Self> {
+        let manifest = latest_manifest(&root_dir);
+        Arc::new(Self {
+            root_dir,
+            manifest: RwLock::new(manifest),
+        })
+    }
+
+    pub fn get(&self) -> Option<Manifest> {
+        self.manifest.read().clone()
+    }
+
+    /// Reads the directory again, after snapshots have been written or removed.
+    pub fn refresh(&self) {
+        let manifest = latest_manifest(&self.root_dir);
+        *self.manifest.write() = manifest;
+    }
+
+    /// Whether the newest snapshot is taken at `block_hash` and has the chunk.
+    pub fn has_chunk(&self, block_hash: &H256, chunk_hash: &H256) -> bool {
+        match *self.manifest.read() {
+            Some(ref manifest) => manifest.block_hash == *block_hash && manifest.chunk_hashes.contains(chunk_hash),
+            None => false,
         }
     }
+
+    /// Reads the chunk of the newest snapshot.
+    /// Returns `None` if the snapshot doesn't have it, or if the snapshot is removed meanwhile.
+    pub fn read_chunk(&self, block_hash: &H256, chunk_hash: &H256) -> Option<Bytes> {
+        if !self.has_chunk(block_hash, chunk_hash) {
+            return None
+        }
+        let path = self.root_dir.join(format!("{:x}", block_hash)).join(format!("{:x}", chunk_hash));
+        let mut bytes = Vec::new();
+        File::open(path).ok()?.read_to_end(&mut bytes).ok()?;
+        Some(bytes)
+    }
+}
+
+/// Returns the manifest of the snapshot with the highest block number under `root_dir`.
+pub fn latest_manifest(root_dir: &Path) -> Option<Manifest> {
+    read_manifests(root_dir).into_iter().map(|(_, manifest)| manifest).max_by_key(|manifest| manifest.block_number)
 }
 
-fn write_snapshot(db: Arc<KeyValueDB>, path: PathBuf, root: &H256) -> Result<(), Error> {
+/// Writes the state of the block into `root_dir/<block hash>`.
+///
+/// The state DB is an archive whose nodes are never modified nor removed,
+/// so the nodes under `state_root` stay consistent while new blocks are imported.
+/// The manifest is written last; a directory without it is an incomplete snapshot.
+pub fn write_snapshot(
+    db: Arc<KeyValueDB>,
+    root_dir: &Path,
+    block_hash: H256,
+    block_number: BlockNumber,
+    state_root: H256,
+) -> Result<Manifest, Error> {
+    let path = root_dir.join(format!("{:x}", block_hash));
+    if path.join(MANIFEST_FILE_NAME).exists() {
+        return Err(Error::FileError(ErrorKind::AlreadyExists))
+    }
     create_dir_all(&path)?;
 
-    let root_val = get_node(&db, root)?;
+    let root_val = get_node(&db, &state_root)?;
     let children = children_of(&db, &root_val)?;
     let mut grandchildren = Vec::new();
     for (_, value) in &children {
         grandchildren.extend(children_of(&db, value)?);
     }
 
-    {
-        let mut file = File::create(path.join("head"))?;
+    let mut chunk_hashes = Vec::new();
+    let head = vec![(state_root, root_val)].into_iter().chain(grandchildren.clone()).chain(children).collect();
+    chunk_hashes.push(write_chunk(&path, head)?);
+    for (grandchild, _) in &grandchildren {
+        chunk_hashes.push(write_chunk(&path, enumerate_subtree(&db, grandchild)?)?);
+    }
 
-        let mut stream = RlpStream::new();
-        stream.begin_unbounded_list();
-        for (key, value) in vec![(*root, root_val)].iter().chain(&grandchildren).chain(&children) {
-            stream.begin_list(2);
-            stream.append(key);
-            stream.append(value);
-        }
-        stream.complete_unbounded_list();
+    let manifest = Manifest {
+        block_hash,
+        block_number,
+        state_root,
+        chunk_hashes,
+    };
+    let temp_path = path.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+    File::create(&temp_path)?.write_all(&rlp::encode(&manifest))?;
+    rename(&temp_path, path.join(MANIFEST_FILE_NAME))?;
+    Ok(manifest)
+}
 
-        file.write(&stream.drain())?;
+/// Writes the compressed RLP of the nodes, sorted by their keys, into a file named after its hash.
+fn write_chunk(path: &Path, mut nodes: Vec<(H256, Vec<u8>)>) -> Result<H256, Error> {
+    nodes.sort_unstable_by_key(|(key, _)| *key);
+    nodes.dedup_by_key(|(key, _)| *key);
+
+    let mut stream = RlpStream::new();
+    stream.begin_unbounded_list();
+    for (key, value) in nodes {
+        stream.begin_list(2);
+        stream.append(&key);
+        stream.append(&value);
     }
+    stream.complete_unbounded_list();
 
-    for (grandchild, _) in &grandchildren {
-        let nodes = enumerate_subtree(&db, grandchild)?;
-        let mut file = File::create(path.join(format!("{:x}", grandchild)))?;
-        let mut stream = RlpStream::new();
-        stream.begin_unbounded_list();
-        for (key, value) in nodes {
-            stream.begin_list(2);
-            stream.append(&key);
-            stream.append(&value);
+    let compressed = compress(&stream.drain(), snapshot_swapper());
+    let hash = blake256(&compressed);
+    File::create(path.join(format!("{:x}", hash)))?.write_all(&compressed)?;
+    Ok(hash)
+}
+
+/// Keeps the latest `max_snapshots` snapshots and removes the others, including the incomplete ones.
+/// Only the directories named after a block hash are snapshots; the other entries are left alone.
+fn prune_snapshots(root_dir: &Path, max_snapshots: usize) -> Result<(), Error> {
+    let mut snapshots = read_manifests(root_dir);
+    snapshots.sort_unstable_by_key(|(_, manifest)| manifest.block_number);
+    let outdated = snapshots.len().saturating_sub(max_snapshots);
+    let mut kept: Vec<_> = snapshots.split_off(outdated).into_iter().map(|(path, _)| path).collect();
+    kept.sort();
+
+    for entry in read_dir(root_dir)? {
+        let path = entry?.path();
+        if is_snapshot_dir(&path) && kept.binary_search(&path).is_err() {
+            remove_dir_all(&path)?;
         }
-        stream.complete_unbounded_list();
-        file.write(&stream.drain())?;
     }
-
     Ok(())
 }
 
-fn get_node(db: &Arc<KeyValueDB>, key: &H256) -> Result<Vec<u8>, Error> {
+fn is_snapshot_dir(path: &Path) -> bool {
+    let is_hash = path.file_name().and_then(|name| name.to_str()).map_or(false, |name| H256::from_str(name).is_ok());
+    is_hash && path.is_dir()
+}
+
+fn read_manifests(root_dir: &Path) -> Vec<(PathBuf, Manifest)> {
+    let entries = match read_dir(root_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter_map(|path| {
+            let mut bytes = Vec::new();
+            File::open(path.join(MANIFEST_FILE_NAME)).ok()?.read_to_end(&mut bytes).ok()?;
+            let manifest = UntrustedRlp::new(&bytes).as_val().ok()?;
+            Some((path, manifest))
+        })
+        .collect()
+}
+
+pub fn get_node(db: &Arc<KeyValueDB>, key: &H256) -> Result<Vec<u8>, Error> {
     match db.get(COL_STATE, key) {
         Ok(Some(value)) => Ok(value.to_vec()),
         Ok(None) => Err(Error::NodeNotFound(*key)),
@@ -129,25 +301,30 @@ fn get_node(db: &Arc<KeyValueDB>, key: &H256) -> Result<Vec<u8>, Error> {
     }
 }
 
-fn children_of(db: &Arc<KeyValueDB>, node: &[u8]) -> Result<Vec<(H256, Vec<u8>)>, Error> {
-    let keys = match OwnedNode::from(Node::decoded(node)) {
+/// Returns the keys of the nodes referred by `node`.
+pub fn child_keys(node: &[u8]) -> Vec<H256> {
+    let references = match OwnedNode::from(Node::decoded(node)) {
         OwnedNode::Empty => Vec::new(),
         OwnedNode::Leaf(..) => Vec::new(),
-        OwnedNode::Extension(_, child) => vec![H256::from_slice(&child)],
-        OwnedNode::Branch(children, _) => children
-            .iter()
-            .filter_map(|child| {
-                let decoded: Vec<u8> = rlp_decode(child);
-                if decoded.len() != 0 {
-                    Some(H256::from_slice(&decoded))
-                } else {
-                    None
-                }
-            })
-            .collect(),
+        OwnedNode::Extension(_, child) => vec![child.to_vec()],
+        OwnedNode::Branch(children, _) => children.iter().map(|child| child.to_vec()).collect(),
     };
+    references
+        .iter()
+        .filter_map(|reference| {
+            let decoded: Vec<u8> = rlp_decode(reference);
+            if decoded.len() != 0 {
+                Some(H256::from_slice(&decoded))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn children_of(db: &Arc<KeyValueDB>, node: &[u8]) -> Result<Vec<(H256, Vec<u8>)>, Error> {
     let mut result = Vec::new();
-    for key in keys {
+    for key in child_keys(node) {
         result.push((key, get_node(db, &key)?));
     }
     Ok(result)
@@ -155,25 +332,128 @@ fn children_of(db: &Arc<KeyValueDB>, node: &[u8]) -> Result<Vec<(H256, Vec<u8>)>
 
 fn enumerate_subtree(db: &Arc<KeyValueDB>, root: &H256) -> Result<Vec<(H256, Vec<u8>)>, Error> {
     let node = get_node(db, root)?;
-    let children = match OwnedNode::from(Node::decoded(&node)) {
-        OwnedNode::Empty => Vec::new(),
-        OwnedNode::Leaf(..) => Vec::new(),
-        OwnedNode::Extension(_, child) => vec![H256::from_slice(&child)],
-        OwnedNode::Branch(children, _) => children
-            .iter()
-            .filter_map(|child| {
-                let decoded: Vec<u8> = rlp_decode(child);
-                if decoded.len() != 0 {
-                    Some(H256::from_slice(&decoded))
-                } else {
-                    None
-                }
-            })
-            .collect(),
-    };
+    let children = child_keys(&node);
     let mut result: Vec<_> = vec![(*root, node)];
     for child in children {
         result.extend(enumerate_subtree(db, &child)?);
     }
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use ccore::NUM_COLUMNS;
+    use journaldb::{self, Algorithm};
+    use kvdb_memorydb;
+    use rlp_compress::decompress;
+    use tempdir::TempDir;
+    use trie::{TrieDBMut, TrieMut};
+
+    use super::*;
+
+    fn state_db() -> (Arc<KeyValueDB>, H256) {
+        let db: Arc<KeyValueDB> = Arc::new(kvdb_memorydb::create(NUM_COLUMNS.unwrap_or(0)));
+        let mut journal_db = journaldb::new(Arc::clone(&db), Algorithm::Archive, COL_STATE);
+        let mut root = H256::zero();
+        {
+            let mut trie = TrieDBMut::new(journal_db.as_hashdb_mut(), &mut root);
+            for i in 0..1000u64 {
+                let key = blake256(&rlp::encode(&i));
+                trie.insert(&key, &blake256(&key)).unwrap();
+            }
+        }
+        let mut batch = db.transaction();
+        journal_db.inject(&mut batch).unwrap();
+        db.write(batch).unwrap();
+        (db, root)
+    }
+
+    fn read_file(path: &Path) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn every_chunk_matches_its_hash_in_the_manifest() {
+        let (db, state_root) = state_db();
+        let dir = TempDir::new("snapshot").unwrap();
+        let block_hash = H256::from(1);
+
+        let manifest = write_snapshot(Arc::clone(&db), dir.path(), block_hash, 16384, state_root).unwrap();
+        assert_eq!(Some(manifest.clone()), latest_manifest(dir.path()));
+        assert_eq!(state_root, manifest.state_root);
+        assert!(manifest.chunk_hashes.len() > 1);
+
+        let path = dir.path().join(format!("{:x}", block_hash));
+        let mut nodes = HashSet::new();
+        for hash in &manifest.chunk_hashes {
+            let bytes = read_file(&path.join(format!("{:x}", hash)));
+            assert_eq!(*hash, blake256(&bytes));
+
+            let chunk = decompress(&bytes, snapshot_swapper());
+            for node in UntrustedRlp::new(&chunk).iter() {
+                let key: H256 = node.val_at(0).unwrap();
+                let value: Vec<u8> = node.val_at(1).unwrap();
+                assert_eq!(key, blake256(&value));
+                nodes.insert(key);
+            }
+        }
+        assert_eq!(db.iter(COL_STATE).count(), nodes.len());
+    }
+
+    #[test]
+    fn only_the_latest_snapshots_are_kept() {
+        let (db, state_root) = state_db();
+        let dir = TempDir::new("snapshot").unwrap();
+        for number in 1..4 {
+            write_snapshot(Arc::clone(&db), dir.path(), H256::from(number), number, state_root).unwrap();
+        }
+        let incomplete = dir.path().join(format!("{:x}", H256::from(4)));
+        create_dir_all(&incomplete).unwrap();
+        let unrelated = dir.path().join("backup");
+        create_dir_all(&unrelated).unwrap();
+
+        prune_snapshots(dir.path(), 2).unwrap();
+        let mut numbers: Vec<_> = read_manifests(dir.path()).into_iter().map(|(_, m)| m.block_number).collect();
+        numbers.sort();
+        assert_eq!(vec![2, 3], numbers);
+        assert!(!incomplete.exists());
+        assert!(unrelated.exists(), "A directory which is not a snapshot is kept");
+        assert_eq!(Some(3), latest_manifest(dir.path()).map(|manifest| manifest.block_number));
+    }
+
+    #[test]
+    fn latest_manifest_is_read_again_only_when_refreshed() {
+        let (db, state_root) = state_db();
+        let dir = TempDir::new("snapshot").unwrap();
+        let latest = LatestManifest::new(dir.path().to_path_buf());
+        assert_eq!(None, latest.get());
+
+        let manifest = write_snapshot(db, dir.path(), H256::from(1), 1, state_root).unwrap();
+        assert_eq!(None, latest.get());
+        latest.refresh();
+        assert_eq!(Some(manifest), latest.get());
+    }
+
+    #[test]
+    fn only_the_chunks_of_the_latest_snapshot_are_read() {
+        let (db, state_root) = state_db();
+        let dir = TempDir::new("snapshot").unwrap();
+        let old = write_snapshot(Arc::clone(&db), dir.path(), H256::from(1), 1, state_root).unwrap();
+        let new = write_snapshot(db, dir.path(), H256::from(2), 2, state_root).unwrap();
+        let latest = LatestManifest::new(dir.path().to_path_buf());
+
+        let chunk_hash = new.chunk_hashes[0];
+        let chunk = latest.read_chunk(&new.block_hash, &chunk_hash).unwrap();
+        assert_eq!(chunk_hash, blake256(&chunk));
+        assert_eq!(None, latest.read_chunk(&old.block_hash, &old.chunk_hashes[0]));
+        assert_eq!(None, latest.read_chunk(&new.block_hash, &H256::from(3)));
+
+        remove_dir_all(dir.path().join(format!("{:x}", new.block_hash))).unwrap();
+        assert!(latest.has_chunk(&new.block_hash, &chunk_hash));
+        assert_eq!(None, latest.read_chunk(&new.block_hash, &chunk_hash));
+    }
+}
@@ -102,6 +102,13 @@ impl error::Error for IoError {
     fn description(&self) -> &str {
         "IO error"
     }
+
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            IoError::Mio(ref std_err) => Some(std_err),
+            IoError::StdIo(ref std_err) => Some(std_err),
+        }
+    }
 }
 
 impl From<::std::io::Error> for IoError {
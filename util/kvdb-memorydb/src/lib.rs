@@ -136,3 +136,35 @@ impl KeyValueDB for InMemory {
         Err("Attempted to restore in-memory database".into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_and_transactions() {
+        let db = create(2);
+        let mut batch = db.transaction();
+        batch.put(None, b"key1", b"cat");
+        batch.put(Some(0), b"key1", b"dog");
+        batch.put(Some(1), b"key2", b"horse");
+        db.write(batch).unwrap();
+
+        assert_eq!(&*db.get(None, b"key1").unwrap().unwrap(), b"cat");
+        assert_eq!(&*db.get(Some(0), b"key1").unwrap().unwrap(), b"dog");
+        assert!(db.get(Some(1), b"key1").unwrap().is_none());
+        assert!(db.get(Some(2), b"key1").is_err());
+
+        let mut batch = db.transaction();
+        batch.delete(Some(0), b"key1");
+        batch.put(Some(0), b"key3", b"elephant");
+        db.write_buffered(batch);
+        assert!(db.get(Some(0), b"key1").unwrap().is_none());
+        assert_eq!(&*db.get_by_prefix(Some(0), b"key").unwrap(), b"elephant");
+
+        let contents: Vec<_> = db.iter(Some(1)).collect();
+        assert_eq!(contents.len(), 1);
+        assert_eq!(&*contents[0].0, b"key2");
+        assert_eq!(&*contents[0].1, b"horse");
+    }
+}
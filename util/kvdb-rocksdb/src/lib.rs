@@ -156,6 +156,15 @@ impl CompactionProfile {
     }
 }
 
+/// Column family configuration overriding the database-wide one
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ColumnConfig {
+    /// Memory budget (in MiB) used for the block cache and write buffer of this column.
+    pub memory_budget: Option<usize>,
+    /// Compaction profile of this column.
+    pub compaction: Option<CompactionProfile>,
+}
+
 /// Database configuration
 #[derive(Clone)]
 pub struct DatabaseConfig {
@@ -169,6 +178,8 @@ pub struct DatabaseConfig {
     pub columns: Option<u32>,
     /// Should we keep WAL enabled?
     pub wal: bool,
+    /// Per-column configurations, indexed by the column number
+    pub column_configs: HashMap<u32, ColumnConfig>,
 }
 
 impl DatabaseConfig {
@@ -187,6 +198,11 @@ impl DatabaseConfig {
     pub fn memory_budget_per_col(&self) -> usize {
         self.memory_budget() / self.columns.unwrap_or(1) as usize
     }
+
+    /// Returns the configuration of the given column.
+    pub fn column_config(&self, col: u32) -> ColumnConfig {
+        self.column_configs.get(&col).cloned().unwrap_or_default()
+    }
 }
 
 impl Default for DatabaseConfig {
@@ -197,6 +213,7 @@ impl Default for DatabaseConfig {
             compaction: CompactionProfile::default(),
             columns: None,
             wal: true,
+            column_configs: HashMap::new(),
         }
     }
 }
@@ -224,20 +241,33 @@ struct DBAndColumns {
 }
 
 // get column family configuration from database config.
-fn col_config(config: &DatabaseConfig, block_opts: &BlockBasedOptions) -> Result<Options> {
+fn col_config(config: &DatabaseConfig, col: u32, block_opts: &BlockBasedOptions) -> Result<Options> {
+    let column = config.column_config(col);
+    let compaction = column.compaction.unwrap_or(config.compaction);
+    let memory_budget_per_col = column.memory_budget.map_or(config.memory_budget_per_col(), |mb| mb * 1024 * 1024);
+
     let mut opts = Options::new();
 
     opts.set_parsed_options("level_compaction_dynamic_level_bytes=true")?;
 
-    opts.set_block_based_table_factory(block_opts);
+    if column == ColumnConfig::default() {
+        opts.set_block_based_table_factory(block_opts);
+    } else {
+        // The column has its own block cache
+        let mut col_block_opts = BlockBasedOptions::new();
+        col_block_opts.set_block_size(compaction.block_size);
+        let cache_size = cmp::max(8, memory_budget_per_col / 3);
+        col_block_opts.set_cache(Cache::new(cache_size));
+        opts.set_block_based_table_factory(&col_block_opts);
+    }
 
     opts.set_parsed_options(&format!(
         "block_based_table_factory={{{};{}}}",
         "cache_index_and_filter_blocks=true", "pin_l0_filter_and_index_blocks_in_cache=true"
     ))?;
 
-    opts.optimize_level_style_compaction(config.memory_budget_per_col() as i32);
-    opts.set_target_file_size_base(config.compaction.initial_file_size);
+    opts.optimize_level_style_compaction(memory_budget_per_col as i32);
+    opts.set_target_file_size_base(compaction.initial_file_size);
 
     opts.set_parsed_options("compression_per_level=")?;
 
@@ -323,8 +353,8 @@ impl Database {
         let cfnames: Vec<_> = (0..columns).map(|c| format!("col{}", c)).collect();
         let cfnames: Vec<&str> = cfnames.iter().map(|n| n as &str).collect();
 
-        for _ in 0..config.columns.unwrap_or(0) {
-            cf_options.push(col_config(&config, &block_opts)?);
+        for col in 0..config.columns.unwrap_or(0) {
+            cf_options.push(col_config(&config, col, &block_opts)?);
         }
 
         let mut write_opts = WriteOptions::new();
@@ -758,7 +788,7 @@ impl Database {
             }) => {
                 let col = cfs.len() as u32;
                 let name = format!("col{}", col);
-                cfs.push(db.create_cf(&name, &col_config(&self.config, &self.block_opts)?)?);
+                cfs.push(db.create_cf(&name, &col_config(&self.config, col, &self.block_opts)?)?);
                 Ok(())
             }
             None => Ok(()),
@@ -900,6 +930,32 @@ mod tests {
         assert_eq!(rotational_from_df_output(example_df), expected_output);
     }
 
+    #[test]
+    fn column_configs() {
+        let mut config = DatabaseConfig::with_columns(Some(2));
+        config.column_configs.insert(
+            0,
+            ColumnConfig {
+                memory_budget: Some(16),
+                compaction: Some(CompactionProfile::hdd()),
+            },
+        );
+        assert_eq!(config.column_config(1), ColumnConfig::default());
+
+        let tempdir = TempDir::new("").unwrap();
+        let db = Database::open(&config, tempdir.path().to_str().unwrap()).unwrap();
+        assert_eq!(db.num_columns(), 2);
+
+        let key = H256::from_str("02c69be41d0b7e40352fc85be1cd65eb03d40ef8427a0ca4596b1ead9a00e9fc").unwrap();
+        let mut batch = db.transaction();
+        batch.put(Some(0), &key, b"cat");
+        batch.put(Some(1), &key, b"dog");
+        db.write(batch).unwrap();
+
+        assert_eq!(&*db.get(Some(0), &key).unwrap().unwrap(), b"cat");
+        assert_eq!(&*db.get(Some(1), &key).unwrap().unwrap(), b"dog");
+    }
+
     #[test]
     fn add_columns() {
         let config = DatabaseConfig::default();
@@ -19,13 +19,79 @@
 extern crate backtrace;
 
 use backtrace::Backtrace;
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::fs::{self, File};
 use std::io::{self, Write};
 use std::panic::{self, PanicInfo};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Set the panic hook
 pub fn set() {
-    panic::set_hook(Box::new(panic_hook));
+    panic::set_hook(Box::new(|info| panic_hook(info, None)));
+}
+
+/// Set the panic hook which also writes a crash report under `dir`
+pub fn set_with_report_dir<P: Into<PathBuf>>(dir: P) {
+    let dir = dir.into();
+    panic::set_hook(Box::new(move |info| panic_hook(info, Some(&dir))));
+}
+
+struct ContextEntry {
+    key: &'static str,
+    value: String,
+    dump: Option<Rc<Vec<u8>>>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Vec<ContextEntry>> = RefCell::new(Vec::new());
+}
+
+/// Removes the context entry registered by `with_context` or `with_dump` when dropped.
+#[must_use]
+pub struct ContextGuard {
+    depth: usize,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        let depth = self.depth;
+        let _ = CONTEXT.try_with(|context| context.borrow_mut().truncate(depth));
+    }
+}
+
+/// Registers `key: value` as the context of the current thread until the returned guard is dropped.
+/// The context is reported if the thread panics.
+pub fn with_context<V: Debug>(key: &'static str, value: V) -> ContextGuard {
+    push_context(ContextEntry {
+        key,
+        value: format!("{:?}", value),
+        dump: None,
+    })
+}
+
+/// Same as `with_context`, but `dump` is also written to a file next to the crash report.
+/// The dump is shared rather than copied, since it is registered for every verified block.
+pub fn with_dump<V: Debug>(key: &'static str, value: V, dump: Rc<Vec<u8>>) -> ContextGuard {
+    push_context(ContextEntry {
+        key,
+        value: format!("{:?}", value),
+        dump: Some(dump),
+    })
+}
+
+fn push_context(entry: ContextEntry) -> ContextGuard {
+    CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        let depth = context.len();
+        context.push(entry);
+        ContextGuard {
+            depth,
+        }
+    })
 }
 
 static ABOUT_PANIC: &str = "
@@ -34,7 +100,7 @@ This is a bug. Please report it at:
     https://github.com/kodebox-io/codechain/issues/new
 ";
 
-fn panic_hook(info: &PanicInfo) {
+fn panic_hook(info: &PanicInfo, report_dir: Option<&Path>) {
     let location = info.location();
     let file = location.as_ref().map(|l| l.file()).unwrap_or("<unknown>");
     let line = location.as_ref().map(|l| l.line()).unwrap_or(0);
@@ -52,14 +118,117 @@ fn panic_hook(info: &PanicInfo) {
 
     let backtrace = Backtrace::new();
 
+    let mut report = Vec::new();
+    let _ = writeln!(report, "{:?}", backtrace);
+    let _ = writeln!(report, "");
+    let _ = writeln!(report, "Thread '{}' panicked at '{}', {}:{}", name, msg, file, line);
+    let _ = CONTEXT.try_with(|context| {
+        if let Ok(context) = context.try_borrow() {
+            for entry in context.iter() {
+                let _ = writeln!(report, "Context {}: {}", entry.key, entry.value);
+            }
+        }
+    });
+
     let mut stderr = io::stderr();
 
     let _ = writeln!(stderr, "");
     let _ = writeln!(stderr, "====================");
     let _ = writeln!(stderr, "");
-    let _ = writeln!(stderr, "{:?}", backtrace);
-    let _ = writeln!(stderr, "");
-    let _ = writeln!(stderr, "Thread '{}' panicked at '{}', {}:{}", name, msg, file, line);
+    let _ = stderr.write_all(&report);
+
+    if let Some(dir) = report_dir {
+        match write_report(dir, &report) {
+            Ok(path) => {
+                let _ = writeln!(stderr, "Crash report is written to {}", path.display());
+            }
+            Err(err) => {
+                let _ = writeln!(stderr, "Cannot write crash report: {}", err);
+            }
+        }
+    }
 
     let _ = writeln!(stderr, "{}", ABOUT_PANIC);
 }
+
+fn write_report(dir: &Path, report: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let prefix = format!("crash-{}-{:09}", now.as_secs(), now.subsec_nanos());
+
+    let path = dir.join(format!("{}.log", prefix));
+    File::create(&path)?.write_all(report)?;
+
+    let _ = CONTEXT.try_with(|context| {
+        if let Ok(context) = context.try_borrow() {
+            for entry in context.iter() {
+                if let Some(ref dump) = entry.dump {
+                    let dump_path = dir.join(format!("{}-{}.dump", prefix, entry.key));
+                    let _ = File::create(&dump_path).and_then(|mut f| f.write_all(dump));
+                }
+            }
+        }
+    });
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+    use std::process;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn crash_report_contains_registered_context() {
+        let dir = ::std::env::temp_dir().join(format!("panic-hook-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        set_with_report_dir(dir.clone());
+
+        let block_hash = "0x1d0cc1d0c3e0ad1bd4c2dc5c1d6c8ea1a0b3cfae8b1ef1f21b4467cf1c2ddf6a";
+        let worker = thread::Builder::new()
+            .name("Verifier #0".to_string())
+            .spawn(move || {
+                let _guard = with_dump("block", block_hash, Rc::new(vec![0xc0]));
+                panic!("invalid block");
+            })
+            .unwrap();
+        assert!(worker.join().is_err());
+        let _ = panic::take_hook();
+
+        let mut report = String::new();
+        let mut dump = Vec::new();
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if name.ends_with(".log") {
+                File::open(&path).unwrap().read_to_string(&mut report).unwrap();
+            } else if name.ends_with("-block.dump") {
+                File::open(&path).unwrap().read_to_end(&mut dump).unwrap();
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(report.contains("Verifier #0"));
+        assert!(report.contains("invalid block"));
+        assert!(report.contains(block_hash));
+        assert_eq!(vec![0xc0], dump);
+    }
+
+    #[test]
+    fn context_is_removed_when_guard_is_dropped() {
+        {
+            let _outer = with_context("block", "outer");
+            {
+                let _inner = with_context("parcel", "inner");
+                CONTEXT.with(|context| assert_eq!(2, context.borrow().len()));
+            }
+            CONTEXT.with(|context| assert_eq!(1, context.borrow().len()));
+        }
+        CONTEXT.with(|context| assert!(context.borrow().is_empty()));
+    }
+}
@@ -6,7 +6,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use bigint::{H128, H160, H256, H512, H520, H64, U128, U256};
+use bigint::{Signature, H128, H160, H256, H512, H520, H64, U128, U256};
 use byteorder::{BigEndian, ByteOrder};
 use std::{cmp, mem, str};
 use stream::RlpStream;
@@ -270,6 +270,18 @@ impl_decodable_for_hash!(H256, 32);
 impl_decodable_for_hash!(H512, 64);
 impl_decodable_for_hash!(H520, 65);
 
+impl Encodable for Signature {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.encoder().encode_value(&self[..]);
+    }
+}
+
+impl Decodable for Signature {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        H520::decode(rlp).map(Signature::from)
+    }
+}
+
 macro_rules! impl_encodable_for_uint {
     ($name:ident, $size:expr) => {
         impl Encodable for $name {
@@ -9,7 +9,7 @@
 extern crate codechain_types as bigint;
 extern crate rlp;
 
-use bigint::{H160, U256};
+use bigint::{Signature, H160, U256};
 use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
 use std::{cmp, fmt};
 
@@ -219,6 +219,22 @@ fn encode_address() {
     run_encode_tests(tests);
 }
 
+fn signature_bytes() -> [u8; 65] {
+    let mut bytes = [0u8; 65];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    bytes
+}
+
+#[test]
+fn encode_signature() {
+    let bytes = signature_bytes();
+    let mut encoded = vec![0xb8, 0x41];
+    encoded.extend_from_slice(&bytes);
+    run_encode_tests(vec![ETestPair(Signature::from(bytes), encoded)]);
+}
+
 /// Vec<u8> (Bytes) is treated as a single value
 #[test]
 fn encode_vector_u8() {
@@ -402,6 +418,17 @@ fn decode_untrusted_address() {
     run_decode_tests(tests);
 }
 
+#[test]
+fn decode_untrusted_signature() {
+    let bytes = signature_bytes();
+    let mut encoded = vec![0xb8, 0x41];
+    encoded.extend_from_slice(&bytes);
+    run_decode_tests(vec![DTestPair(Signature::from(bytes), encoded)]);
+
+    let short = rlp::encode(&&bytes[..64]);
+    assert_eq!(Err(DecoderError::RlpIsTooShort), UntrustedRlp::new(&short).as_val::<Signature>());
+}
+
 #[test]
 fn decode_untrusted_vector_u64() {
     let tests = vec![